@@ -12,6 +12,10 @@ cargo fmt --all -- --check
 echo "Running tests with Cargo..."
 cargo test --all
 
+# Check that the Link-Layer can't panic on malformed packets
+echo "Running Clippy with deny-panics..."
+cargo clippy -p rubble --features deny-panics
+
 # Check that the device crates build with all feature combinations.
 # Only use `cargo check` because the PAC crates are very slow to build.
 (
@@ -49,6 +49,11 @@ impl Config for AppConfig {
     type PacketQueue = &'static mut SimpleQueue;
     type PacketProducer = SimpleProducer<'static>;
     type PacketConsumer = SimpleConsumer<'static>;
+
+    type EventHook = ();
+    type ControlHandler = ();
+    type Observer = ();
+    type PowerHooks = ();
 }
 
 /// Whether to broadcast a beacon or to establish a proper connection.
@@ -0,0 +1,781 @@
+//! Generates Rubble GATT databases from a schema file.
+//!
+//! Writing large GATT databases by hand is error-prone: Every attribute needs a handle, and the
+//! application code needs to know the handles of the values it wants to update. This crate reads a
+//! TOML schema describing the services and characteristics, and generates Rust code that
+//!
+//! * builds a `rubble::gatt::table::AttributeTable` that is exactly large enough for the database,
+//! * defines a module for every service, containing a constant with the handle of its declaration,
+//! * and defines a struct for every characteristic, with constants for its handles and typed
+//!   `get`/`set` methods for its value.
+//!
+//! # Schema
+//!
+//! ```toml
+//! [[service]]
+//! name = "battery"
+//! uuid = "180F"
+//!
+//! [[service.characteristic]]
+//! name = "battery_level"
+//! uuid = "2A19"
+//! properties = ["read", "notify"]
+//! format = "u8"
+//! value = [100]
+//! description = "Main battery"
+//!
+//! [[service]]
+//! name = "hid"
+//! uuid = "1812"
+//! includes = ["battery"]
+//! ```
+//!
+//! Available `properties` are `broadcast`, `read`, `write_without_response`, `write`, `notify` and
+//! `indicate`. A *Client Characteristic Configuration* descriptor is added automatically for
+//! characteristics that can notify or indicate.
+//!
+//! `format` is optional and can be one of `u8`, `u16`, `u32`, `i8`, `i16`, `i32` (little-endian
+//! integers) or `utf8`. Without a format, the accessors work with raw Byte slices. The `capacity`
+//! key reserves space for values that can grow.
+//!
+//! # Stable handles
+//!
+//! Bonded clients cache the handles of the database, so firmware updates should not move existing
+//! attributes. Services can be pinned to a fixed declaration `handle`, and can `reserve` a block
+//! of handles that leaves room for characteristics added in later versions:
+//!
+//! ```toml
+//! [[service]]
+//! name = "sensor"
+//! uuid = "181A"
+//! handle = 0x0010
+//! reserve = 16
+//!
+//! [[service.characteristic]]
+//! name = "temperature"
+//! uuid = "2A6E"
+//! properties = ["read"]
+//! format = "i16"
+//! handle = 0x0012
+//! ```
+//!
+//! The `handle` of a characteristic is the handle its value is expected at. If the schema is
+//! changed in a way that would move a pinned attribute, or a service outgrows its reserved block,
+//! generation fails, so the mistake is caught when building the firmware.
+//!
+//! Rubble doesn't enforce attribute permissions yet, so the `security` key currently only accepts
+//! `"none"` (the default). Schemas requiring `"encrypted"` or `"authenticated"` access are
+//! rejected instead of silently generating an unprotected database.
+//!
+//! # Usage
+//!
+//! Call `generate_file` from the build script:
+//!
+//! ```no_run
+//! use std::{env, path::PathBuf};
+//!
+//! let out = PathBuf::from(env::var_os("OUT_DIR").unwrap()).join("gatt.rs");
+//! rubble_gatt_codegen::generate_file("gatt.toml", &out).unwrap();
+//! println!("cargo:rerun-if-changed=gatt.toml");
+//! ```
+//!
+//! And include the generated code in a module of the application:
+//!
+//! ```ignore
+//! mod gatt {
+//!     include!(concat!(env!("OUT_DIR"), "/gatt.rs"));
+//! }
+//!
+//! let mut table = gatt::build();
+//! gatt::battery::BatteryLevel::set(&mut table, 99);
+//! ```
+//!
+//! The generated code uses `heapless` to size the table, so the application needs to depend on
+//! the same version of `heapless` as Rubble.
+
+#![warn(rust_2018_idioms)]
+
+pub mod schema;
+
+use {
+    crate::schema::{Characteristic, Format, Property, Schema, Security, Service},
+    std::{fmt, fmt::Write as _, fs, io, path::Path},
+};
+
+/// Errors that can occur while generating code.
+#[derive(Debug)]
+pub enum Error {
+    /// The schema file couldn't be read or the output couldn't be written.
+    Io(io::Error),
+
+    /// The schema isn't valid TOML, or doesn't follow the schema format.
+    Parse(toml::de::Error),
+
+    /// The schema is well-formed, but describes an invalid database.
+    Schema(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Parse(e) => write!(f, "invalid schema: {}", e),
+            Error::Schema(msg) => write!(f, "invalid GATT database: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(e: toml::de::Error) -> Self {
+        Error::Parse(e)
+    }
+}
+
+fn schema_error<T>(msg: impl Into<String>) -> Result<T, Error> {
+    Err(Error::Schema(msg.into()))
+}
+
+/// Reads the schema at `input` and writes the generated code to `output`.
+pub fn generate_file(input: impl AsRef<Path>, output: impl AsRef<Path>) -> Result<(), Error> {
+    let schema = fs::read_to_string(input)?;
+    let code = generate(&schema)?;
+    fs::write(output, code)?;
+    Ok(())
+}
+
+/// Generates the code for the GATT database described by the TOML `schema`.
+pub fn generate(schema: &str) -> Result<String, Error> {
+    let schema: Schema = toml::from_str(schema)?;
+    Generator::new().generate(&schema)
+}
+
+/// A parsed UUID.
+#[derive(Debug, Copy, Clone)]
+enum Uuid {
+    Uuid16(u16),
+    Uuid128([u8; 16]),
+}
+
+impl Uuid {
+    fn parse(s: &str) -> Result<Self, Error> {
+        let hex: String = s
+            .trim_start_matches("0x")
+            .chars()
+            .filter(|&c| c != '-')
+            .collect();
+        let invalid = || Error::Schema(format!("invalid UUID `{}`", s));
+
+        match hex.len() {
+            4 => Ok(Uuid::Uuid16(
+                u16::from_str_radix(&hex, 16).map_err(|_| invalid())?,
+            )),
+            32 => {
+                let mut bytes = [0; 16];
+                for (i, byte) in bytes.iter_mut().enumerate() {
+                    *byte =
+                        u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+                }
+                Ok(Uuid::Uuid128(bytes))
+            }
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Size of the UUID in attribute values.
+    fn size(&self) -> usize {
+        match self {
+            Uuid::Uuid16(_) => 2,
+            Uuid::Uuid128(_) => 16,
+        }
+    }
+}
+
+/// Formats the UUID as a Rust expression.
+impl fmt::Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Uuid::Uuid16(uuid) => write!(f, "::rubble::uuid::Uuid16({:#06X})", uuid),
+            Uuid::Uuid128(bytes) => {
+                f.write_str("::rubble::uuid::Uuid::from_bytes([")?;
+                for (i, byte) in bytes.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{:#04X}", byte)?;
+                }
+                f.write_str("])")
+            }
+        }
+    }
+}
+
+/// A service that was already generated.
+struct ServiceInfo {
+    name: String,
+    uuid: Uuid,
+    start: u16,
+}
+
+/// Code generator state.
+///
+/// Handles are assigned in exactly the same way as `AttributeTable` does it, so the generated
+/// constants match the handles of the table built at runtime.
+struct Generator {
+    next_handle: u32,
+    attrs: usize,
+    data_size: usize,
+    services: Vec<ServiceInfo>,
+    build: String,
+    modules: String,
+}
+
+impl Generator {
+    fn new() -> Self {
+        Self {
+            next_handle: 1,
+            attrs: 0,
+            data_size: 0,
+            services: Vec::new(),
+            build: String::new(),
+            modules: String::new(),
+        }
+    }
+
+    /// Assigns the next handle to an attribute with a value of `size` Bytes.
+    fn allocate(&mut self, size: usize) -> Result<u16, Error> {
+        if self.next_handle > 0xFFFF {
+            return schema_error("database has more than 65535 attributes");
+        }
+        let handle = self.next_handle as u16;
+        self.next_handle += 1;
+        self.attrs += 1;
+        self.data_size += size;
+        Ok(handle)
+    }
+
+    fn generate(mut self, schema: &Schema) -> Result<String, Error> {
+        for service in &schema.services {
+            self.service(service)?;
+        }
+
+        let attrs = self.attrs;
+        let mut out = String::new();
+        writeln!(
+            out,
+            "// This file was generated by rubble-gatt-codegen. Do not edit it manually.\n"
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "/// The attribute table holding the database ({} attributes, {} Bytes of values).",
+            attrs, self.data_size
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "pub type Table = ::rubble::gatt::table::AttributeTable<::heapless::consts::{}, \
+             ::heapless::consts::{}>;\n",
+            typenum(attrs)?,
+            typenum(self.data_size)?
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "/// Creates the attribute table and adds all services to it."
+        )
+        .unwrap();
+        writeln!(out, "pub fn build() -> Table {{").unwrap();
+        writeln!(out, "    let mut table = Table::new();").unwrap();
+        out.push_str(&self.build);
+        writeln!(out, "    table\n}}").unwrap();
+        out.push_str(&self.modules);
+        Ok(out)
+    }
+
+    fn service(&mut self, service: &Service) -> Result<(), Error> {
+        check_ident(&service.name)?;
+        if self.services.iter().any(|s| s.name == service.name) {
+            return schema_error(format!("duplicate service `{}`", service.name));
+        }
+
+        let uuid = Uuid::parse(&service.uuid)?;
+        if service.secondary && (service.handle.is_some() || service.reserve.is_some()) {
+            return schema_error(format!(
+                "secondary service `{}` can't be placed at a fixed handle or reserve handles",
+                service.name
+            ));
+        }
+        if service.reserve == Some(0) {
+            return schema_error(format!("service `{}` reserves 0 handles", service.name));
+        }
+
+        if let Some(handle) = service.handle {
+            if u32::from(handle) < self.next_handle {
+                return schema_error(format!(
+                    "service `{}` is pinned to handle {:#06X}, but the previous services already \
+                     use handles up to {:#06X}",
+                    service.name,
+                    handle,
+                    self.next_handle - 1
+                ));
+            }
+            self.next_handle = u32::from(handle);
+        }
+        let start = self.allocate(uuid.size())?;
+        match (service.handle, service.reserve) {
+            (Some(handle), reserve) => writeln!(
+                self.build,
+                "    table.add_service_at({}, {}, {:?}).unwrap();",
+                handle_expr(handle),
+                uuid,
+                reserve
+            ),
+            (None, Some(reserve)) => writeln!(
+                self.build,
+                "    table.add_service_reserved({}, {}).unwrap();",
+                uuid, reserve
+            ),
+            (None, None) => {
+                let method = if service.secondary {
+                    "add_secondary_service"
+                } else {
+                    "add_service"
+                };
+                writeln!(self.build, "    table.{}({}).unwrap();", method, uuid)
+            }
+        }
+        .unwrap();
+
+        let mut module = String::new();
+        writeln!(
+            module,
+            "\n/// The `{}` service (UUID `{}`).",
+            service.name, service.uuid
+        )
+        .unwrap();
+        writeln!(module, "pub mod {} {{", service.name).unwrap();
+        writeln!(module, "    /// Handle of the service declaration.").unwrap();
+        writeln!(
+            module,
+            "    pub const SERVICE: ::rubble::att::Handle = {};",
+            handle_expr(start)
+        )
+        .unwrap();
+
+        for include in &service.includes {
+            let included = match self.services.iter().find(|s| &s.name == include) {
+                Some(s) => s,
+                None => {
+                    return schema_error(format!(
+                        "service `{}` includes `{}`, which isn't defined before it",
+                        service.name, include
+                    ))
+                }
+            };
+            // The definition contains the included service's UUID only if it's a 16-bit UUID
+            let size = if included.uuid.size() == 2 { 6 } else { 4 };
+            let included_start = included.start;
+            self.allocate(size)?;
+            writeln!(
+                self.build,
+                "    table.add_include({}).unwrap();",
+                handle_expr(included_start)
+            )
+            .unwrap();
+        }
+
+        for characteristic in &service.characteristics {
+            self.characteristic(characteristic, &mut module)?;
+        }
+
+        if let Some(reserve) = service.reserve {
+            let used = self.next_handle - u32::from(start);
+            if used > u32::from(reserve) {
+                return schema_error(format!(
+                    "service `{}` needs {} handles, but only reserves {}",
+                    service.name, used, reserve
+                ));
+            }
+            self.next_handle = u32::from(start) + u32::from(reserve);
+        }
+
+        writeln!(module, "}}").unwrap();
+        self.modules.push_str(&module);
+        self.services.push(ServiceInfo {
+            name: service.name.clone(),
+            uuid,
+            start,
+        });
+        Ok(())
+    }
+
+    fn characteristic(&mut self, ch: &Characteristic, module: &mut String) -> Result<(), Error> {
+        check_ident(&ch.name)?;
+        if ch.security != Security::None {
+            return schema_error(format!(
+                "characteristic `{}` requires {:?} access, but attribute permissions are not \
+                 supported yet",
+                ch.name, ch.security
+            ));
+        }
+
+        let uuid = Uuid::parse(&ch.uuid)?;
+        let numeric = ch.format.and_then(Format::numeric);
+        let value = match (&ch.value, numeric) {
+            (Some(value), _) => value.clone(),
+            (None, Some((_, size))) => vec![0; size],
+            (None, None) => Vec::new(),
+        };
+        let capacity = ch.capacity.unwrap_or(value.len());
+        if value.len() > capacity {
+            return schema_error(format!(
+                "initial value of `{}` doesn't fit in its capacity",
+                ch.name
+            ));
+        }
+        if let Some((ty, size)) = numeric {
+            if capacity != size || value.len() != size {
+                return schema_error(format!(
+                    "value of `{}` must be exactly {} Bytes to be a `{}`",
+                    ch.name, size, ty
+                ));
+            }
+        }
+
+        let props = ch.properties.iter().fold(0, |bits, p| bits | p.bit());
+        let has_cccd = ch
+            .properties
+            .iter()
+            .any(|&p| p == Property::Notify || p == Property::Indicate);
+
+        self.allocate(3 + uuid.size())?;
+        let value_handle = self.allocate(capacity)?;
+        if let Some(expected) = ch.handle {
+            if expected != value_handle {
+                return schema_error(format!(
+                    "value of `{}` is pinned to handle {:#06X}, but would be placed at {:#06X}",
+                    ch.name, expected, value_handle
+                ));
+            }
+        }
+        let props = format!(
+            "::rubble::gatt::characteristic::Properties::from_bits_truncate({:#04X})",
+            props
+        );
+        writeln!(
+            self.build,
+            "    table.add_characteristic_with_capacity({}, {}, &{:?}, {}).unwrap();",
+            uuid, props, value, capacity
+        )
+        .unwrap();
+        let cccd = if has_cccd {
+            Some(self.allocate(2)?)
+        } else {
+            None
+        };
+        let description = match &ch.description {
+            Some(text) => {
+                writeln!(
+                    self.build,
+                    "    table.add_user_description({:?}).unwrap();",
+                    text
+                )
+                .unwrap();
+                Some(self.allocate(text.len())?)
+            }
+            None => None,
+        };
+
+        let name = camel_case(&ch.name);
+        writeln!(
+            module,
+            "\n    /// The `{}` characteristic (UUID `{}`).",
+            ch.name, ch.uuid
+        )
+        .unwrap();
+        writeln!(module, "    pub struct {};\n", name).unwrap();
+        writeln!(module, "    impl {} {{", name).unwrap();
+        writeln!(module, "        /// Handle of the characteristic value.").unwrap();
+        writeln!(
+            module,
+            "        pub const VALUE: ::rubble::att::Handle = {};",
+            handle_expr(value_handle)
+        )
+        .unwrap();
+        if let Some(cccd) = cccd {
+            writeln!(
+                module,
+                "\n        /// Handle of the Client Characteristic Configuration descriptor."
+            )
+            .unwrap();
+            writeln!(
+                module,
+                "        pub const CCCD: ::rubble::att::Handle = {};",
+                handle_expr(cccd)
+            )
+            .unwrap();
+        }
+        if let Some(description) = description {
+            writeln!(
+                module,
+                "\n        /// Handle of the Characteristic User Description descriptor."
+            )
+            .unwrap();
+            writeln!(
+                module,
+                "        pub const DESCRIPTION: ::rubble::att::Handle = {};",
+                handle_expr(description)
+            )
+            .unwrap();
+        }
+
+        let accessors = match (ch.format, numeric) {
+            (_, Some((ty, size))) => format!(
+                r#"
+        /// Returns the current value.
+        pub fn get(table: &super::Table) -> {ty} {{
+            let value = table.value(Self::VALUE).unwrap();
+            let mut bytes = [0; {size}];
+            bytes[..value.len()].copy_from_slice(value);
+            {ty}::from_le_bytes(bytes)
+        }}
+
+        /// Changes the value.
+        pub fn set(table: &mut super::Table, value: {ty}) {{
+            table.set_value(Self::VALUE, &value.to_le_bytes()).unwrap();
+        }}
+"#,
+                ty = ty,
+                size = size
+            ),
+            (Some(Format::Utf8), None) => format!(
+                r#"
+        /// Returns the current value.
+        ///
+        /// Returns an empty string if a client wrote invalid UTF-8.
+        pub fn get(table: &super::Table) -> &str {{
+            ::core::str::from_utf8(table.value(Self::VALUE).unwrap()).unwrap_or("")
+        }}
+
+        /// Changes the value.
+        ///
+        /// Returns `Error::InvalidLength` if `value` is longer than {capacity} Bytes.
+        pub fn set(table: &mut super::Table, value: &str) -> Result<(), ::rubble::Error> {{
+            table.set_value(Self::VALUE, value.as_bytes())
+        }}
+"#,
+                capacity = capacity
+            ),
+            _ => format!(
+                r#"
+        /// Returns the current value.
+        pub fn get(table: &super::Table) -> &[u8] {{
+            table.value(Self::VALUE).unwrap()
+        }}
+
+        /// Changes the value.
+        ///
+        /// Returns `Error::InvalidLength` if `value` is longer than {capacity} Bytes.
+        pub fn set(table: &mut super::Table, value: &[u8]) -> Result<(), ::rubble::Error> {{
+            table.set_value(Self::VALUE, value)
+        }}
+"#,
+                capacity = capacity
+            ),
+        };
+        module.push_str(&accessors);
+        writeln!(module, "    }}").unwrap();
+
+        Ok(())
+    }
+}
+
+/// Formats `handle` as a Rust expression.
+fn handle_expr(handle: u16) -> String {
+    format!("::rubble::att::Handle::from_raw({:#06X})", handle)
+}
+
+/// Returns the name of the `typenum` constant for an array length of at least `n`.
+///
+/// `typenum` defines constants for every number up to 1024, and for powers of 2 above that.
+fn typenum(n: usize) -> Result<String, Error> {
+    let n = if n <= 1024 { n } else { n.next_power_of_two() };
+    if n > 1 << 16 {
+        return schema_error("database too large");
+    }
+    Ok(format!("U{}", n))
+}
+
+fn check_ident(name: &str) -> Result<(), Error> {
+    let mut chars = name.chars();
+    let valid = match chars.next() {
+        Some(c) => {
+            (c.is_ascii_lowercase() || c == '_')
+                && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        }
+        None => false,
+    };
+
+    if valid {
+        Ok(())
+    } else {
+        schema_error(format!("`{}` is not a valid snake_case identifier", name))
+    }
+}
+
+/// Converts a `snake_case` name to `CamelCase`.
+fn camel_case(name: &str) -> String {
+    name.split('_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            let first = chars.next().unwrap().to_ascii_uppercase();
+            std::iter::once(first).chain(chars).collect::<String>()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMA: &str = r#"
+        [[service]]
+        name = "battery"
+        uuid = "180F"
+
+        [[service.characteristic]]
+        name = "battery_level"
+        uuid = "2A19"
+        properties = ["read", "notify"]
+        format = "u8"
+        value = [100]
+        description = "Main"
+
+        [[service]]
+        name = "uart"
+        uuid = "6e400001-b5a3-f393-e0a9-e50e24dcca9e"
+        includes = ["battery"]
+
+        [[service.characteristic]]
+        name = "rx"
+        uuid = "6e400002-b5a3-f393-e0a9-e50e24dcca9e"
+        properties = ["write"]
+        capacity = 20
+    "#;
+
+    fn has_handle(code: &str, name: &str, handle: u16) -> bool {
+        code.contains(&format!(
+            "pub const {}: ::rubble::att::Handle = {};",
+            name,
+            handle_expr(handle)
+        ))
+    }
+
+    #[test]
+    fn handles() {
+        let code = generate(SCHEMA).unwrap();
+
+        // 1: BAS, 2: decl, 3: value, 4: CCCD, 5: description
+        assert!(has_handle(&code, "VALUE", 3));
+        assert!(has_handle(&code, "CCCD", 4));
+        assert!(has_handle(&code, "DESCRIPTION", 5));
+        // 6: UART, 7: include, 8: decl, 9: value
+        assert!(has_handle(&code, "SERVICE", 6));
+        assert!(code.contains(&format!("table.add_include({})", handle_expr(1))));
+        assert!(has_handle(&code, "VALUE", 9));
+
+        // Values: 2 + 5 + 1 + 2 + 4 + 16 + 6 + 19 + 20
+        assert!(code.contains("AttributeTable<::heapless::consts::U9, ::heapless::consts::U75>"));
+        assert!(code.contains("pub struct BatteryLevel;"));
+    }
+
+    #[test]
+    fn invalid() {
+        let undefined = r#"
+            [[service]]
+            name = "hid"
+            uuid = "1812"
+            includes = ["battery"]
+        "#;
+        assert!(generate(undefined).is_err());
+
+        let secure = r#"
+            [[service]]
+            name = "hid"
+            uuid = "1812"
+
+            [[service.characteristic]]
+            name = "report"
+            uuid = "2A4D"
+            properties = ["read"]
+            security = "encrypted"
+        "#;
+        assert!(generate(secure).is_err());
+    }
+
+    #[test]
+    fn stable_handles() {
+        let schema = |extra: &str| {
+            format!(
+                r#"
+                [[service]]
+                name = "battery"
+                uuid = "180F"
+                reserve = 6
+
+                [[service.characteristic]]
+                name = "battery_level"
+                uuid = "2A19"
+                properties = ["read"]
+                format = "u8"
+                handle = 3
+                {}
+
+                [[service]]
+                name = "device_info"
+                uuid = "180A"
+                handle = 0x10
+                "#,
+                extra
+            )
+        };
+
+        let code = generate(&schema("")).unwrap();
+        assert!(code.contains(&format!(
+            "table.add_service_at({}, ::rubble::uuid::Uuid16(0x180A), None)",
+            handle_expr(0x10)
+        )));
+        assert!(has_handle(&code, "SERVICE", 0x10));
+        // The gaps don't take up table entries
+        assert!(code.contains("AttributeTable<::heapless::consts::U4,"));
+
+        // A second characteristic still fits into the block
+        let grown = r#"
+            [[service.characteristic]]
+            name = "battery_state"
+            uuid = "2A1A"
+            properties = ["read"]
+            format = "u8"
+        "#;
+        let code = generate(&schema(grown)).unwrap();
+        assert!(has_handle(&code, "VALUE", 5));
+        assert!(has_handle(&code, "SERVICE", 0x10));
+
+        // But a third one doesn't
+        let too_much = format!(
+            "{}{}",
+            grown,
+            grown.replace("battery_state", "battery_other")
+        );
+        assert!(generate(&schema(&too_much)).is_err());
+    }
+}
@@ -0,0 +1,136 @@
+//! The schema file format.
+
+use serde::Deserialize;
+
+/// A complete GATT database.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Schema {
+    /// The services, in the order they appear in the database.
+    #[serde(default, rename = "service")]
+    pub services: Vec<Service>,
+}
+
+/// A primary or secondary service.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Service {
+    /// Name of the generated module (must be a valid Rust identifier).
+    pub name: String,
+
+    /// 16-bit (`"180F"`) or 128-bit (`"6e400001-b5a3-f393-e0a9-e50e24dcca9e"`) UUID.
+    pub uuid: String,
+
+    /// Whether this is a secondary service.
+    #[serde(default)]
+    pub secondary: bool,
+
+    /// Handle the service declaration must be placed at. Only allowed for primary services.
+    pub handle: Option<u16>,
+
+    /// Number of handles to reserve for the service, including its declaration.
+    pub reserve: Option<u16>,
+
+    /// Names of services included by this service. They must be defined before this service.
+    #[serde(default)]
+    pub includes: Vec<String>,
+
+    #[serde(default, rename = "characteristic")]
+    pub characteristics: Vec<Characteristic>,
+}
+
+/// A characteristic and its descriptors.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Characteristic {
+    /// Name of the characteristic, in `snake_case`. The accessor struct is named after this.
+    pub name: String,
+
+    /// 16-bit or 128-bit UUID.
+    pub uuid: String,
+
+    pub properties: Vec<Property>,
+
+    /// Initial value. Defaults to all zeros for numeric formats and to an empty value otherwise.
+    pub value: Option<Vec<u8>>,
+
+    /// Number of Bytes to reserve for the value. Defaults to the size of `format` or `value`.
+    pub capacity: Option<usize>,
+
+    /// Type of the value, used for the typed accessors.
+    pub format: Option<Format>,
+
+    /// Text of a *Characteristic User Description* descriptor to add.
+    pub description: Option<String>,
+
+    /// Security required to access the value.
+    #[serde(default)]
+    pub security: Security,
+
+    /// Handle the characteristic value is expected at. Generation fails if it ends up elsewhere.
+    pub handle: Option<u16>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Property {
+    Broadcast,
+    Read,
+    WriteWithoutResponse,
+    Write,
+    Notify,
+    Indicate,
+}
+
+impl Property {
+    /// Returns the bit in the characteristic declaration's properties field.
+    pub fn bit(self) -> u8 {
+        match self {
+            Property::Broadcast => 0x01,
+            Property::Read => 0x02,
+            Property::WriteWithoutResponse => 0x04,
+            Property::Write => 0x08,
+            Property::Notify => 0x10,
+            Property::Indicate => 0x20,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    U8,
+    U16,
+    U32,
+    I8,
+    I16,
+    I32,
+    Utf8,
+}
+
+impl Format {
+    /// Returns the Rust type and size of numeric formats.
+    pub fn numeric(self) -> Option<(&'static str, usize)> {
+        Some(match self {
+            Format::U8 => ("u8", 1),
+            Format::U16 => ("u16", 2),
+            Format::U32 => ("u32", 4),
+            Format::I8 => ("i8", 1),
+            Format::I16 => ("i16", 2),
+            Format::I32 => ("i32", 4),
+            Format::Utf8 => return None,
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Security {
+    /// The value can be accessed without encryption.
+    #[default]
+    None,
+    /// The connection must be encrypted.
+    Encrypted,
+    /// The connection must be encrypted with an authenticated (MITM-protected) key.
+    Authenticated,
+}
@@ -0,0 +1,386 @@
+//! Runs the Rubble host stack on a PC, against an external HCI controller.
+//!
+//! Rubble's host-side layers (L2CAP, ATT, GATT and the Security Manager) don't depend on any
+//! hardware and build for `std` targets just fine. This crate connects them to a standard
+//! Bluetooth controller attached via the HCI UART transport (H4), for example a Zephyr
+//! `hci_uart` dongle or an nRF52 running the `hci_uart` sample. The controller takes care of the
+//! Link-Layer, so GATT services can be developed and tested on a PC before flashing them onto the
+//! real device.
+//!
+//! The port can be any `Read + Write` type. Serial port crates usually report read timeouts as
+//! `io::ErrorKind::TimedOut`, which makes `HciHost::poll` return without doing anything:
+//!
+//! ```ignore
+//! let port = serialport::new("/dev/ttyACM0", 1_000_000)
+//!     .timeout(Duration::from_millis(10))
+//!     .open()?;
+//! let mut host = HciHost::new(port, BleChannelMap::with_attributes(MyAttrs::new()));
+//! host.init()?;
+//! let name = AdStructure::CompleteLocalName("Rubble");
+//! host.advertise(rubble::time::Duration::from_millis(100), &[name])?;
+//! loop {
+//!     host.poll()?;
+//! }
+//! ```
+//!
+//! Only a single connection in the peripheral role is supported, just like with Rubble's own
+//! Link-Layer. Advertising is restarted automatically when the connection is closed.
+//!
+//! Controller-specific commands and events can be used via the [`vendor`] module.
+//!
+//! [`vendor`]: vendor/index.html
+
+#![warn(rust_2018_idioms)]
+
+pub mod packet;
+pub mod vendor;
+
+use {
+    crate::{
+        packet::{opcode, Boundary, Event, Packet},
+        vendor::{VendorCommand, VendorEventHandler},
+    },
+    log::{debug, info, warn},
+    rubble::{
+        att::LinkSecurity,
+        bytes::{ByteWriter, ToBytes},
+        l2cap::{ChannelMapper, L2CAPState, L2CAPStateTx},
+        link::{
+            ad_structure::{AdStructure, Flags},
+            data::Llid,
+            queue::{Consume, Consumer, PacketQueue, SimpleProducer, SimpleQueue},
+        },
+        time::Duration,
+    },
+    std::{
+        fmt,
+        io::{self, Read, Write},
+    },
+};
+
+/// Errors returned by `HciHost`.
+#[derive(Debug)]
+pub enum Error {
+    /// Communication with the controller failed.
+    Io(io::Error),
+
+    /// The controller rejected a command.
+    Command { opcode: u16, status: u8 },
+
+    /// The Rubble stack reported an error.
+    Stack(rubble::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Command { opcode, status } => write!(
+                f,
+                "command {:#06X} failed with status {:#04X}",
+                opcode, status
+            ),
+            Error::Stack(e) => write!(f, "{:?}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<rubble::Error> for Error {
+    fn from(e: rubble::Error) -> Self {
+        Error::Stack(e)
+    }
+}
+
+/// A BLE peripheral using an external controller.
+pub struct HciHost<S: Read + Write, M: ChannelMapper> {
+    port: S,
+    l2cap: L2CAPState<M>,
+    /// Outgoing L2CAP data.
+    queue: SimpleQueue,
+    /// Handle of the current connection.
+    connection: Option<u16>,
+    /// Number of ACL packets the controller can still buffer.
+    credits: u16,
+    /// An L2CAP message that couldn't be processed because the TX queue was full.
+    pending: Option<Vec<u8>>,
+    advertising: bool,
+    restart_advertising: bool,
+    vendor_events: Option<VendorEventHandler>,
+}
+
+impl<S: Read + Write, M: ChannelMapper> HciHost<S, M> {
+    /// Creates a host talking to the controller on `port`, and serving the L2CAP channels managed
+    /// by `mapper`.
+    pub fn new(port: S, mapper: M) -> Self {
+        Self {
+            port,
+            l2cap: L2CAPState::new(mapper),
+            queue: SimpleQueue::new(),
+            connection: None,
+            credits: 0,
+            pending: None,
+            advertising: false,
+            restart_advertising: false,
+            vendor_events: None,
+        }
+    }
+
+    /// Resets the controller and configures it for use by the host.
+    pub fn init(&mut self) -> Result<(), Error> {
+        self.command(opcode::RESET, &[])?;
+        // Default mask plus LE Meta events
+        self.command(
+            opcode::SET_EVENT_MASK,
+            &0x2000_1FFF_FFFF_FFFFu64.to_le_bytes(),
+        )?;
+        let rsp = self.command(opcode::LE_READ_BUFFER_SIZE, &[])?;
+        self.credits = rsp.get(2).map_or(1, |&n| u16::from(n.max(1)));
+        info!("controller ready, {} ACL buffers", self.credits);
+        Ok(())
+    }
+
+    /// Starts connectable undirected advertising with the given interval and AD structures.
+    ///
+    /// Like `LinkLayer::start_advertise`, this automatically prepends a *Flags* structure to the
+    /// advertising data.
+    pub fn advertise(&mut self, interval: Duration, data: &[AdStructure<'_>]) -> Result<(), Error> {
+        let interval = (interval.as_micros() / 625).clamp(0x20, 0x4000) as u16;
+        let mut params = Vec::new();
+        params.extend_from_slice(&interval.to_le_bytes()); // min
+        params.extend_from_slice(&interval.to_le_bytes()); // max
+        params.push(0x00); // ADV_IND
+        params.push(0x00); // own address: public
+        params.extend_from_slice(&[0; 7]); // peer address (unused for ADV_IND)
+        params.push(0x07); // all advertising channels
+        params.push(0x00); // no filtering
+        self.command(opcode::LE_SET_ADVERTISING_PARAMETERS, &params)?;
+
+        let mut buf = [0; 32];
+        let mut writer = ByteWriter::new(&mut buf[1..]);
+        let free = writer.space_left();
+        AdStructure::from(Flags::discoverable()).to_bytes(&mut writer)?;
+        for ad in data {
+            ad.to_bytes(&mut writer)?;
+        }
+        buf[0] = (free - writer.space_left()) as u8;
+        self.command(opcode::LE_SET_ADVERTISING_DATA, &buf)?;
+
+        self.command(opcode::LE_SET_ADVERTISING_ENABLE, &[0x01])?;
+        self.advertising = true;
+        Ok(())
+    }
+
+    /// Sends a vendor-specific command and waits for its completion.
+    pub fn vendor_command<C: VendorCommand>(&mut self, cmd: &C) -> Result<C::Response, Error> {
+        let ret = self.command(C::opcode(), &cmd.params())?;
+        Ok(C::parse_response(&ret)?)
+    }
+
+    /// Registers a handler for vendor-specific events, replacing the previous one.
+    ///
+    /// Without a handler, vendor events are only logged.
+    pub fn on_vendor_event(&mut self, handler: impl FnMut(&[u8]) + 'static) {
+        self.vendor_events = Some(Box::new(handler));
+    }
+
+    /// Returns whether a central is connected.
+    pub fn is_connected(&self) -> bool {
+        self.connection.is_some()
+    }
+
+    /// Processes the next packet from the controller, if any, and sends outgoing data.
+    ///
+    /// This blocks until a packet is received or the port's read timeout expires.
+    pub fn poll(&mut self) -> Result<(), Error> {
+        if let Some(packet) = packet::read_packet(&mut self.port)? {
+            self.handle_packet(packet)?;
+        }
+
+        if self.restart_advertising {
+            self.restart_advertising = false;
+            self.command(opcode::LE_SET_ADVERTISING_ENABLE, &[0x01])?;
+        }
+
+        self.flush()?;
+        if let Some(message) = self.pending.take() {
+            self.process_message(message);
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Obtains access to the L2CAP layer, eg. to send notifications.
+    ///
+    /// Data enqueued by `f` is sent to the controller afterwards.
+    pub fn with_l2cap<R>(
+        &mut self,
+        f: impl FnOnce(&mut L2CAPStateTx<'_, M, SimpleProducer<'_>>) -> R,
+    ) -> Result<R, Error> {
+        let (mut producer, _) = (&mut self.queue).split();
+        let result = f(&mut self.l2cap.tx(&mut producer));
+        self.flush()?;
+        Ok(result)
+    }
+
+    /// Sends a command and waits for its completion, returning the return parameters after the
+    /// status.
+    fn command(&mut self, opcode: u16, params: &[u8]) -> Result<Vec<u8>, Error> {
+        debug!("-> command {:#06X} {:02X?}", opcode, params);
+        packet::write_command(&mut self.port, opcode, params)?;
+
+        loop {
+            let packet = match packet::read_packet(&mut self.port)? {
+                Some(packet) => packet,
+                None => continue,
+            };
+
+            match packet {
+                Packet::Event(Event::CommandComplete { opcode: op, params }) if op == opcode => {
+                    return match params.first() {
+                        Some(0) => Ok(params[1..].to_vec()),
+                        Some(&status) => Err(Error::Command { opcode, status }),
+                        None => Ok(Vec::new()),
+                    };
+                }
+                Packet::Event(Event::CommandStatus { status, opcode: op }) if op == opcode => {
+                    return if status == 0 {
+                        Ok(Vec::new())
+                    } else {
+                        Err(Error::Command { opcode, status })
+                    };
+                }
+                other => self.handle_packet(other)?,
+            }
+        }
+    }
+
+    fn handle_packet(&mut self, packet: Packet) -> Result<(), Error> {
+        match packet {
+            Packet::Event(Event::LeConnectionComplete {
+                status: 0,
+                handle,
+                peer,
+            }) => {
+                info!("connected to {:02X?} (handle {:#05X})", peer.1, handle);
+                self.connection = Some(handle);
+            }
+            Packet::Event(Event::DisconnectionComplete { handle, reason }) => {
+                if self.connection == Some(handle) {
+                    info!("disconnected (reason {:#04X})", reason);
+                    self.connection = None;
+                    self.l2cap
+                        .att_server()
+                        .set_link_security(LinkSecurity::Unencrypted);
+                    self.pending = None;
+                    // Drop any unsent data
+                    let (_, mut consumer) = (&mut self.queue).split();
+                    while consumer
+                        .consume_raw_with(|_, _| Consume::always(Ok(())))
+                        .is_ok()
+                    {}
+                    self.restart_advertising = self.advertising;
+                }
+            }
+            Packet::Event(Event::EncryptionChange {
+                status: 0,
+                handle,
+                enabled,
+            }) if self.connection == Some(handle) => {
+                // The controller doesn't know whether the key was authenticated
+                let security = if enabled {
+                    LinkSecurity::Encrypted
+                } else {
+                    LinkSecurity::Unencrypted
+                };
+                info!("link security changed to {:?}", security);
+                self.l2cap.att_server().set_link_security(security);
+            }
+            Packet::Event(Event::NumberOfCompletedPackets { completed }) => {
+                for (_, count) in completed {
+                    self.credits = self.credits.saturating_add(count);
+                }
+            }
+            Packet::Event(Event::Vendor { params }) => match &mut self.vendor_events {
+                Some(handler) => handler(&params),
+                None => debug!("<- vendor event {:02X?}", params),
+            },
+            Packet::Event(event) => debug!("<- {:?}", event),
+            Packet::Acl {
+                handle,
+                boundary,
+                data,
+            } => {
+                if self.connection != Some(handle) {
+                    warn!("ACL data for unknown connection {:#05X}", handle);
+                } else if boundary == Boundary::Continuation {
+                    warn!("L2CAP reassembly is not supported, dropping {:02X?}", data);
+                } else if self.pending.is_some() {
+                    warn!("host busy, dropping {:02X?}", data);
+                } else {
+                    self.process_message(data);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Hands an L2CAP message to the stack, storing it in `pending` if the stack has no space to
+    /// respond.
+    fn process_message(&mut self, message: Vec<u8>) {
+        let length = message
+            .get(..2)
+            .map(|raw| usize::from(u16::from_le_bytes([raw[0], raw[1]])));
+        if length != Some(message.len().saturating_sub(4)) {
+            warn!("fragmented L2CAP message, dropping {:02X?}", message);
+            return;
+        }
+
+        let (mut producer, _) = (&mut self.queue).split();
+        let consume = self.l2cap.tx(&mut producer).process_start(&message);
+        if !consume.should_consume() {
+            self.pending = Some(message);
+        } else if let Err(e) = consume.into_result() {
+            warn!("error processing L2CAP message: {:?}", e);
+        }
+    }
+
+    /// Sends the data in the TX queue to the controller, as long as it has buffer space.
+    fn flush(&mut self) -> Result<(), Error> {
+        let handle = match self.connection {
+            Some(handle) => handle,
+            None => return Ok(()),
+        };
+
+        let (_, mut consumer) = (&mut self.queue).split();
+        while self.credits > 0 && consumer.has_data() {
+            let port = &mut self.port;
+            let result = consumer.consume_raw_with(|header, payload| {
+                let boundary = match header.llid() {
+                    Llid::DataStart => Boundary::Start,
+                    Llid::DataCont => Boundary::Continuation,
+                    llid => {
+                        warn!("dropping {:?} PDU, the controller handles those", llid);
+                        return Consume::always(Ok(Ok(false)));
+                    }
+                };
+                match packet::write_acl(port, handle, boundary, payload) {
+                    Ok(()) => Consume::always(Ok(Ok(true))),
+                    Err(e) => Consume::never(Ok(Err(e))),
+                }
+            })?;
+
+            if result? {
+                self.credits -= 1;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,286 @@
+//! HCI packets exchanged over the UART transport (H4).
+//!
+//! Every packet is prefixed with a Byte indicating its type. Only the packets needed by a
+//! peripheral are supported here.
+
+use std::io::{self, Read, Write};
+
+/// H4 packet type of HCI commands.
+pub const COMMAND: u8 = 0x01;
+/// H4 packet type of ACL data.
+pub const ACL_DATA: u8 = 0x02;
+/// H4 packet type of HCI events.
+pub const EVENT: u8 = 0x04;
+
+/// Opcodes of the HCI commands used by the host.
+pub mod opcode {
+    pub const SET_EVENT_MASK: u16 = 0x0C01;
+    pub const RESET: u16 = 0x0C03;
+    pub const LE_READ_BUFFER_SIZE: u16 = 0x2002;
+    pub const LE_SET_ADVERTISING_PARAMETERS: u16 = 0x2006;
+    pub const LE_SET_ADVERTISING_DATA: u16 = 0x2008;
+    pub const LE_SET_SCAN_RESPONSE_DATA: u16 = 0x2009;
+    pub const LE_SET_ADVERTISING_ENABLE: u16 = 0x200A;
+
+    /// OGF reserved for vendor-specific commands.
+    pub const OGF_VENDOR: u16 = 0x3F;
+
+    /// Builds an opcode from its command group (OGF) and command (OCF) fields.
+    pub const fn new(ogf: u16, ocf: u16) -> u16 {
+        (ogf << 10) | (ocf & 0x03FF)
+    }
+
+    /// Builds the opcode of the vendor-specific command `ocf`.
+    pub const fn vendor(ocf: u16) -> u16 {
+        new(OGF_VENDOR, ocf)
+    }
+}
+
+/// Event code reserved for vendor-specific events.
+pub const VENDOR_EVENT: u8 = 0xFF;
+
+/// Packet Boundary flag of ACL data.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Boundary {
+    /// First fragment of an L2CAP PDU (or a complete PDU).
+    Start,
+    /// Continuation fragment.
+    Continuation,
+}
+
+/// A decoded HCI event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    CommandComplete {
+        opcode: u16,
+        /// Return parameters, starting with the status.
+        params: Vec<u8>,
+    },
+    CommandStatus {
+        status: u8,
+        opcode: u16,
+    },
+    DisconnectionComplete {
+        handle: u16,
+        reason: u8,
+    },
+    EncryptionChange {
+        status: u8,
+        handle: u16,
+        /// Whether encryption is now enabled.
+        enabled: bool,
+    },
+    NumberOfCompletedPackets {
+        /// Pairs of connection handle and number of packets.
+        completed: Vec<(u16, u16)>,
+    },
+    LeConnectionComplete {
+        status: u8,
+        handle: u16,
+        /// Peer address type (0 = public, 1 = random) and address.
+        peer: (u8, [u8; 6]),
+    },
+    /// A vendor-specific event.
+    Vendor {
+        /// Event parameters. Their format is defined by the controller vendor.
+        params: Vec<u8>,
+    },
+    /// Any event not used by the host.
+    Other {
+        code: u8,
+        params: Vec<u8>,
+    },
+}
+
+impl Event {
+    /// Decodes an event from its code and parameters.
+    ///
+    /// Returns `None` if the parameters are too short for the event.
+    pub fn parse(code: u8, params: &[u8]) -> Option<Self> {
+        let u16_at = |i: usize| -> Option<u16> {
+            Some(u16::from_le_bytes([*params.get(i)?, *params.get(i + 1)?]))
+        };
+
+        Some(match code {
+            0x0E => Event::CommandComplete {
+                opcode: u16_at(1)?,
+                params: params.get(3..)?.to_vec(),
+            },
+            0x0F => Event::CommandStatus {
+                status: *params.first()?,
+                opcode: u16_at(2)?,
+            },
+            0x05 => Event::DisconnectionComplete {
+                handle: u16_at(1)? & 0x0FFF,
+                reason: *params.get(3)?,
+            },
+            0x08 => Event::EncryptionChange {
+                status: *params.first()?,
+                handle: u16_at(1)? & 0x0FFF,
+                enabled: *params.get(3)? != 0,
+            },
+            0x13 => {
+                let count = usize::from(*params.first()?);
+                let completed = (0..count)
+                    .map(|i| Some((u16_at(1 + 4 * i)? & 0x0FFF, u16_at(3 + 4 * i)?)))
+                    .collect::<Option<_>>()?;
+                Event::NumberOfCompletedPackets { completed }
+            }
+            0x3E if params.first() == Some(&0x01) => {
+                let mut addr = [0; 6];
+                addr.copy_from_slice(params.get(6..12)?);
+                Event::LeConnectionComplete {
+                    status: *params.get(1)?,
+                    handle: u16_at(2)? & 0x0FFF,
+                    peer: (*params.get(5)?, addr),
+                }
+            }
+            VENDOR_EVENT => Event::Vendor {
+                params: params.to_vec(),
+            },
+            _ => Event::Other {
+                code,
+                params: params.to_vec(),
+            },
+        })
+    }
+}
+
+/// A packet received from the controller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    Event(Event),
+    Acl {
+        handle: u16,
+        boundary: Boundary,
+        data: Vec<u8>,
+    },
+}
+
+/// Writes an HCI command packet.
+pub fn write_command(w: &mut impl Write, opcode: u16, params: &[u8]) -> io::Result<()> {
+    assert!(params.len() <= 255);
+
+    let mut packet = vec![COMMAND];
+    packet.extend_from_slice(&opcode.to_le_bytes());
+    packet.push(params.len() as u8);
+    packet.extend_from_slice(params);
+    w.write_all(&packet)?;
+    w.flush()
+}
+
+/// Writes an ACL data packet.
+pub fn write_acl(
+    w: &mut impl Write,
+    handle: u16,
+    boundary: Boundary,
+    data: &[u8],
+) -> io::Result<()> {
+    assert!(data.len() <= usize::from(u16::MAX));
+
+    // Host to controller starts use the "first non-automatically-flushable" flag
+    let pb = match boundary {
+        Boundary::Start => 0b00,
+        Boundary::Continuation => 0b01,
+    };
+
+    let mut packet = vec![ACL_DATA];
+    packet.extend_from_slice(&((handle & 0x0FFF) | (pb << 12)).to_le_bytes());
+    packet.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    packet.extend_from_slice(data);
+    w.write_all(&packet)?;
+    w.flush()
+}
+
+/// Reads the next packet sent by the controller.
+///
+/// Returns `Ok(None)` if the read timed out before the start of a packet was received, or if an
+/// event was too short to be decoded.
+pub fn read_packet(r: &mut impl Read) -> io::Result<Option<Packet>> {
+    let mut kind = [0];
+    match r.read(&mut kind) {
+        Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock => {
+            return Ok(None)
+        }
+        Err(e) => return Err(e),
+    }
+
+    match kind[0] {
+        EVENT => {
+            let mut header = [0; 2];
+            r.read_exact(&mut header)?;
+            let mut params = vec![0; usize::from(header[1])];
+            r.read_exact(&mut params)?;
+            Ok(Event::parse(header[0], &params).map(Packet::Event))
+        }
+        ACL_DATA => {
+            let mut header = [0; 4];
+            r.read_exact(&mut header)?;
+            let raw_handle = u16::from_le_bytes([header[0], header[1]]);
+            let len = u16::from_le_bytes([header[2], header[3]]);
+            let mut data = vec![0; usize::from(len)];
+            r.read_exact(&mut data)?;
+
+            let boundary = if (raw_handle >> 12) & 0b11 == 0b01 {
+                Boundary::Continuation
+            } else {
+                Boundary::Start
+            };
+            Ok(Some(Packet::Acl {
+                handle: raw_handle & 0x0FFF,
+                boundary,
+                data,
+            }))
+        }
+        other => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unknown H4 packet type {:#04X}", other),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events() {
+        let raw: &[u8] = &[
+            // LE Connection Complete
+            0x04, 0x3E, 0x13, 0x01, 0x00, 0x40, 0x00, 0x01, 0x01, 1, 2, 3, 4, 5, 6, 0x18, 0x00,
+            0x00, 0x00, 0xC8, 0x00, 0x00, //
+            // ACL data, continuation
+            0x02, 0x40, 0x10, 0x01, 0x00, 0xAA,
+        ];
+        let mut r = raw;
+        assert_eq!(
+            read_packet(&mut r).unwrap(),
+            Some(Packet::Event(Event::LeConnectionComplete {
+                status: 0,
+                handle: 0x40,
+                peer: (1, [1, 2, 3, 4, 5, 6]),
+            }))
+        );
+        assert_eq!(
+            read_packet(&mut r).unwrap(),
+            Some(Packet::Acl {
+                handle: 0x40,
+                boundary: Boundary::Continuation,
+                data: vec![0xAA],
+            })
+        );
+    }
+
+    #[test]
+    fn vendor() {
+        assert_eq!(opcode::vendor(0x0006), 0xFC06);
+        assert_eq!(
+            Event::parse(VENDOR_EVENT, &[0x01, 0x02]),
+            Some(Event::Vendor {
+                params: vec![0x01, 0x02],
+            })
+        );
+    }
+}
@@ -0,0 +1,66 @@
+//! Vendor-specific HCI commands and events.
+//!
+//! Controllers expose chip-specific functionality (TX power tables, sleep clock configuration,
+//! writing the public address, ...) via commands in the vendor OGF (0x3F) and report vendor
+//! events with event code 0xFF. The layout of both is defined by the controller vendor, so this
+//! crate doesn't know about any of them. Instead, crates supporting a particular controller can
+//! define their commands by implementing [`VendorCommand`] and send them with
+//! `HciHost::vendor_command`:
+//!
+//! ```
+//! use rubble_hci::vendor::VendorCommand;
+//!
+//! /// Zephyr's "Write Tx Power Level" command.
+//! struct WriteTxPower {
+//!     handle_type: u8,
+//!     handle: u16,
+//!     dbm: i8,
+//! }
+//!
+//! impl VendorCommand for WriteTxPower {
+//!     const OCF: u16 = 0x000E;
+//!
+//!     /// The TX power the controller selected.
+//!     type Response = i8;
+//!
+//!     fn params(&self) -> Vec<u8> {
+//!         let handle = self.handle.to_le_bytes();
+//!         vec![self.handle_type, handle[0], handle[1], self.dbm as u8]
+//!     }
+//!
+//!     fn parse_response(ret: &[u8]) -> Result<i8, rubble::Error> {
+//!         ret.get(3).map(|&b| b as i8).ok_or(rubble::Error::Eof)
+//!     }
+//! }
+//! ```
+//!
+//! Vendor events are passed to the handler registered with `HciHost::on_vendor_event`.
+//!
+//! [`VendorCommand`]: trait.VendorCommand.html
+
+use crate::packet::opcode;
+
+/// A vendor-specific HCI command.
+pub trait VendorCommand {
+    /// Opcode Command Field of the command. The OGF is always 0x3F.
+    const OCF: u16;
+
+    /// Data returned by the controller on success.
+    type Response;
+
+    /// Encodes the command parameters.
+    fn params(&self) -> Vec<u8>;
+
+    /// Decodes the return parameters of the *Command Complete* event, **after** the status Byte.
+    ///
+    /// Commands that are acknowledged with a *Command Status* event instead get an empty slice.
+    fn parse_response(ret: &[u8]) -> Result<Self::Response, rubble::Error>;
+
+    /// Returns the full opcode of the command.
+    fn opcode() -> u16 {
+        opcode::vendor(Self::OCF)
+    }
+}
+
+/// Handler for vendor-specific events, called with the event parameters.
+pub type VendorEventHandler = Box<dyn FnMut(&[u8])>;
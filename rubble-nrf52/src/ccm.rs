@@ -0,0 +1,270 @@
+//! AES-CCM using the CCM peripheral.
+//!
+//! The CCM peripheral implements the packet encryption used by encrypted BLE connections. It can
+//! be used in two ways:
+//!
+//! * Blocking, via Rubble's `CcmEngine` trait: The packet is encrypted or decrypted in RAM before
+//!   it is transmitted or after it was received.
+//! * On the fly, synchronized with the RADIO: For transmissions, the CCM encrypts the packet while
+//!   the radio ramps up and the radio then transmits from the CCM's output buffer. For receptions,
+//!   the pre-programmed PPI channels 24 (`RADIO.READY` → `CCM.KSGEN`) and 25 (`RADIO.ADDRESS` →
+//!   `CCM.CRYPT`) make the CCM decrypt the packet while it is being received, so the plain payload
+//!   is available right after `RADIO.END`.
+//!
+//! The CCM shares its hardware with the AAR (Accelerated Address Resolver) and ECB peripherals.
+//! The AAR must not be used while encryption is running, and `BleEcb` operations will be aborted
+//! and restarted when the CCM needs the AES core.
+//!
+//! The CCM operates on packets in its own RAM layout (header, length and one RFU Byte, followed by
+//! the payload), so for on-the-fly operation the RADIO must be configured to include the S1 field
+//! in RAM (`PCNF0.S1INCL`) while the connection is encrypted.
+//!
+//! Only the default packet length mode (payloads of up to 27 Bytes) is supported.
+
+#[cfg(feature = "52810")]
+use nrf52810_hal::nrf52810_pac as pac;
+
+#[cfg(feature = "52832")]
+use nrf52832_hal::nrf52832_pac as pac;
+
+#[cfg(feature = "52840")]
+use nrf52840_hal::nrf52840_pac as pac;
+
+use {
+    core::sync::atomic::{compiler_fence, Ordering},
+    pac::{CCM, PPI},
+    rubble::{
+        link::ccm::{CcmEngine, CcmParams, Direction, MIC_LEN},
+        Error,
+    },
+};
+
+/// Largest payload the CCM can handle in default length mode.
+pub const MAX_PAYLOAD: usize = 27;
+
+/// A packet in the CCM's RAM layout: header, length, RFU Byte, payload and MIC.
+pub type CcmPacket = [u8; 3 + MAX_PAYLOAD + MIC_LEN];
+
+/// Memory used by the CCM peripheral.
+///
+/// The peripheral accesses this via DMA, also while on-the-fly operations are running, so it has
+/// to be `'static`.
+pub struct CcmBuffers {
+    /// The "CCM data structure": key, packet counter, direction and IV.
+    config: [u8; 33],
+    /// Scratch area for the key stream.
+    scratch: [u8; 43],
+    /// Output packet.
+    out: CcmPacket,
+}
+
+impl CcmBuffers {
+    /// Creates zeroed buffers.
+    pub const fn new() -> Self {
+        Self {
+            config: [0; 33],
+            scratch: [0; 43],
+            out: [0; 3 + MAX_PAYLOAD + MIC_LEN],
+        }
+    }
+}
+
+/// MODE register: decryption instead of encryption.
+const MODE_DECRYPTION: u32 = 1 << 0;
+
+/// SHORTS register: start `CRYPT` after `ENDKSGEN`.
+const SHORTS_ENDKSGEN_CRYPT: u32 = 1 << 0;
+
+/// Pre-programmed PPI channels used for on-the-fly decryption.
+const PPI_CHANNELS: u32 = (1 << 24) | (1 << 25);
+
+/// Implements Rubble's `CcmEngine` trait using the CCM peripheral.
+pub struct BleCcm {
+    ccm: CCM,
+    buffers: &'static mut CcmBuffers,
+}
+
+impl BleCcm {
+    /// Takes ownership of the CCM peripheral and the buffers it uses.
+    pub fn new(ccm: CCM, buffers: &'static mut CcmBuffers) -> Self {
+        ccm.enable.write(|w| unsafe { w.bits(2) });
+        ccm.scratchptr
+            .write(|w| unsafe { w.bits(buffers.scratch.as_mut_ptr() as u32) });
+        Self { ccm, buffers }
+    }
+
+    /// Disables and releases the CCM peripheral.
+    pub fn free(self) -> CCM {
+        self.ccm.enable.write(|w| unsafe { w.bits(0) });
+        self.ccm
+    }
+
+    /// Starts encrypting `packet` on the fly.
+    ///
+    /// `packet` must be in the CCM's RAM layout. The key stream generation is started immediately,
+    /// followed by the encryption. The returned pointer must be written to `RADIO.PACKETPTR`, and
+    /// the radio can then be started: The CCM is done long before the radio has ramped up.
+    ///
+    /// `packet` must not be modified until `wait` has been called, which must happen before the
+    /// next CCM operation.
+    pub fn start_encrypt(&mut self, params: &CcmParams, packet: &CcmPacket) -> u32 {
+        self.configure(params, false, packet.as_ptr());
+        self.ccm
+            .shorts
+            .write(|w| unsafe { w.bits(SHORTS_ENDKSGEN_CRYPT) });
+
+        // Make sure the buffers are written before the peripheral starts reading them
+        compiler_fence(Ordering::Release);
+        self.ccm.tasks_ksgen.write(|w| unsafe { w.bits(1) });
+
+        self.buffers.out.as_ptr() as u32
+    }
+
+    /// Prepares decrypting a packet on the fly, while the radio receives it into `radio_buf`.
+    ///
+    /// `radio_buf` must be the buffer `RADIO.PACKETPTR` points to, in the CCM's RAM layout. This
+    /// enables the PPI channels that start the CCM along with the radio, so it must be called
+    /// before the radio is enabled. After `RADIO.END`, `finish_decrypt` returns the result.
+    pub fn arm_decrypt(&mut self, ppi: &PPI, params: &CcmParams, radio_buf: &CcmPacket) {
+        self.configure(params, true, radio_buf.as_ptr());
+        self.ccm
+            .shorts
+            .write(|w| unsafe { w.bits(SHORTS_ENDKSGEN_CRYPT) });
+        compiler_fence(Ordering::Release);
+        ppi.chenset.write(|w| unsafe { w.bits(PPI_CHANNELS) });
+    }
+
+    /// Waits for the on-the-fly decryption to finish and disables the PPI channels again.
+    ///
+    /// Returns the decrypted packet (in the CCM's RAM layout) if the MIC matched, and
+    /// `Error::InvalidValue` otherwise. Packets with an empty payload are not encrypted and come
+    /// back unchanged.
+    pub fn finish_decrypt(&mut self, ppi: &PPI) -> Result<&CcmPacket, Error> {
+        let result = self.wait();
+        ppi.chenclr.write(|w| unsafe { w.bits(PPI_CHANNELS) });
+        result?;
+        Ok(&self.buffers.out)
+    }
+
+    /// Waits for the current operation to finish.
+    ///
+    /// Returns `Error::InvalidValue` if the operation was a decryption whose MIC didn't match.
+    pub fn wait(&mut self) -> Result<(), Error> {
+        while self.ccm.events_endcrypt.read().bits() == 0 {
+            if self.ccm.events_error.read().bits() != 0 {
+                // Only happens when the radio delivers data faster than the CCM can process it,
+                // which can't happen at 1 Mbit/s
+                self.ccm.events_error.reset();
+                return Err(Error::InvalidValue);
+            }
+        }
+        compiler_fence(Ordering::Acquire);
+        self.ccm.events_endcrypt.reset();
+        self.ccm.events_endksgen.reset();
+        self.ccm.shorts.reset();
+
+        let decrypting = self.ccm.mode.read().bits() & MODE_DECRYPTION != 0;
+        if decrypting && self.ccm.micstatus.read().bits() == 0 {
+            Err(Error::InvalidValue)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Writes the CCM data structure and sets up the peripheral for an operation reading from
+    /// `input`.
+    fn configure(&mut self, params: &CcmParams, decrypt: bool, input: *const u8) {
+        let config = &mut self.buffers.config;
+        config[..16].copy_from_slice(&params.key);
+        config[16..24].copy_from_slice(&params.counter.to_le_bytes());
+        config[24] = match params.direction {
+            Direction::MasterToSlave => 1,
+            Direction::SlaveToMaster => 0,
+        };
+        config[25..].copy_from_slice(&params.iv);
+
+        let mode = if decrypt { MODE_DECRYPTION } else { 0 };
+        unsafe {
+            self.ccm.mode.write(|w| w.bits(mode));
+            self.ccm.cnfptr.write(|w| w.bits(config.as_ptr() as u32));
+            self.ccm.inptr.write(|w| w.bits(input as u32));
+            self.ccm
+                .outptr
+                .write(|w| w.bits(self.buffers.out.as_mut_ptr() as u32));
+        }
+        self.ccm.events_endksgen.reset();
+        self.ccm.events_endcrypt.reset();
+        self.ccm.events_error.reset();
+    }
+
+    /// Runs a blocking operation on `payload`, which is copied into the CCM's RAM layout first.
+    fn run(
+        &mut self,
+        params: &CcmParams,
+        decrypt: bool,
+        header: u8,
+        payload: &[u8],
+    ) -> Result<&[u8], Error> {
+        let max = if decrypt {
+            MAX_PAYLOAD + MIC_LEN
+        } else {
+            MAX_PAYLOAD
+        };
+        if payload.len() > max {
+            return Err(Error::Eof);
+        }
+
+        let mut packet: CcmPacket = [0; 3 + MAX_PAYLOAD + MIC_LEN];
+        packet[0] = header;
+        packet[1] = payload.len() as u8;
+        packet[3..3 + payload.len()].copy_from_slice(payload);
+
+        self.configure(params, decrypt, packet.as_ptr());
+        self.ccm
+            .shorts
+            .write(|w| unsafe { w.bits(SHORTS_ENDKSGEN_CRYPT) });
+        compiler_fence(Ordering::Release);
+        self.ccm.tasks_ksgen.write(|w| unsafe { w.bits(1) });
+        self.wait()?;
+
+        let len = usize::from(self.buffers.out[1]);
+        Ok(&self.buffers.out[3..3 + len])
+    }
+}
+
+impl CcmEngine for BleCcm {
+    fn encrypt(
+        &mut self,
+        params: &CcmParams,
+        header: u8,
+        payload: &[u8],
+        out: &mut [u8],
+    ) -> Result<usize, Error> {
+        if out.len() < payload.len() + MIC_LEN {
+            return Err(Error::Eof);
+        }
+
+        let encrypted = self.run(params, false, header, payload)?;
+        out[..encrypted.len()].copy_from_slice(encrypted);
+        Ok(encrypted.len())
+    }
+
+    fn decrypt(
+        &mut self,
+        params: &CcmParams,
+        header: u8,
+        payload: &[u8],
+        out: &mut [u8],
+    ) -> Result<usize, Error> {
+        if payload.len() < MIC_LEN {
+            return Err(Error::InvalidLength);
+        }
+        if out.len() < payload.len() - MIC_LEN {
+            return Err(Error::Eof);
+        }
+
+        let decrypted = self.run(params, true, header, payload)?;
+        out[..decrypted.len()].copy_from_slice(decrypted);
+        Ok(decrypted.len())
+    }
+}
@@ -0,0 +1,182 @@
+//! Direct Test Mode radio backend.
+//!
+//! `BleDtm` configures the RADIO for DTM test packets and implements Rubble's `DtmRadio` trait.
+//! It needs some help from the firmware, since it doesn't own a timer:
+//!
+//! * During a transmitter test, `transmit_next` must be called every `tx_interval` (eg. from a
+//!   timer interrupt). The first packet is sent when the test starts.
+//! * During a receiver test, the RADIO interrupt should be enabled for the `END` event, and
+//!   `take_received` called from the interrupt handler. When it returns `true`,
+//!   `Dtm::packet_received` must be called.
+
+#[cfg(feature = "52810")]
+use nrf52810_hal::nrf52810_pac as pac;
+
+#[cfg(feature = "52832")]
+use nrf52832_hal::nrf52832_pac as pac;
+
+#[cfg(feature = "52840")]
+use nrf52840_hal::nrf52840_pac as pac;
+
+use {
+    core::sync::atomic::{compiler_fence, Ordering},
+    pac::RADIO,
+    rubble::{
+        dtm::{DtmRadio, ACCESS_ADDRESS, CRC_PRESET, MAX_PAYLOAD},
+        link::CRC_POLY,
+        time::Duration,
+    },
+};
+
+/// Buffer holding a test packet (header and payload).
+pub type DtmBuffer = [u8; 2 + MAX_PAYLOAD];
+
+/// Implements `DtmRadio` on top of the nRF RADIO peripheral.
+pub struct BleDtm {
+    radio: RADIO,
+    buf: &'static mut DtmBuffer,
+    tx_interval: Option<Duration>,
+}
+
+impl BleDtm {
+    /// Takes ownership of the radio and configures it for Direct Test Mode.
+    ///
+    /// `buf` is used for transmitted and received test packets.
+    pub fn new(radio: RADIO, buf: &'static mut DtmBuffer) -> Self {
+        assert!(radio.state.read().state().is_disabled());
+
+        radio.mode.write(|w| w.mode().ble_1mbit());
+        unsafe {
+            radio
+                .pcnf0
+                .write(|w| w.s0len().bit(true).lflen().bits(8).s1len().bits(0));
+            // Test packets are not whitened
+            radio
+                .pcnf1
+                .write(|w| w.maxlen().bits(MAX_PAYLOAD as u8).balen().bits(3));
+
+            #[cfg(not(feature = "52840"))]
+            radio.crccnf.write(|w| w.skipaddr().set_bit().len().three());
+
+            #[cfg(feature = "52840")]
+            radio.crccnf.write(|w| w.skipaddr().bits(1).len().three());
+
+            radio
+                .crcpoly
+                .write(|w| w.crcpoly().bits(CRC_POLY & 0x00FFFFFF));
+            radio.crcinit.write(|w| w.crcinit().bits(CRC_PRESET));
+
+            // See `BleRadio::setup_ble` for why the base address is shifted
+            radio.base0.write(|w| w.bits(ACCESS_ADDRESS << 8));
+            radio
+                .prefix0
+                .write(|w| w.ap0().bits((ACCESS_ADDRESS >> 24) as u8));
+            radio.txaddress.write(|w| w.txaddress().bits(0));
+        }
+        radio.rxaddresses.write(|w| w.addr0().enabled());
+
+        Self {
+            radio,
+            buf,
+            tx_interval: None,
+        }
+    }
+
+    /// Releases the radio.
+    pub fn free(mut self) -> RADIO {
+        self.stop_test();
+        self.radio
+    }
+
+    /// Returns the interval at which `transmit_next` must be called, if a transmitter test is
+    /// running.
+    pub fn tx_interval(&self) -> Option<Duration> {
+        self.tx_interval
+    }
+
+    /// Sends the next test packet of a running transmitter test.
+    pub fn transmit_next(&mut self) {
+        if self.tx_interval.is_none() {
+            return;
+        }
+
+        // The previous packet has long been sent, but make sure the radio is ready
+        while !self.radio.state.read().state().is_disabled() {}
+        self.radio.events_disabled.reset();
+        self.radio
+            .packetptr
+            .write(|w| unsafe { w.bits(self.buf.as_ptr() as u32) });
+
+        // "Preceding reads and writes cannot be moved past subsequent writes."
+        compiler_fence(Ordering::Release);
+        self.radio.tasks_txen.write(|w| unsafe { w.bits(1) });
+    }
+
+    /// Checks whether a test packet with correct CRC has been received during a receiver test.
+    ///
+    /// The radio keeps receiving after each packet.
+    pub fn take_received(&mut self) -> bool {
+        if self.radio.events_end.read().bits() == 0 {
+            return false;
+        }
+
+        self.radio.events_end.reset();
+        compiler_fence(Ordering::Acquire);
+        self.radio.crcstatus.read().crcstatus().is_crcok()
+    }
+
+    /// Disables the radio and waits until it's done.
+    fn disable(&mut self) {
+        self.radio.intenclr.write(|w| w.end().clear());
+        self.radio.shorts.reset();
+        self.radio.events_disabled.reset();
+        self.radio.tasks_disable.write(|w| unsafe { w.bits(1) });
+        while self.radio.events_disabled.read().bits() == 0 {}
+        self.radio.events_disabled.reset();
+    }
+
+    fn set_channel(&mut self, rf_channel: u8) {
+        // RF channel 0 is at 2402 MHz, in steps of 2 MHz
+        let offset = 2 + 2 * rf_channel;
+        self.radio
+            .frequency
+            .write(|w| unsafe { w.frequency().bits(offset) });
+    }
+}
+
+impl DtmRadio for BleDtm {
+    fn start_transmitter_test(&mut self, rf_channel: u8, packet: &[u8], interval: Duration) {
+        self.disable();
+        self.set_channel(rf_channel);
+        self.buf[..packet.len()].copy_from_slice(packet);
+        self.radio
+            .shorts
+            .write(|w| w.ready_start().enabled().end_disable().enabled());
+
+        self.tx_interval = Some(interval);
+        self.transmit_next();
+    }
+
+    fn start_receiver_test(&mut self, rf_channel: u8) {
+        self.disable();
+        self.set_channel(rf_channel);
+        self.radio
+            .packetptr
+            .write(|w| unsafe { w.bits(self.buf.as_mut_ptr() as u32) });
+
+        // Keep receiving after each packet
+        self.radio
+            .shorts
+            .write(|w| w.ready_start().enabled().end_start().enabled());
+        self.radio.events_end.reset();
+        self.radio.intenset.write(|w| w.end().set());
+
+        compiler_fence(Ordering::Release);
+        self.radio.tasks_rxen.write(|w| unsafe { w.bits(1) });
+    }
+
+    fn stop_test(&mut self) {
+        self.tx_interval = None;
+        self.disable();
+    }
+}
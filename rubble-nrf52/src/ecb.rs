@@ -0,0 +1,73 @@
+//! AES-128 using the ECB peripheral.
+//!
+//! The ECB peripheral encrypts a single 16-Byte block in ECB mode. Rubble uses this for LE
+//! Privacy (resolving private addresses).
+
+#[cfg(feature = "52810")]
+use nrf52810_hal::nrf52810_pac as pac;
+
+#[cfg(feature = "52832")]
+use nrf52832_hal::nrf52832_pac as pac;
+
+#[cfg(feature = "52840")]
+use nrf52840_hal::nrf52840_pac as pac;
+
+use {
+    core::sync::atomic::{compiler_fence, Ordering},
+    pac::ECB,
+    rubble::link::privacy::Aes128,
+};
+
+/// Implements Rubble's `Aes128` trait using the ECB peripheral.
+pub struct BleEcb {
+    ecb: ECB,
+}
+
+impl BleEcb {
+    /// Takes ownership of the ECB peripheral.
+    pub fn new(ecb: ECB) -> Self {
+        Self { ecb }
+    }
+
+    /// Releases the ECB peripheral.
+    pub fn free(self) -> ECB {
+        self.ecb
+    }
+}
+
+impl Aes128 for BleEcb {
+    fn encrypt_block(&mut self, key: &[u8; 16], block: &mut [u8; 16]) {
+        // The peripheral expects key, cleartext and room for the ciphertext in one buffer. All of
+        // it is big-endian, just like the arguments.
+        let mut data = [0u8; 48];
+        data[..16].copy_from_slice(key);
+        data[16..32].copy_from_slice(block);
+
+        self.ecb
+            .ecbdataptr
+            .write(|w| unsafe { w.bits(data.as_mut_ptr() as u32) });
+        self.ecb.events_endecb.reset();
+        self.ecb.events_errorecb.reset();
+
+        // Make sure the buffer is written before the peripheral starts reading it
+        compiler_fence(Ordering::Release);
+        self.ecb.tasks_startecb.write(|w| unsafe { w.bits(1) });
+
+        loop {
+            if self.ecb.events_endecb.read().bits() != 0 {
+                break;
+            }
+
+            // An error only happens when the radio's CCM block preempts us. Just restart.
+            if self.ecb.events_errorecb.read().bits() != 0 {
+                self.ecb.events_errorecb.reset();
+                self.ecb.tasks_startecb.write(|w| unsafe { w.bits(1) });
+            }
+        }
+
+        compiler_fence(Ordering::Acquire);
+        self.ecb.events_endecb.reset();
+
+        block.copy_from_slice(&data[32..]);
+    }
+}
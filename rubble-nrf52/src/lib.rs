@@ -3,5 +3,9 @@
 #![no_std]
 #![warn(rust_2018_idioms)]
 
+pub mod ccm;
+pub mod dtm;
+pub mod ecb;
+pub mod power;
 pub mod radio;
 pub mod timer;
@@ -0,0 +1,78 @@
+//! Power hooks that stop the high-frequency crystal oscillator between radio activities.
+//!
+//! The radio needs the 32 MHz crystal (HFXO) to run, but most of the time between BLE events it
+//! is idle. `HfxoHooks` stops the HFXO when the Link-Layer turns the radio off, and restarts it
+//! (blocking until it is stable) before the radio is needed again.
+//!
+//! While the HFXO is stopped, the HFCLK falls back to the internal RC oscillator, which is far
+//! less accurate. `BleTimer` runs from the HFCLK, so these hooks must only be used with a `Timer`
+//! clocked by the LFCLK (eg. one built on an RTC).
+
+#[cfg(feature = "52810")]
+use nrf52810_hal::nrf52810_pac as pac;
+
+#[cfg(feature = "52832")]
+use nrf52832_hal::nrf52832_pac as pac;
+
+#[cfg(feature = "52840")]
+use nrf52840_hal::nrf52840_pac as pac;
+
+use {
+    pac::CLOCK,
+    rubble::{
+        link::power::{PowerHooks, SleepWindow},
+        time::Duration,
+    },
+};
+
+/// Worst-case time the HFXO needs to start up and stabilize.
+///
+/// This depends on the crystal used. The datasheets specify a typical startup time of 360 µs, so
+/// this leaves a generous margin.
+pub const HFXO_STARTUP: Duration = Duration::from_micros(1_500);
+
+/// Implements Rubble's `PowerHooks` by stopping and starting the HFXO.
+pub struct HfxoHooks {
+    clock: CLOCK,
+}
+
+impl HfxoHooks {
+    /// Takes ownership of the `CLOCK` peripheral and starts the HFXO.
+    pub fn new(clock: CLOCK) -> Self {
+        let mut this = Self { clock };
+        this.exit_idle();
+        this
+    }
+
+    /// Releases the `CLOCK` peripheral, leaving the HFXO running.
+    pub fn free(mut self) -> CLOCK {
+        self.exit_idle();
+        self.clock
+    }
+
+    /// Returns whether the HFCLK is currently running from the crystal.
+    pub fn is_running(&self) -> bool {
+        self.clock.hfclkstat.read().src().is_xtal()
+    }
+}
+
+impl PowerHooks for HfxoHooks {
+    fn wakeup_time(&self) -> Duration {
+        HFXO_STARTUP
+    }
+
+    fn enter_idle(&mut self, _: &SleepWindow) {
+        self.clock.tasks_hfclkstop.write(|w| unsafe { w.bits(1) });
+    }
+
+    fn exit_idle(&mut self) {
+        if self.is_running() {
+            return;
+        }
+
+        self.clock.events_hfclkstarted.reset();
+        self.clock.tasks_hfclkstart.write(|w| unsafe { w.bits(1) });
+        while self.clock.events_hfclkstarted.read().bits() == 0 {}
+        self.clock.events_hfclkstarted.reset();
+    }
+}
@@ -51,6 +51,7 @@ use nrf52832_hal::nrf52832_pac as pac;
 use nrf52840_hal::nrf52840_pac as pac;
 
 use {
+    crate::timer::AddressCapture,
     core::{
         cmp,
         sync::atomic::{compiler_fence, Ordering},
@@ -58,15 +59,60 @@ use {
     pac::{radio::state::STATER, RADIO},
     rubble::{
         config::Config,
-        link::{advertising, data, Cmd, LinkLayer, RadioCmd, Transmitter, CRC_POLY, MIN_PDU_BUF},
-        phy::{AdvertisingChannel, DataChannel},
+        link::{
+            advertising, data,
+            filter::HardwareAddressFilter,
+            trace::{self, TracePoint},
+            Cmd, DeviceAddress, LinkLayer, RadioCmd, Transmitter, CRC_POLY, MIN_PDU_BUF,
+        },
+        phy::{AdvertisingChannel, DataChannel, TxPower},
         time::{Duration, Instant},
+        Error,
     },
 };
 
 /// A packet buffer that can hold header and payload of any advertising or data channel packet.
 pub type PacketBuffer = [u8; MIN_PDU_BUF];
 
+/// Number of bits after the access address after which the device address match is checked.
+///
+/// This covers the 2-Byte header and the 6-Byte sender address, plus a few bits of margin to make
+/// sure the `DEVMATCH`/`DEVMISS` event has been generated.
+const FILTER_BIT_COUNT: u32 = 16 + 48 + 8;
+
+/// The TX power levels supported by the radio, in ascending order.
+#[cfg(not(feature = "52840"))]
+static TX_POWER_LEVELS: &[TxPower] = &[
+    TxPower::from_dbm(-40),
+    TxPower::from_dbm(-20),
+    TxPower::from_dbm(-16),
+    TxPower::from_dbm(-12),
+    TxPower::from_dbm(-8),
+    TxPower::from_dbm(-4),
+    TxPower::from_dbm(0),
+    TxPower::from_dbm(3),
+    TxPower::from_dbm(4),
+];
+
+/// The TX power levels supported by the radio, in ascending order.
+#[cfg(feature = "52840")]
+static TX_POWER_LEVELS: &[TxPower] = &[
+    TxPower::from_dbm(-40),
+    TxPower::from_dbm(-20),
+    TxPower::from_dbm(-16),
+    TxPower::from_dbm(-12),
+    TxPower::from_dbm(-8),
+    TxPower::from_dbm(-4),
+    TxPower::from_dbm(0),
+    TxPower::from_dbm(2),
+    TxPower::from_dbm(3),
+    TxPower::from_dbm(4),
+    TxPower::from_dbm(5),
+    TxPower::from_dbm(6),
+    TxPower::from_dbm(7),
+    TxPower::from_dbm(8),
+];
+
 /// An interface to the nRF radio in BLE mode.
 pub struct BleRadio {
     /// `true` if the radio is operating on an advertising channel, `false` if it's a data channel.
@@ -74,11 +120,23 @@ pub struct BleRadio {
     radio: RADIO,
     tx_buf: &'static mut PacketBuffer,
 
+    /// The currently configured TX power (always one of `TX_POWER_LEVELS`).
+    tx_power: TxPower,
+
+    /// Whether device address matching is enabled on advertising channels.
+    address_filter: bool,
+
+    /// The advertising channel we're listening on, to restart reception after dropping a packet.
+    adv_channel: AdvertisingChannel,
+
     /// Receive buffer.
     ///
     /// This is an `Option` because we need to pass a `&mut BleRadio` to the BLE stack while still
     /// having access to this buffer.
     rx_buf: Option<&'static mut PacketBuffer>,
+
+    /// Source of precise RX timestamps, if enabled.
+    address_capture: Option<AddressCapture>,
 }
 
 impl BleRadio {
@@ -91,11 +149,30 @@ impl BleRadio {
     ) -> Self {
         assert!(radio.state.read().state().is_disabled());
 
+        let mut this = Self {
+            advertising: false,
+            radio,
+            tx_buf,
+            tx_power: TxPower::DEFAULT,
+            address_filter: false,
+            adv_channel: AdvertisingChannel::first(),
+            rx_buf: Some(rx_buf),
+            address_capture: None,
+        };
+        this.setup_ble();
+        this
+    }
+
+    /// Applies the BLE radio configuration that is independent of the current channel.
+    fn setup_ble(&mut self) {
+        let radio = &self.radio;
         radio.mode.write(|w| w.mode().ble_1mbit());
-        radio.txpower.write(|w| w.txpower().pos4d_bm());
+        radio
+            .txpower
+            .write(|w| unsafe { w.bits(u32::from(self.tx_power.as_dbm() as u8)) });
 
-        let max_payload = rx_buf.len() - 2;
-        assert!(max_payload <= usize::from(u8::max_value()));
+        let max_payload = MIN_PDU_BUF - 2;
+        assert!(max_payload <= usize::from(u8::MAX));
 
         unsafe {
             radio.pcnf1.write(|w| {
@@ -163,13 +240,33 @@ impl BleRadio {
 
         // We can now start the TXEN/RXEN tasks and the radio will do the rest and return to the
         // disabled state.
+    }
 
-        Self {
-            advertising: false,
-            radio,
-            tx_buf,
-            rx_buf: Some(rx_buf),
-        }
+    /// Enables or disables reporting precise RX timestamps to the Link-Layer.
+    ///
+    /// Without an `AddressCapture`, `Transmitter::rx_timestamp` returns `None`.
+    pub fn set_address_capture(&mut self, capture: Option<AddressCapture>) {
+        self.address_capture = capture;
+    }
+
+    /// Disables the radio and hands it over to the application for the duration of a timeslot.
+    ///
+    /// The application may reconfigure the radio freely. Once the timeslot has ended (see
+    /// `LinkLayer::end_timeslot`), `end_timeslot` must be called to restore the BLE configuration.
+    pub fn begin_timeslot(&mut self) -> &mut RADIO {
+        self.configure_receiver(RadioCmd::Off);
+        &mut self.radio
+    }
+
+    /// Takes the radio back from the application after a timeslot, and resumes BLE operation
+    /// according to `cmd`.
+    pub fn end_timeslot(&mut self, cmd: RadioCmd) {
+        // Interrupts enabled by the application are not expected by the BLE interrupt handler
+        self.radio
+            .intenclr
+            .write(|w| unsafe { w.bits(0xFFFF_FFFF) });
+        self.setup_ble();
+        self.configure_receiver(cmd);
     }
 
     /// Returns the current radio state.
@@ -180,7 +277,13 @@ impl BleRadio {
     /// Configures the Radio for (not) receiving data according to `cmd`.
     pub fn configure_receiver(&mut self, cmd: RadioCmd) {
         // Disable `DISABLED` interrupt, effectively stopping reception
-        self.radio.intenclr.write(|w| w.disabled().clear());
+        self.radio
+            .intenclr
+            .write(|w| w.disabled().clear().bcmatch().clear());
+        // Turn the bit counter off (it's only used when listening on advertising channels)
+        self.radio
+            .shorts
+            .modify(|_, w| w.address_bcstart().disabled());
 
         // Acknowledge left-over disable event
         self.radio.events_disabled.reset();
@@ -205,6 +308,29 @@ impl BleRadio {
                 // Match on logical address 0 only
                 self.radio.rxaddresses.write(|w| w.addr0().enabled());
 
+                // Measure the RSSI of received packets (reported for scan requests)
+                self.radio.shorts.modify(|_, w| {
+                    w.address_rssistart()
+                        .enabled()
+                        .disabled_rssistop()
+                        .enabled()
+                });
+
+                if self.address_filter {
+                    // Count the header and the sender address after the access address, then
+                    // check whether the sender matched (see `recv_interrupt`)
+                    self.radio
+                        .bcc
+                        .write(|w| unsafe { w.bits(FILTER_BIT_COUNT) });
+                    self.radio.events_bcmatch.reset();
+                    self.radio.events_devmatch.reset();
+                    self.radio.events_devmiss.reset();
+                    self.radio
+                        .shorts
+                        .modify(|_, w| w.address_bcstart().enabled());
+                    self.radio.intenset.write(|w| w.bcmatch().set());
+                }
+
                 // "Preceding reads and writes cannot be moved past subsequent writes."
                 compiler_fence(Ordering::Release);
 
@@ -255,11 +381,28 @@ impl BleRadio {
     /// Automatically reconfigures the radio according to the `RadioCmd` returned by the BLE stack.
     ///
     /// Returns when the `update` method should be called the next time.
-    pub fn recv_interrupt<C: Config<Transmitter = Self>>(
+    pub fn recv_interrupt<C: Config<Transmitter = Self>, const CONNECTIONS: usize>(
         &mut self,
         timestamp: Instant,
-        ll: &mut LinkLayer<C>,
+        ll: &mut LinkLayer<C, CONNECTIONS>,
     ) -> Option<Cmd> {
+        trace::mark(TracePoint::RadioIrq);
+
+        if self.radio.events_bcmatch.read().bits() != 0 {
+            self.radio.events_bcmatch.reset();
+            self.radio.tasks_bcstop.write(|w| unsafe { w.bits(1) });
+
+            if self.radio.events_devmatch.read().bits() == 0 {
+                // Sender isn't in the hardware filter. Drop the packet and listen again.
+                self.configure_receiver(RadioCmd::ListenAdvertising {
+                    channel: self.adv_channel,
+                });
+                return None;
+            }
+            self.radio.events_devmatch.reset();
+            self.radio.events_devmiss.reset();
+        }
+
         if self.radio.events_disabled.read().bits() == 0 {
             return None;
         }
@@ -317,6 +460,7 @@ impl BleRadio {
     /// Of course, other tasks may also be performed.
     fn prepare_txrx_advertising(&mut self, channel: AdvertisingChannel) {
         self.advertising = true;
+        self.adv_channel = channel;
 
         unsafe {
             // Acknowledge left-over disable event
@@ -395,6 +539,11 @@ impl BleRadio {
             // Acknowledge left-over disable event
             self.radio.events_disabled.reset(); // FIXME unnecessary, right?
 
+            // The address filter only applies to received packets
+            self.radio
+                .shorts
+                .modify(|_, w| w.address_bcstart().disabled());
+
             // "Preceding reads and writes cannot be moved past subsequent writes."
             compiler_fence(Ordering::Release);
 
@@ -406,12 +555,51 @@ impl BleRadio {
 
             // "Subsequent reads and writes cannot be moved ahead of preceding reads."
             compiler_fence(Ordering::Acquire);
+            self.radio.events_bcmatch.reset();
 
             // Now our `tx_buf` can be used again.
         }
     }
 }
 
+impl HardwareAddressFilter for BleRadio {
+    const MAX_ADDRESSES: usize = 8;
+
+    fn set_address_filter(&mut self, addresses: &[DeviceAddress]) -> Result<(), Error> {
+        if addresses.len() > Self::MAX_ADDRESSES {
+            return Err(Error::Eof);
+        }
+
+        let mut enable = 0;
+        let mut txadd = 0;
+        for (i, address) in addresses.iter().enumerate() {
+            let raw = address.raw();
+            let base = u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]);
+            let prefix = u16::from_le_bytes([raw[4], raw[5]]);
+            unsafe {
+                self.radio.dab[i].write(|w| w.bits(base));
+                self.radio.dap[i].write(|w| w.bits(prefix.into()));
+            }
+            enable |= 1 << i;
+            if address.is_random() {
+                txadd |= 1 << i;
+            }
+        }
+
+        // DACNF: ENA0..7 in bits 0-7, TXADD0..7 in bits 8-15
+        self.radio
+            .dacnf
+            .write(|w| unsafe { w.bits(enable | (txadd << 8)) });
+        self.address_filter = !addresses.is_empty();
+        Ok(())
+    }
+
+    fn clear_address_filter(&mut self) {
+        self.radio.dacnf.write(|w| unsafe { w.bits(0) });
+        self.address_filter = false;
+    }
+}
+
 impl Transmitter for BleRadio {
     fn tx_payload_buf(&mut self) -> &mut [u8] {
         // FIXME: Some check must be done to ensure that no transaction is being processed
@@ -472,4 +660,30 @@ impl Transmitter for BleRadio {
             .shorts
             .write(|w| w.ready_start().enabled().end_disable().enabled());
     }
+
+    fn supported_tx_power(&self) -> &[TxPower] {
+        TX_POWER_LEVELS
+    }
+
+    fn last_rssi(&self) -> Option<i8> {
+        // The sample is the magnitude of the (negative) RSSI in dBm
+        Some(-(self.radio.rssisample.read().rssisample().bits() as i8))
+    }
+
+    fn rx_timestamp(&self) -> Option<Instant> {
+        self.address_capture.as_ref().map(AddressCapture::last)
+    }
+
+    fn set_tx_power(&mut self, power: TxPower) {
+        let power = power.clamp_to(TX_POWER_LEVELS);
+        if power == self.tx_power {
+            return;
+        }
+
+        // The register holds the level in dBm as a two's complement 8-bit value
+        self.radio
+            .txpower
+            .write(|w| unsafe { w.bits(u32::from(power.as_dbm() as u8)) });
+        self.tx_power = power;
+    }
 }
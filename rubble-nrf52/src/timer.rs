@@ -10,8 +10,8 @@ use nrf52832_hal::nrf52832_pac as pac;
 use nrf52840_hal::nrf52840_pac as pac;
 
 use {
-    core::mem,
-    pac::{TIMER0, TIMER1, TIMER2},
+    core::{mem, ptr},
+    pac::{PPI, RADIO, TIMER0, TIMER1, TIMER2},
     rubble::{
         link::NextUpdate,
         time::{Instant, Timer},
@@ -89,6 +89,24 @@ impl<T: NrfTimerExt> BleTimer<T> {
             inner: unsafe { self.inner.duplicate() },
         }
     }
+
+    /// Makes the timer capture the time of every `RADIO.ADDRESS` event.
+    ///
+    /// This connects the event to the timer's `CAPTURE[2]` task via the given programmable PPI
+    /// channel, which must not be used for anything else. Pass the returned `AddressCapture` to
+    /// `BleRadio::set_address_capture` to report the captured times as RX timestamps.
+    pub fn capture_radio_address(&self, ppi: &PPI, channel: usize) -> AddressCapture {
+        let radio = unsafe { &*RADIO::ptr() };
+        let ch = &ppi.ch[channel];
+        ch.eep
+            .write(|w| unsafe { w.bits(&radio.events_address as *const _ as u32) });
+        ch.tep
+            .write(|w| unsafe { w.bits(self.inner.capture_task(2)) });
+        ppi.chenset.write(|w| unsafe { w.bits(1 << channel) });
+        AddressCapture {
+            cc: self.inner.cc_register(2),
+        }
+    }
 }
 
 impl<T: NrfTimerExt> Timer for BleTimer<T> {
@@ -97,6 +115,21 @@ impl<T: NrfTimerExt> Timer for BleTimer<T> {
     }
 }
 
+/// Reads the times captured for `RADIO.ADDRESS` events, see `BleTimer::capture_radio_address`.
+pub struct AddressCapture {
+    cc: *const u32,
+}
+
+// The register is only ever read
+unsafe impl Send for AddressCapture {}
+
+impl AddressCapture {
+    /// Returns the time at which the last access address was received.
+    pub fn last(&self) -> Instant {
+        Instant::from_raw_micros(unsafe { ptr::read_volatile(self.cc) })
+    }
+}
+
 /// A timer interface that only allows reading the current time stamp.
 pub struct StampSource<T: NrfTimerExt> {
     inner: T,
@@ -114,7 +147,8 @@ mod sealed {
 
 /// Extension trait implemented for the nRF timer peripherals.
 ///
-/// We use `CC[0]` to read the counter value, and `CC[1]` to set timer interrupts.
+/// We use `CC[0]` to read the counter value, `CC[1]` to set timer interrupts, and `CC[2]` to
+/// capture radio events.
 pub trait NrfTimerExt: sealed::Sealed {
     unsafe fn duplicate(&self) -> Self;
 
@@ -134,6 +168,12 @@ pub trait NrfTimerExt: sealed::Sealed {
 
     /// Obtains the current time as an `Instant`.
     fn now(&self) -> Instant;
+
+    /// Returns the address of the `TASKS_CAPTURE[n]` register, to trigger it via PPI.
+    fn capture_task(&self, n: usize) -> u32;
+
+    /// Returns a pointer to the `CC[n]` register.
+    fn cc_register(&self, n: usize) -> *const u32;
 }
 
 macro_rules! impl_timer {
@@ -176,6 +216,14 @@ macro_rules! impl_timer {
                 let micros = self.cc[0].read().bits();
                 Instant::from_raw_micros(micros)
             }
+
+            fn capture_task(&self, n: usize) -> u32 {
+                &self.tasks_capture[n] as *const _ as u32
+            }
+
+            fn cc_register(&self, n: usize) -> *const u32 {
+                &self.cc[n] as *const _ as *const u32
+            }
         }
 
         impl sealed::Sealed for $ty {}
@@ -0,0 +1,372 @@
+//! ATT client role.
+//!
+//! Both devices of a connection can host attributes, and both can access the attributes of the
+//! other one. A peripheral acts as a client for example to read the time from a phone's *Current
+//! Time Service*, or to receive notifications from a phone's *Apple Notification Center Service*.
+//!
+//! The client shares the ATT bearer with the local `AttributeServer`: Requests are sent via
+//! `AttributeServerTx` (obtained from `L2CAPStateTx::att`), and the server passes responses,
+//! notifications and indications sent by the peer on to
+//! [`AttributeProvider::client_event`][client_event] as [`ClientEvent`]s. Indications are
+//! confirmed automatically.
+//!
+//! Only one request can be outstanding at a time. Starting another one before the previous one has
+//! been answered fails with `Error::InvalidValue`. Procedures consisting of several requests can
+//! instead be driven by the `AttributeProvider`, which hands out each [`ClientRequest`] via
+//! [`AttributeProvider::next_request`][next_request] once the previous one has completed.
+//!
+//! A request that isn't answered within [`TRANSACTION_TIMEOUT`] ends the ATT bearer: No further
+//! PDUs may be sent on it, and the connection has to be closed (see
+//! `AttributeServer::check_timeout`).
+//!
+//! [client_event]: ../trait.AttributeProvider.html#method.client_event
+//! [next_request]: ../trait.AttributeProvider.html#method.next_request
+//! [`ClientEvent`]: enum.ClientEvent.html
+//! [`ClientRequest`]: enum.ClientRequest.html
+//! [`TRANSACTION_TIMEOUT`]: constant.TRANSACTION_TIMEOUT.html
+
+use {
+    super::{
+        handle::RawHandleRange,
+        pdus::{AttPdu, ErrorCode, Opcode},
+        AttUuid, Handle,
+    },
+    crate::{
+        l2cap::Sender,
+        time::{Duration, Instant},
+        utils::HexSlice,
+        uuid::Uuid16,
+        Error,
+    },
+};
+
+/// Time the peer's server has to answer a request before the transaction fails.
+pub const TRANSACTION_TIMEOUT: Duration = Duration::from_micros(30_000_000);
+
+/// Largest value that fits into a write PDU, after the opcode and handle.
+const MAX_WRITE_VALUE: usize = 23 - 3;
+
+/// A request to the peer's ATT server.
+#[derive(Debug, Copy, Clone)]
+pub enum ClientRequest<'a> {
+    /// Reads the attribute at `handle`. Answered with `ClientEvent::Read`.
+    Read { handle: Handle },
+
+    /// Writes `value` to the attribute at `handle`. Answered with `ClientEvent::Written`.
+    Write { handle: Handle, value: &'a [u8] },
+
+    /// Finds attributes in `start..=end` with type `attribute_type` and the given value.
+    /// Answered with `ClientEvent::FoundByTypeValue`.
+    ///
+    /// This is used to discover services by UUID.
+    FindByTypeValue {
+        start: Handle,
+        end: Handle,
+        attribute_type: Uuid16,
+        value: &'a [u8],
+    },
+
+    /// Reads the attributes in `start..=end` with type `attribute_type`. Answered with
+    /// `ClientEvent::ReadByType`.
+    ///
+    /// This is used to discover characteristics.
+    ReadByType {
+        start: Handle,
+        end: Handle,
+        attribute_type: AttUuid,
+    },
+
+    /// Finds the types of the attributes in `start..=end`. Answered with
+    /// `ClientEvent::Information`.
+    ///
+    /// This is used to discover descriptors.
+    FindInformation { start: Handle, end: Handle },
+}
+
+impl<'a> ClientRequest<'a> {
+    /// Returns the handle the request is about, or the start of its handle range.
+    pub fn handle(&self) -> Handle {
+        match *self {
+            ClientRequest::Read { handle } | ClientRequest::Write { handle, .. } => handle,
+            ClientRequest::FindByTypeValue { start, .. }
+            | ClientRequest::ReadByType { start, .. }
+            | ClientRequest::FindInformation { start, .. } => start,
+        }
+    }
+
+    fn to_pdu(self) -> AttPdu<'a> {
+        match self {
+            ClientRequest::Read { handle } => AttPdu::ReadReq { handle },
+            ClientRequest::Write { handle, value } => AttPdu::WriteReq {
+                handle,
+                value: HexSlice(value),
+            },
+            ClientRequest::FindByTypeValue {
+                start,
+                end,
+                attribute_type,
+                value,
+            } => AttPdu::FindByTypeValueReq {
+                handle_range: RawHandleRange::new(start, end),
+                attribute_type: attribute_type.0,
+                attribute_value: HexSlice(value),
+            },
+            ClientRequest::ReadByType {
+                start,
+                end,
+                attribute_type,
+            } => AttPdu::ReadByTypeReq {
+                handle_range: RawHandleRange::new(start, end),
+                attribute_type,
+            },
+            ClientRequest::FindInformation { start, end } => AttPdu::FindInformationReq {
+                handle_range: RawHandleRange::new(start, end),
+            },
+        }
+    }
+}
+
+/// A message from the peer's ATT server.
+#[derive(Debug, Copy, Clone)]
+pub enum ClientEvent<'a> {
+    /// The attribute at `handle` was read.
+    ///
+    /// Values longer than `ATT_MTU - 1` Bytes are truncated.
+    Read { handle: Handle, value: &'a [u8] },
+
+    /// The attribute at `handle` was written.
+    Written { handle: Handle },
+
+    /// The server rejected the request for the attribute at `handle`.
+    Error { handle: Handle, code: ErrorCode },
+
+    /// The server notified the value of the attribute at `handle`.
+    Notification { handle: Handle, value: &'a [u8] },
+
+    /// The server indicated the value of the attribute at `handle`.
+    ///
+    /// The indication is confirmed after the event has been processed.
+    Indication { handle: Handle, value: &'a [u8] },
+
+    /// Response to a `ClientRequest::FindByTypeValue` starting at `handle`.
+    ///
+    /// `handles` is a list of 4-Byte entries, each holding the handle of a found attribute and the
+    /// end handle of its group.
+    FoundByTypeValue { handle: Handle, handles: &'a [u8] },
+
+    /// Response to a `ClientRequest::ReadByType` starting at `handle`.
+    ///
+    /// `data` is a list of `length`-Byte entries, each holding an attribute handle followed by the
+    /// attribute value.
+    ReadByType {
+        handle: Handle,
+        length: u8,
+        data: &'a [u8],
+    },
+
+    /// Response to a `ClientRequest::FindInformation` starting at `handle`.
+    ///
+    /// `data` is a list of attribute handles, each followed by a 16-bit (`format` 1) or 128-bit
+    /// (`format` 2) attribute type.
+    Information {
+        handle: Handle,
+        format: u8,
+        data: &'a [u8],
+    },
+}
+
+impl<'a> ClientEvent<'a> {
+    /// Returns the handle of the attribute the event is about.
+    pub fn handle(&self) -> Handle {
+        match *self {
+            ClientEvent::Read { handle, .. }
+            | ClientEvent::Written { handle }
+            | ClientEvent::Error { handle, .. }
+            | ClientEvent::Notification { handle, .. }
+            | ClientEvent::Indication { handle, .. }
+            | ClientEvent::FoundByTypeValue { handle, .. }
+            | ClientEvent::ReadByType { handle, .. }
+            | ClientEvent::Information { handle, .. } => handle,
+        }
+    }
+}
+
+/// The request that is waiting for a response.
+#[derive(Debug, Copy, Clone)]
+struct Pending {
+    opcode: Opcode,
+    handle: Handle,
+    /// When the transaction timer was started, set by the first `check_timeout` after sending.
+    started: Option<Instant>,
+}
+
+/// Client-side state of the ATT bearer.
+pub(super) struct ClientState {
+    pending: Option<Pending>,
+}
+
+impl ClientState {
+    pub(super) fn new() -> Self {
+        Self { pending: None }
+    }
+
+    /// Returns whether the outstanding request has been waiting for longer than
+    /// `TRANSACTION_TIMEOUT` at `now`.
+    pub(super) fn check_timeout(&mut self, now: Instant) -> bool {
+        match &mut self.pending {
+            Some(pending) => {
+                let started = *pending.started.get_or_insert(now);
+                now.duration_since(started) >= TRANSACTION_TIMEOUT
+            }
+            None => false,
+        }
+    }
+
+    /// Returns whether a request is waiting for its response.
+    pub(super) fn is_busy(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Sends `request` via `sender`.
+    ///
+    /// Returns `Error::InvalidValue` if another request is still outstanding, and
+    /// `Error::InvalidLength` if a written value doesn't fit into a single PDU.
+    pub(super) fn send(
+        &mut self,
+        request: ClientRequest<'_>,
+        sender: &mut Sender<'_>,
+    ) -> Result<(), Error> {
+        if self.pending.is_some() {
+            return Err(Error::InvalidValue);
+        }
+        if let ClientRequest::Write { value, .. } = request {
+            check_write_len(value)?;
+        }
+
+        let pdu = request.to_pdu();
+        let opcode = pdu.opcode();
+        sender.send(pdu)?;
+        self.pending = Some(Pending {
+            opcode,
+            handle: request.handle(),
+            started: None,
+        });
+        Ok(())
+    }
+
+    /// Turns a PDU sent by the peer's server into an event.
+    ///
+    /// Returns `None` if `pdu` isn't meant for the client, or is a response to a request that
+    /// wasn't sent (in which case the pending request, if any, stays outstanding).
+    pub(super) fn process<'a>(&mut self, pdu: &AttPdu<'a>) -> Option<ClientEvent<'a>> {
+        match pdu {
+            AttPdu::HandleValueNotification { handle, value } => Some(ClientEvent::Notification {
+                handle: *handle,
+                value: value.0,
+            }),
+            AttPdu::HandleValueIndication { handle, value } => Some(ClientEvent::Indication {
+                handle: *handle,
+                value: value.0,
+            }),
+            AttPdu::ReadRsp { value } => {
+                let handle = self.finish(Opcode::ReadReq)?;
+                Some(ClientEvent::Read {
+                    handle,
+                    value: value.0,
+                })
+            }
+            AttPdu::WriteRsp => {
+                let handle = self.finish(Opcode::WriteReq)?;
+                Some(ClientEvent::Written { handle })
+            }
+            AttPdu::FindByTypeValueRsp {
+                handles_information_list,
+            } => {
+                let handle = self.finish(Opcode::FindByTypeValueReq)?;
+                Some(ClientEvent::FoundByTypeValue {
+                    handle,
+                    handles: handles_information_list.0,
+                })
+            }
+            AttPdu::ReadByTypeRsp { length, data_list } => {
+                let handle = self.finish(Opcode::ReadByTypeReq)?;
+                Some(ClientEvent::ReadByType {
+                    handle,
+                    length: *length,
+                    data: data_list.0,
+                })
+            }
+            AttPdu::FindInformationRsp { format, data } => {
+                let handle = self.finish(Opcode::FindInformationReq)?;
+                Some(ClientEvent::Information {
+                    handle,
+                    format: *format,
+                    data: data.0,
+                })
+            }
+            AttPdu::ErrorRsp {
+                opcode,
+                handle,
+                error_code,
+            } => {
+                self.finish(*opcode)?;
+                Some(ClientEvent::Error {
+                    handle: *handle,
+                    code: *error_code,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Ends the pending request if it was sent with `opcode`, returning its handle.
+    fn finish(&mut self, opcode: Opcode) -> Option<Handle> {
+        let pending = self.pending?;
+        if pending.opcode.raw() != opcode.raw() {
+            return None;
+        }
+        self.pending = None;
+        Some(pending.handle)
+    }
+}
+
+/// Checks that `value` fits into a write PDU.
+pub(super) fn check_write_len(value: &[u8]) -> Result<(), Error> {
+    if value.len() > MAX_WRITE_VALUE {
+        Err(Error::InvalidLength)
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transaction_timeout() {
+        let mut client = ClientState::new();
+        let start = Instant::from_raw_micros(1_000);
+        assert!(!client.check_timeout(start));
+
+        client.pending = Some(Pending {
+            opcode: Opcode::ReadReq,
+            handle: Handle::from_raw(1),
+            started: None,
+        });
+        assert!(!client.check_timeout(start));
+        assert!(!client.check_timeout(start + Duration::from_secs(29)));
+        assert!(client.check_timeout(start + TRANSACTION_TIMEOUT));
+
+        // Stray responses don't end the transaction
+        assert!(client.process(&AttPdu::WriteRsp).is_none());
+        assert!(client.is_busy());
+        match client.process(&AttPdu::ReadRsp {
+            value: HexSlice(&[1, 2]),
+        }) {
+            Some(ClientEvent::Read { value, .. }) => assert_eq!(value, [1, 2]),
+            other => panic!("{:?}", other),
+        }
+        assert!(!client.check_timeout(start + TRANSACTION_TIMEOUT));
+    }
+}
@@ -26,7 +26,7 @@ impl Handle {
     }
 
     /// Create an attribute handle from a raw u16
-    pub fn from_raw(raw: u16) -> Self {
+    pub const fn from_raw(raw: u16) -> Self {
         Handle(raw)
     }
 }
@@ -58,6 +58,10 @@ pub struct RawHandleRange {
 }
 
 impl RawHandleRange {
+    pub(crate) fn new(start: Handle, end: Handle) -> Self {
+        Self { start, end }
+    }
+
     /// Checks that this handle range is valid according to the Bluetooth spec.
     ///
     /// Returns an `AttError` that should be sent as a response if the range is invalid.
@@ -70,6 +74,12 @@ impl RawHandleRange {
     }
 }
 
+impl fmt::Display for RawHandleRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}..={:?}", self.start, self.end)
+    }
+}
+
 impl FromBytes<'_> for RawHandleRange {
     fn from_bytes(bytes: &mut ByteReader<'_>) -> Result<Self, Error> {
         Ok(Self {
@@ -27,25 +27,47 @@
 //! the group. The *Group End Handle* isn't known by the ATT server and must be provided by the
 //! higher-level protocol (GATT).
 //!
+//! ## Attribute Security
+//!
+//! Attributes can require the link to be encrypted (and the encryption key to be authenticated)
+//! before they may be read or written. The `AttributeProvider` states the requirement of every
+//! attribute via [`AttributeProvider::required_security`], and the `AttributeServer` rejects
+//! accesses over insufficiently secured links with *Insufficient Encryption* or *Insufficient
+//! Authentication* errors. The client is then expected to pair or enable encryption, and to retry.
+//!
+//! The server can't know how the link is secured on its own: Whoever manages encryption has to
+//! tell it via `AttributeServer::set_link_security`.
+//!
 //! [`Handle`]: struct.Handle.html
+//! [`AttributeProvider::required_security`]: trait.AttributeProvider.html#method.required_security
 
+pub mod client;
 mod handle;
 mod pdus;
 mod server;
 mod uuid;
 
 use {
-    self::{handle::*, pdus::*},
+    self::handle::*,
     crate::{utils::HexSlice, Error},
 };
 
+pub use self::client::{ClientEvent, ClientRequest};
 pub use self::handle::{Handle, HandleRange};
+pub use self::pdus::{AttError, ErrorCode};
 pub use self::server::{AttributeServer, AttributeServerTx};
 pub use self::uuid::AttUuid;
 
+#[cfg(any(feature = "fmt", feature = "defmt"))]
+pub(crate) use self::pdus::AttPdu;
+
+/// The `ATT_MTU` of a connection before it is changed with an *Exchange MTU Request*.
+pub const DEFAULT_ATT_MTU: u8 = 23;
+
 /// An ATT server attribute
 pub struct Attribute<'a> {
-    /// The type of the attribute as a UUID16, EG "Primary Service" or "Anaerobic Heart Rate Lower Limit"
+    /// The type of the attribute as a 16- or 128-bit UUID, EG "Primary Service", "Anaerobic Heart
+    /// Rate Lower Limit" or a vendor-specific type
     pub att_type: AttUuid,
     /// Unique server-side identifer for attribute
     pub handle: Handle,
@@ -54,6 +76,81 @@ pub struct Attribute<'a> {
     pub value: HexSlice<&'a [u8]>,
 }
 
+impl<'a> Attribute<'a> {
+    /// Creates an attribute of type `att_type`, which can be given as any kind of UUID.
+    pub fn new(att_type: impl Into<AttUuid>, handle: Handle, value: &'a [u8]) -> Self {
+        Self {
+            att_type: att_type.into(),
+            handle,
+            value: HexSlice(value),
+        }
+    }
+}
+
+/// Security properties of a link, ordered from least to most secure.
+///
+/// This is used both for the state of the link and for the requirements of attributes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LinkSecurity {
+    /// The link is not encrypted.
+    Unencrypted,
+
+    /// The link is encrypted with an unauthenticated key (eg. obtained via *"Just Works"*
+    /// pairing).
+    Encrypted,
+
+    /// The link is encrypted with an authenticated (MITM-protected) key.
+    Authenticated,
+}
+
+impl LinkSecurity {
+    /// Checks whether a link secured with `self` satisfies the attribute requirement `required`.
+    ///
+    /// Returns the ATT error code to reject the access with otherwise.
+    pub fn check(self, required: LinkSecurity) -> Result<(), ErrorCode> {
+        if self >= required {
+            Ok(())
+        } else if required == LinkSecurity::Authenticated || self == LinkSecurity::Encrypted {
+            // Encrypting the link again won't help, a (better) key is needed
+            Err(ErrorCode::InsufficientAuthentication)
+        } else {
+            Err(ErrorCode::InsufficientEncryption)
+        }
+    }
+}
+
+/// An access to an attribute value that the application is asked to authorize.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Access {
+    /// The client wants to read the value.
+    Read,
+
+    /// The client wants to write the value.
+    Write,
+}
+
+/// The application's decision on whether the client may access an attribute.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Authorization {
+    /// The access is allowed.
+    Granted,
+
+    /// The attribute may never be accessed like this.
+    ///
+    /// The request is rejected with `ReadNotPermitted` or `WriteNotPermitted`.
+    NotPermitted,
+
+    /// The client isn't authorized to access the attribute.
+    ///
+    /// The request is rejected with `InsufficientAuthorization`.
+    Unauthorized,
+
+    /// The application hasn't decided yet.
+    ///
+    /// The request is held back and processed again later.
+    Pending,
+}
+
 /// Trait for attribute sets that can be hosted by an `AttributeServer`.
 pub trait AttributeProvider {
     /// Calls a closure `f` with every attribute whose handle is inside `range`, ascending.
@@ -74,13 +171,80 @@ pub trait AttributeProvider {
     /// Group Type* requests.
     fn is_grouping_attr(&self, uuid: AttUuid) -> bool;
 
-    /// Queries the last attribute that is part of the attribute group denoted by the grouping
-    /// attribute at `handle`.
+    /// Queries the handle of the last attribute that is part of the attribute group denoted by the
+    /// grouping attribute at `handle`.
     ///
     /// If `handle` does not refer to a grouping attribute, returns `None`.
     ///
     /// TODO: Human-readable docs that explain what grouping is
-    fn group_end(&self, handle: Handle) -> Option<&Attribute<'_>>;
+    fn group_end(&self, handle: Handle) -> Option<Handle>;
+
+    /// Writes `value` to the attribute at `handle`.
+    ///
+    /// This is invoked when the client sends a *Write Request* or *Write Command*. If the write is
+    /// rejected, the returned `AttError` is sent back to the client (for requests only; errors
+    /// caused by commands are silently dropped).
+    ///
+    /// The default implementation rejects all writes with `WriteNotPermitted`.
+    fn write_attr(&mut self, handle: Handle, value: &[u8]) -> Result<(), AttError> {
+        let _ = value;
+        Err(AttError::new(ErrorCode::WriteNotPermitted, handle))
+    }
+
+    /// Returns the link security needed to read or write the attribute at `handle`.
+    ///
+    /// This is checked by the `AttributeServer` before reading or writing the attribute value.
+    /// Discovery of services and characteristics is always allowed.
+    ///
+    /// The default implementation makes all attributes accessible over unencrypted links.
+    fn required_security(&self, handle: Handle) -> LinkSecurity {
+        let _ = handle;
+        LinkSecurity::Unencrypted
+    }
+
+    /// Decides whether the connected client may perform `access` on the attribute at `handle`.
+    ///
+    /// This is checked after `required_security` is satisfied. The client is the peer of the
+    /// connection whose `Responder` processes the request.
+    ///
+    /// If the decision can't be made right away (eg. because the user has to confirm it), return
+    /// `Authorization::Pending`. The server then doesn't answer the request and reports the access
+    /// via `AttributeServer::pending_authorization` instead. The L2CAP layer (or the EATT bearer)
+    /// keeps the request and processes it again (calling this method again) every time
+    /// `Responder::process_one` (or `L2CAPStateTx::process_eatt`) is called, until the answer is no longer `Pending`. Requests are
+    /// answered in order, so later messages on the same ATT bearer have to wait, while other L2CAP
+    /// channels are still served. Only one request can wait at a time: A request on another bearer
+    /// that would have to wait too is rejected with `InsufficientResources`. The client gives up on
+    /// the request after 30 seconds.
+    ///
+    /// The default implementation allows all accesses.
+    fn authorize(&self, handle: Handle, access: Access) -> Authorization {
+        let _ = (handle, access);
+        Authorization::Granted
+    }
+
+    /// Called with the responses, notifications and indications sent by the peer's ATT server,
+    /// when this device acts as a client. Also see the [`client`] module.
+    ///
+    /// The default implementation ignores them.
+    ///
+    /// [`client`]: client/index.html
+    fn client_event(&mut self, event: ClientEvent<'_>) {
+        let _ = event;
+    }
+
+    /// Returns the request the client wants to send to the peer's server next, if any.
+    ///
+    /// This is queried whenever no request is outstanding: After every `client_event`, and from
+    /// `AttributeServerTx::send_next_request`. The request is only considered sent once its
+    /// response arrives as a `ClientEvent`, so this must keep returning it until then. This lets
+    /// procedures made up of several requests (like service discovery) run without the
+    /// application having to send each request.
+    ///
+    /// The default implementation never sends requests.
+    fn next_request(&self) -> Option<ClientRequest<'_>> {
+        None
+    }
 }
 
 /// An empty attribute set.
@@ -101,7 +265,28 @@ impl AttributeProvider for NoAttributes {
         false
     }
 
-    fn group_end(&self, _handle: Handle) -> Option<&Attribute<'_>> {
+    fn group_end(&self, _handle: Handle) -> Option<Handle> {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn link_security() {
+        use self::LinkSecurity::*;
+
+        assert!(Encrypted.check(Encrypted).is_ok());
+        assert!(Authenticated.check(Unencrypted).is_ok());
+        match Unencrypted.check(Encrypted) {
+            Err(ErrorCode::InsufficientEncryption) => {}
+            other => panic!("{:?}", other),
+        }
+        match Encrypted.check(Authenticated) {
+            Err(ErrorCode::InsufficientAuthentication) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -46,6 +46,10 @@ enum_with_unknown! {
         UnsupportedGroupType = 0x10,
         /// Server didn't have enough resources to complete a request.
         InsufficientResources = 0x11,
+        /// The server requests the client to rediscover the database.
+        DatabaseOutOfSync = 0x12,
+        /// The attribute value was rejected by the server.
+        ValueNotAllowed = 0x13,
     }
 }
 
@@ -1,27 +1,197 @@
 //! ATT server implementation.
+//!
+//! `AttributeServer` is generic over its `AttributeProvider`, but the request processing itself
+//! isn't: It is implemented on the concrete `ServerState`, which accesses the attributes through
+//! the object-safe `Attributes` trait. Only the thin wrappers in `AttributeServer` are instantiated
+//! for each provider type, so firmware hosting several attribute sets (or building the stack for
+//! several configurations) only contains one copy of the ATT server.
 
 use {
     super::{
+        client::{check_write_len, ClientEvent, ClientRequest, ClientState},
         pdus::{AttPdu, ByGroupAttData, ByTypeAttData, ErrorCode, Opcode},
-        AttError, AttributeProvider, Handle, HandleRange,
+        Access, AttError, AttUuid, Attribute, AttributeProvider, Authorization, Handle,
+        HandleRange, LinkSecurity, DEFAULT_ATT_MTU,
     },
     crate::{
         bytes::{ByteReader, FromBytes, ToBytes},
-        l2cap::{Protocol, ProtocolObj, Sender},
+        l2cap::{Channel, Protocol, ProtocolObj, Sender},
+        security::AuthReq,
+        time::Instant,
         utils::HexSlice,
         Error,
     },
+    core::cmp,
 };
 
 /// An Attribute Protocol server providing read and write access to stored attributes.
 pub struct AttributeServer<A: AttributeProvider> {
     attrs: A,
+    state: ServerState,
+}
+
+/// The part of the `AttributeServer` that doesn't depend on the `AttributeProvider`.
+struct ServerState {
+    /// How the link is currently secured.
+    link_security: LinkSecurity,
+
+    /// *Security Request* to send when an access is denied, if enabled.
+    auto_request: Option<AuthReq>,
+
+    /// Set when an access was denied and `auto_request` should be sent.
+    request_pending: bool,
+
+    /// State of requests sent to the peer's server.
+    client: ClientState,
+
+    /// Set when a client request timed out. No PDUs may be sent or processed afterwards.
+    closed: bool,
+
+    /// Largest `ATT_MTU` we can handle, limited by the L2CAP buffers.
+    max_mtu: u8,
+
+    /// `ATT_MTU` negotiated with the client.
+    mtu: u8,
+
+    /// The access a request is waiting to be authorized for, and the bearer it was received on.
+    ///
+    /// Only one request can wait at a time.
+    deferred: Option<(Handle, Access, Channel)>,
+
+    /// Whether an indication was sent and hasn't been confirmed by the client yet.
+    indication_pending: bool,
 }
 
 impl<A: AttributeProvider> AttributeServer<A> {
     /// Creates an `AttributeServer` hosting attributes from an `AttributeProvider`.
     pub fn new(attrs: A) -> Self {
-        Self { attrs }
+        Self {
+            attrs,
+            state: ServerState {
+                link_security: LinkSecurity::Unencrypted,
+                auto_request: None,
+                request_pending: false,
+                client: ClientState::new(),
+                closed: false,
+                max_mtu: DEFAULT_ATT_MTU,
+                mtu: DEFAULT_ATT_MTU,
+                deferred: None,
+                indication_pending: false,
+            },
+        }
+    }
+
+    /// Informs the server about a change of the link's security.
+    ///
+    /// This must be called when encryption is enabled or the key is refreshed, and with
+    /// `LinkSecurity::Unencrypted` when the connection is closed.
+    pub fn set_link_security(&mut self, security: LinkSecurity) {
+        self.state.link_security = security;
+        if security != LinkSecurity::Unencrypted {
+            self.state.request_pending = false;
+        }
+    }
+
+    /// Returns the link security the server currently assumes.
+    pub fn link_security(&self) -> LinkSecurity {
+        self.state.link_security
+    }
+
+    /// Configures the server to send a *Security Request* with `auth_req` when it rejects an
+    /// access because the link isn't secured enough.
+    ///
+    /// The request is sent by `L2CAPStateTx` right after the error response, so the master can
+    /// start pairing without waiting for its client to react to the error. Pass `None` to disable
+    /// this again (the default).
+    pub fn request_security_on_denial(&mut self, auth_req: Option<AuthReq>) {
+        self.state.auto_request = auth_req;
+    }
+
+    /// Returns whether a client request sent to the peer is still waiting for its response.
+    pub fn client_busy(&self) -> bool {
+        self.state.client.is_busy()
+    }
+
+    /// Forgets the outstanding client request, if any.
+    ///
+    /// This must be called when the connection is closed, since the response will never arrive.
+    /// It also reopens the bearer after a transaction timeout, and resets the `ATT_MTU` to its
+    /// default for the next connection.
+    pub fn cancel_request(&mut self) {
+        self.state.client = ClientState::new();
+        self.state.closed = false;
+        self.state.mtu = DEFAULT_ATT_MTU;
+        self.state.deferred = None;
+        self.state.indication_pending = false;
+    }
+
+    /// Returns the access the last client request is waiting for, if the `AttributeProvider`
+    /// answered it with `Authorization::Pending`.
+    ///
+    /// The application can poll this to find out when to ask for a decision.
+    pub fn pending_authorization(&self) -> Option<(Handle, Access)> {
+        self.state
+            .deferred
+            .map(|(handle, access, _)| (handle, access))
+    }
+
+    /// Returns whether the request waiting for authorization was received on `bearer` (identified
+    /// by the channel responses are sent to).
+    pub(crate) fn is_deferred_on(&self, bearer: Channel) -> bool {
+        self.state
+            .deferred
+            .is_some_and(|(_, _, channel)| channel == bearer)
+    }
+
+    /// Returns the `ATT_MTU` negotiated with the client.
+    ///
+    /// This is `DEFAULT_ATT_MTU` unless the client sent an *Exchange MTU Request* and the L2CAP
+    /// layer was given buffers for larger messages (see `L2CAPState::with_buffers`).
+    pub fn mtu(&self) -> u8 {
+        self.state.mtu
+    }
+
+    /// Returns whether an indication was sent that the client hasn't confirmed yet.
+    ///
+    /// Only one indication can be outstanding at a time.
+    pub fn indication_pending(&self) -> bool {
+        self.state.indication_pending
+    }
+
+    /// Sets the largest `ATT_MTU` offered to the client.
+    pub(crate) fn set_max_mtu(&mut self, mtu: u8) {
+        self.state.max_mtu = mtu;
+    }
+
+    /// Checks whether the outstanding client request has timed out, and closes the bearer if so.
+    ///
+    /// This should be called periodically while connected (eg. once per connection event). The
+    /// 30-second transaction timer of a request starts at the first call after sending it, so the
+    /// time between calls adds to the timeout.
+    ///
+    /// Returns `true` if the bearer is closed. The server then drops all incoming PDUs and sends
+    /// nothing, as required by the spec, and the connection should be terminated.
+    pub fn check_timeout(&mut self, now: Instant) -> bool {
+        if !self.state.closed && self.state.client.check_timeout(now) {
+            warn!("ATT transaction timed out, closing bearer");
+            self.state.closed = true;
+        }
+        self.state.closed
+    }
+
+    /// Returns whether the bearer was closed because of a transaction timeout.
+    pub fn is_closed(&self) -> bool {
+        self.state.closed
+    }
+
+    /// Returns the *Security Request* to send because of a denied access, if any.
+    pub(crate) fn take_security_request(&mut self) -> Option<AuthReq> {
+        if self.state.request_pending {
+            self.state.request_pending = false;
+            self.state.auto_request
+        } else {
+            None
+        }
     }
 
     /// Prepares for performing a server-initiated action (eg. sending a notification/indication).
@@ -36,11 +206,119 @@ impl<A: AttributeProvider> AttributeServer<A> {
             sender,
         }
     }
+}
+
+/// Object-safe counterpart of `AttributeProvider`, used by `ServerState`.
+trait Attributes {
+    fn for_attrs_in_range(
+        &mut self,
+        range: HandleRange,
+        f: &mut dyn FnMut(&dyn Attributes, Attribute<'_>) -> Result<(), Error>,
+    ) -> Result<(), Error>;
+    fn is_grouping_attr(&self, uuid: AttUuid) -> bool;
+    fn group_end(&self, handle: Handle) -> Option<Handle>;
+    fn write_attr(&mut self, handle: Handle, value: &[u8]) -> Result<(), AttError>;
+    fn required_security(&self, handle: Handle) -> LinkSecurity;
+    fn authorize(&self, handle: Handle, access: Access) -> Authorization;
+    fn client_event(&mut self, event: ClientEvent<'_>);
+    fn next_request(&self) -> Option<ClientRequest<'_>>;
+}
+
+impl<A: AttributeProvider> Attributes for A {
+    fn for_attrs_in_range(
+        &mut self,
+        range: HandleRange,
+        f: &mut dyn FnMut(&dyn Attributes, Attribute<'_>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        AttributeProvider::for_attrs_in_range(self, range, |provider, attr| f(provider, attr))
+    }
+
+    fn is_grouping_attr(&self, uuid: AttUuid) -> bool {
+        AttributeProvider::is_grouping_attr(self, uuid)
+    }
+
+    fn group_end(&self, handle: Handle) -> Option<Handle> {
+        AttributeProvider::group_end(self, handle)
+    }
+
+    fn write_attr(&mut self, handle: Handle, value: &[u8]) -> Result<(), AttError> {
+        AttributeProvider::write_attr(self, handle, value)
+    }
+
+    fn required_security(&self, handle: Handle) -> LinkSecurity {
+        AttributeProvider::required_security(self, handle)
+    }
+
+    fn authorize(&self, handle: Handle, access: Access) -> Authorization {
+        AttributeProvider::authorize(self, handle, access)
+    }
+
+    fn client_event(&mut self, event: ClientEvent<'_>) {
+        AttributeProvider::client_event(self, event)
+    }
+
+    fn next_request(&self) -> Option<ClientRequest<'_>> {
+        AttributeProvider::next_request(self)
+    }
+}
+
+impl ServerState {
+    /// Checks whether the attribute at `handle` may be accessed over the current link.
+    fn check_security(
+        &mut self,
+        attrs: &mut dyn Attributes,
+        handle: Handle,
+    ) -> Result<(), AttError> {
+        let required = attrs.required_security(handle);
+        self.link_security.check(required).map_err(|code| {
+            self.request_pending = self.auto_request.is_some();
+            AttError::new(code, handle)
+        })
+    }
+
+    /// Asks the application whether the client may perform `access` on the attribute at `handle`.
+    ///
+    /// Returns `Ok(false)` if the decision is pending, in which case the request must not be
+    /// answered.
+    fn check_authorization(
+        &mut self,
+        attrs: &dyn Attributes,
+        handle: Handle,
+        access: Access,
+        bearer: Channel,
+    ) -> Result<bool, AttError> {
+        match attrs.authorize(handle, access) {
+            Authorization::Granted => Ok(true),
+            Authorization::Pending => self.defer(handle, access, bearer).map(|()| false),
+            answer => Err(AttError::new(denial_code(answer, access), handle)),
+        }
+    }
 
-    /// Returns the `ATT_MTU` value, the maximum size of an ATT PDU that can be processed and sent
-    /// out by the server.
-    fn att_mtu(&self) -> u8 {
-        Self::RSP_PDU_SIZE
+    /// Records that the request received on `bearer` waits for authorization of `access`.
+    ///
+    /// Fails if another request is waiting already, since only one decision can be pending.
+    fn defer(&mut self, handle: Handle, access: Access, bearer: Channel) -> Result<(), AttError> {
+        if self.deferred.is_some() {
+            debug!("another request is waiting for authorization, rejecting");
+            return Err(AttError::new(ErrorCode::InsufficientResources, handle));
+        }
+        self.deferred = Some((handle, access, bearer));
+        Ok(())
+    }
+
+    /// Sends the next request of the `AttributeProvider` if the client is idle.
+    fn send_next_request(
+        &mut self,
+        attrs: &mut dyn Attributes,
+        sender: &mut Sender<'_>,
+    ) -> Result<bool, Error> {
+        if self.client.is_busy() || self.closed {
+            return Ok(false);
+        }
+        match attrs.next_request() {
+            Some(request) => self.client.send(request, sender).map(|()| true),
+            None => Ok(false),
+        }
     }
 
     /// Process an incoming request (or command) PDU and return a response.
@@ -49,14 +327,21 @@ impl<A: AttributeProvider> AttributeServer<A> {
     /// case, this method will send the response (if any).
     fn process_request(
         &mut self,
+        attrs: &mut dyn Attributes,
         msg: &AttPdu<'_>,
         responder: &mut Sender<'_>,
     ) -> Result<(), AttError> {
-        /// Error returned when an ATT error should be sent back.
+        /// Error returned when the response should not be sent.
         ///
         /// Returning this from inside `responder.send_with` will not send the response and
         /// instead bail out of the closure.
-        struct RspError(AttError);
+        enum RspError {
+            /// An ATT error should be sent back instead.
+            Att(AttError),
+
+            /// The request is waiting for authorization and must not be answered yet.
+            Deferred,
+        }
 
         impl From<Error> for RspError {
             fn from(e: Error) -> Self {
@@ -66,17 +351,37 @@ impl<A: AttributeProvider> AttributeServer<A> {
 
         impl From<AttError> for RspError {
             fn from(att: AttError) -> Self {
-                RspError(att)
+                RspError::Att(att)
             }
         }
 
+        if let Some(event) = self.client.process(msg) {
+            attrs.client_event(event);
+            if let ClientEvent::Indication { .. } = event {
+                responder.send(AttPdu::HandleValueConfirmation).unwrap();
+            }
+            if let Err(e) = self.send_next_request(attrs, responder) {
+                // `AttributeServerTx::send_next_request` can retry later
+                debug!("couldn't send next client request: {:?}", e);
+            }
+            return Ok(());
+        }
+
+        // The size of everything we send is limited by the bearer's `ATT_MTU`
+        let att_mtu = responder.pdu_size();
+
         match msg {
-            AttPdu::ExchangeMtuReq { mtu: _mtu } => {
+            AttPdu::ExchangeMtuReq { mtu } => {
                 responder
                     .send(AttPdu::ExchangeMtuRsp {
-                        mtu: u16::from(Self::RSP_PDU_SIZE),
+                        mtu: u16::from(self.max_mtu),
                     })
                     .unwrap();
+
+                // The response is still sent with the old `ATT_MTU`, the new one applies to all
+                // PDUs after it
+                let mtu = cmp::min(*mtu, u16::from(self.max_mtu));
+                self.mtu = cmp::max(mtu, u16::from(DEFAULT_ATT_MTU)) as u8;
                 Ok(())
             }
 
@@ -86,6 +391,8 @@ impl<A: AttributeProvider> AttributeServer<A> {
             } => {
                 let range = handle_range.check()?;
 
+                // Attribute that is waiting for authorization of the read, if it's the first one
+                let mut pending = None;
                 let result = responder.send_with(|writer| {
                     // If no attributes match request, return `AttributeNotFound` error, else send
                     // `ReadByTypeResponse` with at least one entry
@@ -94,16 +401,52 @@ impl<A: AttributeProvider> AttributeServer<A> {
                     let length = writer.split_next_mut().ok_or(Error::Eof)?;
 
                     let mut size = None;
-                    let att_mtu = self.att_mtu();
-                    self.attrs
-                        .for_attrs_in_range(range, |_provider, attr| {
+                    let mut denied = None;
+                    let link_security = self.link_security;
+                    attrs
+                        .for_attrs_in_range(range, &mut |provider, attr| {
                             if attr.att_type == *attribute_type {
+                                let required = provider.required_security(attr.handle);
+                                if let Err(code) = link_security.check(required) {
+                                    // Only report the error if this is the first attribute,
+                                    // otherwise the client reads it in its next request.
+                                    if size.is_none() {
+                                        denied = Some(AttError::new(code, attr.handle));
+                                    }
+                                    return Err(Error::Eof);
+                                }
+
+                                // Like above, the list ends before attributes that aren't
+                                // readable right now
+                                match provider.authorize(attr.handle, Access::Read) {
+                                    Authorization::Granted => {}
+                                    Authorization::Pending => {
+                                        if size.is_none() {
+                                            pending = Some(attr.handle);
+                                        }
+                                        return Err(Error::Eof);
+                                    }
+                                    answer => {
+                                        if size.is_none() {
+                                            let code = denial_code(answer, Access::Read);
+                                            denied = Some(AttError::new(code, attr.handle));
+                                        }
+                                        return Err(Error::Eof);
+                                    }
+                                }
+
                                 let data =
                                     ByTypeAttData::new(att_mtu, attr.handle, attr.value.as_ref());
                                 if size == Some(data.encoded_size()) || size.is_none() {
                                     // Can try to encode `data`. If we run out of space, end the list.
                                     data.to_bytes(writer)?;
                                     size = Some(data.encoded_size());
+                                } else {
+                                    // All entries must have the same size. The client continues
+                                    // after the last returned handle, so end the list here instead
+                                    // of skipping this attribute (eg. an include definition of a
+                                    // service with a 128-bit UUID).
+                                    return Err(Error::Eof);
                                 }
                             }
 
@@ -115,6 +458,10 @@ impl<A: AttributeProvider> AttributeServer<A> {
                         // At least one attr
                         *length = size;
                         Ok(())
+                    } else if pending.is_some() {
+                        Err(RspError::Deferred)
+                    } else if let Some(denied) = denied {
+                        Err(denied.into())
                     } else {
                         Err(AttError::attribute_not_found().into())
                     }
@@ -122,7 +469,16 @@ impl<A: AttributeProvider> AttributeServer<A> {
 
                 match result {
                     Ok(()) => Ok(()),
-                    Err(RspError(e)) => Err(e),
+                    Err(RspError::Att(e)) => {
+                        if is_security_error(e.error_code()) {
+                            self.request_pending = self.auto_request.is_some();
+                        }
+                        Err(e)
+                    }
+                    Err(RspError::Deferred) => match pending {
+                        Some(handle) => self.defer(handle, Access::Read, responder.channel()),
+                        None => Ok(()),
+                    },
                 }
             }
 
@@ -133,7 +489,7 @@ impl<A: AttributeProvider> AttributeServer<A> {
                 let range = handle_range.check()?;
 
                 // Reject if `group_type` is not a grouping attribute
-                if !self.attrs.is_grouping_attr(*group_type) {
+                if !attrs.is_grouping_attr(*group_type) {
                     return Err(AttError::new(
                         ErrorCode::UnsupportedGroupType,
                         range.start(),
@@ -148,14 +504,13 @@ impl<A: AttributeProvider> AttributeServer<A> {
                     let length = writer.split_next_mut().ok_or(Error::Eof)?;
 
                     let mut size = None;
-                    let att_mtu = self.att_mtu();
-                    self.attrs
-                        .for_attrs_in_range(range, |provider, attr| {
+                    attrs
+                        .for_attrs_in_range(range, &mut |provider, attr| {
                             if attr.att_type == *group_type {
                                 let data = ByGroupAttData::new(
                                     att_mtu,
                                     attr.handle,
-                                    provider.group_end(attr.handle).unwrap().handle,
+                                    provider.group_end(attr.handle).unwrap(),
                                     attr.value.as_ref(),
                                 );
                                 if size == Some(data.encoded_size()) || size.is_none() {
@@ -183,20 +538,73 @@ impl<A: AttributeProvider> AttributeServer<A> {
                     }
                 });
 
+                // Group declarations are readable without authorization, so this is never deferred
+                match result {
+                    Ok(()) => Ok(()),
+                    Err(RspError::Att(e)) => Err(e),
+                    Err(RspError::Deferred) => Ok(()),
+                }
+            }
+
+            AttPdu::FindInformationReq { handle_range } => {
+                let range = handle_range.check()?;
+                let start = range.start();
+
+                let result = responder.send_with(|writer| {
+                    writer.write_u8(Opcode::FindInformationRsp.into())?;
+                    let format = writer.split_next_mut().ok_or(Error::Eof)?;
+
+                    let mut uuid_len = None;
+                    let listed = attrs.for_attrs_in_range(range, &mut |_provider, attr| {
+                        // Types are reported in their shortest form. All entries must have the
+                        // same format, so the list ends before the first one that differs.
+                        let uuid = attr.att_type.shortest();
+                        let len = uuid.encoded_len();
+                        if uuid_len.is_some_and(|l| l != len) || writer.space_left() < 2 + len {
+                            return Err(Error::Eof);
+                        }
+                        attr.handle.to_bytes(writer)?;
+                        uuid.to_bytes(writer)?;
+                        uuid_len = Some(len);
+                        Ok(())
+                    });
+
+                    match listed {
+                        // `Eof` ends the list once the response is full or the format changes
+                        Ok(()) | Err(Error::Eof) => {}
+                        Err(e) => return Err(e.into()),
+                    }
+
+                    match uuid_len {
+                        Some(len) => {
+                            *format = if len == 2 { 0x01 } else { 0x02 };
+                            Ok(())
+                        }
+                        None => Err(AttError::new(ErrorCode::AttributeNotFound, start).into()),
+                    }
+                });
+
+                // Attribute types are always discoverable, so this is never deferred
                 match result {
                     Ok(()) => Ok(()),
-                    Err(RspError(e)) => Err(e),
+                    Err(RspError::Att(e)) => Err(e),
+                    Err(RspError::Deferred) => Ok(()),
                 }
             }
 
             AttPdu::ReadReq { handle } => {
+                self.check_security(attrs, *handle)?;
+                if !self.check_authorization(attrs, *handle, Access::Read, responder.channel())? {
+                    return Ok(());
+                }
+
                 responder
                     .send_with(|writer| -> Result<(), Error> {
                         writer.write_u8(Opcode::ReadRsp.into())?;
 
-                        self.attrs.for_attrs_in_range(
+                        attrs.for_attrs_in_range(
                             HandleRange::new(*handle, *handle),
-                            |_provider, attr| {
+                            &mut |_provider, attr| {
                                 let value = if writer.space_left() < attr.value.as_ref().len() {
                                     &attr.value.as_ref()[..writer.space_left()]
                                 } else {
@@ -213,10 +621,12 @@ impl<A: AttributeProvider> AttributeServer<A> {
                 Ok(())
             }
 
-            AttPdu::WriteReq { .. } => {
-                // FIXME: ATT Writes are not yet supported, but we pretend they work so that some
-                // applications that only need CCCD writes work (eg. BLE MIDI).
-                warn!("NYI: ATT Write Req");
+            AttPdu::WriteReq { handle, value } => {
+                self.check_security(attrs, *handle)?;
+                if !self.check_authorization(attrs, *handle, Access::Write, responder.channel())? {
+                    return Ok(());
+                }
+                attrs.write_attr(*handle, value.as_ref())?;
 
                 responder
                     .send_with(|writer| -> Result<(), Error> {
@@ -227,7 +637,26 @@ impl<A: AttributeProvider> AttributeServer<A> {
                 Ok(())
             }
 
-            // Responses are always invalid here
+            AttPdu::WriteCommand { handle, value } => {
+                // Commands don't get a response, so errors are dropped
+                let result = self.check_security(attrs, *handle).and_then(|()| {
+                    self.check_authorization(attrs, *handle, Access::Write, responder.channel())
+                });
+                let result = match result {
+                    Ok(true) => attrs.write_attr(*handle, value.as_ref()),
+                    // Retried later
+                    Ok(false) => Ok(()),
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = result {
+                    debug!("ignoring failed write command: {:?}", e);
+                }
+                Ok(())
+            }
+
+            // Responses to requests we didn't send (or that were already answered) violate the
+            // protocol, but answering them with an error would as well. Drop them without touching
+            // the outstanding request.
             AttPdu::ErrorRsp { .. }
             | AttPdu::ExchangeMtuRsp { .. }
             | AttPdu::FindInformationRsp { .. }
@@ -242,20 +671,26 @@ impl<A: AttributeProvider> AttributeServer<A> {
             | AttPdu::ExecuteWriteRsp { .. }
             | AttPdu::HandleValueNotification { .. }
             | AttPdu::HandleValueIndication { .. } => {
-                Err(AttError::new(ErrorCode::InvalidPdu, Handle::NULL))
+                debug!("dropping unexpected {:?}", msg.opcode());
+                Ok(())
+            }
+
+            AttPdu::HandleValueConfirmation => {
+                if !self.indication_pending {
+                    debug!("dropping unexpected confirmation");
+                }
+                self.indication_pending = false;
+                Ok(())
             }
 
             // Unknown (undecoded) or unimplemented requests and commands
             AttPdu::Unknown { .. }
-            | AttPdu::FindInformationReq { .. }
             | AttPdu::FindByTypeValueReq { .. }
             | AttPdu::ReadBlobReq { .. }
             | AttPdu::ReadMultipleReq { .. }
-            | AttPdu::WriteCommand { .. }
             | AttPdu::SignedWriteCommand { .. }
             | AttPdu::PrepareWriteReq { .. }
-            | AttPdu::ExecuteWriteReq { .. }
-            | AttPdu::HandleValueConfirmation { .. } => {
+            | AttPdu::ExecuteWriteReq { .. } => {
                 if msg.opcode().is_command() {
                     // According to the spec, unknown Command PDUs should be ignored
                     Ok(())
@@ -266,15 +701,32 @@ impl<A: AttributeProvider> AttributeServer<A> {
             }
         }
     }
-}
 
-impl<A: AttributeProvider> ProtocolObj for AttributeServer<A> {
-    fn process_message(&mut self, message: &[u8], mut responder: Sender<'_>) -> Result<(), Error> {
+    /// Decodes and processes a PDU, answering rejected requests with an *Error Response*.
+    fn process_message(
+        &mut self,
+        attrs: &mut dyn Attributes,
+        message: &[u8],
+        mut responder: Sender<'_>,
+    ) -> Result<(), Error> {
         let pdu = &AttPdu::from_bytes(&mut ByteReader::new(message))?;
         let opcode = pdu.opcode();
         debug!("ATT<- {:?}", pdu);
 
-        match self.process_request(pdu, &mut responder) {
+        if self.closed {
+            debug!("ATT bearer closed, dropping PDU");
+            return Ok(());
+        }
+
+        // Bearers pass on one request at a time, so a PDU received on the bearer of the deferred
+        // request is that request again. It's set again if it still can't be answered.
+        if self
+            .deferred
+            .is_some_and(|(_, _, bearer)| bearer == responder.channel())
+        {
+            self.deferred = None;
+        }
+        match self.process_request(attrs, pdu, &mut responder) {
             Ok(()) => Ok(()),
             Err(att_error) => {
                 debug!("ATT-> {:?}", att_error);
@@ -289,9 +741,33 @@ impl<A: AttributeProvider> ProtocolObj for AttributeServer<A> {
     }
 }
 
+/// Returns the error code to reject an `access` with, which the application didn't authorize.
+fn denial_code(answer: Authorization, access: Access) -> ErrorCode {
+    match (answer, access) {
+        (Authorization::Unauthorized, _) => ErrorCode::InsufficientAuthorization,
+        (_, Access::Read) => ErrorCode::ReadNotPermitted,
+        (_, Access::Write) => ErrorCode::WriteNotPermitted,
+    }
+}
+
+/// Returns whether `code` rejects an access because of insufficient link security.
+fn is_security_error(code: ErrorCode) -> bool {
+    matches!(
+        code,
+        ErrorCode::InsufficientEncryption | ErrorCode::InsufficientAuthentication
+    )
+}
+
+impl<A: AttributeProvider> ProtocolObj for AttributeServer<A> {
+    fn process_message(&mut self, message: &[u8], responder: Sender<'_>) -> Result<(), Error> {
+        self.state
+            .process_message(&mut self.attrs, message, responder)
+    }
+}
+
 impl<A: AttributeProvider> Protocol for AttributeServer<A> {
     // FIXME: Would it be useful to have this as a runtime parameter instead?
-    const RSP_PDU_SIZE: u8 = 23;
+    const RSP_PDU_SIZE: u8 = DEFAULT_ATT_MTU;
 }
 
 /// An ATT server handle that can send packets and initiate actions.
@@ -299,7 +775,6 @@ impl<A: AttributeProvider> Protocol for AttributeServer<A> {
 /// This type is needed for any server-initiated procedure, where the server sends out a packet on
 /// its own instead of reacting to a client packet.
 pub struct AttributeServerTx<'a, A: AttributeProvider> {
-    #[allow(unused)]
     server: &'a mut AttributeServer<A>,
 
     sender: Sender<'a>,
@@ -312,8 +787,14 @@ impl<'a, A: AttributeProvider> AttributeServerTx<'a, A> {
     ///
     /// If `value` is too large to be transmitted in a single `ATT_MTU`, it will be truncated to
     /// fit. A client may fetch the rest of the truncated value by using a *Read Blob Request*.
-    /// If this is unwanted, only notify with a `value` of 19 Bytes or less.
+    /// If this is unwanted, only notify with a `value` of at most `AttributeServer::mtu() - 3`
+    /// Bytes (19 Bytes with the default `ATT_MTU`).
     pub fn notify_raw(mut self, handle: Handle, value: &[u8]) {
+        if self.server.state.closed {
+            debug!("ATT bearer closed, dropping notification");
+            return;
+        }
+
         // This cannot fail. The `self` guarantees that there's `RSP_PDU_SIZE` bytes free in
         // `sender`, and is consumed by this method. `AttPdu`s encoder will truncate `value` to fit
         // and doesn't error.
@@ -324,4 +805,78 @@ impl<'a, A: AttributeProvider> AttributeServerTx<'a, A> {
             })
             .unwrap()
     }
+
+    /// Sends an attribute value indication to the connected client.
+    ///
+    /// The client has to confirm the indication, and no other indication may be sent before that
+    /// (see `AttributeServer::indication_pending`). Returns `Error::InvalidValue` if an indication
+    /// is still outstanding or the bearer is closed. `value` is truncated like in `notify_raw`.
+    pub fn indicate_raw(mut self, handle: Handle, value: &[u8]) -> Result<(), Error> {
+        if self.server.state.closed || self.server.state.indication_pending {
+            return Err(Error::InvalidValue);
+        }
+
+        self.sender.send(AttPdu::HandleValueIndication {
+            handle,
+            value: HexSlice(value),
+        })?;
+        self.server.state.indication_pending = true;
+        Ok(())
+    }
+
+    /// Sends a client request to the peer's server.
+    ///
+    /// The response is reported via `AttributeProvider::client_event`. Returns
+    /// `Error::InvalidValue` if another request is still outstanding or the bearer is closed.
+    pub fn request(mut self, request: ClientRequest<'_>) -> Result<(), Error> {
+        if self.server.state.closed {
+            return Err(Error::InvalidValue);
+        }
+        self.server.state.client.send(request, &mut self.sender)
+    }
+
+    /// Sends the request returned by `AttributeProvider::next_request`, if there is one and no
+    /// other request is outstanding.
+    ///
+    /// Call this to start a procedure driven by the `AttributeProvider`, or to retry sending a
+    /// request that didn't fit into the TX queue. Returns whether a request was sent.
+    pub fn send_next_request(mut self) -> Result<bool, Error> {
+        let server = &mut *self.server;
+        server
+            .state
+            .send_next_request(&mut server.attrs, &mut self.sender)
+    }
+
+    /// Sends a *Read Request* for the attribute at `handle` on the peer's server.
+    ///
+    /// The value is reported as `ClientEvent::Read`. Returns `Error::InvalidValue` if another
+    /// request is still outstanding.
+    pub fn read(self, handle: Handle) -> Result<(), Error> {
+        self.request(ClientRequest::Read { handle })
+    }
+
+    /// Sends a *Write Request* writing `value` to the attribute at `handle` on the peer's server.
+    ///
+    /// Completion is reported as `ClientEvent::Written`. Returns `Error::InvalidValue` if another
+    /// request is still outstanding, and `Error::InvalidLength` if `value` doesn't fit into a
+    /// single PDU.
+    pub fn write(self, handle: Handle, value: &[u8]) -> Result<(), Error> {
+        self.request(ClientRequest::Write { handle, value })
+    }
+
+    /// Sends a *Write Command* writing `value` to the attribute at `handle` on the peer's server.
+    ///
+    /// Commands are not acknowledged, so this can be used while a request is outstanding.
+    /// Returns `Error::InvalidLength` if `value` doesn't fit into a single PDU, and
+    /// `Error::InvalidValue` if the bearer is closed.
+    pub fn write_command(mut self, handle: Handle, value: &[u8]) -> Result<(), Error> {
+        if self.server.state.closed {
+            return Err(Error::InvalidValue);
+        }
+        check_write_len(value)?;
+        self.sender.send(AttPdu::WriteCommand {
+            handle,
+            value: HexSlice(value),
+        })
+    }
 }
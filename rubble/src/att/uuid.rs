@@ -6,12 +6,36 @@ use {
 /// ATT protocol UUID (either a 16 or a 128-bit UUID).
 ///
 /// 32-bit UUIDs are not supported by ATT are must be converted to 128-bit UUIDs.
+///
+/// Any UUID type can be converted into an `AttUuid` via `From`, including `DynUuid`. Comparisons
+/// take aliases into account, so a 16-bit UUID is equal to its 128-bit form.
 #[derive(Copy, Clone, Eq)]
 pub enum AttUuid {
     Uuid16(Uuid16),
     Uuid128(Uuid),
 }
 
+impl AttUuid {
+    /// Returns the shortest form of this UUID that can be used in ATT PDUs.
+    ///
+    /// 128-bit UUIDs that are aliases of 16-bit UUIDs are turned into the 16-bit UUID. This is
+    /// used when the server reports attribute types, since clients may compare them bytewise.
+    pub fn shortest(&self) -> Self {
+        match DynUuid::from(*self).shortest() {
+            DynUuid::Uuid16(uuid) => AttUuid::Uuid16(uuid),
+            _ => *self,
+        }
+    }
+
+    /// Returns the number of Bytes `ToBytes` will write (2 or 16).
+    pub fn encoded_len(&self) -> usize {
+        match self {
+            AttUuid::Uuid16(_) => 2,
+            AttUuid::Uuid128(_) => 16,
+        }
+    }
+}
+
 impl FromBytes<'_> for AttUuid {
     fn from_bytes(bytes: &mut ByteReader<'_>) -> Result<Self, Error> {
         Ok(match bytes.bytes_left() {
@@ -76,9 +100,28 @@ impl From<Uuid> for AttUuid {
     }
 }
 
-impl Into<Uuid> for AttUuid {
-    fn into(self) -> Uuid {
-        match self {
+impl From<DynUuid> for AttUuid {
+    fn from(uu: DynUuid) -> Self {
+        match uu {
+            DynUuid::Uuid16(uu) => AttUuid::Uuid16(uu),
+            DynUuid::Uuid32(uu) => uu.into(),
+            DynUuid::Uuid128(uu) => AttUuid::Uuid128(uu),
+        }
+    }
+}
+
+impl From<AttUuid> for DynUuid {
+    fn from(uu: AttUuid) -> Self {
+        match uu {
+            AttUuid::Uuid16(uu) => DynUuid::Uuid16(uu),
+            AttUuid::Uuid128(uu) => DynUuid::Uuid128(uu),
+        }
+    }
+}
+
+impl From<AttUuid> for Uuid {
+    fn from(uuid: AttUuid) -> Self {
+        match uuid {
             AttUuid::Uuid16(u) => u.into(),
             AttUuid::Uuid128(u) => u,
         }
@@ -93,3 +136,29 @@ impl fmt::Debug for AttUuid {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions() {
+        let battery: AttUuid = DynUuid::from(Uuid16(0x180F)).into();
+        let uuid128: Uuid = Uuid16(0x180F).into();
+        let battery128 = AttUuid::from(uuid128);
+        assert_eq!(battery, battery128);
+        assert_eq!(battery128.encoded_len(), 16);
+        assert_eq!(battery128.shortest().encoded_len(), 2);
+
+        // 32-bit aliases have to use the 128-bit form
+        let uuid32 = AttUuid::from(DynUuid::from(Uuid32(0x1234_5678)));
+        assert_eq!(uuid32.shortest().encoded_len(), 16);
+
+        let custom = AttUuid::from(Uuid::from_bytes([
+            0x39, 0x48, 0x7f, 0x3c, 0x0e, 0x9c, 0x4d, 0x7e, 0xa6, 0x1b, 0x11, 0x8e, 0x2e, 0x79,
+            0x6a, 0x5c,
+        ]));
+        assert_ne!(custom, battery);
+        assert_eq!(custom.shortest(), custom);
+    }
+}
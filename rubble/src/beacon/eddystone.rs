@@ -0,0 +1,325 @@
+//! Eddystone frames with ephemeral identifiers.
+//!
+//! [Eddystone] is Google's beacon format, sent as service data of the 16-bit UUID `0xFEAA`. This
+//! module implements the frames needed for beacons that shouldn't be trackable or spoofable:
+//!
+//! * *Eddystone-EID* frames carry an ephemeral identifier that changes every `2^K` seconds. It is
+//!   derived from a 128-bit *identity key* that is shared with a resolver service during
+//!   registration, and from the beacon's clock (seconds since registration).
+//! * *Eddystone-TLM* frames carry telemetry (battery voltage, temperature, uptime). EID beacons
+//!   should send them encrypted (*eTLM*), since the plain uptime would allow linking identifiers.
+//!
+//! All cryptography is built on the platform's [`Aes128`] implementation.
+//!
+//! [Eddystone]: https://github.com/google/eddystone
+//! [`Aes128`]: ../../link/privacy/trait.Aes128.html
+
+use {
+    crate::{
+        link::{
+            ad_structure::{AdStructure, ServiceUuids},
+            privacy::Aes128,
+        },
+        uuid::Uuid16,
+    },
+    core::iter,
+};
+
+/// The 16-bit UUID used by all Eddystone frames.
+pub const EDDYSTONE_UUID: Uuid16 = Uuid16(0xFEAA);
+
+const EDDYSTONE_UUIDS: &[Uuid16] = &[EDDYSTONE_UUID];
+
+const FRAME_TLM: u8 = 0x20;
+const FRAME_EID: u8 = 0x30;
+
+const TLM_VERSION_PLAIN: u8 = 0x00;
+const TLM_VERSION_ENCRYPTED: u8 = 0x01;
+
+/// Largest rotation exponent allowed by the spec (rotating every 9.1 hours).
+pub const MAX_ROTATION_EXPONENT: u8 = 15;
+
+/// An encoded Eddystone frame.
+#[derive(Debug, Copy, Clone)]
+pub struct Frame {
+    buf: [u8; 18],
+    len: u8,
+}
+
+impl Frame {
+    fn new(data: &[u8]) -> Self {
+        let mut buf = [0; 18];
+        buf[..data.len()].copy_from_slice(data);
+        Self {
+            buf,
+            len: data.len() as u8,
+        }
+    }
+
+    /// Returns the frame as transmitted in the service data.
+    pub fn data(&self) -> &[u8] {
+        &self.buf[..usize::from(self.len)]
+    }
+
+    /// Returns the AD structures to broadcast (the Eddystone UUID and the frame).
+    pub fn ad_structures(&self) -> [AdStructure<'_>; 2] {
+        [
+            AdStructure::ServiceUuids16(ServiceUuids::from_uuids(true, EDDYSTONE_UUIDS)),
+            AdStructure::ServiceData16 {
+                uuid: EDDYSTONE_UUID.0,
+                data: self.data(),
+            },
+        ]
+    }
+}
+
+/// Telemetry sent in TLM frames.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Tlm {
+    /// Battery voltage in mV, or 0 if unknown.
+    pub battery_mv: u16,
+    /// Temperature in °C as 8.8 fixed point number, or `-0x8000` if unknown.
+    pub temperature: i16,
+    /// Number of advertising PDUs sent since boot.
+    pub adv_count: u32,
+    /// Time since boot in units of 0.1 seconds.
+    pub uptime: u32,
+}
+
+impl Tlm {
+    /// Returns the 12-Byte TLM payload (without frame type and version).
+    fn to_array(self) -> [u8; 12] {
+        let mut buf = [0; 12];
+        buf[0..2].copy_from_slice(&self.battery_mv.to_be_bytes());
+        buf[2..4].copy_from_slice(&self.temperature.to_be_bytes());
+        buf[4..8].copy_from_slice(&self.adv_count.to_be_bytes());
+        buf[8..12].copy_from_slice(&self.uptime.to_be_bytes());
+        buf
+    }
+
+    /// Encodes an unencrypted TLM frame.
+    ///
+    /// EID beacons should use `EidBeacon::etlm_frame` instead.
+    pub fn frame(&self) -> Frame {
+        let mut data = [0; 14];
+        data[0] = FRAME_TLM;
+        data[1] = TLM_VERSION_PLAIN;
+        data[2..].copy_from_slice(&self.to_array());
+        Frame::new(&data)
+    }
+}
+
+/// Generates EID and eTLM frames.
+#[derive(Debug, Copy, Clone)]
+pub struct EidBeacon {
+    identity_key: [u8; 16],
+    exponent: u8,
+}
+
+impl EidBeacon {
+    /// Creates an EID generator from the identity key established during registration.
+    ///
+    /// The identifier will change every `2^rotation_exponent` seconds. `rotation_exponent` is
+    /// clamped to `MAX_ROTATION_EXPONENT`.
+    pub fn new(identity_key: [u8; 16], rotation_exponent: u8) -> Self {
+        Self {
+            identity_key,
+            exponent: rotation_exponent.min(MAX_ROTATION_EXPONENT),
+        }
+    }
+
+    /// Returns the time in seconds between identifier changes.
+    pub fn rotation_period(&self) -> u32 {
+        1 << self.exponent
+    }
+
+    /// Computes the 8-Byte ephemeral identifier at `time`.
+    ///
+    /// `time` is the beacon clock, counting seconds since registration.
+    pub fn eid(&self, aes: &mut impl Aes128, time: u32) -> [u8; 8] {
+        // The temporary key only changes every ~18 hours, but it's cheap enough to derive it on
+        // every rotation.
+        let mut key = [0; 16];
+        key[11] = 0xFF;
+        key[14..].copy_from_slice(&((time >> 16) as u16).to_be_bytes());
+        aes.encrypt_block(&self.identity_key, &mut key);
+
+        let mut block = [0; 16];
+        block[11] = self.exponent;
+        block[12..].copy_from_slice(&self.quantize(time).to_be_bytes());
+        aes.encrypt_block(&key, &mut block);
+
+        let mut eid = [0; 8];
+        eid.copy_from_slice(&block[..8]);
+        eid
+    }
+
+    /// Encodes the EID frame for `time`.
+    ///
+    /// `tx_power` is the calibrated TX power at 0 meters in dBm.
+    pub fn eid_frame(&self, aes: &mut impl Aes128, time: u32, tx_power: i8) -> Frame {
+        let mut data = [0; 11];
+        data[0] = FRAME_EID;
+        data[1] = tx_power as u8;
+        data[2] = self.exponent;
+        data[3..].copy_from_slice(&self.eid(aes, time));
+        Frame::new(&data)
+    }
+
+    /// Encodes an encrypted TLM frame for `time`.
+    ///
+    /// The telemetry is encrypted with AES-EAX using the identity key. The nonce consists of the
+    /// time quantized to the rotation period (as used for the current EID) and `salt`, which
+    /// should be a random value chosen for every frame.
+    pub fn etlm_frame(&self, aes: &mut impl Aes128, time: u32, tlm: &Tlm, salt: u16) -> Frame {
+        let mut nonce = [0; 6];
+        nonce[..4].copy_from_slice(&self.quantize(time).to_be_bytes());
+        nonce[4..].copy_from_slice(&salt.to_be_bytes());
+
+        let mut data = [0; 18];
+        data[0] = FRAME_TLM;
+        data[1] = TLM_VERSION_ENCRYPTED;
+        data[2..14].copy_from_slice(&tlm.to_array());
+        let tag = eax_encrypt(aes, &self.identity_key, &nonce, &mut data[2..14]);
+        data[14..16].copy_from_slice(&nonce[4..]);
+        data[16..].copy_from_slice(&tag[..2]);
+        Frame::new(&data)
+    }
+
+    fn quantize(&self, time: u32) -> u32 {
+        time & !(self.rotation_period() - 1)
+    }
+}
+
+/// Encrypts `data` in place using AES-EAX with an empty header, and returns the tag.
+fn eax_encrypt(aes: &mut impl Aes128, key: &[u8; 16], nonce: &[u8], data: &mut [u8]) -> [u8; 16] {
+    let n = omac(aes, key, 0, nonce);
+    let h = omac(aes, key, 1, &[]);
+
+    let mut counter = n;
+    for chunk in data.chunks_mut(16) {
+        let mut keystream = counter;
+        aes.encrypt_block(key, &mut keystream);
+        for (b, k) in chunk.iter_mut().zip(&keystream) {
+            *b ^= k;
+        }
+        increment(&mut counter);
+    }
+
+    let c = omac(aes, key, 2, data);
+    let mut tag = n;
+    for ((t, h), c) in tag.iter_mut().zip(&h).zip(&c) {
+        *t ^= h ^ c;
+    }
+    tag
+}
+
+/// Computes `OMAC^t(data)`, which is the AES-CMAC of the block `t` followed by `data`.
+fn omac(aes: &mut impl Aes128, key: &[u8; 16], t: u8, data: &[u8]) -> [u8; 16] {
+    let mut k1 = [0; 16];
+    aes.encrypt_block(key, &mut k1);
+    double(&mut k1);
+    let mut k2 = k1;
+    double(&mut k2);
+
+    let mut prefix = [0; 16];
+    prefix[15] = t;
+    let last = data.chunks(16).count();
+    let mut mac = [0; 16];
+    for (i, block) in iter::once(&prefix[..]).chain(data.chunks(16)).enumerate() {
+        for (m, b) in mac.iter_mut().zip(block) {
+            *m ^= b;
+        }
+        if i == last {
+            let subkey = if block.len() == 16 {
+                &k1
+            } else {
+                mac[block.len()] ^= 0x80;
+                &k2
+            };
+            for (m, k) in mac.iter_mut().zip(subkey) {
+                *m ^= k;
+            }
+        }
+        aes.encrypt_block(key, &mut mac);
+    }
+    mac
+}
+
+/// Multiplies `block` by `x` in GF(2^128), as needed for CMAC subkeys.
+fn double(block: &mut [u8; 16]) {
+    let msb = block[0] >> 7;
+    for i in 0..15 {
+        block[i] = (block[i] << 1) | (block[i + 1] >> 7);
+    }
+    block[15] = (block[15] << 1) ^ (0x87 * msb);
+}
+
+/// Increments a big-endian 128-bit counter.
+fn increment(block: &mut [u8; 16]) {
+    for b in block.iter_mut().rev() {
+        *b = b.wrapping_add(1);
+        if *b != 0 {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Not AES, but deterministic and key-dependent, and every output Byte depends on every input
+    /// Byte.
+    struct MixCipher;
+
+    impl Aes128 for MixCipher {
+        fn encrypt_block(&mut self, key: &[u8; 16], block: &mut [u8; 16]) {
+            // The second pass makes the first Bytes depend on the last ones
+            let mut acc = 0u8;
+            for _ in 0..2 {
+                for (i, (b, k)) in block.iter_mut().zip(key).enumerate() {
+                    acc = acc.rotate_left(3) ^ *b ^ k;
+                    *b = acc.wrapping_mul(0x1D).wrapping_add(i as u8);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn frames() {
+        let beacon = EidBeacon::new([0x5A; 16], 10);
+        let eid = beacon.eid(&mut MixCipher, 5000);
+        assert_eq!(beacon.eid(&mut MixCipher, 5000 + 1023 - 5000 % 1024), eid);
+        assert_ne!(beacon.eid(&mut MixCipher, 5000 + 1024), eid);
+
+        let frame = beacon.eid_frame(&mut MixCipher, 5000, -20);
+        assert_eq!(frame.data()[..3], [0x30, 0xEC, 10]);
+        assert_eq!(frame.data()[3..], eid);
+
+        let tlm = Tlm {
+            battery_mv: 3000,
+            temperature: 0x1780,
+            adv_count: 1,
+            uptime: 2,
+        };
+        assert_eq!(
+            tlm.frame().data(),
+            [0x20, 0, 0x0B, 0xB8, 0x17, 0x80, 0, 0, 0, 1, 0, 0, 0, 2]
+        );
+
+        let etlm = beacon.etlm_frame(&mut MixCipher, 5000, &tlm, 0xBEEF);
+        assert_eq!(etlm.data().len(), 18);
+        assert_eq!(etlm.data()[..2], [0x20, 0x01]);
+        assert_eq!(etlm.data()[14..16], [0xBE, 0xEF]);
+
+        // CTR mode is its own inverse
+        let mut nonce = [0; 6];
+        nonce[..4].copy_from_slice(&4096u32.to_be_bytes());
+        nonce[4..].copy_from_slice(&[0xBE, 0xEF]);
+        let mut plain = [0; 12];
+        plain.copy_from_slice(&etlm.data()[2..14]);
+        eax_encrypt(&mut MixCipher, &[0x5A; 16], &nonce, &mut plain);
+        assert_eq!(plain, tlm.to_array());
+    }
+}
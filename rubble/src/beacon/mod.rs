@@ -1,14 +1,16 @@
 //! BLE beacon support, without dealing with Link-Layer stuff.
 
+pub mod eddystone;
+
 use {
     super::{
         link::{
             ad_structure::AdStructure,
             advertising::{Header, Pdu, PduBuf},
             filter::{self, AddressFilter, ScanFilter},
-            Cmd, DeviceAddress, NextUpdate, RadioCmd, Transmitter,
+            Cmd, CompanyId, DeviceAddress, NextUpdate, RadioCmd, Transmitter,
         },
-        phy::AdvertisingChannel,
+        phy::{AdvChannelMap, AdvertisingChannel},
     },
     crate::{
         bytes::*,
@@ -45,6 +47,11 @@ impl Beacon {
     ///
     /// This will broadcast once on every advertising channel.
     pub fn broadcast<T: Transmitter>(&self, tx: &mut T) {
+        self.broadcast_on(tx, AdvChannelMap::ALL);
+    }
+
+    /// Broadcasts the beacon data once on every channel in `channels`.
+    pub fn broadcast_on<T: Transmitter>(&self, tx: &mut T, channels: AdvChannelMap) {
         // The spec says that we have to broadcast on all 3 channels in sequence, so that the total
         // time of this broadcast ("advertising event") is <10ms.
 
@@ -55,12 +62,101 @@ impl Beacon {
         let buf = tx.tx_payload_buf();
         buf[..payload.len()].copy_from_slice(payload);
 
-        for channel in AdvertisingChannel::iter_all() {
+        for channel in channels.iter() {
             tx.transmit_advertising(self.pdu.header(), channel);
         }
     }
 }
 
+/// An AltBeacon advertisement.
+///
+/// [AltBeacon] is an open beacon format sent as *Manufacturer Specific Data*. Send it by passing
+/// the result of `encode` to `Beacon::new`, and recognize received ones with `from_ad_structure`.
+///
+/// [AltBeacon]: https://github.com/AltBeacon/spec
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AltBeacon {
+    /// Company ID of the beacon manufacturer.
+    pub manufacturer: CompanyId,
+    /// Identifies the beacon.
+    ///
+    /// Usually a 16-Byte organizational unit UUID followed by 4 Bytes identifying the beacon within
+    /// the organization.
+    pub beacon_id: [u8; 20],
+    /// Average RSSI (in dBm) measured at a distance of 1 meter from the beacon.
+    pub reference_rssi: i8,
+    /// Reserved for use by the manufacturer.
+    pub reserved: u8,
+}
+
+impl AltBeacon {
+    /// Length of the encoded *Manufacturer Specific Data* (including the company ID).
+    pub const DATA_LEN: usize = 26;
+
+    const BEACON_CODE: [u8; 2] = [0xBE, 0xAC];
+
+    /// AD type of *Manufacturer Specific Data*.
+    const MANUFACTURER_SPECIFIC_DATA: u8 = 0xFF;
+
+    /// Creates an AltBeacon with a reserved Byte of 0.
+    pub fn new(manufacturer: CompanyId, beacon_id: [u8; 20], reference_rssi: i8) -> Self {
+        Self {
+            manufacturer,
+            beacon_id,
+            reference_rssi,
+            reserved: 0,
+        }
+    }
+
+    /// Encodes the beacon into `buf` and returns the resulting AD structure.
+    pub fn encode<'a>(&self, buf: &'a mut [u8; Self::DATA_LEN]) -> AdStructure<'a> {
+        self.to_bytes(&mut ByteWriter::new(buf)).unwrap();
+        AdStructure::Unknown {
+            ty: Self::MANUFACTURER_SPECIFIC_DATA,
+            data: buf,
+        }
+    }
+
+    /// Decodes an AltBeacon from a received AD structure.
+    ///
+    /// Returns `None` if `ad` is not an AltBeacon.
+    pub fn from_ad_structure(ad: &AdStructure<'_>) -> Option<Self> {
+        match ad {
+            AdStructure::Unknown { ty, data }
+                if *ty == Self::MANUFACTURER_SPECIFIC_DATA && data.len() == Self::DATA_LEN =>
+            {
+                Self::from_bytes(&mut ByteReader::new(data)).ok()
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<'a> FromBytes<'a> for AltBeacon {
+    fn from_bytes(bytes: &mut ByteReader<'a>) -> Result<Self, Error> {
+        let manufacturer = CompanyId::from_raw(bytes.read_u16_le()?);
+        if bytes.read_array::<[u8; 2]>()? != Self::BEACON_CODE {
+            return Err(Error::InvalidValue);
+        }
+        Ok(Self {
+            manufacturer,
+            beacon_id: bytes.read_array()?,
+            reference_rssi: bytes.read_u8()? as i8,
+            reserved: bytes.read_u8()?,
+        })
+    }
+}
+
+impl ToBytes for AltBeacon {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u16_le(self.manufacturer.as_u16())?;
+        writer.write_slice(&Self::BEACON_CODE)?;
+        writer.write_slice(&self.beacon_id)?;
+        writer.write_u8(self.reference_rssi as u8)?;
+        writer.write_u8(self.reserved)
+    }
+}
+
 /// Callback for the `BeaconScanner`.
 pub trait ScanCallback {
     /// Called when a beacon is received and has passed the configured device address filter.
@@ -80,6 +176,7 @@ pub struct BeaconScanner<C: ScanCallback, F: AddressFilter> {
     filter: ScanFilter<F>,
     interval: Duration,
     channel: AdvertisingChannel,
+    channels: AdvChannelMap,
 }
 
 impl<C: ScanCallback> BeaconScanner<C, filter::AllowAll> {
@@ -97,9 +194,18 @@ impl<C: ScanCallback, F: AddressFilter> BeaconScanner<C, F> {
             filter: ScanFilter::new(scan_filter),
             interval: Duration::from_micros(0),
             channel: AdvertisingChannel::first(),
+            channels: AdvChannelMap::ALL,
         }
     }
 
+    /// Restricts the scanner to listen on `channels` only.
+    ///
+    /// This takes effect on the next call to `configure`.
+    pub fn with_channels(mut self, channels: AdvChannelMap) -> Self {
+        self.channels = channels;
+        self
+    }
+
     /// Configures the `BeaconScanner` and returns a `Cmd` to apply to the radio.
     ///
     /// The `next_update` field of the returned `Cmd` specifies when to call `timer_update` the next
@@ -107,7 +213,7 @@ impl<C: ScanCallback, F: AddressFilter> BeaconScanner<C, F> {
     /// to the next advertising channel after `interval` elapses.
     pub fn configure(&mut self, now: Instant, interval: Duration) -> Cmd {
         self.interval = interval;
-        self.channel = AdvertisingChannel::first();
+        self.channel = self.channels.first();
 
         Cmd {
             // Switch channels
@@ -125,7 +231,7 @@ impl<C: ScanCallback, F: AddressFilter> BeaconScanner<C, F> {
     ///
     /// This switches to the next advertising channel and will listen there.
     pub fn timer_update(&mut self, now: Instant) -> Cmd {
-        self.channel = self.channel.cycle();
+        self.channel = self.channel.cycle_in(self.channels);
 
         Cmd {
             // Switch channels
@@ -163,3 +269,22 @@ impl<C: ScanCallback, F: AddressFilter> BeaconScanner<C, F> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn altbeacon() {
+        let mut id = [0; 20];
+        id[0] = 0x42;
+        id[19] = 0x07;
+        let beacon = AltBeacon::new(CompanyId::from_raw(0x0118), id, -59);
+
+        let mut buf = [0; AltBeacon::DATA_LEN];
+        let ad = beacon.encode(&mut buf);
+        assert_eq!(AltBeacon::from_ad_structure(&ad), Some(beacon));
+        assert_eq!(buf[..5], [0x18, 0x01, 0xBE, 0xAC, 0x42]);
+        assert_eq!(buf[23..], [0x07, 0xC5, 0x00]);
+    }
+}
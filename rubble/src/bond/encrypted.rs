@@ -0,0 +1,153 @@
+//! Encryption of the keys in a `BondStore`.
+//!
+//! The keys exchanged during pairing allow anyone who knows them to impersonate the bonded
+//! devices, or to decrypt recorded connections. On MCUs without readout protection, anyone with a
+//! debugger can read them out of flash. `EncryptedBondStore` wraps another `BondStore` and encrypts
+//! the keys of every bond with a `KeyCipher` before it is stored, and decrypts them when loading
+//! it. Only the addresses and other metadata are stored in the clear.
+//!
+//! This is only useful if the cipher's own key is not stored next to the bonds, eg. because it is
+//! derived from a device-unique secret or kept in a secure element.
+
+use {
+    super::{Bond, BondStore},
+    crate::link::{privacy::IdentityResolvingKey, DeviceAddress},
+};
+
+/// A cipher protecting the 16-Byte keys of a bond while it is stored.
+///
+/// An implementation using AES-128 in ECB mode is a good choice: The keys are random and each fits
+/// into a single block.
+pub trait KeyCipher {
+    /// Encrypts `key` in place before it is stored.
+    fn encrypt(&mut self, key: &mut [u8; 16]);
+
+    /// Decrypts a `key` that was encrypted by `encrypt`, in place.
+    fn decrypt(&mut self, key: &mut [u8; 16]);
+}
+
+/// A `BondStore` that encrypts the LTK, IRK and Account Key of all bonds stored in `S`.
+pub struct EncryptedBondStore<S: BondStore, C: KeyCipher> {
+    store: S,
+    cipher: C,
+}
+
+impl<S: BondStore, C: KeyCipher> EncryptedBondStore<S, C> {
+    /// Wraps `store`, encrypting keys with `cipher`.
+    ///
+    /// Bonds that were stored in `store` without encryption can't be loaded correctly anymore.
+    pub fn new(store: S, cipher: C) -> Self {
+        Self { store, cipher }
+    }
+
+    /// Releases the underlying store and the cipher.
+    pub fn free(self) -> (S, C) {
+        (self.store, self.cipher)
+    }
+}
+
+impl<S: BondStore, C: KeyCipher> BondStore for EncryptedBondStore<S, C> {
+    type Error = S::Error;
+
+    fn load(&mut self, address: &DeviceAddress) -> Result<Option<Bond>, Self::Error> {
+        let cipher = &mut self.cipher;
+        Ok(self
+            .store
+            .load(address)?
+            .map(|bond| convert_keys(bond, &mut |key| cipher.decrypt(key))))
+    }
+
+    fn store(&mut self, bond: &Bond) -> Result<(), Self::Error> {
+        let cipher = &mut self.cipher;
+        let encrypted = convert_keys(*bond, &mut |key| cipher.encrypt(key));
+        self.store.store(&encrypted)
+    }
+
+    fn remove(&mut self, address: &DeviceAddress) -> Result<(), Self::Error> {
+        self.store.remove(address)
+    }
+
+    fn for_each(&mut self, f: &mut dyn FnMut(&Bond)) -> Result<(), Self::Error> {
+        let cipher = &mut self.cipher;
+        self.store.for_each(&mut |bond| {
+            f(&convert_keys(*bond, &mut |key| cipher.decrypt(key)));
+        })
+    }
+}
+
+/// Applies `convert` to all keys in `bond`.
+fn convert_keys(mut bond: Bond, convert: &mut dyn FnMut(&mut [u8; 16])) -> Bond {
+    convert(&mut bond.ltk);
+    if let Some(irk) = &mut bond.irk {
+        let mut bytes = irk.to_le_bytes();
+        convert(&mut bytes);
+        *irk = IdentityResolvingKey::from_le_bytes(bytes);
+    }
+    if let Some(key) = &mut bond.account_key {
+        convert(key);
+    }
+    bond
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::link::AddressKind};
+
+    /// Stores a single bond as-is.
+    struct Single(Option<Bond>);
+
+    impl BondStore for Single {
+        type Error = ();
+
+        fn load(&mut self, address: &DeviceAddress) -> Result<Option<Bond>, ()> {
+            Ok(self.0.filter(|bond| bond.address == *address))
+        }
+
+        fn store(&mut self, bond: &Bond) -> Result<(), ()> {
+            self.0 = Some(*bond);
+            Ok(())
+        }
+
+        fn remove(&mut self, _address: &DeviceAddress) -> Result<(), ()> {
+            self.0 = None;
+            Ok(())
+        }
+
+        fn for_each(&mut self, f: &mut dyn FnMut(&Bond)) -> Result<(), ()> {
+            self.0.iter().for_each(f);
+            Ok(())
+        }
+    }
+
+    /// Not a cipher, but enough to tell encrypted and plain keys apart.
+    struct Xor(u8);
+
+    impl KeyCipher for Xor {
+        fn encrypt(&mut self, key: &mut [u8; 16]) {
+            key.iter_mut().for_each(|b| *b ^= self.0);
+        }
+
+        fn decrypt(&mut self, key: &mut [u8; 16]) {
+            self.encrypt(key);
+        }
+    }
+
+    #[test]
+    fn keys_encrypted() {
+        let address = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+        let mut bond = Bond::new(address, [0x11; 16]);
+        bond.irk = Some(IdentityResolvingKey::from_le_bytes([0x22; 16]));
+
+        let mut store = EncryptedBondStore::new(Single(None), Xor(0xFF));
+        store.store(&bond).unwrap();
+        let loaded = store.load(&address).unwrap().unwrap();
+        assert_eq!(loaded.ltk, [0x11; 16]);
+        assert_eq!(loaded.irk.unwrap().to_le_bytes(), [0x22; 16]);
+        assert_eq!(loaded.account_key, None);
+
+        let (mut inner, _) = store.free();
+        let stored = inner.load(&address).unwrap().unwrap();
+        assert_eq!(stored.ltk, [0xEE; 16]);
+        assert_eq!(stored.irk.unwrap().to_le_bytes(), [0xDD; 16]);
+    }
+}
@@ -0,0 +1,493 @@
+//! A `BondStore` storing bonds in NOR flash.
+//!
+//! NOR flash can only be erased in whole pages, and bits can only be cleared when writing. To
+//! avoid erasing a page for every change, `FlashBondStore` treats a page as a log: Every stored or
+//! removed bond appends a fixed-size record, and the most recent record for a device wins.
+//!
+//! When the page is full, the live records are compacted into a second page, which then becomes
+//! the active page and the old page is erased. Both pages are thus erased equally often, and only
+//! once every few dozen bond updates (depending on the page size). The second page is also what
+//! keeps the bonds safe when power is lost while compacting: The new page only becomes valid once
+//! all records have been copied, and until then the old page is still intact.
+//!
+//! Records carry a checksum, so records that were only partially written when power was lost are
+//! ignored.
+
+use {
+    super::{Bond, BondStore, MAX_CCCDS},
+    crate::{
+        att::Handle,
+        link::{privacy::IdentityResolvingKey, AddressKind, DeviceAddress},
+    },
+    core::convert::TryInto,
+};
+
+/// Interface to NOR flash memory.
+///
+/// Offsets are relative to the start of the flash region. The erased state of all bits must be
+/// `1`.
+pub trait NorFlash {
+    /// Error reported by the flash driver.
+    type Error;
+
+    /// Size of an erasable page, in Bytes.
+    const ERASE_SIZE: u32;
+
+    /// Writes must be aligned to and a multiple of this many Bytes. Must be a divisor of 8.
+    const WRITE_SIZE: u32;
+
+    /// Reads `bytes.len()` Bytes starting at `offset`.
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Writes `bytes` to the erased flash at `offset`.
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error>;
+
+    /// Erases the page starting at `offset`.
+    fn erase_page(&mut self, offset: u32) -> Result<(), Self::Error>;
+}
+
+/// Errors returned by `FlashBondStore`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StoreError<E> {
+    /// The flash driver reported an error.
+    Flash(E),
+
+    /// There are more bonds than fit into a page.
+    Full,
+}
+
+impl<E> From<E> for StoreError<E> {
+    fn from(e: E) -> Self {
+        StoreError::Flash(e)
+    }
+}
+
+/// Size of the header at the start of the active page: Magic and sequence number.
+const HEADER_SIZE: u32 = 8;
+const MAGIC: [u8; 4] = *b"RBN3";
+
+/// Size of a record. Must be a multiple of `NorFlash::WRITE_SIZE`.
+const RECORD_SIZE: usize = 88;
+const TAG_FREE: u8 = 0xFF;
+const TAG_BOND: u8 = 0xB0;
+const TAG_REMOVED: u8 = 0x7E;
+
+/// A `BondStore` using 2 pages of NOR flash.
+pub struct FlashBondStore<F: NorFlash> {
+    flash: F,
+    /// Offsets of the 2 pages.
+    pages: [u32; 2],
+    /// Index of the active page in `pages`.
+    active: usize,
+    /// Sequence number of the active page, incremented when compacting.
+    seq: u32,
+    /// Index of the next free record in the active page.
+    next: u32,
+}
+
+impl<F: NorFlash> FlashBondStore<F> {
+    /// Opens the store located in the 2 pages starting at `offset`, formatting the flash if it
+    /// doesn't contain a store yet.
+    ///
+    /// `offset` must be page-aligned.
+    pub fn new(mut flash: F, offset: u32) -> Result<Self, StoreError<F::Error>> {
+        let pages = [offset, offset + F::ERASE_SIZE];
+        let mut active = None;
+        for (i, &page) in pages.iter().enumerate() {
+            let mut header = [0; HEADER_SIZE as usize];
+            flash.read(page, &mut header)?;
+            if header[..4] == MAGIC {
+                let seq = u32::from_le_bytes(header[4..].try_into().unwrap());
+                match active {
+                    Some((_, active_seq)) if active_seq >= seq => {}
+                    _ => active = Some((i, seq)),
+                }
+            }
+        }
+
+        let mut this = Self {
+            flash,
+            pages,
+            active: 0,
+            seq: 0,
+            next: 0,
+        };
+        match active {
+            Some((active, seq)) => {
+                this.active = active;
+                this.seq = seq;
+                this.next = this.find_free()?;
+            }
+            None => {
+                this.flash.erase_page(pages[0])?;
+                this.write_header(0, 1)?;
+                this.seq = 1;
+            }
+        }
+        Ok(this)
+    }
+
+    /// Releases the flash driver.
+    pub fn free(self) -> F {
+        self.flash
+    }
+
+    fn records(&self) -> u32 {
+        (F::ERASE_SIZE - HEADER_SIZE) / RECORD_SIZE as u32
+    }
+
+    fn record_offset(&self, page: usize, index: u32) -> u32 {
+        self.pages[page] + HEADER_SIZE + index * RECORD_SIZE as u32
+    }
+
+    fn write_header(&mut self, page: usize, seq: u32) -> Result<(), F::Error> {
+        let mut header = [0; HEADER_SIZE as usize];
+        header[..4].copy_from_slice(&MAGIC);
+        header[4..].copy_from_slice(&seq.to_le_bytes());
+        self.flash.write(self.pages[page], &header)
+    }
+
+    fn read_record(&mut self, index: u32) -> Result<[u8; RECORD_SIZE], F::Error> {
+        let mut record = [0; RECORD_SIZE];
+        let offset = self.record_offset(self.active, index);
+        self.flash.read(offset, &mut record)?;
+        Ok(record)
+    }
+
+    /// Returns the index of the first free record in the active page.
+    fn find_free(&mut self) -> Result<u32, F::Error> {
+        for index in 0..self.records() {
+            if self.read_record(index)?[0] == TAG_FREE {
+                return Ok(index);
+            }
+        }
+        Ok(self.records())
+    }
+
+    /// Returns the index of the most recent valid record for `address`.
+    fn find(&mut self, address: &DeviceAddress, before: u32) -> Result<Option<u32>, F::Error> {
+        for index in (0..before).rev() {
+            let record = self.read_record(index)?;
+            if decode_address(&record) == Some(*address) {
+                return Ok(Some(index));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the bond stored in the record at `index`, if it is the most recent record for
+    /// its device.
+    fn live_bond(&mut self, index: u32) -> Result<Option<Bond>, F::Error> {
+        let record = self.read_record(index)?;
+        let bond = match decode_bond(&record) {
+            Some(bond) => bond,
+            None => return Ok(None),
+        };
+        for later in index + 1..self.next {
+            if decode_address(&self.read_record(later)?) == Some(bond.address) {
+                return Ok(None);
+            }
+        }
+        Ok(Some(bond))
+    }
+
+    fn append(&mut self, record: &[u8; RECORD_SIZE]) -> Result<(), StoreError<F::Error>> {
+        if self.next == self.records() {
+            self.compact()?;
+            if self.next == self.records() {
+                return Err(StoreError::Full);
+            }
+        }
+
+        let offset = self.record_offset(self.active, self.next);
+        self.flash.write(offset, record)?;
+        self.next += 1;
+        Ok(())
+    }
+
+    /// Copies all live bonds to the other page and makes it the active page.
+    fn compact(&mut self) -> Result<(), F::Error> {
+        let target = 1 - self.active;
+        self.flash.erase_page(self.pages[target])?;
+
+        let mut copied = 0;
+        for index in 0..self.next {
+            if self.live_bond(index)?.is_some() {
+                let record = self.read_record(index)?;
+                let offset = self.record_offset(target, copied);
+                self.flash.write(offset, &record)?;
+                copied += 1;
+            }
+        }
+
+        // The new page becomes valid with its header, the old page is erased after that
+        self.write_header(target, self.seq.wrapping_add(1))?;
+        self.flash.erase_page(self.pages[self.active])?;
+        self.active = target;
+        self.seq = self.seq.wrapping_add(1);
+        self.next = copied;
+        Ok(())
+    }
+}
+
+impl<F: NorFlash> BondStore for FlashBondStore<F> {
+    type Error = StoreError<F::Error>;
+
+    fn load(&mut self, address: &DeviceAddress) -> Result<Option<Bond>, Self::Error> {
+        match self.find(address, self.next)? {
+            Some(index) => Ok(decode_bond(&self.read_record(index)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn store(&mut self, bond: &Bond) -> Result<(), Self::Error> {
+        self.append(&encode_bond(bond))
+    }
+
+    fn remove(&mut self, address: &DeviceAddress) -> Result<(), Self::Error> {
+        if self.load(address)?.is_none() {
+            return Ok(());
+        }
+
+        let mut record = [TAG_FREE; RECORD_SIZE];
+        record[0] = TAG_REMOVED;
+        encode_address(&mut record, address);
+        seal(&mut record);
+        self.append(&record)
+    }
+
+    fn for_each(&mut self, f: &mut dyn FnMut(&Bond)) -> Result<(), Self::Error> {
+        for index in 0..self.next {
+            if let Some(bond) = self.live_bond(index)? {
+                f(&bond);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Record layout:
+// 0: tag, 1: address kind, 2-7: address, 8: flags, 9: key size, 10-25: LTK, 26-27: EDIV,
+// 28-35: Rand, 36-51: IRK, 52-67: CCCDs (handle + value), 68-83: Account Key, 84-85: layout hash,
+// 86-87: checksum
+
+const FLAG_IRK: u8 = 1 << 0;
+const FLAG_AUTHENTICATED: u8 = 1 << 1;
+const FLAG_ACCOUNT_KEY: u8 = 1 << 2;
+const FLAG_LAYOUT_HASH: u8 = 1 << 3;
+const CHECKSUM: usize = 86;
+
+fn encode_address(record: &mut [u8; RECORD_SIZE], address: &DeviceAddress) {
+    record[1] = match address.kind() {
+        AddressKind::Public => 0,
+        AddressKind::Random => 1,
+    };
+    record[2..8].copy_from_slice(address.raw());
+}
+
+fn encode_bond(bond: &Bond) -> [u8; RECORD_SIZE] {
+    let mut record = [TAG_FREE; RECORD_SIZE];
+    record[0] = TAG_BOND;
+    encode_address(&mut record, &bond.address);
+
+    let mut flags = 0;
+    if bond.irk.is_some() {
+        flags |= FLAG_IRK;
+    }
+    if bond.authenticated {
+        flags |= FLAG_AUTHENTICATED;
+    }
+    if bond.account_key.is_some() {
+        flags |= FLAG_ACCOUNT_KEY;
+    }
+    if bond.layout_hash.is_some() {
+        flags |= FLAG_LAYOUT_HASH;
+    }
+    record[8] = flags;
+    record[9] = bond.key_size;
+    record[10..26].copy_from_slice(&bond.ltk);
+    record[26..28].copy_from_slice(&bond.ediv.to_le_bytes());
+    record[28..36].copy_from_slice(&bond.rand.to_le_bytes());
+    if let Some(irk) = &bond.irk {
+        record[36..52].copy_from_slice(&irk.to_le_bytes());
+    }
+    for (i, cccd) in bond.cccds.iter().enumerate() {
+        if let Some((handle, value)) = cccd {
+            let at = 52 + i * 4;
+            record[at..at + 2].copy_from_slice(&handle.as_u16().to_le_bytes());
+            record[at + 2..at + 4].copy_from_slice(&value.to_le_bytes());
+        }
+    }
+    if let Some(key) = &bond.account_key {
+        record[68..84].copy_from_slice(key);
+    }
+    if let Some(hash) = bond.layout_hash {
+        record[84..86].copy_from_slice(&hash.to_le_bytes());
+    }
+
+    seal(&mut record);
+    record
+}
+
+/// Computes the checksum of a record and writes it into the record.
+fn seal(record: &mut [u8; RECORD_SIZE]) {
+    let sum = checksum(record);
+    record[CHECKSUM..CHECKSUM + 2].copy_from_slice(&sum.to_le_bytes());
+}
+
+/// Fletcher-16 checksum over the record contents.
+fn checksum(record: &[u8; RECORD_SIZE]) -> u16 {
+    let (mut a, mut b) = (0u16, 0u16);
+    for &byte in &record[..CHECKSUM] {
+        a = (a + u16::from(byte)) % 255;
+        b = (b + a) % 255;
+    }
+    (b << 8) | a
+}
+
+fn is_valid(record: &[u8; RECORD_SIZE]) -> bool {
+    let stored = u16::from_le_bytes([record[CHECKSUM], record[CHECKSUM + 1]]);
+    (record[0] == TAG_BOND || record[0] == TAG_REMOVED) && stored == checksum(record)
+}
+
+/// Returns the device address of a valid bond or removal record.
+fn decode_address(record: &[u8; RECORD_SIZE]) -> Option<DeviceAddress> {
+    if !is_valid(record) {
+        return None;
+    }
+
+    let kind = match record[1] {
+        0 => AddressKind::Public,
+        _ => AddressKind::Random,
+    };
+    Some(DeviceAddress::new(record[2..8].try_into().unwrap(), kind))
+}
+
+fn decode_bond(record: &[u8; RECORD_SIZE]) -> Option<Bond> {
+    let address = decode_address(record)?;
+    if record[0] != TAG_BOND {
+        return None;
+    }
+
+    let flags = record[8];
+    let mut cccds = [None; MAX_CCCDS];
+    for (i, cccd) in cccds.iter_mut().enumerate() {
+        let at = 52 + i * 4;
+        let handle = u16::from_le_bytes([record[at], record[at + 1]]);
+        let value = u16::from_le_bytes([record[at + 2], record[at + 3]]);
+        // Unused slots are left erased (`0xFFFF` is not a valid handle)
+        if handle != 0xFFFF {
+            *cccd = Some((Handle::from_raw(handle), value));
+        }
+    }
+
+    Some(Bond {
+        address,
+        irk: if flags & FLAG_IRK != 0 {
+            Some(IdentityResolvingKey::from_le_bytes(
+                record[36..52].try_into().unwrap(),
+            ))
+        } else {
+            None
+        },
+        ltk: record[10..26].try_into().unwrap(),
+        ediv: u16::from_le_bytes([record[26], record[27]]),
+        rand: u64::from_le_bytes(record[28..36].try_into().unwrap()),
+        key_size: record[9],
+        authenticated: flags & FLAG_AUTHENTICATED != 0,
+        cccds,
+        account_key: if flags & FLAG_ACCOUNT_KEY != 0 {
+            Some(record[68..84].try_into().unwrap())
+        } else {
+            None
+        },
+        layout_hash: if flags & FLAG_LAYOUT_HASH != 0 {
+            Some(u16::from_le_bytes([record[84], record[85]]))
+        } else {
+            None
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2 pages of 272 Bytes, which fit 3 records each.
+    struct RamFlash([u8; 544]);
+
+    impl NorFlash for RamFlash {
+        type Error = ();
+        const ERASE_SIZE: u32 = 272;
+        const WRITE_SIZE: u32 = 4;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), ()> {
+            let offset = offset as usize;
+            bytes.copy_from_slice(&self.0[offset..offset + bytes.len()]);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), ()> {
+            for (i, byte) in bytes.iter().enumerate() {
+                // Writes can only clear bits
+                self.0[offset as usize + i] &= byte;
+            }
+            Ok(())
+        }
+
+        fn erase_page(&mut self, offset: u32) -> Result<(), ()> {
+            let offset = offset as usize;
+            for byte in &mut self.0[offset..offset + 272] {
+                *byte = 0xFF;
+            }
+            Ok(())
+        }
+    }
+
+    fn bond(last: u8) -> Bond {
+        let address = DeviceAddress::new([1, 2, 3, 4, 5, last], AddressKind::Random);
+        Bond::new(address, [last; 16])
+    }
+
+    #[test]
+    fn store_load_remove() {
+        let mut store = FlashBondStore::new(RamFlash([0xFF; 544]), 0).unwrap();
+        let mut a = bond(1);
+        a.irk = Some(IdentityResolvingKey::from_le_bytes([7; 16]));
+        assert!(a.set_cccd(Handle::from_raw(4), 1));
+        a.layout_hash = Some(0xABCD);
+        store.store(&a).unwrap();
+        store.store(&bond(2)).unwrap();
+
+        let loaded = store.load(&a.address).unwrap().unwrap();
+        assert_eq!(loaded.ltk, [1; 16]);
+        assert_eq!(loaded.irk.unwrap().to_le_bytes(), [7; 16]);
+        assert_eq!(loaded.cccd(Handle::from_raw(4)), Some(1));
+        assert_eq!(loaded.layout_hash, Some(0xABCD));
+
+        store.remove(&a.address).unwrap();
+        assert!(store.load(&a.address).unwrap().is_none());
+
+        // Reopening the store finds the same bonds
+        let mut store = FlashBondStore::new(store.free(), 0).unwrap();
+        assert!(store.load(&a.address).unwrap().is_none());
+        assert!(store.load(&bond(2).address).unwrap().is_some());
+    }
+
+    #[test]
+    fn compaction() {
+        let mut store = FlashBondStore::new(RamFlash([0xFF; 544]), 0).unwrap();
+        // More updates than fit into a page
+        for _ in 0..5 {
+            store.store(&bond(1)).unwrap();
+            store.store(&bond(2)).unwrap();
+        }
+
+        let mut count = 0;
+        store.for_each(&mut |_| count += 1).unwrap();
+        assert_eq!(count, 2);
+
+        store.store(&bond(3)).unwrap();
+        assert_eq!(store.store(&bond(4)), Err(StoreError::Full));
+    }
+}
@@ -0,0 +1,140 @@
+//! Persistent storage of bonding information.
+//!
+//! When two devices bond, they exchange keys that allow them to re-establish an encrypted
+//! connection later, without pairing again (see the [`security`] module). For this to work across
+//! power cycles, the keys have to be stored in non-volatile memory. The same goes for state the
+//! peer expects to be retained between connections, like the *Client Characteristic
+//! Configuration* of the characteristics it subscribed to.
+//!
+//! The `BondStore` trait abstracts over the storage used for this. The [`flash`] module provides
+//! an implementation on top of NOR flash memory (like the internal flash of nRF52 MCUs), and the
+//! stored keys can be encrypted by wrapping the store with the [`encrypted`] module.
+//!
+//! [`security`]: ../security/index.html
+//! [`flash`]: flash/index.html
+//! [`encrypted`]: encrypted/index.html
+
+pub mod encrypted;
+pub mod flash;
+
+use crate::{
+    att::Handle,
+    link::{
+        privacy::{Identity, IdentityResolvingKey},
+        DeviceAddress,
+    },
+};
+
+/// Maximum number of CCCD values stored per bond.
+pub const MAX_CCCDS: usize = 4;
+
+/// Information about a bonded device.
+#[derive(Debug, Copy, Clone)]
+pub struct Bond {
+    /// Identity address of the device.
+    ///
+    /// Bonds are identified by this address. For devices using resolvable private addresses,
+    /// this is the identity address they distributed during pairing.
+    pub address: DeviceAddress,
+
+    /// The device's Identity Resolving Key, if it distributed one.
+    pub irk: Option<IdentityResolvingKey>,
+
+    /// The Long Term Key used to encrypt the connection.
+    pub ltk: [u8; 16],
+
+    /// Encrypted Diversifier identifying the LTK (0 for LE Secure Connections).
+    pub ediv: u16,
+
+    /// Random number identifying the LTK (0 for LE Secure Connections).
+    pub rand: u64,
+
+    /// Size of the LTK in Bytes (7-16).
+    pub key_size: u8,
+
+    /// Whether the key was generated with MITM protection.
+    pub authenticated: bool,
+
+    /// Client Characteristic Configuration values written by the device, as pairs of descriptor
+    /// handle and value.
+    pub cccds: [Option<(Handle, u16)>; MAX_CCCDS],
+
+    /// Google Fast Pair *Account Key* written by the device, if any.
+    pub account_key: Option<[u8; 16]>,
+
+    /// Fingerprint of the attribute database layout the device has last seen, if known.
+    ///
+    /// Refer to `gatt::handles::layout_hash` for how this is used to decide whether the device
+    /// needs a *Service Changed* indication.
+    pub layout_hash: Option<u16>,
+}
+
+impl Bond {
+    /// Creates a bond with the given device and key, without IRK or CCCD values.
+    pub fn new(address: DeviceAddress, ltk: [u8; 16]) -> Self {
+        Self {
+            address,
+            irk: None,
+            ltk,
+            ediv: 0,
+            rand: 0,
+            key_size: 16,
+            authenticated: false,
+            cccds: [None; MAX_CCCDS],
+            account_key: None,
+            layout_hash: None,
+        }
+    }
+
+    /// Returns the identity of the device, if it distributed an IRK.
+    ///
+    /// This can be used to build an `IdentityResolver` from the stored bonds.
+    pub fn identity(&self) -> Option<Identity> {
+        self.irk.map(|irk| Identity::new(irk, self.address))
+    }
+
+    /// Returns the stored value of the CCCD at `handle`.
+    pub fn cccd(&self, handle: Handle) -> Option<u16> {
+        self.cccds
+            .iter()
+            .filter_map(|&cccd| cccd)
+            .find(|(h, _)| *h == handle)
+            .map(|(_, value)| value)
+    }
+
+    /// Stores the value of the CCCD at `handle`.
+    ///
+    /// Returns `false` if there's no space left for a new CCCD.
+    pub fn set_cccd(&mut self, handle: Handle, value: u16) -> bool {
+        let slot = match self.cccds.iter().position(|c| match c {
+            Some((h, _)) => *h == handle,
+            None => false,
+        }) {
+            Some(i) => i,
+            None => match self.cccds.iter().position(Option::is_none) {
+                Some(i) => i,
+                None => return false,
+            },
+        };
+        self.cccds[slot] = Some((handle, value));
+        true
+    }
+}
+
+/// Trait for non-volatile storage of bonds.
+pub trait BondStore {
+    /// Error returned by the underlying storage.
+    type Error;
+
+    /// Looks up the bond with the device whose identity address is `address`.
+    fn load(&mut self, address: &DeviceAddress) -> Result<Option<Bond>, Self::Error>;
+
+    /// Stores `bond`, replacing any existing bond with the same device.
+    fn store(&mut self, bond: &Bond) -> Result<(), Self::Error>;
+
+    /// Deletes the bond with the device using identity address `address`, if any.
+    fn remove(&mut self, address: &DeviceAddress) -> Result<(), Self::Error>;
+
+    /// Invokes `f` with every stored bond.
+    fn for_each(&mut self, f: &mut dyn FnMut(&Bond)) -> Result<(), Self::Error>;
+}
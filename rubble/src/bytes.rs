@@ -73,16 +73,13 @@ enum Inner<'a, T: ?Sized> {
 
 impl<'a, T: ?Sized> Clone for Inner<'a, T> {
     fn clone(&self) -> Self {
-        match self {
-            Inner::Bytes(b) => Inner::Bytes(b),
-            Inner::Or(t) => Inner::Or(t),
-        }
+        *self
     }
 }
 
 impl<'a, T: ?Sized> Clone for BytesOr<'a, T> {
     fn clone(&self) -> Self {
-        BytesOr(self.0)
+        *self
     }
 }
 
@@ -215,7 +212,7 @@ impl<'a, T: Copy + FromBytes<'a>> Iterator for IterBytesOr<'a, T> {
                     None
                 } else {
                     // Read a `T` and overwrite our `b` with the left-over data
-                    let mut reader = ByteReader::new(*b);
+                    let mut reader = ByteReader::new(b);
                     let t = T::from_bytes(&mut reader).unwrap();
                     *b = reader.into_rest();
                     Some(t)
@@ -264,7 +261,7 @@ impl<'a> ByteWriter<'a> {
         if self.space_left() < bytes {
             Err(Error::Eof)
         } else {
-            let this = mem::replace(&mut self.0, &mut []);
+            let this = mem::take(&mut self.0);
             self.0 = &mut this[bytes..];
             Ok(())
         }
@@ -278,12 +275,11 @@ impl<'a> ByteWriter<'a> {
     /// Note that if the created `ByteWriter` is not used, the bytes will contain whatever contents
     /// they had before creating `self` (ie. most likely garbage data left over from earlier use).
     /// If you are really sure you want that, `skip` is a more explicit way of accomplishing that.
-    #[must_use]
     pub fn split_off(&mut self, len: usize) -> Result<Self, Error> {
         if self.space_left() < len {
             Err(Error::Eof)
         } else {
-            let this = mem::replace(&mut self.0, &mut []);
+            let this = mem::take(&mut self.0);
             let (head, tail) = this.split_at_mut(len);
             self.0 = tail;
             Ok(ByteWriter::new(head))
@@ -300,7 +296,7 @@ impl<'a> ByteWriter<'a> {
     ///
     /// [`split_off`]: #method.split_off
     pub fn split_next_mut(&mut self) -> Option<&'a mut u8> {
-        let this = mem::replace(&mut self.0, &mut []);
+        let this = mem::take(&mut self.0);
         // Slight contortion to please the borrow checker:
         if this.is_empty() {
             self.0 = this;
@@ -326,7 +322,7 @@ impl<'a> ByteWriter<'a> {
             Err(Error::Eof)
         } else {
             self.0[..other.len()].copy_from_slice(other);
-            let this = mem::replace(&mut self.0, &mut []);
+            let this = mem::take(&mut self.0);
             self.0 = &mut this[other.len()..];
             Ok(())
         }
@@ -361,6 +357,20 @@ impl<'a> ByteWriter<'a> {
         self.write_slice(&bytes)
     }
 
+    /// Writes the lower 24 bits of a `u32` to `self`, using Little Endian byte order.
+    ///
+    /// If `self` does not have enough space left, an error will be returned and no bytes will be
+    /// written to `self`.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if `value` does not fit in 24 bits.
+    pub fn write_u24_le(&mut self, value: u32) -> Result<(), Error> {
+        let mut bytes = [0; 3];
+        LittleEndian::write_u24(&mut bytes, value);
+        self.write_slice(&bytes)
+    }
+
     /// Writes a `u32` to `self`, using Little Endian byte order.
     ///
     /// If `self` does not have enough space left, an error will be returned and no bytes will be
@@ -424,7 +434,6 @@ impl<'a> ByteReader<'a> {
     ///
     /// Note that if the created `ByteReader` is not used, the bytes will be ignored. If you are
     /// really sure you want that, `skip` is a more explicit way of accomplishing that.
-    #[must_use]
     pub fn split_off(&mut self, len: usize) -> Result<Self, Error> {
         if self.bytes_left() < len {
             Err(Error::Eof)
@@ -500,6 +509,12 @@ impl<'a> ByteReader<'a> {
         Ok(LittleEndian::read_u16(&arr))
     }
 
+    /// Reads a 24-bit value from `self`, using Little Endian byte order.
+    pub fn read_u24_le(&mut self) -> Result<u32, Error> {
+        let arr = self.read_array::<[u8; 3]>()?;
+        Ok(LittleEndian::read_u24(&arr))
+    }
+
     /// Reads a `u32` from `self`, using Little Endian byte order.
     pub fn read_u32_le(&mut self) -> Result<u32, Error> {
         let arr = self.read_array::<[u8; 4]>()?;
@@ -542,9 +557,9 @@ impl<T: ToBytes> ToBytes for [T] {
     }
 }
 
-impl<'a> ToBytes for &'a [u8] {
+impl ToBytes for &[u8] {
     fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
-        writer.write_slice(*self)
+        writer.write_slice(self)
     }
 }
 
@@ -3,8 +3,11 @@
 use crate::{
     l2cap::ChannelMapper,
     link::{
+        llcp::ControlPduHandler,
+        power::PowerHooks,
         queue::{self, PacketQueue},
-        Transmitter,
+        scan::Observer,
+        ConnectionEventHook, Transmitter,
     },
     time::Timer,
 };
@@ -35,4 +38,24 @@ pub trait Config {
 
     type PacketProducer: queue::Producer;
     type PacketConsumer: queue::Consumer;
+
+    /// Hook invoked by the Link-Layer at the end of each connection event.
+    ///
+    /// Use `()` if you don't need one.
+    type EventHook: ConnectionEventHook;
+
+    /// Handler for LL Control PDUs not supported by Rubble.
+    ///
+    /// Use `()` to respond to all of them with `LL_UNKNOWN_RSP`.
+    type ControlHandler: ControlPduHandler;
+
+    /// Receives the advertisements found while scanning.
+    ///
+    /// Use `()` if the device doesn't scan.
+    type Observer: Observer;
+
+    /// Hooks notified when the radio is turned off between radio activities.
+    ///
+    /// Use `()` if the radio doesn't need to be released.
+    type PowerHooks: PowerHooks;
 }
@@ -22,3 +22,28 @@ pub fn ble_crc24(data: &[u8], preset: u32) -> u32 {
 
     crc & 0x00FFFFFF
 }
+
+/// Computes the CRC-24 of a PDU in the bit order used on air.
+///
+/// Unlike `ble_crc24`, this feeds every Byte into the shift register LSb first, like the radio
+/// transmits it. This is the CRC a BLE radio computes in hardware; `ble_crc24` is only used as a
+/// checksum between Rubble instances.
+pub fn ble_crc24_on_air(data: &[u8], preset: u32) -> u32 {
+    let polynomial = CRC_POLY & 0x00FFFFFF;
+    let mut crc = preset & 0x00FFFFFF;
+
+    for byte in data {
+        let mut byte = *byte;
+        for _ in 0..8 {
+            let feedback = (crc >> 23) as u8 & 1 != byte & 1;
+            crc = (crc << 1) & 0x00FFFFFF;
+            byte >>= 1;
+
+            if feedback {
+                crc ^= polynomial;
+            }
+        }
+    }
+
+    crc
+}
@@ -0,0 +1,352 @@
+//! LE Direct Test Mode (DTM).
+//!
+//! Direct Test Mode is used for RF qualification and regulatory testing: A tester (like an RF
+//! test box) sends commands via a 2-wire UART interface, which make the device under test either
+//! transmit test packets on a given channel or count the test packets it receives. This is
+//! specified in *Vol 6, Part F* of the Bluetooth Core Specification.
+//!
+//! This module implements the UART protocol and the state machine. The radio side is provided by
+//! the platform via the [`DtmRadio`] trait. A DTM firmware then just forwards all bytes received
+//! via the UART to [`Dtm::process_uart_byte`] and sends back the responses:
+//!
+//! ```ignore
+//! let mut dtm = Dtm::new(radio);
+//! loop {
+//!     let byte = uart.read();
+//!     if let Some(event) = dtm.process_uart_byte(byte) {
+//!         uart.write(&event);
+//!     }
+//! }
+//! ```
+//!
+//! The tester leaves at least 5 ms between commands, so if more time has passed since the last
+//! byte, [`Dtm::reset_uart_framing`] should be called to resynchronize.
+//!
+//! Only the LE 1M PHY, the standard modulation index and payloads of up to 37 Bytes are
+//! supported.
+//!
+//! [`DtmRadio`]: trait.DtmRadio.html
+//! [`Dtm::process_uart_byte`]: struct.Dtm.html#method.process_uart_byte
+//! [`Dtm::reset_uart_framing`]: struct.Dtm.html#method.reset_uart_framing
+
+use crate::time::Duration;
+
+/// Access Address used by all test packets.
+pub const ACCESS_ADDRESS: u32 = 0x7176_4129;
+
+/// CRC preset used by all test packets.
+pub const CRC_PRESET: u32 = 0x0055_5555;
+
+/// Largest supported test packet payload.
+pub const MAX_PAYLOAD: usize = 37;
+
+/// Radio functionality needed for Direct Test Mode.
+///
+/// Test packets are sent on RF channels 0 to 39 (2402 to 2480 MHz), use `ACCESS_ADDRESS` and
+/// `CRC_PRESET`, and are not whitened.
+pub trait DtmRadio {
+    /// Starts transmitting `packet` every `interval` on `rf_channel`, until `stop_test` is called.
+    ///
+    /// `packet` contains the 2-Byte header followed by the payload.
+    fn start_transmitter_test(&mut self, rf_channel: u8, packet: &[u8], interval: Duration);
+
+    /// Starts receiving test packets on `rf_channel`, until `stop_test` is called.
+    ///
+    /// `Dtm::packet_received` must be called for every received packet with a correct CRC.
+    fn start_receiver_test(&mut self, rf_channel: u8);
+
+    /// Stops the running test and turns the radio off.
+    fn stop_test(&mut self);
+}
+
+/// Payload patterns of test packets.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PayloadPattern {
+    /// Pseudo-random bit sequence 9.
+    Prbs9,
+    /// Repeated `11110000` (in transmission order).
+    Pattern11110000,
+    /// Repeated `10101010` (in transmission order).
+    Pattern10101010,
+    /// All bits set.
+    ///
+    /// The spec leaves this packet type vendor-specific on the LE 1M PHY.
+    AllOnes,
+}
+
+impl PayloadPattern {
+    /// Decodes the 2-bit packet type field of a transmitter test command.
+    fn from_bits(bits: u8) -> Self {
+        match bits & 0b11 {
+            0b00 => PayloadPattern::Prbs9,
+            0b01 => PayloadPattern::Pattern11110000,
+            0b10 => PayloadPattern::Pattern10101010,
+            _ => PayloadPattern::AllOnes,
+        }
+    }
+
+    /// Returns the value of the PDU Type field in the test packet header.
+    pub fn pdu_type(&self) -> u8 {
+        match self {
+            PayloadPattern::Prbs9 => 0,
+            PayloadPattern::Pattern11110000 => 1,
+            PayloadPattern::Pattern10101010 => 2,
+            PayloadPattern::AllOnes => 4,
+        }
+    }
+
+    /// Fills `payload` with this pattern.
+    pub fn fill(&self, payload: &mut [u8]) {
+        match self {
+            PayloadPattern::Prbs9 => {
+                // x^9 + x^5 + 1, starting with all ones. Bits are sent LSb first.
+                let mut lfsr: u16 = 0x1FF;
+                for byte in payload {
+                    *byte = 0;
+                    for bit in 0..8 {
+                        *byte |= ((lfsr & 1) as u8) << bit;
+                        let feedback = (lfsr ^ (lfsr >> 4)) & 1;
+                        lfsr = (lfsr >> 1) | (feedback << 8);
+                    }
+                }
+            }
+            PayloadPattern::Pattern11110000 => payload.iter_mut().for_each(|b| *b = 0x0F),
+            PayloadPattern::Pattern10101010 => payload.iter_mut().for_each(|b| *b = 0x55),
+            PayloadPattern::AllOnes => payload.iter_mut().for_each(|b| *b = 0xFF),
+        }
+    }
+}
+
+/// Returns the interval at which test packets with `payload_len` Bytes are sent on the LE 1M PHY.
+pub fn packet_interval(payload_len: usize) -> Duration {
+    // Preamble, Access Address, header and CRC take 10 Bytes, and every Byte takes 8 µs
+    let packet_time = (10 + payload_len as u32) * 8;
+    let slots = (packet_time + 249).div_ceil(625);
+    Duration::from_micros(slots * 625)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    Idle,
+    Transmitting,
+    Receiving,
+}
+
+/// Direct Test Mode state machine.
+pub struct Dtm<R: DtmRadio> {
+    radio: R,
+    state: State,
+    /// Number of test packets received in the current receiver test.
+    received: u16,
+    /// First Byte of a command, if only 1 Byte has been received so far.
+    pending_byte: Option<u8>,
+    packet: [u8; 2 + MAX_PAYLOAD],
+}
+
+impl<R: DtmRadio> Dtm<R> {
+    /// Creates a DTM instance controlling `radio`.
+    pub fn new(radio: R) -> Self {
+        Self {
+            radio,
+            state: State::Idle,
+            received: 0,
+            pending_byte: None,
+            packet: [0; 2 + MAX_PAYLOAD],
+        }
+    }
+
+    /// Returns a mutable reference to the radio.
+    pub fn radio(&mut self) -> &mut R {
+        &mut self.radio
+    }
+
+    /// Processes a Byte received via the UART.
+    ///
+    /// Commands are 2 Bytes long (most significant Byte first). Once both Bytes have been
+    /// received, the command is executed and the 2-Byte event to send back is returned.
+    pub fn process_uart_byte(&mut self, byte: u8) -> Option<[u8; 2]> {
+        match self.pending_byte.take() {
+            None => {
+                self.pending_byte = Some(byte);
+                None
+            }
+            Some(msb) => {
+                let event = self.process_command(u16::from_be_bytes([msb, byte]));
+                Some(event.to_be_bytes())
+            }
+        }
+    }
+
+    /// Discards a partially received command.
+    pub fn reset_uart_framing(&mut self) {
+        self.pending_byte = None;
+    }
+
+    /// Executes a 16-bit DTM command and returns the event to send back.
+    pub fn process_command(&mut self, cmd: u16) -> u16 {
+        let kind = cmd >> 14;
+        let channel = ((cmd >> 8) & 0x3F) as u8;
+        let param = ((cmd >> 2) & 0x3F) as u8;
+        let dc = (cmd & 0b11) as u8;
+
+        match kind {
+            // LE Test Setup
+            0b00 => self.setup(channel, param),
+            // LE Receiver Test
+            0b01 => {
+                if self.state != State::Idle || channel > 39 {
+                    return STATUS_ERROR;
+                }
+                self.received = 0;
+                self.state = State::Receiving;
+                self.radio.start_receiver_test(channel);
+                STATUS_SUCCESS
+            }
+            // LE Transmitter Test
+            0b10 => {
+                let len = usize::from(param);
+                if self.state != State::Idle || channel > 39 || len > MAX_PAYLOAD {
+                    return STATUS_ERROR;
+                }
+                let pattern = PayloadPattern::from_bits(dc);
+                self.packet[0] = pattern.pdu_type();
+                self.packet[1] = param;
+                pattern.fill(&mut self.packet[2..2 + len]);
+                self.state = State::Transmitting;
+                self.radio.start_transmitter_test(
+                    channel,
+                    &self.packet[..2 + len],
+                    packet_interval(len),
+                );
+                STATUS_SUCCESS
+            }
+            // LE Test End
+            _ => {
+                if self.state == State::Idle {
+                    return STATUS_ERROR;
+                }
+                let received = if self.state == State::Receiving {
+                    self.received
+                } else {
+                    0
+                };
+                self.stop();
+                PACKET_REPORT | received
+            }
+        }
+    }
+
+    /// Counts a test packet with correct CRC received during a receiver test.
+    ///
+    /// This should be called by the radio backend.
+    pub fn packet_received(&mut self) {
+        if self.state == State::Receiving && self.received < 0x7FFF {
+            self.received += 1;
+        }
+    }
+
+    fn stop(&mut self) {
+        if self.state != State::Idle {
+            self.radio.stop_test();
+            self.state = State::Idle;
+        }
+    }
+
+    /// Handles an LE Test Setup command.
+    fn setup(&mut self, control: u8, param: u8) -> u16 {
+        match (control, param) {
+            // Reset
+            (0x00, 0x00) => {
+                self.stop();
+                STATUS_SUCCESS
+            }
+            // Upper 2 bits of the payload length: Longer payloads aren't supported
+            (0x01, 0x00) => STATUS_SUCCESS,
+            // Select PHY: Only LE 1M
+            (0x02, 0x01) => STATUS_SUCCESS,
+            // Modulation index: Only standard
+            (0x03, 0x00) => STATUS_SUCCESS,
+            // Read supported features: None of the optional ones
+            (0x04, 0x00) => response(0),
+            // Read maximum TX/RX octets and time
+            (0x05, 0x00) | (0x05, 0x02) => response(MAX_PAYLOAD as u16),
+            (0x05, 0x01) | (0x05, 0x03) => response(packet_time_us(MAX_PAYLOAD)),
+            _ => STATUS_ERROR,
+        }
+    }
+}
+
+/// LE Test Status Event reporting success.
+const STATUS_SUCCESS: u16 = 0x0000;
+
+/// LE Test Status Event reporting an error.
+const STATUS_ERROR: u16 = 0x0001;
+
+/// Event type bit of the LE Packet Report Event.
+const PACKET_REPORT: u16 = 0x8000;
+
+/// Builds a successful LE Test Status Event carrying `value` in its response field.
+fn response(value: u16) -> u16 {
+    (value << 1) & 0x7FFE
+}
+
+/// Returns the air time of a data channel packet with `payload_len` Bytes of payload (including a
+/// MIC), as used in the "maximum time" setup responses.
+fn packet_time_us(payload_len: usize) -> u16 {
+    ((payload_len + 14) * 8) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Radio {
+        tx: Option<(u8, usize, u32)>,
+        rx: Option<u8>,
+    }
+
+    impl DtmRadio for Radio {
+        fn start_transmitter_test(&mut self, rf_channel: u8, packet: &[u8], interval: Duration) {
+            self.tx = Some((rf_channel, packet.len(), interval.as_micros()));
+        }
+
+        fn start_receiver_test(&mut self, rf_channel: u8) {
+            self.rx = Some(rf_channel);
+        }
+
+        fn stop_test(&mut self) {
+            self.tx = None;
+            self.rx = None;
+        }
+    }
+
+    #[test]
+    fn prbs9() {
+        let mut buf = [0; 4];
+        PayloadPattern::Prbs9.fill(&mut buf);
+        assert_eq!(buf, [0xFF, 0xC1, 0xFB, 0xE8]);
+    }
+
+    #[test]
+    fn commands() {
+        let mut dtm = Dtm::new(Radio::default());
+
+        // Transmitter test on channel 19, 37 Bytes of PRBS9
+        assert_eq!(dtm.process_uart_byte(0x93), None);
+        assert_eq!(dtm.process_uart_byte(37 << 2), Some([0, 0]));
+        assert_eq!(dtm.radio().tx, Some((19, 39, 625)));
+        assert_eq!(dtm.process_command(0xC000), PACKET_REPORT);
+        assert_eq!(dtm.radio().tx, None);
+
+        // Receiver test on channel 0
+        assert_eq!(dtm.process_command(0x4000), STATUS_SUCCESS);
+        assert_eq!(dtm.process_command(0x4000), STATUS_ERROR);
+        dtm.packet_received();
+        dtm.packet_received();
+        assert_eq!(dtm.process_command(0xC000), PACKET_REPORT | 2);
+
+        // Unsupported PHY
+        assert_eq!(dtm.process_command(0x0208), STATUS_ERROR);
+    }
+}
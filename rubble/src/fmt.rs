@@ -0,0 +1,465 @@
+//! Human-readable breakdowns of raw PDUs for debug logs.
+//!
+//! The `Debug` output of the packet types is exact, but hard to follow when a connection is logged
+//! as a stream of hex dumps. The wrappers in this module decode a raw PDU and print it the way a
+//! protocol analyzer would, with field names, durations, handle ranges and the names of well-known
+//! UUIDs:
+//!
+//! ```notrust
+//! ADV_IND AdvA=c0:ff:ee:c0:ff:ee(Random) [Flags(...), CompleteLocalName("Rubble")]
+//! LL_CONNECTION_UPDATE_REQ interval=30ms latency=0 timeout=4s instant=123 ...
+//! ATT ReadByGroupReq handles=0x0001..=0xFFFF type=Primary Service (0x2800)
+//! ```
+//!
+//! Malformed PDUs are printed as hex, together with the decoding error. With the `defmt` feature,
+//! the wrappers also implement `defmt::Format`.
+//!
+//! This module is only available when the `fmt` feature is enabled.
+
+use {
+    crate::{
+        att::{AttPdu, AttUuid, Handle},
+        bytes::{ByteReader, FromBytes},
+        link::{
+            advertising::{Pdu, PduType},
+            llcp::{ControlOpcode, ControlPdu},
+        },
+        utils::HexSlice,
+        uuid::Uuid16,
+    },
+    core::fmt,
+};
+
+/// Formats an advertising channel PDU, including its 2-Byte header.
+#[derive(Copy, Clone)]
+pub struct Advertising<'a>(pub &'a [u8]);
+
+/// Formats an LL Control PDU (the payload of a data channel PDU with LLID `Control`).
+#[derive(Copy, Clone)]
+pub struct Control<'a>(pub &'a [u8]);
+
+/// Formats an ATT PDU (the payload of an L2CAP message on channel `0x0004`).
+#[derive(Copy, Clone)]
+pub struct Att<'a>(pub &'a [u8]);
+
+impl fmt::Display for Advertising<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pdu = match Pdu::from_bytes(&mut ByteReader::new(self.0)) {
+            Ok(pdu) => pdu,
+            Err(e) => {
+                return write!(
+                    f,
+                    "malformed advertising PDU ({}): {:?}",
+                    e,
+                    HexSlice(self.0)
+                )
+            }
+        };
+
+        f.write_str(adv_name(pdu.ty()))?;
+        match &pdu {
+            Pdu::ScanRequest {
+                scanner_addr,
+                advertiser_addr,
+            } => write!(f, " ScanA={:?} AdvA={:?}", scanner_addr, advertiser_addr)?,
+            Pdu::ConnectRequest {
+                initiator_addr,
+                advertiser_addr,
+                lldata,
+            } => {
+                write!(f, " InitA={:?} AdvA={:?}", initiator_addr, advertiser_addr)?;
+                write!(
+                    f,
+                    " AA={:#010x} CRCInit={:#08x} interval={} latency={} timeout={} hop={} \
+                     SCA={:?} channels={}",
+                    lldata.access_address(),
+                    lldata.crc_init(),
+                    lldata.interval(),
+                    lldata.slave_latency(),
+                    lldata.supervision_timeout(),
+                    lldata.hop(),
+                    lldata.sleep_clock_accuracy(),
+                    lldata.channel_map(),
+                )?;
+            }
+            Pdu::ConnectableDirected {
+                advertiser_addr,
+                initiator_addr,
+            } => write!(f, " AdvA={:?} InitA={:?}", advertiser_addr, initiator_addr)?,
+            _ => write!(f, " AdvA={:?}", pdu.sender())?,
+        }
+
+        if let Some(ads) = pdu.advertising_data() {
+            f.write_str(" [")?;
+            for (i, ad) in ads.enumerate() {
+                if i != 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "{:?}", ad)?;
+            }
+            f.write_str("]")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Control<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pdu = match ControlPdu::from_bytes(&mut ByteReader::new(self.0)) {
+            Ok(pdu) => pdu,
+            Err(e) => {
+                return write!(
+                    f,
+                    "malformed LL Control PDU ({}): {:?}",
+                    e,
+                    HexSlice(self.0)
+                )
+            }
+        };
+
+        f.write_str(ll_name(pdu.opcode()))?;
+        match pdu {
+            ControlPdu::ConnectionUpdateReq(data) => write!(
+                f,
+                " interval={} latency={} timeout={} instant={} win_offset={} win_size={}",
+                data.interval(),
+                data.latency(),
+                data.timeout(),
+                data.instant(),
+                data.win_offset(),
+                data.win_size(),
+            ),
+            ControlPdu::ChannelMapReq { map, instant } => {
+                write!(f, " channels={} instant={}", map, instant)
+            }
+            ControlPdu::TerminateInd { error_code } => write!(f, " reason={:?}", error_code),
+            ControlPdu::UnknownRsp { unknown_type } => write!(f, " {}", ll_name(unknown_type)),
+            ControlPdu::FeatureReq { features_master } => write!(f, " {:?}", features_master),
+            ControlPdu::FeatureRsp { features_used } => write!(f, " {:?}", features_used),
+            ControlPdu::VersionInd {
+                vers_nr,
+                comp_id,
+                sub_vers_nr,
+            } => write!(f, " {:?} {:?} sub={:?}", vers_nr, comp_id, sub_vers_nr),
+            ControlPdu::ConnectionParamReq(params) | ControlPdu::ConnectionParamRsp(params) => {
+                write!(
+                    f,
+                    " interval={}..={} latency={} timeout={}",
+                    params.min_conn_interval(),
+                    params.max_conn_interval(),
+                    params.slave_latency(),
+                    params.supervision_timeout(),
+                )
+            }
+            ControlPdu::RejectExtInd {
+                reject_opcode,
+                error_code,
+            } => write!(f, " {} reason={:?}", ll_name(reject_opcode), error_code),
+            ControlPdu::Unknown { ctr_data, .. } => write!(f, " {:?}", HexSlice(ctr_data)),
+            other => write!(f, " {:?}", other),
+        }
+    }
+}
+
+impl fmt::Display for Att<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pdu = match AttPdu::from_bytes(&mut ByteReader::new(self.0)) {
+            Ok(pdu) => pdu,
+            Err(e) => return write!(f, "malformed ATT PDU ({}): {:?}", e, HexSlice(self.0)),
+        };
+
+        write!(f, "ATT {:?}", pdu.opcode())?;
+        match pdu {
+            AttPdu::ErrorRsp {
+                opcode,
+                handle,
+                error_code,
+            } => write!(f, " {:?} on {:?} failed: {:?}", opcode, handle, error_code),
+            AttPdu::ExchangeMtuReq { mtu } | AttPdu::ExchangeMtuRsp { mtu } => {
+                write!(f, " mtu={}", mtu)
+            }
+            AttPdu::FindInformationReq { handle_range } => write!(f, " handles={}", handle_range),
+            AttPdu::FindInformationRsp { format, data } => {
+                let uuid_len = if format == 0x01 { 2 } else { 16 };
+                for entry in data.0.chunks(2 + uuid_len) {
+                    entry_uuid(f, entry)?;
+                }
+                Ok(())
+            }
+            AttPdu::FindByTypeValueReq {
+                handle_range,
+                attribute_type,
+                attribute_value,
+            } => write!(
+                f,
+                " handles={} type={} value={:?}",
+                handle_range,
+                Type(Uuid16(attribute_type).into()),
+                attribute_value
+            ),
+            AttPdu::FindByTypeValueRsp {
+                handles_information_list,
+            } => {
+                for entry in handles_information_list.0.chunks(4) {
+                    if entry.len() == 4 {
+                        write!(f, " {:?}..={:?}", handle(&entry[..2]), handle(&entry[2..]))?;
+                    }
+                }
+                Ok(())
+            }
+            AttPdu::ReadByTypeReq {
+                handle_range,
+                attribute_type,
+            } => write!(f, " handles={} type={}", handle_range, Type(attribute_type)),
+            AttPdu::ReadByGroupReq {
+                handle_range,
+                group_type,
+            } => write!(f, " handles={} type={}", handle_range, Type(group_type)),
+            AttPdu::ReadByTypeRsp { length, data_list } => {
+                for entry in data_list.0.chunks(usize::from(length.max(2))) {
+                    write!(
+                        f,
+                        " {:?}={:?}",
+                        handle(entry),
+                        HexSlice(entry.get(2..).unwrap_or(&[]))
+                    )?;
+                }
+                Ok(())
+            }
+            AttPdu::ReadByGroupRsp { length, data_list } => {
+                for entry in data_list.0.chunks(usize::from(length.max(4))) {
+                    if entry.len() >= 4 {
+                        write!(
+                            f,
+                            " {:?}..={:?} {}",
+                            handle(&entry[..2]),
+                            handle(&entry[2..4]),
+                            Value(&entry[4..])
+                        )?;
+                    }
+                }
+                Ok(())
+            }
+            AttPdu::ReadReq { handle } => write!(f, " handle={:?}", handle),
+            AttPdu::ReadBlobReq { handle, offset } => {
+                write!(f, " handle={:?} offset={}", handle, offset)
+            }
+            AttPdu::ReadRsp { value } | AttPdu::ReadBlobRsp { value } => {
+                write!(f, " value={:?}", value)
+            }
+            AttPdu::ReadMultipleReq { handles } => {
+                for h in handles.0.chunks(2) {
+                    write!(f, " {:?}", handle(h))?;
+                }
+                Ok(())
+            }
+            AttPdu::ReadMultipleRsp { values } => write!(f, " values={:?}", values),
+            AttPdu::WriteReq { handle, value }
+            | AttPdu::WriteCommand { handle, value }
+            | AttPdu::SignedWriteCommand { handle, value, .. }
+            | AttPdu::HandleValueNotification { handle, value }
+            | AttPdu::HandleValueIndication { handle, value } => {
+                write!(f, " handle={:?} value={:?}", handle, value)
+            }
+            AttPdu::PrepareWriteReq {
+                handle,
+                offset,
+                value,
+            }
+            | AttPdu::PrepareWriteRsp {
+                handle,
+                offset,
+                value,
+            } => write!(
+                f,
+                " handle={:?} offset={} value={:?}",
+                handle, offset, value
+            ),
+            AttPdu::ExecuteWriteReq { flags } => {
+                f.write_str(if flags == 0 { " cancel" } else { " write" })
+            }
+            AttPdu::Unknown { params, .. } => write!(f, " {:?}", params),
+            AttPdu::WriteRsp | AttPdu::ExecuteWriteRsp | AttPdu::HandleValueConfirmation => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Advertising<'_> {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(f, "{}", defmt::Display2Format(self))
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Control<'_> {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(f, "{}", defmt::Display2Format(self))
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Att<'_> {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(f, "{}", defmt::Display2Format(self))
+    }
+}
+
+/// Formats an attribute type, adding the name of well-known 16-bit UUIDs.
+struct Type(AttUuid);
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            AttUuid::Uuid16(uuid) => match uuid_name(uuid) {
+                Some(name) => write!(f, "{} ({:#06X})", name, uuid.0),
+                None => write!(f, "{:#06X}", uuid.0),
+            },
+            AttUuid::Uuid128(uuid) => {
+                // Stored in the Byte order used on air, printed in the usual big-endian notation
+                for (i, b) in uuid.as_bytes().iter().rev().enumerate() {
+                    if i == 4 || i == 6 || i == 8 || i == 10 {
+                        f.write_str("-")?;
+                    }
+                    write!(f, "{:02X}", b)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Formats a grouping attribute value, which is a service UUID.
+struct Value<'a>(&'a [u8]);
+
+impl fmt::Display for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match AttUuid::from_bytes(&mut ByteReader::new(self.0)) {
+            Ok(uuid) => Type(uuid).fmt(f),
+            Err(_) => write!(f, "{:?}", HexSlice(self.0)),
+        }
+    }
+}
+
+/// Formats a `FindInformationRsp` entry consisting of a handle and an attribute type.
+fn entry_uuid(f: &mut fmt::Formatter<'_>, entry: &[u8]) -> fmt::Result {
+    if entry.len() < 2 {
+        return write!(f, " {:?}", HexSlice(entry));
+    }
+    write!(f, " {:?}={}", handle(entry), Value(&entry[2..]))
+}
+
+/// Decodes a little-endian handle from the start of `raw`, which may be truncated.
+fn handle(raw: &[u8]) -> Handle {
+    let mut bytes = [0; 2];
+    bytes[..raw.len().min(2)].copy_from_slice(&raw[..raw.len().min(2)]);
+    Handle::from_raw(u16::from_le_bytes(bytes))
+}
+
+/// Returns the name of a well-known 16-bit UUID used in GATT discovery.
+fn uuid_name(uuid: Uuid16) -> Option<&'static str> {
+    Some(match uuid.0 {
+        0x1800 => "Generic Access",
+        0x1801 => "Generic Attribute",
+        0x180A => "Device Information",
+        0x180F => "Battery Service",
+        0x2800 => "Primary Service",
+        0x2801 => "Secondary Service",
+        0x2802 => "Include",
+        0x2803 => "Characteristic",
+        0x2900 => "Characteristic Extended Properties",
+        0x2901 => "Characteristic User Description",
+        0x2902 => "Client Characteristic Configuration",
+        0x2903 => "Server Characteristic Configuration",
+        0x2904 => "Characteristic Presentation Format",
+        0x2A00 => "Device Name",
+        0x2A01 => "Appearance",
+        0x2A04 => "Peripheral Preferred Connection Parameters",
+        0x2A05 => "Service Changed",
+        0x2A19 => "Battery Level",
+        _ => return None,
+    })
+}
+
+fn adv_name(ty: PduType) -> &'static str {
+    match ty {
+        PduType::AdvInd => "ADV_IND",
+        PduType::AdvDirectInd => "ADV_DIRECT_IND",
+        PduType::AdvNonconnInd => "ADV_NONCONN_IND",
+        PduType::AdvScanInd => "ADV_SCAN_IND",
+        PduType::ScanReq => "SCAN_REQ",
+        PduType::ScanRsp => "SCAN_RSP",
+        PduType::ConnectReq => "CONNECT_REQ",
+        PduType::Unknown(_) => "ADV_UNKNOWN",
+    }
+}
+
+fn ll_name(opcode: ControlOpcode) -> &'static str {
+    use crate::link::llcp::ControlOpcode::*;
+
+    match opcode {
+        ConnectionUpdateReq => "LL_CONNECTION_UPDATE_REQ",
+        ChannelMapReq => "LL_CHANNEL_MAP_REQ",
+        TerminateInd => "LL_TERMINATE_IND",
+        EncReq => "LL_ENC_REQ",
+        EncRsp => "LL_ENC_RSP",
+        StartEncReq => "LL_START_ENC_REQ",
+        StartEncRsp => "LL_START_ENC_RSP",
+        UnknownRsp => "LL_UNKNOWN_RSP",
+        FeatureReq => "LL_FEATURE_REQ",
+        FeatureRsp => "LL_FEATURE_RSP",
+        PauseEncReq => "LL_PAUSE_ENC_REQ",
+        PauseEncRsp => "LL_PAUSE_ENC_RSP",
+        VersionInd => "LL_VERSION_IND",
+        RejectInd => "LL_REJECT_IND",
+        SlaveFeatureReq => "LL_SLAVE_FEATURE_REQ",
+        ConnectionParamReq => "LL_CONNECTION_PARAM_REQ",
+        ConnectionParamRsp => "LL_CONNECTION_PARAM_RSP",
+        RejectIndExt => "LL_REJECT_EXT_IND",
+        PingReq => "LL_PING_REQ",
+        PingRsp => "LL_PING_RSP",
+        LengthReq => "LL_LENGTH_REQ",
+        LengthRsp => "LL_LENGTH_RSP",
+        PhyReq => "LL_PHY_REQ",
+        PhyRsp => "LL_PHY_RSP",
+        PhyUpdateInd => "LL_PHY_UPDATE_IND",
+        MinUsedChannelsInd => "LL_MIN_USED_CHANNELS_IND",
+        CteReq => "LL_CTE_REQ",
+        CteRsp => "LL_CTE_RSP",
+        PeriodicSyncInd => "LL_PERIODIC_SYNC_IND",
+        ClockAccuracyReq => "LL_CLOCK_ACCURACY_REQ",
+        ClockAccuracyRsp => "LL_CLOCK_ACCURACY_RSP",
+        CisReq => "LL_CIS_REQ",
+        CisRsp => "LL_CIS_RSP",
+        CisInd => "LL_CIS_IND",
+        CisTerminateInd => "LL_CIS_TERMINATE_IND",
+        PowerControlReq => "LL_POWER_CONTROL_REQ",
+        PowerControlRsp => "LL_POWER_CONTROL_RSP",
+        PowerChangeInd => "LL_POWER_CHANGE_IND",
+        SubrateReq => "LL_SUBRATE_REQ",
+        SubrateInd => "LL_SUBRATE_IND",
+        Unknown(_) => "LL_UNKNOWN",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn att() {
+        let req = [0x10, 0x01, 0x00, 0xFF, 0xFF, 0x00, 0x28];
+        assert_eq!(
+            Att(&req).to_string(),
+            "ATT ReadByGroupReq handles=0x0001..=0xFFFF type=Primary Service (0x2800)"
+        );
+        let rsp = [0x01, 0x0A, 0x03, 0x00, 0x0F];
+        assert_eq!(
+            Att(&rsp).to_string(),
+            "ATT ErrorRsp ReadReq on 0x0003 failed: InsufficientEncryption"
+        );
+        assert!(Att(&[0x0A, 0x01])
+            .to_string()
+            .starts_with("malformed ATT PDU"));
+    }
+}
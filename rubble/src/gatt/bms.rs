@@ -0,0 +1,269 @@
+//! *Bond Management Service* (BMS).
+//!
+//! BMS gives users a standard way of clearing the bonds of a device from a connected phone or PC,
+//! instead of requiring a reset button or vendor-specific characteristic. Clients write an
+//! operation to the *Bond Management Control Point*, and the operations the device supports are
+//! advertised in the *Bond Management Feature* characteristic.
+//!
+//! `BondManagementService` adds both characteristics to an `AttributeTable`. The application's
+//! `AttributeProvider` passes writes to it via `BondManagementService::write_attr`, which checks
+//! the requested operation. Since the provider has no access to the `BondStore`, the operation is
+//! then carried out by `BondManagementService::execute`, which the application calls from its idle
+//! loop (or when the connection was closed, so the requesting device doesn't lose its keys while
+//! still connected).
+//!
+//! The spec requires the control point to only be writable over an encrypted link. The
+//! application's `AttributeProvider::required_security` has to enforce that for
+//! `BondManagementService::control_point`. Authorization codes are not supported.
+
+use {
+    crate::{
+        att::{AttError, ErrorCode, Handle},
+        bond::BondStore,
+        gatt::{
+            characteristic::Properties,
+            table::{AttributeTable, TableEntry},
+        },
+        link::DeviceAddress,
+        uuid::Uuid16,
+        Error,
+    },
+    bitflags::bitflags,
+    heapless::ArrayLength,
+};
+
+/// UUID of the *Bond Management Service*.
+pub const BOND_MANAGEMENT_SERVICE: Uuid16 = Uuid16(0x181E);
+
+/// UUID of the *Bond Management Control Point* characteristic.
+pub const CONTROL_POINT: Uuid16 = Uuid16(0x2AA4);
+
+/// UUID of the *Bond Management Feature* characteristic.
+pub const FEATURE: Uuid16 = Uuid16(0x2AA5);
+
+/// ATT error returned for unsupported operations.
+const OP_CODE_NOT_SUPPORTED: u8 = 0x80;
+
+bitflags! {
+    /// Operations supported by the device, as advertised in the *Bond Management Feature*.
+    ///
+    /// Only the LE variants of the operations are supported.
+    pub struct BondManagementFeatures: u32 {
+        const DELETE_REQUESTING = 1 << 4;
+        const DELETE_ALL = 1 << 10;
+        const DELETE_ALL_BUT_REQUESTING = 1 << 16;
+    }
+}
+
+/// An operation requested via the control point.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BondOperation {
+    /// Delete the bond of the device that requested the operation.
+    DeleteRequesting,
+    /// Delete all bonds.
+    DeleteAll,
+    /// Delete all bonds except the one of the requesting device.
+    DeleteAllButRequesting,
+}
+
+impl BondOperation {
+    fn from_opcode(opcode: u8) -> Option<Self> {
+        match opcode {
+            0x03 => Some(BondOperation::DeleteRequesting),
+            0x06 => Some(BondOperation::DeleteAll),
+            0x09 => Some(BondOperation::DeleteAllButRequesting),
+            _ => None,
+        }
+    }
+
+    fn feature(&self) -> BondManagementFeatures {
+        match self {
+            BondOperation::DeleteRequesting => BondManagementFeatures::DELETE_REQUESTING,
+            BondOperation::DeleteAll => BondManagementFeatures::DELETE_ALL,
+            BondOperation::DeleteAllButRequesting => {
+                BondManagementFeatures::DELETE_ALL_BUT_REQUESTING
+            }
+        }
+    }
+}
+
+/// The server side of BMS, hosted in an `AttributeTable`.
+#[derive(Debug)]
+pub struct BondManagementService {
+    features: BondManagementFeatures,
+    control_point: Handle,
+    pending: Option<BondOperation>,
+}
+
+impl BondManagementService {
+    /// Adds the service to `table`, supporting the operations in `features`.
+    pub fn add_to<N, B>(
+        table: &mut AttributeTable<N, B>,
+        features: BondManagementFeatures,
+    ) -> Result<Self, Error>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        table.add_service(BOND_MANAGEMENT_SERVICE)?;
+        // The value is never stored, the control point is handled by `write_attr`
+        let control_point = table.add_characteristic(CONTROL_POINT, Properties::WRITE, &[])?;
+        let bits = features.bits().to_le_bytes();
+        table.add_characteristic(FEATURE, Properties::READ, &bits[..3])?;
+        Ok(Self {
+            features,
+            control_point,
+            pending: None,
+        })
+    }
+
+    /// Returns the handle of the control point value.
+    pub fn control_point(&self) -> Handle {
+        self.control_point
+    }
+
+    /// Returns the requested operation that hasn't been executed yet.
+    pub fn pending(&self) -> Option<BondOperation> {
+        self.pending
+    }
+
+    /// Handles a write to the attribute at `handle`.
+    ///
+    /// Returns `None` if `handle` doesn't belong to the service, in which case the write should be
+    /// processed as usual. Unsupported operations are rejected with the *Op Code Not Supported*
+    /// error defined by BMS.
+    pub fn write_attr(&mut self, handle: Handle, value: &[u8]) -> Option<Result<(), AttError>> {
+        if handle != self.control_point {
+            return None;
+        }
+
+        let op = value.first().and_then(|op| BondOperation::from_opcode(*op));
+        Some(match op {
+            Some(op) if self.features.contains(op.feature()) => {
+                self.pending = Some(op);
+                Ok(())
+            }
+            _ => Err(AttError::new(
+                ErrorCode::from(OP_CODE_NOT_SUPPORTED),
+                handle,
+            )),
+        })
+    }
+
+    /// Carries out the pending operation on `store`.
+    ///
+    /// `requesting` is the identity address of the device that requested the operation. Returns
+    /// the executed operation, if there was one.
+    pub fn execute<S: BondStore>(
+        &mut self,
+        store: &mut S,
+        requesting: &DeviceAddress,
+    ) -> Result<Option<BondOperation>, S::Error> {
+        let op = match self.pending {
+            Some(op) => op,
+            None => return Ok(None),
+        };
+
+        match op {
+            BondOperation::DeleteRequesting => store.remove(requesting)?,
+            BondOperation::DeleteAll => remove_all_except(store, None)?,
+            BondOperation::DeleteAllButRequesting => remove_all_except(store, Some(requesting))?,
+        }
+        self.pending = None;
+        Ok(Some(op))
+    }
+}
+
+/// Removes all bonds from `store`, except for the one with `keep`.
+fn remove_all_except<S: BondStore>(
+    store: &mut S,
+    keep: Option<&DeviceAddress>,
+) -> Result<(), S::Error> {
+    // Bonds can't be removed while iterating, so remove them one at a time
+    loop {
+        let mut victim = None;
+        store.for_each(&mut |bond| {
+            if victim.is_none() && Some(&bond.address) != keep {
+                victim = Some(bond.address);
+            }
+        })?;
+
+        match victim {
+            Some(address) => store.remove(&address)?,
+            None => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{bond::Bond, link::AddressKind},
+        heapless::consts::*,
+    };
+
+    struct RamStore([Option<Bond>; 3]);
+
+    impl BondStore for RamStore {
+        type Error = ();
+
+        fn load(&mut self, address: &DeviceAddress) -> Result<Option<Bond>, ()> {
+            Ok(self
+                .0
+                .iter()
+                .flatten()
+                .find(|b| b.address == *address)
+                .copied())
+        }
+
+        fn store(&mut self, bond: &Bond) -> Result<(), ()> {
+            let slot = self.0.iter_mut().find(|b| b.is_none()).ok_or(())?;
+            *slot = Some(*bond);
+            Ok(())
+        }
+
+        fn remove(&mut self, address: &DeviceAddress) -> Result<(), ()> {
+            for slot in &mut self.0 {
+                if slot.is_some_and(|b| b.address == *address) {
+                    *slot = None;
+                }
+            }
+            Ok(())
+        }
+
+        fn for_each(&mut self, f: &mut dyn FnMut(&Bond)) -> Result<(), ()> {
+            self.0.iter().flatten().for_each(f);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn delete_all_but_requesting() {
+        let addr = |b| DeviceAddress::new([b; 6], AddressKind::Public);
+        let mut store = RamStore([None; 3]);
+        for b in 1..=3 {
+            store.store(&Bond::new(addr(b), [b; 16])).unwrap();
+        }
+
+        let mut table = AttributeTable::<U8, U32>::new();
+        let mut bms = BondManagementService::add_to(
+            &mut table,
+            BondManagementFeatures::DELETE_ALL_BUT_REQUESTING,
+        )
+        .unwrap();
+        let cp = bms.control_point();
+        assert!(bms.write_attr(cp, &[0x06]).unwrap().is_err());
+        assert!(bms.write_attr(cp, &[0x09]).unwrap().is_ok());
+        assert!(bms.write_attr(Handle::from_raw(0x99), &[0x09]).is_none());
+
+        assert_eq!(
+            bms.execute(&mut store, &addr(2)),
+            Ok(Some(BondOperation::DeleteAllButRequesting))
+        );
+        assert!(store.load(&addr(1)).unwrap().is_none());
+        assert!(store.load(&addr(2)).unwrap().is_some());
+        assert!(store.load(&addr(3)).unwrap().is_none());
+        assert_eq!(bms.execute(&mut store, &addr(2)), Ok(None));
+    }
+}
@@ -0,0 +1,339 @@
+//! Retaining the GATT server state of bonded clients across connections.
+//!
+//! GATT requires a server to remember the *Client Characteristic Configuration* of every bonded
+//! client: A client that subscribed to a characteristic stays subscribed after reconnecting, and
+//! doesn't subscribe again. The server must also tell bonded clients when its database has
+//! changed, using an indication of the *Service Changed* characteristic. If the client isn't
+//! connected when that happens (eg. because of a firmware update), it is indicated as soon as it
+//! reconnects.
+//!
+//! `BondedClient` adds the *Generic Attribute* service with the *Service Changed* characteristic
+//! to an `AttributeTable`, and keeps the CCCD values and the last seen database layout of the
+//! connected client in its `Bond`:
+//!
+//! * When a new bond is created during pairing, pass it to `BondedClient::bonded`.
+//! * When a bonded client reconnects and has been identified, `BondedClient::restore` loads its
+//!   bond and writes the stored CCCD values back into the table. If the layout of the table
+//!   differs from the one the client has seen (see `gatt::handles::layout_hash`), a *Service
+//!   Changed* indication is queued, and the stored CCCD values are dropped, since the handles
+//!   they refer to might have moved.
+//! * The application's `AttributeProvider` forwards successful writes to
+//!   `BondedClient::written`, which records CCCD changes.
+//! * From the idle loop, the application sends the indication returned by
+//!   `BondedClient::pending_indication` (using `AttributeServerTx::indicate_raw`) and reports
+//!   that with `BondedClient::indication_sent`. `BondedClient::save` writes changes back to the
+//!   `BondStore`.
+
+use {
+    crate::{
+        att::{AttributeProvider, Handle, HandleRange},
+        bond::{Bond, BondStore},
+        gatt::{
+            characteristic::Properties,
+            handles::{layout_hash, needs_service_changed},
+            table::{cccd_value, AttributeTable, TableEntry, CCCD, CCCD_INDICATE},
+        },
+        link::DeviceAddress,
+        uuid::Uuid16,
+        Error,
+    },
+    heapless::ArrayLength,
+};
+
+/// UUID of the *Generic Attribute* service.
+pub const GENERIC_ATTRIBUTE_SERVICE: Uuid16 = Uuid16(0x1801);
+
+/// UUID of the *Service Changed* characteristic.
+pub const SERVICE_CHANGED: Uuid16 = Uuid16(0x2A05);
+
+/// Server state of the connected client, if it's bonded.
+pub struct BondedClient {
+    service_changed: Handle,
+    bond: Option<Bond>,
+    /// Whether `bond` has changes that haven't been saved yet.
+    dirty: bool,
+    /// Whether a *Service Changed* indication has to be sent.
+    indicate: bool,
+    /// Layout hash of the table the client is connected to.
+    layout: u16,
+}
+
+impl BondedClient {
+    /// Adds the *Generic Attribute* service to `table`.
+    ///
+    /// The service should be added right at the start of the table, so that it doesn't move when
+    /// other services change.
+    pub fn add_to<N, B>(table: &mut AttributeTable<N, B>) -> Result<Self, Error>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        table.add_service(GENERIC_ATTRIBUTE_SERVICE)?;
+        // The affected handle range is only sent in indications, it can't be read
+        let service_changed =
+            table.add_characteristic(SERVICE_CHANGED, Properties::INDICATE, &[])?;
+        Ok(Self {
+            service_changed,
+            bond: None,
+            dirty: false,
+            indicate: false,
+            layout: 0,
+        })
+    }
+
+    /// Returns the handle of the *Service Changed* value.
+    pub fn service_changed(&self) -> Handle {
+        self.service_changed
+    }
+
+    /// Returns the identity address of the connected bonded client, if there is one.
+    pub fn peer(&self) -> Option<DeviceAddress> {
+        self.bond.as_ref().map(|bond| bond.address)
+    }
+
+    /// Returns the handle and value of the *Service Changed* indication to send, if one is
+    /// pending.
+    ///
+    /// The indication covers the whole database. Once it was sent, call `indication_sent`.
+    pub fn pending_indication(&self) -> Option<(Handle, [u8; 4])> {
+        if self.indicate {
+            Some((self.service_changed, [0x01, 0x00, 0xFF, 0xFF]))
+        } else {
+            None
+        }
+    }
+
+    /// Marks the pending *Service Changed* indication as sent.
+    ///
+    /// The client now knows that it has to rediscover the database, so its bond is updated to the
+    /// current layout.
+    pub fn indication_sent(&mut self) {
+        if !self.indicate {
+            return;
+        }
+        self.indicate = false;
+        if let Some(bond) = &mut self.bond {
+            bond.layout_hash = Some(self.layout);
+            self.dirty = true;
+        }
+    }
+
+    /// Starts tracking the state of a newly bonded client.
+    ///
+    /// The client has just discovered the database (or can do so now), so the current layout and
+    /// CCCD values of `table` are recorded in `bond`. The bond is stored by the next `save`.
+    pub fn bonded<N, B>(&mut self, mut bond: Bond, table: &mut AttributeTable<N, B>)
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        self.layout = layout_hash(table);
+        bond.layout_hash = Some(self.layout);
+        let range = HandleRange::new(Handle::from_raw(0x0001), Handle::from_raw(0xFFFF));
+        table
+            .for_attrs_in_range(range, |_, attr| {
+                if attr.att_type == CCCD {
+                    if let Some(value) = cccd_value(attr.value.0) {
+                        if value != 0 && !bond.set_cccd(attr.handle, value) {
+                            debug!("no space for CCCD {:?} in bond", attr.handle);
+                        }
+                    }
+                }
+                Ok(())
+            })
+            .ok();
+        self.bond = Some(bond);
+        self.dirty = true;
+        self.indicate = false;
+    }
+
+    /// Restores the state of the bonded client with identity address `identity`, which has just
+    /// reconnected.
+    ///
+    /// Returns `false` if there's no bond with the client.
+    pub fn restore<S: BondStore, N, B>(
+        &mut self,
+        store: &mut S,
+        identity: &DeviceAddress,
+        table: &mut AttributeTable<N, B>,
+    ) -> Result<bool, S::Error>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        self.disconnected();
+        let mut bond = match store.load(identity)? {
+            Some(bond) => bond,
+            None => return Ok(false),
+        };
+
+        let current = layout_hash(table);
+        self.layout = current;
+        if needs_service_changed(bond.layout_hash, current) {
+            // Only the Service Changed CCCD is guaranteed to stay where it was
+            let cccd = table.cccd_handle(self.service_changed);
+            let sc_config = cccd.and_then(|cccd| bond.cccd(cccd)).unwrap_or(0);
+            bond.cccds = Default::default();
+            if let (Some(cccd), true) = (cccd, sc_config != 0) {
+                bond.set_cccd(cccd, sc_config);
+            }
+            self.indicate = sc_config & CCCD_INDICATE != 0;
+            if !self.indicate {
+                // The client doesn't want to be told, it will rediscover the database instead
+                bond.layout_hash = Some(current);
+            }
+            self.dirty = true;
+        }
+
+        for (handle, value) in bond.cccds.iter().flatten() {
+            if is_cccd(table, *handle) {
+                table.set_value(*handle, &value.to_le_bytes()).ok();
+            }
+        }
+        self.bond = Some(bond);
+        Ok(true)
+    }
+
+    /// Records a successful write of `value` to the attribute at `handle` by the client.
+    ///
+    /// Writes to attributes other than CCCDs, and writes by clients that aren't bonded, are
+    /// ignored.
+    pub fn written<N, B>(&mut self, table: &mut AttributeTable<N, B>, handle: Handle, value: &[u8])
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        let bond = match &mut self.bond {
+            Some(bond) => bond,
+            None => return,
+        };
+        if !is_cccd(table, handle) {
+            return;
+        }
+
+        if let Some(value) = cccd_value(value) {
+            if bond.cccd(handle).unwrap_or(0) != value {
+                if !bond.set_cccd(handle, value) {
+                    debug!("no space for CCCD {:?} in bond", handle);
+                }
+                self.dirty = true;
+            }
+        }
+    }
+
+    /// Writes the changed state of the client to `store`.
+    ///
+    /// Returns whether anything had to be written.
+    pub fn save<S: BondStore>(&mut self, store: &mut S) -> Result<bool, S::Error> {
+        match &self.bond {
+            Some(bond) if self.dirty => {
+                store.store(bond)?;
+                self.dirty = false;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Forgets the connected client.
+    ///
+    /// Call `save` before this, or unsaved changes are lost.
+    pub fn disconnected(&mut self) {
+        self.bond = None;
+        self.dirty = false;
+        self.indicate = false;
+    }
+}
+
+fn is_cccd<A: AttributeProvider>(attrs: &mut A, handle: Handle) -> bool {
+    let mut found = false;
+    attrs
+        .for_attrs_in_range(HandleRange::new(handle, handle), |_, attr| {
+            found = attr.att_type == CCCD;
+            Ok(())
+        })
+        .ok();
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::link::AddressKind, heapless::consts::*};
+
+    struct Single(Option<Bond>);
+
+    impl BondStore for Single {
+        type Error = ();
+
+        fn load(&mut self, address: &DeviceAddress) -> Result<Option<Bond>, ()> {
+            Ok(self.0.filter(|b| b.address == *address))
+        }
+
+        fn store(&mut self, bond: &Bond) -> Result<(), ()> {
+            self.0 = Some(*bond);
+            Ok(())
+        }
+
+        fn remove(&mut self, _address: &DeviceAddress) -> Result<(), ()> {
+            self.0 = None;
+            Ok(())
+        }
+
+        fn for_each(&mut self, f: &mut dyn FnMut(&Bond)) -> Result<(), ()> {
+            self.0.iter().for_each(f);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn restore_cccds() {
+        let build = |extra: bool| {
+            // Room for both services, including the extra characteristic
+            let mut table = AttributeTable::<U16, U64>::new();
+            let gatt = BondedClient::add_to(&mut table).expect("no space for GATT service");
+            assert_eq!(table.add_service(Uuid16(0x180F)).map(drop), Ok(()));
+            let level = table
+                .add_characteristic(Uuid16(0x2A19), Properties::NOTIFY, &[100])
+                .expect("no space for Battery Level");
+            if extra {
+                assert_eq!(
+                    table
+                        .add_characteristic(Uuid16(0x2A1A), Properties::READ, &[0])
+                        .map(drop),
+                    Ok(())
+                );
+            }
+            let cccd = table.cccd_handle(level).unwrap();
+            (table, gatt, cccd)
+        };
+        let address = DeviceAddress::new([1; 6], AddressKind::Public);
+        let mut store = Single(None);
+
+        let (mut table, mut gatt, cccd) = build(false);
+        gatt.bonded(Bond::new(address, [0; 16]), &mut table);
+        let sc_cccd = table.cccd_handle(gatt.service_changed()).unwrap();
+        for &handle in &[cccd, sc_cccd] {
+            table.write_attr(handle, &[0x02, 0x00]).unwrap();
+            gatt.written(&mut table, handle, &[0x02, 0x00]);
+        }
+        assert!(gatt.save(&mut store).unwrap());
+        assert!(!gatt.save(&mut store).unwrap());
+        gatt.disconnected();
+
+        // Same firmware: the subscription is back, nothing to indicate
+        let (mut table, mut gatt, cccd) = build(false);
+        assert!(gatt.restore(&mut store, &address, &mut table).unwrap());
+        assert_eq!(table.value(cccd), Some(&[0x02, 0x00][..]));
+        assert_eq!(gatt.pending_indication(), None);
+
+        // Changed layout: Service Changed is indicated, other subscriptions are dropped
+        let (mut table, mut gatt, cccd) = build(true);
+        assert!(gatt.restore(&mut store, &address, &mut table).unwrap());
+        assert_eq!(table.value(cccd), Some(&[0x00, 0x00][..]));
+        let (handle, _) = gatt.pending_indication().unwrap();
+        assert_eq!(handle, gatt.service_changed());
+        gatt.indication_sent();
+        assert!(gatt.save(&mut store).unwrap());
+        assert_eq!(store.0.unwrap().layout_hash, Some(layout_hash(&mut table)));
+    }
+}
@@ -0,0 +1,270 @@
+//! Typed access to characteristics on the peer's GATT server.
+//!
+//! The ATT client (see [`att::client`]) only deals with raw attribute values. A
+//! [`Characteristic<T>`] wraps the handles of a characteristic whose value type is known, and
+//! encodes and decodes values via [`CharacteristicValue`], so applications don't have to:
+//!
+//! ```
+//! use rubble::att::{ClientEvent, Handle};
+//! use rubble::gatt::client::Characteristic;
+//!
+//! // Battery Level of the peer, discovered earlier
+//! let battery: Characteristic<u8> = Characteristic::new(Handle::from_raw(0x0012))
+//!     .with_cccd(Handle::from_raw(0x0013));
+//!
+//! // Requests are sent with `battery.read(att)` or `battery.subscribe(att, false)`, where `att`
+//! // is obtained via `L2CAPStateTx::att`. The answer arrives at `AttributeProvider::client_event`:
+//! let event = ClientEvent::Notification {
+//!     handle: Handle::from_raw(0x0012),
+//!     value: &[87],
+//! };
+//! assert_eq!(battery.value(&event), Some(Ok(87)));
+//! ```
+//!
+//! [`att::client`]: ../../att/client/index.html
+//! [`Characteristic<T>`]: struct.Characteristic.html
+//! [`CharacteristicValue`]: trait.CharacteristicValue.html
+
+use {
+    crate::{
+        att::{AttributeProvider, AttributeServerTx, ClientEvent, Handle},
+        bytes::{ByteReader, ByteWriter},
+        Error,
+    },
+    core::marker::PhantomData,
+};
+
+/// Largest value that can be written in a single PDU with the default `ATT_MTU`.
+const MAX_WRITE_VALUE: usize = 20;
+
+/// Types that can be stored in a characteristic value.
+pub trait CharacteristicValue: Sized {
+    /// Decodes a value that was read from, or notified by, the server.
+    ///
+    /// Trailing Bytes should be ignored, since later versions of a characteristic may append
+    /// fields.
+    fn decode(bytes: &mut ByteReader<'_>) -> Result<Self, Error>;
+
+    /// Encodes `self` for writing it to the server.
+    fn encode(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error>;
+}
+
+impl CharacteristicValue for u8 {
+    fn decode(bytes: &mut ByteReader<'_>) -> Result<Self, Error> {
+        bytes.read_u8()
+    }
+
+    fn encode(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u8(*self)
+    }
+}
+
+impl CharacteristicValue for i8 {
+    fn decode(bytes: &mut ByteReader<'_>) -> Result<Self, Error> {
+        Ok(bytes.read_u8()? as i8)
+    }
+
+    fn encode(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u8(*self as u8)
+    }
+}
+
+/// Multi-Byte integers are little-endian, like all GATT values.
+impl CharacteristicValue for u16 {
+    fn decode(bytes: &mut ByteReader<'_>) -> Result<Self, Error> {
+        bytes.read_u16_le()
+    }
+
+    fn encode(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u16_le(*self)
+    }
+}
+
+impl CharacteristicValue for i16 {
+    fn decode(bytes: &mut ByteReader<'_>) -> Result<Self, Error> {
+        Ok(bytes.read_u16_le()? as i16)
+    }
+
+    fn encode(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u16_le(*self as u16)
+    }
+}
+
+impl CharacteristicValue for u32 {
+    fn decode(bytes: &mut ByteReader<'_>) -> Result<Self, Error> {
+        bytes.read_u32_le()
+    }
+
+    fn encode(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u32_le(*self)
+    }
+}
+
+impl CharacteristicValue for i32 {
+    fn decode(bytes: &mut ByteReader<'_>) -> Result<Self, Error> {
+        Ok(bytes.read_u32_le()? as i32)
+    }
+
+    fn encode(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u32_le(*self as u32)
+    }
+}
+
+/// Booleans are a single Byte, and any non-zero value is `true`.
+impl CharacteristicValue for bool {
+    fn decode(bytes: &mut ByteReader<'_>) -> Result<Self, Error> {
+        Ok(bytes.read_u8()? != 0)
+    }
+
+    fn encode(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u8(*self as u8)
+    }
+}
+
+/// A characteristic on the peer's server, holding a value of type `T`.
+#[derive(Debug)]
+pub struct Characteristic<T: CharacteristicValue> {
+    value_handle: Handle,
+    cccd: Option<Handle>,
+    _value: PhantomData<fn() -> T>,
+}
+
+impl<T: CharacteristicValue> Clone for Characteristic<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: CharacteristicValue> Copy for Characteristic<T> {}
+
+impl<T: CharacteristicValue> Characteristic<T> {
+    /// Creates a characteristic whose value attribute is at `value_handle`.
+    pub fn new(value_handle: Handle) -> Self {
+        Self {
+            value_handle,
+            cccd: None,
+            _value: PhantomData,
+        }
+    }
+
+    /// Sets the handle of the characteristic's *Client Characteristic Configuration* descriptor,
+    /// which is needed to subscribe to notifications or indications.
+    pub fn with_cccd(mut self, cccd: Handle) -> Self {
+        self.cccd = Some(cccd);
+        self
+    }
+
+    /// Returns the handle of the value attribute.
+    pub fn value_handle(&self) -> Handle {
+        self.value_handle
+    }
+
+    /// Returns the handle of the *Client Characteristic Configuration* descriptor, if known.
+    pub fn cccd(&self) -> Option<Handle> {
+        self.cccd
+    }
+
+    /// Requests the current value. It is reported as `ClientEvent::Read`.
+    pub fn read<A: AttributeProvider>(&self, att: AttributeServerTx<'_, A>) -> Result<(), Error> {
+        att.read(self.value_handle)
+    }
+
+    /// Writes `value` and waits for the server to acknowledge it with `ClientEvent::Written`.
+    pub fn write<A: AttributeProvider>(
+        &self,
+        att: AttributeServerTx<'_, A>,
+        value: &T,
+    ) -> Result<(), Error> {
+        let mut buf = [0; MAX_WRITE_VALUE];
+        let len = encode(value, &mut buf)?;
+        att.write(self.value_handle, &buf[..len])
+    }
+
+    /// Writes `value` without waiting for an acknowledgement.
+    pub fn write_without_response<A: AttributeProvider>(
+        &self,
+        att: AttributeServerTx<'_, A>,
+        value: &T,
+    ) -> Result<(), Error> {
+        let mut buf = [0; MAX_WRITE_VALUE];
+        let len = encode(value, &mut buf)?;
+        att.write_command(self.value_handle, &buf[..len])
+    }
+
+    /// Subscribes to value updates by writing the CCCD.
+    ///
+    /// With `indications` set, the server sends indications instead of notifications. Returns
+    /// `Error::InvalidValue` if the CCCD handle isn't known.
+    pub fn subscribe<A: AttributeProvider>(
+        &self,
+        att: AttributeServerTx<'_, A>,
+        indications: bool,
+    ) -> Result<(), Error> {
+        let config: u16 = if indications { 0x0002 } else { 0x0001 };
+        self.write_cccd(att, config)
+    }
+
+    /// Turns off notifications and indications by clearing the CCCD.
+    pub fn unsubscribe<A: AttributeProvider>(
+        &self,
+        att: AttributeServerTx<'_, A>,
+    ) -> Result<(), Error> {
+        self.write_cccd(att, 0)
+    }
+
+    /// Decodes the value carried by `event`, if it is a read response, notification or indication
+    /// of this characteristic.
+    pub fn value(&self, event: &ClientEvent<'_>) -> Option<Result<T, Error>> {
+        match *event {
+            ClientEvent::Read { handle, value }
+            | ClientEvent::Notification { handle, value }
+            | ClientEvent::Indication { handle, value }
+                if handle == self.value_handle =>
+            {
+                Some(T::decode(&mut ByteReader::new(value)))
+            }
+            _ => None,
+        }
+    }
+
+    fn write_cccd<A: AttributeProvider>(
+        &self,
+        att: AttributeServerTx<'_, A>,
+        config: u16,
+    ) -> Result<(), Error> {
+        let cccd = self.cccd.ok_or(Error::InvalidValue)?;
+        att.write(cccd, &config.to_le_bytes())
+    }
+}
+
+fn encode<T: CharacteristicValue>(value: &T, buf: &mut [u8]) -> Result<usize, Error> {
+    let mut writer = ByteWriter::new(buf);
+    let space = writer.space_left();
+    value.encode(&mut writer)?;
+    Ok(space - writer.space_left())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode() {
+        let chr: Characteristic<i16> = Characteristic::new(Handle::from_raw(5));
+        let read = ClientEvent::Read {
+            handle: Handle::from_raw(5),
+            value: &[0xFE, 0xFF, 0x42],
+        };
+        assert_eq!(chr.value(&read), Some(Ok(-2)));
+
+        let other = ClientEvent::Notification {
+            handle: Handle::from_raw(6),
+            value: &[1, 0],
+        };
+        assert_eq!(chr.value(&other), None);
+
+        let mut buf = [0; MAX_WRITE_VALUE];
+        assert_eq!(encode(&0x1234u32, &mut buf), Ok(4));
+        assert_eq!(buf[..4], [0x34, 0x12, 0, 0]);
+    }
+}
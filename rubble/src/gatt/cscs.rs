@@ -0,0 +1,449 @@
+//! *Cycling Speed and Cadence Service* (CSCS).
+//!
+//! CSCS is hosted by bicycle sensors that count wheel and/or crank revolutions. The sensor notifies
+//! the cumulative revolution counts together with the time of the last revolution, and the client
+//! (a bike computer or phone) derives speed and cadence from consecutive measurements.
+//!
+//! Like most fitness profiles, CSCS is configured through a *control point*: The client writes a
+//! request to the *SC Control Point* and receives the result as an indication of the same
+//! characteristic. [`CyclingSpeedCadenceServer`] handles the requests it can carry out on its own
+//! (resetting the wheel revolution count and changing the sensor location) and queues the
+//! response, which the application sends with `AttributeServerTx::indicate_raw`:
+//!
+//! ```ignore
+//! if let Some(response) = cscs.pending_response() {
+//!     if att.indicate_raw(cscs.control_point(), response).is_ok() {
+//!         cscs.response_sent();
+//!     }
+//! }
+//! ```
+//!
+//! [`CyclingSpeedCadenceServer`]: struct.CyclingSpeedCadenceServer.html
+
+use {
+    crate::{
+        att::{AttError, ErrorCode, Handle},
+        bytes::{ByteReader, ByteWriter},
+        gatt::{
+            characteristic::Properties,
+            client::CharacteristicValue,
+            table::{AttributeTable, TableEntry},
+        },
+        utils,
+        uuid::Uuid16,
+        Error,
+    },
+    bitflags::bitflags,
+    heapless::{consts::U20, ArrayLength, Vec},
+};
+
+/// UUID of the *Cycling Speed and Cadence* service.
+pub const CYCLING_SPEED_AND_CADENCE: Uuid16 = Uuid16(0x1816);
+
+/// UUID of the *CSC Measurement* characteristic.
+pub const CSC_MEASUREMENT: Uuid16 = Uuid16(0x2A5B);
+
+/// UUID of the *CSC Feature* characteristic.
+pub const CSC_FEATURE: Uuid16 = Uuid16(0x2A5C);
+
+/// UUID of the *Sensor Location* characteristic.
+pub const SENSOR_LOCATION: Uuid16 = Uuid16(0x2A5D);
+
+/// UUID of the *SC Control Point* characteristic.
+pub const SC_CONTROL_POINT: Uuid16 = Uuid16(0x2A55);
+
+/// ATT error returned when a control point request is written while the previous one is still in
+/// progress.
+const PROCEDURE_ALREADY_IN_PROGRESS: u8 = 0x80;
+
+/// ATT error returned when the control point is written without indications being enabled.
+const CCCD_IMPROPERLY_CONFIGURED: u8 = 0x81;
+
+const OP_SET_CUMULATIVE_VALUE: u8 = 0x01;
+const OP_UPDATE_SENSOR_LOCATION: u8 = 0x03;
+const OP_REQUEST_SUPPORTED_LOCATIONS: u8 = 0x04;
+const OP_RESPONSE: u8 = 0x10;
+
+bitflags! {
+    /// Features of the sensor, as advertised in the *CSC Feature* characteristic.
+    pub struct CscFeatures: u16 {
+        const WHEEL_REVOLUTION_DATA = 1 << 0;
+        const CRANK_REVOLUTION_DATA = 1 << 1;
+        const MULTIPLE_SENSOR_LOCATIONS = 1 << 2;
+    }
+}
+
+enum_with_unknown! {
+    /// Where the sensor is mounted.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum SensorLocation(u8) {
+        Other = 0,
+        TopOfShoe = 1,
+        InShoe = 2,
+        Hip = 3,
+        FrontWheel = 4,
+        LeftCrank = 5,
+        RightCrank = 6,
+        LeftPedal = 7,
+        RightPedal = 8,
+        FrontHub = 9,
+        RearDropout = 10,
+        Chainstay = 11,
+        RearWheel = 12,
+        RearHub = 13,
+        Chest = 14,
+        Spider = 15,
+        ChainRing = 16,
+    }
+}
+
+enum_with_unknown! {
+    /// Result of a control point request, sent in the response indication.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum ResponseValue(u8) {
+        Success = 0x01,
+        OpCodeNotSupported = 0x02,
+        InvalidParameter = 0x03,
+        OperationFailed = 0x04,
+    }
+}
+
+/// Value of the *CSC Measurement* characteristic.
+///
+/// Event times are in units of 1/1024 seconds and wrap around.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct CscMeasurement {
+    /// Cumulative wheel revolutions and the time of the last one.
+    pub wheel: Option<(u32, u16)>,
+    /// Cumulative crank revolutions and the time of the last one.
+    pub crank: Option<(u16, u16)>,
+}
+
+impl CscMeasurement {
+    /// Maximum size of the encoded value in Bytes.
+    pub const MAX_SIZE: usize = 11;
+}
+
+impl CharacteristicValue for CscMeasurement {
+    fn decode(bytes: &mut ByteReader<'_>) -> Result<Self, Error> {
+        let flags = bytes.read_u8()?;
+        let wheel = if flags & 0x01 != 0 {
+            Some((bytes.read_u32_le()?, bytes.read_u16_le()?))
+        } else {
+            None
+        };
+        let crank = if flags & 0x02 != 0 {
+            Some((bytes.read_u16_le()?, bytes.read_u16_le()?))
+        } else {
+            None
+        };
+        Ok(Self { wheel, crank })
+    }
+
+    fn encode(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        let flags = self.wheel.map_or(0, |_| 0x01) | self.crank.map_or(0, |_| 0x02);
+        writer.write_u8(flags)?;
+        if let Some((revs, time)) = self.wheel {
+            writer.write_u32_le(revs)?;
+            writer.write_u16_le(time)?;
+        }
+        if let Some((revs, time)) = self.crank {
+            writer.write_u16_le(revs)?;
+            writer.write_u16_le(time)?;
+        }
+        Ok(())
+    }
+}
+
+/// The server side of CSCS, hosted in an `AttributeTable`.
+///
+/// The server keeps the cumulative revolution counts, so that the client can reset the wheel
+/// count through the control point.
+pub struct CyclingSpeedCadenceServer {
+    features: CscFeatures,
+    measurement: CscMeasurement,
+    measurement_handle: Handle,
+    location: Option<Handle>,
+    control_point: Handle,
+    locations: &'static [SensorLocation],
+    /// Encoded response to the last control point request, until it has been indicated.
+    response: Vec<u8, U20>,
+}
+
+impl CyclingSpeedCadenceServer {
+    /// Adds the service to `table`.
+    ///
+    /// `locations` lists the sensor locations the sensor can be mounted at, starting with the
+    /// current one. If it's empty, the *Sensor Location* characteristic is omitted. If it contains
+    /// more than one location, the client can change the location via the control point.
+    pub fn add_to<N, B>(
+        table: &mut AttributeTable<N, B>,
+        features: CscFeatures,
+        locations: &'static [SensorLocation],
+    ) -> Result<Self, Error>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        let mut features = features - CscFeatures::MULTIPLE_SENSOR_LOCATIONS;
+        if locations.len() > 1 {
+            features |= CscFeatures::MULTIPLE_SENSOR_LOCATIONS;
+        }
+
+        table.add_service(CYCLING_SPEED_AND_CADENCE)?;
+        // Measurements are only notified, so they aren't stored
+        let measurement_handle =
+            table.add_characteristic(CSC_MEASUREMENT, Properties::NOTIFY, &[])?;
+        table.add_characteristic(
+            CSC_FEATURE,
+            Properties::READ,
+            &features.bits().to_le_bytes(),
+        )?;
+        let location = match locations.first() {
+            Some(loc) => Some(table.add_characteristic(
+                SENSOR_LOCATION,
+                Properties::READ,
+                &[u8::from(*loc)],
+            )?),
+            None => None,
+        };
+        let control_point = table.add_characteristic(
+            SC_CONTROL_POINT,
+            Properties::WRITE | Properties::INDICATE,
+            &[],
+        )?;
+
+        Ok(Self {
+            features,
+            measurement: CscMeasurement {
+                wheel: if features.contains(CscFeatures::WHEEL_REVOLUTION_DATA) {
+                    Some((0, 0))
+                } else {
+                    None
+                },
+                crank: if features.contains(CscFeatures::CRANK_REVOLUTION_DATA) {
+                    Some((0, 0))
+                } else {
+                    None
+                },
+            },
+            measurement_handle,
+            location,
+            control_point,
+            locations,
+            response: Vec::new(),
+        })
+    }
+
+    /// Returns the handle of the *CSC Measurement* value.
+    pub fn measurement_handle(&self) -> Handle {
+        self.measurement_handle
+    }
+
+    /// Returns the handle of the *SC Control Point* value.
+    pub fn control_point(&self) -> Handle {
+        self.control_point
+    }
+
+    /// Returns the current cumulative measurement.
+    pub fn measurement(&self) -> CscMeasurement {
+        self.measurement
+    }
+
+    /// Records `revolutions` new wheel revolutions, the last of which happened at `event_time`.
+    pub fn record_wheel(&mut self, revolutions: u32, event_time: u16) {
+        if let Some((revs, time)) = &mut self.measurement.wheel {
+            *revs = revs.wrapping_add(revolutions);
+            *time = event_time;
+        }
+    }
+
+    /// Records `revolutions` new crank revolutions, the last of which happened at `event_time`.
+    pub fn record_crank(&mut self, revolutions: u16, event_time: u16) {
+        if let Some((revs, time)) = &mut self.measurement.crank {
+            *revs = revs.wrapping_add(revolutions);
+            *time = event_time;
+        }
+    }
+
+    /// Encodes the current measurement into `buf` for notification on `measurement_handle()`.
+    ///
+    /// Returns `None` if the client hasn't enabled notifications.
+    pub fn measurement_notification<'a, N, B>(
+        &self,
+        table: &AttributeTable<N, B>,
+        buf: &'a mut [u8; CscMeasurement::MAX_SIZE],
+    ) -> Option<&'a [u8]>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        if !table.notifications_enabled(self.measurement_handle) {
+            return None;
+        }
+        let mut writer = ByteWriter::new(&mut buf[..]);
+        self.measurement.encode(&mut writer).unwrap();
+        let len = CscMeasurement::MAX_SIZE - writer.space_left();
+        Some(&buf[..len])
+    }
+
+    /// Returns the response to indicate on `control_point()`, if there is one.
+    pub fn pending_response(&self) -> Option<&[u8]> {
+        if self.response.is_empty() {
+            None
+        } else {
+            Some(&self.response[..])
+        }
+    }
+
+    /// Marks the pending response as sent, allowing the client to write the next request.
+    pub fn response_sent(&mut self) {
+        utils::truncate(&mut self.response, 0);
+    }
+
+    /// Handles a write to the attribute at `handle`.
+    ///
+    /// Returns `None` if `handle` doesn't belong to the service, in which case the write should be
+    /// processed as usual. Requests are rejected with the ATT errors defined by CSCS if the client
+    /// hasn't enabled indications of the control point, or if the previous response hasn't been
+    /// sent yet. Otherwise, the request is carried out and its response queued.
+    pub fn write_attr<N, B>(
+        &mut self,
+        table: &mut AttributeTable<N, B>,
+        handle: Handle,
+        value: &[u8],
+    ) -> Option<Result<(), AttError>>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        if handle != self.control_point {
+            return None;
+        }
+        if !table.indications_enabled(handle) {
+            let code = ErrorCode::from(CCCD_IMPROPERLY_CONFIGURED);
+            return Some(Err(AttError::new(code, handle)));
+        }
+        if !self.response.is_empty() {
+            let code = ErrorCode::from(PROCEDURE_ALREADY_IN_PROGRESS);
+            return Some(Err(AttError::new(code, handle)));
+        }
+
+        let (&opcode, params) = match value.split_first() {
+            Some(split) => split,
+            None => {
+                let code = ErrorCode::InvalidAttributeValueLength;
+                return Some(Err(AttError::new(code, handle)));
+            }
+        };
+        let result = self.execute(table, opcode, params);
+        self.response
+            .extend_from_slice(&[OP_RESPONSE, opcode, result.into()])
+            .unwrap();
+        if opcode == OP_REQUEST_SUPPORTED_LOCATIONS && result == ResponseValue::Success {
+            for loc in self.locations {
+                // The response is limited to the default MTU
+                if self.response.push(u8::from(*loc)).is_err() {
+                    break;
+                }
+            }
+        }
+        Some(Ok(()))
+    }
+
+    fn execute<N, B>(
+        &mut self,
+        table: &mut AttributeTable<N, B>,
+        opcode: u8,
+        params: &[u8],
+    ) -> ResponseValue
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        let multiple = self
+            .features
+            .contains(CscFeatures::MULTIPLE_SENSOR_LOCATIONS);
+        match opcode {
+            OP_SET_CUMULATIVE_VALUE => match (&mut self.measurement.wheel, params) {
+                (Some((revs, _)), [a, b, c, d]) => {
+                    *revs = u32::from_le_bytes([*a, *b, *c, *d]);
+                    ResponseValue::Success
+                }
+                (Some(_), _) => ResponseValue::InvalidParameter,
+                (None, _) => ResponseValue::OpCodeNotSupported,
+            },
+            OP_UPDATE_SENSOR_LOCATION if multiple => match params {
+                [raw] if self.locations.contains(&SensorLocation::from(*raw)) => {
+                    match self.location {
+                        Some(handle) if table.set_value(handle, &[*raw]).is_ok() => {
+                            ResponseValue::Success
+                        }
+                        _ => ResponseValue::OperationFailed,
+                    }
+                }
+                _ => ResponseValue::InvalidParameter,
+            },
+            OP_REQUEST_SUPPORTED_LOCATIONS if multiple => ResponseValue::Success,
+            _ => ResponseValue::OpCodeNotSupported,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, heapless::consts::*};
+
+    #[test]
+    fn control_point() {
+        static LOCATIONS: [SensorLocation; 2] =
+            [SensorLocation::RearWheel, SensorLocation::FrontWheel];
+        let mut table = AttributeTable::<U16, U64>::new();
+        let mut cscs = CyclingSpeedCadenceServer::add_to(
+            &mut table,
+            CscFeatures::WHEEL_REVOLUTION_DATA | CscFeatures::CRANK_REVOLUTION_DATA,
+            &LOCATIONS,
+        )
+        .unwrap();
+        let cp = cscs.control_point();
+
+        cscs.record_wheel(10, 1024);
+        cscs.record_crank(3, 2000);
+        let notify_cccd = table.cccd_handle(cscs.measurement_handle()).unwrap();
+        table.set_value(notify_cccd, &[0x01, 0x00]).unwrap();
+        let mut buf = [0; CscMeasurement::MAX_SIZE];
+        let value = cscs.measurement_notification(&table, &mut buf).unwrap();
+        assert_eq!(value, &[0x03, 10, 0, 0, 0, 0x00, 0x04, 3, 0, 0xD0, 0x07]);
+
+        // Indications must be enabled first
+        assert!(cscs
+            .write_attr(&mut table, cp, &[0x01, 0, 0, 0, 0])
+            .unwrap()
+            .is_err());
+        let cp_cccd = table.cccd_handle(cp).unwrap();
+        table.set_value(cp_cccd, &[0x02, 0x00]).unwrap();
+
+        cscs.write_attr(&mut table, cp, &[0x01, 0xE8, 0x03, 0, 0])
+            .unwrap()
+            .unwrap();
+        assert_eq!(cscs.measurement().wheel, Some((1000, 1024)));
+        assert_eq!(cscs.pending_response(), Some(&[0x10, 0x01, 0x01][..]));
+        // The next request has to wait for the response
+        assert!(cscs.write_attr(&mut table, cp, &[0x04]).unwrap().is_err());
+        cscs.response_sent();
+
+        cscs.write_attr(&mut table, cp, &[0x03, 4])
+            .unwrap()
+            .unwrap();
+        cscs.response_sent();
+        assert_eq!(
+            table.value(Handle::from_raw(cp.as_u16() - 2)),
+            Some(&[4][..])
+        );
+        cscs.write_attr(&mut table, cp, &[0x04]).unwrap().unwrap();
+        assert_eq!(
+            cscs.pending_response(),
+            Some(&[0x10, 0x04, 0x01, 12, 4][..])
+        );
+    }
+}
@@ -0,0 +1,276 @@
+//! *Current Time Service* (CTS).
+//!
+//! CTS exposes the wall-clock time of a device. A peripheral can host it to let phones read (and
+//! get notified about changes of) its time via [`CurrentTimeServer`], or sync its own clock from a
+//! phone's CTS via [`profiles::cts::CurrentTimeClient`].
+//!
+//! ```
+//! use rubble::gatt::cts::{AdjustReason, CurrentTime, CurrentTimeServer, DateTime};
+//! use rubble::gatt::cts::LocalTimeInformation;
+//! use rubble::gatt::table::AttributeTable;
+//! use heapless::consts::*;
+//!
+//! let mut time = CurrentTime::new(DateTime::new(2020, 2, 29, 13, 37, 0));
+//! let mut table = AttributeTable::<U8, U64>::new();
+//! let cts = CurrentTimeServer::add_to(&mut table, &time, LocalTimeInformation::unknown())
+//!     .unwrap();
+//!
+//! // The user set the clock
+//! time.date_time.minutes = 42;
+//! time.adjust_reason = AdjustReason::MANUAL;
+//! if cts.set_time(&mut table, &time).unwrap() {
+//!     // Send `time.to_array()` via `AttributeServerTx::notify_raw(cts.current_time(), ..)`
+//! }
+//! ```
+//!
+//! [`CurrentTimeServer`]: struct.CurrentTimeServer.html
+//! [`profiles::cts::CurrentTimeClient`]: ../profiles/cts/struct.CurrentTimeClient.html
+
+use {
+    crate::{
+        att::Handle,
+        bytes::{ByteReader, ByteWriter},
+        gatt::{
+            characteristic::Properties,
+            client::CharacteristicValue,
+            table::{AttributeTable, TableEntry},
+        },
+        uuid::Uuid16,
+        Error,
+    },
+    bitflags::bitflags,
+    heapless::ArrayLength,
+};
+
+/// UUID of the *Current Time Service*.
+pub const CURRENT_TIME_SERVICE: Uuid16 = Uuid16(0x1805);
+
+/// UUID of the *Current Time* characteristic.
+pub const CURRENT_TIME: Uuid16 = Uuid16(0x2A2B);
+
+/// UUID of the *Local Time Information* characteristic.
+pub const LOCAL_TIME_INFORMATION: Uuid16 = Uuid16(0x2A0F);
+
+/// A calendar date and time of day.
+///
+/// Fields that are unknown are 0 (`year`, `month` and `day` only).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1 to 12.
+    pub month: u8,
+    /// 1 to 31.
+    pub day: u8,
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+}
+
+impl DateTime {
+    pub fn new(year: u16, month: u8, day: u8, hours: u8, minutes: u8, seconds: u8) -> Self {
+        Self {
+            year,
+            month,
+            day,
+            hours,
+            minutes,
+            seconds,
+        }
+    }
+}
+
+bitflags! {
+    /// Why the time was changed.
+    pub struct AdjustReason: u8 {
+        const MANUAL = 1 << 0;
+        const EXTERNAL_REFERENCE = 1 << 1;
+        const TIME_ZONE = 1 << 2;
+        const DST = 1 << 3;
+    }
+}
+
+/// Value of the *Current Time* characteristic.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CurrentTime {
+    pub date_time: DateTime,
+    /// 1 (Monday) to 7 (Sunday), or 0 if unknown.
+    pub day_of_week: u8,
+    /// Fractions of the current second, in units of 1/256 seconds.
+    pub fractions256: u8,
+    pub adjust_reason: AdjustReason,
+}
+
+impl CurrentTime {
+    /// Size of the encoded value in Bytes.
+    pub const SIZE: usize = 10;
+
+    /// Creates a time without day of week, fractions and adjust reason.
+    pub fn new(date_time: DateTime) -> Self {
+        Self {
+            date_time,
+            day_of_week: 0,
+            fractions256: 0,
+            adjust_reason: AdjustReason::empty(),
+        }
+    }
+
+    /// Returns the encoded characteristic value.
+    pub fn to_array(&self) -> [u8; Self::SIZE] {
+        let mut buf = [0; Self::SIZE];
+        self.encode(&mut ByteWriter::new(&mut buf)).unwrap();
+        buf
+    }
+}
+
+impl CharacteristicValue for CurrentTime {
+    fn decode(bytes: &mut ByteReader<'_>) -> Result<Self, Error> {
+        Ok(Self {
+            date_time: DateTime {
+                year: bytes.read_u16_le()?,
+                month: bytes.read_u8()?,
+                day: bytes.read_u8()?,
+                hours: bytes.read_u8()?,
+                minutes: bytes.read_u8()?,
+                seconds: bytes.read_u8()?,
+            },
+            day_of_week: bytes.read_u8()?,
+            fractions256: bytes.read_u8()?,
+            adjust_reason: AdjustReason::from_bits_truncate(bytes.read_u8()?),
+        })
+    }
+
+    fn encode(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        let dt = &self.date_time;
+        writer.write_u16_le(dt.year)?;
+        writer.write_slice(&[dt.month, dt.day, dt.hours, dt.minutes, dt.seconds])?;
+        writer.write_u8(self.day_of_week)?;
+        writer.write_u8(self.fractions256)?;
+        writer.write_u8(self.adjust_reason.bits())
+    }
+}
+
+/// Value of the *Local Time Information* characteristic.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LocalTimeInformation {
+    /// Offset from UTC in units of 15 minutes, or -128 if unknown.
+    pub time_zone: i8,
+    /// Daylight saving time offset in units of 15 minutes (0, 2, 4 or 8), or 255 if unknown.
+    pub dst_offset: u8,
+}
+
+impl LocalTimeInformation {
+    /// Returns a value stating that neither time zone nor DST offset are known.
+    pub fn unknown() -> Self {
+        Self {
+            time_zone: -128,
+            dst_offset: 255,
+        }
+    }
+}
+
+impl CharacteristicValue for LocalTimeInformation {
+    fn decode(bytes: &mut ByteReader<'_>) -> Result<Self, Error> {
+        Ok(Self {
+            time_zone: bytes.read_u8()? as i8,
+            dst_offset: bytes.read_u8()?,
+        })
+    }
+
+    fn encode(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u8(self.time_zone as u8)?;
+        writer.write_u8(self.dst_offset)
+    }
+}
+
+/// The server side of CTS, hosted in an `AttributeTable`.
+#[derive(Debug, Copy, Clone)]
+pub struct CurrentTimeServer {
+    current_time: Handle,
+    local_time: Handle,
+}
+
+impl CurrentTimeServer {
+    /// Adds the service to `table`.
+    pub fn add_to<N, B>(
+        table: &mut AttributeTable<N, B>,
+        time: &CurrentTime,
+        local: LocalTimeInformation,
+    ) -> Result<Self, Error>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        table.add_service(CURRENT_TIME_SERVICE)?;
+        let current_time = table.add_characteristic(
+            CURRENT_TIME,
+            Properties::READ | Properties::NOTIFY,
+            &time.to_array(),
+        )?;
+        let mut buf = [0; 2];
+        local.encode(&mut ByteWriter::new(&mut buf))?;
+        let local_time =
+            table.add_characteristic(LOCAL_TIME_INFORMATION, Properties::READ, &buf)?;
+        Ok(Self {
+            current_time,
+            local_time,
+        })
+    }
+
+    /// Returns the handle of the *Current Time* value.
+    pub fn current_time(&self) -> Handle {
+        self.current_time
+    }
+
+    /// Updates the *Current Time* value.
+    ///
+    /// The time should be updated regularly (clients may read it at any time). Returns whether
+    /// the change has to be notified to the client, which is the case if `time` has an adjust
+    /// reason and the client enabled notifications. Regular ticks of the clock aren't notified.
+    pub fn set_time<N, B>(
+        &self,
+        table: &mut AttributeTable<N, B>,
+        time: &CurrentTime,
+    ) -> Result<bool, Error>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        table.set_value(self.current_time, &time.to_array())?;
+
+        let subscribed = table.notifications_enabled(self.current_time);
+        Ok(subscribed && !time.adjust_reason.is_empty())
+    }
+
+    /// Updates the *Local Time Information* value.
+    ///
+    /// If this changes the time zone or DST offset, also update the current time with the
+    /// corresponding adjust reason.
+    pub fn set_local_time_information<N, B>(
+        &self,
+        table: &mut AttributeTable<N, B>,
+        local: LocalTimeInformation,
+    ) -> Result<(), Error>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        let mut buf = [0; 2];
+        local.encode(&mut ByteWriter::new(&mut buf))?;
+        table.set_value(self.local_time, &buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn current_time() {
+        let mut time = CurrentTime::new(DateTime::new(2021, 12, 24, 18, 30, 5));
+        time.day_of_week = 5;
+        time.adjust_reason = AdjustReason::TIME_ZONE;
+        let raw = time.to_array();
+        assert_eq!(raw, [0xE5, 0x07, 12, 24, 18, 30, 5, 5, 0, 0x04]);
+        assert_eq!(CurrentTime::decode(&mut ByteReader::new(&raw)), Ok(time));
+    }
+}
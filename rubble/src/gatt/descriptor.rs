@@ -0,0 +1,203 @@
+//! Characteristic descriptors that make characteristics self-describing.
+//!
+//! Generic GATT client apps (like nRF Connect) can't know what the value of a vendor-specific
+//! characteristic means. The *Characteristic User Description* descriptor gives it a
+//! human-readable name, and the *Characteristic Presentation Format* descriptor describes how to
+//! decode and display its value:
+//!
+//! ```
+//! use rubble::gatt::{characteristic::Properties, descriptor::*, table::AttributeTable};
+//! use rubble::uuid::Uuid16;
+//! use heapless::consts::*;
+//!
+//! let mut table = AttributeTable::<U8, U64>::new();
+//! table.add_service(Uuid16(0x181A)).unwrap();
+//! // Temperature in 0.01 °C
+//! table
+//!     .add_characteristic(Uuid16(0x2A6E), Properties::READ, &[0x34, 0x08])
+//!     .unwrap();
+//! table.add_user_description("Case temperature").unwrap();
+//! table
+//!     .add_presentation_format(&PresentationFormat::new(Format::SInt16, -2, unit::CELSIUS))
+//!     .unwrap();
+//! ```
+
+use crate::{bytes::*, uuid::Uuid16, Error};
+
+/// UUID of the *Characteristic User Description* descriptor.
+///
+/// Its value is a UTF-8 string describing the characteristic.
+pub const USER_DESCRIPTION: Uuid16 = Uuid16(0x2901);
+
+/// UUID of the *Characteristic Presentation Format* descriptor.
+pub const PRESENTATION_FORMAT: Uuid16 = Uuid16(0x2904);
+
+/// The *Bluetooth SIG* namespace for `PresentationFormat` descriptions.
+pub const NAMESPACE_BLUETOOTH_SIG: u8 = 0x01;
+
+enum_with_unknown! {
+    /// Data type of a characteristic value.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum Format(u8) {
+        Boolean = 0x01,
+        UInt2 = 0x02,
+        UInt4 = 0x03,
+        UInt8 = 0x04,
+        UInt12 = 0x05,
+        UInt16 = 0x06,
+        UInt24 = 0x07,
+        UInt32 = 0x08,
+        UInt48 = 0x09,
+        UInt64 = 0x0A,
+        UInt128 = 0x0B,
+        SInt8 = 0x0C,
+        SInt12 = 0x0D,
+        SInt16 = 0x0E,
+        SInt24 = 0x0F,
+        SInt32 = 0x10,
+        SInt48 = 0x11,
+        SInt64 = 0x12,
+        SInt128 = 0x13,
+        /// IEEE-754 32-bit float.
+        Float32 = 0x14,
+        /// IEEE-754 64-bit float.
+        Float64 = 0x15,
+        /// IEEE-11073 16-bit SFLOAT.
+        SFloat = 0x16,
+        /// IEEE-11073 32-bit FLOAT.
+        Float = 0x17,
+        /// IEEE-20601 format.
+        DUInt16 = 0x18,
+        /// UTF-8 string.
+        Utf8s = 0x19,
+        /// UTF-16 string.
+        Utf16s = 0x1A,
+        /// Opaque structure.
+        Struct = 0x1B,
+    }
+}
+
+/// Units defined in the *Assigned Numbers* document, for use in `PresentationFormat`.
+///
+/// This only lists commonly used units.
+pub mod unit {
+    use crate::uuid::Uuid16;
+
+    pub const UNITLESS: Uuid16 = Uuid16(0x2700);
+    pub const METRE: Uuid16 = Uuid16(0x2701);
+    pub const KILOGRAM: Uuid16 = Uuid16(0x2702);
+    pub const SECOND: Uuid16 = Uuid16(0x2703);
+    pub const AMPERE: Uuid16 = Uuid16(0x2704);
+    pub const KELVIN: Uuid16 = Uuid16(0x2705);
+    pub const HERTZ: Uuid16 = Uuid16(0x2722);
+    pub const PASCAL: Uuid16 = Uuid16(0x2724);
+    pub const WATT: Uuid16 = Uuid16(0x2726);
+    pub const VOLT: Uuid16 = Uuid16(0x2728);
+    pub const CELSIUS: Uuid16 = Uuid16(0x272F);
+    pub const LUX: Uuid16 = Uuid16(0x2731);
+    pub const PERCENTAGE: Uuid16 = Uuid16(0x27AD);
+    pub const BEATS_PER_MINUTE: Uuid16 = Uuid16(0x27A7);
+}
+
+/// Value of a *Characteristic Presentation Format* descriptor.
+///
+/// The actual value of the characteristic is `raw * 10^exponent`, in `unit`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PresentationFormat {
+    format: Format,
+    exponent: i8,
+    unit: Uuid16,
+    namespace: u8,
+    description: u16,
+}
+
+impl PresentationFormat {
+    /// Encoded size of the descriptor value in Bytes.
+    pub const SIZE: usize = 7;
+
+    /// Creates a presentation format without a description.
+    pub fn new(format: Format, exponent: i8, unit: Uuid16) -> Self {
+        Self {
+            format,
+            exponent,
+            unit,
+            namespace: NAMESPACE_BLUETOOTH_SIG,
+            description: 0,
+        }
+    }
+
+    /// Sets the description, which distinguishes multiple characteristics of the same type.
+    ///
+    /// Descriptions are defined by the organization owning `namespace`. In the Bluetooth SIG
+    /// namespace, they are used for positions like "left" (`0x010D`) or "right" (`0x010E`).
+    pub fn with_description(mut self, namespace: u8, description: u16) -> Self {
+        self.namespace = namespace;
+        self.description = description;
+        self
+    }
+
+    /// Returns the data type of the characteristic value.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Returns the base 10 exponent to apply to the value.
+    pub fn exponent(&self) -> i8 {
+        self.exponent
+    }
+
+    /// Returns the unit of the value.
+    pub fn unit(&self) -> Uuid16 {
+        self.unit
+    }
+
+    /// Returns the namespace of the description.
+    pub fn namespace(&self) -> u8 {
+        self.namespace
+    }
+
+    /// Returns the description.
+    pub fn description(&self) -> u16 {
+        self.description
+    }
+}
+
+impl<'a> FromBytes<'a> for PresentationFormat {
+    fn from_bytes(bytes: &mut ByteReader<'a>) -> Result<Self, Error> {
+        Ok(Self {
+            format: Format::from(bytes.read_u8()?),
+            exponent: bytes.read_u8()? as i8,
+            unit: Uuid16::from_bytes(bytes)?,
+            namespace: bytes.read_u8()?,
+            description: bytes.read_u16_le()?,
+        })
+    }
+}
+
+impl ToBytes for PresentationFormat {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u8(self.format.into())?;
+        writer.write_u8(self.exponent as u8)?;
+        self.unit.to_bytes(writer)?;
+        writer.write_u8(self.namespace)?;
+        writer.write_u16_le(self.description)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presentation_format_encoding() {
+        let format = PresentationFormat::new(Format::SInt16, -2, unit::CELSIUS)
+            .with_description(NAMESPACE_BLUETOOTH_SIG, 0x010D);
+        let mut buf = [0; PresentationFormat::SIZE];
+        format.to_bytes(&mut ByteWriter::new(&mut buf)).unwrap();
+        assert_eq!(buf, [0x0E, 0xFE, 0x2F, 0x27, 0x01, 0x0D, 0x01]);
+
+        let decoded = PresentationFormat::from_bytes(&mut ByteReader::new(&buf)).unwrap();
+        assert_eq!(decoded, format);
+    }
+}
@@ -0,0 +1,400 @@
+//! Over-the-air firmware updates.
+//!
+//! This module implements a simple DFU (*Device Firmware Update*) service: The client starts a
+//! transfer by writing the image size and CRC-32 to the *Control Point*, streams the image via
+//! *Write Commands* to the *Packet* characteristic, and then asks the device to validate and
+//! activate it. The device acknowledges every control point operation with a notification.
+//! The protocol is specific to Rubble and not compatible with Nordic's DFU.
+//!
+//! The image is handed to a [`FirmwareTarget`] (usually the staging area of a bootloader) in
+//! blocks of `BLOCK_SIZE` Bytes, so that flash alignment requirements are easy to meet. Once the
+//! CRC of the complete image has been verified, `FirmwareTarget::commit` is called, which should
+//! mark the image as ready for installation. After the client requests activation,
+//! `DfuService::take_activation` returns `true`, and the application should reset into the
+//! bootloader once the response notification has been sent.
+//!
+//! # Control Point
+//!
+//! Requests start with an opcode:
+//!
+//! * `0x01` *Start*, followed by the image size and CRC-32 (both `u32`, little-endian).
+//! * `0x02` *Validate*, after the whole image has been written.
+//! * `0x03` *Activate*, after successful validation.
+//! * `0x04` *Abort*.
+//!
+//! Each request is answered with a notification of 3 Bytes: `0x60`, the request opcode, and a
+//! [`DfuStatus`].
+//!
+//! [`FirmwareTarget`]: trait.FirmwareTarget.html
+//! [`DfuStatus`]: enum.DfuStatus.html
+
+use {
+    crate::{
+        att::{AttError, AttUuid, ErrorCode, Handle},
+        bytes::ByteReader,
+        gatt::{
+            characteristic::Properties,
+            table::{AttributeTable, TableEntry},
+        },
+        uuid::Uuid,
+        Error,
+    },
+    heapless::ArrayLength,
+};
+
+// UUIDs are given in the Byte order used on air.
+
+/// UUID of the DFU service.
+pub const DFU_SERVICE: AttUuid = AttUuid::Uuid128(Uuid::from_bytes([
+    0x9E, 0xCA, 0xDC, 0x24, 0x0E, 0xE5, 0xA9, 0xE0, 0x93, 0xF3, 0xA3, 0xB5, 0x01, 0x00, 0xB1, 0x2E,
+]));
+
+/// UUID of the *Control Point* characteristic.
+pub const CONTROL_POINT: AttUuid = AttUuid::Uuid128(Uuid::from_bytes([
+    0x9E, 0xCA, 0xDC, 0x24, 0x0E, 0xE5, 0xA9, 0xE0, 0x93, 0xF3, 0xA3, 0xB5, 0x02, 0x00, 0xB1, 0x2E,
+]));
+
+/// UUID of the *Packet* characteristic.
+pub const PACKET: AttUuid = AttUuid::Uuid128(Uuid::from_bytes([
+    0x9E, 0xCA, 0xDC, 0x24, 0x0E, 0xE5, 0xA9, 0xE0, 0x93, 0xF3, 0xA3, 0xB5, 0x03, 0x00, 0xB1, 0x2E,
+]));
+
+/// Size of the blocks passed to `FirmwareTarget::write`.
+pub const BLOCK_SIZE: usize = 64;
+
+const OP_START: u8 = 0x01;
+const OP_VALIDATE: u8 = 0x02;
+const OP_ACTIVATE: u8 = 0x03;
+const OP_ABORT: u8 = 0x04;
+const OP_RESPONSE: u8 = 0x60;
+
+/// Result of a control point operation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DfuStatus {
+    Success = 0x01,
+    /// The operation isn't allowed in the current state of the transfer.
+    InvalidState = 0x02,
+    /// The opcode is unknown, or the request is malformed.
+    NotSupported = 0x03,
+    /// The image doesn't fit into the target, or more data than announced was written.
+    InvalidSize = 0x04,
+    /// The CRC of the received image doesn't match.
+    CrcMismatch = 0x05,
+    /// The `FirmwareTarget` reported an error.
+    TargetError = 0x06,
+}
+
+/// Storage for received firmware images.
+pub trait FirmwareTarget {
+    /// Error reported by the target.
+    type Error;
+
+    /// Prepares for receiving an image of `size` Bytes, eg. by erasing the staging area.
+    ///
+    /// Should fail if the image doesn't fit.
+    fn begin(&mut self, size: u32) -> Result<(), Self::Error>;
+
+    /// Writes a block of the image at `offset`.
+    ///
+    /// `offset` is a multiple of `BLOCK_SIZE`, and `data` is `BLOCK_SIZE` Bytes long except for
+    /// the last block of the image.
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Hands the complete and verified image to the bootloader.
+    fn commit(&mut self, size: u32, crc: u32) -> Result<(), Self::Error>;
+}
+
+#[derive(Debug, Copy, Clone)]
+enum State {
+    Idle,
+    Receiving,
+    /// All data was received.
+    Received,
+    Validated,
+}
+
+/// The DFU service, hosted in an `AttributeTable`.
+pub struct DfuService<T: FirmwareTarget> {
+    target: T,
+    control_point: Handle,
+    packet: Handle,
+    state: State,
+    size: u32,
+    expected_crc: u32,
+    crc: u32,
+    offset: u32,
+    block: [u8; BLOCK_SIZE],
+    block_len: usize,
+    response: Option<[u8; 3]>,
+    activate: bool,
+}
+
+impl<T: FirmwareTarget> DfuService<T> {
+    /// Adds the service to `table`, storing received images into `target`.
+    pub fn add_to<N, B>(table: &mut AttributeTable<N, B>, target: T) -> Result<Self, Error>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        table.add_service(DFU_SERVICE)?;
+        // Neither value is stored, writes are handled by `write_attr`
+        let control_point =
+            table.add_characteristic(CONTROL_POINT, Properties::WRITE | Properties::NOTIFY, &[])?;
+        let packet = table.add_characteristic(PACKET, Properties::WRITE_NO_RSP, &[])?;
+        Ok(Self {
+            target,
+            control_point,
+            packet,
+            state: State::Idle,
+            size: 0,
+            expected_crc: 0,
+            crc: CRC_INIT,
+            offset: 0,
+            block: [0; BLOCK_SIZE],
+            block_len: 0,
+            response: None,
+            activate: false,
+        })
+    }
+
+    /// Returns the handle of the control point value, on which responses are notified.
+    pub fn control_point(&self) -> Handle {
+        self.control_point
+    }
+
+    /// Returns the number of image Bytes received so far.
+    pub fn progress(&self) -> u32 {
+        self.offset
+    }
+
+    /// Returns the `FirmwareTarget`.
+    pub fn target(&mut self) -> &mut T {
+        &mut self.target
+    }
+
+    /// Returns the response to notify on the control point, if there is one.
+    ///
+    /// Send it with `AttributeServerTx::notify_raw(dfu.control_point(), &response)`.
+    pub fn take_response(&mut self) -> Option<[u8; 3]> {
+        self.response.take()
+    }
+
+    /// Returns whether the client asked to activate the new image.
+    ///
+    /// The application should reset into the bootloader after sending the response.
+    pub fn take_activation(&mut self) -> bool {
+        core::mem::replace(&mut self.activate, false)
+    }
+
+    /// Aborts the transfer, eg. because the connection was closed.
+    pub fn abort(&mut self) {
+        self.state = State::Idle;
+    }
+
+    /// Handles a write to the attribute at `handle`.
+    ///
+    /// Returns `None` if `handle` doesn't belong to the service, in which case the write should be
+    /// processed as usual. This may write to the `FirmwareTarget`, which can take a while.
+    pub fn write_attr(&mut self, handle: Handle, value: &[u8]) -> Option<Result<(), AttError>> {
+        if handle == self.packet {
+            self.packet(value);
+            Some(Ok(()))
+        } else if handle == self.control_point {
+            let opcode = match value.first() {
+                Some(op) => *op,
+                None => {
+                    let code = ErrorCode::InvalidAttributeValueLength;
+                    return Some(Err(AttError::new(code, handle)));
+                }
+            };
+            let status = self.request(opcode, &value[1..]);
+            self.response = Some([OP_RESPONSE, opcode, status as u8]);
+            Some(Ok(()))
+        } else {
+            None
+        }
+    }
+
+    fn request(&mut self, opcode: u8, params: &[u8]) -> DfuStatus {
+        match (opcode, self.state) {
+            (OP_START, _) => {
+                let mut bytes = ByteReader::new(params);
+                let (size, crc) = match (bytes.read_u32_le(), bytes.read_u32_le()) {
+                    (Ok(size), Ok(crc)) => (size, crc),
+                    _ => return DfuStatus::NotSupported,
+                };
+                self.state = State::Idle;
+                if size == 0 {
+                    return DfuStatus::InvalidSize;
+                }
+                if self.target.begin(size).is_err() {
+                    return DfuStatus::TargetError;
+                }
+                self.size = size;
+                self.expected_crc = crc;
+                self.crc = CRC_INIT;
+                self.offset = 0;
+                self.block_len = 0;
+                self.state = State::Receiving;
+                DfuStatus::Success
+            }
+            (OP_VALIDATE, State::Received) => {
+                if self.crc ^ CRC_INIT != self.expected_crc {
+                    self.state = State::Idle;
+                    return DfuStatus::CrcMismatch;
+                }
+                if self.target.commit(self.size, self.expected_crc).is_err() {
+                    self.state = State::Idle;
+                    return DfuStatus::TargetError;
+                }
+                self.state = State::Validated;
+                DfuStatus::Success
+            }
+            (OP_ACTIVATE, State::Validated) => {
+                self.activate = true;
+                DfuStatus::Success
+            }
+            (OP_ABORT, _) => {
+                self.state = State::Idle;
+                DfuStatus::Success
+            }
+            (OP_VALIDATE, _) | (OP_ACTIVATE, _) => DfuStatus::InvalidState,
+            _ => DfuStatus::NotSupported,
+        }
+    }
+
+    fn packet(&mut self, data: &[u8]) {
+        match self.state {
+            State::Receiving => {}
+            // Write Commands can't be rejected, so the client learns about it on validation
+            _ => return,
+        }
+
+        if self.offset + data.len() as u32 > self.size {
+            self.fail(DfuStatus::InvalidSize);
+            return;
+        }
+        self.crc = crc32_update(self.crc, data);
+        self.offset += data.len() as u32;
+
+        let mut data = data;
+        while !data.is_empty() {
+            let n = data.len().min(BLOCK_SIZE - self.block_len);
+            self.block[self.block_len..self.block_len + n].copy_from_slice(&data[..n]);
+            self.block_len += n;
+            data = &data[n..];
+
+            let complete = self.offset == self.size && data.is_empty();
+            if self.block_len == BLOCK_SIZE || complete {
+                let block_offset = self.offset - data.len() as u32 - self.block_len as u32;
+                if self
+                    .target
+                    .write(block_offset, &self.block[..self.block_len])
+                    .is_err()
+                {
+                    self.fail(DfuStatus::TargetError);
+                    return;
+                }
+                self.block_len = 0;
+            }
+        }
+
+        if self.offset == self.size {
+            self.state = State::Received;
+        }
+    }
+
+    /// Aborts the transfer and notifies the client about the reason.
+    fn fail(&mut self, status: DfuStatus) {
+        self.state = State::Idle;
+        self.response = Some([OP_RESPONSE, OP_START, status as u8]);
+    }
+}
+
+const CRC_INIT: u32 = 0xFFFF_FFFF;
+
+/// Updates a CRC-32 (as used by zlib and Ethernet) with `data`.
+///
+/// The final CRC is `crc ^ CRC_INIT`.
+fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, heapless::consts::*};
+
+    struct Ram {
+        image: [u8; 256],
+        committed: bool,
+    }
+
+    impl FirmwareTarget for Ram {
+        type Error = ();
+
+        fn begin(&mut self, size: u32) -> Result<(), ()> {
+            if size as usize > self.image.len() {
+                Err(())
+            } else {
+                Ok(())
+            }
+        }
+
+        fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), ()> {
+            assert_eq!(offset as usize % BLOCK_SIZE, 0);
+            self.image[offset as usize..][..data.len()].copy_from_slice(data);
+            Ok(())
+        }
+
+        fn commit(&mut self, _: u32, _: u32) -> Result<(), ()> {
+            self.committed = true;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn transfer() {
+        assert_eq!(crc32_update(CRC_INIT, b"123456789") ^ CRC_INIT, 0xCBF4_3926);
+
+        let mut image = [0; 150];
+        for (i, b) in image.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let crc = crc32_update(CRC_INIT, &image) ^ CRC_INIT;
+
+        let mut table = AttributeTable::<U8, U128>::new();
+        let target = Ram {
+            image: [0; 256],
+            committed: false,
+        };
+        let mut dfu = DfuService::add_to(&mut table, target).unwrap();
+        let (cp, packet) = (dfu.control_point, dfu.packet);
+
+        let mut start = [OP_START, 150, 0, 0, 0, 0, 0, 0, 0];
+        start[5..].copy_from_slice(&crc.to_le_bytes());
+        assert!(dfu.write_attr(cp, &start).unwrap().is_ok());
+        assert_eq!(dfu.take_response(), Some([0x60, OP_START, 0x01]));
+
+        dfu.write_attr(cp, &[OP_VALIDATE]).unwrap().unwrap();
+        assert_eq!(dfu.take_response(), Some([0x60, OP_VALIDATE, 0x02]));
+
+        for chunk in image.chunks(20) {
+            dfu.write_attr(packet, chunk).unwrap().unwrap();
+        }
+        assert_eq!(dfu.progress(), 150);
+        assert_eq!(dfu.target().image[..150], image[..]);
+
+        dfu.write_attr(cp, &[OP_VALIDATE]).unwrap().unwrap();
+        assert_eq!(dfu.take_response(), Some([0x60, OP_VALIDATE, 0x01]));
+        assert!(dfu.target().committed);
+        dfu.write_attr(cp, &[OP_ACTIVATE]).unwrap().unwrap();
+        assert!(dfu.take_activation());
+    }
+}
@@ -0,0 +1,427 @@
+//! Discovery of services and characteristics on the peer's GATT server.
+//!
+//! A [`ServiceDiscovery`] finds a primary service by UUID, the characteristics of interest in it,
+//! and their *Client Characteristic Configuration* descriptors (if they support notifications or
+//! indications). It is driven by the `AttributeProvider`, which has to forward its
+//! `next_request` calls and the `ClientEvent`s it receives:
+//!
+//! ```
+//! use rubble::att::{AttUuid, ClientEvent, ClientRequest};
+//! use rubble::gatt::discovery::ServiceDiscovery;
+//! use rubble::uuid::Uuid16;
+//!
+//! struct Attrs {
+//!     discovery: ServiceDiscovery,
+//!     // ...
+//! }
+//!
+//! impl Attrs {
+//!     // Called from the `AttributeProvider` methods of the same name
+//!     fn next_request(&self) -> Option<ClientRequest<'_>> {
+//!         self.discovery.next_request()
+//!     }
+//!
+//!     fn client_event(&mut self, event: ClientEvent<'_>) {
+//!         if !self.discovery.process(&event) {
+//!             // Some other event
+//!         }
+//!     }
+//! }
+//!
+//! // Battery Service with its Battery Level characteristic
+//! const BATTERY_LEVEL: AttUuid = AttUuid::Uuid16(Uuid16(0x2A19));
+//! let mut discovery = ServiceDiscovery::new(Uuid16(0x180F).into(), &[BATTERY_LEVEL]);
+//! discovery.start();
+//! // ... then start sending requests with `AttributeServerTx::send_next_request`
+//! ```
+//!
+//! Several discoveries (or other users of the client) can share the ATT bearer, as long as the
+//! events are passed to them in the same order in which their requests are queried.
+//!
+//! [`ServiceDiscovery`]: struct.ServiceDiscovery.html
+
+use crate::{
+    att::{AttUuid, ClientEvent, ClientRequest, ErrorCode, Handle},
+    bytes::{ByteReader, ByteWriter, FromBytes, ToBytes},
+    gatt::{
+        characteristic::Properties,
+        client::{Characteristic, CharacteristicValue},
+    },
+    uuid::Uuid16,
+    Error,
+};
+
+/// Maximum number of characteristics a `ServiceDiscovery` can look for.
+pub const MAX_CHARACTERISTICS: usize = 4;
+
+const PRIMARY_SERVICE: Uuid16 = Uuid16(0x2800);
+const CHARACTERISTIC: Uuid16 = Uuid16(0x2803);
+const CCCD: Uuid16 = Uuid16(0x2902);
+
+/// A characteristic found on the peer's server.
+#[derive(Debug, Copy, Clone)]
+pub struct DiscoveredCharacteristic {
+    properties: Properties,
+    value_handle: Handle,
+    /// Last handle belonging to the characteristic.
+    end: Handle,
+    cccd: Option<Handle>,
+}
+
+impl DiscoveredCharacteristic {
+    /// Returns the operations the characteristic supports.
+    pub fn properties(&self) -> Properties {
+        self.properties
+    }
+
+    /// Returns the handle of the characteristic value.
+    pub fn value_handle(&self) -> Handle {
+        self.value_handle
+    }
+
+    /// Returns the handle of the *Client Characteristic Configuration* descriptor, if the
+    /// characteristic has one.
+    pub fn cccd(&self) -> Option<Handle> {
+        self.cccd
+    }
+
+    /// Returns a typed accessor for the characteristic.
+    pub fn typed<T: CharacteristicValue>(&self) -> Characteristic<T> {
+        let chr = Characteristic::new(self.value_handle);
+        match self.cccd {
+            Some(cccd) => chr.with_cccd(cccd),
+            None => chr,
+        }
+    }
+}
+
+/// Progress of a `ServiceDiscovery`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DiscoveryStatus {
+    /// Discovery hasn't been started.
+    Idle,
+
+    /// Requests are being exchanged.
+    InProgress,
+
+    /// The service was found. Characteristics that weren't found are missing from the service.
+    Done,
+
+    /// The peer doesn't have the service.
+    NotFound,
+
+    /// The server responded with an unexpected error or a malformed PDU.
+    Failed,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Step {
+    Idle,
+    Service,
+    Characteristics { next: Handle },
+    Descriptors { index: usize, next: Handle },
+    Done,
+    NotFound,
+    Failed,
+}
+
+/// Discovers a primary service and some of its characteristics.
+pub struct ServiceDiscovery {
+    targets: &'static [AttUuid],
+    /// The service UUID, encoded as attribute value.
+    uuid: [u8; 16],
+    uuid_len: u8,
+    /// End handle of the service.
+    end: Handle,
+    /// Found characteristics, with the same indices as `targets`.
+    found: [Option<DiscoveredCharacteristic>; MAX_CHARACTERISTICS],
+    /// Index of the found characteristic whose end handle isn't known yet.
+    open: Option<usize>,
+    step: Step,
+}
+
+impl ServiceDiscovery {
+    /// Creates a discovery for the service `service` and the characteristics with the UUIDs in
+    /// `characteristics`.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if more than `MAX_CHARACTERISTICS` characteristics are passed.
+    pub fn new(service: AttUuid, characteristics: &'static [AttUuid]) -> Self {
+        assert!(characteristics.len() <= MAX_CHARACTERISTICS);
+
+        let mut uuid = [0; 16];
+        let mut writer = ByteWriter::new(&mut uuid);
+        service.to_bytes(&mut writer).unwrap();
+        let uuid_len = (16 - writer.space_left()) as u8;
+
+        Self {
+            targets: characteristics,
+            uuid,
+            uuid_len,
+            end: Handle::from_raw(0xFFFF),
+            found: [None; MAX_CHARACTERISTICS],
+            open: None,
+            step: Step::Idle,
+        }
+    }
+
+    /// Starts (or restarts) the discovery, forgetting previous results.
+    pub fn start(&mut self) {
+        self.found = [None; MAX_CHARACTERISTICS];
+        self.open = None;
+        self.step = Step::Service;
+    }
+
+    /// Resets the discovery to `DiscoveryStatus::Idle`, eg. after the connection was closed.
+    pub fn reset(&mut self) {
+        self.start();
+        self.step = Step::Idle;
+    }
+
+    /// Returns the progress of the discovery.
+    pub fn status(&self) -> DiscoveryStatus {
+        match self.step {
+            Step::Idle => DiscoveryStatus::Idle,
+            Step::Service | Step::Characteristics { .. } | Step::Descriptors { .. } => {
+                DiscoveryStatus::InProgress
+            }
+            Step::Done => DiscoveryStatus::Done,
+            Step::NotFound => DiscoveryStatus::NotFound,
+            Step::Failed => DiscoveryStatus::Failed,
+        }
+    }
+
+    /// Returns the characteristic with type `uuid`, if it was found.
+    ///
+    /// `uuid` must be one of the UUIDs passed to `new`. Descriptors are only known once the
+    /// discovery is `Done`.
+    pub fn characteristic(&self, uuid: AttUuid) -> Option<&DiscoveredCharacteristic> {
+        let index = self.targets.iter().position(|t| *t == uuid)?;
+        self.found[index].as_ref()
+    }
+
+    /// Returns the next request to send.
+    pub fn next_request(&self) -> Option<ClientRequest<'_>> {
+        match self.step {
+            Step::Service => Some(ClientRequest::FindByTypeValue {
+                start: Handle::from_raw(0x0001),
+                end: Handle::from_raw(0xFFFF),
+                attribute_type: PRIMARY_SERVICE,
+                value: &self.uuid[..usize::from(self.uuid_len)],
+            }),
+            Step::Characteristics { next } => Some(ClientRequest::ReadByType {
+                start: next,
+                end: self.end,
+                attribute_type: CHARACTERISTIC.into(),
+            }),
+            Step::Descriptors { index, next } => Some(ClientRequest::FindInformation {
+                start: next,
+                end: self.found[index].unwrap().end,
+            }),
+            Step::Idle | Step::Done | Step::NotFound | Step::Failed => None,
+        }
+    }
+
+    /// Processes the response to a request returned by `next_request`.
+    ///
+    /// Returns whether `event` was such a response.
+    pub fn process(&mut self, event: &ClientEvent<'_>) -> bool {
+        match self.next_request() {
+            Some(req) if req.handle() == event.handle() => {}
+            _ => return false,
+        }
+
+        let result = match (self.step, *event) {
+            (Step::Service, ClientEvent::FoundByTypeValue { handles, .. }) => {
+                self.service_found(handles)
+            }
+            (Step::Characteristics { .. }, ClientEvent::ReadByType { length, data, .. }) => {
+                self.characteristics_found(length, data)
+            }
+            (Step::Descriptors { index, .. }, ClientEvent::Information { format, data, .. }) => {
+                self.descriptors_found(index, format, data)
+            }
+            (step, ClientEvent::Error { code, .. }) => {
+                match (step, code) {
+                    (Step::Service, ErrorCode::AttributeNotFound) => self.step = Step::NotFound,
+                    (Step::Characteristics { .. }, ErrorCode::AttributeNotFound) => {
+                        self.find_descriptors(0)
+                    }
+                    (Step::Descriptors { index, .. }, ErrorCode::AttributeNotFound) => {
+                        self.find_descriptors(index + 1)
+                    }
+                    (_, code) => {
+                        debug!("discovery failed: {:?}", code);
+                        self.step = Step::Failed;
+                    }
+                }
+                Ok(())
+            }
+            _ => return false,
+        };
+
+        if let Err(e) = result {
+            debug!("malformed discovery response: {:?}", e);
+            self.step = Step::Failed;
+        }
+        true
+    }
+
+    fn service_found(&mut self, handles: &[u8]) -> Result<(), Error> {
+        let mut bytes = ByteReader::new(handles);
+        let start = Handle::from_bytes(&mut bytes)?;
+        self.end = Handle::from_bytes(&mut bytes)?;
+        self.step = Step::Characteristics { next: start };
+        Ok(())
+    }
+
+    fn characteristics_found(&mut self, length: u8, data: &[u8]) -> Result<(), Error> {
+        if length < 7 {
+            return Err(Error::InvalidLength);
+        }
+
+        let mut last = self.end;
+        for entry in data.chunks(usize::from(length)) {
+            let mut bytes = ByteReader::new(entry);
+            let declaration = Handle::from_bytes(&mut bytes)?;
+            let properties = Properties::from_bits_truncate(bytes.read_u8()?);
+            let value_handle = Handle::from_bytes(&mut bytes)?;
+            let uuid = AttUuid::from_bytes(&mut bytes)?;
+
+            if let Some(open) = self.open.take() {
+                self.found[open].as_mut().unwrap().end =
+                    Handle::from_raw(declaration.as_u16().saturating_sub(1));
+            }
+            if let Some(index) = self.targets.iter().position(|t| *t == uuid) {
+                self.found[index] = Some(DiscoveredCharacteristic {
+                    properties,
+                    value_handle,
+                    end: self.end,
+                    cccd: None,
+                });
+                self.open = Some(index);
+            }
+            last = value_handle;
+        }
+
+        match after(last, self.end) {
+            Some(next) => self.step = Step::Characteristics { next },
+            None => self.find_descriptors(0),
+        }
+        Ok(())
+    }
+
+    fn descriptors_found(&mut self, index: usize, format: u8, data: &[u8]) -> Result<(), Error> {
+        let entry_len = match format {
+            1 => 4,
+            2 => 18,
+            _ => return Err(Error::InvalidValue),
+        };
+
+        let chr = self.found[index].as_mut().unwrap();
+        let mut last = chr.end;
+        for entry in data.chunks(entry_len) {
+            let mut bytes = ByteReader::new(entry);
+            let handle = Handle::from_bytes(&mut bytes)?;
+            if AttUuid::from_bytes(&mut bytes)? == CCCD {
+                chr.cccd = Some(handle);
+            }
+            last = handle;
+        }
+
+        match after(last, chr.end) {
+            Some(next) if chr.cccd.is_none() => self.step = Step::Descriptors { index, next },
+            _ => self.find_descriptors(index + 1),
+        }
+        Ok(())
+    }
+
+    /// Continues with the descriptors of the first characteristic at or after `index` that can
+    /// have a CCCD.
+    fn find_descriptors(&mut self, index: usize) {
+        let subscribable = Properties::NOTIFY | Properties::INDICATE;
+        for (i, chr) in self.found.iter().enumerate().skip(index) {
+            if let Some(chr) = chr {
+                if chr.properties.intersects(subscribable) {
+                    if let Some(next) = after(chr.value_handle, chr.end) {
+                        self.step = Step::Descriptors { index: i, next };
+                        return;
+                    }
+                }
+            }
+        }
+        self.step = Step::Done;
+    }
+}
+
+/// Returns the handle after `handle`, if it is not past `end`.
+fn after(handle: Handle, end: Handle) -> Option<Handle> {
+    if handle.as_u16() < end.as_u16() {
+        Some(Handle::from_raw(handle.as_u16() + 1))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEVEL: AttUuid = AttUuid::Uuid16(Uuid16(0x2A19));
+    const TARGETS: &[AttUuid] = &[LEVEL];
+
+    #[test]
+    fn battery_service() {
+        let h = Handle::from_raw;
+        let mut disc = ServiceDiscovery::new(Uuid16(0x180F).into(), TARGETS);
+        assert_eq!(disc.status(), DiscoveryStatus::Idle);
+        assert!(disc.next_request().is_none());
+
+        disc.start();
+        match disc.next_request() {
+            Some(ClientRequest::FindByTypeValue { value, .. }) => assert_eq!(value, [0x0F, 0x18]),
+            other => panic!("unexpected request {:?}", other),
+        }
+        assert!(disc.process(&ClientEvent::FoundByTypeValue {
+            handle: h(1),
+            handles: &[0x10, 0, 0x14, 0],
+        }));
+
+        // Battery Level at 0x12 (notify + read), CCCD at 0x13, Presentation Format at 0x14
+        assert!(disc.process(&ClientEvent::ReadByType {
+            handle: h(0x10),
+            length: 7,
+            data: &[0x11, 0, 0x12, 0x12, 0, 0x19, 0x2A],
+        }));
+        // The rest of the service is searched for more characteristics
+        match disc.next_request() {
+            Some(ClientRequest::ReadByType { start, end, .. }) => {
+                assert_eq!((start, end), (h(0x13), h(0x14)))
+            }
+            other => panic!("unexpected request {:?}", other),
+        }
+        assert!(disc.process(&ClientEvent::Error {
+            handle: h(0x13),
+            code: ErrorCode::AttributeNotFound,
+        }));
+        match disc.next_request() {
+            Some(ClientRequest::FindInformation { start, end }) => {
+                assert_eq!((start, end), (h(0x13), h(0x14)))
+            }
+            other => panic!("unexpected request {:?}", other),
+        }
+        // Unrelated response
+        assert!(!disc.process(&ClientEvent::Written { handle: h(0x20) }));
+        assert!(disc.process(&ClientEvent::Information {
+            handle: h(0x13),
+            format: 1,
+            data: &[0x13, 0, 0x02, 0x29, 0x14, 0, 0x04, 0x29],
+        }));
+
+        assert_eq!(disc.status(), DiscoveryStatus::Done);
+        let level = disc.characteristic(LEVEL).unwrap();
+        assert_eq!(level.value_handle(), h(0x12));
+        assert_eq!(level.cccd(), Some(h(0x13)));
+    }
+}
@@ -0,0 +1,303 @@
+//! A heap-allocated attribute database whose services can be added and removed at runtime.
+//!
+//! This module is only available when the `alloc` feature is enabled, and requires a global
+//! allocator. It is meant for gateway-class devices that construct their GATT database from
+//! runtime configuration and don't know its size in advance. Devices with a fixed database should
+//! use `AttributeTable` instead, which doesn't need an allocator.
+//!
+//! New services are always assigned handles after the highest handle in use, and removing a
+//! service leaves a hole in the handle space, so the handles of the remaining attributes never
+//! change. Clients that have cached the database need to be told to rediscover it after it was
+//! changed (using the *Service Changed* characteristic), which isn't done automatically.
+//!
+//! ```
+//! use rubble::gatt::{characteristic::Properties, dynamic::DynamicTable};
+//! use rubble::uuid::Uuid16;
+//!
+//! let mut table = DynamicTable::new();
+//! let bas = table.add_service(Uuid16(0x180F)).unwrap();
+//! let level = table
+//!     .add_characteristic(Uuid16(0x2A19), Properties::READ, &[100])
+//!     .unwrap();
+//! table.add_service(Uuid16(0x180A)).unwrap();
+//!
+//! table.remove_service(bas).unwrap();
+//! assert_eq!(table.value(level), None);
+//! ```
+
+use {
+    crate::{
+        att::{AttError, AttUuid, Attribute, AttributeProvider, ErrorCode, Handle, HandleRange},
+        bytes::{ByteWriter, ToBytes},
+        gatt::characteristic::Properties,
+        utils::HexSlice,
+        uuid::Uuid16,
+        Error,
+    },
+    alloc::vec::Vec,
+};
+
+const PRIMARY_SERVICE: Uuid16 = Uuid16(0x2800);
+const SECONDARY_SERVICE: Uuid16 = Uuid16(0x2801);
+const CHARACTERISTIC: Uuid16 = Uuid16(0x2803);
+const CCCD: Uuid16 = Uuid16(0x2902);
+
+/// A single attribute in a `DynamicTable`.
+#[derive(Debug, Clone)]
+struct Entry {
+    att_type: AttUuid,
+    handle: Handle,
+    value: Vec<u8>,
+    writable: bool,
+}
+
+/// An `AttributeProvider` storing its attributes on the heap.
+#[derive(Debug, Clone)]
+pub struct DynamicTable {
+    /// Attributes, sorted by handle.
+    entries: Vec<Entry>,
+    /// Handle the next attribute will get. Handles of removed attributes aren't reused.
+    next_handle: u32,
+}
+
+impl DynamicTable {
+    /// Creates an empty attribute table.
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            next_handle: 1,
+        }
+    }
+
+    fn next_handle(&self) -> Result<u16, Error> {
+        if self.next_handle > u32::from(u16::MAX) {
+            Err(Error::Eof)
+        } else {
+            Ok(self.next_handle as u16)
+        }
+    }
+
+    /// Adds an attribute with the given type and value after all existing attributes.
+    ///
+    /// Returns the handle assigned to the attribute, or `Error::Eof` if the handle space is
+    /// exhausted.
+    pub fn add_attribute(
+        &mut self,
+        att_type: AttUuid,
+        value: &[u8],
+        writable: bool,
+    ) -> Result<Handle, Error> {
+        let handle = Handle::from_raw(self.next_handle()?);
+        self.next_handle += 1;
+        self.entries.push(Entry {
+            att_type,
+            handle,
+            value: value.to_vec(),
+            writable,
+        });
+        Ok(handle)
+    }
+
+    /// Adds a primary service declaration.
+    ///
+    /// All characteristics added after this call (until the next service is added) belong to this
+    /// service.
+    pub fn add_service(&mut self, uuid: impl Into<AttUuid>) -> Result<Handle, Error> {
+        self.add_service_decl(PRIMARY_SERVICE, uuid.into())
+    }
+
+    /// Adds a secondary service declaration.
+    pub fn add_secondary_service(&mut self, uuid: impl Into<AttUuid>) -> Result<Handle, Error> {
+        self.add_service_decl(SECONDARY_SERVICE, uuid.into())
+    }
+
+    fn add_service_decl(&mut self, decl: Uuid16, uuid: AttUuid) -> Result<Handle, Error> {
+        let mut buf = [0; 16];
+        let mut writer = ByteWriter::new(&mut buf);
+        let left = writer.space_left();
+        uuid.shortest().to_bytes(&mut writer)?;
+        let len = left - writer.space_left();
+        self.add_attribute(decl.into(), &buf[..len], false)
+    }
+
+    /// Adds a characteristic declaration and value to the last added service.
+    ///
+    /// If `props` includes `NOTIFY` or `INDICATE`, a *Client Characteristic Configuration*
+    /// descriptor is added as well. Values of writable characteristics can later be changed to
+    /// any length.
+    ///
+    /// Returns the handle of the characteristic value.
+    pub fn add_characteristic(
+        &mut self,
+        uuid: impl Into<AttUuid>,
+        props: Properties,
+        value: &[u8],
+    ) -> Result<Handle, Error> {
+        let uuid = uuid.into();
+        let decl_handle = self.next_handle()?;
+        let has_cccd = props.intersects(Properties::NOTIFY | Properties::INDICATE);
+        // Make sure all attributes fit so that we don't leave a partial characteristic behind
+        let attrs = if has_cccd { 2 } else { 1 };
+        decl_handle.checked_add(attrs).ok_or(Error::Eof)?;
+        let value_handle = decl_handle + 1;
+
+        // Properties, value handle, characteristic UUID
+        let mut decl = [0; 19];
+        let mut writer = ByteWriter::new(&mut decl);
+        let left = writer.space_left();
+        writer.write_u8(props.bits())?;
+        writer.write_u16_le(value_handle)?;
+        uuid.shortest().to_bytes(&mut writer)?;
+        let len = left - writer.space_left();
+
+        self.add_attribute(CHARACTERISTIC.into(), &decl[..len], false)?;
+        let writable = props.intersects(Properties::WRITE | Properties::WRITE_NO_RSP);
+        let handle = self.add_attribute(uuid, value, writable)?;
+        if has_cccd {
+            self.add_attribute(CCCD.into(), &[0x00, 0x00], true)?;
+        }
+
+        Ok(handle)
+    }
+
+    /// Adds a characteristic descriptor to the last added characteristic.
+    ///
+    /// Returns the handle of the descriptor.
+    pub fn add_descriptor(
+        &mut self,
+        uuid: impl Into<AttUuid>,
+        value: &[u8],
+        writable: bool,
+    ) -> Result<Handle, Error> {
+        self.add_attribute(uuid.into(), value, writable)
+    }
+
+    /// Removes the service declared at `service`, including all of its attributes.
+    ///
+    /// Returns `Error::InvalidValue` if there's no service declaration at `service`.
+    pub fn remove_service(&mut self, service: Handle) -> Result<(), Error> {
+        let start = self.index(service).ok_or(Error::InvalidValue)?;
+        if !Self::is_service_decl(&self.entries[start]) {
+            return Err(Error::InvalidValue);
+        }
+        let len = self.entries[start + 1..]
+            .iter()
+            .take_while(|entry| !Self::is_service_decl(entry))
+            .count();
+        self.entries.drain(start..=start + len);
+        Ok(())
+    }
+
+    fn index(&self, handle: Handle) -> Option<usize> {
+        self.entries
+            .binary_search_by_key(&handle.as_u16(), |entry| entry.handle.as_u16())
+            .ok()
+    }
+
+    /// Returns the current value of the attribute at `handle`.
+    pub fn value(&self, handle: Handle) -> Option<&[u8]> {
+        self.index(handle).map(|i| &self.entries[i].value[..])
+    }
+
+    /// Changes the value of the attribute at `handle`.
+    ///
+    /// Returns `Error::InvalidValue` if there's no attribute with that handle.
+    pub fn set_value(&mut self, handle: Handle, value: &[u8]) -> Result<(), Error> {
+        let index = self.index(handle).ok_or(Error::InvalidValue)?;
+        let entry = &mut self.entries[index];
+        entry.value.clear();
+        entry.value.extend_from_slice(value);
+        Ok(())
+    }
+
+    fn is_service_decl(entry: &Entry) -> bool {
+        entry.att_type == PRIMARY_SERVICE || entry.att_type == SECONDARY_SERVICE
+    }
+}
+
+impl Default for DynamicTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AttributeProvider for DynamicTable {
+    fn for_attrs_in_range(
+        &mut self,
+        range: HandleRange,
+        mut f: impl FnMut(&Self, Attribute<'_>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        for entry in self.entries.iter().filter(|e| range.contains(e.handle)) {
+            f(
+                self,
+                Attribute {
+                    att_type: entry.att_type,
+                    handle: entry.handle,
+                    value: HexSlice(&entry.value[..]),
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    fn is_grouping_attr(&self, uuid: AttUuid) -> bool {
+        uuid == PRIMARY_SERVICE || uuid == SECONDARY_SERVICE
+    }
+
+    fn group_end(&self, handle: Handle) -> Option<Handle> {
+        let index = self.index(handle)?;
+        if !Self::is_service_decl(&self.entries[index]) {
+            return None;
+        }
+
+        let end = self.entries[index + 1..]
+            .iter()
+            .take_while(|entry| !Self::is_service_decl(entry))
+            .last()
+            .map_or(handle, |entry| entry.handle);
+        Some(end)
+    }
+
+    fn write_attr(&mut self, handle: Handle, value: &[u8]) -> Result<(), AttError> {
+        match self.index(handle) {
+            None => Err(AttError::new(ErrorCode::InvalidHandle, handle)),
+            Some(i) if !self.entries[i].writable => {
+                Err(AttError::new(ErrorCode::WriteNotPermitted, handle))
+            }
+            Some(i) => {
+                self.entries[i].value = value.to_vec();
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_and_remove() {
+        let mut table = DynamicTable::new();
+        let bas = table.add_service(Uuid16(0x180F)).unwrap();
+        let level = table
+            .add_characteristic(Uuid16(0x2A19), Properties::READ | Properties::NOTIFY, &[50])
+            .unwrap();
+        let dis = table.add_service(Uuid16(0x180A)).unwrap();
+        table
+            .add_characteristic(Uuid16(0x2A24), Properties::READ, b"rubble")
+            .unwrap();
+
+        assert_eq!(level.as_u16(), 3);
+        assert_eq!(table.group_end(bas).map(|h| h.as_u16()), Some(4));
+        assert_eq!(table.group_end(dis).map(|h| h.as_u16()), Some(7));
+
+        table.remove_service(bas).unwrap();
+        assert_eq!(table.value(level), None);
+        assert_eq!(table.remove_service(bas), Err(Error::InvalidValue));
+        table.remove_service(dis).unwrap();
+
+        // Handles of removed services aren't reused
+        assert_eq!(table.add_service(Uuid16(0x1800)).unwrap().as_u16(), 8);
+    }
+}
@@ -0,0 +1,534 @@
+//! *Environmental Sensing Service* (ESS).
+//!
+//! ESS exposes the readings of environmental sensors like thermometers, hygrometers and
+//! barometers. Every sensor gets its own characteristic, and a device with several sensors of the
+//! same kind (eg. an indoor and an outdoor thermometer) adds the characteristic multiple times.
+//! The *ES Measurement* descriptor of each instance tells the client how the value is sampled, and
+//! the *ES Trigger Setting* descriptor, which the client can write, controls when the value is
+//! notified.
+//!
+//! [`EnvironmentalSensingService`] adds the service and its sensors to an `AttributeTable` and
+//! evaluates the trigger settings:
+//!
+//! ```ignore
+//! let mut ess = EnvironmentalSensingService::<U4>::add_to(&mut table)?;
+//! let outdoor = ess.add_sensor(&mut table, Measurement::Temperature, &es_measurement, trigger)?;
+//!
+//! // New sample (in 0.01 °C); `now` is a seconds counter, eg. from an RTC
+//! if ess.set_value(&mut table, outdoor, -512, now)? {
+//!     att.notify_raw(outdoor, table.value(outdoor).unwrap());
+//! }
+//! // Time-based triggers are evaluated by `poll`
+//! while let Some(handle) = ess.poll(&table, now) { /* notify as above */ }
+//! ```
+//!
+//! Only a single trigger setting is supported per characteristic, so the optional *ES
+//! Configuration* descriptor is never added.
+//!
+//! [`EnvironmentalSensingService`]: struct.EnvironmentalSensingService.html
+
+use {
+    crate::{
+        att::{AttError, ErrorCode, Handle},
+        gatt::{
+            characteristic::Properties,
+            table::{AttributeTable, TableEntry},
+        },
+        uuid::Uuid16,
+        Error,
+    },
+    heapless::{ArrayLength, Vec},
+};
+
+/// UUID of the *Environmental Sensing* service.
+pub const ENVIRONMENTAL_SENSING: Uuid16 = Uuid16(0x181A);
+
+/// UUID of the *ES Measurement* descriptor.
+pub const ES_MEASUREMENT: Uuid16 = Uuid16(0x290C);
+
+/// UUID of the *ES Trigger Setting* descriptor.
+pub const ES_TRIGGER_SETTING: Uuid16 = Uuid16(0x290D);
+
+/// ATT error returned for trigger settings that are malformed.
+const WRITE_REQUEST_REJECTED: u8 = 0x80;
+
+/// ATT error returned for trigger conditions that aren't supported.
+const CONDITION_NOT_SUPPORTED: u8 = 0x81;
+
+/// The kind of value measured by a sensor.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Measurement {
+    /// Temperature in units of 0.01 °C (`sint16`).
+    Temperature,
+    /// Relative humidity in units of 0.01 % (`uint16`).
+    Humidity,
+    /// Pressure in units of 0.1 Pa (`uint32`).
+    Pressure,
+}
+
+impl Measurement {
+    /// Returns the UUID of the characteristic carrying this measurement.
+    pub fn uuid(&self) -> Uuid16 {
+        match self {
+            Measurement::Temperature => Uuid16(0x2A6E),
+            Measurement::Humidity => Uuid16(0x2A6F),
+            Measurement::Pressure => Uuid16(0x2A6D),
+        }
+    }
+
+    /// Returns the size of the characteristic value in Bytes.
+    pub fn size(&self) -> usize {
+        match self {
+            Measurement::Temperature | Measurement::Humidity => 2,
+            Measurement::Pressure => 4,
+        }
+    }
+
+    fn encode<'a>(&self, value: i32, buf: &'a mut [u8; 4]) -> Result<&'a [u8], Error> {
+        let in_range = match self {
+            Measurement::Temperature => {
+                (i32::from(i16::MIN)..=i32::from(i16::MAX)).contains(&value)
+            }
+            Measurement::Humidity => (0..=i32::from(u16::MAX)).contains(&value),
+            Measurement::Pressure => value >= 0,
+        };
+        if !in_range {
+            return Err(Error::InvalidValue);
+        }
+        *buf = value.to_le_bytes();
+        Ok(&buf[..self.size()])
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Option<i32> {
+        Some(match (self, bytes) {
+            (Measurement::Temperature, [lo, hi]) => i32::from(i16::from_le_bytes([*lo, *hi])),
+            (Measurement::Humidity, [lo, hi]) => i32::from(u16::from_le_bytes([*lo, *hi])),
+            (Measurement::Pressure, [a, b, c, d]) => {
+                let raw = u32::from_le_bytes([*a, *b, *c, *d]);
+                if raw > i32::MAX as u32 {
+                    return None;
+                }
+                raw as i32
+            }
+            _ => return None,
+        })
+    }
+}
+
+enum_with_unknown! {
+    /// How a sensor derives its value from the samples it takes.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum SamplingFunction(u8) {
+        Unspecified = 0x00,
+        Instantaneous = 0x01,
+        ArithmeticMean = 0x02,
+        Rms = 0x03,
+        Maximum = 0x04,
+        Minimum = 0x05,
+        Accumulated = 0x06,
+        Count = 0x07,
+    }
+}
+
+/// Value of the *ES Measurement* descriptor.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EsMeasurement {
+    pub sampling_function: SamplingFunction,
+    /// Period the sampling function is evaluated over, in seconds (24 bits, 0 if not in use).
+    pub measurement_period: u32,
+    /// How often the value is updated, in seconds (24 bits, 0 if not in use).
+    pub update_interval: u32,
+    /// What the value is used for (eg. `0x01` for air), as defined by ESS.
+    pub application: u8,
+    /// Uncertainty in units of 0.5 % of the value, or `0xFF` if unknown.
+    pub uncertainty: u8,
+}
+
+impl EsMeasurement {
+    /// Size of the encoded value in Bytes.
+    pub const SIZE: usize = 11;
+
+    /// Returns the encoded descriptor value.
+    pub fn to_array(&self) -> [u8; Self::SIZE] {
+        let period = self.measurement_period.to_le_bytes();
+        let interval = self.update_interval.to_le_bytes();
+        [
+            // Flags (reserved)
+            0,
+            0,
+            self.sampling_function.into(),
+            period[0],
+            period[1],
+            period[2],
+            interval[0],
+            interval[1],
+            interval[2],
+            self.application,
+            self.uncertainty,
+        ]
+    }
+}
+
+/// Value of the *ES Trigger Setting* descriptor: When a sensor's value is notified.
+///
+/// Values are given in the unit of the sensor's `Measurement`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TriggerCondition {
+    /// Never notify.
+    Inactive,
+    /// Notify periodically, every this many seconds.
+    FixedInterval(u32),
+    /// Notify changes, but not more often than every this many seconds.
+    NoLessThan(u32),
+    /// Notify every change.
+    ValueChanged,
+    LessThan(i32),
+    LessOrEqual(i32),
+    GreaterThan(i32),
+    GreaterOrEqual(i32),
+    Equal(i32),
+    NotEqual(i32),
+}
+
+impl TriggerCondition {
+    /// Encodes the condition for a sensor measuring `kind` into `buf`.
+    fn encode<'a>(&self, kind: Measurement, buf: &'a mut [u8; 5]) -> Result<&'a [u8], Error> {
+        let (condition, operand): (u8, Option<i32>) = match *self {
+            TriggerCondition::Inactive => (0x00, None),
+            TriggerCondition::FixedInterval(secs) => (0x01, Some(secs as i32)),
+            TriggerCondition::NoLessThan(secs) => (0x02, Some(secs as i32)),
+            TriggerCondition::ValueChanged => (0x03, None),
+            TriggerCondition::LessThan(v) => (0x04, Some(v)),
+            TriggerCondition::LessOrEqual(v) => (0x05, Some(v)),
+            TriggerCondition::GreaterThan(v) => (0x06, Some(v)),
+            TriggerCondition::GreaterOrEqual(v) => (0x07, Some(v)),
+            TriggerCondition::Equal(v) => (0x08, Some(v)),
+            TriggerCondition::NotEqual(v) => (0x09, Some(v)),
+        };
+        buf[0] = condition;
+        let len = match (condition, operand) {
+            (_, None) => 0,
+            (0x01, Some(secs)) | (0x02, Some(secs)) => {
+                if secs as u32 > 0x00FF_FFFF {
+                    return Err(Error::InvalidValue);
+                }
+                buf[1..4].copy_from_slice(&secs.to_le_bytes()[..3]);
+                3
+            }
+            (_, Some(v)) => {
+                let mut value = [0; 4];
+                let len = kind.encode(v, &mut value)?.len();
+                buf[1..=len].copy_from_slice(&value[..len]);
+                len
+            }
+        };
+        Ok(&buf[..=len])
+    }
+
+    /// Decodes a trigger setting written by the client for a sensor measuring `kind`.
+    ///
+    /// Returns the ESS-defined ATT error code on failure.
+    fn decode(kind: Measurement, bytes: &[u8]) -> Result<Self, u8> {
+        let (&condition, operand) = bytes.split_first().ok_or(WRITE_REQUEST_REJECTED)?;
+        let secs = || match operand {
+            [a, b, c] => Ok(u32::from_le_bytes([*a, *b, *c, 0])),
+            _ => Err(WRITE_REQUEST_REJECTED),
+        };
+        let value = || kind.decode(operand).ok_or(WRITE_REQUEST_REJECTED);
+        Ok(match condition {
+            0x00 if operand.is_empty() => TriggerCondition::Inactive,
+            0x01 => TriggerCondition::FixedInterval(secs()?),
+            0x02 => TriggerCondition::NoLessThan(secs()?),
+            0x03 if operand.is_empty() => TriggerCondition::ValueChanged,
+            0x04 => TriggerCondition::LessThan(value()?),
+            0x05 => TriggerCondition::LessOrEqual(value()?),
+            0x06 => TriggerCondition::GreaterThan(value()?),
+            0x07 => TriggerCondition::GreaterOrEqual(value()?),
+            0x08 => TriggerCondition::Equal(value()?),
+            0x09 => TriggerCondition::NotEqual(value()?),
+            0x00 | 0x03 => return Err(WRITE_REQUEST_REJECTED),
+            _ => return Err(CONDITION_NOT_SUPPORTED),
+        })
+    }
+
+    /// Returns whether the condition is met by the time and value change of a new sample.
+    fn is_met(&self, previous: Option<i32>, value: i32, elapsed: Option<u32>) -> bool {
+        let changed = previous != Some(value);
+        match *self {
+            TriggerCondition::Inactive | TriggerCondition::FixedInterval(_) => false,
+            TriggerCondition::NoLessThan(secs) => changed && elapsed.is_none_or(|e| e >= secs),
+            TriggerCondition::ValueChanged => changed,
+            TriggerCondition::LessThan(v) => value < v,
+            TriggerCondition::LessOrEqual(v) => value <= v,
+            TriggerCondition::GreaterThan(v) => value > v,
+            TriggerCondition::GreaterOrEqual(v) => value >= v,
+            TriggerCondition::Equal(v) => value == v,
+            TriggerCondition::NotEqual(v) => value != v,
+        }
+    }
+}
+
+/// A sensor characteristic added with `EnvironmentalSensingService::add_sensor`.
+#[derive(Debug, Clone)]
+pub struct Sensor {
+    kind: Measurement,
+    value: Handle,
+    trigger_handle: Handle,
+    trigger: TriggerCondition,
+    /// Value and time (in seconds) of the last notification.
+    last_notified: Option<(i32, u32)>,
+    /// Whether a value has been set since the last notification.
+    updated: bool,
+}
+
+impl Sensor {
+    /// Returns the kind of value measured by the sensor.
+    pub fn kind(&self) -> Measurement {
+        self.kind
+    }
+
+    /// Returns the handle of the sensor's characteristic value.
+    pub fn value_handle(&self) -> Handle {
+        self.value
+    }
+
+    /// Returns the current trigger condition.
+    pub fn trigger(&self) -> TriggerCondition {
+        self.trigger
+    }
+}
+
+/// The server side of ESS, hosted in an `AttributeTable`.
+///
+/// `S` is the maximum number of sensors.
+pub struct EnvironmentalSensingService<S: ArrayLength<Sensor>> {
+    sensors: Vec<Sensor, S>,
+}
+
+impl<S: ArrayLength<Sensor>> EnvironmentalSensingService<S> {
+    /// Adds the service to `table`.
+    ///
+    /// The sensors have to be added with `add_sensor` right after this, before adding any other
+    /// service.
+    pub fn add_to<N, B>(table: &mut AttributeTable<N, B>) -> Result<Self, Error>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        table.add_service(ENVIRONMENTAL_SENSING)?;
+        Ok(Self {
+            sensors: Vec::new(),
+        })
+    }
+
+    /// Adds a sensor characteristic measuring `kind`, and returns the handle of its value.
+    ///
+    /// `trigger` is the initial trigger condition, which the client may change. The sensor's value
+    /// is 0 until `set_value` is called.
+    pub fn add_sensor<N, B>(
+        &mut self,
+        table: &mut AttributeTable<N, B>,
+        kind: Measurement,
+        measurement: &EsMeasurement,
+        trigger: TriggerCondition,
+    ) -> Result<Handle, Error>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        if self.sensors.len() == self.sensors.capacity() {
+            return Err(Error::Eof);
+        }
+        let mut buf = [0; 5];
+        let trigger_value = trigger.encode(kind, &mut buf)?;
+
+        let value = table.add_characteristic(
+            kind.uuid(),
+            Properties::READ | Properties::NOTIFY,
+            &[0; 4][..kind.size()],
+        )?;
+        table.add_descriptor(ES_MEASUREMENT, &measurement.to_array(), false)?;
+        // Room for the longest condition, writes are validated by `write_attr`
+        let trigger_handle =
+            table.add_attribute(ES_TRIGGER_SETTING.into(), trigger_value, 5, true)?;
+        self.sensors
+            .push(Sensor {
+                kind,
+                value,
+                trigger_handle,
+                trigger,
+                last_notified: None,
+                updated: false,
+            })
+            .ok();
+        Ok(value)
+    }
+
+    /// Returns the sensors of the service.
+    pub fn sensors(&self) -> &[Sensor] {
+        &self.sensors
+    }
+
+    /// Updates the value of the sensor with value `handle` to a new sample.
+    ///
+    /// `now` is the current time in seconds, from any monotonic clock. Returns whether the trigger
+    /// condition is met and the client has enabled notifications, in which case the new value
+    /// (`table.value(handle)`) has to be notified. Returns `Error::InvalidValue` if `handle`
+    /// isn't a sensor or `value` is out of range for its measurement.
+    pub fn set_value<N, B>(
+        &mut self,
+        table: &mut AttributeTable<N, B>,
+        handle: Handle,
+        value: i32,
+        now: u32,
+    ) -> Result<bool, Error>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        let sensor = self
+            .sensors
+            .iter_mut()
+            .find(|s| s.value == handle)
+            .ok_or(Error::InvalidValue)?;
+        let mut buf = [0; 4];
+        table.set_value(handle, sensor.kind.encode(value, &mut buf)?)?;
+        sensor.updated = true;
+
+        let previous = sensor.last_notified.map(|(v, _)| v);
+        let elapsed = sensor.last_notified.map(|(_, t)| now.wrapping_sub(t));
+        let notify =
+            sensor.trigger.is_met(previous, value, elapsed) && table.notifications_enabled(handle);
+        if notify {
+            sensor.last_notified = Some((value, now));
+            sensor.updated = false;
+        }
+        Ok(notify)
+    }
+
+    /// Evaluates the time-based trigger conditions.
+    ///
+    /// Returns the value handle of a sensor whose notification is due, if there is one. Call this
+    /// repeatedly (eg. once per second) until it returns `None`.
+    pub fn poll<N, B>(&mut self, table: &AttributeTable<N, B>, now: u32) -> Option<Handle>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        for sensor in self.sensors.iter_mut() {
+            let due = match sensor.trigger {
+                TriggerCondition::FixedInterval(secs) => sensor
+                    .last_notified
+                    .is_none_or(|(_, t)| now.wrapping_sub(t) >= secs),
+                // A change that was suppressed because it came too early
+                TriggerCondition::NoLessThan(secs) => {
+                    sensor.updated
+                        && sensor.last_notified.is_none_or(|(v, t)| {
+                            now.wrapping_sub(t) >= secs
+                                && sensor.kind.decode(table.value(sensor.value).unwrap_or(&[]))
+                                    != Some(v)
+                        })
+                }
+                _ => false,
+            };
+            if due && table.notifications_enabled(sensor.value) {
+                let value = sensor.kind.decode(table.value(sensor.value)?)?;
+                sensor.last_notified = Some((value, now));
+                sensor.updated = false;
+                return Some(sensor.value);
+            }
+        }
+        None
+    }
+
+    /// Handles a write to the attribute at `handle`.
+    ///
+    /// Returns `None` if `handle` doesn't belong to the service, in which case the write should be
+    /// processed as usual. Trigger settings are validated and rejected with the ATT errors defined
+    /// by ESS.
+    pub fn write_attr<N, B>(
+        &mut self,
+        table: &mut AttributeTable<N, B>,
+        handle: Handle,
+        value: &[u8],
+    ) -> Option<Result<(), AttError>>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        let sensor = self
+            .sensors
+            .iter_mut()
+            .find(|s| s.trigger_handle == handle)?;
+        Some(match TriggerCondition::decode(sensor.kind, value) {
+            Ok(trigger) => {
+                sensor.trigger = trigger;
+                // Restart interval timing with the new setting
+                sensor.last_notified = None;
+                table.set_value(handle, value).ok();
+                Ok(())
+            }
+            Err(code) => Err(AttError::new(ErrorCode::from(code), handle)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, heapless::consts::*};
+
+    #[test]
+    fn triggers() {
+        let mut table = AttributeTable::<U16, U64>::new();
+        let mut ess = EnvironmentalSensingService::<U2>::add_to(&mut table).unwrap();
+        let meas = EsMeasurement {
+            sampling_function: SamplingFunction::Instantaneous,
+            measurement_period: 0,
+            update_interval: 60,
+            application: 0x01,
+            uncertainty: 0xFF,
+        };
+        let indoor = ess
+            .add_sensor(
+                &mut table,
+                Measurement::Temperature,
+                &meas,
+                TriggerCondition::ValueChanged,
+            )
+            .unwrap();
+        let outdoor = ess
+            .add_sensor(
+                &mut table,
+                Measurement::Temperature,
+                &meas,
+                TriggerCondition::FixedInterval(10),
+            )
+            .unwrap();
+        for handle in &[indoor, outdoor] {
+            let cccd = table.cccd_handle(*handle).unwrap();
+            table.set_value(cccd, &[0x01, 0x00]).unwrap();
+        }
+
+        assert!(ess.set_value(&mut table, indoor, 2150, 0).unwrap());
+        assert_eq!(table.value(indoor), Some(&[0x66, 0x08][..]));
+        assert!(!ess.set_value(&mut table, indoor, 2150, 1).unwrap());
+        assert!(!ess.set_value(&mut table, outdoor, -500, 1).unwrap());
+
+        assert_eq!(ess.poll(&table, 1), Some(outdoor));
+        assert_eq!(ess.poll(&table, 5), None);
+        assert_eq!(ess.poll(&table, 11), Some(outdoor));
+
+        // The client asks to be told when it's freezing outside
+        let trigger = ess.sensors()[1].trigger_handle;
+        assert!(ess
+            .write_attr(&mut table, trigger, &[0x0A])
+            .unwrap()
+            .is_err());
+        ess.write_attr(&mut table, trigger, &[0x04, 0x00, 0x00])
+            .unwrap()
+            .unwrap();
+        assert!(ess.set_value(&mut table, outdoor, -100, 12).unwrap());
+        assert!(!ess.set_value(&mut table, outdoor, 100, 13).unwrap());
+        assert!(ess.set_value(&mut table, indoor, 70_000, 14).is_err());
+    }
+}
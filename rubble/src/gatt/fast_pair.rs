@@ -0,0 +1,491 @@
+//! Google Fast Pair provider.
+//!
+//! [Fast Pair] lets Android phones (the *Seeker*) discover and pair with accessories (the
+//! *Provider*) with a single tap. The provider advertises its *Model ID* (registered with Google)
+//! while in pairing mode, and an *Account Key Filter* otherwise, so that phones logged into the
+//! same account recognize it.
+//!
+//! Pairing starts with the seeker writing a *Key-based Pairing* request, encrypted with a key that
+//! is derived via ECDH from the model's *Anti-Spoofing* key pair (or with a previously stored
+//! account key). `FastPairProvider` verifies the request and enables the following steps:
+//!
+//! 1. Regular LE Secure Connections pairing using numeric comparison. The seeker writes its
+//!    passkey to the *Passkey* characteristic, and the application has to confirm pairing if
+//!    `FastPairProvider::passkey_confirmed` returns `Some(true)`.
+//! 2. The seeker writes an *Account Key* for the user's account. It is persisted together with
+//!    the bond via `FastPairProvider::store_account_key`.
+//!
+//! The cryptographic primitives (AES decryption, P-256 ECDH with the anti-spoofing private key,
+//! SHA-256 and random numbers) are supplied by the application via [`FastPairCrypto`].
+//!
+//! [Fast Pair]: https://developers.google.com/nearby/fast-pair/spec
+//! [`FastPairCrypto`]: trait.FastPairCrypto.html
+
+use {
+    crate::{
+        att::{AttError, AttUuid, ErrorCode, Handle},
+        bond::BondStore,
+        gatt::{
+            characteristic::Properties,
+            table::{AttributeTable, TableEntry},
+        },
+        link::{privacy::Aes128, DeviceAddress},
+        uuid::{Uuid, Uuid16},
+        Error,
+    },
+    heapless::ArrayLength,
+};
+
+/// UUID of the Fast Pair service, also used for the advertised service data.
+pub const FAST_PAIR_SERVICE: Uuid16 = Uuid16(0xFE2C);
+
+// UUIDs are given in the Byte order used on air.
+
+/// UUID of the *Model ID* characteristic (`FE2C1233-8366-4814-8EB0-01DE32100BEA`).
+pub const MODEL_ID: AttUuid = AttUuid::Uuid128(Uuid::from_bytes([
+    0xEA, 0x0B, 0x10, 0x32, 0xDE, 0x01, 0xB0, 0x8E, 0x14, 0x48, 0x66, 0x83, 0x33, 0x12, 0x2C, 0xFE,
+]));
+
+/// UUID of the *Key-based Pairing* characteristic (`FE2C1234-...`).
+pub const KEY_BASED_PAIRING: AttUuid = AttUuid::Uuid128(Uuid::from_bytes([
+    0xEA, 0x0B, 0x10, 0x32, 0xDE, 0x01, 0xB0, 0x8E, 0x14, 0x48, 0x66, 0x83, 0x34, 0x12, 0x2C, 0xFE,
+]));
+
+/// UUID of the *Passkey* characteristic (`FE2C1235-...`).
+pub const PASSKEY: AttUuid = AttUuid::Uuid128(Uuid::from_bytes([
+    0xEA, 0x0B, 0x10, 0x32, 0xDE, 0x01, 0xB0, 0x8E, 0x14, 0x48, 0x66, 0x83, 0x35, 0x12, 0x2C, 0xFE,
+]));
+
+/// UUID of the *Account Key* characteristic (`FE2C1236-...`).
+pub const ACCOUNT_KEY: AttUuid = AttUuid::Uuid128(Uuid::from_bytes([
+    0xEA, 0x0B, 0x10, 0x32, 0xDE, 0x01, 0xB0, 0x8E, 0x14, 0x48, 0x66, 0x83, 0x36, 0x12, 0x2C, 0xFE,
+]));
+
+/// Maximum number of account keys kept in memory and advertised in the account key filter.
+pub const MAX_ACCOUNT_KEYS: usize = 5;
+
+/// After this many invalid Key-based Pairing requests, all further ones are rejected until the
+/// provider is recreated (the spec asks for this to prevent brute-forcing the keys).
+const MAX_FAILURES: u8 = 10;
+
+const MSG_KEY_BASED_PAIRING_REQUEST: u8 = 0x00;
+const MSG_KEY_BASED_PAIRING_RESPONSE: u8 = 0x01;
+const MSG_SEEKERS_PASSKEY: u8 = 0x02;
+const MSG_PROVIDERS_PASSKEY: u8 = 0x03;
+const MSG_ACCOUNT_KEY: u8 = 0x04;
+
+/// Cryptographic primitives needed by Fast Pair.
+pub trait FastPairCrypto: Aes128 {
+    /// Decrypts `block` in place using AES-128 with `key`.
+    fn decrypt_block(&mut self, key: &[u8; 16], block: &mut [u8; 16]);
+
+    /// Computes the ECDH shared secret of the model's anti-spoofing private key and the seeker's
+    /// `public_key`.
+    ///
+    /// The public key is given as X and Y coordinates, both big-endian. Returns `None` if the key
+    /// is not a valid point on P-256.
+    fn ecdh(&mut self, public_key: &[u8; 64]) -> Option<[u8; 32]>;
+
+    /// Computes the SHA-256 hash of `data`.
+    fn sha256(&mut self, data: &[u8]) -> [u8; 32];
+
+    /// Fills `buf` with random Bytes.
+    fn fill_random(&mut self, buf: &mut [u8]);
+}
+
+/// Returns the service data to advertise in pairing mode.
+///
+/// Broadcast it as `AdStructure::ServiceData16` with the `FAST_PAIR_SERVICE` UUID.
+pub fn model_id_data(model_id: u32) -> [u8; 3] {
+    let bytes = model_id.to_be_bytes();
+    [bytes[1], bytes[2], bytes[3]]
+}
+
+/// Service data advertised while not in pairing mode.
+#[derive(Debug, Copy, Clone)]
+pub struct AccountData {
+    buf: [u8; 13],
+    len: u8,
+}
+
+impl AccountData {
+    /// Returns the service data to advertise with the `FAST_PAIR_SERVICE` UUID.
+    pub fn data(&self) -> &[u8] {
+        &self.buf[..usize::from(self.len)]
+    }
+}
+
+/// The Fast Pair provider, hosted in an `AttributeTable`.
+pub struct FastPairProvider {
+    key_based_pairing: Handle,
+    passkey: Handle,
+    account_key: Handle,
+    /// The provider's address, most significant Byte first.
+    address: [u8; 6],
+    account_keys: [Option<[u8; 16]>; MAX_ACCOUNT_KEYS],
+    failures: u8,
+    /// The key established by the last valid Key-based Pairing request.
+    key: Option<[u8; 16]>,
+    local_passkey: Option<u32>,
+    seeker_passkey: Option<u32>,
+    new_account_key: Option<[u8; 16]>,
+    notification: Option<(Handle, [u8; 16])>,
+}
+
+impl FastPairProvider {
+    /// Adds the Fast Pair service to `table`.
+    ///
+    /// `address` is the address the provider advertises with, which seekers include in their
+    /// requests.
+    pub fn add_to<N, B>(
+        table: &mut AttributeTable<N, B>,
+        model_id: u32,
+        address: DeviceAddress,
+    ) -> Result<Self, Error>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        let props = Properties::WRITE | Properties::NOTIFY;
+        table.add_service(FAST_PAIR_SERVICE)?;
+        table.add_characteristic(MODEL_ID, Properties::READ, &model_id_data(model_id))?;
+        // None of the written values are stored, they are handled by `write_attr`
+        let key_based_pairing = table.add_characteristic(KEY_BASED_PAIRING, props, &[])?;
+        let passkey = table.add_characteristic(PASSKEY, props, &[])?;
+        let account_key = table.add_characteristic(ACCOUNT_KEY, Properties::WRITE, &[])?;
+
+        let mut msb_first = *address.raw();
+        msb_first.reverse();
+        Ok(Self {
+            key_based_pairing,
+            passkey,
+            account_key,
+            address: msb_first,
+            account_keys: [None; MAX_ACCOUNT_KEYS],
+            failures: 0,
+            key: None,
+            local_passkey: None,
+            seeker_passkey: None,
+            new_account_key: None,
+            notification: None,
+        })
+    }
+
+    /// Loads the account keys of all bonded seekers from `store`.
+    ///
+    /// If there are more than `MAX_ACCOUNT_KEYS`, only the first ones are used.
+    pub fn load_account_keys<S: BondStore>(&mut self, store: &mut S) -> Result<(), S::Error> {
+        let mut keys = [None; MAX_ACCOUNT_KEYS];
+        let mut slots = keys.iter_mut();
+        store.for_each(&mut |bond| {
+            if let Some(key) = bond.account_key {
+                if let Some(slot) = slots.next() {
+                    *slot = Some(key);
+                }
+            }
+        })?;
+        self.account_keys = keys;
+        Ok(())
+    }
+
+    /// Computes the account data to advertise while not in pairing mode.
+    ///
+    /// `salt` should be a new random value whenever the advertising address changes. The filter
+    /// makes seekers show a notification if one of the account keys belongs to them.
+    pub fn account_data(&self, crypto: &mut impl FastPairCrypto, salt: u8) -> AccountData {
+        let mut buf = [0; 13];
+        let count = self.account_keys.iter().flatten().count();
+        if count == 0 {
+            // Flags and an empty account key list
+            return AccountData { buf, len: 2 };
+        }
+
+        let size = count * 6 / 5 + 3;
+        buf[1] = (size as u8) << 4;
+        let filter = &mut buf[2..2 + size];
+        for key in self.account_keys.iter().flatten() {
+            let mut input = [0; 17];
+            input[..16].copy_from_slice(key);
+            input[16] = salt;
+            let hash = crypto.sha256(&input);
+            for chunk in hash.chunks(4) {
+                let x = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+                let bit = x as usize % (size * 8);
+                filter[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+        buf[2 + size] = 0x11; // length 1, type salt
+        buf[3 + size] = salt;
+        AccountData {
+            buf,
+            len: (4 + size) as u8,
+        }
+    }
+
+    /// Handles a write to the attribute at `handle`.
+    ///
+    /// Returns `None` if `handle` doesn't belong to the service, in which case the write should be
+    /// processed as usual.
+    pub fn write_attr(
+        &mut self,
+        crypto: &mut impl FastPairCrypto,
+        handle: Handle,
+        value: &[u8],
+    ) -> Option<Result<(), AttError>> {
+        let result = if handle == self.key_based_pairing {
+            self.key_based_pairing(crypto, value)
+        } else if handle == self.passkey {
+            self.decrypt(crypto, value, MSG_SEEKERS_PASSKEY)
+                .map(|block| {
+                    let passkey = u32::from_be_bytes([0, block[1], block[2], block[3]]);
+                    self.seeker_passkey = Some(passkey);
+                    self.compare_passkeys(crypto);
+                })
+        } else if handle == self.account_key {
+            self.decrypt(crypto, value, MSG_ACCOUNT_KEY).map(|block| {
+                self.new_account_key = Some(block);
+                self.remember(block);
+            })
+        } else {
+            return None;
+        };
+        Some(result.map_err(|code| AttError::new(code, handle)))
+    }
+
+    /// Sets the passkey displayed by the Security Manager for numeric comparison.
+    pub fn set_local_passkey(&mut self, crypto: &mut impl FastPairCrypto, passkey: u32) {
+        self.local_passkey = Some(passkey);
+        self.compare_passkeys(crypto);
+    }
+
+    /// Returns whether the seeker's passkey matches the local one, once both are known.
+    ///
+    /// The application should confirm the numeric comparison if this returns `Some(true)` and
+    /// reject it if it returns `Some(false)`.
+    pub fn passkey_confirmed(&self) -> Option<bool> {
+        match (self.local_passkey, self.seeker_passkey) {
+            (Some(local), Some(seeker)) => Some(local == seeker),
+            _ => None,
+        }
+    }
+
+    /// Returns the next notification to send, as handle and value.
+    ///
+    /// Send it with `AttributeServerTx::notify_raw`.
+    pub fn take_notification(&mut self) -> Option<(Handle, [u8; 16])> {
+        self.notification.take()
+    }
+
+    /// Stores the account key written by the seeker in its bond.
+    ///
+    /// This must be called after pairing with the seeker has completed and its bond was stored,
+    /// with its identity `address`. Returns whether there was an account key to store.
+    pub fn store_account_key<S: BondStore>(
+        &mut self,
+        store: &mut S,
+        address: &DeviceAddress,
+    ) -> Result<bool, S::Error> {
+        let key = match self.new_account_key {
+            Some(key) => key,
+            None => return Ok(false),
+        };
+        let mut bond = match store.load(address)? {
+            Some(bond) => bond,
+            None => return Ok(false),
+        };
+        bond.account_key = Some(key);
+        store.store(&bond)?;
+        self.new_account_key = None;
+        Ok(true)
+    }
+
+    /// Forgets all state of the current pairing attempt, eg. when the connection was closed.
+    pub fn reset(&mut self) {
+        self.key = None;
+        self.local_passkey = None;
+        self.seeker_passkey = None;
+        self.notification = None;
+    }
+
+    fn key_based_pairing(
+        &mut self,
+        crypto: &mut impl FastPairCrypto,
+        value: &[u8],
+    ) -> Result<(), ErrorCode> {
+        if self.failures >= MAX_FAILURES {
+            return Err(ErrorCode::WriteNotPermitted);
+        }
+
+        let mut candidates = [None; MAX_ACCOUNT_KEYS];
+        match value.len() {
+            // Encrypted request followed by the seeker's public key
+            80 => {
+                let mut public_key = [0; 64];
+                public_key.copy_from_slice(&value[16..]);
+                let secret = crypto.ecdh(&public_key).ok_or(ErrorCode::ValueNotAllowed)?;
+                let mut key = [0; 16];
+                key.copy_from_slice(&crypto.sha256(&secret)[..16]);
+                candidates[0] = Some(key);
+            }
+            // Encrypted with one of the account keys
+            16 => candidates = self.account_keys,
+            _ => return Err(ErrorCode::InvalidAttributeValueLength),
+        }
+
+        let mut request = [0; 16];
+        request.copy_from_slice(&value[..16]);
+        let valid = candidates.iter().flatten().copied().find(|key| {
+            let mut block = request;
+            crypto.decrypt_block(key, &mut block);
+            block[0] == MSG_KEY_BASED_PAIRING_REQUEST && block[2..8] == self.address
+        });
+        let key = match valid {
+            Some(key) => key,
+            None => {
+                self.failures += 1;
+                return Err(ErrorCode::UnlikelyError);
+            }
+        };
+
+        let mut response = [0; 16];
+        response[0] = MSG_KEY_BASED_PAIRING_RESPONSE;
+        response[1..7].copy_from_slice(&self.address);
+        crypto.fill_random(&mut response[7..]);
+        crypto.encrypt_block(&key, &mut response);
+        self.notification = Some((self.key_based_pairing, response));
+        self.key = Some(key);
+        self.local_passkey = None;
+        self.seeker_passkey = None;
+        Ok(())
+    }
+
+    /// Decrypts a 16-Byte message of type `ty` using the key of the current pairing attempt.
+    fn decrypt(
+        &self,
+        crypto: &mut impl FastPairCrypto,
+        value: &[u8],
+        ty: u8,
+    ) -> Result<[u8; 16], ErrorCode> {
+        let key = self.key.ok_or(ErrorCode::WriteNotPermitted)?;
+        if value.len() != 16 {
+            return Err(ErrorCode::InvalidAttributeValueLength);
+        }
+        let mut block = [0; 16];
+        block.copy_from_slice(value);
+        crypto.decrypt_block(&key, &mut block);
+        if block[0] == ty {
+            Ok(block)
+        } else {
+            Err(ErrorCode::UnlikelyError)
+        }
+    }
+
+    /// Notifies the local passkey once the seeker's passkey has been received.
+    fn compare_passkeys(&mut self, crypto: &mut impl FastPairCrypto) {
+        let (key, local) = match (self.key, self.local_passkey, self.seeker_passkey) {
+            (Some(key), Some(local), Some(_)) => (key, local),
+            _ => return,
+        };
+        let mut block = [0; 16];
+        block[0] = MSG_PROVIDERS_PASSKEY;
+        block[1..4].copy_from_slice(&local.to_be_bytes()[1..]);
+        crypto.fill_random(&mut block[4..]);
+        crypto.encrypt_block(&key, &mut block);
+        self.notification = Some((self.passkey, block));
+    }
+
+    /// Adds `key` to the in-memory account key list, dropping the oldest key if it is full.
+    fn remember(&mut self, key: [u8; 16]) {
+        if self.account_keys.contains(&Some(key)) {
+            return;
+        }
+        self.account_keys.rotate_right(1);
+        self.account_keys[0] = Some(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::link::AddressKind, heapless::consts::*};
+
+    /// XOR "encryption" and fake key agreement.
+    struct FakeCrypto;
+
+    impl Aes128 for FakeCrypto {
+        fn encrypt_block(&mut self, key: &[u8; 16], block: &mut [u8; 16]) {
+            for (b, k) in block.iter_mut().zip(key) {
+                *b ^= k;
+            }
+        }
+    }
+
+    impl FastPairCrypto for FakeCrypto {
+        fn decrypt_block(&mut self, key: &[u8; 16], block: &mut [u8; 16]) {
+            self.encrypt_block(key, block);
+        }
+
+        fn ecdh(&mut self, public_key: &[u8; 64]) -> Option<[u8; 32]> {
+            let mut secret = [0; 32];
+            secret.copy_from_slice(&public_key[..32]);
+            Some(secret)
+        }
+
+        fn sha256(&mut self, data: &[u8]) -> [u8; 32] {
+            let mut hash = [0; 32];
+            hash[..data.len().min(32)].copy_from_slice(&data[..data.len().min(32)]);
+            hash
+        }
+
+        fn fill_random(&mut self, buf: &mut [u8]) {
+            for b in buf {
+                *b = 0x55;
+            }
+        }
+    }
+
+    #[test]
+    fn key_based_pairing() {
+        let address = DeviceAddress::new([6, 5, 4, 3, 2, 1], AddressKind::Random);
+        let mut table = AttributeTable::<U16, U256>::new();
+        let mut fp = FastPairProvider::add_to(&mut table, 0x00_AB_CD_EF, address).unwrap();
+        let crypto = &mut FakeCrypto;
+
+        // The fake ECDH and SHA-256 make the key the first 16 Bytes of the public key
+        let key = [0x42; 16];
+        let mut block = [0; 16];
+        block[2..8].copy_from_slice(&[1, 2, 3, 4, 5, 6]);
+        crypto.encrypt_block(&key, &mut block);
+        let mut request = [0; 80];
+        request[..16].copy_from_slice(&block);
+        request[16..32].copy_from_slice(&key);
+
+        let kbp = fp.key_based_pairing;
+        request[3] ^= 1;
+        assert!(fp.write_attr(crypto, kbp, &request).unwrap().is_err());
+        request[3] ^= 1;
+        assert!(fp.write_attr(crypto, kbp, &request).unwrap().is_ok());
+        let (handle, mut response) = fp.take_notification().unwrap();
+        assert_eq!(handle, kbp);
+        crypto.decrypt_block(&key, &mut response);
+        assert_eq!(response[..7], [0x01, 1, 2, 3, 4, 5, 6]);
+
+        let mut passkey = [0; 16];
+        passkey[..4].copy_from_slice(&[0x02, 0x01, 0xE2, 0x40]);
+        crypto.encrypt_block(&key, &mut passkey);
+        fp.write_attr(crypto, fp.passkey, &passkey)
+            .unwrap()
+            .unwrap();
+        assert_eq!(fp.passkey_confirmed(), None);
+        fp.set_local_passkey(crypto, 123456);
+        assert_eq!(fp.passkey_confirmed(), Some(true));
+        assert_eq!(fp.take_notification().unwrap().0, fp.passkey);
+
+        let mut account_key = [0x04; 16];
+        crypto.encrypt_block(&key, &mut account_key);
+        fp.write_attr(crypto, fp.account_key, &account_key)
+            .unwrap()
+            .unwrap();
+        assert_eq!(fp.new_account_key, Some([0x04; 16]));
+        assert_eq!(fp.account_data(crypto, 0x99).data().len(), 4 + 4);
+    }
+}
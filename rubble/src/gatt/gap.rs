@@ -0,0 +1,392 @@
+//! The GAP service (*Generic Access*, `0x1800`).
+//!
+//! Every GATT server must contain exactly one GAP service. It exposes the device name and
+//! appearance, and optionally the connection parameters the peripheral would like the central to
+//! use (*Peripheral Preferred Connection Parameters*, PPCP) and whether the device supports
+//! resolving privately addressed peers (*Central Address Resolution*).
+//!
+//! Characteristics are assigned consecutive handles in the following order, omitting the ones
+//! that aren't configured:
+//!
+//! ```notrust
+//! Handle  Type                        Value
+//! 0x0001  Primary Service             0x1800 (Generic Access)
+//! 0x0002  Characteristic              READ (| WRITE), 0x0003, 0x2A00
+//! 0x0003  Device Name                 <name>
+//! 0x0004  Characteristic              READ, 0x0005, 0x2A01
+//! 0x0005  Appearance                  <appearance>
+//! ....    Characteristic              READ, ...., 0x2A04
+//! ....    PPCP                        <preferred connection parameters>
+//! ....    Characteristic              READ, ...., 0x2AA6
+//! ....    Central Address Resolution  <0 or 1>
+//! ```
+
+use {
+    crate::{
+        att::{AttError, AttUuid, Attribute, AttributeProvider, ErrorCode, Handle, HandleRange},
+        bytes::{ByteWriter, ToBytes},
+        gatt::characteristic::{Appearance, Properties},
+        link::ad_structure::AdStructure,
+        time::Duration,
+        utils::{truncate_utf8, HexSlice},
+        uuid::Uuid16,
+        Error,
+    },
+    core::{cmp, str},
+};
+
+/// Connection parameters the peripheral would like the central to use.
+///
+/// When exposed via the GAP service, well-behaved centrals will pick connection parameters from
+/// this range without the peripheral having to request a connection parameter update.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PreferredConnectionParams {
+    /// Minimum connection interval in units of 1.25 ms.
+    interval_min: u16,
+    /// Maximum connection interval in units of 1.25 ms.
+    interval_max: u16,
+    slave_latency: u16,
+    /// Supervision timeout in units of 10 ms.
+    supervision_timeout: u16,
+}
+
+impl PreferredConnectionParams {
+    /// Creates a new set of preferred connection parameters.
+    ///
+    /// # Parameters
+    ///
+    /// * `min_interval`, `max_interval`: Preferred range of the connection interval. Both will be
+    ///   rounded down to units of 1.25 ms and clamped to the valid range of 7.5 ms to 4 s.
+    /// * `slave_latency`: Number of connection events the peripheral may skip. Must be at most 499.
+    /// * `supervision_timeout`: Link supervision timeout. Will be rounded down to units of 10 ms
+    ///   and clamped to the valid range of 100 ms to 32 s.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if `min_interval > max_interval` or `slave_latency` is out of range.
+    pub fn new(
+        min_interval: Duration,
+        max_interval: Duration,
+        slave_latency: u16,
+        supervision_timeout: Duration,
+    ) -> Self {
+        assert!(min_interval <= max_interval);
+        assert!(slave_latency <= 499);
+
+        let min = (min_interval.as_micros() / 1_250).clamp(6, 3200);
+        let max = (max_interval.as_micros() / 1_250).clamp(6, 3200);
+        let timeout = (supervision_timeout.as_micros() / 10_000).clamp(10, 3200);
+
+        Self {
+            interval_min: min as u16,
+            interval_max: max as u16,
+            slave_latency,
+            supervision_timeout: timeout as u16,
+        }
+    }
+
+    /// Returns the minimum preferred connection interval.
+    pub fn min_conn_interval(&self) -> Duration {
+        Duration::from_micros(u32::from(self.interval_min) * 1_250)
+    }
+
+    /// Returns the maximum preferred connection interval.
+    pub fn max_conn_interval(&self) -> Duration {
+        Duration::from_micros(u32::from(self.interval_max) * 1_250)
+    }
+
+    /// Returns the preferred slave latency in number of connection events.
+    pub fn slave_latency(&self) -> u16 {
+        self.slave_latency
+    }
+
+    /// Returns the preferred supervision timeout.
+    pub fn supervision_timeout(&self) -> Duration {
+        Duration::from_micros(u32::from(self.supervision_timeout) * 10_000)
+    }
+}
+
+impl ToBytes for PreferredConnectionParams {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u16_le(self.interval_min)?;
+        writer.write_u16_le(self.interval_max)?;
+        writer.write_u16_le(self.slave_latency)?;
+        writer.write_u16_le(self.supervision_timeout)?;
+        Ok(())
+    }
+}
+
+const PRIMARY_SERVICE: Uuid16 = Uuid16(0x2800);
+const CHARACTERISTIC: Uuid16 = Uuid16(0x2803);
+const DEVICE_NAME: Uuid16 = Uuid16(0x2A00);
+const APPEARANCE: Uuid16 = Uuid16(0x2A01);
+const PPCP: Uuid16 = Uuid16(0x2A04);
+const CENTRAL_ADDRESS_RESOLUTION: Uuid16 = Uuid16(0x2AA6);
+
+/// Maximum length of the device name stored by `GapServiceAttrs`, in Bytes.
+pub const MAX_DEVICE_NAME_LEN: usize = 32;
+
+/// The characteristics that can be part of the GAP service.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum GapChar {
+    DeviceName,
+    Appearance,
+    Ppcp,
+    CentralAddressResolution,
+}
+
+impl GapChar {
+    fn uuid(self) -> Uuid16 {
+        match self {
+            GapChar::DeviceName => DEVICE_NAME,
+            GapChar::Appearance => APPEARANCE,
+            GapChar::Ppcp => PPCP,
+            GapChar::CentralAddressResolution => CENTRAL_ADDRESS_RESOLUTION,
+        }
+    }
+}
+
+/// An `AttributeProvider` hosting the GAP service.
+pub struct GapServiceAttrs {
+    name: [u8; MAX_DEVICE_NAME_LEN],
+    name_len: u8,
+    name_writable: bool,
+    appearance: [u8; 2],
+    ppcp: Option<[u8; 8]>,
+    central_address_resolution: Option<[u8; 1]>,
+
+    /// The characteristics present in the service, in handle order.
+    chars: [Option<GapChar>; 4],
+
+    /// Characteristic declaration values, indexed like `chars`.
+    decls: [[u8; 5]; 4],
+}
+
+impl GapServiceAttrs {
+    /// Creates a GAP service exposing `device_name` and `appearance`.
+    ///
+    /// If `device_name` is longer than `MAX_DEVICE_NAME_LEN` Bytes, it is truncated to the
+    /// characters that fit.
+    pub fn new(device_name: &str, appearance: Appearance) -> Self {
+        let mut this = Self {
+            name: [0; MAX_DEVICE_NAME_LEN],
+            name_len: 0,
+            name_writable: false,
+            appearance: (appearance as u16).to_le_bytes(),
+            ppcp: None,
+            central_address_resolution: None,
+            chars: [None; 4],
+            decls: [[0; 5]; 4],
+        };
+        this.set_device_name(truncate_utf8(device_name, MAX_DEVICE_NAME_LEN))
+            .unwrap();
+        this.update_layout();
+        this
+    }
+
+    /// Allows the connected client to change the device name by writing to the *Device Name*
+    /// characteristic.
+    pub fn with_writable_name(mut self) -> Self {
+        self.name_writable = true;
+        self.update_layout();
+        self
+    }
+
+    /// Adds the *Peripheral Preferred Connection Parameters* characteristic to the service.
+    pub fn with_preferred_params(mut self, params: PreferredConnectionParams) -> Self {
+        let mut buf = [0; 8];
+        params.to_bytes(&mut ByteWriter::new(&mut buf)).unwrap();
+        self.ppcp = Some(buf);
+        self.update_layout();
+        self
+    }
+
+    /// Adds the *Central Address Resolution* characteristic to the service.
+    ///
+    /// `supported` indicates whether this device supports address resolution (ie. it will accept
+    /// directed advertisements using a resolvable private address as the target address).
+    pub fn with_central_address_resolution(mut self, supported: bool) -> Self {
+        self.central_address_resolution = Some([supported as u8]);
+        self.update_layout();
+        self
+    }
+
+    /// Returns the current device name.
+    ///
+    /// If the name is writable, this might have been changed by the client.
+    pub fn device_name(&self) -> &str {
+        // Only valid UTF-8 is ever stored in `name`
+        str::from_utf8(&self.name[..usize::from(self.name_len)]).unwrap()
+    }
+
+    /// Returns the AD structure advertising the device name, if it may occupy up to `max_len`
+    /// Bytes.
+    ///
+    /// This is a `ShortenedLocalName` if the name doesn't fit (see `AdStructure::local_name`).
+    pub fn local_name(&self, max_len: usize) -> AdStructure<'_> {
+        AdStructure::local_name(self.device_name(), max_len)
+    }
+
+    /// Changes the device name exposed by the service.
+    ///
+    /// Returns `Error::InvalidLength` if `name` is longer than `MAX_DEVICE_NAME_LEN` Bytes.
+    pub fn set_device_name(&mut self, name: &str) -> Result<(), Error> {
+        let bytes = name.as_bytes();
+        if bytes.len() > MAX_DEVICE_NAME_LEN {
+            return Err(Error::InvalidLength);
+        }
+
+        self.name[..bytes.len()].copy_from_slice(bytes);
+        self.name_len = bytes.len() as u8;
+        Ok(())
+    }
+
+    /// Returns the appearance value exposed by the service.
+    ///
+    /// Pass this to `AdvertisingSetBuilder::appearance` to advertise the same value.
+    pub fn appearance(&self) -> u16 {
+        u16::from_le_bytes(self.appearance)
+    }
+
+    /// Changes the appearance value exposed by the service.
+    pub fn set_appearance(&mut self, appearance: Appearance) {
+        self.appearance = (appearance as u16).to_le_bytes();
+    }
+
+    /// Recomputes the handle layout after a characteristic was added or changed.
+    fn update_layout(&mut self) {
+        let chars = [
+            Some(GapChar::DeviceName),
+            Some(GapChar::Appearance),
+            self.ppcp.map(|_| GapChar::Ppcp),
+            self.central_address_resolution
+                .map(|_| GapChar::CentralAddressResolution),
+        ];
+
+        self.chars = [None; 4];
+        for (i, ch) in chars.iter().filter_map(|ch| *ch).enumerate() {
+            let props = if ch == GapChar::DeviceName && self.name_writable {
+                Properties::READ | Properties::WRITE
+            } else {
+                Properties::READ
+            };
+            let value_handle = self.value_handle(i).to_le_bytes();
+            let uuid = ch.uuid().0.to_le_bytes();
+
+            self.chars[i] = Some(ch);
+            self.decls[i] = [
+                props.bits(),
+                value_handle[0],
+                value_handle[1],
+                uuid[0],
+                uuid[1],
+            ];
+        }
+    }
+
+    /// Returns the raw handle of the value attribute of the characteristic at `index`.
+    ///
+    /// The declaration is located at the handle right before this.
+    fn value_handle(&self, index: usize) -> u16 {
+        // Service declaration is at 0x0001, followed by (declaration, value) pairs
+        0x0003 + 2 * index as u16
+    }
+
+    /// Returns the handle of the last attribute in the service.
+    fn last_handle(&self) -> Handle {
+        let num_chars = self.chars.iter().filter(|ch| ch.is_some()).count();
+        Handle::from_raw(self.value_handle(num_chars - 1))
+    }
+
+    /// Looks up the characteristic whose declaration or value is at `handle`.
+    ///
+    /// Returns the characteristic's index and whether `handle` refers to the value attribute.
+    fn lookup(&self, handle: u16) -> Option<(usize, bool)> {
+        if handle < 0x0002 {
+            return None;
+        }
+
+        let index = usize::from((handle - 0x0002) / 2);
+        let is_value = handle % 2 == 1;
+        match self.chars.get(index) {
+            Some(Some(_)) => Some((index, is_value)),
+            _ => None,
+        }
+    }
+
+    fn char_value(&self, ch: GapChar) -> &[u8] {
+        match ch {
+            GapChar::DeviceName => &self.name[..usize::from(self.name_len)],
+            GapChar::Appearance => &self.appearance,
+            GapChar::Ppcp => self.ppcp.as_ref().unwrap(),
+            GapChar::CentralAddressResolution => self.central_address_resolution.as_ref().unwrap(),
+        }
+    }
+
+    /// Returns the attribute with the given raw handle.
+    ///
+    /// `handle` must be in range `1..=self.last_handle()`.
+    fn attr(&self, handle: u16) -> Attribute<'_> {
+        let (att_type, value): (Uuid16, &[u8]) = if handle == 0x0001 {
+            (PRIMARY_SERVICE, &[0x00, 0x18])
+        } else {
+            let (index, is_value) = self.lookup(handle).unwrap();
+            let ch = self.chars[index].unwrap();
+            if is_value {
+                (ch.uuid(), self.char_value(ch))
+            } else {
+                (CHARACTERISTIC, &self.decls[index])
+            }
+        };
+
+        Attribute {
+            att_type: att_type.into(),
+            handle: Handle::from_raw(handle),
+            value: HexSlice(value),
+        }
+    }
+}
+
+impl AttributeProvider for GapServiceAttrs {
+    fn for_attrs_in_range(
+        &mut self,
+        range: HandleRange,
+        mut f: impl FnMut(&Self, Attribute<'_>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let start = cmp::max(range.start().as_u16(), 0x0001);
+        let end = cmp::min(range.end().as_u16(), self.last_handle().as_u16());
+
+        for handle in start..=end {
+            f(self, self.attr(handle))?;
+        }
+        Ok(())
+    }
+
+    fn is_grouping_attr(&self, uuid: AttUuid) -> bool {
+        uuid == PRIMARY_SERVICE
+    }
+
+    fn group_end(&self, handle: Handle) -> Option<Handle> {
+        match handle.as_u16() {
+            0x0001 => Some(self.last_handle()),
+            _ => None,
+        }
+    }
+
+    fn write_attr(&mut self, handle: Handle, value: &[u8]) -> Result<(), AttError> {
+        let ch = match self.lookup(handle.as_u16()) {
+            Some((index, true)) => self.chars[index].unwrap(),
+            Some((_, false)) => return Err(AttError::new(ErrorCode::WriteNotPermitted, handle)),
+            None => return Err(AttError::new(ErrorCode::InvalidHandle, handle)),
+        };
+
+        if ch != GapChar::DeviceName || !self.name_writable {
+            return Err(AttError::new(ErrorCode::WriteNotPermitted, handle));
+        }
+
+        let name =
+            str::from_utf8(value).map_err(|_| AttError::new(ErrorCode::ValueNotAllowed, handle))?;
+        self.set_device_name(name)
+            .map_err(|_| AttError::new(ErrorCode::InvalidAttributeValueLength, handle))
+    }
+}
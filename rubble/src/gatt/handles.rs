@@ -0,0 +1,333 @@
+//! Attribute handle allocation.
+//!
+//! Clients that are bonded with a server may cache the server's attribute handles (that's the
+//! whole point of bonding, in part). If a firmware update moves attributes to different handles,
+//! these clients will misbehave until they rediscover the database.
+//!
+//! To make this less likely, `HandleAllocator` can reserve a block of handles for every service,
+//! leaving a gap after the service's last attribute. New characteristics can later be added into
+//! this gap without moving any of the following services.
+//!
+//! Handles are always handed out in ascending order. As long as services are allocated in the
+//! same order with the same block sizes, and new attributes are only appended at the end of a
+//! block, all previously assigned handles stay the same.
+//!
+//! Databases generated by `rubble-gatt-codegen` can pin services and characteristics to explicit
+//! handles, which is checked when the firmware is built.
+//!
+//! When the layout does change, bonded clients have to be told with a *Service Changed*
+//! indication. `layout_hash` fingerprints the parts of the database clients cache, so storing it
+//! with each bond allows `needs_service_changed` to only send the indication to clients whose
+//! cached layout is really out of date.
+
+use crate::{
+    att::{AttUuid, Attribute, AttributeProvider, Handle, HandleRange},
+    bytes::{ByteWriter, ToBytes},
+    uuid::Uuid16,
+    Error,
+};
+
+/// The largest valid attribute handle.
+const MAX_HANDLE: u32 = 0xFFFF;
+
+/// Hands out attribute handles in ascending order.
+#[derive(Debug, Clone)]
+pub struct HandleAllocator {
+    /// The next handle to hand out. Can be `MAX_HANDLE + 1` when all handles are exhausted.
+    next: u32,
+}
+
+impl HandleAllocator {
+    /// Creates an allocator that starts handing out handles at `0x0001`.
+    pub fn new() -> Self {
+        Self { next: 1 }
+    }
+
+    /// Returns the handle that will be returned by the next call to `allocate`, without allocating
+    /// it.
+    pub fn peek(&self) -> Option<Handle> {
+        if self.next <= MAX_HANDLE {
+            Some(Handle::from_raw(self.next as u16))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the number of handles left to be allocated.
+    pub fn remaining(&self) -> u32 {
+        MAX_HANDLE + 1 - self.next
+    }
+
+    /// Allocates a single handle.
+    ///
+    /// Returns `None` when all handles have been allocated.
+    pub fn allocate(&mut self) -> Option<Handle> {
+        let handle = self.peek()?;
+        self.next += 1;
+        Some(handle)
+    }
+
+    /// Reserves a block of `count` consecutive handles.
+    ///
+    /// Returns `None` when there aren't enough handles left.
+    pub fn reserve(&mut self, count: u16) -> Option<HandleBlock> {
+        if count == 0 || self.remaining() < u32::from(count) {
+            return None;
+        }
+
+        let start = self.next;
+        self.next += u32::from(count);
+        Some(HandleBlock {
+            start: start as u16,
+            end: (self.next - 1) as u16,
+            next: start,
+        })
+    }
+
+    /// Skips ahead so that the next allocated handle is `handle`.
+    ///
+    /// This can be used to place a service at a fixed handle. Returns `Error::InvalidValue` if
+    /// `handle` (or a later handle) was already allocated.
+    pub fn skip_to(&mut self, handle: Handle) -> Result<(), Error> {
+        let handle = u32::from(handle.as_u16());
+        if handle < self.next || handle == 0 {
+            Err(Error::InvalidValue)
+        } else {
+            self.next = handle;
+            Ok(())
+        }
+    }
+}
+
+impl Default for HandleAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A block of consecutive handles reserved by `HandleAllocator::reserve`.
+#[derive(Debug, Clone)]
+pub struct HandleBlock {
+    start: u16,
+    end: u16,
+    next: u32,
+}
+
+impl HandleBlock {
+    /// Returns the first handle in the block.
+    pub fn start(&self) -> Handle {
+        Handle::from_raw(self.start)
+    }
+
+    /// Returns the last handle in the block.
+    ///
+    /// When the block is used for a service, this is the service's group end handle.
+    pub fn end(&self) -> Handle {
+        Handle::from_raw(self.end)
+    }
+
+    /// Returns the range of handles covered by the block.
+    pub fn range(&self) -> HandleRange {
+        HandleRange::new(self.start(), self.end())
+    }
+
+    /// Returns the handle that will be returned by the next call to `allocate`.
+    pub fn peek(&self) -> Option<Handle> {
+        if self.next <= u32::from(self.end) {
+            Some(Handle::from_raw(self.next as u16))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the number of handles in the block that haven't been allocated yet.
+    pub fn remaining(&self) -> u32 {
+        u32::from(self.end) + 1 - self.next
+    }
+
+    /// Allocates the next handle in the block.
+    ///
+    /// Returns `None` when the block is exhausted.
+    pub fn allocate(&mut self) -> Option<Handle> {
+        let handle = self.peek()?;
+        self.next += 1;
+        Some(handle)
+    }
+}
+
+/// Computes the group end handle of the grouping attribute at `handle`.
+///
+/// `attrs` must be sorted by handle. A group extends up to (but not including) the next grouping
+/// attribute, as determined by `is_grouping_attr`, or to the end of `attrs`.
+///
+/// Returns `None` if there's no grouping attribute at `handle`.
+///
+/// This is useful for implementing `AttributeProvider::group_end` for attribute arrays.
+pub fn group_end(
+    attrs: &[Attribute<'_>],
+    handle: Handle,
+    is_grouping_attr: impl Fn(AttUuid) -> bool,
+) -> Option<Handle> {
+    let index = attrs.iter().position(|attr| attr.handle == handle)?;
+    if !is_grouping_attr(attrs[index].att_type) {
+        return None;
+    }
+
+    let end = attrs[index + 1..]
+        .iter()
+        .take_while(|attr| !is_grouping_attr(attr.att_type))
+        .last()
+        .map_or(handle, |attr| attr.handle);
+    Some(end)
+}
+
+/// Attribute types whose values are part of the layout cached by clients.
+const DECLARATIONS: [Uuid16; 4] = [
+    Uuid16(0x2800), // Primary Service
+    Uuid16(0x2801), // Secondary Service
+    Uuid16(0x2802), // Include
+    Uuid16(0x2803), // Characteristic
+];
+
+/// Computes a fingerprint of the layout of the attribute database provided by `attrs`.
+///
+/// This covers the handle and type of every attribute, the values of service, include and
+/// characteristic declarations, and the group end of every grouping attribute. Other values are
+/// not included, so the fingerprint only changes if the discovery results of a client change.
+///
+/// The result is a 32-bit FNV-1a hash folded to 16 bits, to fit into `Bond::layout_hash`.
+pub fn layout_hash<A: AttributeProvider>(attrs: &mut A) -> u16 {
+    fn feed(hash: &mut u32, bytes: &[u8]) {
+        for &byte in bytes {
+            *hash ^= u32::from(byte);
+            *hash = hash.wrapping_mul(0x0100_0193);
+        }
+    }
+
+    let mut hash = 0x811C_9DC5;
+    let range = HandleRange::new(Handle::from_raw(0x0001), Handle::from_raw(0xFFFF));
+    attrs
+        .for_attrs_in_range(range, |provider, attr| {
+            feed(&mut hash, &attr.handle.as_u16().to_le_bytes());
+
+            let mut buf = [0; 16];
+            let mut writer = ByteWriter::new(&mut buf);
+            attr.att_type.to_bytes(&mut writer)?;
+            let len = 16 - writer.space_left();
+            feed(&mut hash, &buf[..len]);
+
+            if DECLARATIONS.iter().any(|&decl| attr.att_type == decl) {
+                feed(&mut hash, attr.value.0);
+            }
+            if let Some(end) = provider.group_end(attr.handle) {
+                feed(&mut hash, &end.as_u16().to_le_bytes());
+            }
+            Ok(())
+        })
+        .ok();
+
+    (hash >> 16) as u16 ^ hash as u16
+}
+
+/// Returns whether a client that has cached the layout with hash `cached` must be sent a
+/// *Service Changed* indication, given the `current` hash of the database.
+///
+/// `cached` is `None` when it isn't known what the client has cached (eg. for bonds that were
+/// created before layout hashes were stored), in which case the indication is always needed.
+pub fn needs_service_changed(cached: Option<u16>, current: u16) -> bool {
+    cached != Some(current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{utils::HexSlice, uuid::Uuid16};
+
+    #[test]
+    fn blocks() {
+        let mut alloc = HandleAllocator::new();
+        let mut a = alloc.reserve(4).unwrap();
+        let mut b = alloc.reserve(8).unwrap();
+
+        assert_eq!(a.allocate().map(|h| h.as_u16()), Some(1));
+        assert_eq!(b.allocate().map(|h| h.as_u16()), Some(5));
+        assert_eq!(a.end().as_u16(), 4);
+        assert_eq!(b.end().as_u16(), 12);
+        assert_eq!(alloc.peek().map(|h| h.as_u16()), Some(13));
+
+        assert!(a.allocate().is_some());
+        assert!(a.allocate().is_some());
+        assert!(a.allocate().is_some());
+        assert!(a.allocate().is_none());
+
+        assert!(alloc.skip_to(Handle::from_raw(12)).is_err());
+        alloc.skip_to(Handle::from_raw(0x100)).unwrap();
+        assert_eq!(alloc.allocate().map(|h| h.as_u16()), Some(0x100));
+    }
+
+    #[test]
+    fn exhaustion() {
+        let mut alloc = HandleAllocator::new();
+        alloc.skip_to(Handle::from_raw(0xFFFE)).unwrap();
+        assert!(alloc.reserve(3).is_none());
+        assert!(alloc.reserve(2).is_some());
+        assert!(alloc.allocate().is_none());
+    }
+
+    #[test]
+    fn group_end_of_array() {
+        let attr = |handle, uuid| Attribute {
+            att_type: Uuid16(uuid).into(),
+            handle: Handle::from_raw(handle),
+            value: HexSlice(&[]),
+        };
+        let attrs = [
+            attr(1, 0x2800),
+            attr(2, 0x2803),
+            attr(3, 0x2A19),
+            attr(0x10, 0x2800),
+        ];
+        let is_service = |uuid: AttUuid| uuid == Uuid16(0x2800);
+
+        assert_eq!(
+            group_end(&attrs, Handle::from_raw(1), is_service).map(|h| h.as_u16()),
+            Some(3)
+        );
+        assert_eq!(
+            group_end(&attrs, Handle::from_raw(0x10), is_service).map(|h| h.as_u16()),
+            Some(0x10)
+        );
+        assert_eq!(group_end(&attrs, Handle::from_raw(2), is_service), None);
+    }
+
+    #[test]
+    fn layout_changes() {
+        use crate::gatt::{characteristic::Properties, table::AttributeTable};
+        use heapless::consts::*;
+
+        let build = |extra: bool, level: u8| {
+            let mut table = AttributeTable::<U8, U32>::new();
+            table.add_service_reserved(Uuid16(0x180F), 8).unwrap();
+            table
+                .add_characteristic(Uuid16(0x2A19), Properties::READ, &[level])
+                .unwrap();
+            if extra {
+                table
+                    .add_characteristic(Uuid16(0x2A1A), Properties::READ, &[0])
+                    .unwrap();
+            }
+            table.add_service(Uuid16(0x180A)).unwrap();
+            layout_hash(&mut table)
+        };
+
+        // Values don't matter, but new attributes do, even inside the reserved block
+        assert_eq!(build(false, 100), build(false, 50));
+        assert_ne!(build(false, 100), build(true, 100));
+
+        assert!(!needs_service_changed(
+            Some(build(false, 0)),
+            build(false, 0)
+        ));
+        assert!(needs_service_changed(None, build(false, 0)));
+    }
+}
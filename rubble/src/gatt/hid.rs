@@ -0,0 +1,435 @@
+//! Building blocks for HID over GATT (HOGP) devices.
+//!
+//! A HID device describes the format of its reports in a *Report Map*, which hosts read once when
+//! connecting. Writing these by hand is tedious, so `ReportMap` can assemble one from descriptors
+//! for the most common device types. Each application collection gets its own report ID, which
+//! can be used to build composite devices (eg. a keyboard with media keys):
+//!
+//! ```
+//! use rubble::gatt::hid::{KeyboardReport, Modifiers, ReportMap};
+//! use rubble::bytes::{ByteWriter, ToBytes};
+//!
+//! let mut map = ReportMap::new();
+//! map.keyboard(1).unwrap().consumer_control(2).unwrap();
+//! // `map.as_bytes()` is the value of the Report Map characteristic
+//!
+//! // Press Shift+A
+//! let report = KeyboardReport::new(Modifiers::LEFT_SHIFT, &[0x04]);
+//! let mut buf = [0; KeyboardReport::SIZE];
+//! report.to_bytes(&mut ByteWriter::new(&mut buf)).unwrap();
+//! assert_eq!(buf, [0x02, 0x00, 0x04, 0, 0, 0, 0, 0]);
+//! ```
+//!
+//! The report structs in this module encode the values of the corresponding *Report*
+//! characteristics. They don't include the report ID, since HOGP conveys it in the *Report
+//! Reference* descriptor of each Report characteristic instead.
+
+use {
+    crate::{bytes::*, uuid::Uuid16, Error},
+    bitflags::bitflags,
+};
+
+/// UUID of the *Human Interface Device* service.
+pub const HID_SERVICE: Uuid16 = Uuid16(0x1812);
+
+/// UUID of the *HID Information* characteristic.
+pub const HID_INFORMATION: Uuid16 = Uuid16(0x2A4A);
+
+/// UUID of the *Report Map* characteristic.
+pub const REPORT_MAP: Uuid16 = Uuid16(0x2A4B);
+
+/// UUID of the *HID Control Point* characteristic.
+pub const HID_CONTROL_POINT: Uuid16 = Uuid16(0x2A4C);
+
+/// UUID of the *Report* characteristic.
+pub const REPORT: Uuid16 = Uuid16(0x2A4D);
+
+/// UUID of the *Protocol Mode* characteristic.
+pub const PROTOCOL_MODE: Uuid16 = Uuid16(0x2A4E);
+
+/// UUID of the *Report Reference* descriptor.
+pub const REPORT_REFERENCE: Uuid16 = Uuid16(0x2908);
+
+/// Maximum length of a Report Map allowed by HOGP.
+pub const MAX_REPORT_MAP: usize = 512;
+
+// Report descriptor bodies, between the Report ID and the End Collection item.
+
+/// Boot-compatible keyboard: modifiers, reserved byte, 5 LEDs (output), 6 keys.
+const KEYBOARD: &[u8] = &[
+    0x05, 0x07, // Usage Page (Keyboard/Keypad)
+    0x19, 0xE0, // Usage Minimum (Left Control)
+    0x29, 0xE7, // Usage Maximum (Right GUI)
+    0x15, 0x00, // Logical Minimum (0)
+    0x25, 0x01, // Logical Maximum (1)
+    0x75, 0x01, // Report Size (1)
+    0x95, 0x08, // Report Count (8)
+    0x81, 0x02, // Input (Data, Variable, Absolute)
+    0x95, 0x01, // Report Count (1)
+    0x75, 0x08, // Report Size (8)
+    0x81, 0x01, // Input (Constant)
+    0x95, 0x05, // Report Count (5)
+    0x75, 0x01, // Report Size (1)
+    0x05, 0x08, // Usage Page (LEDs)
+    0x19, 0x01, // Usage Minimum (Num Lock)
+    0x29, 0x05, // Usage Maximum (Kana)
+    0x91, 0x02, // Output (Data, Variable, Absolute)
+    0x95, 0x01, // Report Count (1)
+    0x75, 0x03, // Report Size (3)
+    0x91, 0x01, // Output (Constant)
+    0x95, 0x06, // Report Count (6)
+    0x75, 0x08, // Report Size (8)
+    0x15, 0x00, // Logical Minimum (0)
+    0x25, 0x65, // Logical Maximum (101)
+    0x05, 0x07, // Usage Page (Keyboard/Keypad)
+    0x19, 0x00, // Usage Minimum (0)
+    0x29, 0x65, // Usage Maximum (101)
+    0x81, 0x00, // Input (Data, Array)
+];
+
+/// 5-button mouse with relative X, Y and wheel.
+const MOUSE: &[u8] = &[
+    0x09, 0x01, // Usage (Pointer)
+    0xA1, 0x00, // Collection (Physical)
+    0x05, 0x09, // Usage Page (Button)
+    0x19, 0x01, // Usage Minimum (1)
+    0x29, 0x05, // Usage Maximum (5)
+    0x15, 0x00, // Logical Minimum (0)
+    0x25, 0x01, // Logical Maximum (1)
+    0x95, 0x05, // Report Count (5)
+    0x75, 0x01, // Report Size (1)
+    0x81, 0x02, // Input (Data, Variable, Absolute)
+    0x95, 0x01, // Report Count (1)
+    0x75, 0x03, // Report Size (3)
+    0x81, 0x01, // Input (Constant)
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x30, // Usage (X)
+    0x09, 0x31, // Usage (Y)
+    0x09, 0x38, // Usage (Wheel)
+    0x15, 0x81, // Logical Minimum (-127)
+    0x25, 0x7F, // Logical Maximum (127)
+    0x75, 0x08, // Report Size (8)
+    0x95, 0x03, // Report Count (3)
+    0x81, 0x06, // Input (Data, Variable, Relative)
+    0xC0, // End Collection
+];
+
+/// A single 16-bit consumer control usage.
+const CONSUMER_CONTROL: &[u8] = &[
+    0x15, 0x00, // Logical Minimum (0)
+    0x26, 0xFF, 0x03, // Logical Maximum (1023)
+    0x19, 0x00, // Usage Minimum (0)
+    0x2A, 0xFF, 0x03, // Usage Maximum (1023)
+    0x75, 0x10, // Report Size (16)
+    0x95, 0x01, // Report Count (1)
+    0x81, 0x00, // Input (Data, Array)
+];
+
+/// 16 buttons and 4 absolute axes (X, Y, Z, Rz).
+const GAMEPAD: &[u8] = &[
+    0x05, 0x09, // Usage Page (Button)
+    0x19, 0x01, // Usage Minimum (1)
+    0x29, 0x10, // Usage Maximum (16)
+    0x15, 0x00, // Logical Minimum (0)
+    0x25, 0x01, // Logical Maximum (1)
+    0x75, 0x01, // Report Size (1)
+    0x95, 0x10, // Report Count (16)
+    0x81, 0x02, // Input (Data, Variable, Absolute)
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x30, // Usage (X)
+    0x09, 0x31, // Usage (Y)
+    0x09, 0x32, // Usage (Z)
+    0x09, 0x35, // Usage (Rz)
+    0x15, 0x81, // Logical Minimum (-127)
+    0x25, 0x7F, // Logical Maximum (127)
+    0x75, 0x08, // Report Size (8)
+    0x95, 0x04, // Report Count (4)
+    0x81, 0x02, // Input (Data, Variable, Absolute)
+];
+
+/// A HID Report Map assembled from predefined collections.
+#[derive(Clone)]
+pub struct ReportMap {
+    buf: [u8; MAX_REPORT_MAP],
+    len: usize,
+}
+
+impl ReportMap {
+    /// Creates an empty Report Map.
+    pub fn new() -> Self {
+        Self {
+            buf: [0; MAX_REPORT_MAP],
+            len: 0,
+        }
+    }
+
+    /// Returns the encoded Report Map.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    /// Adds a boot-compatible keyboard with input report `report_id` (see `KeyboardReport`) and an
+    /// output report with the same ID for the LEDs (see `KeyboardLeds`).
+    pub fn keyboard(&mut self, report_id: u8) -> Result<&mut Self, Error> {
+        self.add_collection(0x01, 0x06, report_id, KEYBOARD)
+    }
+
+    /// Adds a mouse with input report `report_id` (see `MouseReport`).
+    pub fn mouse(&mut self, report_id: u8) -> Result<&mut Self, Error> {
+        self.add_collection(0x01, 0x02, report_id, MOUSE)
+    }
+
+    /// Adds consumer controls (media keys) with input report `report_id` (see `ConsumerReport`).
+    pub fn consumer_control(&mut self, report_id: u8) -> Result<&mut Self, Error> {
+        self.add_collection(0x0C, 0x01, report_id, CONSUMER_CONTROL)
+    }
+
+    /// Adds a gamepad with input report `report_id` (see `GamepadReport`).
+    pub fn gamepad(&mut self, report_id: u8) -> Result<&mut Self, Error> {
+        self.add_collection(0x01, 0x05, report_id, GAMEPAD)
+    }
+
+    /// Appends raw report descriptor items.
+    pub fn raw(&mut self, items: &[u8]) -> Result<&mut Self, Error> {
+        let mut writer = ByteWriter::new(&mut self.buf[self.len..]);
+        writer.write_slice(items)?;
+        self.len += items.len();
+        Ok(self)
+    }
+
+    /// Appends an application collection with the given usage and report ID.
+    ///
+    /// Returns `Error::InvalidValue` for report ID 0, which is reserved, and `Error::Eof` if the
+    /// map would exceed `MAX_REPORT_MAP` Bytes. The map is left unchanged on error.
+    fn add_collection(
+        &mut self,
+        usage_page: u8,
+        usage: u8,
+        report_id: u8,
+        body: &[u8],
+    ) -> Result<&mut Self, Error> {
+        if report_id == 0 {
+            return Err(Error::InvalidValue);
+        }
+
+        let mut writer = ByteWriter::new(&mut self.buf[self.len..]);
+        let free = writer.space_left();
+        writer.write_slice(&[
+            0x05, usage_page, // Usage Page
+            0x09, usage, // Usage
+            0xA1, 0x01, // Collection (Application)
+            0x85, report_id, // Report ID
+        ])?;
+        writer.write_slice(body)?;
+        writer.write_u8(0xC0)?; // End Collection
+        self.len += free - writer.space_left();
+        Ok(self)
+    }
+}
+
+impl Default for ReportMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+enum_with_unknown! {
+    /// Type of a report, as specified in its Report Reference descriptor.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum ReportType(u8) {
+        Input = 0x01,
+        Output = 0x02,
+        Feature = 0x03,
+    }
+}
+
+/// Value of a *Report Reference* descriptor, mapping a Report characteristic to a report in the
+/// Report Map.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ReportReference {
+    pub report_id: u8,
+    pub report_type: ReportType,
+}
+
+impl FromBytes<'_> for ReportReference {
+    fn from_bytes(bytes: &mut ByteReader<'_>) -> Result<Self, Error> {
+        Ok(Self {
+            report_id: bytes.read_u8()?,
+            report_type: ReportType::from(bytes.read_u8()?),
+        })
+    }
+}
+
+impl ToBytes for ReportReference {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u8(self.report_id)?;
+        writer.write_u8(self.report_type.into())
+    }
+}
+
+bitflags! {
+    /// Keyboard modifier keys.
+    pub struct Modifiers: u8 {
+        const LEFT_CTRL   = 0x01;
+        const LEFT_SHIFT  = 0x02;
+        const LEFT_ALT    = 0x04;
+        const LEFT_GUI    = 0x08;
+        const RIGHT_CTRL  = 0x10;
+        const RIGHT_SHIFT = 0x20;
+        const RIGHT_ALT   = 0x40;
+        const RIGHT_GUI   = 0x80;
+    }
+}
+
+bitflags! {
+    /// Keyboard LEDs, written by the host to the keyboard's output report.
+    pub struct KeyboardLeds: u8 {
+        const NUM_LOCK    = 0x01;
+        const CAPS_LOCK   = 0x02;
+        const SCROLL_LOCK = 0x04;
+        const COMPOSE     = 0x08;
+        const KANA        = 0x10;
+    }
+}
+
+impl FromBytes<'_> for KeyboardLeds {
+    fn from_bytes(bytes: &mut ByteReader<'_>) -> Result<Self, Error> {
+        Ok(Self::from_bits_truncate(bytes.read_u8()?))
+    }
+}
+
+/// Input report of a keyboard added with `ReportMap::keyboard`.
+///
+/// This uses the boot protocol layout, so it can also be sent via the *Boot Keyboard Input Report*
+/// characteristic.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct KeyboardReport {
+    pub modifiers: Modifiers,
+    /// Usage IDs of up to 6 pressed keys (0 = no key).
+    pub keys: [u8; 6],
+}
+
+impl KeyboardReport {
+    /// Encoded size of the report.
+    pub const SIZE: usize = 8;
+
+    /// Creates a report with the given modifiers and pressed keys.
+    ///
+    /// If more than 6 keys are passed, the report signals *ErrorRollOver* instead.
+    pub fn new(modifiers: Modifiers, keys: &[u8]) -> Self {
+        let mut report = Self {
+            modifiers,
+            keys: [0; 6],
+        };
+        if keys.len() > 6 {
+            report.keys = [0x01; 6];
+        } else {
+            report.keys[..keys.len()].copy_from_slice(keys);
+        }
+        report
+    }
+
+    /// Creates a report with no keys pressed.
+    pub fn released() -> Self {
+        Self::new(Modifiers::empty(), &[])
+    }
+}
+
+impl ToBytes for KeyboardReport {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u8(self.modifiers.bits())?;
+        writer.write_u8(0)?;
+        writer.write_slice(&self.keys)
+    }
+}
+
+/// Input report of a mouse added with `ReportMap::mouse`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct MouseReport {
+    /// Pressed buttons (bit 0 = left, bit 1 = right, bit 2 = middle, bits 3 and 4 = back and
+    /// forward).
+    pub buttons: u8,
+    pub x: i8,
+    pub y: i8,
+    pub wheel: i8,
+}
+
+impl MouseReport {
+    /// Encoded size of the report.
+    pub const SIZE: usize = 4;
+}
+
+impl ToBytes for MouseReport {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u8(self.buttons & 0x1F)?;
+        writer.write_u8(self.x as u8)?;
+        writer.write_u8(self.y as u8)?;
+        writer.write_u8(self.wheel as u8)
+    }
+}
+
+/// Input report of the consumer controls added with `ReportMap::consumer_control`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct ConsumerReport {
+    /// Consumer page usage ID of the pressed control (eg. `0xE9` for *Volume Increment*), or 0.
+    pub usage: u16,
+}
+
+impl ConsumerReport {
+    /// Encoded size of the report.
+    pub const SIZE: usize = 2;
+}
+
+impl ToBytes for ConsumerReport {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u16_le(self.usage)
+    }
+}
+
+/// Input report of a gamepad added with `ReportMap::gamepad`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct GamepadReport {
+    /// Pressed buttons, button 1 in bit 0.
+    pub buttons: u16,
+    pub x: i8,
+    pub y: i8,
+    pub z: i8,
+    pub rz: i8,
+}
+
+impl GamepadReport {
+    /// Encoded size of the report.
+    pub const SIZE: usize = 6;
+}
+
+impl ToBytes for GamepadReport {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u16_le(self.buttons)?;
+        writer.write_u8(self.x as u8)?;
+        writer.write_u8(self.y as u8)?;
+        writer.write_u8(self.z as u8)?;
+        writer.write_u8(self.rz as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_map() {
+        let mut map = ReportMap::new();
+        map.mouse(3).unwrap();
+        let bytes = map.as_bytes();
+        assert_eq!(
+            &bytes[..8],
+            &[0x05, 0x01, 0x09, 0x02, 0xA1, 0x01, 0x85, 0x03]
+        );
+        assert_eq!(&bytes[bytes.len() - 2..], &[0xC0, 0xC0]);
+        assert_eq!(bytes.len(), 8 + MOUSE.len() + 1);
+
+        assert_eq!(map.gamepad(0).err(), Some(Error::InvalidValue));
+        while map.keyboard(1).is_ok() {}
+        assert!(map.as_bytes().len() <= MAX_REPORT_MAP);
+    }
+}
@@ -0,0 +1,436 @@
+//! *Health Thermometer Service* (HTS).
+//!
+//! HTS is hosted by medical and fitness thermometers. Every finished measurement is sent to the
+//! client as an indication of the *Temperature Measurement* characteristic, so that it is
+//! guaranteed to arrive. Thermometers that measure periodically expose the period via the
+//! *Measurement Interval* characteristic, which the client may be allowed to change.
+//!
+//! Temperatures are encoded in the IEEE-11073 32-bit `Float` format used by medical devices.
+//!
+//! [`HealthThermometerServer`] adds the service to an `AttributeTable`. Indications are sent by
+//! the application, using `AttributeServerTx::indicate_raw` with the value returned by
+//! `HealthThermometerServer::measurement_indication`. Only one indication can be in flight, so
+//! measurements taken while `AttributeServer::indication_pending` is `true` have to be queued or
+//! dropped by the application.
+//!
+//! The spec requires a bond for writing the *Measurement Interval*. The application's
+//! `AttributeProvider::required_security` has to enforce that for
+//! `HealthThermometerServer::measurement_interval`.
+//!
+//! [`HealthThermometerServer`]: struct.HealthThermometerServer.html
+
+use {
+    crate::{
+        att::{AttError, ErrorCode, Handle},
+        bytes::{ByteReader, ByteWriter},
+        gatt::{
+            characteristic::Properties,
+            client::CharacteristicValue,
+            cts::DateTime,
+            table::{AttributeTable, TableEntry},
+        },
+        uuid::Uuid16,
+        Error,
+    },
+    heapless::ArrayLength,
+};
+
+/// UUID of the *Health Thermometer Service*.
+pub const HEALTH_THERMOMETER_SERVICE: Uuid16 = Uuid16(0x1809);
+
+/// UUID of the *Temperature Measurement* characteristic.
+pub const TEMPERATURE_MEASUREMENT: Uuid16 = Uuid16(0x2A1C);
+
+/// UUID of the *Temperature Type* characteristic.
+pub const TEMPERATURE_TYPE: Uuid16 = Uuid16(0x2A1D);
+
+/// UUID of the *Measurement Interval* characteristic.
+pub const MEASUREMENT_INTERVAL: Uuid16 = Uuid16(0x2A21);
+
+/// UUID of the *Valid Range* descriptor.
+pub const VALID_RANGE: Uuid16 = Uuid16(0x2906);
+
+/// ATT error returned for measurement intervals outside of the valid range.
+const OUT_OF_RANGE: u8 = 0x80;
+
+/// An IEEE-11073 32-bit floating point number.
+///
+/// The value is `mantissa * 10^exponent`, with a 24-bit signed mantissa and an 8-bit signed
+/// exponent. Some mantissa values are reserved to encode special values like `NAN`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Float(u32);
+
+impl Float {
+    /// Not a Number, eg. for a measurement that failed.
+    pub const NAN: Self = Float(0x007F_FFFF);
+
+    /// The value can't be represented with the available resolution.
+    pub const NRES: Self = Float(0x0080_0000);
+
+    pub const POSITIVE_INFINITY: Self = Float(0x007F_FFFE);
+
+    pub const NEGATIVE_INFINITY: Self = Float(0x0080_0002);
+
+    const MANTISSA_MAX: i32 = 0x007F_FFFD;
+    const MANTISSA_MIN: i32 = -0x007F_FFFD;
+
+    /// Creates a `Float` with the value `mantissa * 10^exponent`.
+    ///
+    /// Returns `NRES` if `mantissa` doesn't fit into 24 bits (without hitting a reserved value).
+    pub fn new(mantissa: i32, exponent: i8) -> Self {
+        if !(Self::MANTISSA_MIN..=Self::MANTISSA_MAX).contains(&mantissa) {
+            return Self::NRES;
+        }
+        Float((u32::from(exponent as u8) << 24) | (mantissa as u32 & 0x00FF_FFFF))
+    }
+
+    /// Creates a `Float` from a value in hundredths, eg. `3712` for 37.12.
+    pub fn from_hundredths(value: i32) -> Self {
+        Self::new(value, -2)
+    }
+
+    /// Creates a `Float` from its encoded representation.
+    pub fn from_bits(bits: u32) -> Self {
+        Float(bits)
+    }
+
+    /// Returns the encoded representation.
+    pub fn to_bits(&self) -> u32 {
+        self.0
+    }
+
+    /// Returns the sign-extended 24-bit mantissa.
+    pub fn mantissa(&self) -> i32 {
+        ((self.0 << 8) as i32) >> 8
+    }
+
+    /// Returns the exponent.
+    pub fn exponent(&self) -> i8 {
+        (self.0 >> 24) as i8
+    }
+
+    /// Returns whether this is one of the reserved special values instead of a number.
+    pub fn is_special(&self) -> bool {
+        self.exponent() == 0
+            && !(Self::MANTISSA_MIN..=Self::MANTISSA_MAX).contains(&self.mantissa())
+    }
+}
+
+/// Unit of a `TemperatureMeasurement`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TemperatureUnit {
+    Celsius,
+    Fahrenheit,
+}
+
+enum_with_unknown! {
+    /// Where on (or in) the body the temperature is measured.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum TemperatureType(u8) {
+        Armpit = 1,
+        Body = 2,
+        Ear = 3,
+        Finger = 4,
+        GastroIntestinalTract = 5,
+        Mouth = 6,
+        Rectum = 7,
+        Toe = 8,
+        Tympanum = 9,
+    }
+}
+
+/// Value of the *Temperature Measurement* characteristic.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TemperatureMeasurement {
+    pub temperature: Float,
+    pub unit: TemperatureUnit,
+    /// When the measurement was taken, if the thermometer has a clock.
+    pub timestamp: Option<DateTime>,
+    /// Measurement site, if it can change between measurements.
+    ///
+    /// A fixed site should be exposed with the *Temperature Type* characteristic instead.
+    pub temperature_type: Option<TemperatureType>,
+}
+
+impl TemperatureMeasurement {
+    /// Maximum size of the encoded value in Bytes.
+    pub const MAX_SIZE: usize = 13;
+
+    /// Creates a measurement in degrees Celsius, without timestamp and type.
+    pub fn celsius(temperature: Float) -> Self {
+        Self {
+            temperature,
+            unit: TemperatureUnit::Celsius,
+            timestamp: None,
+            temperature_type: None,
+        }
+    }
+}
+
+impl CharacteristicValue for TemperatureMeasurement {
+    fn decode(bytes: &mut ByteReader<'_>) -> Result<Self, Error> {
+        let flags = bytes.read_u8()?;
+        let temperature = Float::from_bits(bytes.read_u32_le()?);
+        let timestamp = if flags & 0x02 != 0 {
+            Some(DateTime {
+                year: bytes.read_u16_le()?,
+                month: bytes.read_u8()?,
+                day: bytes.read_u8()?,
+                hours: bytes.read_u8()?,
+                minutes: bytes.read_u8()?,
+                seconds: bytes.read_u8()?,
+            })
+        } else {
+            None
+        };
+        let temperature_type = if flags & 0x04 != 0 {
+            Some(TemperatureType::from(bytes.read_u8()?))
+        } else {
+            None
+        };
+        Ok(Self {
+            temperature,
+            unit: if flags & 0x01 != 0 {
+                TemperatureUnit::Fahrenheit
+            } else {
+                TemperatureUnit::Celsius
+            },
+            timestamp,
+            temperature_type,
+        })
+    }
+
+    fn encode(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        let mut flags = 0;
+        if self.unit == TemperatureUnit::Fahrenheit {
+            flags |= 0x01;
+        }
+        if self.timestamp.is_some() {
+            flags |= 0x02;
+        }
+        if self.temperature_type.is_some() {
+            flags |= 0x04;
+        }
+        writer.write_u8(flags)?;
+        writer.write_u32_le(self.temperature.to_bits())?;
+        if let Some(dt) = &self.timestamp {
+            writer.write_u16_le(dt.year)?;
+            writer.write_slice(&[dt.month, dt.day, dt.hours, dt.minutes, dt.seconds])?;
+        }
+        if let Some(ty) = self.temperature_type {
+            writer.write_u8(ty.into())?;
+        }
+        Ok(())
+    }
+}
+
+/// The server side of HTS, hosted in an `AttributeTable`.
+#[derive(Debug, Copy, Clone)]
+pub struct HealthThermometerServer {
+    measurement: Handle,
+    interval: Option<Handle>,
+    /// Valid range for intervals written by the client, if it may write them.
+    range: Option<(u16, u16)>,
+}
+
+impl HealthThermometerServer {
+    /// Adds the service to `table`.
+    ///
+    /// `temperature_type` is the fixed measurement site, if there is one. If `interval` is given,
+    /// the *Measurement Interval* characteristic is added with that value (in seconds, 0 meaning
+    /// no periodic measurements). If `range` is also given, the client is allowed to change the
+    /// interval to any value in that inclusive range.
+    pub fn add_to<N, B>(
+        table: &mut AttributeTable<N, B>,
+        temperature_type: Option<TemperatureType>,
+        interval: Option<u16>,
+        range: Option<(u16, u16)>,
+    ) -> Result<Self, Error>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        table.add_service(HEALTH_THERMOMETER_SERVICE)?;
+        // Measurements are only ever indicated, never stored
+        let measurement =
+            table.add_characteristic(TEMPERATURE_MEASUREMENT, Properties::INDICATE, &[])?;
+        if let Some(ty) = temperature_type {
+            table.add_characteristic(TEMPERATURE_TYPE, Properties::READ, &[ty.into()])?;
+        }
+        let interval = match interval {
+            Some(secs) => {
+                let mut props = Properties::READ;
+                if range.is_some() {
+                    props |= Properties::WRITE | Properties::INDICATE;
+                }
+                let handle =
+                    table.add_characteristic(MEASUREMENT_INTERVAL, props, &secs.to_le_bytes())?;
+                if let Some((min, max)) = range {
+                    let [a, b] = min.to_le_bytes();
+                    let [c, d] = max.to_le_bytes();
+                    table.add_descriptor(VALID_RANGE, &[a, b, c, d], false)?;
+                }
+                Some(handle)
+            }
+            None => None,
+        };
+        Ok(Self {
+            measurement,
+            interval,
+            range: if interval.is_some() { range } else { None },
+        })
+    }
+
+    /// Returns the handle of the *Temperature Measurement* value.
+    pub fn measurement(&self) -> Handle {
+        self.measurement
+    }
+
+    /// Returns the handle of the *Measurement Interval* value, if the characteristic exists.
+    pub fn measurement_interval(&self) -> Option<Handle> {
+        self.interval
+    }
+
+    /// Returns the current measurement interval in seconds, if the characteristic exists.
+    pub fn interval<N, B>(&self, table: &AttributeTable<N, B>) -> Option<u16>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        match table.value(self.interval?)? {
+            [lo, hi] => Some(u16::from_le_bytes([*lo, *hi])),
+            _ => None,
+        }
+    }
+
+    /// Encodes `measurement` into `buf` for indication on `measurement()`.
+    ///
+    /// Returns `None` if the client hasn't enabled indications, in which case the measurement
+    /// should be stored until it does (or dropped).
+    pub fn measurement_indication<'a, N, B>(
+        &self,
+        table: &AttributeTable<N, B>,
+        measurement: &TemperatureMeasurement,
+        buf: &'a mut [u8; TemperatureMeasurement::MAX_SIZE],
+    ) -> Option<&'a [u8]>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        if !table.indications_enabled(self.measurement) {
+            return None;
+        }
+        let mut writer = ByteWriter::new(&mut buf[..]);
+        measurement.encode(&mut writer).unwrap();
+        let len = TemperatureMeasurement::MAX_SIZE - writer.space_left();
+        Some(&buf[..len])
+    }
+
+    /// Changes the measurement interval, eg. in response to user input.
+    ///
+    /// Returns whether the change has to be indicated to the client (with the new value as
+    /// little-endian `u16`).
+    pub fn set_interval<N, B>(
+        &self,
+        table: &mut AttributeTable<N, B>,
+        secs: u16,
+    ) -> Result<bool, Error>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        let handle = self.interval.ok_or(Error::InvalidValue)?;
+        table.set_value(handle, &secs.to_le_bytes())?;
+        Ok(self.range.is_some() && table.indications_enabled(handle))
+    }
+
+    /// Handles a write to the attribute at `handle`.
+    ///
+    /// Returns `None` if `handle` doesn't belong to the service, in which case the write should be
+    /// processed as usual. Intervals outside of the valid range are rejected with the *Out of
+    /// Range* error defined by HTS.
+    pub fn write_attr<N, B>(
+        &self,
+        table: &mut AttributeTable<N, B>,
+        handle: Handle,
+        value: &[u8],
+    ) -> Option<Result<(), AttError>>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        if Some(handle) != self.interval {
+            return None;
+        }
+        let (min, max) = match self.range {
+            Some(range) => range,
+            None => return Some(Err(AttError::new(ErrorCode::WriteNotPermitted, handle))),
+        };
+
+        let secs = match value {
+            [lo, hi] => u16::from_le_bytes([*lo, *hi]),
+            _ => {
+                return Some(Err(AttError::new(
+                    ErrorCode::InvalidAttributeValueLength,
+                    handle,
+                )))
+            }
+        };
+        // 0 disables periodic measurements and is always allowed
+        if secs != 0 && (secs < min || secs > max) {
+            return Some(Err(AttError::new(ErrorCode::from(OUT_OF_RANGE), handle)));
+        }
+        table.set_value(handle, value).ok();
+        Some(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, heapless::consts::*};
+
+    #[test]
+    fn float() {
+        let f = Float::from_hundredths(-3712);
+        assert_eq!(f.to_bits(), 0xFEFF_F180);
+        assert_eq!((f.mantissa(), f.exponent()), (-3712, -2));
+        assert!(!f.is_special());
+        assert!(Float::NAN.is_special());
+        assert_eq!(Float::new(0x0100_0000, 0), Float::NRES);
+    }
+
+    #[test]
+    fn indications_and_interval() {
+        let mut table = AttributeTable::<U16, U64>::new();
+        let hts = HealthThermometerServer::add_to(
+            &mut table,
+            Some(TemperatureType::Ear),
+            Some(60),
+            Some((1, 600)),
+        )
+        .unwrap();
+        let mut meas = TemperatureMeasurement::celsius(Float::from_hundredths(3712));
+        meas.temperature_type = Some(TemperatureType::Mouth);
+
+        let mut buf = [0; TemperatureMeasurement::MAX_SIZE];
+        assert!(hts
+            .measurement_indication(&table, &meas, &mut buf)
+            .is_none());
+        let cccd = table.cccd_handle(hts.measurement()).unwrap();
+        table.set_value(cccd, &[0x02, 0x00]).unwrap();
+        let value = hts.measurement_indication(&table, &meas, &mut buf).unwrap();
+        assert_eq!(value, &[0x04, 0x80, 0x0E, 0x00, 0xFE, 0x06]);
+        let decoded = TemperatureMeasurement::decode(&mut ByteReader::new(value)).unwrap();
+        assert_eq!(decoded, meas);
+
+        let interval = hts.measurement_interval().unwrap();
+        assert!(hts
+            .write_attr(&mut table, interval, &[0x00, 0x10])
+            .unwrap()
+            .is_err());
+        hts.write_attr(&mut table, interval, &[0x1E, 0x00])
+            .unwrap()
+            .unwrap();
+        assert_eq!(hts.interval(&table), Some(30));
+    }
+}
@@ -0,0 +1,311 @@
+//! Walking an attribute database as services, characteristics and descriptors.
+//!
+//! Attribute servers only store a flat list of attributes. This module recovers the GATT structure
+//! from the declarations in that list, the same way a client performing discovery would, but
+//! without going over the air. This is useful for logging the database at startup, for rendering
+//! it in companion tooling, or for verifying in a test that a table contains what was intended.
+//!
+//! The entry point is `services`, which takes an iterator over the attributes in ascending handle
+//! order, such as the one returned by `AttributeTable::attributes`:
+//!
+//! ```ignore
+//! for service in introspect::services(table.attributes()) {
+//!     info!("service {:?}", service.uuid());
+//!     for characteristic in service.characteristics() {
+//!         info!("  {:?} {:?}", characteristic.uuid(), characteristic.properties());
+//!         for descriptor in characteristic.descriptors() {
+//!             info!("    {:?}", descriptor.uuid);
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! Malformed declarations are skipped.
+
+use crate::{
+    att::{AttUuid, Attribute, Handle},
+    bytes::{ByteReader, FromBytes},
+    gatt::characteristic::Properties,
+    uuid::Uuid16,
+};
+
+const PRIMARY_SERVICE: Uuid16 = Uuid16(0x2800);
+const SECONDARY_SERVICE: Uuid16 = Uuid16(0x2801);
+const CHARACTERISTIC: Uuid16 = Uuid16(0x2803);
+
+/// Returns an iterator over the services declared in `attrs`.
+///
+/// `attrs` must yield the attributes in ascending handle order.
+pub fn services<'a, I>(attrs: I) -> Services<I>
+where
+    I: Iterator<Item = Attribute<'a>> + Clone,
+{
+    Services { attrs }
+}
+
+/// Iterator over the services in a database, returned by `services`.
+#[derive(Clone)]
+pub struct Services<I> {
+    attrs: I,
+}
+
+impl<'a, I> Iterator for Services<I>
+where
+    I: Iterator<Item = Attribute<'a>> + Clone,
+{
+    type Item = Service<I>;
+
+    fn next(&mut self) -> Option<Service<I>> {
+        loop {
+            let attr = self.attrs.next()?;
+            let primary = if attr.att_type == PRIMARY_SERVICE {
+                true
+            } else if attr.att_type == SECONDARY_SERVICE {
+                false
+            } else {
+                continue;
+            };
+
+            if let Ok(uuid) = AttUuid::from_bytes(&mut ByteReader::new(attr.value.0)) {
+                return Some(Service {
+                    handle: attr.handle,
+                    uuid,
+                    primary,
+                    attrs: self.attrs.clone(),
+                });
+            }
+        }
+    }
+}
+
+/// A service declaration.
+#[derive(Clone)]
+pub struct Service<I> {
+    handle: Handle,
+    uuid: AttUuid,
+    primary: bool,
+    /// The attributes following the declaration.
+    attrs: I,
+}
+
+impl<'a, I> Service<I>
+where
+    I: Iterator<Item = Attribute<'a>> + Clone,
+{
+    /// Returns the handle of the service declaration.
+    pub fn handle(&self) -> Handle {
+        self.handle
+    }
+
+    /// Returns the UUID of the service.
+    pub fn uuid(&self) -> AttUuid {
+        self.uuid
+    }
+
+    /// Returns whether this is a primary service (as opposed to a secondary service).
+    pub fn is_primary(&self) -> bool {
+        self.primary
+    }
+
+    /// Returns the handle of the last attribute belonging to the service.
+    ///
+    /// Handles reserved for the service but not in use aren't taken into account.
+    pub fn end(&self) -> Handle {
+        self.attrs
+            .clone()
+            .take_while(|attr| !is_service_decl(attr))
+            .last()
+            .map_or(self.handle, |attr| attr.handle)
+    }
+
+    /// Returns an iterator over the characteristics of the service.
+    pub fn characteristics(&self) -> Characteristics<I> {
+        Characteristics {
+            attrs: self.attrs.clone(),
+        }
+    }
+}
+
+/// Iterator over the characteristics of a service, returned by `Service::characteristics`.
+#[derive(Clone)]
+pub struct Characteristics<I> {
+    attrs: I,
+}
+
+impl<'a, I> Iterator for Characteristics<I>
+where
+    I: Iterator<Item = Attribute<'a>> + Clone,
+{
+    type Item = Characteristic<I>;
+
+    fn next(&mut self) -> Option<Characteristic<I>> {
+        loop {
+            // Look ahead so that the next service isn't consumed
+            let mut ahead = self.attrs.clone();
+            let attr = ahead.next()?;
+            if is_service_decl(&attr) {
+                return None;
+            }
+            self.attrs = ahead;
+
+            if attr.att_type != CHARACTERISTIC {
+                continue;
+            }
+            if let Some((properties, value_handle, uuid)) = parse_characteristic(attr.value.0) {
+                return Some(Characteristic {
+                    declaration: attr.handle,
+                    value_handle,
+                    properties,
+                    uuid,
+                    attrs: self.attrs.clone(),
+                });
+            }
+        }
+    }
+}
+
+/// A characteristic declaration.
+#[derive(Clone)]
+pub struct Characteristic<I> {
+    declaration: Handle,
+    value_handle: Handle,
+    properties: Properties,
+    uuid: AttUuid,
+    /// The attributes following the declaration.
+    attrs: I,
+}
+
+impl<'a, I> Characteristic<I>
+where
+    I: Iterator<Item = Attribute<'a>> + Clone,
+{
+    /// Returns the handle of the characteristic declaration.
+    pub fn declaration(&self) -> Handle {
+        self.declaration
+    }
+
+    /// Returns the handle of the characteristic value.
+    pub fn value_handle(&self) -> Handle {
+        self.value_handle
+    }
+
+    /// Returns the properties stated in the declaration.
+    pub fn properties(&self) -> Properties {
+        self.properties
+    }
+
+    /// Returns the UUID of the characteristic.
+    pub fn uuid(&self) -> AttUuid {
+        self.uuid
+    }
+
+    /// Returns an iterator over the descriptors of the characteristic.
+    pub fn descriptors(&self) -> Descriptors<I> {
+        Descriptors {
+            value_handle: self.value_handle,
+            attrs: self.attrs.clone(),
+        }
+    }
+}
+
+/// Iterator over the descriptors of a characteristic, returned by `Characteristic::descriptors`.
+#[derive(Clone)]
+pub struct Descriptors<I> {
+    value_handle: Handle,
+    attrs: I,
+}
+
+impl<'a, I> Iterator for Descriptors<I>
+where
+    I: Iterator<Item = Attribute<'a>> + Clone,
+{
+    type Item = Descriptor<'a>;
+
+    fn next(&mut self) -> Option<Descriptor<'a>> {
+        loop {
+            let attr = self.attrs.next()?;
+            if is_service_decl(&attr) || attr.att_type == CHARACTERISTIC {
+                return None;
+            }
+            if attr.handle == self.value_handle {
+                continue;
+            }
+            return Some(Descriptor {
+                handle: attr.handle,
+                uuid: attr.att_type,
+                value: attr.value.0,
+            });
+        }
+    }
+}
+
+/// A characteristic descriptor.
+#[derive(Debug, Copy, Clone)]
+pub struct Descriptor<'a> {
+    /// Handle of the descriptor.
+    pub handle: Handle,
+
+    /// Type of the descriptor.
+    pub uuid: AttUuid,
+
+    /// Current value of the descriptor, as stored by the server.
+    pub value: &'a [u8],
+}
+
+fn is_service_decl(attr: &Attribute<'_>) -> bool {
+    attr.att_type == PRIMARY_SERVICE || attr.att_type == SECONDARY_SERVICE
+}
+
+fn parse_characteristic(value: &[u8]) -> Option<(Properties, Handle, AttUuid)> {
+    let mut bytes = ByteReader::new(value);
+    let properties = Properties::from_bits_truncate(bytes.read_u8().ok()?);
+    let handle = Handle::from_raw(bytes.read_u16_le().ok()?);
+    let uuid = AttUuid::from_bytes(&mut bytes).ok()?;
+    Some((properties, handle, uuid))
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::gatt::table::AttributeTable, heapless::consts::*};
+
+    #[test]
+    fn walk_table() {
+        let mut table = AttributeTable::<U16, U64>::new();
+        table.add_service(Uuid16(0x180F)).unwrap();
+        let level = table
+            .add_characteristic(Uuid16(0x2A19), Properties::READ | Properties::NOTIFY, &[50])
+            .unwrap();
+        table.add_service(Uuid16(0x180A)).unwrap();
+        table
+            .add_characteristic(Uuid16(0x2A24), Properties::READ, b"rubble")
+            .unwrap();
+        let empty = table.add_service(Uuid16(0x1234)).unwrap();
+
+        let mut services = services(table.attributes());
+        let bas = services.next().unwrap();
+        assert!(bas.uuid() == Uuid16(0x180F) && bas.is_primary());
+        assert_eq!(bas.end().as_u16(), 4);
+        let mut chars = bas.characteristics();
+        let battery_level = chars.next().unwrap();
+        assert!(chars.next().is_none());
+        assert_eq!(battery_level.value_handle(), level);
+        assert!(battery_level.uuid() == Uuid16(0x2A19));
+        assert_eq!(
+            battery_level.properties(),
+            Properties::READ | Properties::NOTIFY
+        );
+        let cccd = battery_level.descriptors().next().unwrap();
+        assert!(cccd.uuid == Uuid16(0x2902));
+        assert_eq!(cccd.value, &[0, 0]);
+
+        let dis = services.next().unwrap();
+        let model = dis.characteristics().next().unwrap();
+        assert_eq!(model.descriptors().count(), 0);
+
+        let last = services.next().unwrap();
+        assert_eq!(last.handle(), empty);
+        assert_eq!(last.end(), empty);
+        assert_eq!(last.characteristics().count(), 0);
+        assert!(services.next().is_none());
+    }
+}
@@ -3,11 +3,36 @@
 //! GATT describes a service framework that uses the Attribute Protocol for discovery and
 //! interaction
 
+pub mod bms;
+pub mod bonded;
 pub mod characteristic;
+pub mod client;
+pub mod cscs;
+pub mod cts;
+pub mod descriptor;
+pub mod dfu;
+pub mod discovery;
+#[cfg(feature = "alloc")]
+pub mod dynamic;
+pub mod ess;
+pub mod fast_pair;
+pub mod gap;
+pub mod handles;
+pub mod hid;
+pub mod hts;
+pub mod introspect;
+pub mod notify;
+pub mod ots;
+pub mod profiles;
+pub mod proximity;
+pub mod racp;
+pub mod smp;
+pub mod subscriptions;
+pub mod table;
 
 use {
     crate::{
-        att::{AttUuid, Attribute, AttributeProvider, Handle, HandleRange},
+        att::{AttError, AttUuid, Attribute, AttributeProvider, ErrorCode, Handle, HandleRange},
         utils::HexSlice,
         uuid::{Uuid, Uuid16},
         Error,
@@ -49,6 +74,12 @@ impl BatteryServiceAttrs {
     }
 }
 
+impl Default for BatteryServiceAttrs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl AttributeProvider for BatteryServiceAttrs {
     fn for_attrs_in_range(
         &mut self,
@@ -83,10 +114,10 @@ impl AttributeProvider for BatteryServiceAttrs {
         uuid == Uuid16(0x2800) // FIXME not characteristics?
     }
 
-    fn group_end(&self, handle: Handle) -> Option<&Attribute<'_>> {
+    fn group_end(&self, handle: Handle) -> Option<Handle> {
         match handle.as_u16() {
-            0x0001 => Some(&self.attributes[2]),
-            0x0002 => Some(&self.attributes[2]),
+            0x0001 => Some(self.attributes[2].handle),
+            0x0002 => Some(self.attributes[2].handle),
             _ => None,
         }
     }
@@ -182,6 +213,12 @@ impl MidiServiceAttrs {
     }
 }
 
+impl Default for MidiServiceAttrs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl AttributeProvider for MidiServiceAttrs {
     fn for_attrs_in_range(
         &mut self,
@@ -216,11 +253,22 @@ impl AttributeProvider for MidiServiceAttrs {
         uuid == Uuid16(0x2800) // FIXME not characteristics?
     }
 
-    fn group_end(&self, handle: Handle) -> Option<&Attribute<'_>> {
+    fn group_end(&self, handle: Handle) -> Option<Handle> {
         match handle.as_u16() {
-            0x0001 => Some(&self.attributes[3]),
-            0x0002 => Some(&self.attributes[3]),
+            0x0001 => Some(self.attributes[3].handle),
+            0x0002 => Some(self.attributes[3].handle),
             _ => None,
         }
     }
+
+    fn write_attr(&mut self, handle: Handle, _value: &[u8]) -> Result<(), AttError> {
+        match handle.as_u16() {
+            // The MIDI Data I/O characteristic accepts writes, but we don't process MIDI input.
+            0x0003 => Ok(()),
+            // Accept CCCD writes so that clients can subscribe. Notifications are sent
+            // unconditionally, so the value is not stored.
+            0x0004 => Ok(()),
+            _ => Err(AttError::new(ErrorCode::WriteNotPermitted, handle)),
+        }
+    }
 }
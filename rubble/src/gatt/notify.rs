@@ -0,0 +1,147 @@
+//! Rate limiting of characteristic notifications.
+//!
+//! A sensor loop that notifies every new sample can easily produce data faster than the
+//! connection can transfer it, filling up the TX queue and delaying everything else (like responses
+//! to the client's requests). A `RateLimitedNotifier` sits between the application and the ATT
+//! server: The application hands it every new value, and it sends at most one notification per
+//! interval, always containing the most recent value. Intermediate values are dropped.
+//!
+//! ```
+//! use rubble::att::Handle;
+//! use rubble::gatt::notify::RateLimitedNotifier;
+//! use rubble::time::{Duration, Instant};
+//!
+//! let mut notifier = RateLimitedNotifier::new(Handle::from_raw(3), Duration::from_millis(100));
+//! notifier.update(&[42]);
+//! assert!(notifier.is_pending());
+//! assert_eq!(notifier.next_send(Instant::from_raw_micros(1000)).raw_micros(), 1000);
+//!
+//! // Then, from the idle loop:
+//! // notifier.poll(timer.now(), &mut responder.l2cap());
+//! ```
+
+use crate::{
+    att::Handle,
+    l2cap::{ChannelMapper, L2CAPStateTx},
+    link::queue::Producer,
+    time::{Duration, Instant},
+};
+
+/// Largest notification value that fits into the default `ATT_MTU` of 23 Bytes.
+pub const MAX_VALUE: usize = 20;
+
+/// Coalesces value updates of a single characteristic into rate-limited notifications.
+#[derive(Debug, Clone)]
+pub struct RateLimitedNotifier {
+    handle: Handle,
+    interval: Duration,
+    last_sent: Option<Instant>,
+    value: [u8; MAX_VALUE],
+    len: usize,
+    pending: bool,
+}
+
+impl RateLimitedNotifier {
+    /// Creates a notifier for the characteristic value at `handle` that sends at most one
+    /// notification per `interval`.
+    pub fn new(handle: Handle, interval: Duration) -> Self {
+        Self {
+            handle,
+            interval,
+            last_sent: None,
+            value: [0; MAX_VALUE],
+            len: 0,
+            pending: false,
+        }
+    }
+
+    /// Sets the value to notify next, replacing any value that hasn't been sent yet.
+    ///
+    /// Values longer than `MAX_VALUE` Bytes are truncated.
+    pub fn update(&mut self, value: &[u8]) {
+        let len = value.len().min(MAX_VALUE);
+        self.value[..len].copy_from_slice(&value[..len]);
+        self.len = len;
+        self.pending = true;
+    }
+
+    /// Returns whether there's an updated value that hasn't been notified yet.
+    pub fn is_pending(&self) -> bool {
+        self.pending
+    }
+
+    /// Discards the pending value, eg. because the client unsubscribed.
+    pub fn cancel(&mut self) {
+        self.pending = false;
+    }
+
+    /// Returns the earliest time at which the next notification may be sent, assuming the current
+    /// time is `now`.
+    ///
+    /// This can be used to schedule a wakeup when a value is pending.
+    pub fn next_send(&self, now: Instant) -> Instant {
+        match self.last_sent {
+            Some(last) if !self.interval_elapsed(last, now) => last + self.interval,
+            _ => now,
+        }
+    }
+
+    /// Sends the pending value, if there is one and the interval since the last notification has
+    /// elapsed.
+    ///
+    /// If the TX queue is full, the value stays pending and will be sent by a later call. Returns
+    /// whether a notification was enqueued.
+    pub fn poll<M: ChannelMapper, P: Producer>(
+        &mut self,
+        now: Instant,
+        l2cap: &mut L2CAPStateTx<'_, M, P>,
+    ) -> bool {
+        if !self.pending {
+            return false;
+        }
+        if let Some(last) = self.last_sent {
+            if !self.interval_elapsed(last, now) {
+                return false;
+            }
+        }
+
+        match l2cap.att() {
+            Some(att) => {
+                att.notify_raw(self.handle, &self.value[..self.len]);
+                self.last_sent = Some(now);
+                self.pending = false;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Checks whether `interval` has passed between `last` and `now`.
+    ///
+    /// This doesn't use `Instant::duration_since`, since the last notification may be arbitrarily
+    /// long ago.
+    fn interval_elapsed(&self, last: Instant, now: Instant) -> bool {
+        now.raw_micros().wrapping_sub(last.raw_micros()) >= self.interval.as_micros()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coalesce() {
+        let t = Instant::from_raw_micros;
+        let mut n = RateLimitedNotifier::new(Handle::from_raw(1), Duration::from_millis(10));
+        n.last_sent = Some(t(0));
+        n.update(&[1]);
+        n.update(&[2; 30]);
+        assert!(n.is_pending());
+        assert_eq!(n.len, MAX_VALUE);
+        assert_eq!(n.next_send(t(5_000)).raw_micros(), 10_000);
+        assert_eq!(n.next_send(t(12_000)).raw_micros(), 12_000);
+
+        n.cancel();
+        assert!(!n.is_pending());
+    }
+}
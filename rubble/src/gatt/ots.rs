@@ -0,0 +1,809 @@
+//! *Object Transfer Service* (OTS).
+//!
+//! OTS exposes a list of *objects* (files, logs, firmware images, ...) that the client can browse
+//! and read or write in bulk. The GATT side only carries metadata and control: The client selects
+//! the *current object* with the *Object List Control Point* (OLCP), reads its metadata from the
+//! object characteristics, and starts a transfer with the *Object Action Control Point* (OACP).
+//! The object data itself is then exchanged over the *Object Transfer Channel*, an L2CAP
+//! connection-oriented channel the client opens to SPSM `Spsm::OTS`.
+//!
+//! The objects are provided by the application through the [`ObjectStore`] trait.
+//! [`ObjectTransferServer`] hosts the service in an `AttributeTable` and drives transfers:
+//!
+//! * The `CocListener` has to accept channels to `Spsm::OTS`. Whenever a channel is opened or
+//!   closed, the application calls `ObjectTransferServer::set_channel_available`.
+//! * The `AttributeProvider` passes writes to `ObjectTransferServer::write_attr`. Control point
+//!   responses are sent by the application with `AttributeServerTx::indicate_raw`, using
+//!   `pending_oacp_response` and `pending_olcp_response`.
+//! * From the idle loop, the application calls `ObjectTransferServer::process` with the channel
+//!   returned by `L2CAPStateTx::credit_channel_for(Spsm::OTS)`, which moves object data in either
+//!   direction.
+//!
+//! Only the *Read*, *Write*, *Delete* and *Abort* object actions are supported, and objects can't
+//! be created by the client. The metadata characteristics are empty while no object is selected.
+//!
+//! [`ObjectStore`]: trait.ObjectStore.html
+//! [`ObjectTransferServer`]: struct.ObjectTransferServer.html
+
+use {
+    crate::{
+        att::{AttError, AttUuid, ErrorCode, Handle},
+        bytes::{ByteWriter, ToBytes},
+        gatt::{
+            characteristic::Properties,
+            table::{AttributeTable, TableEntry},
+        },
+        l2cap::coc::CreditChannelTx,
+        utils::{self, truncate_utf8},
+        uuid::Uuid16,
+        Error,
+    },
+    bitflags::bitflags,
+    heapless::{consts::U8, ArrayLength, Vec},
+};
+
+/// UUID of the *Object Transfer Service*.
+pub const OBJECT_TRANSFER_SERVICE: Uuid16 = Uuid16(0x1825);
+
+/// UUID of the *OTS Feature* characteristic.
+pub const OTS_FEATURE: Uuid16 = Uuid16(0x2ABD);
+
+/// UUID of the *Object Name* characteristic.
+pub const OBJECT_NAME: Uuid16 = Uuid16(0x2ABE);
+
+/// UUID of the *Object Type* characteristic.
+pub const OBJECT_TYPE: Uuid16 = Uuid16(0x2ABF);
+
+/// UUID of the *Object Size* characteristic.
+pub const OBJECT_SIZE: Uuid16 = Uuid16(0x2AC0);
+
+/// UUID of the *Object ID* characteristic.
+pub const OBJECT_ID: Uuid16 = Uuid16(0x2AC3);
+
+/// UUID of the *Object Properties* characteristic.
+pub const OBJECT_PROPERTIES: Uuid16 = Uuid16(0x2AC4);
+
+/// UUID of the *Object Action Control Point* characteristic.
+pub const OBJECT_ACTION_CONTROL_POINT: Uuid16 = Uuid16(0x2AC5);
+
+/// UUID of the *Object List Control Point* characteristic.
+pub const OBJECT_LIST_CONTROL_POINT: Uuid16 = Uuid16(0x2AC6);
+
+/// Number of Bytes reserved for the *Object Name* value.
+pub const MAX_NAME_LEN: usize = 32;
+
+/// ATT error returned when a control point is written without indications being enabled.
+const CCCD_IMPROPERLY_CONFIGURED: u8 = 0xFD;
+
+/// ATT error returned when a control point is written before the last response was sent.
+const PROCEDURE_ALREADY_IN_PROGRESS: u8 = 0xFE;
+
+const OACP_DELETE: u8 = 0x02;
+const OACP_READ: u8 = 0x05;
+const OACP_WRITE: u8 = 0x06;
+const OACP_ABORT: u8 = 0x07;
+const OACP_RESPONSE: u8 = 0x60;
+
+const OLCP_FIRST: u8 = 0x01;
+const OLCP_LAST: u8 = 0x02;
+const OLCP_PREVIOUS: u8 = 0x03;
+const OLCP_NEXT: u8 = 0x04;
+const OLCP_GO_TO: u8 = 0x05;
+const OLCP_REQUEST_NUMBER: u8 = 0x07;
+const OLCP_RESPONSE: u8 = 0x70;
+
+/// *Write* mode flag requesting the object to be truncated to the end of the written data.
+const WRITE_TRUNCATE: u8 = 1 << 1;
+
+bitflags! {
+    /// What the client may do with an object.
+    pub struct ObjectProperties: u32 {
+        const DELETE = 1 << 0;
+        const EXECUTE = 1 << 1;
+        const READ = 1 << 2;
+        const WRITE = 1 << 3;
+        const APPEND = 1 << 4;
+        const TRUNCATE = 1 << 5;
+        const PATCH = 1 << 6;
+        const MARK = 1 << 7;
+    }
+}
+
+enum_with_unknown! {
+    /// Result of an OACP request.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum OacpResult(u8) {
+        Success = 0x01,
+        OpCodeNotSupported = 0x02,
+        InvalidParameter = 0x03,
+        InsufficientResources = 0x04,
+        InvalidObject = 0x05,
+        ChannelUnavailable = 0x06,
+        UnsupportedType = 0x07,
+        ProcedureNotPermitted = 0x08,
+        ObjectLocked = 0x09,
+        OperationFailed = 0x0A,
+    }
+}
+
+enum_with_unknown! {
+    /// Result of an OLCP request.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum OlcpResult(u8) {
+        Success = 0x01,
+        OpCodeNotSupported = 0x02,
+        InvalidParameter = 0x03,
+        OperationFailed = 0x04,
+        OutOfBounds = 0x05,
+        TooManyObjects = 0x06,
+        NoObject = 0x07,
+        ObjectIdNotFound = 0x08,
+    }
+}
+
+/// Metadata of a stored object.
+#[derive(Debug, Copy, Clone)]
+pub struct ObjectMetadata<'a> {
+    /// Unique 48-bit ID of the object. IDs below `0x100` are reserved.
+    pub id: u64,
+    pub name: &'a str,
+    pub object_type: AttUuid,
+    /// Number of Bytes of data stored in the object.
+    pub current_size: u32,
+    /// Number of Bytes the object can hold.
+    pub allocated_size: u32,
+    pub properties: ObjectProperties,
+}
+
+/// The objects exposed by an `ObjectTransferServer`, provided by the application.
+///
+/// Objects are addressed by their index in the object list.
+pub trait ObjectStore {
+    /// Returns the number of objects.
+    fn len(&self) -> usize;
+
+    /// Returns whether there are no objects.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the metadata of the object at `index`.
+    fn metadata(&self, index: usize) -> Option<ObjectMetadata<'_>>;
+
+    /// Reads object data starting at `offset` into `buf`.
+    ///
+    /// Returns the number of Bytes read, which may be less than `buf.len()`.
+    fn read(&mut self, index: usize, offset: u32, buf: &mut [u8]) -> Result<usize, Error>;
+
+    /// Writes `data` to the object at `offset`.
+    ///
+    /// The default implementation refuses to write.
+    fn write(&mut self, index: usize, offset: u32, data: &[u8]) -> Result<(), Error> {
+        let _ = (index, offset, data);
+        Err(Error::InvalidValue)
+    }
+
+    /// Sets the current size of the object after a truncating write.
+    ///
+    /// The default implementation refuses to truncate.
+    fn truncate(&mut self, index: usize, len: u32) -> Result<(), Error> {
+        let _ = (index, len);
+        Err(Error::InvalidValue)
+    }
+
+    /// Deletes the object.
+    ///
+    /// The indices of all following objects decrease by one. The default implementation refuses
+    /// to delete.
+    fn delete(&mut self, index: usize) -> Result<(), Error> {
+        let _ = index;
+        Err(Error::InvalidValue)
+    }
+}
+
+/// A running object transfer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Transfer {
+    object: usize,
+    /// Client to server.
+    write: bool,
+    truncate: bool,
+    offset: u32,
+    end: u32,
+}
+
+/// The server side of OTS, hosted in an `AttributeTable`.
+#[derive(Debug)]
+pub struct ObjectTransferServer {
+    name: Handle,
+    object_type: Handle,
+    size: Handle,
+    id: Handle,
+    properties: Handle,
+    oacp: Handle,
+    olcp: Handle,
+    current: Option<usize>,
+    transfer: Option<Transfer>,
+    channel_available: bool,
+    oacp_response: Vec<u8, U8>,
+    olcp_response: Vec<u8, U8>,
+}
+
+impl ObjectTransferServer {
+    /// Adds the service to `table`.
+    pub fn add_to<N, B>(table: &mut AttributeTable<N, B>) -> Result<Self, Error>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        // OACP: Delete, Read, Write, Truncation, Abort. OLCP: Go To, Request Number of Objects.
+        let oacp_features: u32 = (1 << 1) | (1 << 4) | (1 << 5) | (1 << 7) | (1 << 9);
+        let olcp_features: u32 = (1 << 0) | (1 << 2);
+        let mut features = [0; 8];
+        features[..4].copy_from_slice(&oacp_features.to_le_bytes());
+        features[4..].copy_from_slice(&olcp_features.to_le_bytes());
+
+        let read = Properties::READ;
+        let control = Properties::WRITE | Properties::INDICATE;
+        table.add_service(OBJECT_TRANSFER_SERVICE)?;
+        table.add_characteristic(OTS_FEATURE, read, &features)?;
+        let name = table.add_characteristic_with_capacity(OBJECT_NAME, read, &[], MAX_NAME_LEN)?;
+        let object_type = table.add_characteristic_with_capacity(OBJECT_TYPE, read, &[], 16)?;
+        let size = table.add_characteristic_with_capacity(OBJECT_SIZE, read, &[], 8)?;
+        let id = table.add_characteristic_with_capacity(OBJECT_ID, read, &[], 6)?;
+        let properties = table.add_characteristic_with_capacity(OBJECT_PROPERTIES, read, &[], 4)?;
+        let oacp = table.add_characteristic(OBJECT_ACTION_CONTROL_POINT, control, &[])?;
+        let olcp = table.add_characteristic(OBJECT_LIST_CONTROL_POINT, control, &[])?;
+        Ok(Self {
+            name,
+            object_type,
+            size,
+            id,
+            properties,
+            oacp,
+            olcp,
+            current: None,
+            transfer: None,
+            channel_available: false,
+            oacp_response: Vec::new(),
+            olcp_response: Vec::new(),
+        })
+    }
+
+    /// Returns the handle of the OACP value.
+    pub fn oacp(&self) -> Handle {
+        self.oacp
+    }
+
+    /// Returns the handle of the OLCP value.
+    pub fn olcp(&self) -> Handle {
+        self.olcp
+    }
+
+    /// Returns the index of the current object, if one is selected.
+    pub fn current(&self) -> Option<usize> {
+        self.current
+    }
+
+    /// Returns whether an object transfer is in progress.
+    pub fn is_transferring(&self) -> bool {
+        self.transfer.is_some()
+    }
+
+    /// Tells the server whether the client has an Object Transfer Channel open.
+    ///
+    /// Closing the channel aborts a running transfer.
+    pub fn set_channel_available(&mut self, available: bool) {
+        self.channel_available = available;
+        if !available {
+            self.transfer = None;
+        }
+    }
+
+    /// Selects the object at `index` as the current object, eg. to point the client at a newly
+    /// recorded log.
+    ///
+    /// Returns `Error::InvalidValue` if there's no such object or a transfer is in progress.
+    pub fn select<N, B, S>(
+        &mut self,
+        table: &mut AttributeTable<N, B>,
+        store: &S,
+        index: usize,
+    ) -> Result<(), Error>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+        S: ObjectStore,
+    {
+        if self.transfer.is_some() {
+            return Err(Error::InvalidValue);
+        }
+        let meta = store.metadata(index).ok_or(Error::InvalidValue)?;
+        self.current = Some(index);
+        self.update_metadata(table, &meta)
+    }
+
+    /// Returns the response to indicate on `oacp()`, if there is one.
+    pub fn pending_oacp_response(&self) -> Option<&[u8]> {
+        Some(&self.oacp_response[..]).filter(|r| !r.is_empty())
+    }
+
+    /// Marks the pending OACP response as sent.
+    pub fn oacp_response_sent(&mut self) {
+        utils::truncate(&mut self.oacp_response, 0);
+    }
+
+    /// Returns the response to indicate on `olcp()`, if there is one.
+    pub fn pending_olcp_response(&self) -> Option<&[u8]> {
+        Some(&self.olcp_response[..]).filter(|r| !r.is_empty())
+    }
+
+    /// Marks the pending OLCP response as sent.
+    pub fn olcp_response_sent(&mut self) {
+        utils::truncate(&mut self.olcp_response, 0);
+    }
+
+    /// Handles a write to the attribute at `handle`.
+    ///
+    /// Returns `None` if `handle` isn't one of the control points, in which case the write should
+    /// be processed as usual.
+    pub fn write_attr<N, B, S>(
+        &mut self,
+        table: &mut AttributeTable<N, B>,
+        store: &mut S,
+        handle: Handle,
+        value: &[u8],
+    ) -> Option<Result<(), AttError>>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+        S: ObjectStore,
+    {
+        let pending = if handle == self.oacp {
+            !self.oacp_response.is_empty()
+        } else if handle == self.olcp {
+            !self.olcp_response.is_empty()
+        } else {
+            return None;
+        };
+
+        let reject = |code: u8| Some(Err(AttError::new(ErrorCode::from(code), handle)));
+        if !table.indications_enabled(handle) {
+            return reject(CCCD_IMPROPERLY_CONFIGURED);
+        }
+        if pending {
+            return reject(PROCEDURE_ALREADY_IN_PROGRESS);
+        }
+        let (&opcode, params) = match value.split_first() {
+            Some(split) => split,
+            None => {
+                let code = ErrorCode::InvalidAttributeValueLength;
+                return Some(Err(AttError::new(code, handle)));
+            }
+        };
+
+        if handle == self.oacp {
+            let result = self.object_action(table, store, opcode, params);
+            self.oacp_response
+                .extend_from_slice(&[OACP_RESPONSE, opcode, result.into()])
+                .unwrap();
+        } else {
+            let (result, count) = self.list_action(table, store, opcode, params);
+            self.olcp_response
+                .extend_from_slice(&[OLCP_RESPONSE, opcode, result.into()])
+                .unwrap();
+            if let Some(count) = count {
+                self.olcp_response
+                    .extend_from_slice(&count.to_le_bytes())
+                    .unwrap();
+            }
+        }
+        Some(Ok(()))
+    }
+
+    /// Moves object data of a running transfer over the Object Transfer Channel.
+    ///
+    /// Data is sent until the peer runs out of credits or the TX queue is full, or received SDUs
+    /// are written to the object. Call this repeatedly from the idle loop while
+    /// `is_transferring()` is `true`. Returns `Error::Eof` if the TX queue was full while granting
+    /// credits, in which case this has to be called again later.
+    pub fn process<N, B, S>(
+        &mut self,
+        table: &mut AttributeTable<N, B>,
+        store: &mut S,
+        channel: &mut CreditChannelTx<'_>,
+    ) -> Result<(), Error>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+        S: ObjectStore,
+    {
+        let write = match self.transfer {
+            Some(t) => t.write,
+            None => return Ok(()),
+        };
+
+        if write {
+            if let Some(sdu) = channel.sdu() {
+                self.receive(table, store, sdu);
+                channel.release()?;
+            }
+            return Ok(());
+        }
+
+        let mut buf = [0; 32];
+        let max = channel.max_send_len().min(buf.len());
+        while let Some(chunk) = self.next_chunk(store, &mut buf[..max]) {
+            match channel.send(chunk) {
+                Ok(()) => self.advance(chunk.len()),
+                // Out of credits or queue space, resume later
+                Err(Error::Eof) => break,
+                Err(_) => {
+                    self.transfer = None;
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the next chunk of object data to send into `buf`.
+    fn next_chunk<'a, S: ObjectStore>(
+        &mut self,
+        store: &mut S,
+        buf: &'a mut [u8],
+    ) -> Option<&'a [u8]> {
+        let t = self.transfer?;
+        let len = (t.end - t.offset).min(buf.len() as u32) as usize;
+        match store.read(t.object, t.offset, &mut buf[..len]) {
+            Ok(read) if read > 0 => Some(&buf[..read]),
+            _ => {
+                // The object shrunk or can't be read, there's no way to report that in-band
+                self.transfer = None;
+                None
+            }
+        }
+    }
+
+    /// Accounts for `len` Bytes of object data that were sent.
+    fn advance(&mut self, len: usize) {
+        if let Some(t) = &mut self.transfer {
+            t.offset += len as u32;
+            if t.offset >= t.end {
+                self.transfer = None;
+            }
+        }
+    }
+
+    /// Writes `data` received on the channel to the object.
+    fn receive<N, B, S>(&mut self, table: &mut AttributeTable<N, B>, store: &mut S, data: &[u8])
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+        S: ObjectStore,
+    {
+        let t = match &mut self.transfer {
+            Some(t) => t,
+            None => return,
+        };
+        let len = data.len().min((t.end - t.offset) as usize);
+        if store.write(t.object, t.offset, &data[..len]).is_err() {
+            self.transfer = None;
+            return;
+        }
+        t.offset += len as u32;
+        if t.offset < t.end {
+            return;
+        }
+
+        let t = *t;
+        self.transfer = None;
+        if t.truncate {
+            store.truncate(t.object, t.end).ok();
+        }
+        if let Some(meta) = store.metadata(t.object) {
+            self.update_metadata(table, &meta).ok();
+        }
+    }
+
+    fn object_action<N, B, S>(
+        &mut self,
+        table: &mut AttributeTable<N, B>,
+        store: &mut S,
+        opcode: u8,
+        params: &[u8],
+    ) -> OacpResult
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+        S: ObjectStore,
+    {
+        if opcode == OACP_ABORT {
+            return match self.transfer.take() {
+                Some(t) if !t.write => OacpResult::Success,
+                other => {
+                    // Only reads can be aborted
+                    self.transfer = other;
+                    OacpResult::ProcedureNotPermitted
+                }
+            };
+        }
+        if opcode != OACP_DELETE && opcode != OACP_READ && opcode != OACP_WRITE {
+            return OacpResult::OpCodeNotSupported;
+        }
+
+        let index = match self.current {
+            Some(index) => index,
+            None => return OacpResult::InvalidObject,
+        };
+        let meta = match store.metadata(index) {
+            Some(meta) => meta,
+            None => return OacpResult::InvalidObject,
+        };
+        if self.transfer.is_some() {
+            return OacpResult::ObjectLocked;
+        }
+
+        match opcode {
+            OACP_DELETE => {
+                if !meta.properties.contains(ObjectProperties::DELETE) {
+                    return OacpResult::ProcedureNotPermitted;
+                }
+                if store.delete(index).is_err() {
+                    return OacpResult::OperationFailed;
+                }
+                self.current = None;
+                self.clear_metadata(table);
+                OacpResult::Success
+            }
+            _ => {
+                let write = opcode == OACP_WRITE;
+                let (offset, len, mode) = match (write, params) {
+                    (false, [a, b, c, d, e, f, g, h]) => (
+                        u32::from_le_bytes([*a, *b, *c, *d]),
+                        u32::from_le_bytes([*e, *f, *g, *h]),
+                        0,
+                    ),
+                    (true, [a, b, c, d, e, f, g, h, mode]) => (
+                        u32::from_le_bytes([*a, *b, *c, *d]),
+                        u32::from_le_bytes([*e, *f, *g, *h]),
+                        *mode,
+                    ),
+                    _ => return OacpResult::InvalidParameter,
+                };
+                let required = if write {
+                    ObjectProperties::WRITE
+                } else {
+                    ObjectProperties::READ
+                };
+                let truncate = mode & WRITE_TRUNCATE != 0;
+                if !meta.properties.contains(required)
+                    || (truncate && !meta.properties.contains(ObjectProperties::TRUNCATE))
+                {
+                    return OacpResult::ProcedureNotPermitted;
+                }
+                let end = match offset.checked_add(len) {
+                    Some(end) if offset <= meta.current_size && len > 0 => end,
+                    _ => return OacpResult::InvalidParameter,
+                };
+                if (!write && end > meta.current_size) || (write && end > meta.allocated_size) {
+                    return OacpResult::InvalidParameter;
+                }
+                if !self.channel_available {
+                    return OacpResult::ChannelUnavailable;
+                }
+                self.transfer = Some(Transfer {
+                    object: index,
+                    write,
+                    truncate,
+                    offset,
+                    end,
+                });
+                OacpResult::Success
+            }
+        }
+    }
+
+    fn list_action<N, B, S>(
+        &mut self,
+        table: &mut AttributeTable<N, B>,
+        store: &S,
+        opcode: u8,
+        params: &[u8],
+    ) -> (OlcpResult, Option<u32>)
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+        S: ObjectStore,
+    {
+        let len = store.len();
+        let target = match (opcode, self.current) {
+            (OLCP_REQUEST_NUMBER, _) => return (OlcpResult::Success, Some(len as u32)),
+            (OLCP_FIRST, _) | (OLCP_LAST, _) if len == 0 => return (OlcpResult::NoObject, None),
+            (OLCP_FIRST, _) => 0,
+            (OLCP_LAST, _) => len - 1,
+            (OLCP_PREVIOUS, Some(0)) => return (OlcpResult::OutOfBounds, None),
+            (OLCP_PREVIOUS, Some(i)) => i - 1,
+            (OLCP_NEXT, Some(i)) if i + 1 >= len => return (OlcpResult::OutOfBounds, None),
+            (OLCP_NEXT, Some(i)) => i + 1,
+            (OLCP_PREVIOUS, None) | (OLCP_NEXT, None) => {
+                return (OlcpResult::OperationFailed, None)
+            }
+            (OLCP_GO_TO, _) => {
+                let id = match params {
+                    [a, b, c, d, e, f] => u64::from_le_bytes([*a, *b, *c, *d, *e, *f, 0, 0]),
+                    _ => return (OlcpResult::InvalidParameter, None),
+                };
+                match (0..len).find(|i| store.metadata(*i).map(|m| m.id) == Some(id)) {
+                    Some(i) => i,
+                    None => return (OlcpResult::ObjectIdNotFound, None),
+                }
+            }
+            _ => return (OlcpResult::OpCodeNotSupported, None),
+        };
+        // Fails if the current object is being transferred
+        match self.select(table, store, target) {
+            Ok(()) => (OlcpResult::Success, None),
+            Err(_) => (OlcpResult::OperationFailed, None),
+        }
+    }
+
+    fn update_metadata<N, B>(
+        &self,
+        table: &mut AttributeTable<N, B>,
+        meta: &ObjectMetadata<'_>,
+    ) -> Result<(), Error>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        let name = truncate_utf8(meta.name, MAX_NAME_LEN);
+        table.set_value(self.name, name.as_bytes())?;
+        let mut uuid = [0; 16];
+        let mut writer = ByteWriter::new(&mut uuid);
+        meta.object_type.to_bytes(&mut writer)?;
+        let uuid_len = 16 - writer.space_left();
+        table.set_value(self.object_type, &uuid[..uuid_len])?;
+        let mut size = [0; 8];
+        size[..4].copy_from_slice(&meta.current_size.to_le_bytes());
+        size[4..].copy_from_slice(&meta.allocated_size.to_le_bytes());
+        table.set_value(self.size, &size)?;
+        table.set_value(self.id, &meta.id.to_le_bytes()[..6])?;
+        table.set_value(self.properties, &meta.properties.bits().to_le_bytes())
+    }
+
+    fn clear_metadata<N, B>(&self, table: &mut AttributeTable<N, B>)
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        for handle in &[
+            self.name,
+            self.object_type,
+            self.size,
+            self.id,
+            self.properties,
+        ] {
+            table.set_value(*handle, &[]).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, heapless::consts::*};
+
+    /// Two 40-Byte objects: A read-only log and a writable config blob.
+    struct Objects([[u8; 40]; 2]);
+
+    impl ObjectStore for Objects {
+        fn len(&self) -> usize {
+            2
+        }
+
+        fn metadata(&self, index: usize) -> Option<ObjectMetadata<'_>> {
+            let properties = if index == 0 {
+                ObjectProperties::READ
+            } else {
+                ObjectProperties::READ | ObjectProperties::WRITE
+            };
+            Some(ObjectMetadata {
+                id: 0x100 + index as u64,
+                name: ["log", "config"].get(index)?,
+                object_type: Uuid16(0x2ACA).into(),
+                current_size: 40,
+                allocated_size: 40,
+                properties,
+            })
+        }
+
+        fn read(&mut self, index: usize, offset: u32, buf: &mut [u8]) -> Result<usize, Error> {
+            let data = &self.0[index][offset as usize..];
+            let len = buf.len().min(data.len());
+            buf[..len].copy_from_slice(&data[..len]);
+            Ok(len)
+        }
+
+        fn write(&mut self, index: usize, offset: u32, data: &[u8]) -> Result<(), Error> {
+            let start = offset as usize;
+            self.0[index][start..start + data.len()].copy_from_slice(data);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn select_and_transfer() {
+        let mut table = AttributeTable::<U32, U128>::new();
+        let mut ots = ObjectTransferServer::add_to(&mut table).unwrap();
+        let mut store = Objects([[0xAA; 40], [0; 40]]);
+        let (oacp, olcp) = (ots.oacp(), ots.olcp());
+        for handle in &[oacp, olcp] {
+            let cccd = table.cccd_handle(*handle).unwrap();
+            table.set_value(cccd, &[0x02, 0x00]).unwrap();
+        }
+
+        // Nothing selected yet
+        let read = [OACP_READ, 0, 0, 0, 0, 40, 0, 0, 0];
+        ots.write_attr(&mut table, &mut store, oacp, &read)
+            .unwrap()
+            .unwrap();
+        assert_eq!(ots.pending_oacp_response(), Some(&[0x60, 0x05, 0x05][..]));
+        ots.oacp_response_sent();
+
+        ots.write_attr(&mut table, &mut store, olcp, &[OLCP_REQUEST_NUMBER])
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            ots.pending_olcp_response(),
+            Some(&[0x70, 0x07, 0x01, 2, 0, 0, 0][..])
+        );
+        ots.olcp_response_sent();
+        ots.write_attr(
+            &mut table,
+            &mut store,
+            olcp,
+            &[OLCP_GO_TO, 0x01, 0x01, 0, 0, 0, 0],
+        )
+        .unwrap()
+        .unwrap();
+        ots.olcp_response_sent();
+        assert_eq!(ots.current(), Some(1));
+        assert_eq!(table.value(ots.name), Some(&b"config"[..]));
+
+        // Reading needs the channel
+        ots.write_attr(&mut table, &mut store, oacp, &read)
+            .unwrap()
+            .unwrap();
+        assert_eq!(ots.pending_oacp_response(), Some(&[0x60, 0x05, 0x06][..]));
+        ots.oacp_response_sent();
+        ots.set_channel_available(true);
+
+        // Write 8 Bytes at offset 4
+        let write = [OACP_WRITE, 4, 0, 0, 0, 8, 0, 0, 0, 0];
+        ots.write_attr(&mut table, &mut store, oacp, &write)
+            .unwrap()
+            .unwrap();
+        ots.oacp_response_sent();
+        ots.receive(&mut table, &mut store, &[1, 2, 3, 4, 5]);
+        assert!(ots.is_transferring());
+        ots.receive(&mut table, &mut store, &[6, 7, 8, 9, 10]);
+        assert!(!ots.is_transferring());
+        assert_eq!(store.0[1][..14], [0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 0, 0]);
+
+        // Read it back in chunks
+        let read = [OACP_READ, 4, 0, 0, 0, 8, 0, 0, 0];
+        ots.write_attr(&mut table, &mut store, oacp, &read)
+            .unwrap()
+            .unwrap();
+        assert_eq!(ots.pending_oacp_response(), Some(&[0x60, 0x05, 0x01][..]));
+        let mut buf = [0; 5];
+        assert_eq!(
+            ots.next_chunk(&mut store, &mut buf),
+            Some(&[1, 2, 3, 4, 5][..])
+        );
+        ots.advance(5);
+        assert_eq!(ots.next_chunk(&mut store, &mut buf), Some(&[6, 7, 8][..]));
+        ots.advance(3);
+        assert!(!ots.is_transferring());
+    }
+}
@@ -0,0 +1,530 @@
+//! *Apple Notification Center Service* (ANCS) consumer.
+//!
+//! iOS devices expose the notifications shown on the phone via ANCS. The `AncsClient` subscribes
+//! to the *Notification Source*, which announces every added, modified or removed notification,
+//! and to the *Data Source*, over which the attributes requested with `fetch_attributes` (title,
+//! message, etc.) arrive. Both are passed to an [`AncsHandler`].
+//!
+//! The phone only allows subscribing once the link is encrypted with a bonded key. Until then,
+//! subscribing fails and `AncsClient::needs_security` returns `true`. The application should pair
+//! and call `AncsClient::retry` once encryption is enabled.
+//!
+//! [`AncsHandler`]: trait.AncsHandler.html
+
+use {
+    crate::{
+        att::{AttUuid, ClientEvent, ClientRequest, ErrorCode, Handle},
+        bytes::{ByteReader, ByteWriter},
+        gatt::discovery::{DiscoveryStatus, ServiceDiscovery},
+        uuid::Uuid,
+        Error,
+    },
+    bitflags::bitflags,
+};
+
+// UUIDs are given in the Byte order used on air.
+
+const ANCS: AttUuid = AttUuid::Uuid128(Uuid::from_bytes([
+    0xD0, 0x00, 0x2D, 0x12, 0x1E, 0x4B, 0x0F, 0xA4, 0x99, 0x4E, 0xCE, 0xB5, 0x31, 0xF4, 0x05, 0x79,
+]));
+const NOTIFICATION_SOURCE: AttUuid = AttUuid::Uuid128(Uuid::from_bytes([
+    0xBD, 0x1D, 0xA2, 0x99, 0xE6, 0x25, 0x58, 0x8C, 0xD9, 0x42, 0x01, 0x63, 0x0D, 0x12, 0xBF, 0x9F,
+]));
+const CONTROL_POINT: AttUuid = AttUuid::Uuid128(Uuid::from_bytes([
+    0xD9, 0xD9, 0xAA, 0xFD, 0xBD, 0x9B, 0x21, 0x98, 0xA8, 0x49, 0xE1, 0x45, 0xF3, 0xD8, 0xD1, 0x69,
+]));
+const DATA_SOURCE: AttUuid = AttUuid::Uuid128(Uuid::from_bytes([
+    0xFB, 0x7B, 0x7C, 0xCE, 0x6A, 0xB3, 0x44, 0xBE, 0xB5, 0x4B, 0xD6, 0x24, 0xE9, 0xC6, 0xEA, 0x22,
+]));
+const CHARACTERISTICS: &[AttUuid] = &[NOTIFICATION_SOURCE, CONTROL_POINT, DATA_SOURCE];
+
+const GET_NOTIFICATION_ATTRIBUTES: u8 = 0;
+const PERFORM_NOTIFICATION_ACTION: u8 = 2;
+
+/// Maximum length of attribute values.
+///
+/// Longer values are truncated by the phone (for title, subtitle and message) or by the client.
+pub const MAX_ATTRIBUTE_LEN: usize = 64;
+
+/// Maximum length of a Control Point command with the default `ATT_MTU`.
+const MAX_COMMAND_LEN: usize = 20;
+
+enum_with_unknown! {
+    /// What happened to a notification.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum EventId(u8) {
+        Added = 0,
+        Modified = 1,
+        Removed = 2,
+    }
+}
+
+enum_with_unknown! {
+    /// Category of a notification.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum CategoryId(u8) {
+        Other = 0,
+        IncomingCall = 1,
+        MissedCall = 2,
+        Voicemail = 3,
+        Social = 4,
+        Schedule = 5,
+        Email = 6,
+        News = 7,
+        HealthAndFitness = 8,
+        BusinessAndFinance = 9,
+        Location = 10,
+        Entertainment = 11,
+    }
+}
+
+enum_with_unknown! {
+    /// Attributes of a notification that can be fetched.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum NotificationAttributeId(u8) {
+        AppIdentifier = 0,
+        Title = 1,
+        Subtitle = 2,
+        Message = 3,
+        MessageSize = 4,
+        Date = 5,
+        PositiveActionLabel = 6,
+        NegativeActionLabel = 7,
+    }
+}
+
+impl NotificationAttributeId {
+    /// Returns whether a maximum length has to be requested for the attribute.
+    fn has_max_len(&self) -> bool {
+        matches!(
+            self,
+            NotificationAttributeId::Title
+                | NotificationAttributeId::Subtitle
+                | NotificationAttributeId::Message
+        )
+    }
+}
+
+bitflags! {
+    /// Flags of a notification.
+    pub struct EventFlags: u8 {
+        const SILENT = 1 << 0;
+        const IMPORTANT = 1 << 1;
+        const PRE_EXISTING = 1 << 2;
+        const POSITIVE_ACTION = 1 << 3;
+        const NEGATIVE_ACTION = 1 << 4;
+    }
+}
+
+/// A notification announced via the *Notification Source*.
+#[derive(Debug, Copy, Clone)]
+pub struct Notification {
+    /// What happened to the notification.
+    pub event: EventId,
+    pub flags: EventFlags,
+    pub category: CategoryId,
+    /// Number of active notifications in `category`.
+    pub category_count: u8,
+    /// Identifies the notification in `AncsClient::fetch_attributes` and
+    /// `AncsClient::perform_action`.
+    pub uid: u32,
+}
+
+impl Notification {
+    fn parse(value: &[u8]) -> Result<Self, Error> {
+        let mut bytes = ByteReader::new(value);
+        Ok(Self {
+            event: bytes.read_u8()?.into(),
+            flags: EventFlags::from_bits_truncate(bytes.read_u8()?),
+            category: bytes.read_u8()?.into(),
+            category_count: bytes.read_u8()?,
+            uid: bytes.read_u32_le()?,
+        })
+    }
+}
+
+/// Receives the data sent by the phone.
+///
+/// This is called from `AttributeProvider::client_event`.
+pub trait AncsHandler {
+    /// A notification was added, modified or removed.
+    fn notification(&mut self, notification: &Notification);
+
+    /// An attribute requested with `AncsClient::fetch_attributes` was received.
+    ///
+    /// `value` is truncated to `MAX_ATTRIBUTE_LEN` Bytes.
+    fn attribute(&mut self, uid: u32, id: NotificationAttributeId, value: &[u8]);
+}
+
+/// Reassembles the *Data Source* response, which can span several notifications.
+struct DataSource {
+    /// Number of attributes that are still expected.
+    remaining: u8,
+    uid: u32,
+    /// Number of header Bytes received: 5 for the command header, then 3 per attribute.
+    header_len: u8,
+    header: [u8; 5],
+    value: [u8; MAX_ATTRIBUTE_LEN],
+    value_len: u16,
+    value_pos: u16,
+}
+
+impl DataSource {
+    fn new() -> Self {
+        Self {
+            remaining: 0,
+            uid: 0,
+            header_len: 0,
+            header: [0; 5],
+            value: [0; MAX_ATTRIBUTE_LEN],
+            value_len: 0,
+            value_pos: 0,
+        }
+    }
+
+    fn expect(&mut self, attributes: u8) {
+        *self = Self::new();
+        self.remaining = attributes;
+    }
+
+    fn feed<H: AncsHandler>(&mut self, data: &[u8], handler: &mut H) {
+        for &byte in data {
+            if self.remaining == 0 {
+                debug!("unexpected ANCS data");
+                return;
+            }
+
+            if self.header_len < 5 {
+                self.header[usize::from(self.header_len)] = byte;
+                self.header_len += 1;
+                if self.header_len == 5 {
+                    let mut uid = [0; 4];
+                    uid.copy_from_slice(&self.header[1..5]);
+                    self.uid = u32::from_le_bytes(uid);
+                }
+            } else if self.header_len < 8 {
+                // Attribute header: ID and length
+                self.header[usize::from(self.header_len - 5)] = byte;
+                self.header_len += 1;
+                if self.header_len == 8 {
+                    self.value_len = u16::from_le_bytes([self.header[1], self.header[2]]);
+                    self.value_pos = 0;
+                    if self.value_len == 0 {
+                        self.attribute_done(handler);
+                    }
+                }
+            } else {
+                if usize::from(self.value_pos) < MAX_ATTRIBUTE_LEN {
+                    self.value[usize::from(self.value_pos)] = byte;
+                }
+                self.value_pos += 1;
+                if self.value_pos == self.value_len {
+                    self.attribute_done(handler);
+                }
+            }
+        }
+    }
+
+    fn attribute_done<H: AncsHandler>(&mut self, handler: &mut H) {
+        let len = usize::from(self.value_len).min(MAX_ATTRIBUTE_LEN);
+        handler.attribute(self.uid, self.header[0].into(), &self.value[..len]);
+        self.remaining -= 1;
+        self.header_len = 5;
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Step {
+    Discovering,
+    /// The Data Source is subscribed to first, so no response can be missed.
+    SubscribingData,
+    SubscribingNotifications,
+    Ready,
+    Unavailable,
+}
+
+/// Consumes notifications of an iOS device.
+pub struct AncsClient {
+    discovery: ServiceDiscovery,
+    step: Step,
+    needs_security: bool,
+    notification_source: Handle,
+    notification_cccd: Handle,
+    control_point: Handle,
+    data_source: Handle,
+    data_cccd: Handle,
+    command: [u8; MAX_COMMAND_LEN],
+    command_len: u8,
+    data: DataSource,
+}
+
+impl AncsClient {
+    /// Creates a client that is idle until `start` is called.
+    pub fn new() -> Self {
+        let none = Handle::from_raw(0);
+        Self {
+            discovery: ServiceDiscovery::new(ANCS, CHARACTERISTICS),
+            step: Step::Discovering,
+            needs_security: false,
+            notification_source: none,
+            notification_cccd: none,
+            control_point: none,
+            data_source: none,
+            data_cccd: none,
+            command: [0; MAX_COMMAND_LEN],
+            command_len: 0,
+            data: DataSource::new(),
+        }
+    }
+
+    /// Starts discovering ANCS on the phone.
+    pub fn start(&mut self) {
+        *self = Self::new();
+        self.discovery.start();
+    }
+
+    /// Returns whether notifications are being received.
+    pub fn is_ready(&self) -> bool {
+        matches!(self.step, Step::Ready)
+    }
+
+    /// Returns whether the peer turned out not to support ANCS.
+    pub fn is_unavailable(&self) -> bool {
+        matches!(self.step, Step::Unavailable)
+    }
+
+    /// Returns whether subscribing was rejected because the link isn't encrypted with a bonded
+    /// key.
+    pub fn needs_security(&self) -> bool {
+        self.needs_security
+    }
+
+    /// Retries subscribing after `needs_security` returned `true` and the link was secured.
+    pub fn retry(&mut self) {
+        self.needs_security = false;
+    }
+
+    /// Requests the attributes `attributes` of the notification `uid`.
+    ///
+    /// They are passed to `AncsHandler::attribute` as they arrive. Text attributes are requested
+    /// with a maximum length of `MAX_ATTRIBUTE_LEN`. The command is sent as the next client
+    /// request (see `AttributeServerTx::send_next_request`).
+    ///
+    /// Returns `Error::InvalidValue` if the client isn't ready or another command is still being
+    /// processed, and `Error::InvalidLength` if too many attributes are requested.
+    pub fn fetch_attributes(
+        &mut self,
+        uid: u32,
+        attributes: &[NotificationAttributeId],
+    ) -> Result<(), Error> {
+        self.check_idle()?;
+        let mut buf = [0; MAX_COMMAND_LEN];
+        let mut writer = ByteWriter::new(&mut buf);
+        writer.write_u8(GET_NOTIFICATION_ATTRIBUTES)?;
+        writer.write_u32_le(uid)?;
+        for id in attributes {
+            writer.write_u8((*id).into())?;
+            if id.has_max_len() {
+                writer.write_u16_le(MAX_ATTRIBUTE_LEN as u16)?;
+            }
+        }
+        let len = MAX_COMMAND_LEN - writer.space_left();
+
+        self.set_command(buf, len);
+        self.data.expect(attributes.len() as u8);
+        Ok(())
+    }
+
+    /// Performs the positive (eg. answering a call) or negative (eg. dismissing it) action of
+    /// the notification `uid`.
+    ///
+    /// The command is sent as the next client request. Returns `Error::InvalidValue` if the
+    /// client isn't ready or another command is still being processed.
+    pub fn perform_action(&mut self, uid: u32, positive: bool) -> Result<(), Error> {
+        self.check_idle()?;
+        let mut buf = [0; MAX_COMMAND_LEN];
+        buf[0] = PERFORM_NOTIFICATION_ACTION;
+        buf[1..5].copy_from_slice(&uid.to_le_bytes());
+        buf[5] = if positive { 0 } else { 1 };
+        self.set_command(buf, 6);
+        Ok(())
+    }
+
+    /// Returns the next request to send.
+    pub fn next_request(&self) -> Option<ClientRequest<'_>> {
+        match self.step {
+            Step::Discovering => self.discovery.next_request(),
+            _ if self.needs_security => None,
+            Step::SubscribingData => Some(ClientRequest::Write {
+                handle: self.data_cccd,
+                value: &super::ENABLE_NOTIFICATIONS,
+            }),
+            Step::SubscribingNotifications => Some(ClientRequest::Write {
+                handle: self.notification_cccd,
+                value: &super::ENABLE_NOTIFICATIONS,
+            }),
+            Step::Ready if self.command_len != 0 => Some(ClientRequest::Write {
+                handle: self.control_point,
+                value: &self.command[..usize::from(self.command_len)],
+            }),
+            Step::Ready | Step::Unavailable => None,
+        }
+    }
+
+    /// Processes an event from the ATT client, passing received data to `handler`.
+    ///
+    /// Returns whether the event was meant for this client.
+    pub fn process<H: AncsHandler>(&mut self, event: &ClientEvent<'_>, handler: &mut H) -> bool {
+        if let Step::Discovering = self.step {
+            if !self.discovery.process(event) {
+                return false;
+            }
+            self.discovered();
+            return true;
+        }
+
+        match (self.step, *event) {
+            (_, ClientEvent::Notification { handle, value })
+                if handle == self.notification_source =>
+            {
+                match Notification::parse(value) {
+                    Ok(notification) => handler.notification(&notification),
+                    Err(e) => debug!("invalid ANCS notification: {:?}", e),
+                }
+                true
+            }
+            (_, ClientEvent::Notification { handle, value }) if handle == self.data_source => {
+                self.data.feed(value, handler);
+                true
+            }
+            (Step::SubscribingData, ClientEvent::Written { handle })
+                if handle == self.data_cccd =>
+            {
+                self.step = Step::SubscribingNotifications;
+                true
+            }
+            (Step::SubscribingNotifications, ClientEvent::Written { handle })
+                if handle == self.notification_cccd =>
+            {
+                self.step = Step::Ready;
+                true
+            }
+            (Step::SubscribingData, ClientEvent::Error { handle, code })
+            | (Step::SubscribingNotifications, ClientEvent::Error { handle, code })
+                if handle == self.data_cccd || handle == self.notification_cccd =>
+            {
+                match code {
+                    ErrorCode::InsufficientAuthentication | ErrorCode::InsufficientEncryption => {
+                        self.needs_security = true
+                    }
+                    _ => self.step = Step::Unavailable,
+                }
+                true
+            }
+            (Step::Ready, ClientEvent::Written { handle }) if handle == self.control_point => {
+                self.command_len = 0;
+                true
+            }
+            (Step::Ready, ClientEvent::Error { handle, code }) if handle == self.control_point => {
+                debug!("ANCS command failed: {:?}", code);
+                self.command_len = 0;
+                self.data.expect(0);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn check_idle(&self) -> Result<(), Error> {
+        match self.step {
+            Step::Ready if self.command_len == 0 => Ok(()),
+            _ => Err(Error::InvalidValue),
+        }
+    }
+
+    fn set_command(&mut self, command: [u8; MAX_COMMAND_LEN], len: usize) {
+        self.command = command;
+        self.command_len = len as u8;
+    }
+
+    fn discovered(&mut self) {
+        match self.discovery.status() {
+            DiscoveryStatus::InProgress => return,
+            DiscoveryStatus::Done => {}
+            _ => {
+                self.step = Step::Unavailable;
+                return;
+            }
+        }
+
+        let discovery = &self.discovery;
+        let chr = |uuid| discovery.characteristic(uuid);
+        match (
+            chr(NOTIFICATION_SOURCE),
+            chr(CONTROL_POINT),
+            chr(DATA_SOURCE),
+        ) {
+            (Some(ns), Some(cp), Some(ds)) if ns.cccd().is_some() && ds.cccd().is_some() => {
+                self.notification_source = ns.value_handle();
+                self.notification_cccd = ns.cccd().unwrap();
+                self.control_point = cp.value_handle();
+                self.data_source = ds.value_handle();
+                self.data_cccd = ds.cccd().unwrap();
+                self.step = Step::SubscribingData;
+            }
+            _ => self.step = Step::Unavailable,
+        }
+    }
+}
+
+impl Default for AncsClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Collect {
+        title: Option<(u32, [u8; 8], usize)>,
+        count: usize,
+    }
+
+    impl AncsHandler for Collect {
+        fn notification(&mut self, _: &Notification) {}
+
+        fn attribute(&mut self, uid: u32, id: NotificationAttributeId, value: &[u8]) {
+            self.count += 1;
+            if id == NotificationAttributeId::Title {
+                let mut buf = [0; 8];
+                buf[..value.len()].copy_from_slice(value);
+                self.title = Some((uid, buf, value.len()));
+            }
+        }
+    }
+
+    #[test]
+    fn data_source_reassembly() {
+        let mut data = DataSource::new();
+        let mut collect = Collect::default();
+        data.expect(2);
+
+        // Response for UID 7: App Identifier "a", Title "Hi!", split across 2 notifications
+        data.feed(&[0, 7, 0, 0, 0, 0, 1, 0, b'a', 1, 3], &mut collect);
+        assert_eq!(collect.count, 1);
+        data.feed(&[0, b'H', b'i', b'!'], &mut collect);
+        assert_eq!(collect.count, 2);
+        let (uid, title, len) = collect.title.unwrap();
+        assert_eq!(uid, 7);
+        assert_eq!(&title[..len], b"Hi!");
+
+        let n = Notification::parse(&[0, 0x02, 1, 3, 0x2A, 0, 0, 0]).unwrap();
+        assert_eq!(n.event, EventId::Added);
+        assert_eq!(n.category, CategoryId::IncomingCall);
+        assert_eq!(n.flags, EventFlags::IMPORTANT);
+        assert_eq!(n.uid, 42);
+    }
+}
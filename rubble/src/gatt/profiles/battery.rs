@@ -0,0 +1,187 @@
+//! *Battery Service* client.
+//!
+//! ```
+//! use rubble::att::{ClientEvent, ClientRequest};
+//! use rubble::gatt::profiles::battery::BatteryClient;
+//!
+//! struct Attrs {
+//!     battery: BatteryClient,
+//! }
+//!
+//! impl Attrs {
+//!     // Called from the `AttributeProvider` methods of the same name
+//!     fn next_request(&self) -> Option<ClientRequest<'_>> {
+//!         self.battery.next_request()
+//!     }
+//!
+//!     fn client_event(&mut self, event: ClientEvent<'_>) {
+//!         if self.battery.process(&event) {
+//!             if let Some(level) = self.battery.level() {
+//!                 // The peer's battery is at `level` percent
+//!             }
+//!         }
+//!     }
+//! }
+//! ```
+
+use crate::{
+    att::{AttUuid, ClientEvent, ClientRequest},
+    gatt::{
+        characteristic::Properties,
+        client::Characteristic,
+        discovery::{DiscoveryStatus, ServiceDiscovery},
+    },
+    uuid::Uuid16,
+};
+
+const BATTERY_SERVICE: Uuid16 = Uuid16(0x180F);
+const BATTERY_LEVEL: AttUuid = AttUuid::Uuid16(Uuid16(0x2A19));
+
+#[derive(Debug, Copy, Clone)]
+enum Step {
+    Discovering,
+    Reading,
+    Subscribing,
+    Ready,
+    Unavailable,
+}
+
+/// Reads and monitors the battery level of the peer.
+pub struct BatteryClient {
+    discovery: ServiceDiscovery,
+    step: Step,
+    characteristic: Option<Characteristic<u8>>,
+    level: Option<u8>,
+}
+
+impl BatteryClient {
+    /// Creates a client that is idle until `start` is called.
+    pub fn new() -> Self {
+        Self {
+            discovery: ServiceDiscovery::new(BATTERY_SERVICE.into(), &[BATTERY_LEVEL]),
+            step: Step::Discovering,
+            characteristic: None,
+            level: None,
+        }
+    }
+
+    /// Starts discovering the peer's *Battery Service*.
+    ///
+    /// Afterwards, the battery level is read and notifications are enabled (if the peer supports
+    /// them).
+    pub fn start(&mut self) {
+        self.discovery.start();
+        self.step = Step::Discovering;
+        self.characteristic = None;
+        self.level = None;
+    }
+
+    /// Returns whether the battery level has been read and updates are being received (or won't
+    /// be, if the peer doesn't notify them).
+    pub fn is_ready(&self) -> bool {
+        matches!(self.step, Step::Ready)
+    }
+
+    /// Returns whether the peer turned out not to have a usable *Battery Service*.
+    pub fn is_unavailable(&self) -> bool {
+        matches!(self.step, Step::Unavailable)
+    }
+
+    /// Returns the last known battery level of the peer, in percent.
+    pub fn level(&self) -> Option<u8> {
+        self.level
+    }
+
+    /// Returns the next request to send.
+    pub fn next_request(&self) -> Option<ClientRequest<'_>> {
+        match (self.step, self.characteristic) {
+            (Step::Discovering, _) => self.discovery.next_request(),
+            (Step::Reading, Some(chr)) => Some(ClientRequest::Read {
+                handle: chr.value_handle(),
+            }),
+            (Step::Subscribing, Some(chr)) => Some(ClientRequest::Write {
+                handle: chr.cccd()?,
+                value: &super::ENABLE_NOTIFICATIONS,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Processes an event from the ATT client.
+    ///
+    /// Returns whether the event was meant for this client. If it carried a new battery level,
+    /// `level` returns it afterwards.
+    pub fn process(&mut self, event: &ClientEvent<'_>) -> bool {
+        if let Step::Discovering = self.step {
+            if !self.discovery.process(event) {
+                return false;
+            }
+            self.discovered();
+            return true;
+        }
+
+        let chr = match self.characteristic {
+            Some(chr) => chr,
+            None => return false,
+        };
+        if let Some(value) = chr.value(event) {
+            match value {
+                Ok(level) => self.level = Some(level),
+                Err(e) => debug!("invalid battery level: {:?}", e),
+            }
+            if let Step::Reading = self.step {
+                self.read_done();
+            }
+            return true;
+        }
+
+        match (self.step, *event) {
+            (Step::Reading, ClientEvent::Error { handle, .. }) if handle == chr.value_handle() => {
+                self.read_done();
+                true
+            }
+            (Step::Subscribing, ClientEvent::Written { handle })
+            | (Step::Subscribing, ClientEvent::Error { handle, .. })
+                if Some(handle) == chr.cccd() =>
+            {
+                self.step = Step::Ready;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Enables notifications once the initial value has been read, if possible.
+    fn read_done(&mut self) {
+        let cccd = self.characteristic.and_then(|chr| chr.cccd());
+        self.step = if cccd.is_some() {
+            Step::Subscribing
+        } else {
+            Step::Ready
+        };
+    }
+
+    fn discovered(&mut self) {
+        match self.discovery.status() {
+            DiscoveryStatus::InProgress => {}
+            DiscoveryStatus::Done => match self.discovery.characteristic(BATTERY_LEVEL) {
+                Some(chr) if chr.properties().contains(Properties::READ) => {
+                    let mut typed = chr.typed();
+                    if !chr.properties().contains(Properties::NOTIFY) {
+                        typed = Characteristic::new(chr.value_handle());
+                    }
+                    self.characteristic = Some(typed);
+                    self.step = Step::Reading;
+                }
+                _ => self.step = Step::Unavailable,
+            },
+            _ => self.step = Step::Unavailable,
+        }
+    }
+}
+
+impl Default for BatteryClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,210 @@
+//! *Current Time Service* client, for syncing the local clock from the peer.
+//!
+//! The client reads the peer's *Current Time* and *Local Time Information* (if present), and then
+//! subscribes to time changes. Every received time can be fetched once with `take_time`.
+
+use crate::{
+    att::{AttUuid, ClientEvent, ClientRequest},
+    gatt::{
+        characteristic::Properties,
+        client::Characteristic,
+        cts::{
+            CurrentTime, LocalTimeInformation, CURRENT_TIME, CURRENT_TIME_SERVICE,
+            LOCAL_TIME_INFORMATION,
+        },
+        discovery::{DiscoveryStatus, ServiceDiscovery},
+    },
+};
+
+const CHARACTERISTICS: &[AttUuid] = &[
+    AttUuid::Uuid16(CURRENT_TIME),
+    AttUuid::Uuid16(LOCAL_TIME_INFORMATION),
+];
+
+#[derive(Debug, Copy, Clone)]
+enum Step {
+    Discovering,
+    ReadingTime,
+    ReadingLocalTime,
+    Subscribing,
+    Ready,
+    Unavailable,
+}
+
+/// Receives the current time from the peer.
+pub struct CurrentTimeClient {
+    discovery: ServiceDiscovery,
+    step: Step,
+    current_time: Option<Characteristic<CurrentTime>>,
+    local_time: Option<Characteristic<LocalTimeInformation>>,
+    time: Option<CurrentTime>,
+    local: Option<LocalTimeInformation>,
+}
+
+impl CurrentTimeClient {
+    /// Creates a client that is idle until `start` is called.
+    pub fn new() -> Self {
+        Self {
+            discovery: ServiceDiscovery::new(CURRENT_TIME_SERVICE.into(), CHARACTERISTICS),
+            step: Step::Discovering,
+            current_time: None,
+            local_time: None,
+            time: None,
+            local: None,
+        }
+    }
+
+    /// Starts discovering the peer's *Current Time Service*.
+    pub fn start(&mut self) {
+        *self = Self::new();
+        self.discovery.start();
+    }
+
+    /// Returns whether the time has been read and changes are being received.
+    pub fn is_ready(&self) -> bool {
+        matches!(self.step, Step::Ready)
+    }
+
+    /// Returns whether the peer turned out not to have a usable *Current Time Service*.
+    pub fn is_unavailable(&self) -> bool {
+        matches!(self.step, Step::Unavailable)
+    }
+
+    /// Returns the time received since the last call, if any.
+    ///
+    /// The time is only accurate at the moment it is received, so the application should set its
+    /// clock right away.
+    pub fn take_time(&mut self) -> Option<CurrentTime> {
+        self.time.take()
+    }
+
+    /// Returns the peer's time zone and DST offset, if known.
+    pub fn local_time_information(&self) -> Option<LocalTimeInformation> {
+        self.local
+    }
+
+    /// Returns the next request to send.
+    pub fn next_request(&self) -> Option<ClientRequest<'_>> {
+        match self.step {
+            Step::Discovering => self.discovery.next_request(),
+            Step::ReadingTime => Some(ClientRequest::Read {
+                handle: self.current_time?.value_handle(),
+            }),
+            Step::ReadingLocalTime => Some(ClientRequest::Read {
+                handle: self.local_time?.value_handle(),
+            }),
+            Step::Subscribing => Some(ClientRequest::Write {
+                handle: self.current_time?.cccd()?,
+                value: &super::ENABLE_NOTIFICATIONS,
+            }),
+            Step::Ready | Step::Unavailable => None,
+        }
+    }
+
+    /// Processes an event from the ATT client.
+    ///
+    /// Returns whether the event was meant for this client.
+    pub fn process(&mut self, event: &ClientEvent<'_>) -> bool {
+        if let Step::Discovering = self.step {
+            if !self.discovery.process(event) {
+                return false;
+            }
+            self.discovered();
+            return true;
+        }
+
+        if let Some(value) = self.current_time.and_then(|chr| chr.value(event)) {
+            match value {
+                Ok(time) => self.time = Some(time),
+                Err(e) => debug!("invalid current time: {:?}", e),
+            }
+            if let Step::ReadingTime = self.step {
+                self.time_read();
+            }
+            return true;
+        }
+
+        if let Some(value) = self.local_time.and_then(|chr| chr.value(event)) {
+            self.local = value.ok();
+            if let Step::ReadingLocalTime = self.step {
+                self.local_time_read();
+            }
+            return true;
+        }
+
+        let handle = event.handle();
+        let ct = match self.current_time {
+            Some(ct) => ct,
+            None => return false,
+        };
+        match (self.step, *event) {
+            (Step::ReadingTime, ClientEvent::Error { .. }) if handle == ct.value_handle() => {
+                self.step = Step::Unavailable;
+                true
+            }
+            (Step::ReadingLocalTime, ClientEvent::Error { .. })
+                if Some(handle) == self.local_time.map(|lt| lt.value_handle()) =>
+            {
+                self.local_time_read();
+                true
+            }
+            // If notifications can't be enabled, the time is still synced once
+            (Step::Subscribing, ClientEvent::Written { .. })
+            | (Step::Subscribing, ClientEvent::Error { .. })
+                if Some(handle) == ct.cccd() =>
+            {
+                self.step = Step::Ready;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn time_read(&mut self) {
+        if self.local_time.is_some() {
+            self.step = Step::ReadingLocalTime;
+        } else {
+            self.local_time_read();
+        }
+    }
+
+    /// Subscribes to time changes once all values have been read, if possible.
+    fn local_time_read(&mut self) {
+        self.step = if self.current_time.and_then(|ct| ct.cccd()).is_some() {
+            Step::Subscribing
+        } else {
+            Step::Ready
+        };
+    }
+
+    fn discovered(&mut self) {
+        match self.discovery.status() {
+            DiscoveryStatus::InProgress => return,
+            DiscoveryStatus::Done => {}
+            _ => {
+                self.step = Step::Unavailable;
+                return;
+            }
+        }
+
+        let discovery = &self.discovery;
+        let readable = |uuid: AttUuid| {
+            discovery
+                .characteristic(uuid)
+                .filter(|chr| chr.properties().contains(Properties::READ))
+        };
+        self.current_time = readable(CURRENT_TIME.into()).map(|chr| chr.typed());
+        self.local_time = readable(LOCAL_TIME_INFORMATION.into()).map(|chr| chr.typed());
+        self.step = if self.current_time.is_some() {
+            Step::ReadingTime
+        } else {
+            Step::Unavailable
+        };
+    }
+}
+
+impl Default for CurrentTimeClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
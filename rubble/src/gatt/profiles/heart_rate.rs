@@ -0,0 +1,358 @@
+//! *Heart Rate Service* client.
+//!
+//! The `HeartRateClient` subscribes to the *Heart Rate Measurement* characteristic and reads the
+//! *Body Sensor Location*, if the sensor provides it. It is hooked up to the `AttributeProvider`
+//! like the [`BatteryClient`].
+//!
+//! [`BatteryClient`]: ../battery/struct.BatteryClient.html
+
+use crate::{
+    att::{AttUuid, AttributeProvider, AttributeServerTx, ClientEvent, ClientRequest},
+    bytes::{ByteReader, ByteWriter},
+    gatt::{
+        characteristic::Properties,
+        client::{Characteristic, CharacteristicValue},
+        discovery::{DiscoveryStatus, ServiceDiscovery},
+    },
+    uuid::Uuid16,
+    Error,
+};
+
+const HEART_RATE_SERVICE: Uuid16 = Uuid16(0x180D);
+const MEASUREMENT: AttUuid = AttUuid::Uuid16(Uuid16(0x2A37));
+const BODY_SENSOR_LOCATION: AttUuid = AttUuid::Uuid16(Uuid16(0x2A38));
+const CONTROL_POINT: AttUuid = AttUuid::Uuid16(Uuid16(0x2A39));
+const CHARACTERISTICS: &[AttUuid] = &[MEASUREMENT, BODY_SENSOR_LOCATION, CONTROL_POINT];
+
+/// *Reset Energy Expended* command of the *Heart Rate Control Point*.
+const RESET_ENERGY_EXPENDED: u8 = 0x01;
+
+/// Maximum number of RR-intervals that fit into a measurement with the default `ATT_MTU`.
+pub const MAX_RR_INTERVALS: usize = 9;
+
+/// A value of the *Heart Rate Measurement* characteristic.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct HeartRateMeasurement {
+    bpm: u16,
+    contact: Option<bool>,
+    energy_expended: Option<u16>,
+    rr_intervals: [u16; MAX_RR_INTERVALS],
+    rr_count: u8,
+}
+
+impl HeartRateMeasurement {
+    /// Creates a measurement of `bpm` beats per minute without any optional fields.
+    pub fn new(bpm: u16) -> Self {
+        Self {
+            bpm,
+            contact: None,
+            energy_expended: None,
+            rr_intervals: [0; MAX_RR_INTERVALS],
+            rr_count: 0,
+        }
+    }
+
+    /// Returns the heart rate in beats per minute.
+    pub fn bpm(&self) -> u16 {
+        self.bpm
+    }
+
+    /// Returns whether the sensor has skin contact, if it can detect that.
+    pub fn sensor_contact(&self) -> Option<bool> {
+        self.contact
+    }
+
+    /// Returns the accumulated energy expended in kilojoules, if included.
+    pub fn energy_expended(&self) -> Option<u16> {
+        self.energy_expended
+    }
+
+    /// Returns the RR-intervals (times between beats) included in the measurement, in units of
+    /// 1/1024 seconds.
+    pub fn rr_intervals(&self) -> &[u16] {
+        &self.rr_intervals[..usize::from(self.rr_count)]
+    }
+}
+
+impl CharacteristicValue for HeartRateMeasurement {
+    fn decode(bytes: &mut ByteReader<'_>) -> Result<Self, Error> {
+        let flags = bytes.read_u8()?;
+        let bpm = if flags & 0x01 != 0 {
+            bytes.read_u16_le()?
+        } else {
+            u16::from(bytes.read_u8()?)
+        };
+        let mut measurement = Self::new(bpm);
+        // Bit 2 indicates support for contact detection, bit 1 the detected contact
+        if flags & 0x04 != 0 {
+            measurement.contact = Some(flags & 0x02 != 0);
+        }
+        if flags & 0x08 != 0 {
+            measurement.energy_expended = Some(bytes.read_u16_le()?);
+        }
+        if flags & 0x10 != 0 {
+            while bytes.bytes_left() >= 2 && usize::from(measurement.rr_count) < MAX_RR_INTERVALS {
+                measurement.rr_intervals[usize::from(measurement.rr_count)] =
+                    bytes.read_u16_le()?;
+                measurement.rr_count += 1;
+            }
+        }
+        Ok(measurement)
+    }
+
+    fn encode(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        let mut flags = 0;
+        if self.bpm > 0xFF {
+            flags |= 0x01;
+        }
+        match self.contact {
+            Some(true) => flags |= 0x06,
+            Some(false) => flags |= 0x04,
+            None => {}
+        }
+        if self.energy_expended.is_some() {
+            flags |= 0x08;
+        }
+        if self.rr_count != 0 {
+            flags |= 0x10;
+        }
+
+        writer.write_u8(flags)?;
+        if self.bpm > 0xFF {
+            writer.write_u16_le(self.bpm)?;
+        } else {
+            writer.write_u8(self.bpm as u8)?;
+        }
+        if let Some(energy) = self.energy_expended {
+            writer.write_u16_le(energy)?;
+        }
+        for rr in self.rr_intervals() {
+            writer.write_u16_le(*rr)?;
+        }
+        Ok(())
+    }
+}
+
+enum_with_unknown! {
+    /// Where on the body a heart rate sensor is worn.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum BodySensorLocation(u8) {
+        Other = 0,
+        Chest = 1,
+        Wrist = 2,
+        Finger = 3,
+        Hand = 4,
+        EarLobe = 5,
+        Foot = 6,
+    }
+}
+
+impl CharacteristicValue for BodySensorLocation {
+    fn decode(bytes: &mut ByteReader<'_>) -> Result<Self, Error> {
+        Ok(bytes.read_u8()?.into())
+    }
+
+    fn encode(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u8((*self).into())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Step {
+    Discovering,
+    ReadingLocation,
+    Subscribing,
+    Ready,
+    Unavailable,
+}
+
+/// Receives heart rate measurements from the peer.
+pub struct HeartRateClient {
+    discovery: ServiceDiscovery,
+    step: Step,
+    measurement: Option<Characteristic<HeartRateMeasurement>>,
+    location: Option<Characteristic<BodySensorLocation>>,
+    control_point: Option<Characteristic<u8>>,
+    latest: Option<HeartRateMeasurement>,
+    body_location: Option<BodySensorLocation>,
+}
+
+impl HeartRateClient {
+    /// Creates a client that is idle until `start` is called.
+    pub fn new() -> Self {
+        Self {
+            discovery: ServiceDiscovery::new(HEART_RATE_SERVICE.into(), CHARACTERISTICS),
+            step: Step::Discovering,
+            measurement: None,
+            location: None,
+            control_point: None,
+            latest: None,
+            body_location: None,
+        }
+    }
+
+    /// Starts discovering the peer's *Heart Rate Service*.
+    pub fn start(&mut self) {
+        self.discovery.start();
+        self.step = Step::Discovering;
+        self.measurement = None;
+        self.location = None;
+        self.control_point = None;
+        self.latest = None;
+        self.body_location = None;
+    }
+
+    /// Returns whether measurements are being received.
+    pub fn is_ready(&self) -> bool {
+        matches!(self.step, Step::Ready)
+    }
+
+    /// Returns whether the peer turned out not to have a usable *Heart Rate Service*.
+    pub fn is_unavailable(&self) -> bool {
+        matches!(self.step, Step::Unavailable)
+    }
+
+    /// Returns the last received measurement.
+    pub fn measurement(&self) -> Option<&HeartRateMeasurement> {
+        self.latest.as_ref()
+    }
+
+    /// Returns the location of the sensor, if the peer provides it.
+    pub fn body_sensor_location(&self) -> Option<BodySensorLocation> {
+        self.body_location
+    }
+
+    /// Asks the sensor to reset the accumulated energy expended.
+    ///
+    /// Returns `Error::InvalidValue` if the sensor doesn't support this (or isn't ready yet), or
+    /// if another request is outstanding.
+    pub fn reset_energy_expended<A: AttributeProvider>(
+        &self,
+        att: AttributeServerTx<'_, A>,
+    ) -> Result<(), Error> {
+        match (self.step, self.control_point) {
+            (Step::Ready, Some(cp)) => cp.write(att, &RESET_ENERGY_EXPENDED),
+            _ => Err(Error::InvalidValue),
+        }
+    }
+
+    /// Returns the next request to send.
+    pub fn next_request(&self) -> Option<ClientRequest<'_>> {
+        match self.step {
+            Step::Discovering => self.discovery.next_request(),
+            Step::ReadingLocation => Some(ClientRequest::Read {
+                handle: self.location?.value_handle(),
+            }),
+            Step::Subscribing => Some(ClientRequest::Write {
+                handle: self.measurement?.cccd()?,
+                value: &super::ENABLE_NOTIFICATIONS,
+            }),
+            Step::Ready | Step::Unavailable => None,
+        }
+    }
+
+    /// Processes an event from the ATT client.
+    ///
+    /// Returns whether the event was meant for this client. New measurements are available from
+    /// `measurement` afterwards.
+    pub fn process(&mut self, event: &ClientEvent<'_>) -> bool {
+        if let Step::Discovering = self.step {
+            if !self.discovery.process(event) {
+                return false;
+            }
+            self.discovered();
+            return true;
+        }
+
+        if let Some(value) = self.measurement.and_then(|chr| chr.value(event)) {
+            match value {
+                Ok(measurement) => self.latest = Some(measurement),
+                Err(e) => debug!("invalid heart rate measurement: {:?}", e),
+            }
+            return true;
+        }
+
+        let handle = event.handle();
+        match self.step {
+            Step::ReadingLocation if Some(handle) == self.location.map(|l| l.value_handle()) => {
+                if let Some(Ok(location)) = self.location.and_then(|l| l.value(event)) {
+                    self.body_location = Some(location);
+                }
+                self.step = Step::Subscribing;
+                true
+            }
+            Step::Subscribing if Some(handle) == self.measurement.and_then(|m| m.cccd()) => {
+                self.step = match event {
+                    ClientEvent::Written { .. } => Step::Ready,
+                    _ => Step::Unavailable,
+                };
+                true
+            }
+            Step::Ready => Some(handle) == self.control_point.map(|cp| cp.value_handle()),
+            _ => false,
+        }
+    }
+
+    fn discovered(&mut self) {
+        match self.discovery.status() {
+            DiscoveryStatus::InProgress => return,
+            DiscoveryStatus::Done => {}
+            _ => {
+                self.step = Step::Unavailable;
+                return;
+            }
+        }
+
+        let measurement = self.discovery.characteristic(MEASUREMENT);
+        match measurement {
+            Some(chr) if chr.cccd().is_some() => self.measurement = Some(chr.typed()),
+            _ => {
+                self.step = Step::Unavailable;
+                return;
+            }
+        }
+        self.location = self
+            .discovery
+            .characteristic(BODY_SENSOR_LOCATION)
+            .filter(|chr| chr.properties().contains(Properties::READ))
+            .map(|chr| chr.typed());
+        self.control_point = self
+            .discovery
+            .characteristic(CONTROL_POINT)
+            .map(|chr| chr.typed());
+
+        self.step = if self.location.is_some() {
+            Step::ReadingLocation
+        } else {
+            Step::Subscribing
+        };
+    }
+}
+
+impl Default for HeartRateClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measurement() {
+        // 16-bit value, contact detected, 2 RR-intervals
+        let raw = [0x17, 0x2C, 0x01, 0x00, 0x04, 0x10, 0x04];
+        let m = HeartRateMeasurement::decode(&mut ByteReader::new(&raw)).unwrap();
+        assert_eq!(m.bpm(), 300);
+        assert_eq!(m.sensor_contact(), Some(true));
+        assert_eq!(m.energy_expended(), None);
+        assert_eq!(m.rr_intervals(), [0x0400, 0x0410]);
+
+        let mut buf = [0; 20];
+        let mut writer = ByteWriter::new(&mut buf);
+        m.encode(&mut writer).unwrap();
+        let left = writer.space_left();
+        assert_eq!(buf[..20 - left], raw);
+    }
+}
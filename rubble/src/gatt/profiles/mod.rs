@@ -0,0 +1,16 @@
+//! Client-side implementations of common GATT profiles.
+//!
+//! Every profile client discovers its service on the peer, subscribes to the characteristics it
+//! cares about, and decodes their values. They are driven like a [`ServiceDiscovery`]: The
+//! `AttributeProvider` forwards `next_request` and `client_event` to them, and the application
+//! calls `start` and `AttributeServerTx::send_next_request` once the connection is established.
+//!
+//! [`ServiceDiscovery`]: ../discovery/struct.ServiceDiscovery.html
+
+pub mod ancs;
+pub mod battery;
+pub mod cts;
+pub mod heart_rate;
+
+/// Value written to a CCCD to enable notifications.
+const ENABLE_NOTIFICATIONS: [u8; 2] = [0x01, 0x00];
@@ -0,0 +1,303 @@
+//! Services of the *Proximity Profile* (PXP): *Link Loss*, *Immediate Alert* and *Tx Power*.
+//!
+//! The Proximity Profile is what "find my keys" tags implement. A phone connects to the tag and
+//! configures how loudly it should alert when the connection is lost unexpectedly (*Link Loss*),
+//! can make it alert right away (*Immediate Alert*), and estimates its distance to the tag by
+//! comparing the RSSI with the advertised transmission power (*Tx Power*).
+//!
+//! [`ProximityServices`] adds all three services to an `AttributeTable`. The application's
+//! `AttributeProvider` passes writes to `ProximityServices::write_attr`, and the application
+//! reports connection state changes:
+//!
+//! * `connected` when a connection is established, which stops a running link loss alert.
+//! * `connection_lost` with `LinkLayer::disconnect_reason` when a connection ends. Unless the
+//!   connection was closed deliberately by either side, this starts an alert at the configured
+//!   link loss level.
+//!
+//! The application polls `ProximityServices::alert` and drives its buzzer or LED accordingly.
+//!
+//! [`ProximityServices`]: struct.ProximityServices.html
+
+use {
+    crate::{
+        att::{AttError, ErrorCode, Handle},
+        gatt::{
+            characteristic::Properties,
+            table::{AttributeTable, TableEntry},
+        },
+        link::llcp,
+        phy::TxPower,
+        uuid::Uuid16,
+        Error,
+    },
+    heapless::ArrayLength,
+};
+
+/// UUID of the *Immediate Alert* service.
+pub const IMMEDIATE_ALERT_SERVICE: Uuid16 = Uuid16(0x1802);
+
+/// UUID of the *Link Loss* service.
+pub const LINK_LOSS_SERVICE: Uuid16 = Uuid16(0x1803);
+
+/// UUID of the *Tx Power* service.
+pub const TX_POWER_SERVICE: Uuid16 = Uuid16(0x1804);
+
+/// UUID of the *Alert Level* characteristic, used by both alert services.
+pub const ALERT_LEVEL: Uuid16 = Uuid16(0x2A06);
+
+/// UUID of the *Tx Power Level* characteristic.
+pub const TX_POWER_LEVEL: Uuid16 = Uuid16(0x2A07);
+
+/// How strongly the device should alert.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AlertLevel {
+    NoAlert = 0,
+    MildAlert = 1,
+    HighAlert = 2,
+}
+
+impl AlertLevel {
+    /// Decodes an *Alert Level* value, returning `None` for reserved values.
+    pub fn from_u8(raw: u8) -> Option<Self> {
+        match raw {
+            0 => Some(AlertLevel::NoAlert),
+            1 => Some(AlertLevel::MildAlert),
+            2 => Some(AlertLevel::HighAlert),
+            _ => None,
+        }
+    }
+}
+
+/// What caused an alert.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AlertSource {
+    /// The client wrote to the *Immediate Alert* service.
+    Immediate,
+    /// The connection was lost.
+    LinkLoss,
+}
+
+/// An alert the device should be signalling to the user.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Alert {
+    pub source: AlertSource,
+    pub level: AlertLevel,
+}
+
+/// The server side of the Proximity Profile, hosted in an `AttributeTable`.
+#[derive(Debug)]
+pub struct ProximityServices {
+    link_loss: Handle,
+    immediate_alert: Handle,
+    tx_power_level: Handle,
+    link_loss_level: AlertLevel,
+    alert: Option<Alert>,
+}
+
+impl ProximityServices {
+    /// Adds the *Link Loss*, *Immediate Alert* and *Tx Power* services to `table`.
+    ///
+    /// `tx_power` is the transmission power used for connections.
+    pub fn add_to<N, B>(table: &mut AttributeTable<N, B>, tx_power: TxPower) -> Result<Self, Error>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        table.add_service(LINK_LOSS_SERVICE)?;
+        let link_loss = table.add_characteristic(
+            ALERT_LEVEL,
+            Properties::READ | Properties::WRITE,
+            &[AlertLevel::NoAlert as u8],
+        )?;
+        table.add_service(IMMEDIATE_ALERT_SERVICE)?;
+        // Immediate alerts aren't readable, so the value is never stored
+        let immediate_alert =
+            table.add_characteristic(ALERT_LEVEL, Properties::WRITE_NO_RSP, &[])?;
+        table.add_service(TX_POWER_SERVICE)?;
+        let tx_power_level = table.add_characteristic(
+            TX_POWER_LEVEL,
+            Properties::READ,
+            &[tx_power.as_dbm() as u8],
+        )?;
+        Ok(Self {
+            link_loss,
+            immediate_alert,
+            tx_power_level,
+            link_loss_level: AlertLevel::NoAlert,
+            alert: None,
+        })
+    }
+
+    /// Returns the handle of the *Link Loss* alert level value.
+    pub fn link_loss(&self) -> Handle {
+        self.link_loss
+    }
+
+    /// Returns the handle of the *Immediate Alert* alert level value.
+    pub fn immediate_alert(&self) -> Handle {
+        self.immediate_alert
+    }
+
+    /// Returns the handle of the *Tx Power Level* value.
+    pub fn tx_power_level(&self) -> Handle {
+        self.tx_power_level
+    }
+
+    /// Returns the alert level configured for link loss.
+    pub fn link_loss_level(&self) -> AlertLevel {
+        self.link_loss_level
+    }
+
+    /// Returns the alert that should currently be signalled, if any.
+    pub fn alert(&self) -> Option<Alert> {
+        self.alert
+    }
+
+    /// Stops the current alert, eg. because the user pressed a button on the device.
+    pub fn stop_alert(&mut self) {
+        self.alert = None;
+    }
+
+    /// Updates the *Tx Power Level* after the connection's transmission power was changed.
+    pub fn set_tx_power<N, B>(
+        &self,
+        table: &mut AttributeTable<N, B>,
+        tx_power: TxPower,
+    ) -> Result<(), Error>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        table.set_value(self.tx_power_level, &[tx_power.as_dbm() as u8])
+    }
+
+    /// Handles a write to the attribute at `handle`.
+    ///
+    /// Returns `None` if `handle` doesn't belong to the services, in which case the write should
+    /// be processed as usual. Reserved alert levels are rejected for *Link Loss* and ignored for
+    /// *Immediate Alert*, whose writes can't be rejected.
+    pub fn write_attr<N, B>(
+        &mut self,
+        table: &mut AttributeTable<N, B>,
+        handle: Handle,
+        value: &[u8],
+    ) -> Option<Result<(), AttError>>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        let level = match value {
+            [raw] => AlertLevel::from_u8(*raw),
+            _ => None,
+        };
+
+        if handle == self.link_loss {
+            Some(match level {
+                Some(level) => {
+                    self.link_loss_level = level;
+                    table.set_value(handle, &[level as u8]).ok();
+                    Ok(())
+                }
+                None if value.len() != 1 => Err(AttError::new(
+                    ErrorCode::InvalidAttributeValueLength,
+                    handle,
+                )),
+                None => Err(AttError::new(ErrorCode::ValueNotAllowed, handle)),
+            })
+        } else if handle == self.immediate_alert {
+            match level {
+                Some(AlertLevel::NoAlert) => {
+                    if self.alert.map(|alert| alert.source) == Some(AlertSource::Immediate) {
+                        self.alert = None;
+                    }
+                }
+                Some(level) => {
+                    self.alert = Some(Alert {
+                        source: AlertSource::Immediate,
+                        level,
+                    })
+                }
+                None => debug!("invalid immediate alert level {:?}", value),
+            }
+            Some(Ok(()))
+        } else {
+            None
+        }
+    }
+
+    /// Notifies the services of a newly established connection.
+    ///
+    /// A link loss alert ends once the client has reconnected.
+    pub fn connected(&mut self) {
+        if self.alert.map(|alert| alert.source) == Some(AlertSource::LinkLoss) {
+            self.alert = None;
+        }
+    }
+
+    /// Notifies the services that the connection was ended for `reason`.
+    ///
+    /// Returns the link loss alert that was started, if any. Immediate alerts end with the
+    /// connection.
+    pub fn connection_lost(&mut self, reason: llcp::ErrorCode) -> Option<Alert> {
+        self.alert = None;
+
+        let deliberate = matches!(
+            reason,
+            llcp::ErrorCode::RemoteUserTerminatedConnection
+                | llcp::ErrorCode::RemoteDeviceTerminatedLowResources
+                | llcp::ErrorCode::RemoteDeviceTerminatedPowerOff
+                | llcp::ErrorCode::ConnectionTerminatedByLocalHost
+        );
+        if !deliberate && self.link_loss_level != AlertLevel::NoAlert {
+            self.alert = Some(Alert {
+                source: AlertSource::LinkLoss,
+                level: self.link_loss_level,
+            });
+        }
+        self.alert
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, heapless::consts::*};
+
+    #[test]
+    fn alerts() {
+        let mut table = AttributeTable::<U16, U32>::new();
+        let mut pxp = ProximityServices::add_to(&mut table, TxPower::from_dbm(-4)).unwrap();
+        let (link_loss, immediate) = (pxp.link_loss(), pxp.immediate_alert());
+        assert_eq!(table.value(pxp.tx_power_level()), Some(&[0xFC][..]));
+
+        assert!(pxp
+            .write_attr(&mut table, link_loss, &[3])
+            .unwrap()
+            .is_err());
+        pxp.write_attr(&mut table, link_loss, &[2])
+            .unwrap()
+            .unwrap();
+        assert_eq!(table.value(link_loss), Some(&[2][..]));
+
+        pxp.write_attr(&mut table, immediate, &[1])
+            .unwrap()
+            .unwrap();
+        assert_eq!(pxp.alert().unwrap().level, AlertLevel::MildAlert);
+        pxp.write_attr(&mut table, immediate, &[0])
+            .unwrap()
+            .unwrap();
+        assert_eq!(pxp.alert(), None);
+
+        // Closing the connection on purpose doesn't alert, losing it does
+        assert_eq!(
+            pxp.connection_lost(llcp::ErrorCode::RemoteUserTerminatedConnection),
+            None
+        );
+        let alert = pxp
+            .connection_lost(llcp::ErrorCode::ConnectionTimeout)
+            .unwrap();
+        assert_eq!(alert.source, AlertSource::LinkLoss);
+        assert_eq!(alert.level, AlertLevel::HighAlert);
+        pxp.connected();
+        assert_eq!(pxp.alert(), None);
+    }
+}
@@ -0,0 +1,475 @@
+//! The *Record Access Control Point* (RACP).
+//!
+//! Profiles of devices that collect measurements while no client is connected (eg. *Glucose* or
+//! *Continuous Glucose Monitoring*) let the client fetch the stored records through the RACP. The
+//! client writes a request that selects records by sequence number, and the server reports the
+//! selected records as notifications of a profile-specific measurement characteristic, deletes
+//! them, or counts them. Every request is concluded by an indication of the RACP.
+//!
+//! [`RecordAccessControlPoint`] implements the request handling and procedure state machine once
+//! for all such profiles. The profile provides the stored records via the [`RecordStore`] trait:
+//!
+//! * The `AttributeProvider` passes writes to `RecordAccessControlPoint::write_attr`.
+//! * From the idle loop, the application calls `RecordAccessControlPoint::process`. It returns
+//!   the sequence numbers of the records to report, one at a time. The profile notifies each of
+//!   them before calling `process` again.
+//! * Once `process` returns `None`, the response returned by `pending_response` is indicated with
+//!   `AttributeServerTx::indicate_raw`, followed by a call to `response_sent`.
+//!
+//! Only filtering by sequence number is supported.
+//!
+//! [`RecordAccessControlPoint`]: struct.RecordAccessControlPoint.html
+//! [`RecordStore`]: trait.RecordStore.html
+
+use {
+    crate::{
+        att::{AttError, ErrorCode, Handle},
+        gatt::{
+            characteristic::Properties,
+            table::{AttributeTable, TableEntry},
+        },
+        utils,
+        uuid::Uuid16,
+        Error,
+    },
+    heapless::{consts::U4, ArrayLength, Vec},
+};
+
+/// UUID of the *Record Access Control Point* characteristic.
+pub const RECORD_ACCESS_CONTROL_POINT: Uuid16 = Uuid16(0x2A52);
+
+/// ATT error returned when a request other than *Abort* is written while a procedure is running.
+const PROCEDURE_ALREADY_IN_PROGRESS: u8 = 0x80;
+
+/// ATT error returned when the RACP is written without indications being enabled.
+const CCCD_IMPROPERLY_CONFIGURED: u8 = 0x81;
+
+const OP_REPORT_RECORDS: u8 = 0x01;
+const OP_DELETE_RECORDS: u8 = 0x02;
+const OP_ABORT: u8 = 0x03;
+const OP_REPORT_NUMBER: u8 = 0x04;
+const OP_NUMBER_RESPONSE: u8 = 0x05;
+const OP_RESPONSE: u8 = 0x06;
+
+/// Filter type selecting records by sequence number.
+const FILTER_SEQUENCE_NUMBER: u8 = 0x01;
+
+enum_with_unknown! {
+    /// Result of a request, sent in the response indication.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum ResponseCode(u8) {
+        Success = 0x01,
+        OpCodeNotSupported = 0x02,
+        InvalidOperator = 0x03,
+        OperatorNotSupported = 0x04,
+        InvalidOperand = 0x05,
+        NoRecordsFound = 0x06,
+        AbortUnsuccessful = 0x07,
+        ProcedureNotCompleted = 0x08,
+        OperandNotSupported = 0x09,
+    }
+}
+
+/// The storage of records, provided by the profile.
+///
+/// Records are identified by their 16-bit sequence number.
+pub trait RecordStore {
+    /// Returns the lowest sequence number of a stored record that is at least `from`.
+    fn next_record(&self, from: u16) -> Option<u16>;
+
+    /// Returns the highest sequence number of a stored record.
+    fn last_record(&self) -> Option<u16>;
+
+    /// Deletes the record with sequence number `seq`.
+    ///
+    /// Returns `false` if the record couldn't be deleted.
+    fn delete_record(&mut self, seq: u16) -> bool;
+}
+
+/// The records selected by a request.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Filter {
+    /// All stored records.
+    All,
+    /// Records with a sequence number of at most this value.
+    AtMost(u16),
+    /// Records with a sequence number of at least this value.
+    AtLeast(u16),
+    /// Records with a sequence number in this inclusive range.
+    Within(u16, u16),
+    /// The oldest record.
+    First,
+    /// The most recent record.
+    Last,
+}
+
+impl Filter {
+    /// Decodes the operator and operand of a request.
+    fn decode(operator: u8, operand: &[u8]) -> Result<Self, ResponseCode> {
+        let no_operand = |filter| {
+            if operand.is_empty() {
+                Ok(filter)
+            } else {
+                Err(ResponseCode::InvalidOperand)
+            }
+        };
+        match operator {
+            0x00 => Err(ResponseCode::InvalidOperator),
+            0x01 => no_operand(Filter::All),
+            0x02 | 0x03 => match sequence_numbers(operand)? {
+                [lo, hi] => {
+                    let value = u16::from_le_bytes([*lo, *hi]);
+                    Ok(if operator == 0x02 {
+                        Filter::AtMost(value)
+                    } else {
+                        Filter::AtLeast(value)
+                    })
+                }
+                _ => Err(ResponseCode::InvalidOperand),
+            },
+            0x04 => match sequence_numbers(operand)? {
+                [a, b, c, d] => {
+                    let min = u16::from_le_bytes([*a, *b]);
+                    let max = u16::from_le_bytes([*c, *d]);
+                    if min > max {
+                        return Err(ResponseCode::InvalidOperand);
+                    }
+                    Ok(Filter::Within(min, max))
+                }
+                _ => Err(ResponseCode::InvalidOperand),
+            },
+            0x05 => no_operand(Filter::First),
+            0x06 => no_operand(Filter::Last),
+            _ => Err(ResponseCode::OperatorNotSupported),
+        }
+    }
+
+    /// Returns the inclusive sequence number range selected in `store`, if any.
+    fn range<S: RecordStore>(&self, store: &S) -> Option<(u16, u16)> {
+        match *self {
+            Filter::All => Some((0, 0xFFFF)),
+            Filter::AtMost(max) => Some((0, max)),
+            Filter::AtLeast(min) => Some((min, 0xFFFF)),
+            Filter::Within(min, max) => Some((min, max)),
+            Filter::First => store.next_record(0).map(|seq| (seq, seq)),
+            Filter::Last => store.last_record().map(|seq| (seq, seq)),
+        }
+    }
+}
+
+/// Strips the filter type from `operand`, returning the sequence numbers following it.
+fn sequence_numbers(operand: &[u8]) -> Result<&[u8], ResponseCode> {
+    match operand.split_first() {
+        Some((&FILTER_SEQUENCE_NUMBER, rest)) => Ok(rest),
+        Some(_) => Err(ResponseCode::OperandNotSupported),
+        None => Err(ResponseCode::InvalidOperand),
+    }
+}
+
+/// A request that has been accepted and waits to be processed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Procedure {
+    Report(Filter),
+    /// Records are being reported, starting at sequence number `next` (up to `last`).
+    Reporting {
+        next: u32,
+        last: u16,
+        found: bool,
+    },
+    Delete(Filter),
+    Count(Filter),
+}
+
+/// The RACP of a profile, hosted in an `AttributeTable`.
+#[derive(Debug)]
+pub struct RecordAccessControlPoint {
+    handle: Handle,
+    procedure: Option<Procedure>,
+    /// Encoded response, until it has been indicated.
+    response: Vec<u8, U4>,
+}
+
+impl RecordAccessControlPoint {
+    /// Adds the RACP characteristic to the current service of `table`.
+    pub fn add_to<N, B>(table: &mut AttributeTable<N, B>) -> Result<Self, Error>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        let handle = table.add_characteristic(
+            RECORD_ACCESS_CONTROL_POINT,
+            Properties::WRITE | Properties::INDICATE,
+            &[],
+        )?;
+        Ok(Self {
+            handle,
+            procedure: None,
+            response: Vec::new(),
+        })
+    }
+
+    /// Returns the handle of the RACP value.
+    pub fn handle(&self) -> Handle {
+        self.handle
+    }
+
+    /// Returns whether a procedure is running or its response hasn't been sent yet.
+    pub fn is_busy(&self) -> bool {
+        self.procedure.is_some() || !self.response.is_empty()
+    }
+
+    /// Returns the response to indicate on `handle()`, if the current procedure has finished.
+    pub fn pending_response(&self) -> Option<&[u8]> {
+        if self.response.is_empty() {
+            None
+        } else {
+            Some(&self.response[..])
+        }
+    }
+
+    /// Marks the pending response as sent, allowing the client to write the next request.
+    pub fn response_sent(&mut self) {
+        utils::truncate(&mut self.response, 0);
+    }
+
+    /// Handles a write to the attribute at `handle`.
+    ///
+    /// Returns `None` if `handle` isn't the RACP, in which case the write should be processed as
+    /// usual. Malformed and unsupported requests are answered with a response indication, while
+    /// writes that violate the procedure rules are rejected with an ATT error.
+    pub fn write_attr<N, B>(
+        &mut self,
+        table: &AttributeTable<N, B>,
+        handle: Handle,
+        value: &[u8],
+    ) -> Option<Result<(), AttError>>
+    where
+        N: ArrayLength<TableEntry>,
+        B: ArrayLength<u8>,
+    {
+        if handle != self.handle {
+            return None;
+        }
+        let reject = |code: u8| Some(Err(AttError::new(ErrorCode::from(code), handle)));
+
+        if !table.indications_enabled(handle) {
+            return reject(CCCD_IMPROPERLY_CONFIGURED);
+        }
+
+        let (opcode, operator, operand) = match value {
+            [opcode, operator, ..] => (*opcode, *operator, &value[2..]),
+            _ => {
+                let code = ErrorCode::InvalidAttributeValueLength;
+                return Some(Err(AttError::new(code, handle)));
+            }
+        };
+
+        if opcode == OP_ABORT {
+            if !self.response.is_empty() {
+                return reject(PROCEDURE_ALREADY_IN_PROGRESS);
+            }
+            let code = if operator == 0x00 && operand.is_empty() {
+                self.procedure = None;
+                ResponseCode::Success
+            } else {
+                ResponseCode::InvalidOperator
+            };
+            self.respond(opcode, code);
+            return Some(Ok(()));
+        }
+        if self.is_busy() {
+            return reject(PROCEDURE_ALREADY_IN_PROGRESS);
+        }
+
+        let procedure = match opcode {
+            OP_REPORT_RECORDS => Filter::decode(operator, operand).map(Procedure::Report),
+            OP_DELETE_RECORDS => Filter::decode(operator, operand).map(Procedure::Delete),
+            OP_REPORT_NUMBER => Filter::decode(operator, operand).map(Procedure::Count),
+            _ => Err(ResponseCode::OpCodeNotSupported),
+        };
+        match procedure {
+            Ok(procedure) => self.procedure = Some(procedure),
+            Err(code) => self.respond(opcode, code),
+        }
+        Some(Ok(()))
+    }
+
+    /// Processes the current procedure on `store`.
+    ///
+    /// While records are being reported, this returns the sequence number of the next record,
+    /// which must be notified by the caller before calling `process` again. Returns `None` once
+    /// the procedure has finished (or when there is none), at which point `pending_response` has
+    /// the response to indicate.
+    pub fn process<S: RecordStore>(&mut self, store: &mut S) -> Option<u16> {
+        let procedure = self.procedure.take()?;
+        match procedure {
+            Procedure::Report(filter) => {
+                if let Some((first, last)) = filter.range(store) {
+                    self.procedure = Some(Procedure::Reporting {
+                        next: u32::from(first),
+                        last,
+                        found: false,
+                    });
+                    self.process(store)
+                } else {
+                    self.respond(OP_REPORT_RECORDS, ResponseCode::NoRecordsFound);
+                    None
+                }
+            }
+            Procedure::Reporting { next, last, found } => {
+                let seq = if next > u32::from(last) {
+                    None
+                } else {
+                    store
+                        .next_record(next as u16)
+                        .filter(|seq| *seq >= next as u16 && *seq <= last)
+                };
+                match seq {
+                    Some(seq) => {
+                        self.procedure = Some(Procedure::Reporting {
+                            next: u32::from(seq) + 1,
+                            last,
+                            found: true,
+                        });
+                        Some(seq)
+                    }
+                    None => {
+                        let code = if found {
+                            ResponseCode::Success
+                        } else {
+                            ResponseCode::NoRecordsFound
+                        };
+                        self.respond(OP_REPORT_RECORDS, code);
+                        None
+                    }
+                }
+            }
+            Procedure::Delete(filter) => {
+                let mut found = false;
+                let mut failed = false;
+                for_each_in(store, filter, |store, seq| {
+                    found = true;
+                    failed |= !store.delete_record(seq);
+                });
+                let code = match (found, failed) {
+                    (false, _) => ResponseCode::NoRecordsFound,
+                    (true, false) => ResponseCode::Success,
+                    (true, true) => ResponseCode::ProcedureNotCompleted,
+                };
+                self.respond(OP_DELETE_RECORDS, code);
+                None
+            }
+            Procedure::Count(filter) => {
+                let mut count = 0u16;
+                for_each_in(store, filter, |_, _| count = count.saturating_add(1));
+                let [lo, hi] = count.to_le_bytes();
+                utils::truncate(&mut self.response, 0);
+                self.response
+                    .extend_from_slice(&[OP_NUMBER_RESPONSE, 0x00, lo, hi])
+                    .unwrap();
+                None
+            }
+        }
+    }
+
+    fn respond(&mut self, opcode: u8, code: ResponseCode) {
+        utils::truncate(&mut self.response, 0);
+        self.response
+            .extend_from_slice(&[OP_RESPONSE, 0x00, opcode, code.into()])
+            .unwrap();
+    }
+}
+
+/// Calls `f` with every record in `store` selected by `filter`, in ascending order.
+fn for_each_in<S: RecordStore>(store: &mut S, filter: Filter, mut f: impl FnMut(&mut S, u16)) {
+    let (first, last) = match filter.range(store) {
+        Some(range) => range,
+        None => return,
+    };
+    let mut next = u32::from(first);
+    while next <= u32::from(last) {
+        match store.next_record(next as u16) {
+            Some(seq) if u32::from(seq) >= next && seq <= last => {
+                f(store, seq);
+                next = u32::from(seq) + 1;
+            }
+            _ => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, heapless::consts::*};
+
+    struct Records(Vec<u16, U8>);
+
+    impl RecordStore for Records {
+        fn next_record(&self, from: u16) -> Option<u16> {
+            self.0.iter().copied().filter(|seq| *seq >= from).min()
+        }
+
+        fn last_record(&self) -> Option<u16> {
+            self.0.iter().copied().max()
+        }
+
+        fn delete_record(&mut self, seq: u16) -> bool {
+            match self.0.iter().position(|s| *s == seq) {
+                Some(i) => {
+                    self.0.swap_remove(i);
+                    true
+                }
+                None => false,
+            }
+        }
+    }
+
+    #[test]
+    fn report_count_delete() {
+        let mut table = AttributeTable::<U8, U32>::new();
+        table.add_service(Uuid16(0x1808)).unwrap();
+        let mut racp = RecordAccessControlPoint::add_to(&mut table).unwrap();
+        let handle = racp.handle();
+        let mut store = Records(Vec::from_slice(&[3, 4, 7, 9]).unwrap());
+
+        assert!(racp
+            .write_attr(&table, handle, &[0x01, 0x01])
+            .unwrap()
+            .is_err());
+        let cccd = table.cccd_handle(handle).unwrap();
+        table.set_value(cccd, &[0x02, 0x00]).unwrap();
+
+        // Report records 4 to 8
+        racp.write_attr(&table, handle, &[0x01, 0x04, 0x01, 4, 0, 8, 0])
+            .unwrap()
+            .unwrap();
+        assert!(racp
+            .write_attr(&table, handle, &[0x04, 0x01])
+            .unwrap()
+            .is_err());
+        assert_eq!(racp.process(&mut store), Some(4));
+        assert_eq!(racp.process(&mut store), Some(7));
+        assert_eq!(racp.process(&mut store), None);
+        assert_eq!(racp.pending_response(), Some(&[0x06, 0x00, 0x01, 0x01][..]));
+        racp.response_sent();
+
+        // Delete everything up to 4, then count the rest
+        racp.write_attr(&table, handle, &[0x02, 0x02, 0x01, 4, 0])
+            .unwrap()
+            .unwrap();
+        assert_eq!(racp.process(&mut store), None);
+        racp.response_sent();
+        racp.write_attr(&table, handle, &[0x04, 0x01])
+            .unwrap()
+            .unwrap();
+        assert_eq!(racp.process(&mut store), None);
+        assert_eq!(racp.pending_response(), Some(&[0x05, 0x00, 2, 0][..]));
+        racp.response_sent();
+
+        // Unsupported filters are answered right away
+        racp.write_attr(&table, handle, &[0x01, 0x03, 0x02, 0, 0])
+            .unwrap()
+            .unwrap();
+        assert_eq!(racp.pending_response(), Some(&[0x06, 0x00, 0x01, 0x09][..]));
+    }
+}
@@ -0,0 +1,335 @@
+//! BLE transport for the mcumgr *Simple Management Protocol* (SMP).
+//!
+//! SMP is the device management protocol used by Zephyr and MCUboot. Tools like `mcumgr` (or the
+//! nRF Connect Device Manager app) use it to upload images, read statistics, access the file
+//! system and more. Each request and response is a *frame* consisting of an 8-Byte
+//! [`SmpHeader`] and a CBOR-encoded payload.
+//!
+//! Over BLE, frames are written to the SMP characteristic and may be split across multiple writes.
+//! Responses are sent as notifications of the same characteristic, again split into fragments if
+//! they don't fit into one. [`SmpTransport`] takes care of both and hands complete requests to an
+//! [`SmpHandler`], which implements the actual management commands (Rubble does not provide any).
+//!
+//! [`SmpHeader`]: struct.SmpHeader.html
+//! [`SmpTransport`]: struct.SmpTransport.html
+//! [`SmpHandler`]: trait.SmpHandler.html
+
+use {
+    crate::{
+        att::{AttError, AttUuid, Handle},
+        bytes::{ByteReader, ByteWriter, FromBytes, ToBytes},
+        gatt::{
+            characteristic::Properties,
+            table::{AttributeTable, TableEntry},
+        },
+        utils,
+        uuid::Uuid,
+        Error,
+    },
+    heapless::{ArrayLength, Vec},
+};
+
+// UUIDs are given in the Byte order used on air.
+
+/// UUID of the SMP service (`8D53DC1D-1DB7-4CD3-868B-8A527460AA84`).
+pub const SMP_SERVICE: AttUuid = AttUuid::Uuid128(Uuid::from_bytes([
+    0x84, 0xAA, 0x60, 0x74, 0x52, 0x8A, 0x8B, 0x86, 0xD3, 0x4C, 0xB7, 0x1D, 0x1D, 0xDC, 0x53, 0x8D,
+]));
+
+/// UUID of the SMP characteristic (`DA2E7828-FBCE-4E01-AE9E-261174997C48`).
+pub const SMP_CHARACTERISTIC: AttUuid = AttUuid::Uuid128(Uuid::from_bytes([
+    0x48, 0x7C, 0x99, 0x74, 0x11, 0x26, 0x9E, 0xAE, 0x01, 0x4E, 0xCE, 0xFB, 0x28, 0x78, 0x2E, 0xDA,
+]));
+
+/// Group of the OS management commands (echo, reset, task stats, ...).
+pub const GROUP_OS: u16 = 0;
+/// Group of the image management commands (upload, list, confirm).
+pub const GROUP_IMAGE: u16 = 1;
+/// Group of the statistics commands.
+pub const GROUP_STATS: u16 = 2;
+/// Group of the file system commands.
+pub const GROUP_FS: u16 = 8;
+
+/// Maximum length of a response notification.
+///
+/// This is the largest value that fits into a notification with the default `ATT_MTU`.
+pub const FRAGMENT_LEN: usize = 20;
+
+enum_with_unknown! {
+    /// The operation of an SMP frame.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum SmpOp(u8) {
+        Read = 0,
+        ReadResponse = 1,
+        Write = 2,
+        WriteResponse = 3,
+    }
+}
+
+/// Errors reported to the client in the `rc` field of a response.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MgmtError {
+    Unknown = 1,
+    NoMemory = 2,
+    InvalidValue = 3,
+    Timeout = 4,
+    NotFound = 5,
+    BadState = 6,
+    MessageSize = 7,
+    NotSupported = 8,
+}
+
+/// Header of an SMP frame.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SmpHeader {
+    pub op: SmpOp,
+    /// Protocol version (0 for SMP version 1, 1 for version 2).
+    pub version: u8,
+    pub flags: u8,
+    /// Length of the payload following the header.
+    pub len: u16,
+    /// The command group (see the `GROUP_*` constants).
+    pub group: u16,
+    /// Sequence number chosen by the client, echoed in the response.
+    pub seq: u8,
+    /// The command within the group.
+    pub id: u8,
+}
+
+impl SmpHeader {
+    /// Size of the encoded header in Bytes.
+    pub const SIZE: usize = 8;
+}
+
+impl<'a> FromBytes<'a> for SmpHeader {
+    fn from_bytes(bytes: &mut ByteReader<'a>) -> Result<Self, Error> {
+        let op = bytes.read_u8()?;
+        Ok(Self {
+            op: SmpOp::from(op & 0b111),
+            version: (op >> 3) & 0b11,
+            flags: bytes.read_u8()?,
+            len: u16::from_be_bytes(bytes.read_array()?),
+            group: u16::from_be_bytes(bytes.read_array()?),
+            seq: bytes.read_u8()?,
+            id: bytes.read_u8()?,
+        })
+    }
+}
+
+impl ToBytes for SmpHeader {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u8(u8::from(self.op) | (self.version << 3))?;
+        writer.write_u8(self.flags)?;
+        writer.write_slice(&self.len.to_be_bytes())?;
+        writer.write_slice(&self.group.to_be_bytes())?;
+        writer.write_u8(self.seq)?;
+        writer.write_u8(self.id)
+    }
+}
+
+/// Implements the management commands.
+pub trait SmpHandler {
+    /// Handles the request described by `header`.
+    ///
+    /// `payload` is the CBOR-encoded request. The CBOR-encoded response payload has to be written
+    /// into `response`. If an error is returned, anything written to `response` is discarded and a
+    /// response containing only the error code is sent instead.
+    fn request(
+        &mut self,
+        header: &SmpHeader,
+        payload: &[u8],
+        response: &mut ByteWriter<'_>,
+    ) -> Result<(), MgmtError>;
+}
+
+/// SMP transport hosted in an `AttributeTable`.
+///
+/// `B` is the size of the frame buffers, and limits the size of both requests and responses.
+pub struct SmpTransport<B: ArrayLength<u8>> {
+    characteristic: Handle,
+    rx: Vec<u8, B>,
+    tx: Vec<u8, B>,
+    tx_sent: usize,
+}
+
+impl<B: ArrayLength<u8>> SmpTransport<B> {
+    /// Adds the SMP service to `table`.
+    pub fn add_to<N, TB>(table: &mut AttributeTable<N, TB>) -> Result<Self, Error>
+    where
+        N: ArrayLength<TableEntry>,
+        TB: ArrayLength<u8>,
+    {
+        table.add_service(SMP_SERVICE)?;
+        // Frames aren't stored in the table, writes are handled by `write_attr`
+        let characteristic = table.add_characteristic(
+            SMP_CHARACTERISTIC,
+            Properties::WRITE_NO_RSP | Properties::NOTIFY,
+            &[],
+        )?;
+        Ok(Self {
+            characteristic,
+            rx: Vec::new(),
+            tx: Vec::new(),
+            tx_sent: 0,
+        })
+    }
+
+    /// Returns the handle of the SMP characteristic value, on which responses are notified.
+    pub fn characteristic(&self) -> Handle {
+        self.characteristic
+    }
+
+    /// Handles a write to the attribute at `handle`.
+    ///
+    /// Returns `None` if `handle` doesn't belong to the service, in which case the write should be
+    /// processed as usual. Once a complete request frame was received, it is passed to `handler`.
+    pub fn write_attr<H: SmpHandler>(
+        &mut self,
+        handle: Handle,
+        value: &[u8],
+        handler: &mut H,
+    ) -> Option<Result<(), AttError>> {
+        if handle != self.characteristic {
+            return None;
+        }
+
+        if self.rx.extend_from_slice(value).is_err() {
+            debug!("SMP frame exceeds buffer, dropping");
+            utils::truncate(&mut self.rx, 0);
+            return Some(Ok(()));
+        }
+
+        let header = match SmpHeader::from_bytes(&mut ByteReader::new(&self.rx)) {
+            Ok(header) => header,
+            Err(_) => return Some(Ok(())),
+        };
+        let frame_len = SmpHeader::SIZE + usize::from(header.len);
+        if self.rx.len() >= frame_len {
+            let response_op = match header.op {
+                SmpOp::Read => Some(SmpOp::ReadResponse),
+                SmpOp::Write => Some(SmpOp::WriteResponse),
+                _ => None,
+            };
+            if let Some(op) = response_op {
+                let payload = &self.rx[SmpHeader::SIZE..frame_len];
+                respond(&mut self.tx, &header, op, payload, handler);
+                self.tx_sent = 0;
+            }
+            utils::truncate(&mut self.rx, 0);
+        }
+        Some(Ok(()))
+    }
+
+    /// Returns the next fragment of the response to notify, if there is one.
+    ///
+    /// Send it with `AttributeServerTx::notify_raw(smp.characteristic(), fragment)` and then call
+    /// `fragment_sent`.
+    pub fn next_fragment(&self) -> Option<&[u8]> {
+        let rest = &self.tx[self.tx_sent..];
+        if rest.is_empty() {
+            None
+        } else {
+            Some(&rest[..rest.len().min(FRAGMENT_LEN)])
+        }
+    }
+
+    /// Marks the fragment returned by `next_fragment` as sent.
+    pub fn fragment_sent(&mut self) {
+        self.tx_sent = self.tx.len().min(self.tx_sent + FRAGMENT_LEN);
+    }
+
+    /// Discards any partially received request and unsent response, eg. after a disconnection.
+    pub fn reset(&mut self) {
+        utils::truncate(&mut self.rx, 0);
+        utils::truncate(&mut self.tx, 0);
+        self.tx_sent = 0;
+    }
+}
+
+/// Encodes the response to the request `header` into `tx`.
+fn respond<B: ArrayLength<u8>, H: SmpHandler>(
+    tx: &mut Vec<u8, B>,
+    header: &SmpHeader,
+    op: SmpOp,
+    payload: &[u8],
+    handler: &mut H,
+) {
+    utils::truncate(tx, 0);
+    tx.resize_default(tx.capacity()).unwrap();
+    let (head, body) = tx.split_at_mut(SmpHeader::SIZE);
+
+    let mut writer = ByteWriter::new(body);
+    let space = writer.space_left();
+    let len = match handler.request(header, payload, &mut writer) {
+        Ok(()) => space - writer.space_left(),
+        Err(e) => {
+            // `{"rc": e}`, all error codes fit into the initial byte
+            let rc = [0xA1, 0x62, b'r', b'c', e as u8];
+            body[..rc.len()].copy_from_slice(&rc);
+            rc.len()
+        }
+    };
+
+    let response = SmpHeader {
+        op,
+        len: len as u16,
+        ..*header
+    };
+    response.to_bytes(&mut ByteWriter::new(head)).unwrap();
+    utils::truncate(tx, SmpHeader::SIZE + len);
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, heapless::consts::*};
+
+    /// Echoes the request payload.
+    struct Echo;
+
+    impl SmpHandler for Echo {
+        fn request(
+            &mut self,
+            header: &SmpHeader,
+            payload: &[u8],
+            response: &mut ByteWriter<'_>,
+        ) -> Result<(), MgmtError> {
+            if header.group != GROUP_OS {
+                return Err(MgmtError::NotSupported);
+            }
+            response
+                .write_slice(payload)
+                .map_err(|_| MgmtError::MessageSize)
+        }
+    }
+
+    #[test]
+    fn fragmentation() {
+        let mut table = AttributeTable::<U4, U64>::new();
+        let mut smp = SmpTransport::<U64>::add_to(&mut table).unwrap();
+        let chr = smp.characteristic();
+
+        let mut request = [0; 8 + 30];
+        request[..8].copy_from_slice(&[0x02, 0, 0, 30, 0, 0, 7, 0]);
+        for (i, b) in request[8..].iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        for chunk in request.chunks(20) {
+            assert!(smp.next_fragment().is_none());
+            smp.write_attr(chr, chunk, &mut Echo).unwrap().unwrap();
+        }
+
+        let first = smp.next_fragment().unwrap();
+        assert_eq!(first.len(), FRAGMENT_LEN);
+        assert_eq!(first[..8], [0x03, 0, 0, 30, 0, 0, 7, 0]);
+        smp.fragment_sent();
+        assert_eq!(smp.next_fragment().unwrap(), &request[20..]);
+        smp.fragment_sent();
+        assert!(smp.next_fragment().is_none());
+
+        let stats = [0x00, 0, 0, 0, 0, GROUP_STATS as u8, 1, 0];
+        smp.write_attr(chr, &stats, &mut Echo).unwrap().unwrap();
+        let rsp = smp.next_fragment().unwrap();
+        assert_eq!(rsp[..8], [0x01, 0, 0, 5, 0, 2, 1, 0]);
+        assert_eq!(rsp[8..], [0xA1, 0x62, b'r', b'c', 8]);
+    }
+}
@@ -0,0 +1,238 @@
+//! Client-side registry of notification and indication subscriptions.
+//!
+//! A `Subscriptions` registry writes the CCCDs of the characteristics the application subscribes
+//! to and remembers a handler for each of them. Forwarding every `ClientEvent` to
+//! `Subscriptions::dispatch` (from `AttributeProvider::client_event`) then calls the right
+//! handler for every notification and indication. Indications are confirmed by the ATT layer
+//! after the event has been processed, so handlers don't have to do anything for that.
+//!
+//! ```
+//! use rubble::att::{ClientEvent, Handle};
+//! use rubble::gatt::subscriptions::Subscriptions;
+//!
+//! fn battery_level(_handle: Handle, value: &[u8]) {
+//!     assert_eq!(value, [87]);
+//! }
+//!
+//! let mut subs = Subscriptions::new();
+//! // Normally done with `subs.subscribe(att, ...)`, which also writes the CCCD
+//! subs.register(Handle::from_raw(0x12), Handle::from_raw(0x13), false, battery_level)
+//!     .unwrap();
+//!
+//! assert!(subs.dispatch(&ClientEvent::Notification {
+//!     handle: Handle::from_raw(0x12),
+//!     value: &[87],
+//! }));
+//! ```
+
+use crate::{
+    att::{AttributeProvider, AttributeServerTx, ClientEvent, Handle},
+    Error,
+};
+
+/// Maximum number of characteristics that can be subscribed to at once.
+pub const MAX_SUBSCRIPTIONS: usize = 8;
+
+/// Handler called with the value handle and the new value of a subscribed characteristic.
+pub type NotificationHandler = fn(Handle, &[u8]);
+
+#[derive(Copy, Clone)]
+struct Subscription {
+    value_handle: Handle,
+    cccd: Handle,
+    indications: bool,
+    handler: NotificationHandler,
+    /// Whether the server has acknowledged the CCCD write.
+    confirmed: bool,
+}
+
+/// A registry of subscribed characteristics on the peer's server.
+pub struct Subscriptions {
+    entries: [Option<Subscription>; MAX_SUBSCRIPTIONS],
+}
+
+impl Subscriptions {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Self {
+            entries: [None; MAX_SUBSCRIPTIONS],
+        }
+    }
+
+    /// Subscribes to the characteristic at `value_handle` by writing its CCCD at `cccd`.
+    ///
+    /// `handler` is called for every notification (or, with `indications` set, indication) of
+    /// the value. Returns `Error::Eof` if `MAX_SUBSCRIPTIONS` characteristics are already
+    /// subscribed to, or an error from sending the write request. A previous subscription to the
+    /// same characteristic is replaced.
+    pub fn subscribe<A: AttributeProvider>(
+        &mut self,
+        att: AttributeServerTx<'_, A>,
+        value_handle: Handle,
+        cccd: Handle,
+        indications: bool,
+        handler: NotificationHandler,
+    ) -> Result<(), Error> {
+        let slot = self.slot_for(value_handle).ok_or(Error::Eof)?;
+        let config: u16 = if indications { 0x0002 } else { 0x0001 };
+        att.write(cccd, &config.to_le_bytes())?;
+        self.entries[slot] = Some(Subscription {
+            value_handle,
+            cccd,
+            indications,
+            handler,
+            confirmed: false,
+        });
+        Ok(())
+    }
+
+    /// Registers a handler without writing the CCCD.
+    ///
+    /// This is useful when reconnecting to a bonded server, which keeps the CCCD values of its
+    /// bonded clients.
+    pub fn register(
+        &mut self,
+        value_handle: Handle,
+        cccd: Handle,
+        indications: bool,
+        handler: NotificationHandler,
+    ) -> Result<(), Error> {
+        let slot = self.slot_for(value_handle).ok_or(Error::Eof)?;
+        self.entries[slot] = Some(Subscription {
+            value_handle,
+            cccd,
+            indications,
+            handler,
+            confirmed: true,
+        });
+        Ok(())
+    }
+
+    /// Unsubscribes from the characteristic at `value_handle` by clearing its CCCD.
+    ///
+    /// Returns `Error::InvalidValue` if it isn't subscribed to.
+    pub fn unsubscribe<A: AttributeProvider>(
+        &mut self,
+        att: AttributeServerTx<'_, A>,
+        value_handle: Handle,
+    ) -> Result<(), Error> {
+        let slot = self.find(value_handle).ok_or(Error::InvalidValue)?;
+        let cccd = self.entries[slot].unwrap().cccd;
+        att.write(cccd, &[0, 0])?;
+        self.entries[slot] = None;
+        Ok(())
+    }
+
+    /// Returns whether the server has acknowledged the subscription to `value_handle`.
+    pub fn is_subscribed(&self, value_handle: Handle) -> bool {
+        self.find(value_handle)
+            .is_some_and(|i| self.entries[i].unwrap().confirmed)
+    }
+
+    /// Removes all subscriptions without writing the CCCDs, eg. after the connection was closed.
+    pub fn clear(&mut self) {
+        self.entries = [None; MAX_SUBSCRIPTIONS];
+    }
+
+    /// Processes an event from the ATT client.
+    ///
+    /// Notifications and indications of subscribed characteristics are passed to their handler,
+    /// and the responses to CCCD writes update the subscription state (a rejected subscription is
+    /// removed). Returns whether the event was consumed; other events should be processed by the
+    /// application.
+    pub fn dispatch(&mut self, event: &ClientEvent<'_>) -> bool {
+        match *event {
+            ClientEvent::Notification { handle, value }
+            | ClientEvent::Indication { handle, value } => match self.find(handle) {
+                Some(i) => {
+                    let sub = self.entries[i].unwrap();
+                    let indication = matches!(event, ClientEvent::Indication { .. });
+                    if indication != sub.indications {
+                        debug!(
+                            "{:?}: got indication={}, subscribed to {}",
+                            handle, indication, sub.indications
+                        );
+                    }
+                    (sub.handler)(handle, value);
+                    true
+                }
+                None => false,
+            },
+            ClientEvent::Written { handle } => match self.find_cccd(handle) {
+                Some(i) => {
+                    self.entries[i].as_mut().unwrap().confirmed = true;
+                    true
+                }
+                None => false,
+            },
+            ClientEvent::Error { handle, code } => match self.find_cccd(handle) {
+                Some(i) => {
+                    debug!("subscription via {:?} rejected: {:?}", handle, code);
+                    self.entries[i] = None;
+                    true
+                }
+                None => false,
+            },
+            _ => false,
+        }
+    }
+
+    fn find(&self, value_handle: Handle) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|e| e.is_some_and(|e| e.value_handle == value_handle))
+    }
+
+    /// Finds the subscription whose CCCD write is still pending.
+    fn find_cccd(&self, cccd: Handle) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|e| e.is_some_and(|e| e.cccd == cccd && !e.confirmed))
+    }
+
+    /// Returns the slot to use for a subscription to `value_handle`.
+    fn slot_for(&self, value_handle: Handle) -> Option<usize> {
+        self.find(value_handle)
+            .or_else(|| self.entries.iter().position(Option::is_none))
+    }
+}
+
+impl Default for Subscriptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::att::ErrorCode;
+
+    fn ignore(_: Handle, _: &[u8]) {}
+
+    #[test]
+    fn cccd_responses() {
+        let mut subs = Subscriptions::new();
+        let value = Handle::from_raw(3);
+        let cccd = Handle::from_raw(4);
+        subs.entries[0] = Some(Subscription {
+            value_handle: value,
+            cccd,
+            indications: true,
+            handler: ignore,
+            confirmed: false,
+        });
+        assert!(!subs.is_subscribed(value));
+
+        assert!(subs.dispatch(&ClientEvent::Written { handle: cccd }));
+        assert!(subs.is_subscribed(value));
+        assert!(!subs.dispatch(&ClientEvent::Written { handle: cccd }));
+
+        subs.entries[0].as_mut().unwrap().confirmed = false;
+        assert!(subs.dispatch(&ClientEvent::Error {
+            handle: cccd,
+            code: ErrorCode::InsufficientEncryption,
+        }));
+        assert!(subs.find(value).is_none());
+    }
+}
@@ -0,0 +1,671 @@
+//! A runtime-populated attribute table.
+//!
+//! Hand-writing an `AttributeProvider` (like `BatteryServiceAttrs`) requires assigning handles and
+//! encoding declarations manually. `AttributeTable` instead lets the application push services and
+//! characteristics at initialization time, assigns handles automatically, and stores all values in
+//! a fixed-capacity buffer.
+//!
+//! Both the number of attributes and the total size of all attribute values are bounded by type
+//! parameters, so no allocator is needed:
+//!
+//! ```
+//! use rubble::gatt::{characteristic::Properties, table::AttributeTable};
+//! use rubble::uuid::Uuid16;
+//! use heapless::consts::*;
+//!
+//! let mut table = AttributeTable::<U8, U64>::new();
+//! table.add_service(Uuid16(0x180F)).unwrap();
+//! let level = table
+//!     .add_characteristic(Uuid16(0x2A19), Properties::READ | Properties::NOTIFY, &[100])
+//!     .unwrap();
+//! table.set_value(level, &[99]).unwrap();
+//! ```
+//!
+//! To keep handles stable across firmware updates, services can be given a reserved block of
+//! handles using `add_service_reserved` (refer to the [`handles`] module for details).
+//!
+//! [`handles`]: ../handles/index.html
+
+use {
+    crate::{
+        att::{AttError, AttUuid, Attribute, AttributeProvider, ErrorCode, Handle, HandleRange},
+        bytes::{ByteWriter, ToBytes},
+        gatt::{
+            characteristic::Properties,
+            descriptor::{PresentationFormat, PRESENTATION_FORMAT, USER_DESCRIPTION},
+            handles::{HandleAllocator, HandleBlock},
+        },
+        utils::HexSlice,
+        uuid::Uuid16,
+        Error,
+    },
+    heapless::{ArrayLength, Vec},
+};
+
+const PRIMARY_SERVICE: Uuid16 = Uuid16(0x2800);
+const SECONDARY_SERVICE: Uuid16 = Uuid16(0x2801);
+const INCLUDE: Uuid16 = Uuid16(0x2802);
+const CHARACTERISTIC: Uuid16 = Uuid16(0x2803);
+pub(crate) const CCCD: Uuid16 = Uuid16(0x2902);
+
+/// CCCD bit enabling notifications.
+pub(crate) const CCCD_NOTIFY: u16 = 0x0001;
+
+/// CCCD bit enabling indications.
+pub(crate) const CCCD_INDICATE: u16 = 0x0002;
+
+/// Decodes the value of a *Client Characteristic Configuration* descriptor.
+pub(crate) fn cccd_value(value: &[u8]) -> Option<u16> {
+    match value {
+        [lo, hi] => Some(u16::from_le_bytes([*lo, *hi])),
+        _ => None,
+    }
+}
+
+/// Storage slot for a single attribute in an `AttributeTable`.
+#[derive(Debug, Copy, Clone)]
+pub struct TableEntry {
+    att_type: AttUuid,
+    handle: Handle,
+    /// Offset of the value in the table's data buffer.
+    offset: u16,
+    /// Current length of the value.
+    len: u16,
+    /// Number of Bytes reserved for the value.
+    capacity: u16,
+    /// Whether the client is allowed to write to this attribute.
+    writable: bool,
+    /// For service declarations with a reserved handle block, the end of that block.
+    reserved_end: Option<Handle>,
+}
+
+/// An `AttributeProvider` whose attributes are added at runtime.
+///
+/// Handles are assigned in ascending order, starting at `0x0001`, in the order attributes are
+/// added.
+///
+/// # Type Parameters
+///
+/// * `N`: Maximum number of attributes in the table.
+/// * `B`: Size of the buffer storing all attribute values, in Bytes.
+pub struct AttributeTable<N: ArrayLength<TableEntry>, B: ArrayLength<u8>> {
+    entries: Vec<TableEntry, N>,
+    data: Vec<u8, B>,
+    alloc: HandleAllocator,
+    /// Handle block of the current service, if it was added with `add_service_reserved`.
+    block: Option<HandleBlock>,
+}
+
+impl<N: ArrayLength<TableEntry>, B: ArrayLength<u8>> AttributeTable<N, B> {
+    /// Creates an empty attribute table.
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            data: Vec::new(),
+            alloc: HandleAllocator::new(),
+            block: None,
+        }
+    }
+
+    /// Returns the handle the next added attribute will get.
+    fn next_handle(&self) -> Result<Handle, Error> {
+        match &self.block {
+            Some(block) => block.peek(),
+            None => self.alloc.peek(),
+        }
+        .ok_or(Error::Eof)
+    }
+
+    fn allocate_handle(&mut self) -> Result<Handle, Error> {
+        match &mut self.block {
+            Some(block) => block.allocate(),
+            None => self.alloc.allocate(),
+        }
+        .ok_or(Error::Eof)
+    }
+
+    /// Returns whether `attrs` more attributes with a total value size of `bytes` fit into the
+    /// table (and into the current service's handle block, if any).
+    fn has_space(&self, attrs: usize, bytes: usize) -> bool {
+        let handles = match &self.block {
+            Some(block) => block.remaining(),
+            None => self.alloc.remaining(),
+        };
+
+        self.entries.capacity() - self.entries.len() >= attrs
+            && self.data.capacity() - self.data.len() >= bytes
+            && handles >= attrs as u32
+    }
+
+    /// Skips ahead so that the next attribute added to the table is assigned `handle`.
+    ///
+    /// This must not be used inside a service added with `add_service_reserved`. Returns
+    /// `Error::InvalidValue` if `handle` was already assigned or if the current service has a
+    /// reserved handle block.
+    pub fn skip_to(&mut self, handle: Handle) -> Result<(), Error> {
+        if self.block.is_some() {
+            return Err(Error::InvalidValue);
+        }
+
+        self.alloc.skip_to(handle)
+    }
+
+    /// Adds an attribute with the given type and initial value, reserving `capacity` Bytes for
+    /// the value.
+    ///
+    /// Returns the handle assigned to the attribute. If the table (or the current service's
+    /// handle block) is out of space, returns `Error::Eof`.
+    pub fn add_attribute(
+        &mut self,
+        att_type: AttUuid,
+        value: &[u8],
+        capacity: usize,
+        writable: bool,
+    ) -> Result<Handle, Error> {
+        if value.len() > capacity || capacity > usize::from(u16::MAX) {
+            return Err(Error::InvalidLength);
+        }
+
+        if !self.has_space(1, capacity) {
+            return Err(Error::Eof);
+        }
+
+        let handle = self.allocate_handle()?;
+        let offset = self.data.len();
+
+        self.data.extend_from_slice(value).unwrap();
+        self.data.resize(offset + capacity, 0).unwrap();
+        self.entries
+            .push(TableEntry {
+                att_type,
+                handle,
+                offset: offset as u16,
+                len: value.len() as u16,
+                capacity: capacity as u16,
+                writable,
+                reserved_end: None,
+            })
+            .unwrap();
+        Ok(handle)
+    }
+
+    /// Adds a primary service declaration.
+    ///
+    /// All characteristics added after this call (until the next service is added) belong to this
+    /// service.
+    pub fn add_service(&mut self, uuid: impl Into<AttUuid>) -> Result<Handle, Error> {
+        self.add_service_decl(PRIMARY_SERVICE, uuid.into(), None)
+    }
+
+    /// Adds a primary service declaration and reserves a block of `num_handles` handles for it.
+    ///
+    /// The block includes the service declaration itself. All attributes of the service must fit
+    /// into the block, and the next service will start after the block, even if not all handles
+    /// are used. The group end handle reported to clients is the end of the block, so adding more
+    /// attributes to the service later does not change any handles visible to clients.
+    pub fn add_service_reserved(
+        &mut self,
+        uuid: impl Into<AttUuid>,
+        num_handles: u16,
+    ) -> Result<Handle, Error> {
+        self.add_service_decl(PRIMARY_SERVICE, uuid.into(), Some(num_handles))
+    }
+
+    /// Adds a primary service declaration at a fixed `handle`, optionally reserving a block of
+    /// `num_handles` handles for it.
+    ///
+    /// This ends the current service (even one with a reserved block) and skips ahead to
+    /// `handle`. Returns `Error::InvalidValue` if `handle` was already assigned.
+    pub fn add_service_at(
+        &mut self,
+        handle: Handle,
+        uuid: impl Into<AttUuid>,
+        num_handles: Option<u16>,
+    ) -> Result<Handle, Error> {
+        self.block = None;
+        self.alloc.skip_to(handle)?;
+        self.add_service_decl(PRIMARY_SERVICE, uuid.into(), num_handles)
+    }
+
+    /// Adds a secondary service declaration.
+    pub fn add_secondary_service(&mut self, uuid: impl Into<AttUuid>) -> Result<Handle, Error> {
+        self.add_service_decl(SECONDARY_SERVICE, uuid.into(), None)
+    }
+
+    fn add_service_decl(
+        &mut self,
+        decl: Uuid16,
+        uuid: AttUuid,
+        num_handles: Option<u16>,
+    ) -> Result<Handle, Error> {
+        let mut buf = [0; 16];
+        let mut writer = ByteWriter::new(&mut buf);
+        let left = writer.space_left();
+        // Clients expect the short form for 16-bit UUIDs (and include definitions rely on it)
+        uuid.shortest().to_bytes(&mut writer)?;
+        let len = left - writer.space_left();
+
+        // A new service always ends the previous service's block
+        self.block = None;
+        if !self.has_space(1, len) {
+            return Err(Error::Eof);
+        }
+
+        let reserved_end = match num_handles {
+            Some(num) => {
+                let block = self.alloc.reserve(num).ok_or(Error::Eof)?;
+                let end = block.end();
+                self.block = Some(block);
+                Some(end)
+            }
+            None => None,
+        };
+
+        let handle = self.add_attribute(decl.into(), &buf[..len], len, false)?;
+        self.entries.last_mut().unwrap().reserved_end = reserved_end;
+        Ok(handle)
+    }
+
+    /// Adds an include definition referencing the service declared at `service` to the current
+    /// service.
+    ///
+    /// Include definitions must be added right after the service declaration, before any
+    /// characteristics. The included service must already be complete when this is called (or
+    /// have a reserved handle block), since its group end handle is copied into the definition.
+    ///
+    /// Returns `Error::InvalidValue` if `service` isn't a service declaration in this table.
+    pub fn add_include(&mut self, service: Handle) -> Result<Handle, Error> {
+        let decl = self.entry(service).ok_or(Error::InvalidValue)?;
+        if !Self::is_service_decl(decl) {
+            return Err(Error::InvalidValue);
+        }
+        let end = self.group_end(service).ok_or(Error::InvalidValue)?;
+
+        // Start handle, end group handle, and the service UUID if it's a 16-bit UUID
+        let mut buf = [0; 6];
+        let uuid = self.entry_value(decl);
+        let len = if uuid.len() == 2 { 6 } else { 4 };
+        buf[..2].copy_from_slice(&service.as_u16().to_le_bytes());
+        buf[2..4].copy_from_slice(&end.as_u16().to_le_bytes());
+        if len == 6 {
+            buf[4..].copy_from_slice(uuid);
+        }
+
+        self.add_attribute(INCLUDE.into(), &buf[..len], len, false)
+    }
+
+    /// Adds a characteristic declaration and value to the current service.
+    ///
+    /// Exactly `value.len()` Bytes are reserved for the value. Use
+    /// `add_characteristic_with_capacity` if the value can grow later.
+    ///
+    /// If `props` includes `NOTIFY` or `INDICATE`, a *Client Characteristic Configuration*
+    /// descriptor is added as well.
+    ///
+    /// Returns the handle of the characteristic value.
+    pub fn add_characteristic(
+        &mut self,
+        uuid: impl Into<AttUuid>,
+        props: Properties,
+        value: &[u8],
+    ) -> Result<Handle, Error> {
+        self.add_characteristic_with_capacity(uuid, props, value, value.len())
+    }
+
+    /// Adds a characteristic declaration and value, reserving `capacity` Bytes for the value.
+    ///
+    /// Returns the handle of the characteristic value.
+    pub fn add_characteristic_with_capacity(
+        &mut self,
+        uuid: impl Into<AttUuid>,
+        props: Properties,
+        value: &[u8],
+        capacity: usize,
+    ) -> Result<Handle, Error> {
+        let uuid = uuid.into();
+        let decl_handle = self.next_handle()?;
+        let value_handle = decl_handle.as_u16().checked_add(1).ok_or(Error::Eof)?;
+
+        // Properties, value handle, characteristic UUID
+        let mut decl = [0; 19];
+        let mut writer = ByteWriter::new(&mut decl);
+        let left = writer.space_left();
+        writer.write_u8(props.bits())?;
+        writer.write_u16_le(value_handle)?;
+        uuid.shortest().to_bytes(&mut writer)?;
+        let len = left - writer.space_left();
+
+        // Check for space up front so that we don't leave a partial characteristic behind
+        let has_cccd = props.intersects(Properties::NOTIFY | Properties::INDICATE);
+        let (attrs, bytes) = if has_cccd {
+            (3, len + capacity + 2)
+        } else {
+            (2, len + capacity)
+        };
+        if value.len() > capacity {
+            return Err(Error::InvalidLength);
+        }
+        if !self.has_space(attrs, bytes) {
+            return Err(Error::Eof);
+        }
+
+        self.add_attribute(CHARACTERISTIC.into(), &decl[..len], len, false)?;
+        let writable = props.intersects(Properties::WRITE | Properties::WRITE_NO_RSP);
+        let handle = self.add_attribute(uuid, value, capacity, writable)?;
+
+        if has_cccd {
+            self.add_descriptor(CCCD, &[0x00, 0x00], true)?;
+        }
+
+        Ok(handle)
+    }
+
+    /// Adds a characteristic descriptor to the last added characteristic.
+    ///
+    /// Returns the handle of the descriptor.
+    pub fn add_descriptor(
+        &mut self,
+        uuid: impl Into<AttUuid>,
+        value: &[u8],
+        writable: bool,
+    ) -> Result<Handle, Error> {
+        self.add_attribute(uuid.into(), value, value.len(), writable)
+    }
+
+    /// Adds a read-only *Characteristic User Description* to the last added characteristic.
+    ///
+    /// Returns the handle of the descriptor.
+    pub fn add_user_description(&mut self, description: &str) -> Result<Handle, Error> {
+        self.add_descriptor(USER_DESCRIPTION, description.as_bytes(), false)
+    }
+
+    /// Adds a *Characteristic Presentation Format* descriptor to the last added characteristic.
+    ///
+    /// Returns the handle of the descriptor.
+    pub fn add_presentation_format(
+        &mut self,
+        format: &PresentationFormat,
+    ) -> Result<Handle, Error> {
+        let mut buf = [0; PresentationFormat::SIZE];
+        format.to_bytes(&mut ByteWriter::new(&mut buf))?;
+        self.add_descriptor(PRESENTATION_FORMAT, &buf, false)
+    }
+
+    fn entry(&self, handle: Handle) -> Option<&TableEntry> {
+        // Entries are sorted by handle
+        self.entries
+            .binary_search_by_key(&handle.as_u16(), |entry| entry.handle.as_u16())
+            .ok()
+            .map(|index| &self.entries[index])
+    }
+
+    /// Returns the current value of the attribute at `handle`.
+    pub fn value(&self, handle: Handle) -> Option<&[u8]> {
+        self.entry(handle).map(|entry| self.entry_value(entry))
+    }
+
+    /// Returns an iterator over all attributes in the table, in ascending handle order.
+    ///
+    /// `gatt::introspect` can be used to walk them as services and characteristics.
+    pub fn attributes(&self) -> impl Iterator<Item = Attribute<'_>> + Clone {
+        self.entries.iter().map(move |entry| Attribute {
+            att_type: entry.att_type,
+            handle: entry.handle,
+            value: HexSlice(self.entry_value(entry)),
+        })
+    }
+
+    /// Returns the handle of the *Client Characteristic Configuration* descriptor of the
+    /// characteristic whose value is at `value_handle`.
+    ///
+    /// The descriptor is looked up among the attributes following the value, up to the next
+    /// characteristic or service. Returns `None` if the characteristic has no CCCD.
+    pub fn cccd_handle(&self, value_handle: Handle) -> Option<Handle> {
+        let index = self
+            .entries
+            .binary_search_by_key(&value_handle.as_u16(), |entry| entry.handle.as_u16())
+            .ok()?;
+        self.entries
+            .get(index + 1..)?
+            .iter()
+            .take_while(|entry| entry.att_type != CHARACTERISTIC && !Self::is_service_decl(entry))
+            .find(|entry| entry.att_type == CCCD)
+            .map(|entry| entry.handle)
+    }
+
+    /// Returns the value of the *Client Characteristic Configuration* descriptor of the
+    /// characteristic whose value is at `value_handle`, or `None` if it has none.
+    pub fn cccd(&self, value_handle: Handle) -> Option<u16> {
+        cccd_value(self.value(self.cccd_handle(value_handle)?)?)
+    }
+
+    /// Returns whether the client has enabled notifications for the characteristic value at
+    /// `value_handle`.
+    pub fn notifications_enabled(&self, value_handle: Handle) -> bool {
+        self.cccd(value_handle)
+            .is_some_and(|cccd| cccd & CCCD_NOTIFY != 0)
+    }
+
+    /// Returns whether the client has enabled indications for the characteristic value at
+    /// `value_handle`.
+    pub fn indications_enabled(&self, value_handle: Handle) -> bool {
+        self.cccd(value_handle)
+            .is_some_and(|cccd| cccd & CCCD_INDICATE != 0)
+    }
+
+    fn entry_value(&self, entry: &TableEntry) -> &[u8] {
+        let start = usize::from(entry.offset);
+        &self.data[start..start + usize::from(entry.len)]
+    }
+
+    /// Changes the value of the attribute at `handle`.
+    ///
+    /// Returns `Error::InvalidValue` if there's no attribute with that handle, and
+    /// `Error::InvalidLength` if `value` exceeds the space reserved for it.
+    pub fn set_value(&mut self, handle: Handle, value: &[u8]) -> Result<(), Error> {
+        let index = self
+            .entries
+            .binary_search_by_key(&handle.as_u16(), |entry| entry.handle.as_u16())
+            .map_err(|_| Error::InvalidValue)?;
+        let entry = &mut self.entries[index];
+        if value.len() > usize::from(entry.capacity) {
+            return Err(Error::InvalidLength);
+        }
+
+        let start = usize::from(entry.offset);
+        self.data[start..start + value.len()].copy_from_slice(value);
+        entry.len = value.len() as u16;
+        Ok(())
+    }
+
+    fn is_service_decl(entry: &TableEntry) -> bool {
+        entry.att_type == PRIMARY_SERVICE || entry.att_type == SECONDARY_SERVICE
+    }
+}
+
+impl<N: ArrayLength<TableEntry>, B: ArrayLength<u8>> Default for AttributeTable<N, B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<N: ArrayLength<TableEntry>, B: ArrayLength<u8>> AttributeProvider for AttributeTable<N, B> {
+    fn for_attrs_in_range(
+        &mut self,
+        range: HandleRange,
+        mut f: impl FnMut(&Self, Attribute<'_>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        for entry in self.entries.iter().filter(|e| range.contains(e.handle)) {
+            f(
+                self,
+                Attribute {
+                    att_type: entry.att_type,
+                    handle: entry.handle,
+                    value: HexSlice(self.entry_value(entry)),
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    fn is_grouping_attr(&self, uuid: AttUuid) -> bool {
+        uuid == PRIMARY_SERVICE || uuid == SECONDARY_SERVICE
+    }
+
+    fn group_end(&self, handle: Handle) -> Option<Handle> {
+        let index = self
+            .entries
+            .binary_search_by_key(&handle.as_u16(), |entry| entry.handle.as_u16())
+            .ok()?;
+        let decl = &self.entries[index];
+        if !Self::is_service_decl(decl) {
+            return None;
+        }
+        if let Some(end) = decl.reserved_end {
+            return Some(end);
+        }
+
+        // The group ends right before the next service declaration (or at the end of the table)
+        let rest = &self.entries[index + 1..];
+        let end = rest
+            .iter()
+            .take_while(|entry| !Self::is_service_decl(entry))
+            .last()
+            .map_or(handle, |entry| entry.handle);
+        Some(end)
+    }
+
+    fn write_attr(&mut self, handle: Handle, value: &[u8]) -> Result<(), AttError> {
+        match self.entry(handle) {
+            None => Err(AttError::new(ErrorCode::InvalidHandle, handle)),
+            Some(entry) if !entry.writable => {
+                Err(AttError::new(ErrorCode::WriteNotPermitted, handle))
+            }
+            Some(_) => self
+                .set_value(handle, value)
+                .map_err(|_| AttError::new(ErrorCode::InvalidAttributeValueLength, handle)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use heapless::consts::*;
+
+    #[test]
+    fn handles_and_groups() {
+        let mut table = AttributeTable::<U16, U128>::new();
+        let bas = table.add_service(Uuid16(0x180F)).unwrap();
+        let level = table
+            .add_characteristic(Uuid16(0x2A19), Properties::READ | Properties::NOTIFY, &[50])
+            .unwrap();
+        let dis = table.add_service(Uuid16(0x180A)).unwrap();
+        let model = table
+            .add_characteristic(Uuid16(0x2A24), Properties::READ, b"rubble")
+            .unwrap();
+
+        assert_eq!(bas.as_u16(), 1);
+        assert_eq!(level.as_u16(), 3);
+        // CCCD at 4
+        assert_eq!(dis.as_u16(), 5);
+        assert_eq!(model.as_u16(), 7);
+
+        assert_eq!(table.group_end(bas).map(|h| h.as_u16()), Some(4));
+        assert_eq!(table.group_end(dis).map(|h| h.as_u16()), Some(7));
+        assert_eq!(table.group_end(level), None);
+
+        assert_eq!(
+            table.value(Handle::from_raw(2)),
+            Some(&[0x12, 0x03, 0x00, 0x19, 0x2A][..])
+        );
+        assert_eq!(table.value(model), Some(&b"rubble"[..]));
+    }
+
+    #[test]
+    fn writes() {
+        let mut table = AttributeTable::<U8, U32>::new();
+        table.add_service(Uuid16(0x180F)).unwrap();
+        let ro = table
+            .add_characteristic(Uuid16(0x2A19), Properties::READ | Properties::NOTIFY, &[50])
+            .unwrap();
+        let plain = table
+            .add_characteristic(Uuid16(0x2A1A), Properties::READ, &[0])
+            .unwrap();
+        let cccd = table.cccd_handle(ro).unwrap();
+        assert_eq!(cccd.as_u16(), ro.as_u16() + 1);
+        assert_eq!(table.cccd_handle(plain), None);
+        assert_eq!(table.cccd(ro), Some(0));
+
+        assert!(table.write_attr(ro, &[1]).is_err());
+        table.write_attr(cccd, &[0x01, 0x00]).unwrap();
+        assert_eq!(table.value(cccd), Some(&[0x01, 0x00][..]));
+        assert!(table.write_attr(cccd, &[0x01, 0x00, 0x00]).is_err());
+        assert!(table.notifications_enabled(ro));
+        assert!(!table.indications_enabled(ro));
+        assert!(!table.notifications_enabled(plain));
+
+        table.set_value(ro, &[49]).unwrap();
+        assert_eq!(table.value(ro), Some(&[49][..]));
+    }
+
+    #[test]
+    fn reserved_blocks() {
+        let mut table = AttributeTable::<U16, U128>::new();
+        let bas = table.add_service_reserved(Uuid16(0x180F), 8).unwrap();
+        table
+            .add_characteristic(Uuid16(0x2A19), Properties::READ, &[50])
+            .unwrap();
+        let dis = table.add_service_reserved(Uuid16(0x180A), 3).unwrap();
+        table
+            .add_characteristic(Uuid16(0x2A24), Properties::READ, b"rubble")
+            .unwrap();
+
+        assert_eq!(bas.as_u16(), 1);
+        assert_eq!(dis.as_u16(), 9);
+        assert_eq!(table.group_end(bas).map(|h| h.as_u16()), Some(8));
+        assert_eq!(table.group_end(dis).map(|h| h.as_u16()), Some(11));
+
+        // The DIS block is full now
+        assert_eq!(
+            table.add_descriptor(Uuid16(0x2901), b"model", false),
+            Err(Error::Eof)
+        );
+
+        table.add_service(Uuid16(0x1800)).unwrap();
+        table.skip_to(Handle::from_raw(0x20)).unwrap();
+        let name = table
+            .add_characteristic(Uuid16(0x2A00), Properties::READ, b"name")
+            .unwrap();
+        assert_eq!(name.as_u16(), 0x21);
+    }
+
+    #[test]
+    fn includes() {
+        let mut table = AttributeTable::<U16, U128>::new();
+        let bas = table.add_service(Uuid16(0x180F)).unwrap();
+        table
+            .add_characteristic(Uuid16(0x2A19), Properties::READ, &[50])
+            .unwrap();
+        table.add_service(Uuid16(0x1812)).unwrap();
+        let include = table.add_include(bas).unwrap();
+
+        assert_eq!(
+            table.value(include),
+            Some(&[0x01, 0x00, 0x03, 0x00, 0x0F, 0x18][..])
+        );
+        assert_eq!(table.add_include(include), Err(Error::InvalidValue));
+    }
+
+    #[test]
+    fn out_of_space() {
+        let mut table = AttributeTable::<U2, U32>::new();
+        table.add_service(Uuid16(0x180F)).unwrap();
+        // Declaration fits, but value doesn't. Nothing may be added in that case.
+        assert_eq!(
+            table.add_characteristic(Uuid16(0x2A19), Properties::READ, &[50]),
+            Err(Error::Eof)
+        );
+        assert_eq!(table.value(Handle::from_raw(2)), None);
+    }
+}
@@ -0,0 +1,196 @@
+//! Adapters between Rubble's time types and the `embedded-hal` and `fugit` ecosystem.
+//!
+//! This module is only available when the `hal` feature is enabled.
+//!
+//! Rubble's `Timer` trait needs a free-running microsecond clock. `embedded-hal` 1.0 doesn't
+//! define a counter abstraction, but most HALs provide some way of reading a hardware counter as
+//! a `fugit` instant. [`CounterTimer`] turns any such counter (with any tick rate) into a Rubble
+//! `Timer`, so no bespoke timer backend is needed:
+//!
+//! ```
+//! use rubble::hal::CounterTimer;
+//! use rubble::time::Timer;
+//! use core::cell::Cell;
+//! use fugit::TimerInstantU32;
+//!
+//! // A 32.768 kHz RTC, read via the HAL
+//! let rtc = Cell::new(100);
+//! let timer = CounterTimer::new(|| TimerInstantU32::<32_768>::from_ticks(rtc.get()));
+//! assert_eq!(timer.now().raw_micros(), 0);
+//!
+//! // One second later
+//! rtc.set(100 + 32_768);
+//! assert_eq!(timer.now().raw_micros(), 1_000_000);
+//! ```
+//!
+//! Conversely, [`TimerDelay`] implements `embedded-hal`'s `DelayNs` on top of a Rubble `Timer`,
+//! for drivers that need a delay provider. `fugit::MicrosDurationU32` can be converted to and from
+//! Rubble's `Duration` with `From`/`Into`.
+//!
+//! Note that the timer used by the `LinkLayer` must also be able to schedule an interrupt at the
+//! instant returned in `Cmd::next_update`, which is platform-specific and not covered here.
+//!
+//! [`CounterTimer`]: struct.CounterTimer.html
+//! [`TimerDelay`]: struct.TimerDelay.html
+
+use {
+    crate::time::{Duration, Instant, Timer},
+    core::cell::Cell,
+    embedded_hal::delay::DelayNs,
+    fugit::{MicrosDurationU32, TimerInstantU32},
+};
+
+/// A free-running hardware counter ticking at `FREQ` Hz.
+///
+/// This is implemented for closures returning the current counter value.
+pub trait Counter<const FREQ: u32> {
+    /// Returns the current counter value.
+    ///
+    /// The counter must count up and may wrap around after `u32::MAX` ticks.
+    fn now(&self) -> TimerInstantU32<FREQ>;
+}
+
+impl<F, const FREQ: u32> Counter<FREQ> for F
+where
+    F: Fn() -> TimerInstantU32<FREQ>,
+{
+    fn now(&self) -> TimerInstantU32<FREQ> {
+        self()
+    }
+}
+
+/// Implements Rubble's `Timer` trait on top of a `Counter`.
+///
+/// Counter ticks are converted to microseconds, which is only precise if `FREQ` is 1 MHz or an
+/// integer multiple of it (otherwise, the result is rounded down to the previous microsecond).
+/// Counters slower than 1 MHz (like 32.768 kHz RTCs) work, but fall short of the microsecond
+/// accuracy the Link-Layer expects, so they are only suitable for non-connectable advertising.
+///
+/// Since the counter wraps around at a different point than Rubble's `Instant`, `now` keeps track
+/// of the elapsed ticks itself. It must be called at least once per counter wraparound for this to
+/// work (the Link-Layer does that for all practical counter sizes).
+pub struct CounterTimer<C: Counter<FREQ>, const FREQ: u32> {
+    counter: C,
+    /// Counter value at the last call to `now`.
+    last: Cell<u32>,
+    /// Ticks elapsed since the timer was created.
+    ticks: Cell<u64>,
+}
+
+impl<C: Counter<FREQ>, const FREQ: u32> CounterTimer<C, FREQ> {
+    /// Creates a timer from `counter`.
+    ///
+    /// The first `Instant` returned by the timer corresponds to the counter's value at this point.
+    pub fn new(counter: C) -> Self {
+        let start = counter.now().ticks();
+        Self {
+            counter,
+            last: Cell::new(start),
+            ticks: Cell::new(0),
+        }
+    }
+
+    /// Returns the wrapped counter.
+    pub fn into_inner(self) -> C {
+        self.counter
+    }
+}
+
+impl<C: Counter<FREQ>, const FREQ: u32> Timer for CounterTimer<C, FREQ> {
+    fn now(&self) -> Instant {
+        let now = self.counter.now().ticks();
+        let elapsed = now.wrapping_sub(self.last.get());
+        self.last.set(now);
+        let ticks = self.ticks.get() + u64::from(elapsed);
+        self.ticks.set(ticks);
+
+        let micros = u128::from(ticks) * 1_000_000 / u128::from(FREQ);
+        Instant::from_raw_micros(micros as u32)
+    }
+}
+
+/// Implements `embedded-hal`'s `DelayNs` by busy-waiting on a Rubble `Timer`.
+///
+/// Delays are rounded up to whole microseconds.
+pub struct TimerDelay<T: Timer> {
+    timer: T,
+}
+
+impl<T: Timer> TimerDelay<T> {
+    /// Creates a delay provider using `timer`.
+    pub fn new(timer: T) -> Self {
+        Self { timer }
+    }
+
+    /// Returns the wrapped timer.
+    pub fn into_inner(self) -> T {
+        self.timer
+    }
+}
+
+impl<T: Timer> DelayNs for TimerDelay<T> {
+    fn delay_ns(&mut self, ns: u32) {
+        self.delay_us(ns / 1000 + u32::from(!ns.is_multiple_of(1000)));
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        // Wait in chunks that `Instant::duration_since` can handle
+        let mut left = us;
+        while left > 0 {
+            let chunk = left.min(Instant::MAX_TIME_BETWEEN.as_micros());
+            let start = self.timer.now();
+            while self.timer.now().duration_since(start).as_micros() < chunk {}
+            left -= chunk;
+        }
+    }
+}
+
+impl From<MicrosDurationU32> for Duration {
+    fn from(d: MicrosDurationU32) -> Self {
+        Duration::from_micros(d.ticks())
+    }
+}
+
+impl From<Duration> for MicrosDurationU32 {
+    fn from(d: Duration) -> Self {
+        MicrosDurationU32::from_ticks(d.as_micros())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counter_wraparound() {
+        let value = Cell::new(u32::MAX - 1);
+        let timer = CounterTimer::new(|| TimerInstantU32::<2_000_000>::from_ticks(value.get()));
+        assert_eq!(timer.now().raw_micros(), 0);
+
+        value.set(3);
+        assert_eq!(timer.now().raw_micros(), 2);
+    }
+
+    /// A timer that advances by 1 ms every time it is read.
+    struct SteppingTimer(Cell<u32>);
+
+    impl Timer for SteppingTimer {
+        fn now(&self) -> Instant {
+            let now = self.0.get();
+            self.0.set(now.wrapping_add(1_000));
+            Instant::from_raw_micros(now)
+        }
+    }
+
+    #[test]
+    fn delay_rounds_up() {
+        let mut delay = TimerDelay::new(SteppingTimer(Cell::new(0)));
+        delay.delay_ns(1);
+        assert_eq!(delay.timer.0.get(), 2_000);
+
+        // Must not overflow when rounding up
+        let mut delay = TimerDelay::new(SteppingTimer(Cell::new(0)));
+        delay.delay_ns(u32::MAX);
+        assert_eq!(delay.timer.0.get(), 4_296_000);
+    }
+}
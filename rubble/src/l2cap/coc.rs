@@ -0,0 +1,620 @@
+//! Connection-oriented channels using credit based flow control.
+//!
+//! LE L2CAP supports dynamically allocated channels (CIDs `0x0040`-`0x007F`) which are opened via
+//! the LE signaling channel. Each channel is connected to a service identified by its *Simplified
+//! Protocol/Service Multiplexer* (SPSM). There are 2 modes that can be used:
+//!
+//! * **LE Credit Based Flow Control Mode** (Bluetooth 4.1): One channel is opened per request.
+//! * **Enhanced Credit Based Flow Control Mode** (Bluetooth 5.2): Up to 5 channels are opened with
+//!   a single request, and the MTU and MPS of open channels can be reconfigured. This mode is
+//!   required by the Enhanced Attribute Protocol (EATT).
+//!
+//! In both modes, the receiver grants the sender *credits*. Every PDU (*K-frame*) sent on a
+//! channel consumes one credit, and the sender has to stop when it runs out. Upper-layer SDUs are
+//! split into K-frames of at most *MPS* Bytes, the first of which carries the total SDU length.
+//!
+//! Rubble stores a complete SDU per channel. While the application hasn't taken the SDU out of the
+//! channel, the credit of the final K-frame is withheld, so the peer is throttled automatically.
+//!
+//! Applications that buffer received data elsewhere can take over credit management instead, by
+//! returning `CreditPolicy::Manual` from `CocListener::credit_policy`. Rubble then never grants
+//! credits on its own, and the application grants them via `CreditChannelTx::grant_credits`
+//! whenever it has room for more data.
+//!
+//! The peer chooses how large its K-frames are (up to our MPS), but since L2CAP reassembly of
+//! Link-Layer fragments is not yet implemented, only K-frames fitting in a single data channel PDU
+//! can currently be received. Likewise, `CreditChannelTx::send` only sends single-frame SDUs.
+
+use {
+    super::{signaling::Command, Channel, Header, Sender},
+    crate::{
+        bytes::*,
+        l2cap::{Protocol, ProtocolObj},
+        link::{data::Llid, queue::Producer, MIN_DATA_PAYLOAD_BUF},
+        utils, Error,
+    },
+    heapless::{consts::U128, Vec},
+};
+
+/// Maximum SDU size that can be received on a channel.
+pub const MAX_SDU: u16 = 128;
+
+/// Maximum number of channels that can be open at the same time.
+pub const MAX_CHANNELS: usize = 5;
+
+/// First dynamically allocated LE channel.
+const FIRST_DYNAMIC: u16 = 0x0040;
+/// Last dynamically allocated LE channel.
+const LAST_DYNAMIC: u16 = 0x007F;
+
+/// Largest K-frame information payload (including the SDU length) that fits in a single data
+/// channel PDU.
+const MAX_KFRAME: u16 = MIN_DATA_PAYLOAD_BUF as u16 - Header::SIZE as u16;
+
+/// A Simplified Protocol/Service Multiplexer, identifying the service behind a channel.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Spsm(u16);
+
+impl Spsm {
+    /// The SPSM of the Enhanced Attribute Protocol.
+    pub const EATT: Self = Spsm(0x0027);
+
+    /// The SPSM of the Object Transfer Channel used by the *Object Transfer Service*.
+    pub const OTS: Self = Spsm(0x0025);
+
+    /// Creates an SPSM from its raw value.
+    ///
+    /// Values `0x0001`-`0x007F` are assigned by the Bluetooth SIG, `0x0080`-`0x00FF` can be used
+    /// for custom services.
+    pub fn from_raw(raw: u16) -> Self {
+        Spsm(raw)
+    }
+
+    /// Returns the raw SPSM value.
+    pub fn as_raw(&self) -> u16 {
+        self.0
+    }
+
+    /// Returns whether this is a valid LE SPSM.
+    pub fn is_valid(&self) -> bool {
+        self.0 >= 0x0001 && self.0 <= 0x00FF
+    }
+}
+
+/// Local parameters of a connection-oriented channel.
+#[derive(Debug, Copy, Clone)]
+pub struct CocConfig {
+    /// Largest SDU we are able to receive.
+    ///
+    /// This is limited to `MAX_SDU`.
+    pub mtu: u16,
+
+    /// Largest K-frame payload we are able to receive.
+    pub mps: u16,
+
+    /// Number of K-frames the peer may send before waiting for more credits.
+    ///
+    /// This should be at least the number of K-frames needed to transfer an SDU of `mtu` Bytes.
+    pub credits: u16,
+}
+
+/// Who grants credits to the peer once the initial credits are used up.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CreditPolicy {
+    /// Rubble grants a credit for every received K-frame, except for the last K-frame of an SDU,
+    /// whose credit is granted when the SDU is released.
+    Automatic,
+
+    /// The application grants credits via `CreditChannelTx::grant_credits`.
+    ///
+    /// Rubble still only stores one SDU per channel: K-frames starting a new SDU while the last
+    /// one hasn't been released are dropped (and their credits are lost).
+    Manual,
+}
+
+/// Decides which incoming channel connections to accept.
+pub trait CocListener {
+    /// Called when the peer wants to open channels to `spsm`.
+    ///
+    /// Returns the channel parameters to use, or `None` to refuse the connection.
+    fn accept(&mut self, spsm: Spsm) -> Option<CocConfig>;
+
+    /// Returns the credit policy of accepted channels to `spsm`.
+    ///
+    /// The default implementation uses `CreditPolicy::Automatic`.
+    fn credit_policy(&mut self, spsm: Spsm) -> CreditPolicy {
+        let _ = spsm;
+        CreditPolicy::Automatic
+    }
+}
+
+/// A `CocListener` that refuses all channels.
+#[derive(Debug)]
+pub struct NoChannels;
+
+impl CocListener for NoChannels {
+    fn accept(&mut self, _spsm: Spsm) -> Option<CocConfig> {
+        None
+    }
+}
+
+/// The flow control mode used by a channel.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CreditMode {
+    /// LE Credit Based Flow Control Mode.
+    LeCredit,
+    /// Enhanced Credit Based Flow Control Mode.
+    Enhanced,
+}
+
+/// State of an open connection-oriented channel.
+#[derive(Debug)]
+pub struct CreditChannel {
+    spsm: Spsm,
+    mode: CreditMode,
+    local: Channel,
+    remote: Channel,
+    local_mtu: u16,
+    local_mps: u16,
+    peer_mtu: u16,
+    peer_mps: u16,
+
+    /// Credits we have left for sending K-frames.
+    tx_credits: u16,
+
+    /// Credits the peer has left for sending K-frames to us.
+    rx_credits: u16,
+
+    credit_policy: CreditPolicy,
+
+    /// Length of the SDU currently being received, if any.
+    sdu_len: Option<u16>,
+    sdu: Vec<u8, U128>,
+
+    /// Identifier to use for the next signaling command sent for this channel.
+    identifier: u8,
+}
+
+impl CreditChannel {
+    /// Returns the SPSM the channel is connected to.
+    pub fn spsm(&self) -> Spsm {
+        self.spsm
+    }
+
+    /// Returns the flow control mode of the channel.
+    pub fn mode(&self) -> CreditMode {
+        self.mode
+    }
+
+    /// Returns the local channel identifier (our end of the channel).
+    pub fn local_cid(&self) -> Channel {
+        self.local
+    }
+
+    /// Returns the peer's channel identifier, to which all data is sent.
+    pub fn remote_cid(&self) -> Channel {
+        self.remote
+    }
+
+    /// Returns the largest SDU the peer is able to receive.
+    pub fn peer_mtu(&self) -> u16 {
+        self.peer_mtu
+    }
+
+    /// Returns the largest K-frame payload the peer is able to receive.
+    pub fn peer_mps(&self) -> u16 {
+        self.peer_mps
+    }
+
+    /// Returns the largest SDU we are able to receive.
+    pub fn local_mtu(&self) -> u16 {
+        self.local_mtu
+    }
+
+    /// Returns the number of K-frames that can be sent before the peer has to grant more credits.
+    pub fn tx_credits(&self) -> u16 {
+        self.tx_credits
+    }
+
+    /// Returns the number of K-frames the peer may still send to us.
+    pub fn rx_credits(&self) -> u16 {
+        self.rx_credits
+    }
+
+    /// Returns who grants new credits to the peer.
+    pub fn credit_policy(&self) -> CreditPolicy {
+        self.credit_policy
+    }
+
+    /// Returns the completely received SDU, if there is one.
+    pub fn sdu(&self) -> Option<&[u8]> {
+        match self.sdu_len {
+            Some(len) if usize::from(len) == self.sdu.len() => Some(&self.sdu),
+            _ => None,
+        }
+    }
+
+    /// Adds credits granted by the peer.
+    pub(super) fn add_tx_credits(&mut self, credits: u16) {
+        self.tx_credits = self.tx_credits.checked_add(credits).unwrap_or_else(|| {
+            warn!("peer overflowed credits of {:?}", self.local);
+            u16::MAX
+        });
+    }
+
+    /// Updates the peer's MTU and MPS after a reconfiguration.
+    pub(super) fn reconfigure(&mut self, mtu: u16, mps: u16) {
+        self.peer_mtu = mtu;
+        self.peer_mps = mps;
+    }
+
+    fn next_identifier(&mut self) -> u8 {
+        // Identifier 0 is not allowed
+        self.identifier = self.identifier.wrapping_add(1).max(1);
+        self.identifier
+    }
+
+    /// Creates a `Sender` that sends single-frame SDUs of up to `pdu` Bytes on this channel.
+    ///
+    /// Returns `None` if the peer hasn't granted any credits, or if there's not enough space in
+    /// `tx`.
+    pub(super) fn sender<'a>(
+        &'a mut self,
+        pdu: u8,
+        tx: &'a mut dyn Producer,
+    ) -> Option<Sender<'a>> {
+        debug_assert!(u16::from(pdu) + 2 <= MAX_KFRAME);
+        if self.tx_credits == 0 || tx.free_space() < Header::SIZE + 2 + pdu {
+            return None;
+        }
+
+        Some(Sender {
+            pdu,
+            tx,
+            channel: self.remote,
+            credits: Some(&mut self.tx_credits),
+            staging: None,
+        })
+    }
+
+    /// Discards the received SDU and, with automatic credits, grants the peer a credit for the
+    /// next one.
+    pub(super) fn release(&mut self, tx: &mut dyn Producer) -> Result<(), Error> {
+        if self.sdu().is_none() {
+            return Ok(());
+        }
+
+        if self.credit_policy == CreditPolicy::Automatic {
+            let mut sender = signaling_sender(tx)?;
+            self.grant(1, &mut sender)?;
+        }
+        self.sdu_len = None;
+        utils::truncate(&mut self.sdu, 0);
+        Ok(())
+    }
+
+    /// Grants the peer a credit for a received K-frame, unless the application manages credits.
+    fn auto_grant(&mut self, sender: &mut Sender<'_>) -> Result<(), Error> {
+        match self.credit_policy {
+            CreditPolicy::Automatic => self.grant(1, sender),
+            CreditPolicy::Manual => Ok(()),
+        }
+    }
+
+    /// Sends `credits` new credits to the peer.
+    fn grant(&mut self, credits: u16, sender: &mut Sender<'_>) -> Result<(), Error> {
+        let identifier = self.next_identifier();
+        sender.send(Command::flow_control_credit(
+            identifier, self.local, credits,
+        ))?;
+        self.rx_credits += credits;
+        Ok(())
+    }
+}
+
+impl ProtocolObj for CreditChannel {
+    /// Processes a received K-frame.
+    ///
+    /// The `responder` is connected to the LE signaling channel and is used to grant new credits.
+    fn process_message(&mut self, message: &[u8], mut responder: Sender<'_>) -> Result<(), Error> {
+        if self.rx_credits == 0 {
+            warn!(
+                "{:?}: K-frame received without credits, dropping",
+                self.local
+            );
+            return Ok(());
+        }
+        if message.len() > usize::from(self.local_mps) {
+            warn!("{:?}: K-frame exceeds MPS, dropping", self.local);
+            return Ok(());
+        }
+        self.rx_credits -= 1;
+
+        let mut bytes = ByteReader::new(message);
+        match self.sdu_len {
+            Some(len) if usize::from(len) == self.sdu.len() => {
+                // This can only happen if the peer sent more frames than we had credits for
+                warn!("{:?}: previous SDU not yet consumed, dropping", self.local);
+                return Ok(());
+            }
+            Some(_) => {}
+            None => {
+                let len = bytes.read_u16_le()?;
+                if len > self.local_mtu {
+                    warn!(
+                        "{:?}: SDU exceeds MTU ({} > {})",
+                        self.local, len, self.local_mtu
+                    );
+                    return self.auto_grant(&mut responder);
+                }
+                self.sdu_len = Some(len);
+                utils::truncate(&mut self.sdu, 0);
+            }
+        }
+
+        let data = bytes.read_rest();
+        let len = self.sdu_len.unwrap();
+        if self.sdu.len() + data.len() > usize::from(len)
+            || self.sdu.extend_from_slice(data).is_err()
+        {
+            warn!("{:?}: SDU longer than announced, dropping", self.local);
+            self.sdu_len = None;
+            return self.auto_grant(&mut responder);
+        }
+
+        if self.sdu().is_none() {
+            // More K-frames to come, give the credit back right away
+            self.auto_grant(&mut responder)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Protocol for CreditChannel {
+    /// Only `L2CAP_FLOW_CONTROL_CREDIT_IND` commands are sent in response to K-frames.
+    const RSP_PDU_SIZE: u8 = Command::FLOW_CONTROL_CREDIT_SIZE;
+}
+
+/// Reason for refusing to open a channel.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) enum Refused {
+    /// No more channels can be allocated.
+    NoResources,
+    /// The peer's CID is not in the dynamic range.
+    InvalidSourceCid,
+    /// The peer's CID is already in use by another channel.
+    SourceCidAllocated,
+}
+
+/// The set of open connection-oriented channels.
+#[derive(Debug)]
+pub struct ChannelTable {
+    channels: [Option<CreditChannel>; MAX_CHANNELS],
+}
+
+impl ChannelTable {
+    /// Creates an empty channel table.
+    pub fn new() -> Self {
+        Self {
+            channels: [None, None, None, None, None],
+        }
+    }
+
+    /// Returns the channel with local CID `local`.
+    pub fn get_mut(&mut self, local: Channel) -> Option<&mut CreditChannel> {
+        self.channels
+            .iter_mut()
+            .filter_map(Option::as_mut)
+            .find(|ch| ch.local == local)
+    }
+
+    /// Returns the channel whose peer CID is `remote`.
+    pub(super) fn by_remote_mut(&mut self, remote: Channel) -> Option<&mut CreditChannel> {
+        self.channels
+            .iter_mut()
+            .filter_map(Option::as_mut)
+            .find(|ch| ch.remote == remote)
+    }
+
+    /// Returns an iterator over all open channels.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = &'a CreditChannel> + 'a {
+        self.channels.iter().filter_map(Option::as_ref)
+    }
+
+    pub(super) fn iter_mut<'a>(&'a mut self) -> impl Iterator<Item = &'a mut CreditChannel> + 'a {
+        self.channels.iter_mut().filter_map(Option::as_mut)
+    }
+
+    /// Returns whether another channel can be opened.
+    pub fn has_free_slot(&self) -> bool {
+        self.channels.iter().any(Option::is_none)
+    }
+
+    /// Opens a new channel to the peer's `remote` CID and returns the allocated local CID.
+    ///
+    /// `peer` contains the parameters sent by the peer, `config` our own.
+    pub(super) fn open(
+        &mut self,
+        spsm: Spsm,
+        mode: CreditMode,
+        remote: Channel,
+        peer: &CocConfig,
+        config: &CocConfig,
+        credit_policy: CreditPolicy,
+    ) -> Result<Channel, Refused> {
+        if remote.as_raw() < FIRST_DYNAMIC || remote.as_raw() > LAST_DYNAMIC {
+            return Err(Refused::InvalidSourceCid);
+        }
+        if self.iter().any(|ch| ch.remote == remote) {
+            return Err(Refused::SourceCidAllocated);
+        }
+
+        let local = (FIRST_DYNAMIC..=LAST_DYNAMIC)
+            .map(Channel)
+            .find(|cid| self.iter().all(|ch| ch.local != *cid))
+            .ok_or(Refused::NoResources)?;
+        let slot = self
+            .channels
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(Refused::NoResources)?;
+
+        *slot = Some(CreditChannel {
+            spsm,
+            mode,
+            local,
+            remote,
+            local_mtu: config.mtu,
+            local_mps: config.mps,
+            peer_mtu: peer.mtu,
+            peer_mps: peer.mps,
+            tx_credits: peer.credits,
+            rx_credits: config.credits,
+            credit_policy,
+            sdu_len: None,
+            sdu: Vec::new(),
+            identifier: 0,
+        });
+        Ok(local)
+    }
+
+    /// Closes the channel with local CID `local` and remote CID `remote`.
+    ///
+    /// Returns `false` if there is no such channel.
+    pub(super) fn close(&mut self, local: Channel, remote: Channel) -> bool {
+        for slot in &mut self.channels {
+            if let Some(ch) = slot {
+                if ch.local == local && ch.remote == remote {
+                    *slot = None;
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+impl Default for ChannelTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A `CreditChannel` with the ability to transmit packets.
+///
+/// Obtained via `L2CAPStateTx::credit_channel`.
+pub struct CreditChannelTx<'a> {
+    channel: &'a mut CreditChannel,
+    tx: &'a mut dyn Producer,
+}
+
+impl<'a> CreditChannelTx<'a> {
+    pub(super) fn new(channel: &'a mut CreditChannel, tx: &'a mut dyn Producer) -> Self {
+        Self { channel, tx }
+    }
+
+    /// Returns the channel state.
+    pub fn channel(&self) -> &CreditChannel {
+        self.channel
+    }
+
+    /// Returns the completely received SDU, if there is one.
+    pub fn sdu(&self) -> Option<&[u8]> {
+        self.channel.sdu()
+    }
+
+    /// Discards the received SDU and allows the peer to send the next one.
+    ///
+    /// Returns `Error::Eof` if the credit could not be returned because the TX queue is full. In
+    /// that case, the SDU is kept and this method should be called again later.
+    ///
+    /// With `CreditPolicy::Manual`, no credit is returned and this never fails.
+    pub fn release(&mut self) -> Result<(), Error> {
+        self.channel.release(&mut *self.tx)
+    }
+
+    /// Grants the peer `credits` more K-frames.
+    ///
+    /// This is meant for channels using `CreditPolicy::Manual`, but also works with automatic
+    /// credits. Returns `Error::InvalidValue` if `credits` is 0 or the peer would end up with more
+    /// than 65535 credits, and `Error::Eof` if the TX queue is full.
+    pub fn grant_credits(&mut self, credits: u16) -> Result<(), Error> {
+        if credits == 0 || self.channel.rx_credits.checked_add(credits).is_none() {
+            return Err(Error::InvalidValue);
+        }
+
+        let mut sender = signaling_sender(&mut *self.tx)?;
+        self.channel.grant(credits, &mut sender)
+    }
+
+    /// Returns the length of the largest SDU `send` accepts on this channel.
+    pub fn max_send_len(&self) -> usize {
+        let frame = self.channel.peer_mps.min(MAX_KFRAME).saturating_sub(2);
+        usize::from(frame.min(self.channel.peer_mtu))
+    }
+
+    /// Sends an SDU to the peer.
+    ///
+    /// The SDU must fit in a single K-frame: It may not be larger than the peer's MPS minus 2
+    /// Bytes, or 21 Bytes, whichever is smaller. Otherwise, `Error::InvalidLength` is returned.
+    ///
+    /// Returns `Error::Eof` if the peer hasn't granted any credits, or if the TX queue is full.
+    pub fn send(&mut self, sdu: &[u8]) -> Result<(), Error> {
+        let frame_len = sdu.len() + 2;
+        if sdu.len() > usize::from(self.channel.peer_mtu)
+            || frame_len > usize::from(self.channel.peer_mps)
+            || frame_len > usize::from(MAX_KFRAME)
+        {
+            return Err(Error::InvalidLength);
+        }
+        if self.channel.tx_credits == 0 {
+            return Err(Error::Eof);
+        }
+
+        let remote = self.channel.remote;
+        self.tx
+            .produce_dyn(Header::SIZE + frame_len as u8, &mut |writer| {
+                Header {
+                    length: frame_len as u16,
+                    channel: remote,
+                }
+                .to_bytes(writer)?;
+                writer.write_u16_le(sdu.len() as u16)?;
+                writer.write_slice(sdu)?;
+                Ok(Llid::DataStart)
+            })?;
+        self.channel.tx_credits -= 1;
+        Ok(())
+    }
+}
+
+/// Creates a `Sender` for the LE signaling channel that can fit a credit indication.
+fn signaling_sender(tx: &mut dyn Producer) -> Result<Sender<'_>, Error> {
+    let pdu = Command::FLOW_CONTROL_CREDIT_SIZE;
+    if tx.free_space() < pdu + Header::SIZE {
+        return Err(Error::Eof);
+    }
+
+    Ok(Sender {
+        pdu,
+        tx,
+        channel: Channel::LE_SIGNALING,
+        credits: None,
+        staging: None,
+    })
+}
+
+/// Clamps a local channel configuration to what the mode requires and Rubble supports.
+pub(super) fn clamp_config(config: CocConfig, mode: CreditMode) -> CocConfig {
+    let min = match mode {
+        CreditMode::LeCredit => 23,
+        CreditMode::Enhanced => 64,
+    };
+
+    CocConfig {
+        mtu: config.mtu.max(min).min(MAX_SDU),
+        mps: config.mps.max(min),
+        credits: config.credits.max(1),
+    }
+}
@@ -0,0 +1,104 @@
+//! Enhanced ATT (EATT) bearers.
+//!
+//! Bluetooth 5.2 allows running ATT over connection-oriented channels using the *Enhanced Credit
+//! Based Flow Control Mode*, in addition to the fixed ATT channel `0x0004`. Every such channel is
+//! an independent *bearer* with its own MTU and its own request/response transaction, so a slow
+//! procedure on one bearer doesn't hold up others (or notifications sent on them).
+//!
+//! EATT bearers are opened by the client on SPSM `0x0027`. To accept them, configure the
+//! `BleChannelMap` with an `EattListener`, and call `L2CAPStateTx::process_eatt` when the
+//! Link-Layer has received data. `L2CAPStateTx::eatt` can be used to send notifications on a
+//! specific bearer.
+//!
+//! Every bearer shares the `AttributeServer` (and thus the attribute database) of the fixed ATT
+//! channel.
+//!
+//! Since L2CAP fragmentation is not yet implemented, ATT PDUs sent on a bearer are limited to
+//! `EATT_PDU_SIZE` Bytes, which is a bit less than over the fixed channel.
+
+use {
+    super::{
+        coc::{ChannelTable, CocConfig, CocListener, Spsm, MAX_SDU},
+        signaling::Command,
+        Header, ProtocolObj,
+    },
+    crate::{
+        att::{AttributeProvider, AttributeServer},
+        link::{queue::Producer, MIN_DATA_PAYLOAD_BUF},
+        Error,
+    },
+};
+
+/// Size of ATT PDUs sent on EATT bearers.
+///
+/// This is the space left in a data channel PDU after the L2CAP header and the SDU length.
+pub const EATT_PDU_SIZE: u8 = MIN_DATA_PAYLOAD_BUF as u8 - 4 - 2;
+
+/// A `CocListener` accepting EATT bearers, and refusing connections to all other SPSMs.
+#[derive(Debug)]
+pub struct EattListener {
+    credits: u16,
+}
+
+impl EattListener {
+    /// Creates a listener that grants `credits` initial credits to every bearer.
+    ///
+    /// Since incoming SDUs are buffered in the bearer until their request has been processed,
+    /// there's no use in granting more credits than are needed for a single SDU.
+    pub fn new(credits: u16) -> Self {
+        Self { credits }
+    }
+}
+
+impl CocListener for EattListener {
+    fn accept(&mut self, spsm: Spsm) -> Option<CocConfig> {
+        if spsm == Spsm::EATT {
+            Some(CocConfig {
+                mtu: MAX_SDU,
+                mps: 64,
+                credits: self.credits,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Processes the received ATT PDUs of all EATT bearers in `channels`.
+///
+/// Bearers whose response can't be sent right now (because the peer hasn't granted credits, or
+/// the request is waiting for authorization) keep their PDU until the next call.
+pub(super) fn process<A: AttributeProvider>(
+    server: &mut AttributeServer<A>,
+    channels: &mut ChannelTable,
+    tx: &mut dyn Producer,
+) -> Result<(), Error> {
+    for bearer in channels.iter_mut().filter(|ch| ch.spsm() == Spsm::EATT) {
+        let mut buf = [0; MAX_SDU as usize];
+        let pdu = match bearer.sdu() {
+            Some(sdu) => {
+                buf[..sdu.len()].copy_from_slice(sdu);
+                &buf[..sdu.len()]
+            }
+            None => continue,
+        };
+
+        // Make sure the credit for the next request can be returned after responding
+        let credit = Header::SIZE + Command::FLOW_CONTROL_CREDIT_SIZE;
+        if tx.free_space() < Header::SIZE + 2 + EATT_PDU_SIZE + credit {
+            return Err(Error::Eof);
+        }
+        let sender = match bearer.sender(EATT_PDU_SIZE, &mut *tx) {
+            Some(sender) => sender,
+            None => continue,
+        };
+
+        server.process_message(pdu, sender)?;
+        if server.is_deferred_on(bearer.remote_cid()) {
+            continue;
+        }
+        bearer.release(&mut *tx)?;
+    }
+
+    Ok(())
+}
@@ -18,13 +18,27 @@
 //! Bluetooth SIG or allocated dynamically for use with the Service Discovery Protocol (SDP). The
 //! preallocated numbers are hosted online [here][l2c].
 //!
+//! # Fragmentation
+//!
+//! L2CAP messages can be longer than a data channel PDU. They are then split into a start fragment
+//! and continuation fragments. Handling such messages needs buffers to reassemble incoming and to
+//! stage outgoing messages in, which are passed to `L2CAPState::with_buffers` (or allocated as
+//! an `L2CAPBuffers` of the desired size). Without them, only messages fitting into a single data
+//! channel PDU can be exchanged, which limits the `ATT_MTU` to its default of 23 Bytes.
+//!
 //! [`Channel`]: struct.Channel.html
 //! [l2c]: https://www.bluetooth.com/specifications/assigned-numbers/logical-link-control
 
+pub mod coc;
+pub mod eatt;
 mod signaling;
 
 use {
-    self::signaling::SignalingState,
+    self::{
+        coc::{CocListener, CreditChannelTx, NoChannels, Spsm},
+        eatt::EATT_PDU_SIZE,
+        signaling::SignalingState,
+    },
     crate::{
         att::{self, AttributeProvider, AttributeServer, NoAttributes},
         bytes::*,
@@ -33,12 +47,12 @@ use {
             queue::{Consume, Producer},
             MIN_DATA_PAYLOAD_BUF,
         },
-        security::{NoSecurity, SecurityLevel, SecurityManager},
+        security::{NoSecurity, SecurityLevel, SecurityManager, SecurityManagerTx},
         utils::HexSlice,
         Error,
     },
     core::{
-        fmt,
+        cmp, fmt,
         ops::{Deref, DerefMut},
     },
 };
@@ -99,10 +113,7 @@ impl Channel {
     ///
     /// L2CAP PDUs addressed to connectionless channels are called *G-frames*.
     pub fn is_connectionless(&self) -> bool {
-        match self.0 {
-            0x0002 | 0x0001 | 0x0005 => true,
-            _ => false,
-        }
+        matches!(self.0, 0x0002 | 0x0001 | 0x0005)
     }
 }
 
@@ -129,11 +140,33 @@ pub trait ChannelMapper {
     /// The attribute provider used by the ATT server.
     type AttributeProvider: AttributeProvider;
 
+    /// The security level supported by the Security Manager.
+    type SecurityLevel: SecurityLevel;
+
     /// Look up what's connected to `channel` (eg. the `Protocol` to which to forward).
     fn lookup(&mut self, channel: Channel) -> Option<ChannelData<'_, dyn ProtocolObj + '_>>;
 
     /// Returns information about the Attribute Protocol on channel `0x0004`.
     fn att(&mut self) -> ChannelData<'_, AttributeServer<Self::AttributeProvider>>;
+
+    /// Returns information about the Security Manager on channel `0x0006`.
+    fn security(&mut self) -> ChannelData<'_, SecurityManager<Self::SecurityLevel>>;
+
+    /// Returns the open connection-oriented channels, if the mapper supports them.
+    fn credit_channels(&mut self) -> Option<&mut coc::ChannelTable> {
+        None
+    }
+
+    /// Returns the ATT server together with the open connection-oriented channels, for serving ATT
+    /// on EATT bearers.
+    fn eatt(
+        &mut self,
+    ) -> Option<(
+        &mut AttributeServer<Self::AttributeProvider>,
+        &mut coc::ChannelTable,
+    )> {
+        None
+    }
 }
 
 /// Data associated with a connected L2CAP channel.
@@ -218,17 +251,27 @@ impl<'a, P: ?Sized> ChannelData<'a, P> {
     }
 }
 
-/// A fixed BLE channel map that provides only the required channel endpoints and does not allow
-/// dynamic channels.
+impl<'a, P: ?Sized> ChannelData<'a, P> {
+    /// Overrides the PDU size of the protocol, eg. with a negotiated `ATT_MTU`.
+    fn with_pdu_size(mut self, pdu: u8) -> Self {
+        self.pdu = pdu;
+        self
+    }
+}
+
+/// A BLE channel map that provides the required channel endpoints and optionally dynamic
+/// connection-oriented channels.
 ///
-/// The channels are mapped as follows (no other channels are supported):
+/// The channels are mapped as follows:
 ///
 /// * `0x0004`: Attribute protocol (ATT).
 /// * `0x0005`: LE L2CAP signaling channel.
 /// * `0x0006`: LE Security Manager protocol.
-pub struct BleChannelMap<A: AttributeProvider, S: SecurityLevel> {
+/// * `0x0040`-`0x007F`: Credit based channels opened by the peer and accepted by the
+///   `CocListener` `L` (by default, all such requests are refused).
+pub struct BleChannelMap<A: AttributeProvider, S: SecurityLevel, L: CocListener = NoChannels> {
     att: AttributeServer<A>,
-    signaling: SignalingState,
+    signaling: SignalingState<L>,
     sm: SecurityManager<S>,
 }
 
@@ -241,7 +284,7 @@ impl BleChannelMap<NoAttributes, NoSecurity> {
     pub fn empty() -> Self {
         Self {
             att: AttributeServer::new(NoAttributes),
-            signaling: SignalingState::new(),
+            signaling: SignalingState::new(NoChannels),
             sm: SecurityManager::no_security(),
         }
     }
@@ -251,26 +294,66 @@ impl<A: AttributeProvider> BleChannelMap<A, NoSecurity> {
     pub fn with_attributes(att: A) -> Self {
         Self {
             att: AttributeServer::new(att),
-            signaling: SignalingState::new(),
+            signaling: SignalingState::new(NoChannels),
             sm: SecurityManager::no_security(),
         }
     }
 }
 
-impl<A: AttributeProvider, S: SecurityLevel> ChannelMapper for BleChannelMap<A, S> {
+impl<A: AttributeProvider, S: SecurityLevel, L: CocListener> BleChannelMap<A, S, L> {
+    /// Accepts incoming connection-oriented channels using `listener`.
+    pub fn with_coc_listener<L2: CocListener>(self, listener: L2) -> BleChannelMap<A, S, L2> {
+        BleChannelMap {
+            att: self.att,
+            signaling: SignalingState::new(listener),
+            sm: self.sm,
+        }
+    }
+}
+
+impl<A: AttributeProvider, S: SecurityLevel, L: CocListener> ChannelMapper
+    for BleChannelMap<A, S, L>
+{
     type AttributeProvider = A;
+    type SecurityLevel = S;
 
     fn lookup(&mut self, channel: Channel) -> Option<ChannelData<'_, dyn ProtocolObj + '_>> {
         match channel {
-            Channel::ATT => Some(ChannelData::new_dyn(channel, &mut self.att)),
+            Channel::ATT => {
+                let mtu = self.att.mtu();
+                Some(ChannelData::new_dyn(channel, &mut self.att).with_pdu_size(mtu))
+            }
             Channel::LE_SIGNALING => Some(ChannelData::new_dyn(channel, &mut self.signaling)),
             Channel::LE_SECURITY_MANAGER => Some(ChannelData::new_dyn(channel, &mut self.sm)),
-            _ => None,
+            // Credits for K-frames are returned on the signaling channel
+            _ => self
+                .signaling
+                .channels()
+                .get_mut(channel)
+                .map(|ch| ChannelData::new_dyn(Channel::LE_SIGNALING, ch)),
         }
     }
 
     fn att(&mut self) -> ChannelData<'_, AttributeServer<Self::AttributeProvider>> {
-        ChannelData::new(Channel::ATT, &mut self.att)
+        let mtu = self.att.mtu();
+        ChannelData::new(Channel::ATT, &mut self.att).with_pdu_size(mtu)
+    }
+
+    fn security(&mut self) -> ChannelData<'_, SecurityManager<Self::SecurityLevel>> {
+        ChannelData::new(Channel::LE_SECURITY_MANAGER, &mut self.sm)
+    }
+
+    fn credit_channels(&mut self) -> Option<&mut coc::ChannelTable> {
+        Some(self.signaling.channels())
+    }
+
+    fn eatt(
+        &mut self,
+    ) -> Option<(
+        &mut AttributeServer<Self::AttributeProvider>,
+        &mut coc::ChannelTable,
+    )> {
+        Some((&mut self.att, self.signaling.channels()))
     }
 }
 
@@ -333,32 +416,149 @@ impl ToBytes for Header {
     }
 }
 
-struct Message<P> {
-    header: Header,
-    payload: P,
+/// Maximum length of a fragment of an L2CAP message.
+///
+/// Fragments are sent as single data channel PDUs. This has to be the maximum payload length
+/// negotiated with the Data Length Update Procedure, once that is supported.
+const FRAGMENT_LEN: usize = MIN_DATA_PAYLOAD_BUF;
+
+/// Largest `ATT_MTU` supported with fragmentation, so that a PDU fits into the largest data
+/// channel PDU allowed by the Data Length Extension.
+const MAX_ATT_MTU: usize = 251 - Header::SIZE as usize;
+
+/// Reassembly state of a fragmented incoming message.
+#[derive(Debug, Copy, Clone)]
+enum RxState {
+    /// No fragmented message is being received.
+    Idle,
+
+    /// Fragments of a message to `channel` are being collected.
+    Receiving {
+        channel: Channel,
+        len: usize,
+        received: usize,
+    },
+
+    /// The message is complete, but couldn't be dispatched yet.
+    ///
+    /// The last fragment stays in the RX queue in that case, so it will be passed to
+    /// `process_cont` again.
+    Complete { channel: Channel, len: usize },
+
+    /// A message that doesn't fit into the buffer is skipped.
+    Discarding { remaining: usize },
+}
+
+/// Buffer for reassembling incoming messages.
+#[derive(Debug)]
+struct Reassembly {
+    buf: &'static mut [u8],
+    state: RxState,
 }
 
-impl<'a, P: FromBytes<'a>> FromBytes<'a> for Message<P> {
-    fn from_bytes(bytes: &mut ByteReader<'a>) -> Result<Self, Error> {
-        let header = Header::from_bytes(bytes)?;
-        assert_eq!(
-            header.length as usize,
-            bytes.bytes_left(),
-            "L2CAP reassembly not yet implemented"
-        );
+/// Buffer for outgoing messages that are sent in several fragments.
+#[derive(Debug)]
+struct Fragmenter {
+    buf: &'static mut [u8],
+    len: usize,
+    sent: usize,
+}
 
-        Ok(Self {
-            header,
-            payload: P::from_bytes(bytes)?,
-        })
+impl Fragmenter {
+    /// Returns whether there are fragments left to send.
+    fn is_busy(&self) -> bool {
+        self.sent < self.len
+    }
+
+    /// Starts sending the first `len` Bytes of the buffer.
+    fn start(&mut self, len: usize, tx: &mut dyn Producer) {
+        self.len = len;
+        self.sent = 0;
+        self.flush(tx);
+    }
+
+    /// Enqueues as many of the remaining fragments as `tx` has room for.
+    ///
+    /// Returns whether the message was sent completely.
+    fn flush(&mut self, tx: &mut dyn Producer) -> bool {
+        while self.is_busy() {
+            let start = self.sent;
+            let len = cmp::min(self.len - start, FRAGMENT_LEN);
+            let fragment = &self.buf[start..start + len];
+            let llid = if start == 0 {
+                Llid::DataStart
+            } else {
+                Llid::DataCont
+            };
+
+            let result = tx.produce_dyn(len as u8, &mut |writer| {
+                writer.write_slice(fragment)?;
+                Ok(llid)
+            });
+            if result.is_err() {
+                return false;
+            }
+            self.sent += len;
+        }
+        true
     }
 }
 
-impl<P: ToBytes> ToBytes for Message<P> {
-    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
-        self.header.to_bytes(writer)?;
-        self.payload.to_bytes(writer)?;
-        Ok(())
+/// Copy of an ATT request received on the fixed ATT channel that is waiting for authorization.
+///
+/// Keeping it here instead of in the RX queue lets messages to other channels through meanwhile.
+/// Only requests fitting into the default `ATT_MTU` are copied, which covers all requests but long
+/// writes.
+#[derive(Debug)]
+struct DeferredAtt {
+    buf: [u8; att::DEFAULT_ATT_MTU as usize],
+    len: usize,
+}
+
+impl DeferredAtt {
+    /// Keeps a copy of `pdu`. Returns `false` if it doesn't fit.
+    fn hold(&mut self, pdu: &[u8]) -> bool {
+        match self.buf.get_mut(..pdu.len()) {
+            Some(buf) => {
+                buf.copy_from_slice(pdu);
+                self.len = pdu.len();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn is_held(&self) -> bool {
+        self.len != 0
+    }
+
+    fn pdu(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+/// Storage for the reassembly (`RX`) and staging (`TX`) buffers of an `L2CAPState`.
+///
+/// Each size is 4 Bytes more than the largest message to receive or send, eg. `ATT_MTU + 4`. This
+/// is meant to be put into a `static`, and passed to `L2CAPState::with_buffer_storage`.
+pub struct L2CAPBuffers<const RX: usize, const TX: usize> {
+    rx: [u8; RX],
+    tx: [u8; TX],
+}
+
+impl<const RX: usize, const TX: usize> L2CAPBuffers<RX, TX> {
+    /// Creates zeroed buffers.
+    pub const fn new() -> Self {
+        Self {
+            rx: [0; RX],
+            tx: [0; TX],
+        }
+    }
+}
+
+impl<const RX: usize, const TX: usize> Default for L2CAPBuffers<RX, TX> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -366,18 +566,93 @@ impl<P: ToBytes> ToBytes for Message<P> {
 #[derive(Debug)]
 pub struct L2CAPState<M: ChannelMapper> {
     mapper: M,
+    rx: Reassembly,
+    tx: Fragmenter,
+    deferred: DeferredAtt,
 }
 
 impl<M: ChannelMapper> L2CAPState<M> {
     /// Creates a new L2CAP state using the given channel configuration.
+    ///
+    /// Only messages fitting into a single data channel PDU are supported. Fragmented messages
+    /// sent by the peer are dropped.
     pub fn new(mapper: M) -> Self {
-        Self { mapper }
+        Self::with_buffers(mapper, &mut [], &mut [])
+    }
+
+    /// Creates an L2CAP state that reassembles incoming messages in `rx_buf` and sends outgoing
+    /// messages longer than a data channel PDU from `tx_buf`.
+    ///
+    /// Each buffer must be able to hold a complete L2CAP message, which is 4 Bytes longer than the
+    /// message of the protocol. The ATT server offers the largest `ATT_MTU` that fits into both
+    /// buffers (but at most 247 Bytes), which the client can then select via an *Exchange MTU
+    /// Request*.
+    ///
+    /// Outgoing messages are split into fragments, which are put into the TX queue as it has room
+    /// for them. If the TX queue fills up before all fragments are enqueued, the remaining ones
+    /// are sent by the next call to an `L2CAPStateTx` method, or by `L2CAPStateTx::flush`.
+    pub fn with_buffers(
+        mut mapper: M,
+        rx_buf: &'static mut [u8],
+        tx_buf: &'static mut [u8],
+    ) -> Self {
+        let buf_mtu = cmp::min(rx_buf.len(), tx_buf.len()).saturating_sub(Header::SIZE.into());
+        let mtu = cmp::min(buf_mtu, MAX_ATT_MTU);
+        if mtu > usize::from(att::DEFAULT_ATT_MTU) {
+            mapper.att().into_protocol().set_max_mtu(mtu as u8);
+        }
+
+        Self {
+            mapper,
+            rx: Reassembly {
+                buf: rx_buf,
+                state: RxState::Idle,
+            },
+            tx: Fragmenter {
+                buf: tx_buf,
+                len: 0,
+                sent: 0,
+            },
+            deferred: DeferredAtt {
+                buf: [0; att::DEFAULT_ATT_MTU as usize],
+                len: 0,
+            },
+        }
+    }
+
+    /// Creates an L2CAP state that uses the buffers in `storage`, like `with_buffers`.
+    pub fn with_buffer_storage<const RX: usize, const TX: usize>(
+        mapper: M,
+        storage: &'static mut L2CAPBuffers<RX, TX>,
+    ) -> Self {
+        Self::with_buffers(mapper, &mut storage.rx, &mut storage.tx)
     }
 
     /// Gives this instance the ability to transmit packets.
     pub fn tx<'a, P: Producer>(&'a mut self, tx: &'a mut P) -> L2CAPStateTx<'a, M, P> {
         L2CAPStateTx { l2cap: self, tx }
     }
+
+    /// Returns the ATT server, eg. to update its view of the link security.
+    /// Returns whether an ATT request is waiting for authorization, and has to be processed again
+    /// by `L2CAPStateTx::process_deferred`.
+    pub fn has_deferred(&mut self) -> bool {
+        self.deferred.is_held()
+            && self
+                .mapper
+                .att()
+                .into_protocol()
+                .is_deferred_on(Channel::ATT)
+    }
+
+    pub fn att_server(&mut self) -> &mut AttributeServer<M::AttributeProvider> {
+        self.mapper.att().into_protocol()
+    }
+
+    /// Returns the Security Manager, eg. to fetch pairing events.
+    pub fn security_manager(&mut self) -> &mut SecurityManager<M::SecurityLevel> {
+        self.mapper.security().into_protocol()
+    }
 }
 
 /// Provides a way to send a L2CAP message with preallocated storage.
@@ -393,17 +668,43 @@ pub struct Sender<'a> {
 
     /// Channel to which the response will be addressed.
     channel: Channel,
+
+    /// Credits of the connection-oriented channel the message is sent on.
+    ///
+    /// If this is set, messages are sent as single-frame SDUs (*K-frames*), each consuming a
+    /// credit.
+    credits: Option<&'a mut u16>,
+
+    /// Buffer for messages that may not fit into a single data channel PDU.
+    ///
+    /// Only set if the protocol's PDU size requires fragmentation.
+    staging: Option<&'a mut Fragmenter>,
 }
 
 impl<'a> Sender<'a> {
     /// Creates a `Sender` from a `Producer`, ensuring that sufficient free space is available to
     /// fit a PDU described by `chdata`.
     ///
-    /// If there is not enough space in `tx`, returns `None`.
-    fn new<T: ?Sized>(chdata: &ChannelData<'_, T>, tx: &'a mut dyn Producer) -> Option<Self> {
+    /// If there is not enough space in `tx`, or the fragments of the last message haven't all been
+    /// enqueued yet, returns `None`.
+    fn new<T: ?Sized>(
+        chdata: &ChannelData<'_, T>,
+        tx: &'a mut dyn Producer,
+        staging: &'a mut Fragmenter,
+    ) -> Option<Self> {
+        // Fragments of different messages must not be interleaved
+        if !staging.flush(tx) {
+            return None;
+        }
+
         let free = tx.free_space();
         let needed = chdata.pdu_size() + Header::SIZE;
-        if free < needed {
+        let fragmented = usize::from(needed) > FRAGMENT_LEN;
+        if fragmented {
+            if staging.buf.len() < needed.into() || free == 0 {
+                return None;
+            }
+        } else if free < needed {
             debug!("{} free bytes, need {}", free, needed);
             return None;
         }
@@ -414,9 +715,21 @@ impl<'a> Sender<'a> {
             pdu,
             tx,
             channel: resp_channel,
+            credits: None,
+            staging: if fragmented { Some(staging) } else { None },
         })
     }
 
+    /// Returns the maximum size of a protocol PDU that can be sent, eg. the `ATT_MTU`.
+    pub fn pdu_size(&self) -> u8 {
+        self.pdu
+    }
+
+    /// Returns the channel the message will be sent to.
+    pub(crate) fn channel(&self) -> Channel {
+        self.channel
+    }
+
     /// Enqueues an L2CAP message to be sent over the data connection.
     ///
     /// L2CAP header (including the destination endpoint's channel) and the data channel PDU header
@@ -441,17 +754,49 @@ impl<'a> Sender<'a> {
     where
         E: From<Error>,
     {
-        // FIXME automatic fragmentation is not implemented
+        let channel = self.channel;
+        let pdu = self.pdu;
+        let sdu_header = if self.credits.is_some() { 2 } else { 0 };
+
+        if let Some(staging) = &mut self.staging {
+            if !staging.flush(self.tx) {
+                return Err(Error::Eof.into());
+            }
+
+            // Encode the whole message into the staging buffer, then send it in fragments
+            let headers = usize::from(Header::SIZE + sdu_header);
+            let (header_buf, payload_buf) =
+                staging.buf[..headers + usize::from(pdu)].split_at_mut(headers);
+            let mut payload_writer = ByteWriter::new(payload_buf);
+            let left = payload_writer.space_left();
+            let result = f(&mut payload_writer)?;
+            let used = left - payload_writer.space_left();
+
+            let mut header_writer = ByteWriter::new(header_buf);
+            Header {
+                length: used as u16 + u16::from(sdu_header),
+                channel,
+            }
+            .to_bytes(&mut header_writer)?;
+            if sdu_header != 0 {
+                header_writer.write_u16_le(used as u16)?;
+            }
+
+            staging.start(headers + used, self.tx);
+            if let Some(credits) = &mut self.credits {
+                **credits -= 1;
+            }
+            return Ok(result);
+        }
 
         // The payload length goes into the header, so we have to skip that part and write it later
         let mut f = Some(f);
-        let channel = self.channel;
-        let pdu = self.pdu;
         let mut r = None;
         let r2 = self.tx.produce_dyn(
-            pdu + Header::SIZE,
+            pdu + Header::SIZE + sdu_header,
             &mut |writer: &mut ByteWriter<'_>| -> Result<_, Error> {
                 let mut header_writer = writer.split_off(usize::from(Header::SIZE))?;
+                let mut sdu_len_writer = writer.split_off(usize::from(sdu_header))?;
 
                 // The PDU size is determined based on how much space is left in `writer`, so we can't
                 // just `split_off` the protocol's PDU size.
@@ -466,10 +811,13 @@ impl<'a> Sender<'a> {
 
                 assert!(used < 0xFFFF);
                 Header {
-                    length: used as u16,
+                    length: used as u16 + u16::from(sdu_header),
                     channel,
                 }
                 .to_bytes(&mut header_writer)?;
+                if sdu_header != 0 {
+                    sdu_len_writer.write_u16_le(used as u16)?;
+                }
 
                 assert_eq!(header_writer.space_left(), 0);
 
@@ -483,8 +831,14 @@ impl<'a> Sender<'a> {
             },
         );
 
-        if let Err(e) = r2 {
-            if e != Error::InvalidValue {
+        match r2 {
+            Ok(()) => {
+                if let Some(credits) = &mut self.credits {
+                    **credits -= 1;
+                }
+            }
+            Err(Error::InvalidValue) => {}
+            Err(e) => {
                 // Legitimate error
                 return Err(e.into());
             }
@@ -506,47 +860,179 @@ impl<'a, M: ChannelMapper, P: Producer> L2CAPStateTx<'a, M, P> {
     /// Process the start of a new L2CAP message (or a complete, unfragmented message).
     ///
     /// If the incoming message is unfragmented, it will be forwarded to the protocol listening on
-    /// the addressed channel, and a response may be sent.
+    /// the addressed channel, and a response may be sent. Otherwise, reassembly of the message is
+    /// started.
     pub fn process_start(&mut self, message: &[u8]) -> Consume<()> {
-        let msg = match Message::<&[u8]>::from_bytes(&mut ByteReader::new(message)) {
-            Ok(msg) => msg,
+        let mut bytes = ByteReader::new(message);
+        let header = match Header::from_bytes(&mut bytes) {
+            Ok(header) => header,
             Err(e) => return Consume::always(Err(e)),
         };
+        let payload = bytes.into_rest();
+        let len = usize::from(header.length);
 
-        if usize::from(msg.header.length) != msg.payload.len() {
-            // Lengths mismatch => Reassembly needed
-            unimplemented!("L2CAP reassembly");
+        if let RxState::Receiving { .. } | RxState::Complete { .. } = self.l2cap.rx.state {
+            debug!("L2CAP message started before the last one was complete, dropping it");
         }
+        self.l2cap.rx.state = RxState::Idle;
 
-        self.dispatch(msg.header.channel, msg.payload)
+        if len == payload.len() {
+            return self.dispatch(header.channel, payload);
+        } else if len < payload.len() {
+            return Consume::always(Err(Error::InvalidLength));
+        }
+
+        let rx = &mut self.l2cap.rx;
+        if len > rx.buf.len() {
+            warn!(
+                "L2CAP message of {} Bytes exceeds reassembly buffer, dropping",
+                len
+            );
+            rx.state = RxState::Discarding {
+                remaining: len - payload.len(),
+            };
+        } else {
+            rx.buf[..payload.len()].copy_from_slice(payload);
+            rx.state = RxState::Receiving {
+                channel: header.channel,
+                len,
+                received: payload.len(),
+            };
+        }
+        Consume::always(Ok(()))
     }
 
     /// Process continuation of an L2CAP message.
     ///
-    /// This is not yet implemented and will always panic.
-    pub fn process_cont(&mut self, _data: &[u8]) -> Consume<()> {
-        unimplemented!("reassembly")
+    /// Once the message is complete, it is forwarded to the protocol listening on the addressed
+    /// channel.
+    pub fn process_cont(&mut self, data: &[u8]) -> Consume<()> {
+        let rx = &mut self.l2cap.rx;
+        let (channel, len) = match rx.state {
+            RxState::Idle => {
+                debug!("unexpected L2CAP continuation fragment, dropping");
+                return Consume::always(Ok(()));
+            }
+            RxState::Discarding { remaining } => {
+                rx.state = match remaining.checked_sub(data.len()) {
+                    Some(remaining) if remaining > 0 => RxState::Discarding { remaining },
+                    _ => RxState::Idle,
+                };
+                return Consume::always(Ok(()));
+            }
+            RxState::Receiving {
+                channel,
+                len,
+                received,
+            } => {
+                let end = received + data.len();
+                if end > len {
+                    rx.state = RxState::Idle;
+                    return Consume::always(Err(Error::InvalidLength));
+                }
+
+                rx.buf[received..end].copy_from_slice(data);
+                if end < len {
+                    rx.state = RxState::Receiving {
+                        channel,
+                        len,
+                        received: end,
+                    };
+                    return Consume::always(Ok(()));
+                }
+                rx.state = RxState::Complete { channel, len };
+                (channel, len)
+            }
+            // The last fragment is passed again after dispatching the message failed, don't add
+            // it twice
+            RxState::Complete { channel, len } => (channel, len),
+        };
+
+        let L2CAPState {
+            mapper,
+            rx,
+            tx: staging,
+            deferred,
+        } = &mut *self.l2cap;
+        let consume = dispatch(
+            mapper,
+            staging,
+            deferred,
+            &mut *self.tx,
+            channel,
+            &rx.buf[..len],
+        );
+        if consume.should_consume() {
+            rx.state = RxState::Idle;
+        }
+        consume
+    }
+
+    /// Enqueues the remaining fragments of the last message sent, as far as the TX queue has room
+    /// for them.
+    ///
+    /// Returns whether all fragments are enqueued. This is done automatically before anything else
+    /// is sent, but should also be called periodically (eg. in the idle loop) when sending messages
+    /// longer than a data channel PDU, so that they are completed without waiting for more traffic.
+    pub fn flush(&mut self) -> bool {
+        self.l2cap.tx.flush(&mut *self.tx)
     }
 
     /// Dispatches a fully reassembled L2CAP message to the protocol listening on the addressed
     /// channel.
     fn dispatch(&mut self, channel: Channel, payload: &[u8]) -> Consume<()> {
-        if let Some(mut chdata) = self.l2cap.mapper.lookup(channel) {
-            let sender = if let Some(sender) = Sender::new(&chdata, self.tx) {
-                sender
-            } else {
-                return Consume::never(Ok(()));
-            };
+        let L2CAPState {
+            mapper,
+            tx: staging,
+            deferred,
+            ..
+        } = &mut *self.l2cap;
+        dispatch(mapper, staging, deferred, &mut *self.tx, channel, payload)
+    }
 
-            Consume::always(chdata.protocol().process_message(payload, sender))
-        } else {
-            warn!(
-                "ignoring message sent to unconnected channel {:?}: {:?}",
-                channel,
-                HexSlice(payload)
-            );
-            Consume::always(Ok(()))
+    /// Processes the ATT request that is waiting for authorization again, if there is one.
+    ///
+    /// If the application has decided in the meantime, the request is answered and later PDUs on
+    /// the ATT channel are processed again. `Responder::process_one` does this automatically.
+    pub fn process_deferred(&mut self) -> Result<(), Error> {
+        let L2CAPState {
+            mapper,
+            tx: staging,
+            deferred,
+            ..
+        } = &mut *self.l2cap;
+        if !deferred.is_held() {
+            return Ok(());
         }
+        if !mapper.att().into_protocol().is_deferred_on(Channel::ATT) {
+            // Cancelled, eg. because the connection was closed
+            deferred.len = 0;
+            return Ok(());
+        }
+
+        let result = {
+            let mut chdata = mapper.att();
+            match Sender::new(&chdata, &mut *self.tx, &mut *staging) {
+                Some(sender) => chdata.protocol().process_message(deferred.pdu(), sender),
+                // Retried on the next call
+                None => return Ok(()),
+            }
+        };
+        if !mapper.att().into_protocol().is_deferred_on(Channel::ATT) {
+            deferred.len = 0;
+            send_security_request(mapper, staging, &mut *self.tx);
+        }
+        result
+    }
+
+    /// Sends the *Security Request* the ATT server wants to send after denying an access, if any.
+    fn send_security_request(&mut self) {
+        let L2CAPState {
+            mapper,
+            tx: staging,
+            ..
+        } = &mut *self.l2cap;
+        send_security_request(mapper, staging, &mut *self.tx);
     }
 
     /// Prepares for sending data using the Attribute Protocol.
@@ -560,20 +1046,294 @@ impl<'a, M: ChannelMapper, P: Producer> L2CAPStateTx<'a, M, P> {
     /// transmit more packets) might succeed.
     pub fn att(&mut self) -> Option<att::AttributeServerTx<'_, M::AttributeProvider>> {
         let att = self.l2cap.mapper.att();
-        Sender::new(&att, self.tx).map(move |sender| att.into_protocol().with_sender(sender))
+        Sender::new(&att, self.tx, &mut self.l2cap.tx)
+            .map(move |sender| att.into_protocol().with_sender(sender))
+    }
+
+    /// Prepares for sending data using the Security Manager Protocol.
+    ///
+    /// Returns `None` if there's not enough space in the TX packet queue to send an SMP PDU.
+    pub fn security(&mut self) -> Option<SecurityManagerTx<'_, M::SecurityLevel>> {
+        let L2CAPState {
+            mapper,
+            tx: staging,
+            ..
+        } = &mut *self.l2cap;
+        security_tx(mapper, staging, &mut *self.tx)
+    }
+
+    /// Prepares for sending data on, or taking received data out of, the connection-oriented
+    /// channel with local CID `local`.
+    ///
+    /// Returns `None` if no such channel is open, or if the fragments of the last message haven't
+    /// all been sent yet.
+    pub fn credit_channel(&mut self, local: Channel) -> Option<CreditChannelTx<'_>> {
+        if !self.flush() {
+            return None;
+        }
+        let channel = self.l2cap.mapper.credit_channels()?.get_mut(local)?;
+        Some(CreditChannelTx::new(channel, &mut *self.tx))
+    }
+
+    /// Like `credit_channel`, but selects the first open channel connected to `spsm`.
+    pub fn credit_channel_for(&mut self, spsm: Spsm) -> Option<CreditChannelTx<'_>> {
+        if !self.flush() {
+            return None;
+        }
+        let channel = self
+            .l2cap
+            .mapper
+            .credit_channels()?
+            .iter_mut()
+            .find(|ch| ch.spsm() == spsm)?;
+        Some(CreditChannelTx::new(channel, &mut *self.tx))
+    }
+
+    /// Processes the ATT requests received on all EATT bearers.
+    ///
+    /// This should be called after incoming data was processed. Returns `Error::Eof` if there isn't
+    /// enough space in the TX queue to respond (in which case the remaining requests are processed
+    /// on the next call).
+    pub fn process_eatt(&mut self) -> Result<(), Error> {
+        if !self.flush() {
+            return Err(Error::Eof);
+        }
+        let result = match self.l2cap.mapper.eatt() {
+            Some((server, channels)) => eatt::process(server, channels, &mut *self.tx),
+            None => Ok(()),
+        };
+        self.send_security_request();
+        result
+    }
+
+    /// Prepares for sending ATT PDUs on the EATT bearer with local CID `local`.
+    ///
+    /// Returns `None` if there is no such bearer, if the peer hasn't granted it any credits, or if
+    /// there's not enough space in the TX packet queue.
+    pub fn eatt(
+        &mut self,
+        local: Channel,
+    ) -> Option<att::AttributeServerTx<'_, M::AttributeProvider>> {
+        if !self.flush() {
+            return None;
+        }
+        let (server, channels) = self.l2cap.mapper.eatt()?;
+        let bearer = channels
+            .get_mut(local)
+            .filter(|ch| ch.spsm() == Spsm::EATT)?;
+        let sender = bearer.sender(EATT_PDU_SIZE, &mut *self.tx)?;
+        Some(server.with_sender(sender))
+    }
+}
+
+/// Forwards `payload` to the protocol on `channel`, or tells the caller to retry later if there's not
+/// enough space to respond.
+fn dispatch<M: ChannelMapper>(
+    mapper: &mut M,
+    staging: &mut Fragmenter,
+    deferred: &mut DeferredAtt,
+    tx: &mut dyn Producer,
+    channel: Channel,
+    payload: &[u8],
+) -> Consume<()> {
+    if channel == Channel::ATT && deferred.is_held() {
+        if mapper.att().into_protocol().is_deferred_on(Channel::ATT) {
+            // Requests are answered in order, so later ATT PDUs wait for the deferred one
+            return Consume::never(Ok(()));
+        }
+        // Cancelled, eg. because the connection was closed
+        deferred.len = 0;
+    }
+
+    if let Some(mut chdata) = mapper.lookup(channel) {
+        let sender = if let Some(sender) = Sender::new(&chdata, &mut *tx, &mut *staging) {
+            sender
+        } else {
+            return Consume::never(Ok(()));
+        };
+
+        let result = chdata.protocol().process_message(payload, sender);
+        if channel == Channel::ATT {
+            if mapper.att().into_protocol().is_deferred_on(Channel::ATT) {
+                // Keep the request until the application has decided. If it can't be copied, it
+                // stays in the RX queue instead, which holds up all other channels too.
+                return if deferred.hold(payload) {
+                    Consume::always(result)
+                } else {
+                    Consume::never(result)
+                };
+            }
+            send_security_request(mapper, staging, tx);
+        }
+        Consume::always(result)
+    } else {
+        warn!(
+            "ignoring message sent to unconnected channel {:?}: {:?}",
+            channel,
+            HexSlice(payload)
+        );
+        Consume::always(Ok(()))
     }
 }
 
+/// Sends the *Security Request* the ATT server wants to send after denying an access, if any.
+fn send_security_request<M: ChannelMapper>(
+    mapper: &mut M,
+    staging: &mut Fragmenter,
+    tx: &mut dyn Producer,
+) {
+    if let Some(auth_req) = mapper.att().into_protocol().take_security_request() {
+        match security_tx(mapper, staging, tx) {
+            Some(sm) => sm.request_security(auth_req),
+            None => warn!("TX queue full, dropping Security Request"),
+        }
+    }
+}
+
+/// Creates a `SecurityManagerTx`, if there's enough space in `tx`.
+fn security_tx<'a, M: ChannelMapper>(
+    mapper: &'a mut M,
+    staging: &'a mut Fragmenter,
+    tx: &'a mut dyn Producer,
+) -> Option<SecurityManagerTx<'a, M::SecurityLevel>> {
+    let sm = mapper.security();
+    Sender::new(&sm, tx, staging).map(move |sender| sm.into_protocol().with_sender(sender))
+}
+
 impl<'a, M: ChannelMapper, P: Producer> Deref for L2CAPStateTx<'a, M, P> {
     type Target = L2CAPState<M>;
 
     fn deref(&self) -> &Self::Target {
-        &self.l2cap
+        self.l2cap
     }
 }
 
 impl<'a, M: ChannelMapper, P: Producer> DerefMut for L2CAPStateTx<'a, M, P> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.l2cap
+        self.l2cap
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            att::{AttUuid, Attribute, Authorization, Handle, HandleRange},
+            link::queue::{Consumer, PacketQueue, RingQueue},
+            uuid::Uuid16,
+        },
+        core::sync::atomic::{AtomicBool, Ordering},
+    };
+
+    static GRANTED: AtomicBool = AtomicBool::new(false);
+
+    /// A single attribute, which can only be read once `GRANTED` is set.
+    struct Deferring;
+
+    impl AttributeProvider for Deferring {
+        fn for_attrs_in_range(
+            &mut self,
+            range: HandleRange,
+            mut f: impl FnMut(&Self, Attribute<'_>) -> Result<(), Error>,
+        ) -> Result<(), Error> {
+            let handle = Handle::from_raw(1);
+            if range.contains(handle) {
+                f(self, Attribute::new(Uuid16(0x2A19), handle, &[42]))?;
+            }
+            Ok(())
+        }
+
+        fn is_grouping_attr(&self, _uuid: AttUuid) -> bool {
+            false
+        }
+
+        fn group_end(&self, _handle: Handle) -> Option<Handle> {
+            None
+        }
+
+        fn authorize(&self, _handle: Handle, _access: att::Access) -> Authorization {
+            if GRANTED.load(Ordering::SeqCst) {
+                Authorization::Granted
+            } else {
+                Authorization::Pending
+            }
+        }
+    }
+
+    #[test]
+    fn deferred_att_request() {
+        let mut l2cap = L2CAPState::new(BleChannelMap::<_, NoSecurity>::with_attributes(Deferring));
+        let mut queue = RingQueue::<4>::new();
+        let (mut p, mut c) = (&mut queue).split();
+
+        // Read Request, which has to wait
+        let read = [3, 0, 4, 0, 0x0A, 1, 0];
+        assert!(l2cap.tx(&mut p).process_start(&read).should_consume());
+        assert!(l2cap.has_deferred());
+        assert!(!c.has_data());
+
+        // The signaling channel is still served, while ATT PDUs wait
+        let disconnect = [8, 0, 5, 0, 0x06, 1, 4, 0, 0x40, 0, 0x40, 0];
+        assert!(l2cap.tx(&mut p).process_start(&disconnect).should_consume());
+        assert!(!l2cap.tx(&mut p).process_start(&read).should_consume());
+        c.consume_raw_with(|_, payload| {
+            assert_eq!(&payload[2..5], &[5, 0, 0x01]);
+            Consume::always(Ok(()))
+        })
+        .unwrap();
+
+        l2cap.tx(&mut p).process_deferred().unwrap();
+        assert!(l2cap.has_deferred());
+        assert!(!c.has_data());
+
+        GRANTED.store(true, Ordering::SeqCst);
+        l2cap.tx(&mut p).process_deferred().unwrap();
+        assert!(!l2cap.has_deferred());
+        c.consume_raw_with(|_, payload| {
+            assert_eq!(payload, &[2, 0, 4, 0, 0x0B, 42]);
+            Consume::always(Ok(()))
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn fragmentation() {
+        let mut queue = RingQueue::<2>::new();
+        let (mut p, mut c) = (&mut queue).split();
+        let mut staging = Fragmenter {
+            buf: Box::leak(Box::new([0; 64])),
+            len: 0,
+            sent: 0,
+        };
+        for (i, b) in staging.buf.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+
+        // Only 2 of the 3 fragments fit into the queue
+        staging.start(60, &mut p);
+        assert!(staging.is_busy());
+
+        let mut received = [0; 60];
+        let mut offset = 0;
+        for &(llid, len) in &[(Llid::DataStart, 27), (Llid::DataCont, 27)] {
+            c.consume_raw_with(|header, payload| {
+                assert_eq!(header.llid(), llid);
+                assert_eq!(payload.len(), len);
+                received[offset..offset + len].copy_from_slice(payload);
+                offset += len;
+                Consume::always(Ok(()))
+            })
+            .unwrap();
+        }
+
+        assert!(staging.flush(&mut p));
+        c.consume_raw_with(|header, payload| {
+            assert_eq!(header.llid(), Llid::DataCont);
+            received[offset..].copy_from_slice(payload);
+            Consume::always(Ok(()))
+        })
+        .unwrap();
+        assert_eq!(&received[..], &staging.buf[..60]);
     }
 }
@@ -1,22 +1,29 @@
 //! L2CAP Signaling channel PDUs and functions (`0x0005`).
 
 use {
-    super::{Protocol, ProtocolObj, Sender},
-    crate::Error,
+    super::{
+        coc::{self, ChannelTable, CocConfig, CocListener, CreditMode, Refused, Spsm},
+        Channel, Protocol, ProtocolObj, Sender,
+    },
+    crate::{bytes::*, utils::HexSlice, Error},
 };
 
 enum_with_unknown! {
     /// LE Signaling Channel opcodes.
-    #[derive(Debug, Copy, Clone)]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     enum Code(u8) {
         CommandReject = 0x01,
         DisconnectionReq = 0x06,
         DisconnectionRsp = 0x07,
         ConnectionParameterUpdateReq = 0x12,
         ConnectionParameterUpdateRsp = 0x13,
-        CreditBasedConnectionReq = 0x14,
-        CreditBasedConnectionRsp = 0x15,
+        LeCreditBasedConnectionReq = 0x14,
+        LeCreditBasedConnectionRsp = 0x15,
         FlowControlCredit = 0x16,
+        CreditBasedConnectionReq = 0x17,
+        CreditBasedConnectionRsp = 0x18,
+        CreditBasedReconfigureReq = 0x19,
+        CreditBasedReconfigureRsp = 0x1A,
     }
 }
 
@@ -30,22 +37,719 @@ enum_with_unknown! {
     }
 }
 
+enum_with_unknown! {
+    /// Result codes of (Enhanced) Credit Based Connection Responses.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    enum ConnectionResult(u16) {
+        Success = 0x0000,
+        SpsmNotSupported = 0x0002,
+        NoResources = 0x0004,
+        InvalidSourceCid = 0x0009,
+        SourceCidAllocated = 0x000A,
+        UnacceptableParameters = 0x000B,
+        InvalidParameters = 0x000C,
+    }
+}
+
+enum_with_unknown! {
+    /// Result codes of Credit Based Reconfigure Responses.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    enum ReconfigureResult(u16) {
+        Success = 0x0000,
+        MtuReduced = 0x0001,
+        MpsReduced = 0x0002,
+        InvalidCid = 0x0003,
+        UnacceptableParameters = 0x0004,
+    }
+}
+
+impl From<Refused> for ConnectionResult {
+    fn from(r: Refused) -> Self {
+        match r {
+            Refused::NoResources => ConnectionResult::NoResources,
+            Refused::InvalidSourceCid => ConnectionResult::InvalidSourceCid,
+            Refused::SourceCidAllocated => ConnectionResult::SourceCidAllocated,
+        }
+    }
+}
+
+/// Maximum number of channels that can be opened with a single enhanced connection request.
+const MAX_CIDS: usize = 5;
+
+/// A list of up to 5 CIDs, as used by the Enhanced Credit Based Flow Control Mode commands.
+#[derive(Debug, Copy, Clone)]
+struct CidList {
+    cids: [Channel; MAX_CIDS],
+    len: u8,
+}
+
+impl CidList {
+    fn new() -> Self {
+        Self {
+            cids: [Channel::NULL; MAX_CIDS],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, cid: Channel) {
+        self.cids[usize::from(self.len)] = cid;
+        self.len += 1;
+    }
+
+    fn as_slice(&self) -> &[Channel] {
+        &self.cids[..usize::from(self.len)]
+    }
+}
+
+impl<'a> FromBytes<'a> for CidList {
+    fn from_bytes(bytes: &mut ByteReader<'a>) -> Result<Self, Error> {
+        let mut list = CidList::new();
+        while !bytes.is_empty() {
+            if usize::from(list.len) == MAX_CIDS {
+                return Err(Error::InvalidLength);
+            }
+            list.push(Channel::from_bytes(bytes)?);
+        }
+
+        if list.len == 0 {
+            Err(Error::InvalidLength)
+        } else {
+            Ok(list)
+        }
+    }
+}
+
+impl ToBytes for CidList {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        for cid in self.as_slice() {
+            cid.to_bytes(writer)?;
+        }
+        Ok(())
+    }
+}
+
+/// A signaling command (the payload of a signaling channel PDU).
+#[derive(Debug)]
+enum Pdu<'a> {
+    CommandReject {
+        reason: RejectReason,
+        data: HexSlice<&'a [u8]>,
+    },
+    DisconnectionReq {
+        dcid: Channel,
+        scid: Channel,
+    },
+    DisconnectionRsp {
+        dcid: Channel,
+        scid: Channel,
+    },
+    ConnectionParameterUpdateRsp {
+        result: u16,
+    },
+    LeCreditBasedConnectionReq {
+        spsm: Spsm,
+        scid: Channel,
+        mtu: u16,
+        mps: u16,
+        credits: u16,
+    },
+    LeCreditBasedConnectionRsp {
+        dcid: Channel,
+        mtu: u16,
+        mps: u16,
+        credits: u16,
+        result: ConnectionResult,
+    },
+    FlowControlCredit {
+        cid: Channel,
+        credits: u16,
+    },
+    CreditBasedConnectionReq {
+        spsm: Spsm,
+        mtu: u16,
+        mps: u16,
+        credits: u16,
+        scids: CidList,
+    },
+    CreditBasedConnectionRsp {
+        mtu: u16,
+        mps: u16,
+        credits: u16,
+        result: ConnectionResult,
+        dcids: CidList,
+    },
+    CreditBasedReconfigureReq {
+        mtu: u16,
+        mps: u16,
+        dcids: CidList,
+    },
+    CreditBasedReconfigureRsp {
+        result: ReconfigureResult,
+    },
+    Unknown {
+        code: Code,
+        data: HexSlice<&'a [u8]>,
+    },
+}
+
+impl Pdu<'_> {
+    fn code(&self) -> Code {
+        match self {
+            Pdu::CommandReject { .. } => Code::CommandReject,
+            Pdu::DisconnectionReq { .. } => Code::DisconnectionReq,
+            Pdu::DisconnectionRsp { .. } => Code::DisconnectionRsp,
+            Pdu::ConnectionParameterUpdateRsp { .. } => Code::ConnectionParameterUpdateRsp,
+            Pdu::LeCreditBasedConnectionReq { .. } => Code::LeCreditBasedConnectionReq,
+            Pdu::LeCreditBasedConnectionRsp { .. } => Code::LeCreditBasedConnectionRsp,
+            Pdu::FlowControlCredit { .. } => Code::FlowControlCredit,
+            Pdu::CreditBasedConnectionReq { .. } => Code::CreditBasedConnectionReq,
+            Pdu::CreditBasedConnectionRsp { .. } => Code::CreditBasedConnectionRsp,
+            Pdu::CreditBasedReconfigureReq { .. } => Code::CreditBasedReconfigureReq,
+            Pdu::CreditBasedReconfigureRsp { .. } => Code::CreditBasedReconfigureRsp,
+            Pdu::Unknown { code, .. } => *code,
+        }
+    }
+
+    fn from_code_and_data<'a>(code: Code, bytes: &mut ByteReader<'a>) -> Result<Pdu<'a>, Error> {
+        Ok(match code {
+            Code::CommandReject => Pdu::CommandReject {
+                reason: RejectReason::from(bytes.read_u16_le()?),
+                data: HexSlice(bytes.read_rest()),
+            },
+            Code::DisconnectionReq => Pdu::DisconnectionReq {
+                dcid: Channel::from_bytes(bytes)?,
+                scid: Channel::from_bytes(bytes)?,
+            },
+            Code::DisconnectionRsp => Pdu::DisconnectionRsp {
+                dcid: Channel::from_bytes(bytes)?,
+                scid: Channel::from_bytes(bytes)?,
+            },
+            Code::ConnectionParameterUpdateRsp => Pdu::ConnectionParameterUpdateRsp {
+                result: bytes.read_u16_le()?,
+            },
+            Code::LeCreditBasedConnectionReq => Pdu::LeCreditBasedConnectionReq {
+                spsm: Spsm::from_raw(bytes.read_u16_le()?),
+                scid: Channel::from_bytes(bytes)?,
+                mtu: bytes.read_u16_le()?,
+                mps: bytes.read_u16_le()?,
+                credits: bytes.read_u16_le()?,
+            },
+            Code::LeCreditBasedConnectionRsp => Pdu::LeCreditBasedConnectionRsp {
+                dcid: Channel::from_bytes(bytes)?,
+                mtu: bytes.read_u16_le()?,
+                mps: bytes.read_u16_le()?,
+                credits: bytes.read_u16_le()?,
+                result: ConnectionResult::from(bytes.read_u16_le()?),
+            },
+            Code::FlowControlCredit => Pdu::FlowControlCredit {
+                cid: Channel::from_bytes(bytes)?,
+                credits: bytes.read_u16_le()?,
+            },
+            Code::CreditBasedConnectionReq => Pdu::CreditBasedConnectionReq {
+                spsm: Spsm::from_raw(bytes.read_u16_le()?),
+                mtu: bytes.read_u16_le()?,
+                mps: bytes.read_u16_le()?,
+                credits: bytes.read_u16_le()?,
+                scids: CidList::from_bytes(bytes)?,
+            },
+            Code::CreditBasedConnectionRsp => Pdu::CreditBasedConnectionRsp {
+                mtu: bytes.read_u16_le()?,
+                mps: bytes.read_u16_le()?,
+                credits: bytes.read_u16_le()?,
+                result: ConnectionResult::from(bytes.read_u16_le()?),
+                dcids: CidList::from_bytes(bytes)?,
+            },
+            Code::CreditBasedReconfigureReq => Pdu::CreditBasedReconfigureReq {
+                mtu: bytes.read_u16_le()?,
+                mps: bytes.read_u16_le()?,
+                dcids: CidList::from_bytes(bytes)?,
+            },
+            Code::CreditBasedReconfigureRsp => Pdu::CreditBasedReconfigureRsp {
+                result: ReconfigureResult::from(bytes.read_u16_le()?),
+            },
+            _ => Pdu::Unknown {
+                code,
+                data: HexSlice(bytes.read_rest()),
+            },
+        })
+    }
+}
+
+impl ToBytes for Pdu<'_> {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        match self {
+            Pdu::CommandReject { reason, data } => {
+                writer.write_u16_le(u16::from(*reason))?;
+                writer.write_slice(data.0)?;
+            }
+            Pdu::DisconnectionReq { dcid, scid } | Pdu::DisconnectionRsp { dcid, scid } => {
+                dcid.to_bytes(writer)?;
+                scid.to_bytes(writer)?;
+            }
+            Pdu::ConnectionParameterUpdateRsp { result } => writer.write_u16_le(*result)?,
+            Pdu::LeCreditBasedConnectionReq {
+                spsm,
+                scid,
+                mtu,
+                mps,
+                credits,
+            } => {
+                writer.write_u16_le(spsm.as_raw())?;
+                scid.to_bytes(writer)?;
+                writer.write_u16_le(*mtu)?;
+                writer.write_u16_le(*mps)?;
+                writer.write_u16_le(*credits)?;
+            }
+            Pdu::LeCreditBasedConnectionRsp {
+                dcid,
+                mtu,
+                mps,
+                credits,
+                result,
+            } => {
+                dcid.to_bytes(writer)?;
+                writer.write_u16_le(*mtu)?;
+                writer.write_u16_le(*mps)?;
+                writer.write_u16_le(*credits)?;
+                writer.write_u16_le(u16::from(*result))?;
+            }
+            Pdu::FlowControlCredit { cid, credits } => {
+                cid.to_bytes(writer)?;
+                writer.write_u16_le(*credits)?;
+            }
+            Pdu::CreditBasedConnectionReq {
+                spsm,
+                mtu,
+                mps,
+                credits,
+                scids,
+            } => {
+                writer.write_u16_le(spsm.as_raw())?;
+                writer.write_u16_le(*mtu)?;
+                writer.write_u16_le(*mps)?;
+                writer.write_u16_le(*credits)?;
+                scids.to_bytes(writer)?;
+            }
+            Pdu::CreditBasedConnectionRsp {
+                mtu,
+                mps,
+                credits,
+                result,
+                dcids,
+            } => {
+                writer.write_u16_le(*mtu)?;
+                writer.write_u16_le(*mps)?;
+                writer.write_u16_le(*credits)?;
+                writer.write_u16_le(u16::from(*result))?;
+                dcids.to_bytes(writer)?;
+            }
+            Pdu::CreditBasedReconfigureReq { mtu, mps, dcids } => {
+                writer.write_u16_le(*mtu)?;
+                writer.write_u16_le(*mps)?;
+                dcids.to_bytes(writer)?;
+            }
+            Pdu::CreditBasedReconfigureRsp { result } => {
+                writer.write_u16_le(u16::from(*result))?;
+            }
+            Pdu::Unknown { data, .. } => writer.write_slice(data.0)?,
+        }
+        Ok(())
+    }
+}
+
+/// A signaling command with its header.
+#[derive(Debug)]
+pub(super) struct Command<'a> {
+    identifier: u8,
+    pdu: Pdu<'a>,
+}
+
+impl Command<'static> {
+    /// Encoded size of an `L2CAP_FLOW_CONTROL_CREDIT_IND` command.
+    pub(super) const FLOW_CONTROL_CREDIT_SIZE: u8 = 4 + 2 + 2;
+
+    /// Creates an `L2CAP_FLOW_CONTROL_CREDIT_IND` granting `credits` on our channel `cid`.
+    pub(super) fn flow_control_credit(identifier: u8, cid: Channel, credits: u16) -> Self {
+        Command {
+            identifier,
+            pdu: Pdu::FlowControlCredit { cid, credits },
+        }
+    }
+}
+
+impl<'a> FromBytes<'a> for Command<'a> {
+    fn from_bytes(bytes: &mut ByteReader<'a>) -> Result<Self, Error> {
+        let code = Code::from(bytes.read_u8()?);
+        let identifier = bytes.read_u8()?;
+        let length = bytes.read_u16_le()?;
+        let data = &mut ByteReader::new(bytes.read_slice(usize::from(length))?);
+        let pdu = Pdu::from_code_and_data(code, data)?;
+        if !data.is_empty() {
+            return Err(Error::IncompleteParse);
+        }
+
+        Ok(Command { identifier, pdu })
+    }
+}
+
+impl ToBytes for Command<'_> {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u8(self.pdu.code().into())?;
+        writer.write_u8(self.identifier)?;
+        let mut length = writer.split_off(2)?;
+        let left = writer.space_left();
+        self.pdu.to_bytes(writer)?;
+        length.write_u16_le((left - writer.space_left()) as u16)?;
+        Ok(())
+    }
+}
+
 /// The `Protocol` implementor listening on the LE Signaling Channel `0x0005`.
-pub struct SignalingState {}
+///
+/// Handles requests to open and close connection-oriented channels and tracks their credits.
+/// Incoming channels are accepted or refused by the `CocListener` `L`.
+pub struct SignalingState<L: CocListener> {
+    channels: ChannelTable,
+    listener: L,
+}
+
+impl<L: CocListener> SignalingState<L> {
+    pub fn new(listener: L) -> Self {
+        Self {
+            channels: ChannelTable::new(),
+            listener,
+        }
+    }
+
+    /// Returns the table of open connection-oriented channels.
+    pub fn channels(&mut self) -> &mut ChannelTable {
+        &mut self.channels
+    }
+
+    /// Handles an `L2CAP_LE_CREDIT_BASED_CONNECTION_REQ`.
+    fn le_credit_connect(&mut self, spsm: Spsm, scid: Channel, peer: CocConfig) -> Pdu<'static> {
+        let refuse = |result| Pdu::LeCreditBasedConnectionRsp {
+            dcid: Channel::NULL,
+            mtu: 0,
+            mps: 0,
+            credits: 0,
+            result,
+        };
+
+        if peer.mtu < 23 || peer.mps < 23 {
+            return refuse(ConnectionResult::UnacceptableParameters);
+        }
+        let config = match self.listener.accept(spsm) {
+            Some(config) => coc::clamp_config(config, CreditMode::LeCredit),
+            None => return refuse(ConnectionResult::SpsmNotSupported),
+        };
+        let policy = self.listener.credit_policy(spsm);
+
+        match self
+            .channels
+            .open(spsm, CreditMode::LeCredit, scid, &peer, &config, policy)
+        {
+            Ok(dcid) => Pdu::LeCreditBasedConnectionRsp {
+                dcid,
+                mtu: config.mtu,
+                mps: config.mps,
+                credits: config.credits,
+                result: ConnectionResult::Success,
+            },
+            Err(e) => refuse(e.into()),
+        }
+    }
+
+    /// Handles an `L2CAP_CREDIT_BASED_CONNECTION_REQ`, opening up to 5 channels at once.
+    fn enhanced_connect(&mut self, spsm: Spsm, peer: CocConfig, scids: &CidList) -> Pdu<'static> {
+        let refuse_all = |result| {
+            let mut dcids = CidList::new();
+            for _ in scids.as_slice() {
+                dcids.push(Channel::NULL);
+            }
+            Pdu::CreditBasedConnectionRsp {
+                mtu: 0,
+                mps: 0,
+                credits: 0,
+                result,
+                dcids,
+            }
+        };
+
+        if peer.mtu < 64 || peer.mps < 64 {
+            return refuse_all(ConnectionResult::InvalidParameters);
+        }
+        let config = match self.listener.accept(spsm) {
+            Some(config) => coc::clamp_config(config, CreditMode::Enhanced),
+            None => return refuse_all(ConnectionResult::SpsmNotSupported),
+        };
+        let policy = self.listener.credit_policy(spsm);
+
+        // Channels are opened individually. If some can't be opened, the result code tells the
+        // peer why, and their DCIDs are set to 0.
+        let mut result = ConnectionResult::Success;
+        let mut dcids = CidList::new();
+        for scid in scids.as_slice() {
+            match self
+                .channels
+                .open(spsm, CreditMode::Enhanced, *scid, &peer, &config, policy)
+            {
+                Ok(dcid) => dcids.push(dcid),
+                Err(e) => {
+                    result = e.into();
+                    dcids.push(Channel::NULL);
+                }
+            }
+        }
+
+        Pdu::CreditBasedConnectionRsp {
+            mtu: config.mtu,
+            mps: config.mps,
+            credits: config.credits,
+            result,
+            dcids,
+        }
+    }
 
-impl SignalingState {
-    pub fn new() -> Self {
-        Self {}
+    /// Handles an `L2CAP_CREDIT_BASED_RECONFIGURE_REQ`.
+    ///
+    /// The request lists the peer's CIDs of the channels to reconfigure.
+    fn reconfigure(&mut self, mtu: u16, mps: u16, dcids: &CidList) -> ReconfigureResult {
+        if mtu < 64 || mps < 64 {
+            return ReconfigureResult::UnacceptableParameters;
+        }
+
+        // Validate all channels before changing any of them
+        for cid in dcids.as_slice() {
+            let ch = match self.channels.by_remote_mut(*cid) {
+                Some(ch) if ch.mode() == CreditMode::Enhanced => ch,
+                _ => return ReconfigureResult::InvalidCid,
+            };
+            if mtu < ch.peer_mtu() {
+                return ReconfigureResult::MtuReduced;
+            }
+            if mps < ch.peer_mps() && dcids.as_slice().len() > 1 {
+                return ReconfigureResult::MpsReduced;
+            }
+        }
+
+        for cid in dcids.as_slice() {
+            if let Some(ch) = self.channels.by_remote_mut(*cid) {
+                ch.reconfigure(mtu, mps);
+            }
+        }
+        ReconfigureResult::Success
     }
 }
 
-impl ProtocolObj for SignalingState {
-    fn process_message(&mut self, message: &[u8], responder: Sender<'_>) -> Result<(), Error> {
-        let _ = (message, responder);
-        unimplemented!();
+impl<L: CocListener> ProtocolObj for SignalingState<L> {
+    fn process_message(&mut self, message: &[u8], mut responder: Sender<'_>) -> Result<(), Error> {
+        let cmd = Command::from_bytes(&mut ByteReader::new(message))?;
+        trace!("signaling cmd {:?}", cmd);
+
+        let response = match cmd.pdu {
+            Pdu::LeCreditBasedConnectionReq {
+                spsm,
+                scid,
+                mtu,
+                mps,
+                credits,
+            } => {
+                let peer = CocConfig { mtu, mps, credits };
+                Some(self.le_credit_connect(spsm, scid, peer))
+            }
+            Pdu::CreditBasedConnectionReq {
+                spsm,
+                mtu,
+                mps,
+                credits,
+                scids,
+            } => {
+                let peer = CocConfig { mtu, mps, credits };
+                Some(self.enhanced_connect(spsm, peer, &scids))
+            }
+            Pdu::CreditBasedReconfigureReq { mtu, mps, dcids } => {
+                Some(Pdu::CreditBasedReconfigureRsp {
+                    result: self.reconfigure(mtu, mps, &dcids),
+                })
+            }
+            Pdu::FlowControlCredit { cid, credits } => {
+                // `cid` is the peer's CID of the channel
+                match self.channels.by_remote_mut(cid) {
+                    Some(ch) => ch.add_tx_credits(credits),
+                    None => warn!("credits for unknown channel {:?}", cid),
+                }
+                None
+            }
+            Pdu::DisconnectionReq { dcid, scid } => {
+                // `dcid` is our CID, `scid` the peer's
+                if self.channels.close(dcid, scid) {
+                    Some(Pdu::DisconnectionRsp { dcid, scid })
+                } else {
+                    Some(Pdu::CommandReject {
+                        reason: RejectReason::InvalidCid,
+                        data: HexSlice(&[]),
+                    })
+                }
+            }
+            Pdu::CommandReject { .. }
+            | Pdu::ConnectionParameterUpdateRsp { .. }
+            | Pdu::DisconnectionRsp { .. } => {
+                // We never send requests, so there's nothing to do with responses
+                debug!("ignoring signaling response {:?}", cmd.pdu);
+                None
+            }
+            Pdu::LeCreditBasedConnectionRsp { .. }
+            | Pdu::CreditBasedConnectionRsp { .. }
+            | Pdu::CreditBasedReconfigureRsp { .. }
+            | Pdu::Unknown { .. } => Some(Pdu::CommandReject {
+                reason: RejectReason::CommandNotUnderstood,
+                data: HexSlice(&[]),
+            }),
+        };
+
+        if let Some(pdu) = response {
+            responder.send(Command {
+                identifier: cmd.identifier,
+                pdu,
+            })?;
+        }
+
+        Ok(())
     }
 }
 
-impl Protocol for SignalingState {
+impl<L: CocListener> Protocol for SignalingState<L> {
     const RSP_PDU_SIZE: u8 = 23;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct AcceptAll;
+
+    impl CocListener for AcceptAll {
+        fn accept(&mut self, _spsm: Spsm) -> Option<CocConfig> {
+            Some(CocConfig {
+                mtu: 100,
+                mps: 64,
+                credits: 4,
+            })
+        }
+    }
+
+    fn cids(raw: &[u16]) -> CidList {
+        let mut list = CidList::new();
+        for cid in raw {
+            list.push(Channel(*cid));
+        }
+        list
+    }
+
+    fn peer() -> CocConfig {
+        CocConfig {
+            mtu: 64,
+            mps: 64,
+            credits: 10,
+        }
+    }
+
+    #[test]
+    fn enhanced_connect() {
+        let mut state = SignalingState::new(AcceptAll);
+        let rsp = state.enhanced_connect(Spsm::EATT, peer(), &cids(&[0x40, 0x41, 0x20]));
+        match rsp {
+            Pdu::CreditBasedConnectionRsp {
+                mtu, result, dcids, ..
+            } => {
+                assert_eq!(mtu, 100);
+                assert_eq!(result, ConnectionResult::InvalidSourceCid);
+                assert_eq!(
+                    dcids.as_slice(),
+                    &[Channel(0x40), Channel(0x41), Channel::NULL]
+                );
+            }
+            _ => panic!("unexpected response {:?}", rsp),
+        }
+        assert_eq!(state.channels().iter().count(), 2);
+
+        // Same source CID again
+        match state.enhanced_connect(Spsm::EATT, peer(), &cids(&[0x41])) {
+            Pdu::CreditBasedConnectionRsp { result, .. } => {
+                assert_eq!(result, ConnectionResult::SourceCidAllocated)
+            }
+            _ => panic!(),
+        }
+
+        assert_eq!(
+            state.reconfigure(100, 64, &cids(&[0x40, 0x41])),
+            ReconfigureResult::Success
+        );
+        assert_eq!(
+            state.reconfigure(80, 64, &cids(&[0x40])),
+            ReconfigureResult::MtuReduced
+        );
+        assert_eq!(
+            state.reconfigure(100, 64, &cids(&[0x50])),
+            ReconfigureResult::InvalidCid
+        );
+
+        assert!(state.channels().close(Channel(0x41), Channel(0x41)));
+        assert_eq!(state.channels().iter().count(), 1);
+    }
+
+    #[test]
+    fn refused() {
+        let mut state = SignalingState::new(coc::NoChannels);
+        match state.enhanced_connect(Spsm::EATT, peer(), &cids(&[0x40, 0x41])) {
+            Pdu::CreditBasedConnectionRsp { result, dcids, .. } => {
+                assert_eq!(result, ConnectionResult::SpsmNotSupported);
+                assert_eq!(dcids.as_slice(), &[Channel::NULL, Channel::NULL]);
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn credit_policy() {
+        struct Manual;
+
+        impl CocListener for Manual {
+            fn accept(&mut self, spsm: Spsm) -> Option<CocConfig> {
+                AcceptAll.accept(spsm)
+            }
+
+            fn credit_policy(&mut self, _spsm: Spsm) -> coc::CreditPolicy {
+                coc::CreditPolicy::Manual
+            }
+        }
+
+        let mut state = SignalingState::new(Manual);
+        state.enhanced_connect(Spsm::EATT, peer(), &cids(&[0x40]));
+        let channel = state.channels().iter().next().unwrap();
+        assert_eq!(channel.credit_policy(), coc::CreditPolicy::Manual);
+        assert_eq!(channel.rx_credits(), 4);
+    }
+
+    #[test]
+    fn command_roundtrip() {
+        let cmd = Command::flow_control_credit(7, Channel(0x40), 3);
+        let mut buf = [0; 16];
+        let mut writer = ByteWriter::new(&mut buf);
+        cmd.to_bytes(&mut writer).unwrap();
+        let len = 16 - writer.space_left();
+        assert_eq!(len, usize::from(Command::FLOW_CONTROL_CREDIT_SIZE));
+        assert_eq!(&buf[..len], &[0x16, 7, 4, 0, 0x40, 0, 3, 0]);
+
+        let cmd = Command::from_bytes(&mut ByteReader::new(&buf[..len])).unwrap();
+        assert_eq!(cmd.identifier, 7);
+        match cmd.pdu {
+            Pdu::FlowControlCredit { cid, credits } => {
+                assert_eq!(cid, Channel(0x40));
+                assert_eq!(credits, 3);
+            }
+            _ => panic!(),
+        }
+    }
+}
@@ -27,19 +27,29 @@
 // The claims of this lint are dubious, disable it
 #![allow(clippy::trivially_copy_pass_by_ref)]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
 #[macro_use]
 mod log;
 #[macro_use]
 mod utils;
 pub mod att;
 pub mod beacon;
+pub mod bond;
 pub mod bytes;
 pub mod config;
 mod crc;
+pub mod dtm;
 mod error;
+#[cfg(any(feature = "fmt", feature = "defmt"))]
+pub mod fmt;
 pub mod gatt;
+#[cfg(feature = "hal")]
+pub mod hal;
 pub mod l2cap;
 pub mod link;
+pub mod mesh;
 pub mod phy;
 pub mod security;
 pub mod time;
@@ -9,10 +9,12 @@
 use {
     crate::{
         bytes::*,
-        uuid::{IsUuid, Uuid, Uuid16, Uuid32, UuidKind},
+        utils::truncate_utf8,
+        uuid::{DynUuid, IsUuid, Uuid, Uuid16, Uuid32, UuidKind},
         Error,
     },
     bitflags::bitflags,
+    core::{convert::TryFrom, fmt},
 };
 
 /// A list of AD structures can be sent along with an advertising packet or scan response.
@@ -26,6 +28,7 @@ use {
 /// From a very unrepresentative scan, most devices seem to include Flags and Manufacturer Data, and
 /// optionally a device name, of course.
 #[derive(Debug, Copy, Clone)]
+#[non_exhaustive]
 pub enum AdStructure<'a> {
     /// Device flags and baseband capabilities.
     ///
@@ -47,6 +50,12 @@ pub enum AdStructure<'a> {
         data: &'a [u8],
     },
 
+    /// Service data with 32-bit service UUID.
+    ServiceData32(ServiceData<'a, Uuid32>),
+
+    /// Service data with 128-bit service UUID.
+    ServiceData128(ServiceData<'a, Uuid>),
+
     /// Sets the full (unabbreviated) device name.
     ///
     /// This will be shown to the user when this device is found.
@@ -55,6 +64,14 @@ pub enum AdStructure<'a> {
     /// Sets the shortened device name.
     ShortenedLocalName(&'a str),
 
+    /// The transmission power the packet was sent with, in dBm.
+    ///
+    /// Scanners can estimate the path loss from this and the received signal strength.
+    TxPowerLevel(i8),
+
+    /// The external appearance of the device, as a value of the GAP *Appearance* characteristic.
+    Appearance(u16),
+
     /// An unknown or unimplemented AD structure stored as raw bytes.
     Unknown {
         /// Type byte.
@@ -62,9 +79,6 @@ pub enum AdStructure<'a> {
         /// Raw data transmitted after the type.
         data: &'a [u8],
     },
-
-    #[doc(hidden)]
-    __Nonexhaustive,
 }
 
 impl<'a> ToBytes for AdStructure<'a> {
@@ -94,6 +108,8 @@ impl<'a> ToBytes for AdStructure<'a> {
                 buf.write_u8((*uuid >> 8) as u8)?;
                 buf.write_slice(data)?;
             }
+            AdStructure::ServiceData32(data) => data.to_bytes(buf)?,
+            AdStructure::ServiceData128(data) => data.to_bytes(buf)?,
             AdStructure::CompleteLocalName(name) => {
                 buf.write_u8(Type::COMPLETE_LOCAL_NAME)?;
                 buf.write_slice(name.as_bytes())?;
@@ -102,20 +118,72 @@ impl<'a> ToBytes for AdStructure<'a> {
                 buf.write_u8(Type::SHORTENED_LOCAL_NAME)?;
                 buf.write_slice(name.as_bytes())?;
             }
+            AdStructure::TxPowerLevel(dbm) => {
+                buf.write_u8(Type::TX_POWER_LEVEL)?;
+                buf.write_u8(*dbm as u8)?;
+            }
+            AdStructure::Appearance(appearance) => {
+                buf.write_u8(Type::APPEARANCE)?;
+                buf.write_u16_le(*appearance)?;
+            }
             AdStructure::Unknown { ty, data } => {
                 buf.write_u8(*ty)?;
                 buf.write_slice(data)?;
             }
-            AdStructure::__Nonexhaustive => unreachable!(),
         }
         let len = left_before - buf.space_left();
-        assert!(len <= 255);
 
-        *first = len as u8;
+        *first = u8::try_from(len).map_err(|_| Error::InvalidLength)?;
         Ok(())
     }
 }
 
+impl<'a> AdStructure<'a> {
+    /// Creates the local name AD structure for `name`, if it may occupy up to `max_len` Bytes.
+    ///
+    /// If `name` fits, this is a `CompleteLocalName`. Otherwise, a `ShortenedLocalName` is
+    /// returned with as many characters of `name` as fit (the name is never cut in the middle of
+    /// a UTF-8 encoded character).
+    pub fn local_name(name: &'a str, max_len: usize) -> Self {
+        if name.len() <= max_len {
+            AdStructure::CompleteLocalName(name)
+        } else {
+            AdStructure::ShortenedLocalName(truncate_utf8(name, max_len))
+        }
+    }
+
+    /// Returns the service UUID and data if this is one of the service data AD structures.
+    pub fn service_data(&self) -> Option<(DynUuid, &'a [u8])> {
+        match *self {
+            AdStructure::ServiceData16 { uuid, data } => Some((Uuid16(uuid).into(), data)),
+            AdStructure::ServiceData32(sd) => Some((sd.uuid().into(), sd.data())),
+            AdStructure::ServiceData128(sd) => Some((sd.uuid().into(), sd.data())),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> From<ServiceData<'a, Uuid16>> for AdStructure<'a> {
+    fn from(sd: ServiceData<'a, Uuid16>) -> Self {
+        AdStructure::ServiceData16 {
+            uuid: sd.uuid.0,
+            data: sd.data,
+        }
+    }
+}
+
+impl<'a> From<ServiceData<'a, Uuid32>> for AdStructure<'a> {
+    fn from(sd: ServiceData<'a, Uuid32>) -> Self {
+        AdStructure::ServiceData32(sd)
+    }
+}
+
+impl<'a> From<ServiceData<'a, Uuid>> for AdStructure<'a> {
+    fn from(sd: ServiceData<'a, Uuid>) -> Self {
+        AdStructure::ServiceData128(sd)
+    }
+}
+
 impl<'a> FromBytes<'a> for AdStructure<'a> {
     fn from_bytes(bytes: &mut ByteReader<'a>) -> Result<Self, Error> {
         let len = bytes.read_u8()?;
@@ -126,16 +194,14 @@ impl<'a> FromBytes<'a> for AdStructure<'a> {
 
         // The `FromBytes` impls of all AD structures also read the type byte
         let ty_and_data = bytes.read_slice(usize::from(len))?;
-        let ty = ty_and_data[0];
-        let data = &ty_and_data[1..];
+        let (&ty, data) = ty_and_data.split_first().ok_or(Error::InvalidLength)?;
 
         Ok(match ty {
             Type::FLAGS => {
-                if data.len() != 1 {
-                    return Err(Error::InvalidLength);
-                }
-
-                let bits = data[0];
+                let bits = match *data {
+                    [bits] => bits,
+                    _ => return Err(Error::InvalidLength),
+                };
                 let flags = Flags::from_bits_truncate(bits);
                 AdStructure::Flags(flags)
             }
@@ -144,11 +210,206 @@ impl<'a> FromBytes<'a> for AdStructure<'a> {
                 let uuids = ServiceUuids::<Uuid16>::from_bytes(&mut ByteReader::new(ty_and_data))?;
                 AdStructure::ServiceUuids16(uuids)
             }
+            Type::COMPLETE_LIST_OF_32BIT_SERVICE_UUIDS
+            | Type::INCOMPLETE_LIST_OF_32BIT_SERVICE_UUIDS => {
+                let uuids = ServiceUuids::<Uuid32>::from_bytes(&mut ByteReader::new(ty_and_data))?;
+                AdStructure::ServiceUuids32(uuids)
+            }
+            Type::COMPLETE_LIST_OF_128BIT_SERVICE_UUIDS
+            | Type::INCOMPLETE_LIST_OF_128BIT_SERVICE_UUIDS => {
+                let uuids = ServiceUuids::<Uuid>::from_bytes(&mut ByteReader::new(ty_and_data))?;
+                AdStructure::ServiceUuids128(uuids)
+            }
+            Type::SERVICE_DATA_16BIT_UUID => {
+                let mut bytes = ByteReader::new(data);
+                let uuid = bytes.read_u16_le()?;
+                AdStructure::ServiceData16 {
+                    uuid,
+                    data: bytes.read_rest(),
+                }
+            }
+            Type::SERVICE_DATA_32BIT_UUID => AdStructure::ServiceData32(ServiceData::from_bytes(
+                &mut ByteReader::new(ty_and_data),
+            )?),
+            Type::SERVICE_DATA_128BIT_UUID => AdStructure::ServiceData128(ServiceData::from_bytes(
+                &mut ByteReader::new(ty_and_data),
+            )?),
+            // Names that aren't valid UTF-8 are passed through as raw bytes
+            Type::COMPLETE_LOCAL_NAME => match core::str::from_utf8(data) {
+                Ok(name) => AdStructure::CompleteLocalName(name),
+                Err(_) => AdStructure::Unknown { ty, data },
+            },
+            Type::SHORTENED_LOCAL_NAME => match core::str::from_utf8(data) {
+                Ok(name) => AdStructure::ShortenedLocalName(name),
+                Err(_) => AdStructure::Unknown { ty, data },
+            },
+            Type::TX_POWER_LEVEL => match data {
+                [dbm] => AdStructure::TxPowerLevel(*dbm as i8),
+                _ => return Err(Error::InvalidLength),
+            },
+            Type::APPEARANCE => match data {
+                [lo, hi] => AdStructure::Appearance(u16::from_le_bytes([*lo, *hi])),
+                _ => return Err(Error::InvalidLength),
+            },
             _ => AdStructure::Unknown { ty, data },
         })
     }
 }
 
+/// An iterator decoding the AD structures in a received advertising or scan response payload.
+///
+/// The AD structures borrow from the payload, so no data is copied. Types Rubble doesn't know
+/// are yielded as `AdStructure::Unknown`.
+///
+/// Iteration stops at the end of the payload or at a length Byte of 0, which marks the start of
+/// zero padding. If an AD structure is malformed (eg. its length exceeds the payload), the error
+/// is yielded and iteration ends, since the following structures can't be located anymore.
+///
+/// ```
+/// use rubble::link::ad_structure::{AdStructure, AdStructures};
+///
+/// let payload = [0x02, 0x01, 0x06, 0x05, 0x09, b'T', b'e', b's', b't'];
+/// let name = AdStructures::new(&payload).find_map(|ad| match ad {
+///     Ok(AdStructure::CompleteLocalName(name)) => Some(name),
+///     _ => None,
+/// });
+/// assert_eq!(name, Some("Test"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct AdStructures<'a> {
+    payload: &'a [u8],
+}
+
+impl<'a> AdStructures<'a> {
+    /// Creates an iterator over the AD structures in `payload`.
+    pub fn new(payload: &'a [u8]) -> Self {
+        Self { payload }
+    }
+
+    /// Returns whether the payload contains a complete or incomplete service UUID list that
+    /// includes `uuid`.
+    ///
+    /// Malformed AD structures are skipped.
+    pub fn contains_service_uuid(self, uuid: impl Into<DynUuid>) -> bool {
+        let uuid = uuid.into();
+        self.filter_map(Result::ok).any(|ad| match ad {
+            AdStructure::ServiceUuids16(uuids) => uuids.iter().any(|u| DynUuid::from(u) == uuid),
+            AdStructure::ServiceUuids32(uuids) => uuids.iter().any(|u| DynUuid::from(u) == uuid),
+            AdStructure::ServiceUuids128(uuids) => uuids.iter().any(|u| DynUuid::from(u) == uuid),
+            _ => false,
+        })
+    }
+}
+
+impl<'a> Iterator for AdStructures<'a> {
+    type Item = Result<AdStructure<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.payload.first() {
+            None | Some(0) => return None,
+            Some(_) => {}
+        }
+
+        let mut bytes = ByteReader::new(self.payload);
+        let result = AdStructure::from_bytes(&mut bytes);
+        self.payload = match result {
+            Ok(_) => bytes.into_rest(),
+            Err(_) => &[],
+        };
+        Some(result)
+    }
+}
+
+/// Errors detected when assembling the AD structures of an advertising or scan response PDU.
+#[derive(Debug, PartialEq, Eq)]
+pub enum AdDataError {
+    /// The encoded AD structures don't fit into the 31 Bytes available in the PDU.
+    TooLong,
+
+    /// A `Flags` AD structure was included in scan response data, where it is not allowed.
+    FlagsInScanResponse,
+
+    /// An AD type that may only appear once per PDU was included multiple times.
+    ///
+    /// This applies to `Flags`, the TX Power Level, the Appearance and the local name (only one of
+    /// `CompleteLocalName` or `ShortenedLocalName` may be used).
+    Duplicate {
+        /// The type Byte of the duplicate AD structure.
+        ty: u8,
+    },
+
+    /// An AD structure could not be encoded.
+    Encoding(Error),
+}
+
+impl From<AdDataError> for Error {
+    fn from(e: AdDataError) -> Self {
+        match e {
+            AdDataError::TooLong => Error::Eof,
+            AdDataError::FlagsInScanResponse | AdDataError::Duplicate { .. } => Error::InvalidValue,
+            AdDataError::Encoding(e) => e,
+        }
+    }
+}
+
+impl fmt::Display for AdDataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdDataError::TooLong => f.write_str("advertising data too long"),
+            AdDataError::FlagsInScanResponse => f.write_str("flags not allowed in scan response"),
+            AdDataError::Duplicate { ty } => write!(f, "duplicate AD type {:#04X}", ty),
+            AdDataError::Encoding(e) => write!(f, "failed to encode AD structure: {}", e),
+        }
+    }
+}
+
+/// Checks the encoded AD structures in `data` for combinations that are not allowed by the
+/// Core Specification Supplement.
+///
+/// `scan_response` indicates whether `data` is sent in a `SCAN_RSP` PDU.
+pub(crate) fn validate(data: &[u8], scan_response: bool) -> Result<(), AdDataError> {
+    // Bitmask of the once-only types seen so far (see `once_only_bit`)
+    let mut seen = 0u8;
+
+    for ad in AdStructures::new(data) {
+        let ty = match ad.map_err(AdDataError::Encoding)? {
+            AdStructure::Flags(_) => Type::FLAGS,
+            AdStructure::CompleteLocalName(_) => Type::COMPLETE_LOCAL_NAME,
+            AdStructure::ShortenedLocalName(_) => Type::SHORTENED_LOCAL_NAME,
+            AdStructure::TxPowerLevel(_) => Type::TX_POWER_LEVEL,
+            AdStructure::Appearance(_) => Type::APPEARANCE,
+            AdStructure::Unknown { ty, .. } => ty,
+            _ => continue,
+        };
+
+        if ty == Type::FLAGS && scan_response {
+            return Err(AdDataError::FlagsInScanResponse);
+        }
+
+        if let Some(bit) = once_only_bit(ty) {
+            if seen & bit != 0 {
+                return Err(AdDataError::Duplicate { ty });
+            }
+            seen |= bit;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns a distinct bit for each AD type that may only appear once per PDU.
+///
+/// Both local name types share a bit, since only one of them may be included.
+fn once_only_bit(ty: u8) -> Option<u8> {
+    match ty {
+        Type::FLAGS => Some(1 << 0),
+        Type::SHORTENED_LOCAL_NAME | Type::COMPLETE_LOCAL_NAME => Some(1 << 1),
+        Type::TX_POWER_LEVEL => Some(1 << 2),
+        Type::APPEARANCE => Some(1 << 3),
+        _ => None,
+    }
+}
+
 /// List of service UUIDs offered by the device.
 ///
 /// The list can be marked as complete or incomplete. For an incomplete list,
@@ -241,6 +502,71 @@ impl<'a, T: IsUuid> ToBytes for ServiceUuids<'a, T> {
     }
 }
 
+/// Data associated with a service UUID of type `T`.
+///
+/// The AD type used depends on the size of the UUID. Many advertising-based protocols (beacons,
+/// exposure notifications, Fast Pair, ...) are built on this.
+///
+/// ```
+/// use rubble::link::ad_structure::{AdStructure, ServiceData};
+/// use rubble::uuid::Uuid32;
+///
+/// let ad: AdStructure<'_> = ServiceData::new(Uuid32(0x12345678), &[0x01]).into();
+/// ```
+#[derive(Debug, Copy, Clone)]
+pub struct ServiceData<'a, T: IsUuid> {
+    uuid: T,
+    data: &'a [u8],
+}
+
+impl<'a, T: IsUuid> ServiceData<'a, T> {
+    /// Creates service data for the service `uuid`.
+    pub fn new(uuid: T, data: &'a [u8]) -> Self {
+        Self { uuid, data }
+    }
+
+    /// Returns the service UUID.
+    pub fn uuid(&self) -> T {
+        self.uuid
+    }
+
+    /// Returns the service data following the UUID. May be empty.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    fn type_() -> u8 {
+        match T::KIND {
+            UuidKind::Uuid16 => Type::SERVICE_DATA_16BIT_UUID,
+            UuidKind::Uuid32 => Type::SERVICE_DATA_32BIT_UUID,
+            UuidKind::Uuid128 => Type::SERVICE_DATA_128BIT_UUID,
+        }
+    }
+}
+
+/// Decodes `ServiceData` from the "Service Data - N-bit UUID" type matching `T`, followed by the
+/// UUID and the data.
+impl<'a, T: IsUuid> FromBytes<'a> for ServiceData<'a, T> {
+    fn from_bytes(bytes: &mut ByteReader<'a>) -> Result<Self, Error> {
+        if bytes.read_u8()? != Self::type_() {
+            return Err(Error::InvalidValue);
+        }
+
+        Ok(Self {
+            uuid: T::from_bytes(bytes)?,
+            data: bytes.read_rest(),
+        })
+    }
+}
+
+impl<'a, T: IsUuid> ToBytes for ServiceData<'a, T> {
+    fn to_bytes(&self, buffer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        buffer.write_u8(Self::type_())?;
+        self.uuid.to_bytes(buffer)?;
+        buffer.write_slice(self.data)
+    }
+}
+
 bitflags! {
     /// BR/EDR and LE compatibility flags.
     ///
@@ -362,3 +688,101 @@ impl Type {
     const _3D_INFORMATION_DATA: u8 = 0x3D;
     const MANUFACTURER_SPECIFIC_DATA: u8 = 0xFF;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_payload() {
+        let payload = [
+            0x02, 0x01, 0x06, // Flags
+            0x03, 0x03, 0x0F, 0x18, // 16-bit UUIDs: Battery Service
+            0x04, 0x16, 0x0F, 0x18, 0x64, // Service Data: battery at 100%
+            0x03, 0x08, 0xFF, 0xFE, // Shortened name, invalid UTF-8
+            0x00, 0x00, // Padding
+        ];
+
+        let mut ads = AdStructures::new(&payload);
+        match ads.next() {
+            Some(Ok(AdStructure::Flags(flags))) => assert_eq!(flags, Flags::discoverable()),
+            other => panic!("unexpected {:?}", other),
+        }
+        match ads.next() {
+            Some(Ok(AdStructure::ServiceUuids16(uuids))) => {
+                assert!(uuids.is_complete());
+                assert_eq!(uuids.iter().next(), Some(Uuid16(0x180F)));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        match ads.next() {
+            Some(Ok(AdStructure::ServiceData16 { uuid, data })) => {
+                assert_eq!((uuid, data), (0x180F, &[0x64][..]));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        match ads.next() {
+            Some(Ok(AdStructure::Unknown { ty, data })) => {
+                assert_eq!((ty, data), (Type::SHORTENED_LOCAL_NAME, &[0xFF, 0xFE][..]));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(ads.next().is_none());
+
+        assert!(AdStructures::new(&payload).contains_service_uuid(Uuid16(0x180F)));
+        assert!(!AdStructures::new(&payload).contains_service_uuid(Uuid16(0x180D)));
+    }
+
+    #[test]
+    fn local_name() {
+        match AdStructure::local_name("Grüße", 7) {
+            AdStructure::CompleteLocalName(name) => assert_eq!(name, "Grüße"),
+            other => panic!("unexpected {:?}", other),
+        }
+        // "ß" is 2 Bytes long and doesn't fit anymore
+        match AdStructure::local_name("Grüße", 5) {
+            AdStructure::ShortenedLocalName(name) => assert_eq!(name, "Grü"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn service_data() {
+        let uuid = Uuid::from_bytes([0xAB; 16]);
+        let ad = AdStructure::from(ServiceData::new(uuid, &[1, 2]));
+        let mut buf = [0; 20];
+        ad.to_bytes(&mut ByteWriter::new(&mut buf)).unwrap();
+        assert_eq!(buf[..2], [19, Type::SERVICE_DATA_128BIT_UUID]);
+
+        let decoded = AdStructure::from_bytes(&mut ByteReader::new(&buf)).unwrap();
+        let (decoded_uuid, data) = decoded.service_data().unwrap();
+        assert_eq!(decoded_uuid, DynUuid::from(uuid));
+        assert_eq!(data, &[1, 2]);
+    }
+
+    #[test]
+    fn validate_pdu_rules() {
+        let flags = [0x02, 0x01, 0x06];
+        assert_eq!(validate(&flags, false), Ok(()));
+        assert_eq!(
+            validate(&flags, true),
+            Err(AdDataError::FlagsInScanResponse)
+        );
+
+        let names = [0x02, 0x09, b'a', 0x02, 0x08, b'a'];
+        assert_eq!(
+            validate(&names, false),
+            Err(AdDataError::Duplicate {
+                ty: Type::SHORTENED_LOCAL_NAME
+            })
+        );
+    }
+
+    #[test]
+    fn truncated() {
+        let mut ads = AdStructures::new(&[0x02, 0x01, 0x06, 0x05, 0x09, b'a']);
+        assert!(ads.next().unwrap().is_ok());
+        assert_eq!(ads.next().unwrap().unwrap_err(), Error::Eof);
+        assert!(ads.next().is_none());
+    }
+}
@@ -0,0 +1,539 @@
+//! Multiple concurrent advertising sets.
+//!
+//! An *advertising set* is an independent advertisement with its own PDU, interval and advertiser
+//! address. A device can run several of them at once, for example a non-connectable iBeacon next
+//! to a connectable advertisement for its GATT server.
+//!
+//! The Link-Layer time-multiplexes all sets passed to `LinkLayer::start_advertising_sets`: Every
+//! time the timer fires, the set that is due next transmits its PDU. Sets that become due at the
+//! same time are sent one after another.
+//!
+//! All sets stop advertising once a connection has been established via one of the connectable
+//! sets, since the connection takes the packet queues. If the `LinkLayer` supports more
+//! connections, advertising can be restarted with new queues while connected.
+//!
+//! An `AdvertisingSetBuilder` can be used to create a set from more AD structures than fit into
+//! a single PDU: It moves the less important ones into the scan response.
+
+use crate::{
+    bytes::{ByteWriter, ToBytes},
+    link::{
+        ad_structure::{AdDataError, AdStructure},
+        advertising::{PduBuf, PduType, MAX_PAYLOAD_SIZE},
+        AddressKind, DeviceAddress,
+    },
+    phy::{AdvChannelMap, AdvertisingChannel, TxPower},
+    time::{Duration, Instant},
+    Error,
+};
+
+/// Maximum number of advertising sets that can be run at the same time.
+pub const MAX_ADV_SETS: usize = 4;
+
+/// Minimum time between the transmissions of 2 different sets.
+///
+/// This leaves enough time for listening for scan and connect requests.
+pub(super) fn set_spacing() -> Duration {
+    Duration::from_millis(2)
+}
+
+/// A single advertisement.
+pub struct AdvertisingSet {
+    pdu: PduBuf,
+    scan_response: PduBuf,
+    interval: Duration,
+    next_adv: Instant,
+    channel: AdvertisingChannel,
+    channels: AdvChannelMap,
+}
+
+impl AdvertisingSet {
+    /// Creates an advertising set that sends `pdu` every `interval`.
+    ///
+    /// The advertiser address of the PDU is also used for scan responses, which don't contain any
+    /// data by default.
+    pub fn new(pdu: PduBuf, interval: Duration) -> Self {
+        Self {
+            scan_response: PduBuf::empty_scan_response(advertiser_address(&pdu)),
+            pdu,
+            interval,
+            next_adv: Instant::from_raw_micros(0),
+            channel: AdvertisingChannel::first(),
+            channels: AdvChannelMap::ALL,
+        }
+    }
+
+    /// Restricts the set to advertise on `channels` only.
+    pub fn with_channels(mut self, channels: AdvChannelMap) -> Self {
+        self.channels = channels;
+        self.channel = channels.first();
+        self
+    }
+
+    /// Returns the advertising channels this set uses.
+    pub fn channels(&self) -> AdvChannelMap {
+        self.channels
+    }
+
+    /// Sets the data to send in response to scan requests.
+    ///
+    /// This only has an effect for scannable PDUs (`ADV_IND` and `ADV_SCAN_IND`).
+    pub fn with_scan_data(mut self, scan_data: &[AdStructure<'_>]) -> Result<Self, Error> {
+        self.scan_response = PduBuf::scan_response(self.address(), scan_data)?;
+        Ok(self)
+    }
+
+    /// Returns the address this set is advertising with.
+    pub fn address(&self) -> DeviceAddress {
+        advertiser_address(&self.pdu)
+    }
+
+    /// Returns the advertising interval.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Returns the PDU sent by this set.
+    pub fn pdu(&self) -> &PduBuf {
+        &self.pdu
+    }
+
+    /// Returns the scan response sent by this set.
+    pub fn scan_response(&self) -> &PduBuf {
+        &self.scan_response
+    }
+
+    /// Returns whether a peer can connect to this set.
+    pub fn is_connectable(&self) -> bool {
+        matches!(
+            self.pdu.header().type_(),
+            PduType::AdvInd | PduType::AdvDirectInd
+        )
+    }
+
+    /// Returns whether a peer can send scan requests to this set.
+    pub fn is_scannable(&self) -> bool {
+        matches!(
+            self.pdu.header().type_(),
+            PduType::AdvInd | PduType::AdvScanInd
+        )
+    }
+
+    /// Returns the channel to send the next PDU on, and schedules the next transmission.
+    pub(super) fn advance(&mut self) -> AdvertisingChannel {
+        self.channel = self.channel.cycle_in(self.channels);
+        self.next_adv += self.interval;
+        self.channel
+    }
+}
+
+/// Builds an `AdvertisingSet` from AD structures that may not all fit into the advertising PDU.
+///
+/// The AD structures are assigned to the advertising PDU in order of importance, and those that
+/// don't fit anymore are moved to the scan response (if the advertisement is scannable). From most
+/// to least important:
+///
+/// 1. `Flags` (never moved, since they are not allowed in scan responses)
+/// 2. Service UUID lists, which scanners commonly filter on
+/// 3. Service data
+/// 4. Other (`Unknown`) AD structures, like manufacturer data
+/// 5. `ShortenedLocalName`
+/// 6. `CompleteLocalName`
+///
+/// AD structures of the same importance are assigned in the order they are passed in, and each
+/// PDU keeps the original order.
+///
+/// If a `CompleteLocalName` doesn't fit into either PDU, it is replaced by a `ShortenedLocalName`
+/// filling the PDU with more space left (see `AdStructure::local_name`).
+///
+/// ```
+/// use rubble::link::{
+///     ad_structure::{AdStructure, Flags},
+///     adv_set::AdvertisingSetBuilder,
+///     AddressKind, DeviceAddress,
+/// };
+/// use rubble::time::Duration;
+///
+/// let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+/// let set = AdvertisingSetBuilder::new(addr, Duration::from_millis(100))
+///     .build(&[
+///         AdStructure::from(Flags::discoverable()),
+///         AdStructure::CompleteLocalName("A rather long device name"),
+///         AdStructure::Unknown { ty: 0xFF, data: &[0x59, 0x00, 1, 2, 3] },
+///     ])
+///     .unwrap();
+///
+/// // The name didn't fit and was moved into the scan response
+/// assert_eq!(set.pdu().payload().len(), 6 + 3 + 7);
+/// assert_eq!(set.scan_response().payload().len(), 6 + 27);
+/// ```
+pub struct AdvertisingSetBuilder {
+    addr: DeviceAddress,
+    interval: Duration,
+    ty: PduType,
+    channels: AdvChannelMap,
+    tx_power: Option<TxPower>,
+    appearance: Option<u16>,
+}
+
+impl AdvertisingSetBuilder {
+    /// Creates a builder for a connectable advertisement (`ADV_IND`) sent every `interval`.
+    pub fn new(addr: DeviceAddress, interval: Duration) -> Self {
+        Self {
+            addr,
+            interval,
+            ty: PduType::AdvInd,
+            channels: AdvChannelMap::ALL,
+            tx_power: None,
+            appearance: None,
+        }
+    }
+
+    /// Adds a `TxPowerLevel` AD structure advertising `power`.
+    ///
+    /// This should be the level the radio actually transmits with, as returned by
+    /// `LinkLayer::effective_advertising_tx_power`.
+    pub fn tx_power_level(mut self, power: TxPower) -> Self {
+        self.tx_power = Some(power);
+        self
+    }
+
+    /// Adds an `Appearance` AD structure with the given value of the GAP *Appearance*
+    /// characteristic.
+    ///
+    /// Use `GapServiceAttrs::appearance` to advertise the value exposed by the GAP service.
+    pub fn appearance(mut self, appearance: u16) -> Self {
+        self.appearance = Some(appearance);
+        self
+    }
+
+    /// Restricts the advertisement to `channels` (all 3 advertising channels are used by
+    /// default).
+    pub fn channels(mut self, channels: AdvChannelMap) -> Self {
+        self.channels = channels;
+        self
+    }
+
+    /// Makes the advertisement scannable, but not connectable (`ADV_SCAN_IND`).
+    pub fn scannable(mut self) -> Self {
+        self.ty = PduType::AdvScanInd;
+        self
+    }
+
+    /// Makes the advertisement neither connectable nor scannable (`ADV_NONCONN_IND`).
+    ///
+    /// There is no scan response then, so all AD structures must fit into the advertising PDU.
+    pub fn nonconnectable(mut self) -> Self {
+        self.ty = PduType::AdvNonconnInd;
+        self
+    }
+
+    /// Distributes `data` between the advertising PDU and the scan response, and creates the set.
+    ///
+    /// The AD structures requested via `tx_power_level` and `appearance` are added after `data`.
+    ///
+    /// Returns `AdDataError::TooLong` if the AD structures don't fit into the available PDUs, and
+    /// the other `AdDataError`s if they contain illegal combinations.
+    pub fn build(self, data: &[AdStructure<'_>]) -> Result<AdvertisingSet, AdDataError> {
+        let extra = [
+            self.tx_power
+                .map(|power| AdStructure::TxPowerLevel(power.as_dbm())),
+            self.appearance.map(AdStructure::Appearance),
+        ];
+        let all = || data.iter().chain(extra.iter().filter_map(Option::as_ref));
+
+        // Each AD structure takes at least 2 Bytes, so no more than this can ever fit
+        if all().count() > 32 {
+            return Err(AdDataError::TooLong);
+        }
+
+        let scannable = self.ty == PduType::AdvInd || self.ty == PduType::AdvScanInd;
+        let mut adv_left = MAX_AD_DATA;
+        let mut rsp_left = if scannable { MAX_AD_DATA } else { 0 };
+        // Bit `i` is set if `data[i]` goes into the scan response
+        let mut in_rsp = 0u32;
+        // Replacement for a `CompleteLocalName` that doesn't fit, and its index in `data`
+        let mut shortened = None;
+
+        for prio in 0..=MAX_PRIORITY {
+            for (i, ad) in all().enumerate() {
+                if priority(ad) != prio {
+                    continue;
+                }
+
+                let len = encoded_len(ad)?;
+                if len <= adv_left {
+                    adv_left -= len;
+                } else if len <= rsp_left && prio != 0 {
+                    rsp_left -= len;
+                    in_rsp |= 1 << i;
+                } else if let AdStructure::CompleteLocalName(name) = ad {
+                    // Length and type Bytes take up 2 Bytes of the space that's left
+                    let in_scan_rsp = rsp_left > adv_left;
+                    let left = if in_scan_rsp { rsp_left } else { adv_left };
+                    let name = match AdStructure::local_name(name, left.saturating_sub(2)) {
+                        AdStructure::ShortenedLocalName(name) if !name.is_empty() => name,
+                        _ => return Err(AdDataError::TooLong),
+                    };
+
+                    let len = name.len() + 2;
+                    if in_scan_rsp {
+                        rsp_left -= len;
+                        in_rsp |= 1 << i;
+                    } else {
+                        adv_left -= len;
+                    }
+                    shortened = Some((i, AdStructure::ShortenedLocalName(name)));
+                } else {
+                    return Err(AdDataError::TooLong);
+                }
+            }
+        }
+
+        let shortened = shortened.as_ref();
+        let selected = |rsp: bool| {
+            all()
+                .enumerate()
+                .filter(move |(i, _)| (in_rsp & (1 << *i) != 0) == rsp)
+                .map(move |(i, ad)| match shortened {
+                    Some((index, name)) if *index == i => name,
+                    _ => ad,
+                })
+        };
+        let pdu = PduBuf::adv(self.ty, self.addr, &mut selected(false))?;
+        let scan_response = PduBuf::adv(PduType::ScanRsp, self.addr, &mut selected(true))?;
+        Ok(AdvertisingSet {
+            pdu,
+            scan_response,
+            interval: self.interval,
+            next_adv: Instant::from_raw_micros(0),
+            channel: self.channels.first(),
+            channels: self.channels,
+        })
+    }
+}
+
+/// Space available for AD structures in an advertising PDU, after the advertiser address.
+const MAX_AD_DATA: usize = MAX_PAYLOAD_SIZE - 6;
+
+/// Lowest importance returned by `priority`.
+const MAX_PRIORITY: u8 = 5;
+
+/// Returns the importance of `ad` when distributing it (0 is the most important).
+fn priority(ad: &AdStructure<'_>) -> u8 {
+    match ad {
+        AdStructure::Flags(_) => 0,
+        AdStructure::ServiceUuids16(_)
+        | AdStructure::ServiceUuids32(_)
+        | AdStructure::ServiceUuids128(_) => 1,
+        AdStructure::ServiceData16 { .. }
+        | AdStructure::ServiceData32(_)
+        | AdStructure::ServiceData128(_) => 2,
+        AdStructure::ShortenedLocalName(_) => 4,
+        AdStructure::CompleteLocalName(_) => 5,
+        _ => 3,
+    }
+}
+
+/// Returns the number of Bytes `ad` occupies in a PDU.
+fn encoded_len(ad: &AdStructure<'_>) -> Result<usize, AdDataError> {
+    let mut buf = [0; MAX_AD_DATA];
+    let mut writer = ByteWriter::new(&mut buf);
+    match ad.to_bytes(&mut writer) {
+        Ok(()) => Ok(MAX_AD_DATA - writer.space_left()),
+        Err(Error::Eof) => Err(AdDataError::TooLong),
+        Err(e) => Err(AdDataError::Encoding(e)),
+    }
+}
+
+/// Extracts the advertiser address (`AdvA`) from an advertising PDU.
+fn advertiser_address(pdu: &PduBuf) -> DeviceAddress {
+    let mut bytes = [0; 6];
+    if let Some(adv_a) = pdu.payload().get(..6) {
+        bytes.copy_from_slice(adv_a);
+    }
+    let kind = if pdu.header().tx_add() {
+        AddressKind::Random
+    } else {
+        AddressKind::Public
+    };
+    DeviceAddress::new(bytes, kind)
+}
+
+/// A collection of advertising sets, identified by their handle.
+pub struct AdvertisingSets {
+    sets: [Option<AdvertisingSet>; MAX_ADV_SETS],
+}
+
+impl AdvertisingSets {
+    /// Creates an empty collection.
+    pub fn new() -> Self {
+        Self {
+            sets: [None, None, None, None],
+        }
+    }
+
+    /// Adds an advertising set and returns its handle.
+    ///
+    /// Returns `Error::Eof` if there already are `MAX_ADV_SETS` sets.
+    pub fn add(&mut self, set: AdvertisingSet) -> Result<u8, Error> {
+        let (handle, slot) = self
+            .sets
+            .iter_mut()
+            .enumerate()
+            .find(|(_, slot)| slot.is_none())
+            .ok_or(Error::Eof)?;
+        *slot = Some(set);
+        Ok(handle as u8)
+    }
+
+    /// Removes the set with the given handle, returning it.
+    pub fn remove(&mut self, handle: u8) -> Option<AdvertisingSet> {
+        self.sets.get_mut(usize::from(handle))?.take()
+    }
+
+    /// Returns the set with the given handle.
+    pub fn get(&self, handle: u8) -> Option<&AdvertisingSet> {
+        self.sets.get(usize::from(handle))?.as_ref()
+    }
+
+    /// Returns the set with the given handle.
+    pub fn get_mut(&mut self, handle: u8) -> Option<&mut AdvertisingSet> {
+        self.sets.get_mut(usize::from(handle))?.as_mut()
+    }
+
+    /// Returns whether there are no sets in this collection.
+    pub fn is_empty(&self) -> bool {
+        self.sets.iter().all(Option::is_none)
+    }
+
+    /// Returns an iterator over all sets and their handles.
+    pub fn iter(&self) -> impl Iterator<Item = (u8, &AdvertisingSet)> + '_ {
+        self.sets
+            .iter()
+            .enumerate()
+            .filter_map(|(i, set)| set.as_ref().map(|set| (i as u8, set)))
+    }
+
+    /// Schedules all sets to start advertising at `start`, spaced apart.
+    pub(super) fn start(&mut self, start: Instant) {
+        let mut next = start;
+        for set in self.sets.iter_mut().filter_map(Option::as_mut) {
+            set.next_adv = next;
+            next += set_spacing();
+        }
+    }
+
+    /// Returns the handle of the set that is due next, relative to `now`.
+    pub(super) fn next_due(&self, now: Instant) -> Option<u8> {
+        self.iter()
+            .min_by_key(|(_, set)| time_key(now, set.next_adv))
+            .map(|(handle, _)| handle)
+    }
+
+    /// Returns the time at which the next set is due.
+    pub(super) fn next_adv(&self, now: Instant) -> Option<Instant> {
+        Some(self.get(self.next_due(now)?)?.next_adv)
+    }
+
+    /// Determines when to transmit next, given that a set has just been transmitted at `now`.
+    pub(super) fn next_update(&self, now: Instant) -> Option<Instant> {
+        let next = self.next_adv(now)?;
+        let earliest = now + set_spacing();
+        if time_key(now, next) < time_key(now, earliest) {
+            Some(earliest)
+        } else {
+            Some(next)
+        }
+    }
+}
+
+impl Default for AdvertisingSets {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Maps `t` to a value that can be compared with the values of other `Instant`s near `now`.
+///
+/// `Instant`s wrap around, so they can't be compared directly. Instants before `now` (sets that
+/// are overdue) compare as earlier than `now`.
+fn time_key(now: Instant, t: Instant) -> u32 {
+    let base = now - Instant::MAX_TIME_BETWEEN;
+    t.raw_micros().wrapping_sub(base.raw_micros())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{link::ad_structure::ServiceUuids, uuid::Uuid16};
+
+    fn set(interval_ms: u16) -> AdvertisingSet {
+        let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+        AdvertisingSet::new(
+            PduBuf::beacon(addr, &[]).unwrap(),
+            Duration::from_millis(interval_ms),
+        )
+    }
+
+    #[test]
+    fn multiplexing() {
+        let mut sets = AdvertisingSets::new();
+        assert_eq!(sets.add(set(100)).unwrap(), 0);
+        assert_eq!(sets.add(set(30)).unwrap(), 1);
+        assert!(!sets.get(0).unwrap().is_connectable());
+
+        let t0 = Instant::from_raw_micros(10);
+        sets.start(t0);
+        assert_eq!(sets.next_due(t0), Some(0));
+        sets.get_mut(0).unwrap().advance();
+
+        // Set 1 was scheduled 2 ms after set 0
+        assert_eq!(sets.next_due(t0), Some(1));
+        let next = sets.next_update(t0).unwrap();
+        assert_eq!((next - t0).as_micros(), 2_000);
+        sets.get_mut(1).unwrap().advance();
+
+        // Set 1 is due again at 32 ms, before set 0 at 100 ms
+        assert_eq!(sets.next_due(next), Some(1));
+        let next = sets.next_update(next).unwrap();
+        assert_eq!((next - t0).as_micros(), 32_000);
+
+        assert!(sets.remove(1).is_some());
+        assert_eq!(sets.next_due(next), Some(0));
+    }
+
+    #[test]
+    fn split() {
+        let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+        let name = AdStructure::CompleteLocalName("abcdefghijklmnopqrstuvwxyz");
+        let uuids = AdStructure::ServiceUuids16(ServiceUuids::from_uuids(true, &[Uuid16(0x180F)]));
+        let builder = || AdvertisingSetBuilder::new(addr, Duration::from_millis(100));
+
+        // The name is listed first, but less important than the UUIDs
+        let set = builder().scannable().build(&[name, uuids]).unwrap();
+        assert_eq!(set.pdu().payload().len(), 6 + 4);
+        assert_eq!(set.scan_response().payload().len(), 6 + 28);
+
+        // Without a scan response, the name is shortened to fit next to the UUIDs
+        let set = builder().nonconnectable().build(&[name, uuids]).unwrap();
+        assert_eq!(set.pdu().payload().len(), 6 + 31);
+        let payload = &set.pdu().payload()[6..];
+        assert_eq!(payload[..2], [26, 0x08]);
+        assert_eq!(&payload[2..27], b"abcdefghijklmnopqrstuvwxy");
+    }
+
+    #[test]
+    fn generated_ads() {
+        let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+        let set = AdvertisingSetBuilder::new(addr, Duration::from_millis(100))
+            .tx_power_level(TxPower::from_dbm(-4))
+            .appearance(0x0040)
+            .build(&[])
+            .unwrap();
+        assert_eq!(
+            &set.pdu().payload()[6..],
+            &[0x02, 0x0A, 0xFC, 0x03, 0x19, 0x40, 0x00]
+        );
+    }
+}
@@ -11,7 +11,7 @@ use {
     crate::{
         bytes::*,
         link::{
-            ad_structure::{AdStructure, Flags},
+            ad_structure::{self, AdDataError, AdStructure, Flags},
             channel_map::ChannelMap,
             AddressKind, DeviceAddress,
         },
@@ -409,12 +409,17 @@ impl ConnectRequestData {
     pub fn supervision_timeout(&self) -> Duration {
         self.timeout
     }
+
+    /// Returns the master's sleep clock accuracy.
+    pub fn sleep_clock_accuracy(&self) -> SleepClockAccuracy {
+        self.sca
+    }
 }
 
 impl FromBytes<'_> for ConnectRequestData {
     fn from_bytes(bytes: &mut ByteReader<'_>) -> Result<Self, Error> {
         let sca;
-        Ok(Self {
+        let data = Self {
             access_address: Hex(bytes.read_u32_le()?),
             crc_init: {
                 let mut le_bytes = [0u8; 4];
@@ -447,11 +452,21 @@ impl FromBytes<'_> for ConnectRequestData {
                     4 => Ppm51To75,
                     5 => Ppm31To50,
                     6 => Ppm21To30,
-                    7 => Ppm0To20,
-                    _ => unreachable!(), // only 3 bits
+                    _ => Ppm0To20, // 7, only 3 bits
                 }
             },
-        })
+        };
+
+        // Out-of-range parameters would break channel selection and connection timing later on
+        if data.chm.num_used_channels() < 2
+            || data.hop < 5
+            || data.hop > 16
+            || data.interval < Duration::from_micros(7_500)
+            || data.interval > Duration::from_secs(4)
+        {
+            return Err(Error::InvalidValue);
+        }
+        Ok(data)
     }
 }
 
@@ -471,6 +486,40 @@ pub enum SleepClockAccuracy {
     Ppm0To20,
 }
 
+impl SleepClockAccuracy {
+    /// Returns the smallest accuracy range that includes `ppm`.
+    ///
+    /// Values above 500 ppm are not allowed by the spec and are mapped to `Ppm251To500`.
+    pub fn from_ppm(ppm: u16) -> Self {
+        use self::SleepClockAccuracy::*;
+        match ppm {
+            0..=20 => Ppm0To20,
+            21..=30 => Ppm21To30,
+            31..=50 => Ppm31To50,
+            51..=75 => Ppm51To75,
+            76..=100 => Ppm76To100,
+            101..=150 => Ppm101To150,
+            151..=250 => Ppm151To250,
+            _ => Ppm251To500,
+        }
+    }
+
+    /// Returns the worst-case accuracy in ppm (the upper bound of the range).
+    pub fn max_ppm(&self) -> u16 {
+        use self::SleepClockAccuracy::*;
+        match self {
+            Ppm251To500 => 500,
+            Ppm151To250 => 250,
+            Ppm101To150 => 150,
+            Ppm76To100 => 100,
+            Ppm51To75 => 75,
+            Ppm31To50 => 50,
+            Ppm21To30 => 30,
+            Ppm0To20 => 20,
+        }
+    }
+}
+
 /// Stores an advertising channel PDU.
 ///
 /// This is an owned version of `Pdu` and should be used when *creating* a PDU
@@ -485,20 +534,30 @@ pub struct PduBuf {
 
 impl PduBuf {
     /// Builds a PDU buffer containing advertiser address and data.
-    fn adv(
+    ///
+    /// The data is checked against the rules for the PDU type, so that no non-compliant PDUs are
+    /// sent.
+    pub(crate) fn adv(
         ty: PduType,
         adv: DeviceAddress,
         adv_data: &mut dyn Iterator<Item = &AdStructure<'_>>,
-    ) -> Result<Self, Error> {
+    ) -> Result<Self, AdDataError> {
         let mut payload = [0; MAX_PAYLOAD_SIZE];
         let mut buf = ByteWriter::new(&mut payload[..]);
-        buf.write_slice(adv.raw()).unwrap();
+        buf.write_slice(adv.raw()).map_err(AdDataError::Encoding)?;
         for ad in adv_data {
-            ad.to_bytes(&mut buf)?;
+            ad.to_bytes(&mut buf).map_err(|e| match e {
+                Error::Eof => AdDataError::TooLong,
+                e => AdDataError::Encoding(e),
+            })?;
         }
 
         let left = buf.space_left();
         let used = payload.len() - left;
+        ad_structure::validate(
+            payload.get(6..used).unwrap_or_default(),
+            ty == PduType::ScanRsp,
+        )?;
         let mut header = Header::new(ty);
         header.set_payload_length(used as u8);
         header.set_tx_add(adv.is_random());
@@ -519,7 +578,7 @@ impl PduBuf {
     pub fn connectable_undirected(
         advertiser_addr: DeviceAddress,
         advertiser_data: &[AdStructure<'_>],
-    ) -> Result<Self, Error> {
+    ) -> Result<Self, AdDataError> {
         Self::adv(
             PduType::AdvInd,
             advertiser_addr,
@@ -555,7 +614,7 @@ impl PduBuf {
     pub fn nonconnectable_undirected(
         advertiser_addr: DeviceAddress,
         advertiser_data: &[AdStructure<'_>],
-    ) -> Result<Self, Error> {
+    ) -> Result<Self, AdDataError> {
         Self::adv(
             PduType::AdvNonconnInd,
             advertiser_addr,
@@ -569,7 +628,7 @@ impl PduBuf {
     pub fn scannable_undirected(
         advertiser_addr: DeviceAddress,
         advertiser_data: &[AdStructure<'_>],
-    ) -> Result<Self, Error> {
+    ) -> Result<Self, AdDataError> {
         Self::adv(
             PduType::AdvScanInd,
             advertiser_addr,
@@ -586,7 +645,7 @@ impl PduBuf {
     pub fn beacon(
         advertiser_addr: DeviceAddress,
         advertiser_data: &[AdStructure<'_>],
-    ) -> Result<Self, Error> {
+    ) -> Result<Self, AdDataError> {
         Self::adv(
             PduType::AdvNonconnInd,
             advertiser_addr,
@@ -608,7 +667,7 @@ impl PduBuf {
     pub fn discoverable(
         advertiser_addr: DeviceAddress,
         advertiser_data: &[AdStructure<'_>],
-    ) -> Result<Self, Error> {
+    ) -> Result<Self, AdDataError> {
         // TODO what's the difference between "general" and "limited" discoverability?
         Self::adv(
             PduType::AdvInd,
@@ -619,7 +678,7 @@ impl PduBuf {
 
     /// Creates a scan request PDU.
     ///
-    /// Note that scanning is not yet implemented.
+    /// Note that the Link-Layer only scans passively and never sends these.
     ///
     /// # Parameters
     ///
@@ -627,8 +686,20 @@ impl PduBuf {
     ///   the request).
     /// * `adv`: Device address of the advertising device that this scan request
     ///   is directed towards.
-    pub fn scan_request(_scanner: DeviceAddress, _adv: DeviceAddress) -> Result<Self, Error> {
-        unimplemented!()
+    pub fn scan_request(scanner: DeviceAddress, adv: DeviceAddress) -> Result<Self, Error> {
+        let mut payload = [0; MAX_PAYLOAD_SIZE];
+        payload[0..6].copy_from_slice(scanner.raw());
+        payload[6..12].copy_from_slice(adv.raw());
+
+        let mut header = Header::new(PduType::ScanReq);
+        header.set_payload_length(6 + 6);
+        header.set_tx_add(scanner.is_random());
+        header.set_rx_add(adv.is_random());
+
+        Ok(Self {
+            header,
+            payload_buf: payload,
+        })
     }
 
     /// Creates a scan response PDU.
@@ -637,24 +708,24 @@ impl PduBuf {
     pub fn scan_response(
         advertiser_addr: DeviceAddress,
         scan_data: &[AdStructure<'_>],
-    ) -> Result<Self, Error> {
+    ) -> Result<Self, AdDataError> {
+        Self::adv(PduType::ScanRsp, advertiser_addr, &mut scan_data.iter())
+    }
+
+    /// Creates a scan response PDU that only contains the advertiser address.
+    pub(crate) fn empty_scan_response(advertiser_addr: DeviceAddress) -> Self {
         let mut payload = [0; MAX_PAYLOAD_SIZE];
-        let mut buf = ByteWriter::new(&mut payload[..]);
-        buf.write_slice(advertiser_addr.raw()).unwrap();
-        for ad in scan_data {
-            ad.to_bytes(&mut buf)?;
-        }
+        payload[0..6].copy_from_slice(advertiser_addr.raw());
 
-        let left = buf.space_left();
-        let used = payload.len() - left;
         let mut header = Header::new(PduType::ScanRsp);
-        header.set_payload_length(used as u8);
+        header.set_payload_length(6);
         header.set_tx_add(advertiser_addr.is_random());
         header.set_rx_add(false);
-        Ok(Self {
+
+        Self {
             header,
             payload_buf: payload,
-        })
+        }
     }
 
     pub fn header(&self) -> Header {
@@ -663,7 +734,7 @@ impl PduBuf {
 
     pub fn payload(&self) -> &[u8] {
         let len = self.header.payload_length() as usize;
-        &self.payload_buf[..len]
+        self.payload_buf.get(..len).unwrap_or_default()
     }
 }
 
@@ -703,7 +774,7 @@ impl Header {
     }
 
     pub fn parse(raw: &[u8]) -> Self {
-        Header(LittleEndian::read_u16(&raw))
+        Header(LittleEndian::read_u16(raw))
     }
 
     /// Returns the raw representation of the header.
@@ -768,7 +839,7 @@ impl Header {
     ///
     /// The `length` must be in range 6...37, otherwise this function panics.
     pub fn set_payload_length(&mut self, length: u8) {
-        assert!(6 <= length && length <= 37);
+        assert!((6..=37).contains(&length));
 
         let header = self.0 & !0b00111111_00000000;
         self.0 = header | (u16::from(length) << 8);
@@ -0,0 +1,164 @@
+//! Async timer and radio interfaces, and a driver running the `LinkLayer` on top of them.
+//!
+//! This module is only available when the `async` feature is enabled.
+//!
+//! Normally, the `LinkLayer` is driven from interrupt handlers: The timer interrupt calls
+//! `LinkLayer::update_timer`, and the radio interrupt passes received packets to
+//! `LinkLayer::process_*_packet`. Async HALs (like `embassy-nrf`) instead provide futures that
+//! complete when a deadline is reached or a packet was received. By implementing [`AsyncTimer`]
+//! and [`AsyncRadio`], such a HAL can run the Link-Layer via [`run`] without writing interrupt
+//! handlers or busy-waiting shims.
+//!
+//! Transmissions stay synchronous: The Link-Layer sends its responses from within
+//! `process_*_packet`, which has to happen within the inter-frame spacing of 150 µs after the
+//! received packet. Radios usually handle this with a hardware shortcut that starts the
+//! transmission automatically, so `Transmitter::transmit_*` only has to set up the packet.
+//!
+//! [`AsyncTimer`]: trait.AsyncTimer.html
+//! [`AsyncRadio`]: trait.AsyncRadio.html
+//! [`run`]: fn.run.html
+
+use {
+    crate::{
+        config::Config,
+        link::{advertising, data, Cmd, LinkLayer, NextUpdate, RadioCmd, Transmitter},
+        time::{Instant, Timer},
+    },
+    core::{
+        future::{self, Future},
+        pin::pin,
+        task::Poll,
+    },
+};
+
+/// A `Timer` that can wait for an `Instant`.
+pub trait AsyncTimer: Timer {
+    /// Returns a future that completes once `instant` has been reached.
+    ///
+    /// If `instant` is already in the past, the future must complete immediately.
+    fn wait_until(&mut self, instant: Instant) -> impl Future<Output = ()>;
+}
+
+/// Information about a packet received by an `AsyncRadio`.
+#[derive(Debug, Copy, Clone)]
+pub struct Received {
+    /// When the packet was fully received (after the CRC).
+    pub rx_end: Instant,
+
+    /// Whether the packet's CRC was correct.
+    pub crc_ok: bool,
+}
+
+/// A `Transmitter` that can also receive packets asynchronously.
+pub trait AsyncRadio: Transmitter {
+    /// Configures the radio to receive as specified by `cmd`, and waits for a packet.
+    ///
+    /// `cmd` is never `RadioCmd::Off`. The received packet's 2-Byte header and its payload must be
+    /// written to `buf`, which has room for the largest possible packet (257 Bytes). Packets with
+    /// the wrong Access Address must be ignored.
+    ///
+    /// The future may be dropped before it completes, when the Link-Layer's timer expires first.
+    /// The radio should then stop receiving.
+    fn receive(&mut self, cmd: &RadioCmd, buf: &mut [u8]) -> impl Future<Output = Received>;
+
+    /// Returns a future that completes when an ongoing transmission has finished.
+    ///
+    /// This is awaited before the radio is turned off, so that the last response (eg. to a
+    /// `LL_TERMINATE_IND`) is not cut short.
+    fn wait_idle(&mut self) -> impl Future<Output = ()>;
+}
+
+/// Drives `ll`, starting with `cmd`, until the Link-Layer goes to standby.
+///
+/// `cmd` is the `Cmd` returned by the method that made the Link-Layer leave standby, like
+/// `LinkLayer::start_advertise`. `queued_work` is called whenever the Link-Layer has put packets
+/// into the RX queue, and should wake up the task running the `Responder`.
+pub async fn run<C: Config, const CONNECTIONS: usize>(
+    ll: &mut LinkLayer<C, CONNECTIONS>,
+    radio: &mut C::Transmitter,
+    mut cmd: Cmd,
+    mut queued_work: impl FnMut(),
+) where
+    C::Timer: AsyncTimer,
+    C::Transmitter: AsyncRadio,
+{
+    let mut buf = [0; 2 + 255];
+    let mut deadline = None;
+
+    loop {
+        if cmd.queued_work {
+            queued_work();
+        }
+
+        match cmd.next_update {
+            NextUpdate::Disable => deadline = None,
+            NextUpdate::Keep => {}
+            NextUpdate::At(instant) => deadline = Some(instant),
+        }
+
+        let listening = match cmd.radio {
+            RadioCmd::Off => {
+                radio.wait_idle().await;
+                false
+            }
+            _ => true,
+        };
+
+        if !listening && deadline.is_none() {
+            return;
+        }
+
+        let timer = async {
+            match deadline {
+                Some(instant) => ll.timer().wait_until(instant).await,
+                None => future::pending().await,
+            }
+        };
+
+        let received = if listening {
+            select(radio.receive(&cmd.radio, &mut buf), timer).await
+        } else {
+            timer.await;
+            None
+        };
+
+        cmd = match received {
+            None => ll.update_timer(radio),
+            Some(rx) => {
+                let len = usize::from(buf[1]);
+                let payload = buf.get(2..2 + len).unwrap_or_default();
+                match cmd.radio {
+                    RadioCmd::ListenAdvertising { .. } => {
+                        let header = advertising::Header::parse(&buf[..2]);
+                        ll.process_adv_packet(rx.rx_end, radio, header, payload, rx.crc_ok)
+                    }
+                    _ => {
+                        let header = data::Header::parse(&buf[..2]);
+                        ll.process_data_packet(rx.rx_end, radio, header, payload, rx.crc_ok)
+                    }
+                }
+            }
+        };
+    }
+}
+
+/// Waits for `packet` or `timer`, whichever completes first.
+///
+/// Returns `None` if the timer completed first.
+async fn select(
+    packet: impl Future<Output = Received>,
+    timer: impl Future<Output = ()>,
+) -> Option<Received> {
+    let mut packet = pin!(packet);
+    let mut timer = pin!(timer);
+    future::poll_fn(|cx| {
+        if let Poll::Ready(rx) = packet.as_mut().poll(cx) {
+            return Poll::Ready(Some(rx));
+        }
+        if let Poll::Ready(()) = timer.as_mut().poll(cx) {
+            return Poll::Ready(None);
+        }
+        Poll::Pending
+    })
+    .await
+}
@@ -0,0 +1,181 @@
+//! AES-CCM packet encryption, as used by encrypted LE connections.
+//!
+//! Once encryption has been started on a connection, the payload of every data channel PDU is
+//! encrypted with AES-CCM and followed by a 4-Byte Message Integrity Check (MIC). The CCM nonce is
+//! built from a per-direction packet counter and the Initialization Vector exchanged during the
+//! encryption start procedure, so every packet is encrypted differently.
+//!
+//! This has to happen between receiving a packet and sending the response 150 µs later, which is
+//! too tight for a software AES implementation on most MCUs. Many BLE chips have a CCM engine that
+//! can do it instead (the nRF52 series can even encrypt and decrypt packets on the fly while the
+//! radio sends or receives them). Platforms expose such an engine by implementing [`CcmEngine`].
+//!
+//! Rubble does not implement the encryption start procedure (`LL_ENC_REQ`) yet, so nothing in the
+//! stack calls into the engine so far.
+//!
+//! [`CcmEngine`]: trait.CcmEngine.html
+
+use crate::Error;
+
+/// Length of the Message Integrity Check appended to encrypted payloads.
+pub const MIC_LEN: usize = 4;
+
+/// Mask of the data channel header bits that are authenticated by the MIC.
+///
+/// `NESN`, `SN` and `MD` change on retransmissions, so they are excluded.
+pub const HEADER_MASK: u8 = 0b1110_0011;
+
+/// Transfer direction of a packet, which is part of the CCM nonce.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Direction {
+    MasterToSlave,
+    SlaveToMaster,
+}
+
+/// Parameters for encrypting or decrypting a single packet.
+#[derive(Debug, Copy, Clone)]
+pub struct CcmParams {
+    /// The session key, most significant Byte first (as output by AES-128).
+    pub key: [u8; 16],
+
+    /// 39-bit packet counter of the packet's direction.
+    pub counter: u64,
+
+    /// Direction the packet is sent in.
+    pub direction: Direction,
+
+    /// Initialization Vector (`IVm` followed by `IVs`, each least significant Byte first).
+    pub iv: [u8; 8],
+}
+
+impl CcmParams {
+    /// Returns the 13-Byte CCM nonce for these parameters.
+    pub fn nonce(&self) -> [u8; 13] {
+        let mut nonce = [0; 13];
+        let counter = self.counter & COUNTER_MASK;
+        for (i, byte) in nonce[..5].iter_mut().enumerate() {
+            *byte = (counter >> (i * 8)) as u8;
+        }
+        if self.direction == Direction::MasterToSlave {
+            nonce[4] |= 0x80;
+        }
+        nonce[5..].copy_from_slice(&self.iv);
+        nonce
+    }
+}
+
+/// Packet counters only have 39 bits.
+const COUNTER_MASK: u64 = (1 << 39) - 1;
+
+/// Encryption state of a connection, as seen by the slave.
+#[derive(Debug, Clone)]
+pub struct CcmSession {
+    key: [u8; 16],
+    iv: [u8; 8],
+    tx_counter: u64,
+    rx_counter: u64,
+}
+
+impl CcmSession {
+    /// Creates a session using the session key and IV derived during the encryption start
+    /// procedure.
+    ///
+    /// Both packet counters start at 0.
+    pub fn new(key: [u8; 16], iv: [u8; 8]) -> Self {
+        Self {
+            key,
+            iv,
+            tx_counter: 0,
+            rx_counter: 0,
+        }
+    }
+
+    /// Returns the parameters for encrypting the next packet sent to the master.
+    pub fn tx_params(&self) -> CcmParams {
+        CcmParams {
+            key: self.key,
+            counter: self.tx_counter,
+            direction: Direction::SlaveToMaster,
+            iv: self.iv,
+        }
+    }
+
+    /// Returns the parameters for decrypting the next packet received from the master.
+    pub fn rx_params(&self) -> CcmParams {
+        CcmParams {
+            key: self.key,
+            counter: self.rx_counter,
+            direction: Direction::MasterToSlave,
+            iv: self.iv,
+        }
+    }
+
+    /// Advances the TX packet counter.
+    ///
+    /// Must be called when the master has acknowledged a packet with a non-empty payload.
+    /// Retransmissions reuse the counter value, and empty packets aren't encrypted.
+    pub fn tx_acknowledged(&mut self) {
+        self.tx_counter = (self.tx_counter + 1) & COUNTER_MASK;
+    }
+
+    /// Advances the RX packet counter.
+    ///
+    /// Must be called for every new (not retransmitted) packet with a non-empty payload received
+    /// from the master.
+    pub fn rx_received(&mut self) {
+        self.rx_counter = (self.rx_counter + 1) & COUNTER_MASK;
+    }
+}
+
+/// Trait for AES-CCM engines that encrypt and decrypt data channel PDUs.
+///
+/// Empty payloads are neither encrypted nor get a MIC, so implementations don't have to handle
+/// them.
+pub trait CcmEngine {
+    /// Encrypts `payload` of a packet with header Byte `header`, writing the encrypted payload
+    /// followed by the MIC to `out`.
+    ///
+    /// Only the bits of `header` in `HEADER_MASK` are authenticated. Returns the number of Bytes
+    /// written to `out` (`payload.len() + MIC_LEN`), or `Error::Eof` if `out` is too small or the
+    /// engine can't handle payloads this long.
+    fn encrypt(
+        &mut self,
+        params: &CcmParams,
+        header: u8,
+        payload: &[u8],
+        out: &mut [u8],
+    ) -> Result<usize, Error>;
+
+    /// Decrypts `payload` (including the trailing MIC) of a received packet, writing the plain
+    /// payload to `out`.
+    ///
+    /// Returns the number of Bytes written to `out` (`payload.len() - MIC_LEN`). If the MIC doesn't
+    /// match, `Error::InvalidValue` is returned and the connection must be terminated. If
+    /// `payload` is shorter than the MIC, `Error::InvalidLength` is returned.
+    fn decrypt(
+        &mut self,
+        params: &CcmParams,
+        header: u8,
+        payload: &[u8],
+        out: &mut [u8],
+    ) -> Result<usize, Error>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nonce() {
+        let mut session = CcmSession::new([0; 16], [1, 2, 3, 4, 5, 6, 7, 8]);
+        session.rx_received();
+        assert_eq!(
+            session.rx_params().nonce(),
+            [1, 0, 0, 0, 0x80, 1, 2, 3, 4, 5, 6, 7, 8]
+        );
+
+        session.tx_counter = COUNTER_MASK;
+        session.tx_acknowledged();
+        assert_eq!(session.tx_params().nonce()[..5], [0; 5]);
+    }
+}
@@ -1,4 +1,7 @@
-use {crate::phy::DataChannel, core::fmt};
+use {
+    crate::phy::DataChannel,
+    core::{cmp, fmt},
+};
 
 /// A map marking data channels as used or unused.
 ///
@@ -46,11 +49,11 @@ impl ChannelMap {
 
     /// Returns whether the given data channel is marked as used.
     pub fn is_used(&self, channel: DataChannel) -> bool {
-        let byte = self.raw[channel.index() as usize / 8];
+        let byte = self.raw.get(usize::from(channel.index() / 8));
         let bitnum = channel.index() % 8;
         let mask = 1 << bitnum;
 
-        byte & mask != 0
+        byte.is_some_and(|byte| byte & mask != 0)
     }
 
     /// Returns an iterator over all data channels marked as used in this map.
@@ -69,15 +72,58 @@ impl ChannelMap {
             })
     }
 
-    /// Returns the `n`th channel marked as used.
+    /// Returns a channel map that uses at least `min` channels.
+    ///
+    /// If `self` already uses `min` or more channels, it is returned unchanged. Otherwise, unused
+    /// channels are added, spread evenly over the band, until the requirement is met. This is how
+    /// a master honors an `LL_MIN_USED_CHANNELS_IND` sent by the slave, even if it has classified
+    /// more channels as bad.
+    ///
+    /// `min` is limited to 37.
+    pub fn with_min_used_channels(&self, min: u8) -> Self {
+        let min = cmp::min(min, 37);
+        let mut map = *self;
+
+        // Step through the channels with a stride that's coprime to 37 (since 37 is prime, all
+        // strides are), so the added channels are spread out.
+        let mut channel = 0u8;
+        while map.num_used_channels < min {
+            let (byte, bit) = (usize::from(channel / 8), channel % 8);
+            if let Some(byte) = map.raw.get_mut(byte) {
+                if *byte & (1 << bit) == 0 {
+                    *byte |= 1 << bit;
+                    map.num_used_channels += 1;
+                }
+            }
+            channel = (channel + 12) % 37;
+        }
+
+        map
+    }
+
+    /// Applies a host channel classification to this channel map.
     ///
-    /// # Panics
+    /// `classification` marks the channels the host considers usable (eg. because a Wi-Fi
+    /// coexistence manager knows which channels overlap with an active Wi-Fi network). The result
+    /// only uses channels used by both maps, but at least `min` channels (and never less than the 2
+    /// required for a valid map), topped up like `with_min_used_channels` does.
     ///
-    /// This will panic when `n >= self.num_used_channels()`.
-    pub fn by_index(&self, n: u8) -> DataChannel {
-        self.iter_used()
-            .nth(n.into())
-            .expect("by_index: index out of bounds")
+    /// A master uses this to compute the channel map sent in `LL_CHANNEL_MAP_IND`, with `min` set to
+    /// the value of the slave's last `LL_MIN_USED_CHANNELS_IND`.
+    pub fn with_classification(&self, classification: &ChannelMap, min: u8) -> Self {
+        let mut raw = self.raw;
+        for (byte, class) in raw.iter_mut().zip(&classification.raw) {
+            *byte &= class;
+        }
+
+        Self::from_raw(raw).with_min_used_channels(cmp::max(min, 2))
+    }
+
+    /// Returns the `n`th channel marked as used.
+    ///
+    /// Returns `None` when `n >= self.num_used_channels()`.
+    pub fn by_index(&self, n: u8) -> Option<DataChannel> {
+        self.iter_used().nth(n.into())
     }
 }
 
@@ -113,7 +159,8 @@ mod tests {
         assert!(!map.is_used(DataChannel::new(7)));
         assert!(!map.is_used(DataChannel::new(8)));
         assert!(!map.is_used(DataChannel::new(36)));
-        assert_eq!(map.by_index(0), DataChannel::new(0));
+        assert_eq!(map.by_index(0), Some(DataChannel::new(0)));
+        assert_eq!(map.by_index(1), None);
         assert!(map.iter_used().eq(vec![DataChannel::new(0)]));
     }
 
@@ -124,6 +171,35 @@ mod tests {
         assert_eq!(map, ChannelMap::with_all_channels());
     }
 
+    #[test]
+    fn min_used_channels() {
+        let map = ChannelMap::from_raw([0x03, 0, 0, 0, 0]);
+        assert_eq!(map.with_min_used_channels(2), map);
+
+        let map = map.with_min_used_channels(8);
+        assert_eq!(map.num_used_channels(), 8);
+        assert!(map.is_used(DataChannel::new(0)));
+        assert!(map.is_used(DataChannel::new(1)));
+        assert_eq!(
+            map.with_min_used_channels(40),
+            ChannelMap::with_all_channels()
+        );
+    }
+
+    #[test]
+    fn classification() {
+        let map = ChannelMap::from_raw([0x0F, 0, 0, 0, 0]);
+        let class = ChannelMap::from_raw([0x06, 0xFF, 0, 0, 0]);
+        assert_eq!(
+            map.with_classification(&class, 0),
+            ChannelMap::from_raw([0x06, 0, 0, 0, 0])
+        );
+
+        let class = ChannelMap::from_raw([0x00, 0xFF, 0, 0, 0]);
+        assert_eq!(map.with_classification(&class, 0).num_used_channels(), 2);
+        assert_eq!(map.with_classification(&class, 9).num_used_channels(), 9);
+    }
+
     #[test]
     fn all_channels() {
         let map = ChannelMap::with_all_channels();
@@ -3,7 +3,7 @@
 use core::fmt;
 
 /// Company identifier for use in link layer Control PDUs.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq)]
 pub struct CompanyId(u16);
 
 impl fmt::Debug for CompanyId {
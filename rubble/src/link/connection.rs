@@ -8,18 +8,33 @@ use {
             advertising::ConnectRequestData,
             channel_map::ChannelMap,
             data::{self, Header, Llid, Pdu},
-            llcp::{ConnectionUpdateData, ControlPdu},
+            link_quality::{LinkQualityEvent, LinkQualityManager},
+            llcp::{
+                ConnectionUpdateData, ControlAction, ControlOpcode, ControlPdu, ControlPduHandler,
+                ErrorCode, Phys, SubrateIndication,
+            },
+            power_control::{self, PeerTxPower, PowerLimits},
             queue::{Consume, Consumer, Producer},
-            Cmd, CompanyId, FeatureSet, NextUpdate, RadioCmd, SeqNum, Transmitter,
+            scheduler::{Activity, Reservation, MIN_CONNECTION_EVENT},
+            stats::{Counter, Stats},
+            trace::{self, TracePoint},
+            Cmd, CompanyId, DeviceAddress, FeatureSet, NextUpdate, RadioCmd, SeqNum, Transmitter,
+            MIN_DATA_PAYLOAD_BUF,
         },
-        phy::DataChannel,
+        phy::{DataChannel, TxPower},
         time::{Duration, Instant, Timer},
         utils::{Hex, HexSlice},
         Error, BLUETOOTH_VERSION,
     },
-    core::{marker::PhantomData, num::Wrapping},
+    core::{cmp, marker::PhantomData, num::Wrapping},
 };
 
+/// Connection event length used when none is configured.
+///
+/// This leaves room for a few packets per event, while keeping events short enough to not get in
+/// the way of other radio activities.
+pub const DEFAULT_EVENT_LENGTH: Duration = Duration::from_micros(2_500);
+
 /// Connection state and parameters.
 pub struct Connection<C: Config> {
     access_address: u32,
@@ -62,6 +77,67 @@ pub struct Connection<C: Config> {
     /// Contains the *instant* at which it should be applied to the Link Layer state.
     update_data: Option<LlcpUpdate>,
 
+    /// Transmission power to use for all data channel PDUs sent in this connection.
+    tx_power: TxPower,
+
+    /// Controller adjusting `tx_power` to the link quality, if enabled.
+    link_quality: Option<LinkQualityManager>,
+
+    /// Address of the master, as sent in the `CONNECT_REQ`.
+    peer_address: DeviceAddress,
+
+    /// Estimated anchor point of the next connection event.
+    ///
+    /// This is `None` until the first packet was received from the master.
+    next_anchor: Option<Instant>,
+
+    /// Anchor point of the last connection event in which a packet was received.
+    ///
+    /// Before the first packet arrived, this is the end of the `CONNECT_REQ`. The receive window
+    /// is widened based on the time that has passed since then.
+    last_anchor: Instant,
+
+    /// Combined sleep clock accuracy of master and slave in ppm.
+    sca_ppm: u16,
+
+    /// Locally initiated LL Control procedure that is waiting for a response from the master.
+    procedure: Option<Procedure>,
+
+    /// Connection supervision timeout, as sent in the `CONNECT_REQ`.
+    supervision_timeout: Duration,
+
+    /// Time at which we sent an `LL_TERMINATE_IND`, if we are ending the connection.
+    termination: Option<Instant>,
+
+    /// Packet that was responded to, but whose connection event hasn't been finished yet.
+    pending: Option<PendingEvent>,
+
+    /// Maximum time from the anchor point to the end of the last packet of a connection event.
+    event_length: Duration,
+
+    /// The connection event that is still open, because the master or we had more data.
+    event: Option<EventProgress>,
+
+    /// Whether there's enough time left in the current event for another exchange of packets.
+    ///
+    /// Computed by `respond` before sending, and used for our MD bit.
+    more_data_allowed: bool,
+
+    /// Transmission power last reported by the master via LE Power Control.
+    peer_power: Option<PeerTxPower>,
+
+    /// Our transmission power as last reported to the master.
+    ///
+    /// This is `None` until the master has used power control. Afterwards, changes of `tx_power`
+    /// are reported with an `LL_POWER_CHANGE_IND`.
+    reported_power: Option<TxPower>,
+
+    /// Subrating parameters indicated by the master, if it has sent any.
+    subrate: Option<SubrateIndication>,
+
+    /// Number of connection events that stay in use after the last one with data exchanged.
+    continuation: u16,
+
     _p: PhantomData<C>,
 }
 
@@ -77,11 +153,22 @@ impl<C: Config> Connection<C> {
     /// * **`rx_end`**: Instant at which the `CONNECT_REQ` PDU was fully received.
     /// * **`tx`**: Channel for packets to transmit.
     /// * **`rx`**: Channel for received packets.
+    /// * **`tx_power`**: Initial transmission power to use for the connection.
+    /// * **`link_quality`**: Link-quality manager to adapt `tx_power` with, if any.
+    /// * **`peer_address`**: Address of the device that sent the `CONNECT_REQ`.
+    /// * **`local_sca_ppm`**: Accuracy of our sleep clock in ppm.
+    /// * **`event_length`**: Maximum length of connection events.
+    #[allow(clippy::too_many_arguments)] // the `LinkLayer` passes its parts individually
     pub(crate) fn create(
         lldata: &ConnectRequestData,
         rx_end: Instant,
         tx: C::PacketConsumer,
         rx: C::PacketProducer,
+        tx_power: TxPower,
+        link_quality: Option<LinkQualityManager>,
+        peer_address: DeviceAddress,
+        local_sca_ppm: u16,
+        event_length: Duration,
     ) -> (Self, Cmd) {
         let mut this = Self {
             access_address: lldata.access_address(),
@@ -102,6 +189,23 @@ impl<C: Config> Connection<C> {
             tx,
             rx,
             update_data: None,
+            tx_power,
+            link_quality,
+            peer_address,
+            next_anchor: None,
+            last_anchor: rx_end,
+            sca_ppm: lldata.sleep_clock_accuracy().max_ppm() + local_sca_ppm,
+            procedure: None,
+            supervision_timeout: lldata.supervision_timeout(),
+            termination: None,
+            pending: None,
+            event_length,
+            event: None,
+            more_data_allowed: false,
+            peer_power: None,
+            reported_power: None,
+            subrate: None,
+            continuation: 0,
 
             _p: PhantomData,
         };
@@ -110,9 +214,7 @@ impl<C: Config> Connection<C> {
         this.hop_channel();
 
         let cmd = Cmd {
-            next_update: NextUpdate::At(
-                rx_end + lldata.end_of_tx_window() + Duration::from_micros(500),
-            ),
+            next_update: NextUpdate::At(this.rx_deadline(rx_end + lldata.end_of_tx_window())),
             radio: RadioCmd::ListenData {
                 channel: this.channel,
                 access_address: this.access_address,
@@ -126,16 +228,55 @@ impl<C: Config> Connection<C> {
 
     /// Called by the `LinkLayer` when a data channel packet is received.
     ///
-    /// Returns `Err(())` when the connection is ended (not necessarily due to an error condition).
+    /// Runs both the time-critical `respond` and the deferred `finish_event`.
+    ///
+    /// Returns `Err` with the reason when the connection is ended (not necessarily due to an error
+    /// condition).
+    #[allow(clippy::too_many_arguments)] // the `LinkLayer` passes its parts individually
     pub(crate) fn process_data_packet(
         &mut self,
         rx_end: Instant,
         tx: &mut C::Transmitter,
         timer: &mut C::Timer,
+        hook: &mut Option<C::EventHook>,
+        handler: &mut Option<C::ControlHandler>,
+        stats: &mut Stats,
         header: data::Header,
         payload: &[u8],
         crc_ok: bool,
-    ) -> Result<Cmd, ()> {
+    ) -> Result<Cmd, ErrorCode> {
+        self.respond(rx_end, tx, handler, stats, header, payload, crc_ok)?;
+        trace!("DATA<- {:?}", HexSlice(payload));
+        self.finish_event(timer, hook)
+    }
+
+    /// The time-critical part of processing a received data channel packet.
+    ///
+    /// This does the acknowledgement bookkeeping, hands the received payload to the RX queue (or
+    /// processes it, if it's an LL Control PDU), and transmits the response. Everything that can
+    /// wait until the response is on air is left to `finish_event`, which must be called before
+    /// the next packet is received.
+    ///
+    /// The PDUs in the TX queue are already serialized, so sending one only copies its payload
+    /// into the radio buffer. No logging happens here, except for errors that end the connection:
+    /// What was received and sent is logged by `finish_event` instead.
+    #[allow(clippy::too_many_arguments)] // the `LinkLayer` passes its parts individually
+    pub(crate) fn respond(
+        &mut self,
+        rx_end: Instant,
+        tx: &mut C::Transmitter,
+        handler: &mut Option<C::ControlHandler>,
+        stats: &mut Stats,
+        header: data::Header,
+        payload: &[u8],
+        crc_ok: bool,
+    ) -> Result<(), ErrorCode> {
+        if !crc_ok {
+            stats.record(Counter::CrcError);
+        }
+        let rssi = tx.last_rssi();
+        let rx_timestamp = tx.rx_timestamp();
+
         // If the sequence number of the packet is the same as our next expected sequence number,
         // the packet contains new data that we should try to process. However, if the CRC is bad,
         // we'll never try to process the data and instead request a retransmission.
@@ -149,15 +290,35 @@ impl<C: Config> Connection<C> {
 
         let is_empty = header.llid() == Llid::DataCont && payload.is_empty();
 
+        // Another exchange has to fit behind our response, assuming full-length packets
+        let anchor = match &self.event {
+            Some(event) => event.anchor,
+            None => rx_end - packet_air_time(header.payload_length()),
+        };
+        let exchange = Duration::T_IFS + packet_air_time(MIN_DATA_PAYLOAD_BUF as u8);
+        let budget = cmp::min(
+            self.event_length,
+            self.conn_interval.saturating_sub(MIN_CONNECTION_EVENT),
+        );
+        let elapsed = rx_end.duration_since(anchor);
+        self.more_data_allowed = budget.saturating_sub(elapsed) >= exchange + exchange + exchange;
+
         if acknowledged {
             self.received_packet = true;
             self.transmit_seq_num += SeqNum::ONE;
+
+            if self.termination.is_some() {
+                // The master has acknowledged our `LL_TERMINATE_IND`, we're done.
+                return Err(ErrorCode::ConnectionTerminatedByLocalHost);
+            }
         }
 
         // Whether we've already sent a response packet.
         let mut responded = false;
         // Whether we've pushed more work into the RX queue.
         let mut queued_work = false;
+        // What to log once the response is on air.
+        let mut log = RespondLog::Nothing;
 
         if is_new {
             if is_empty {
@@ -173,34 +334,47 @@ impl<C: Config> Connection<C> {
                     // packet we sent, because we'll directly use the radio's TX buffer to send
                     // back the LLCP response.
 
-                    match self.process_control_pdu(pdu, acknowledged) {
+                    let supported = tx.supported_tx_power();
+                    match self.process_control_pdu(pdu, acknowledged, supported) {
                         Ok(Some(response)) => {
-                            self.next_expected_seq_num += SeqNum::ONE;
-
                             let rsp = Pdu::from(&response);
                             let mut payload_writer = ByteWriter::new(tx.tx_payload_buf());
                             let left = payload_writer.space_left();
-                            rsp.to_bytes(&mut payload_writer).unwrap();
-
-                            let mut header = Header::new(Llid::Control);
-                            let pl_len = (left - payload_writer.space_left()) as u8;
-                            header.set_payload_length(pl_len);
-                            self.send(header, tx);
-                            responded = true;
-
-                            info!("LLCP<- {:?}", pdu);
-                            info!("LLCP-> {:?}", response);
+                            // If the response doesn't fit in the TX buffer, the PDU isn't
+                            // acknowledged, so the master resends it
+                            if rsp.to_bytes(&mut payload_writer).is_ok() {
+                                self.next_expected_seq_num += SeqNum::ONE;
+
+                                let mut header = Header::new(Llid::Control);
+                                let pl_len = (left - payload_writer.space_left()) as u8;
+                                header.set_payload_length(pl_len);
+                                self.send(header, tx);
+                                responded = true;
+
+                                log = RespondLog::Llcp {
+                                    received: pdu.opcode(),
+                                    response: Some(response.opcode()),
+                                };
+                            }
                         }
                         Ok(None) => {
                             self.next_expected_seq_num += SeqNum::ONE;
 
-                            info!("LLCP<- {:?}", pdu);
-                            info!("LLCP-> (no response)");
+                            log = RespondLog::Llcp {
+                                received: pdu.opcode(),
+                                response: None,
+                            };
+                        }
+                        Err(LlcpError::ConnectionLost(reason)) => {
+                            return Err(reason);
                         }
-                        Err(LlcpError::ConnectionLost) => {
-                            return Err(());
+                        Err(LlcpError::Unhandled) if acknowledged => {
+                            self.next_expected_seq_num += SeqNum::ONE;
+                            responded = self.respond_unhandled(pdu, tx, handler);
+
+                            log = RespondLog::LlcpUnhandled(pdu.opcode());
                         }
-                        Err(LlcpError::NoSpace) => {
+                        Err(LlcpError::NoSpace) | Err(LlcpError::Unhandled) => {
                             // Do not acknowledge the PDU
                         }
                     }
@@ -212,22 +386,29 @@ impl<C: Config> Connection<C> {
                 // resent until we have space.
 
                 let result: Result<(), Error> =
-                    self.rx
-                        .produce_with(header.payload_length().into(), |writer| {
-                            writer.write_slice(payload)?;
-                            Ok(header.llid())
-                        });
+                    self.rx.produce_with(header.payload_length(), |writer| {
+                        writer.write_slice(payload)?;
+                        Ok(header.llid())
+                    });
 
                 if result.is_ok() {
                     // Acknowledge the packet
                     self.next_expected_seq_num += SeqNum::ONE;
                     queued_work = true;
+                } else if let Err(Error::InvalidLength) = result {
+                    // The master sent more than the data length we support. Retransmissions
+                    // won't fit either, so acknowledge and drop the packet.
+                    self.next_expected_seq_num += SeqNum::ONE;
+                    log = RespondLog::Oversized;
                 } else {
-                    trace!("NACK (no space in rx buffer)");
+                    stats.record(Counter::RxQueueFull);
+                    log = RespondLog::Nack;
                 }
             }
         }
 
+        trace::mark(TracePoint::PduParsed);
+
         if acknowledged {
             if !responded {
                 // Send a new data packet.
@@ -235,12 +416,36 @@ impl<C: Config> Connection<C> {
                 // Try to acquire PDU from the tx queue, fall back to an empty PDU.
                 let mut payload_writer = ByteWriter::new(tx.tx_payload_buf());
                 let header = match self.tx.consume_raw_with(|header, pl| {
-                    payload_writer.write_slice(pl).expect("TX buf out of space");
-                    Consume::always(Ok(header))
+                    // A PDU that doesn't fit in the TX buffer can never be sent, so drop it
+                    let result = payload_writer.write_slice(pl).map(|()| header);
+                    Consume::always(result)
                 }) {
                     Ok(h) => h,
-                    Err(_) => Header::new(Llid::DataCont),
+                    Err(_) => self
+                        .power_change_ind(tx)
+                        .unwrap_or_else(|| Header::new(Llid::DataCont)),
+                };
+
+                let opcode = match tx.tx_payload_buf().first() {
+                    Some(raw) if header.llid() == Llid::Control && header.payload_length() > 0 => {
+                        Some(ControlOpcode::from(*raw))
+                    }
+                    _ => None,
                 };
+                if let Some(opcode) = opcode {
+                    if opcode == ControlOpcode::TerminateInd {
+                        self.termination = Some(rx_end);
+                    }
+                    if opcode == ControlOpcode::PowerControlReq && header.payload_length() == 4 {
+                        // The `Responder` doesn't know our power, so fill it in now
+                        let level = self.tx_power.clamp_to(tx.supported_tx_power());
+                        if let Some(tx_power) = tx.tx_payload_buf().get_mut(3) {
+                            *tx_power = level.as_dbm() as u8;
+                            self.reported_power = Some(level);
+                        }
+                    }
+                    self.start_procedure(opcode, rx_end);
+                }
 
                 self.send(header, tx);
             }
@@ -249,86 +454,264 @@ impl<C: Config> Connection<C> {
             // If CRC is bad, this bit could be flipped, so we always retransmit in that case.
             if self.received_packet {
                 self.last_header.set_nesn(self.next_expected_seq_num);
+                tx.set_tx_power(self.tx_power);
+                trace::mark(TracePoint::ResponseReady);
                 tx.transmit_data(
                     self.access_address,
                     self.crc_init,
                     self.last_header,
                     self.channel,
                 );
-                trace!("<<RESENT>>");
+                stats.record(Counter::Retransmission);
             } else {
                 // We've never received (and thus sent) a data packet before, so we can't
                 // *re*transmit anything. Send empty PDU instead.
                 // (this should not really happen, though!)
                 self.received_packet = true;
-
-                let pdu = Pdu::empty();
-                let mut payload_writer = ByteWriter::new(tx.tx_payload_buf());
-                pdu.to_bytes(&mut payload_writer).unwrap();
-                self.send(Header::new(pdu.llid()), tx);
+                self.send(Header::new(Llid::DataCont), tx);
             }
         }
 
-        let last_channel = self.channel;
+        self.pending = Some(PendingEvent {
+            rx_end,
+            header,
+            crc_ok,
+            rssi,
+            rx_timestamp,
+            queued_work,
+            log,
+        });
+        Ok(())
+    }
 
-        // FIXME: Don't hop if one of the MD bits is set to true (also don't log then)
-        {
-            // Connection event closes
-            self.conn_event_count += Wrapping(1);
+    /// Finishes processing the packet last passed to `respond`.
+    ///
+    /// If the master or we have more data, and the event length allows another exchange, the
+    /// connection event stays open. Otherwise, it is closed by `close_event`.
+    ///
+    /// Returns the `Cmd` to apply to the radio and timer before the next packet.
+    pub(crate) fn finish_event(
+        &mut self,
+        timer: &mut C::Timer,
+        hook: &mut Option<C::EventHook>,
+    ) -> Result<Cmd, ErrorCode> {
+        let PendingEvent {
+            rx_end,
+            header,
+            crc_ok,
+            rssi,
+            rx_timestamp,
+            queued_work,
+            log,
+        } = match self.pending.take() {
+            Some(pending) => pending,
+            None => {
+                return Ok(Cmd {
+                    next_update: NextUpdate::Keep,
+                    radio: self.listen_cmd(),
+                    queued_work: false,
+                })
+            }
+        };
 
-            if let Some(update) = self.update_data.take() {
-                if update.instant() == self.conn_event_count.0 {
-                    // Next conn event will the the first one with these parameters.
-                    let result = self.apply_llcp_update(update, rx_end);
-                    info!("LLCP patch applied: {:?} -> {:?}", update, result);
-                    if let Some(mut cmd) = result {
-                        cmd.queued_work = queued_work;
-                        return Ok(cmd);
-                    }
-                } else {
-                    // Put it back
-                    self.update_data = Some(update);
+        match log {
+            RespondLog::Nothing => {}
+            RespondLog::Llcp { received, response } => {
+                info!("LLCP<- {:?}", received);
+                match response {
+                    Some(response) => info!("LLCP-> {:?}", response),
+                    None => info!("LLCP-> (no response)"),
                 }
             }
-
-            // Hop channels after applying LLCP update because it might change the channel map used
-            // by the next event
-            self.hop_channel();
+            RespondLog::LlcpUnhandled(received) => info!("LLCP<- {:?} (unhandled)", received),
+            RespondLog::Oversized => warn!("dropping oversized data PDU: {:?}", header),
+            RespondLog::Nack => trace!("NACK (no space in rx buffer)"),
+        }
+        trace!("DATA-> {:?}", self.last_header);
+
+        let payload = header.payload_length() > 0 || self.last_header.payload_length() > 0;
+        let mut progress = self.event.take().unwrap_or_else(|| EventProgress {
+            // The anchor point is where the master's first packet started
+            anchor: rx_end - packet_air_time(header.payload_length()),
+            first_rx_end: rx_end,
+            packets: 0,
+            crc_errors: 0,
+            queued_work: false,
+            payload: false,
+            rssi,
+            rx_timestamp,
+        });
+        progress.packets = progress.packets.saturating_add(1);
+        if !crc_ok {
+            progress.crc_errors = progress.crc_errors.saturating_add(1);
+        }
+        progress.queued_work |= queued_work;
+        progress.payload |= payload;
+
+        // The event goes on while either side has more data, as long as the budget allows
+        if crc_ok && self.more_data_allowed && (header.md() || self.last_header.md()) {
+            let next_packet = rx_end
+                + Duration::T_IFS
+                + packet_air_time(self.last_header.payload_length())
+                + Duration::T_IFS
+                + packet_air_time(MIN_DATA_PAYLOAD_BUF as u8);
+            self.event = Some(progress);
+            return Ok(Cmd {
+                next_update: NextUpdate::At(next_packet),
+                radio: self.listen_cmd(),
+                queued_work,
+            });
         }
 
         trace!(
-            "#{} DATA({}->{})<- {}{:?}, {:?}",
+            "#{} DATA({})<- {}{:?}",
             self.conn_event_count,
-            last_channel.index(),
             self.channel.index(),
             if crc_ok { "" } else { "BADCRC, " },
             header,
-            HexSlice(payload)
         );
+        let mut cmd = self.close_event(timer, hook, progress, header.md())?;
+        cmd.queued_work = queued_work;
+        Ok(cmd)
+    }
+
+    /// Closes the connection event described by `progress`.
+    ///
+    /// Computes the next anchor point, checks timeouts, applies LLCP updates whose instant has
+    /// come, hops to the next channel and reports the event to the `ConnectionEventHook`.
+    fn close_event(
+        &mut self,
+        timer: &mut C::Timer,
+        hook: &mut Option<C::EventHook>,
+        progress: EventProgress,
+        peer_more_data: bool,
+    ) -> Result<Cmd, ErrorCode> {
+        let anchor = progress.anchor;
+        let mut summary = ConnectionEventSummary {
+            event_counter: self.conn_event_count.0,
+            packets_received: progress.packets,
+            packets_sent: progress.packets,
+            crc_errors: progress.crc_errors,
+            more_data: peer_more_data || self.tx.has_data(),
+            time_to_next_anchor: Duration::from_micros(0),
+            rssi: progress.rssi,
+            rx_timestamp: progress.rx_timestamp,
+            payload_queued: progress.queued_work,
+            tx_power: self.tx_power,
+            link_quality: None,
+        };
+        if progress.crc_errors < progress.packets {
+            // Only packets with a valid CRC reset the supervision timer
+            self.last_anchor = anchor;
+        } else {
+            self.check_supervision_timeout(anchor)?;
+        }
+        self.next_anchor = Some(anchor + self.conn_interval);
+        self.check_timeouts(anchor)?;
+
+        // Connection event closes
+        self.conn_event_count += Wrapping(1);
+
+        if let Some(update) = self.update_data.take() {
+            if update.instant() == self.conn_event_count.0 {
+                // Next conn event will the the first one with these parameters.
+                let old_conn_interval = self.conn_interval;
+                let result = self.apply_llcp_update(update, progress.first_rx_end);
+                info!("LLCP patch applied: {:?} -> {:?}", update, result);
+                if let Some(cmd) = result {
+                    // The next anchor will be somewhere in the transmit window, report its
+                    // start.
+                    if let LlcpUpdate::ConnUpdate(data) = update {
+                        let next_anchor = anchor + old_conn_interval + data.win_offset();
+                        summary.time_to_next_anchor = time_until(timer.now(), next_anchor);
+                        self.next_anchor = Some(next_anchor);
+                    }
+                    self.report_event(hook, &mut summary);
+                    return Ok(cmd);
+                }
+            } else {
+                // Put it back
+                self.update_data = Some(update);
+            }
+        }
+
+        // Hop channels after applying LLCP update because it might change the channel map used
+        // by the next event
+        self.hop_channel();
+
+        if progress.payload {
+            if let Some(subrate) = &self.subrate {
+                self.continuation = subrate.continuation_number();
+            }
+        }
+        let next_anchor = self.skip_unused_events(anchor + self.conn_interval);
+        self.next_anchor = Some(next_anchor);
+
+        let now = timer.now();
+        summary.time_to_next_anchor = time_until(now, next_anchor);
+        self.report_event(hook, &mut summary);
 
         Ok(Cmd {
-            next_update: NextUpdate::At(timer.now() + self.conn_event_timeout()),
+            next_update: NextUpdate::At(self.rx_deadline(next_anchor)),
             radio: RadioCmd::ListenData {
                 channel: self.channel,
                 access_address: self.access_address,
                 crc_init: self.crc_init,
             },
-            queued_work,
+            queued_work: false,
         })
     }
 
     /// Called by the `LinkLayer` when the configured timer expires (according to a `Cmd` returned
     /// earlier).
     ///
-    /// Returns `Err(())` when the connection is closed or lost. In that case, the Link-Layer will
-    /// return to standby state.
-    pub(crate) fn timer_update(&mut self, timer: &mut C::Timer) -> Result<Cmd, ()> {
+    /// Returns `Err` with the reason when the connection is closed or lost. In that case, the
+    /// Link-Layer will return to standby state.
+    pub(crate) fn timer_update(
+        &mut self,
+        timer: &mut C::Timer,
+        hook: &mut Option<C::EventHook>,
+        stats: &mut Stats,
+    ) -> Result<Cmd, ErrorCode> {
+        if let Some(progress) = self.event.take() {
+            // The master didn't send another packet, so the event is over
+            return self.close_event(timer, hook, progress, false);
+        }
+
         if self.received_packet {
             // No packet from master, skip this connection event and listen on the next channel
 
+            let now = timer.now();
+            // `received_packet` is only set after `next_anchor` was updated
+            let anchor = match self.next_anchor {
+                Some(anchor) => anchor,
+                None => return Err(ErrorCode::UnspecifiedError),
+            };
+            self.next_anchor = Some(anchor + self.conn_interval);
+            stats.record(Counter::MissedEvent);
+            self.check_supervision_timeout(anchor)?;
+            self.check_timeouts(anchor)?;
+            let mut summary = ConnectionEventSummary {
+                event_counter: self.conn_event_count.0,
+                packets_received: 0,
+                packets_sent: 0,
+                crc_errors: 0,
+                more_data: false,
+                time_to_next_anchor: Duration::from_micros(0),
+                rssi: None,
+                rx_timestamp: None,
+                payload_queued: false,
+                tx_power: self.tx_power,
+                link_quality: None,
+            };
+
             let last_channel = self.channel;
             self.hop_channel();
             self.conn_event_count += Wrapping(1);
+            let next_anchor = self.skip_unused_events(anchor + self.conn_interval);
+            self.next_anchor = Some(next_anchor);
+            summary.time_to_next_anchor = time_until(now, next_anchor);
+            self.report_event(hook, &mut summary);
             trace!(
                 "DATA({}->{}): missed conn event #{}",
                 last_channel.index(),
@@ -337,7 +720,7 @@ impl<C: Config> Connection<C> {
             );
 
             Ok(Cmd {
-                next_update: NextUpdate::At(timer.now() + self.conn_event_timeout()),
+                next_update: NextUpdate::At(self.rx_deadline(next_anchor)),
                 radio: RadioCmd::ListenData {
                     channel: self.channel,
                     access_address: self.access_address,
@@ -353,13 +736,56 @@ impl<C: Config> Connection<C> {
 
             self.conn_event_count += Wrapping(1);
             trace!("missed transmit window");
-            Err(())
+            Err(ErrorCode::ConnectionFailedToBeEstablished)
+        }
+    }
+
+    /// Skips the connection events that aren't used due to subrating.
+    ///
+    /// `anchor` is the anchor point of the event `conn_event_count`, which `channel` has already
+    /// been hopped to. Returns the anchor point of the next event to listen for, after advancing
+    /// the event counter and channel to it.
+    ///
+    /// Events following one in which data was exchanged are used as long as `continuation` is
+    /// non-zero. Events are never skipped while an LLCP update is pending, so that its instant
+    /// can't be missed.
+    fn skip_unused_events(&mut self, mut anchor: Instant) -> Instant {
+        let subrate = match self.subrate {
+            Some(subrate) => subrate,
+            None => return anchor,
+        };
+        if self.continuation > 0 {
+            self.continuation -= 1;
+            return anchor;
         }
+
+        while self.update_data.is_none() && !subrate.is_subrated_event(self.conn_event_count.0) {
+            self.conn_event_count += Wrapping(1);
+            self.hop_channel();
+            anchor += self.conn_interval;
+        }
+        anchor
+    }
+
+    /// Computes the window widening for a connection event `elapsed` after the last anchor point.
+    ///
+    /// Both sleep clocks may drift in opposite directions, so the master's packet may arrive early
+    /// or late by their combined accuracy (plus 16 µs of allowed jitter). The widening is limited
+    /// to half the connection interval, as required by the spec.
+    fn window_widening(&self, elapsed: Duration) -> Duration {
+        let drift = (u64::from(elapsed.as_micros()) * u64::from(self.sca_ppm)).div_ceil(1_000_000);
+        let max = (self.conn_interval.as_micros() / 2).saturating_sub(Duration::T_IFS.as_micros());
+        Duration::from_micros(cmp::min(drift as u32 + 16, max))
     }
 
-    fn conn_event_timeout(&self) -> Duration {
-        // Time out ~500µs after the anchor point of the next conn event.
-        self.conn_interval + Duration::from_micros(500)
+    /// Returns the time by which the master's packet of the connection event at `anchor` must
+    /// have been received.
+    ///
+    /// This accounts for window widening and for a full-length packet starting at the end of the
+    /// widened window.
+    fn rx_deadline(&self, anchor: Instant) -> Instant {
+        let elapsed = anchor.duration_since(self.last_anchor);
+        anchor + self.window_widening(elapsed) + packet_air_time(MIN_DATA_PAYLOAD_BUF as u8)
     }
 
     /// Whether we want to send more data during this connection event.
@@ -367,7 +793,7 @@ impl<C: Config> Connection<C> {
     /// Note that this *has to* change to `false` eventually, even if there's more data to be sent,
     /// because the connection event must close at least `T_IFS` before the next one occurs.
     fn has_more_data(&self) -> bool {
-        false
+        self.more_data_allowed && self.tx.has_data()
     }
 
     /// Advances the `unmapped_channel` and `channel` fields to the next data channel on which a
@@ -381,9 +807,13 @@ impl<C: Config> Connection<C> {
         self.channel = if self.channel_map.is_used(unmapped_channel) {
             unmapped_channel
         } else {
-            // This channel isn't used, remap channel according to map
-            let remapping_index = unmapped_channel.index() % self.channel_map.num_used_channels();
-            self.channel_map.by_index(remapping_index)
+            // This channel isn't used, remap channel according to map. Channel maps with less than
+            // 2 channels are rejected when received, but still don't divide by zero here.
+            unmapped_channel
+                .index()
+                .checked_rem(self.channel_map.num_used_channels())
+                .and_then(|index| self.channel_map.iter_used().nth(usize::from(index)))
+                .unwrap_or(unmapped_channel)
         };
     }
 
@@ -394,15 +824,143 @@ impl<C: Config> Connection<C> {
         header.set_sn(self.transmit_seq_num);
         self.last_header = header;
 
+        tx.set_tx_power(self.tx_power);
+        trace::mark(TracePoint::ResponseReady);
         tx.transmit_data(self.access_address, self.crc_init, header, self.channel);
+    }
+
+    /// Starts the response timer if `opcode` initiates a procedure that the master has to answer.
+    ///
+    /// The spec starts the timer when the PDU is queued for transmission. We start it when the PDU
+    /// is first transmitted instead, which only makes the timeout slightly more lenient.
+    fn start_procedure(&mut self, opcode: ControlOpcode, now: Instant) {
+        if Procedure::expects_response(opcode) {
+            self.procedure = Some(Procedure {
+                request: opcode,
+                started: now,
+            });
+        }
+    }
+
+    /// Stops the response timer if `pdu` completes the outstanding procedure.
+    ///
+    /// Returns whether the procedure was completed.
+    fn complete_procedure(&mut self, pdu: &ControlPdu<'_>) -> bool {
+        match self.procedure {
+            Some(procedure) if procedure.is_completed_by(pdu) => {
+                self.procedure = None;
+                true
+            }
+            _ => false,
+        }
+    }
 
-        let pl = &tx.tx_payload_buf()[..usize::from(header.payload_length())];
-        trace!("DATA->{:?}, {:?}", header, HexSlice(pl));
+    /// Passes an LL Control PDU not handled by Rubble to the `ControlPduHandler`, and sends its
+    /// response (or `LL_UNKNOWN_RSP`, if the handler doesn't know the PDU either).
+    ///
+    /// Returns whether a response was sent.
+    fn respond_unhandled(
+        &mut self,
+        pdu: ControlPdu<'_>,
+        tx: &mut C::Transmitter,
+        handler: &mut Option<C::ControlHandler>,
+    ) -> bool {
+        let mut writer = ByteWriter::new(tx.tx_payload_buf());
+        let left = writer.space_left();
+        let action = match handler {
+            Some(handler) => handler.handle_control_pdu(pdu, &mut writer),
+            None => ControlAction::Unhandled,
+        };
+
+        let pl_len = match action {
+            ControlAction::NoResponse => return false,
+            ControlAction::Respond => left - writer.space_left(),
+            ControlAction::Unhandled => {
+                let response = ControlPdu::UnknownRsp {
+                    unknown_type: pdu.opcode(),
+                };
+                let mut writer = ByteWriter::new(tx.tx_payload_buf());
+                if response.to_bytes(&mut writer).is_err() {
+                    return false;
+                }
+                left - writer.space_left()
+            }
+        };
+
+        let mut header = Header::new(Llid::Control);
+        header.set_payload_length(pl_len as u8);
+        self.send(header, tx);
+        true
+    }
+
+    /// Writes an `LL_POWER_CHANGE_IND` to the radio's TX buffer if our transmission power has
+    /// changed since it was last reported to the master.
+    ///
+    /// Returns the header to send the PDU with, or `None` if there's nothing to report.
+    fn power_change_ind(&mut self, tx: &mut C::Transmitter) -> Option<Header> {
+        let reported = self.reported_power?;
+        let supported = tx.supported_tx_power();
+        let level = self.tx_power.clamp_to(supported);
+        if level == reported {
+            return None;
+        }
+
+        let pdu = ControlPdu::PowerChangeInd {
+            phys: Phys::LE_1M,
+            limits: PowerLimits::of(level, supported),
+            delta: level.as_dbm().saturating_sub(reported.as_dbm()),
+            tx_power: level.as_dbm(),
+        };
+        let mut writer = ByteWriter::new(tx.tx_payload_buf());
+        let left = writer.space_left();
+        pdu.to_bytes(&mut writer).ok()?;
+        self.reported_power = Some(level);
+
+        let mut header = Header::new(Llid::Control);
+        header.set_payload_length((left - writer.space_left()) as u8);
+        Some(header)
+    }
+
+    /// Ends the connection if no packet with a valid CRC has been received for longer than the
+    /// supervision timeout before the connection event at `anchor`.
+    fn check_supervision_timeout(&self, anchor: Instant) -> Result<(), ErrorCode> {
+        if anchor.duration_since(self.last_anchor) > self.supervision_timeout {
+            warn!("supervision timeout expired, connection lost");
+            Err(ErrorCode::ConnectionTimeout)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Ends the connection if the outstanding procedure has not been answered in time, or if the
+    /// master hasn't acknowledged our `LL_TERMINATE_IND` within the supervision timeout.
+    ///
+    /// `anchor` is the anchor point of the current connection event.
+    fn check_timeouts(&mut self, anchor: Instant) -> Result<(), ErrorCode> {
+        if let Some(started) = self.termination {
+            if anchor.duration_since(started) > self.supervision_timeout {
+                warn!("LL_TERMINATE_IND was not acknowledged, closing connection anyways");
+                return Err(ErrorCode::ConnectionTerminatedByLocalHost);
+            }
+        }
+
+        if let Some(procedure) = self.procedure {
+            if anchor.duration_since(procedure.started) > Procedure::RESPONSE_TIMEOUT {
+                error!(
+                    "no response to {:?} within {:?}, closing connection",
+                    procedure.request,
+                    Procedure::RESPONSE_TIMEOUT
+                );
+                return Err(ErrorCode::LlResponseTimeout);
+            }
+        }
+
+        Ok(())
     }
 
     /// Tries to process and acknowledge an LL Control PDU.
     ///
-    /// Returns `Err(())` when the connection is closed or lost.
+    /// Returns `Err(LlcpError::ConnectionLost)` when the connection is closed or lost.
     ///
     /// Note this this function is on a time-critical path and thus can not use logging since that's
     /// currently way too slow. Critical errors can still be logged, since they abort the connection
@@ -414,17 +972,29 @@ impl<C: Config> Connection<C> {
     /// * **`can_respond`**: Whether the radio's TX buffer may be overwritten to send a response. If
     ///   this is `false`, this method may choose not to acknowledge the PDU and wait for a
     ///   retransmission instead.
+    /// * **`supported`**: The transmission power levels supported by the radio.
     fn process_control_pdu(
         &mut self,
         pdu: ControlPdu<'_>,
         can_respond: bool,
+        supported: &[TxPower],
     ) -> Result<Option<ControlPdu<'static>>, LlcpError> {
+        let completed = self.complete_procedure(&pdu);
+
         let response = match pdu {
             ControlPdu::ConnectionUpdateReq(data) => {
+                if data.interval() < Duration::from_micros(7_500)
+                    || data.interval() > Duration::from_secs(4)
+                {
+                    return Err(LlcpError::ConnectionLost(ErrorCode::InvalidLlParameters));
+                }
                 self.prepare_llcp_update(LlcpUpdate::ConnUpdate(data))?;
                 return Ok(None);
             }
             ControlPdu::ChannelMapReq { map, instant } => {
+                if map.num_used_channels() < 2 {
+                    return Err(LlcpError::ConnectionLost(ErrorCode::InvalidLlParameters));
+                }
                 self.prepare_llcp_update(LlcpUpdate::ChannelMap { map, instant })?;
                 return Ok(None);
             }
@@ -433,11 +1003,13 @@ impl<C: Config> Connection<C> {
                     "closing connection due to termination request: code {:?}",
                     error_code
                 );
-                return Err(LlcpError::ConnectionLost);
+                return Err(LlcpError::ConnectionLost(ErrorCode::from(error_code.0)));
             }
             ControlPdu::FeatureReq { features_master } => ControlPdu::FeatureRsp {
                 features_used: features_master & FeatureSet::supported(),
             },
+            // The master's answer to our own `LL_VERSION_IND` must not be answered again
+            ControlPdu::VersionInd { .. } if completed => return Ok(None),
             ControlPdu::VersionInd { .. } => {
                 // FIXME this should be something real, and defined somewhere else
                 let comp_id = 0xFFFF;
@@ -450,9 +1022,89 @@ impl<C: Config> Connection<C> {
                     sub_vers_nr: Hex(sub_vers_nr),
                 }
             }
-            _ => ControlPdu::UnknownRsp {
-                unknown_type: pdu.opcode(),
-            },
+            ControlPdu::PowerControlReq { phy, .. } if !phy.contains(Phys::LE_1M) => {
+                ControlPdu::RejectExtInd {
+                    reject_opcode: ControlOpcode::PowerControlReq,
+                    error_code: Hex(ErrorCode::UnsupportedLlParameterValue.into()),
+                }
+            }
+            ControlPdu::PowerControlReq {
+                delta, tx_power, ..
+            } => {
+                if !can_respond {
+                    return Err(LlcpError::NoSpace);
+                }
+                self.peer_power = Some(PeerTxPower::from_raw(
+                    tx_power,
+                    PowerLimits::empty(),
+                    power_control::DELTA_UNAVAILABLE,
+                ));
+
+                let current = self.tx_power.clamp_to(supported);
+                let level = if delta == power_control::DELTA_UNAVAILABLE {
+                    current
+                } else {
+                    power_control::adjust(current, delta, supported)
+                };
+                self.tx_power = level;
+                self.reported_power = Some(level);
+
+                let apr = match &self.link_quality {
+                    Some(manager) => power_control::acceptable_reduction(
+                        manager.average_rssi(),
+                        manager.config().rssi_low,
+                    ),
+                    None => power_control::APR_UNAVAILABLE,
+                };
+                ControlPdu::PowerControlRsp {
+                    limits: PowerLimits::of(level, supported),
+                    delta: level.as_dbm().saturating_sub(current.as_dbm()),
+                    tx_power: level.as_dbm(),
+                    apr,
+                }
+            }
+            ControlPdu::PowerControlRsp {
+                limits,
+                delta,
+                tx_power,
+                ..
+            } => {
+                self.peer_power = Some(PeerTxPower::from_raw(tx_power, limits, delta));
+                return Ok(None);
+            }
+            ControlPdu::SubrateInd(ind)
+                if ind.subrate_factor() < 1 || ind.subrate_factor() > 500 =>
+            {
+                return Err(LlcpError::ConnectionLost(ErrorCode::InvalidLlParameters));
+            }
+            ControlPdu::SubrateInd(ind) => {
+                // Takes effect with the end of this event
+                self.supervision_timeout = ind.timeout();
+                self.subrate = Some(ind);
+                self.continuation = 0;
+                return Ok(None);
+            }
+            ControlPdu::PowerChangeInd {
+                limits,
+                delta,
+                tx_power,
+                ..
+            } => {
+                self.peer_power = Some(PeerTxPower::from_raw(tx_power, limits, delta));
+                if self.reported_power.is_none() {
+                    // The master supports power control, report our changes from now on
+                    self.reported_power = Some(self.tx_power.clamp_to(supported));
+                }
+                return Ok(None);
+            }
+            // Rejections and responses to our own requests are never answered
+            ControlPdu::UnknownRsp { .. } | ControlPdu::RejectExtInd { .. } => return Ok(None),
+            ControlPdu::Unknown {
+                opcode: ControlOpcode::RejectInd,
+                ..
+            } => return Ok(None),
+            _ if completed => return Ok(None),
+            _ => return Err(LlcpError::Unhandled),
         };
 
         // If we land here, we have a PDU we want to send
@@ -472,7 +1124,7 @@ impl<C: Config> Connection<C> {
                 "got update data {:?} while update {:?} is already queued",
                 update, data
             );
-            Err(LlcpError::ConnectionLost)
+            Err(LlcpError::ConnectionLost(ErrorCode::LlProcedureCollision))
         } else {
             self.update_data = Some(update);
             Ok(())
@@ -493,9 +1145,9 @@ impl<C: Config> Connection<C> {
 
                 Some(Cmd {
                     // Next update after the tx window ends (= missed it)
-                    next_update: NextUpdate::At(
+                    next_update: NextUpdate::At(self.rx_deadline(
                         rx_end + old_conn_interval + data.win_offset() + data.win_size(),
-                    ),
+                    )),
                     // Listen for the transmit window
                     radio: RadioCmd::ListenData {
                         channel: self.channel,
@@ -528,6 +1180,223 @@ impl<C: Config> Connection<C> {
     pub fn connection_interval(&self) -> Duration {
         self.conn_interval
     }
+
+    /// Returns the estimated anchor point of the next connection event.
+    ///
+    /// Returns `None` if no packet has been received from the master yet, since the anchor point
+    /// is only known after that.
+    pub fn next_anchor(&self) -> Option<Instant> {
+        self.next_anchor
+    }
+
+    /// Returns the instant at which the radio has to start listening for the next connection
+    /// event, which is the estimated anchor point minus the window widening.
+    ///
+    /// Returns `None` if the anchor point isn't known yet.
+    pub(crate) fn next_rx_window(&self) -> Option<Instant> {
+        if self.event.is_some() {
+            // Still in the current event
+            return None;
+        }
+        let anchor = self.next_anchor?;
+        Some(anchor - self.window_widening(anchor.duration_since(self.last_anchor)))
+    }
+
+    /// Returns whether a connection event is in progress, because the master or we had more data.
+    pub(crate) fn in_event(&self) -> bool {
+        self.event.is_some()
+    }
+
+    /// Returns the radio reservation for the next connection event, made for `activity`.
+    ///
+    /// The reservation starts with the widened receive window, and lasts until the event may end
+    /// when the master's first packet arrives at the end of the window. Returns `None` under the
+    /// same conditions as `next_rx_window`.
+    pub(crate) fn next_reservation(&self, activity: Activity) -> Option<Reservation> {
+        let start = self.next_rx_window()?;
+        let widening = self.next_anchor?.duration_since(start);
+        let length = widening + widening + cmp::max(self.event_length, MIN_CONNECTION_EVENT);
+        Some(Reservation::new(activity, start, length))
+    }
+
+    /// Returns the maximum length of connection events.
+    pub fn event_length(&self) -> Duration {
+        self.event_length
+    }
+
+    /// Sets the maximum length of connection events, starting with the next event.
+    ///
+    /// Within this time after the anchor point, packets are exchanged for as long as the master or
+    /// we have more data (indicated by the MD bit). The event length is also reserved with the
+    /// `Scheduler`, and is capped to leave room before the next event.
+    pub fn set_event_length(&mut self, length: Duration) {
+        self.event_length = length;
+    }
+
+    /// Returns the radio configuration for listening for the next packet from the master.
+    pub(crate) fn listen_cmd(&self) -> RadioCmd {
+        RadioCmd::ListenData {
+            channel: self.channel,
+            access_address: self.access_address,
+            crc_init: self.crc_init,
+        }
+    }
+
+    /// Returns the device address of the connected master.
+    ///
+    /// If the master uses LE Privacy, this is a resolvable private address. Its identity can be
+    /// looked up with a `privacy::IdentityResolver`.
+    pub fn peer_address(&self) -> DeviceAddress {
+        self.peer_address
+    }
+
+    /// Returns the transmission power used for this connection.
+    pub fn tx_power(&self) -> TxPower {
+        self.tx_power
+    }
+
+    /// Changes the transmission power used for this connection.
+    ///
+    /// The new level takes effect with the next transmitted packet. The `Transmitter` will clamp
+    /// it to a supported level.
+    pub fn set_tx_power(&mut self, power: TxPower) {
+        self.tx_power = power;
+    }
+
+    /// Returns the transmission power last reported by the master via LE Power Control.
+    ///
+    /// Together with the RSSI of the master's packets, this tells the path loss of the link (see
+    /// `PeerTxPower::path_loss`). Returns `None` if the master hasn't reported its power yet. Use
+    /// `Responder::request_power_change` to ask for it.
+    pub fn peer_tx_power(&self) -> Option<&PeerTxPower> {
+        self.peer_power.as_ref()
+    }
+
+    /// Returns the subrating parameters in use, if the master has enabled subrating.
+    ///
+    /// While subrated, only every `subrate_factor`-th connection event is listened for, plus
+    /// `continuation_number` events after each event in which data was exchanged. Rubble always
+    /// listens at subrated events, so the slave latency isn't used. Subrating can be requested
+    /// with `Responder::request_subrate`.
+    pub fn subrate(&self) -> Option<&SubrateIndication> {
+        self.subrate.as_ref()
+    }
+
+    /// Returns the link-quality manager adapting the transmission power, if one is installed.
+    pub fn link_quality(&self) -> Option<&LinkQualityManager> {
+        self.link_quality.as_ref()
+    }
+
+    /// Installs or removes the link-quality manager of this connection.
+    ///
+    /// While a manager is installed, it may override the power set with `set_tx_power` at the end
+    /// of any connection event.
+    pub fn set_link_quality(&mut self, manager: Option<LinkQualityManager>) {
+        self.link_quality = manager;
+    }
+
+    /// Feeds `summary` to the link-quality manager, applies its decision, and passes the summary
+    /// on to the hook.
+    fn report_event(
+        &mut self,
+        hook: &mut Option<C::EventHook>,
+        summary: &mut ConnectionEventSummary,
+    ) {
+        if let Some(manager) = &mut self.link_quality {
+            summary.link_quality = manager.event_end(summary, self.tx_power);
+            if let Some(LinkQualityEvent::TxPowerChanged(power)) = summary.link_quality {
+                self.tx_power = power;
+            }
+        }
+        if let Some(hook) = hook {
+            hook.connection_event_end(summary);
+        }
+    }
+}
+
+/// Summary of a completed connection event, passed to the `ConnectionEventHook`.
+#[derive(Debug, Copy, Clone)]
+pub struct ConnectionEventSummary {
+    /// The connection event counter of the event that just ended.
+    pub event_counter: u16,
+
+    /// Number of packets received from the master during the event (including empty PDUs).
+    pub packets_received: u8,
+
+    /// Number of packets sent to the master during the event (including empty PDUs and
+    /// retransmissions).
+    pub packets_sent: u8,
+
+    /// Number of received packets that failed the CRC check.
+    pub crc_errors: u8,
+
+    /// Whether either side had more data to send when the event was closed.
+    pub more_data: bool,
+
+    /// Estimated time from now until the anchor point of the next connection event.
+    ///
+    /// This is derived from the reception time of the master's packet and is only accurate to a
+    /// few microseconds. If the estimated anchor is already in the past, this is 0.
+    pub time_to_next_anchor: Duration,
+
+    /// Signal strength of the master's packet in dBm, if the radio measured it.
+    pub rssi: Option<i8>,
+
+    /// Precise instant at which the access address of the master's packet was received, if the
+    /// radio captures it (see `Transmitter::rx_timestamp`).
+    ///
+    /// The master's first packet starts at the anchor point, so this is the actual anchor of the
+    /// event as seen by the radio.
+    pub rx_timestamp: Option<Instant>,
+
+    /// Whether the master's packet carried data that was pushed into the RX queue.
+    ///
+    /// Packets are delivered in order, so counting the events with this set tells which queued
+    /// packet `rx_timestamp` belongs to.
+    pub payload_queued: bool,
+
+    /// Transmission power the event's packets were sent with.
+    pub tx_power: TxPower,
+
+    /// Decision the link-quality manager made at the end of this event, if any.
+    ///
+    /// A changed power takes effect from the next connection event on.
+    pub link_quality: Option<LinkQualityEvent>,
+}
+
+/// Application hook invoked by the Link-Layer at the end of every connection event.
+///
+/// This allows performing low-priority work synchronized to the radio schedule (eg. sampling a
+/// sensor right after an event), or gathering timing and power statistics.
+///
+/// The hook is called from the Link-Layer's real-time context, right after the last packet of the
+/// connection event was handled. It must return quickly, or the next connection event might be
+/// missed.
+pub trait ConnectionEventHook {
+    /// Called when a connection event has been completed (or missed).
+    fn connection_event_end(&mut self, summary: &ConnectionEventSummary);
+}
+
+/// The "no-op" hook, for applications that aren't interested in connection events.
+impl ConnectionEventHook for () {
+    fn connection_event_end(&mut self, _summary: &ConnectionEventSummary) {}
+}
+
+/// Returns the time a data channel packet with the given payload length takes to transmit on the
+/// LE 1M PHY.
+fn packet_air_time(payload_length: u8) -> Duration {
+    // Preamble, Access Address, Header and CRC take 10 octets. Each octet takes 8µs.
+    Duration::from_micros((10 + u32::from(payload_length)) * 8)
+}
+
+/// Returns the time from `now` until `instant`, or 0 if `instant` has already passed.
+fn time_until(now: Instant, instant: Instant) -> Duration {
+    let micros = instant.raw_micros().wrapping_sub(now.raw_micros());
+    if micros > Instant::MAX_TIME_BETWEEN.as_micros() {
+        Duration::from_micros(0)
+    } else {
+        Duration::from_micros(micros)
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -536,7 +1405,138 @@ enum LlcpError {
     NoSpace,
 
     /// Consider the connection lost due to a critical error or timeout.
-    ConnectionLost,
+    ConnectionLost(ErrorCode),
+
+    /// The PDU isn't handled by Rubble and should be passed to the `ControlPduHandler`.
+    Unhandled,
+}
+
+/// A connection event in which more packets may still be exchanged.
+#[derive(Debug, Copy, Clone)]
+struct EventProgress {
+    /// Anchor point of the event.
+    anchor: Instant,
+
+    /// Instant at which the master's first packet was fully received.
+    first_rx_end: Instant,
+
+    /// Number of packets received (and answered) so far.
+    packets: u8,
+
+    crc_errors: u8,
+
+    /// Whether any packet was put into the RX queue.
+    queued_work: bool,
+
+    /// Whether any packet (sent or received) had a non-empty payload.
+    payload: bool,
+
+    /// RSSI of the master's first packet.
+    rssi: Option<i8>,
+
+    /// Access address timestamp of the master's first packet.
+    rx_timestamp: Option<Instant>,
+}
+
+/// State passed from `Connection::respond` to `Connection::finish_event`.
+#[derive(Debug, Copy, Clone)]
+struct PendingEvent {
+    /// Instant at which the master's packet was fully received.
+    rx_end: Instant,
+
+    /// Header of the master's packet.
+    header: data::Header,
+
+    crc_ok: bool,
+
+    /// RSSI of the master's packet, read before responding.
+    rssi: Option<i8>,
+
+    /// Access address timestamp of the master's packet, read before responding.
+    rx_timestamp: Option<Instant>,
+
+    /// Whether the payload was pushed into the RX queue.
+    queued_work: bool,
+
+    /// What `respond` did that is worth logging.
+    log: RespondLog,
+}
+
+/// Outcome of `Connection::respond` that is logged by `Connection::finish_event`.
+///
+/// Formatting log messages takes too long to be done before the response is sent, so only the
+/// opcodes of LL Control PDUs are kept.
+#[derive(Debug, Copy, Clone)]
+enum RespondLog {
+    Nothing,
+
+    /// An LL Control PDU was processed, and answered with `response`, if any.
+    Llcp {
+        received: ControlOpcode,
+        response: Option<ControlOpcode>,
+    },
+
+    /// An LL Control PDU was passed to the `ControlPduHandler`.
+    LlcpUnhandled(ControlOpcode),
+
+    /// A data PDU longer than the supported data length was dropped.
+    Oversized,
+
+    /// A data PDU wasn't acknowledged, because the RX queue is full.
+    Nack,
+}
+
+/// An LL Control procedure initiated by us.
+#[derive(Debug, Copy, Clone)]
+struct Procedure {
+    /// Opcode of the LL Control PDU that started the procedure.
+    request: ControlOpcode,
+
+    /// Time at which the request was sent.
+    started: Instant,
+}
+
+impl Procedure {
+    /// Time the master has to complete a procedure (`T_PRT`).
+    const RESPONSE_TIMEOUT: Duration = Duration::from_micros(40_000_000);
+
+    /// Returns whether the master has to respond to an LL Control PDU with the given opcode.
+    fn expects_response(request: ControlOpcode) -> bool {
+        Self::response_to(request).is_some()
+    }
+
+    /// Returns the opcode of the PDU the master finishes the procedure with.
+    fn response_to(request: ControlOpcode) -> Option<ControlOpcode> {
+        Some(match request {
+            ControlOpcode::SlaveFeatureReq => ControlOpcode::FeatureRsp,
+            ControlOpcode::VersionInd => ControlOpcode::VersionInd,
+            ControlOpcode::ConnectionParamReq => ControlOpcode::ConnectionUpdateReq,
+            ControlOpcode::PingReq => ControlOpcode::PingRsp,
+            ControlOpcode::LengthReq => ControlOpcode::LengthRsp,
+            ControlOpcode::PhyReq => ControlOpcode::PhyUpdateInd,
+            ControlOpcode::CteReq => ControlOpcode::CteRsp,
+            ControlOpcode::ClockAccuracyReq => ControlOpcode::ClockAccuracyRsp,
+            ControlOpcode::PowerControlReq => ControlOpcode::PowerControlRsp,
+            ControlOpcode::SubrateReq => ControlOpcode::SubrateInd,
+            _ => return None,
+        })
+    }
+
+    /// Returns whether receiving `pdu` from the master completes this procedure.
+    ///
+    /// Besides the regular response, the master may also reject the request or report that it
+    /// doesn't support it.
+    fn is_completed_by(&self, pdu: &ControlPdu<'_>) -> bool {
+        match pdu {
+            ControlPdu::UnknownRsp { unknown_type } => *unknown_type == self.request,
+            ControlPdu::RejectExtInd { reject_opcode, .. } => *reject_opcode == self.request,
+            _ => {
+                let opcode = pdu.opcode();
+                opcode == ControlOpcode::RejectInd
+                    || Some(opcode) == Self::response_to(self.request)
+            }
+        }
+    }
 }
 
 /// A Link-Layer state update that may be applied with a delay.
@@ -0,0 +1,198 @@
+//! Constant Tone Extension (CTE) and direction finding.
+//!
+//! Bluetooth 5.1 allows appending a *Constant Tone Extension* to packets. The CTE is an unwhitened
+//! sequence of 1s, sent at the end of a packet, during which the receiver can sample the in-phase
+//! and quadrature (IQ) components of the signal. By switching between multiple antennas while doing
+//! so, the phase differences between the antennas can be used to calculate the direction of the
+//! signal:
+//!
+//! * **Angle of Arrival (AoA)**: The transmitter uses a single antenna, the receiver switches
+//!   antennas while sampling.
+//! * **Angle of Departure (AoD)**: The transmitter switches antennas while sending the CTE, the
+//!   receiver samples using a single antenna.
+//!
+//! CTEs can be sent in a connection (requested by the peer with an `LL_CTE_REQ`, see
+//! `ControlPdu::CteReq`) or in connectionless mode, attached to periodic advertisements.
+//!
+//! Direction finding requires radio hardware support, which is exposed via the `DirectionFinding`
+//! trait. Calculating angles from the IQ samples is left to the application, since it depends on
+//! the antenna array geometry.
+//!
+//! Note that none of the radios currently supported by `rubble-nrf52` (nRF52810, nRF52832,
+//! nRF52840) have the required hardware. Of the nRF52 series, only the nRF52811, nRF52820 and
+//! nRF52833 do.
+
+use crate::{phy::DataChannel, Error};
+
+enum_with_unknown! {
+    /// The type of a Constant Tone Extension.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum CteType(u8) {
+        /// AoA CTE: The transmitter does not switch antennas.
+        AoA = 0,
+        /// AoD CTE with 1 µs switching and sampling slots.
+        AoD1us = 1,
+        /// AoD CTE with 2 µs switching and sampling slots.
+        AoD2us = 2,
+    }
+}
+
+/// The `CTEInfo` field describing a CTE attached to a packet.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CteInfo {
+    length: u8,
+    cte_type: CteType,
+}
+
+impl CteInfo {
+    /// Minimum CTE length in units of 8 µs.
+    pub const MIN_LENGTH: u8 = 2;
+
+    /// Maximum CTE length in units of 8 µs.
+    pub const MAX_LENGTH: u8 = 20;
+
+    /// Creates a `CTEInfo` describing a CTE of `length * 8` µs.
+    ///
+    /// Returns `Error::InvalidValue` if `length` is not between `MIN_LENGTH` and `MAX_LENGTH`.
+    pub fn new(length: u8, cte_type: CteType) -> Result<Self, Error> {
+        if !(Self::MIN_LENGTH..=Self::MAX_LENGTH).contains(&length) {
+            return Err(Error::InvalidValue);
+        }
+
+        Ok(Self { length, cte_type })
+    }
+
+    /// Decodes the `CTEInfo` Byte used in packet headers and LL Control PDUs.
+    pub fn from_raw(raw: u8) -> Self {
+        Self {
+            length: raw & 0b1_1111,
+            cte_type: CteType::from(raw >> 6),
+        }
+    }
+
+    /// Encodes this `CTEInfo` as a raw Byte.
+    pub fn to_raw(&self) -> u8 {
+        self.length | (u8::from(self.cte_type) << 6)
+    }
+
+    /// Returns the length of the CTE in units of 8 µs.
+    pub fn length(&self) -> u8 {
+        self.length
+    }
+
+    /// Returns the type of the CTE.
+    pub fn cte_type(&self) -> CteType {
+        self.cte_type
+    }
+}
+
+/// Antenna switching pattern used for AoA reception or AoD transmission.
+///
+/// The pattern is a list of antenna IDs, as understood by the radio driver. The radio uses the
+/// first antenna during the guard and reference period, and then steps through the pattern for
+/// every switching slot, starting over when it reaches the end.
+#[derive(Debug, Copy, Clone)]
+pub struct AntennaPattern<'a> {
+    antennas: &'a [u8],
+}
+
+impl<'a> AntennaPattern<'a> {
+    /// Maximum length of an antenna switching pattern.
+    pub const MAX_LEN: usize = 75;
+
+    /// Creates a switching pattern from a list of antenna IDs.
+    ///
+    /// Returns `Error::InvalidLength` if `antennas` contains less than 2 or more than `MAX_LEN`
+    /// entries.
+    pub fn new(antennas: &'a [u8]) -> Result<Self, Error> {
+        if antennas.len() < 2 || antennas.len() > Self::MAX_LEN {
+            return Err(Error::InvalidLength);
+        }
+
+        Ok(Self { antennas })
+    }
+
+    /// Returns the antenna IDs in switching order.
+    pub fn antennas(&self) -> &'a [u8] {
+        self.antennas
+    }
+}
+
+/// A single IQ sample taken during the CTE.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct IqSample {
+    /// In-phase component.
+    pub i: i8,
+    /// Quadrature component.
+    pub q: i8,
+}
+
+/// IQ samples taken from a single received CTE.
+#[derive(Debug, Copy, Clone)]
+pub struct IqReport<'a> {
+    /// The channel on which the packet was received.
+    ///
+    /// For connectionless CTEs, this is the (secondary) advertising channel index.
+    pub channel: DataChannel,
+
+    /// The `CTEInfo` of the received packet.
+    pub cte_info: CteInfo,
+
+    /// Received signal strength of the packet in dBm, if measured.
+    pub rssi: Option<i8>,
+
+    /// Whether the CRC of the packet carrying the CTE was correct.
+    ///
+    /// If not, the samples might still be usable, but the `cte_info` might be corrupted.
+    pub crc_ok: bool,
+
+    /// The IQ samples, starting with the samples taken during the reference period.
+    pub samples: &'a [IqSample],
+}
+
+/// Trait for applications that want to receive IQ samples.
+pub trait IqReportHandler {
+    /// Called after a packet with CTE was received and sampled.
+    fn iq_report(&mut self, report: &IqReport<'_>);
+}
+
+/// Trait for radios with direction finding support.
+///
+/// This is implemented in addition to `Transmitter` by drivers for radios capable of sending and
+/// sampling Constant Tone Extensions.
+pub trait DirectionFinding {
+    /// Returns the number of antennas the radio can switch between.
+    fn num_antennas(&self) -> u8;
+
+    /// Returns whether 1 µs switching and sampling slots are supported.
+    ///
+    /// 2 µs slots must always be supported.
+    fn supports_1us_slots(&self) -> bool;
+
+    /// Configures a CTE to be appended to all following transmitted packets.
+    ///
+    /// For AoD CTEs, `pattern` is the switching pattern to use (it is ignored for AoA CTEs).
+    /// Passing `None` as `cte` disables CTE transmission.
+    fn set_cte_transmit(&mut self, cte: Option<CteInfo>, pattern: Option<AntennaPattern<'_>>);
+
+    /// Enables or disables sampling of received CTEs.
+    ///
+    /// For AoA, `pattern` is the antenna switching pattern to use while sampling. When sampling is
+    /// enabled, the radio driver must pass the samples of every received CTE to the
+    /// `IqReportHandler` it was set up with.
+    fn set_cte_sampling(&mut self, enabled: bool, pattern: Option<AntennaPattern<'_>>);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cte_info() {
+        let info = CteInfo::new(20, CteType::AoD2us).unwrap();
+        assert_eq!(info.to_raw(), 0b1001_0100);
+        assert_eq!(CteInfo::from_raw(info.to_raw()), info);
+        assert!(CteInfo::new(1, CteType::AoA).is_err());
+        assert!(CteInfo::new(21, CteType::AoA).is_err());
+    }
+}
@@ -6,7 +6,6 @@ use {
         link::{llcp::ControlPdu, SeqNum},
         Error,
     },
-    byteorder::{ByteOrder, LittleEndian},
     core::fmt,
 };
 
@@ -77,9 +76,14 @@ impl Header {
 
     /// Parses a header from raw bytes.
     ///
-    /// Panics when `raw` contains less than 2 Bytes.
+    /// Missing Bytes are read as 0, so a `raw` slice shorter than 2 Bytes results in a header with
+    /// a payload length of 0.
     pub fn parse(raw: &[u8]) -> Self {
-        Header(LittleEndian::read_u16(&raw))
+        let mut bytes = [0; 2];
+        for (byte, src) in bytes.iter_mut().zip(raw) {
+            *byte = *src;
+        }
+        Header(u16::from_le_bytes(bytes))
     }
 
     /// Returns the raw representation of the header.
@@ -108,8 +112,7 @@ impl Header {
             0b00 => Llid::Reserved,
             0b01 => Llid::DataCont,
             0b10 => Llid::DataStart,
-            0b11 => Llid::Control,
-            _ => unreachable!(),
+            _ => Llid::Control, // 0b11
         }
     }
 
@@ -228,7 +231,7 @@ impl<'a, L> Pdu<'a, L> {
     }
 }
 
-impl<'a, L: FromBytes<'a> + ?Sized> Pdu<'a, L> {
+impl<'a, L: FromBytes<'a>> Pdu<'a, L> {
     /// Parses a PDU from a `Header` and raw payload.
     pub fn parse(header: Header, payload: &'a [u8]) -> Result<Self, Error> {
         match header.llid() {
@@ -63,13 +63,75 @@ bitflags! {
 
         /// Extended scan filter policies.
         const EXT_SCANNER_FILTER_POLICIES = (1 << 7);
+
+        /// Minimum Number of Used Channels procedure.
+        ///
+        /// Setting this bit means that the implementation must support the following:
+        /// * The following types of LL Control PDUs: `LL_MIN_USED_CHANNELS_IND`
+        /// * The *Minimum Number of Used Channels Procedure*
+        const MIN_USED_CHANNELS = (1 << 16);
+
+        /// Support for requesting CTEs from the peer via `LL_CTE_REQ`.
+        const CONNECTION_CTE_REQUEST = (1 << 17);
+
+        /// Support for responding to `LL_CTE_REQ` with an `LL_CTE_RSP` carrying a CTE.
+        const CONNECTION_CTE_RESPONSE = (1 << 18);
+
+        /// Support for sending CTEs with periodic advertisements.
+        const CONNECTIONLESS_CTE_TRANSMITTER = (1 << 19);
+
+        /// Support for sampling CTEs attached to periodic advertisements.
+        const CONNECTIONLESS_CTE_RECEIVER = (1 << 20);
+
+        /// Support for antenna switching during CTE transmission (AoD).
+        const ANTENNA_SWITCHING_TX = (1 << 21);
+
+        /// Support for antenna switching during CTE reception (AoA).
+        const ANTENNA_SWITCHING_RX = (1 << 22);
+
+        /// Support for creating Connected Isochronous Streams as the master.
+        const CONNECTED_ISOCHRONOUS_STREAM_MASTER = (1 << 28);
+
+        /// Support for accepting Connected Isochronous Streams as the slave.
+        ///
+        /// Setting this bit means that the implementation must support the following:
+        /// * The following types of LL Control PDUs: `LL_CIS_REQ`, `LL_CIS_RSP`, `LL_CIS_IND`,
+        ///   `LL_CIS_TERMINATE_IND`
+        /// * Scheduling of isochronous events (see the `iso` module)
+        const CONNECTED_ISOCHRONOUS_STREAM_SLAVE = (1 << 29);
+
+        /// Support for the *Power Control Request Procedure*.
+        ///
+        /// Setting this bit means that the implementation must support the following:
+        /// * The following types of LL Control PDUs: `LL_POWER_CONTROL_REQ`,
+        ///   `LL_POWER_CONTROL_RSP`
+        ///
+        /// This bit and `LE_POWER_CHANGE_INDICATION` must be set together.
+        const LE_POWER_CONTROL_REQUEST = (1 << 33);
+
+        /// Support for the *Power Change Indication Procedure* (`LL_POWER_CHANGE_IND`).
+        const LE_POWER_CHANGE_INDICATION = (1 << 34);
+
+        /// Support for the *Connection Subrate Update Procedure*.
+        ///
+        /// Setting this bit means that the implementation must support the following:
+        /// * The following types of LL Control PDUs: `LL_SUBRATE_REQ`, `LL_SUBRATE_IND`
+        /// * Skipping connection events as described by the subrating parameters
+        const CONNECTION_SUBRATING = (1 << 37);
+
+        /// The host supports connection subrating.
+        const CONNECTION_SUBRATING_HOST_SUPPORT = (1 << 38);
     }
 }
 
 impl FeatureSet {
     /// Returns the feature set supported by Rubble.
     pub fn supported() -> Self {
-        FeatureSet::empty()
+        FeatureSet::MIN_USED_CHANNELS
+            | FeatureSet::LE_POWER_CONTROL_REQUEST
+            | FeatureSet::LE_POWER_CHANGE_INDICATION
+            | FeatureSet::CONNECTION_SUBRATING
+            | FeatureSet::CONNECTION_SUBRATING_HOST_SUPPORT
     }
 }
 
@@ -1,7 +1,8 @@
 //! Link-Layer Device Filtering.
 
 use {
-    super::DeviceAddress,
+    super::{advertising::Pdu, DeviceAddress},
+    crate::Error,
     core::{iter, slice},
 };
 
@@ -9,6 +10,31 @@ pub trait AddressFilter {
     fn matches(&self, address: DeviceAddress) -> bool;
 }
 
+/// A radio capability: Dropping received advertising channel packets by sender address.
+///
+/// Radios implementing this can compare the first address in the payload of received advertising
+/// channel PDUs (`AdvA` for advertisements, `ScanA`/`InitA` for requests) against a small set of
+/// addresses while the packet is being received, and drop non-matching packets without bothering
+/// the CPU. In busy environments, this saves the interrupt load of receiving and parsing every
+/// advertisement around when scanning or initiating only towards a few known devices.
+///
+/// This is only a pre-filter: Whatever the radio delivers should still go through an
+/// `AddressFilter`.
+pub trait HardwareAddressFilter {
+    /// Number of addresses the hardware can match against.
+    const MAX_ADDRESSES: usize;
+
+    /// Configures the hardware to only deliver advertising channel packets sent by one of
+    /// `addresses`.
+    ///
+    /// Both the address and its kind (public or random) must match. Returns `Error::Eof` if
+    /// `addresses` has more than `MAX_ADDRESSES` elements. An empty slice disables the filter.
+    fn set_address_filter(&mut self, addresses: &[DeviceAddress]) -> Result<(), Error>;
+
+    /// Disables the hardware filter, so that all packets are delivered again.
+    fn clear_address_filter(&mut self);
+}
+
 /// An `AddressFilter` that allows all devices (ie. no whitelist in use).
 pub struct AllowAll;
 
@@ -38,6 +64,31 @@ impl<I: Iterator<Item = DeviceAddress> + Clone> WhitelistFilter<I> {
     }
 }
 
+impl<I: Iterator<Item = DeviceAddress> + Clone> WhitelistFilter<I> {
+    /// Loads the whitelisted addresses into the radio's hardware address filter.
+    ///
+    /// Returns `Error::Eof` if the whitelist has more addresses than the hardware can match, in
+    /// which case the hardware filter is left disabled. The software filter still has to be
+    /// applied to the packets the radio delivers either way.
+    pub fn offload<H: HardwareAddressFilter>(&self, hw: &mut H) -> Result<(), Error> {
+        let mut addresses = [DeviceAddress::new([0; 6], super::AddressKind::Public); 8];
+        let mut slots = addresses.iter_mut().take(H::MAX_ADDRESSES);
+        let mut count = 0;
+        for address in self.addresses.clone() {
+            match slots.next() {
+                Some(slot) => *slot = address,
+                None => {
+                    hw.clear_address_filter();
+                    return Err(Error::Eof);
+                }
+            }
+            count += 1;
+        }
+
+        hw.set_address_filter(addresses.get(..count).unwrap_or_default())
+    }
+}
+
 pub type SliceIter<'a> = iter::Cloned<slice::Iter<'a, DeviceAddress>>;
 
 impl<'a> WhitelistFilter<SliceIter<'a>> {
@@ -113,3 +164,51 @@ impl<S: AddressFilter> ScanFilter<S> {
         self.scan.matches(device)
     }
 }
+
+/// Initiator filter policy. Governs which advertisers an initiating device connects to.
+///
+/// With a `WhitelistFilter` of bonded devices, this implements *auto connection establishment*: A
+/// connection is made to whichever device on the list is found advertising first, without the
+/// application having to scan for it. A filter matching a single address corresponds to the
+/// policy of connecting to one specific peer.
+///
+/// Note that Rubble's Link-Layer implements neither the Initiating state nor the master role of a
+/// connection yet, so it does not send `CONNECT_REQ`s on its own. This only provides the decision
+/// of whom to connect to.
+pub struct InitiatorFilter<C: AddressFilter> {
+    connect: C,
+}
+
+impl<C: AddressFilter> InitiatorFilter<C> {
+    /// Creates a new initiator filter policy from an `AddressFilter`.
+    pub fn new(connect: C) -> Self {
+        Self { connect }
+    }
+
+    /// Returns the address of the advertiser to connect to in response to `pdu`, if any.
+    ///
+    /// Only connectable advertisements sent by a device matched by the filter are considered.
+    /// Directed advertisements must additionally be addressed at `own_address`.
+    pub fn connect_target(
+        &self,
+        pdu: &Pdu<'_>,
+        own_address: DeviceAddress,
+    ) -> Option<DeviceAddress> {
+        let advertiser = match pdu {
+            Pdu::ConnectableUndirected {
+                advertiser_addr, ..
+            } => *advertiser_addr,
+            Pdu::ConnectableDirected {
+                advertiser_addr,
+                initiator_addr,
+            } if *initiator_addr == own_address => *advertiser_addr,
+            _ => return None,
+        };
+
+        if self.connect.matches(advertiser) {
+            Some(advertiser)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! Groundwork for LE Isochronous Channels.
+//!
+//! Connected Isochronous Streams (CIS) carry time-bounded data (eg. audio) alongside an ACL
+//! connection. A CIS is set up by the master using 3 LL Control PDUs:
+//!
+//! 1. `LL_CIS_REQ` proposes the stream parameters and a range of offsets for the first CIS anchor
+//!    point, relative to an ACL connection event.
+//! 2. The slave answers with `LL_CIS_RSP` (possibly narrowing the offset range) or rejects the
+//!    stream with `LL_REJECT_EXT_IND`.
+//! 3. `LL_CIS_IND` fixes the access address and the actual offset.
+//!
+//! Either side can end a stream with `LL_CIS_TERMINATE_IND`.
+//!
+//! Rubble does not yet schedule isochronous events, so it does not advertise support for the
+//! *Connected Isochronous Stream* feature and a compliant master will never try to create a CIS.
+//! This module defines the interfaces that an implementation will be built around:
+//!
+//! * `IsoHandler` lets the application accept or reject streams and learn about their lifecycle.
+//! * `IsoDataPath` moves SDUs between the application and the stream.
+//! * `IsoScheduler` is implemented by the platform to run the radio at CIS anchor points.
+
+use crate::{
+    link::llcp::{CisIndication, CisRequest, ControlOpcode, ControlPdu},
+    time::{Duration, Instant},
+    utils::Hex,
+};
+
+/// Error code sent when rejecting a CIS (*Unsupported Remote Feature*).
+const UNSUPPORTED_REMOTE_FEATURE: u8 = 0x1A;
+
+/// Negotiated parameters of an established CIS.
+#[derive(Debug, Copy, Clone)]
+pub struct CisParams {
+    request: CisRequest,
+    indication: CisIndication,
+}
+
+impl CisParams {
+    /// Combines the parameters from the `LL_CIS_REQ` and `LL_CIS_IND` PDUs.
+    pub fn new(request: CisRequest, indication: CisIndication) -> Self {
+        Self {
+            request,
+            indication,
+        }
+    }
+
+    /// Returns the parameters proposed by the master.
+    pub fn request(&self) -> &CisRequest {
+        &self.request
+    }
+
+    /// Returns the final timing parameters.
+    pub fn indication(&self) -> &CisIndication {
+        &self.indication
+    }
+
+    /// Computes the first CIS anchor point from the anchor point of the ACL connection event
+    /// referenced by the `LL_CIS_IND`.
+    pub fn first_anchor(&self, acl_anchor: Instant) -> Instant {
+        acl_anchor + self.indication.cis_offset()
+    }
+
+    /// Computes the anchor point of isochronous event `n` (counting from 0 at the first anchor).
+    pub fn anchor(&self, acl_anchor: Instant, n: u32) -> Instant {
+        let interval = self.request.iso_interval().as_micros();
+        self.first_anchor(acl_anchor) + Duration::from_micros(interval * n)
+    }
+}
+
+/// Application hooks for the CIS lifecycle.
+pub trait IsoHandler {
+    /// Called when the master requests a new CIS.
+    ///
+    /// Return `Some((offset_min, offset_max))` to accept the stream, where the range has to lie
+    /// within `req.cis_offset_range()`. Return `None` to reject it.
+    fn cis_requested(&mut self, req: &CisRequest) -> Option<(Duration, Duration)>;
+
+    /// Called when a CIS has been established.
+    fn cis_established(&mut self, params: &CisParams);
+
+    /// Called when a CIS was terminated by either side.
+    fn cis_terminated(&mut self, cig_id: u8, cis_id: u8, reason: u8);
+}
+
+/// Moves isochronous SDUs between the application and the Link-Layer.
+pub trait IsoDataPath {
+    /// Called with an SDU received on stream `cis_id`.
+    ///
+    /// `timestamp` is the reference anchor point of the isochronous event the SDU belongs to.
+    fn sdu_received(&mut self, cis_id: u8, sdu: &[u8], timestamp: Instant);
+
+    /// Requests the next SDU to send on stream `cis_id`.
+    ///
+    /// The SDU must be written to `buf`, and its length returned. Returns `None` if there's no
+    /// data to send in this isochronous event.
+    fn next_sdu(&mut self, cis_id: u8, buf: &mut [u8]) -> Option<usize>;
+}
+
+/// Platform interface for running isochronous events.
+pub trait IsoScheduler {
+    /// Schedules the radio to take part in the subevents of the isochronous event at `anchor`.
+    ///
+    /// Returns `false` if the event conflicts with another radio activity and will be skipped.
+    fn schedule_cis_event(&mut self, params: &CisParams, anchor: Instant) -> bool;
+
+    /// Cancels all future events of stream `cis_id`.
+    fn cancel_cis(&mut self, cis_id: u8);
+}
+
+/// Builds the slave's answer to an `LL_CIS_REQ`.
+///
+/// This asks `handler` whether to accept the stream and returns either an `LL_CIS_RSP` or an
+/// `LL_REJECT_EXT_IND`. An offset range outside of the one proposed by the master results in a
+/// rejection.
+pub fn cis_response(handler: &mut impl IsoHandler, req: &CisRequest) -> ControlPdu<'static> {
+    let (min, max) = req.cis_offset_range();
+    match handler.cis_requested(req) {
+        Some((offset_min, offset_max))
+            if offset_min >= min && offset_max <= max && offset_min <= offset_max =>
+        {
+            ControlPdu::CisRsp {
+                cis_offset_min: offset_min.as_micros(),
+                cis_offset_max: offset_max.as_micros(),
+                conn_event_count: req.conn_event_count(),
+            }
+        }
+        _ => ControlPdu::RejectExtInd {
+            reject_opcode: ControlOpcode::CisReq,
+            error_code: Hex(UNSUPPORTED_REMOTE_FEATURE),
+        },
+    }
+}
@@ -0,0 +1,256 @@
+//! Link-quality monitoring and adaptive transmission power.
+//!
+//! A [`LinkQualityManager`] can be installed on a connection to watch the RSSI and CRC errors of
+//! the master's packets. It collects them over a window of connection events, and at the end of
+//! each window decides whether the link has enough margin to lower the transmission power, or is
+//! so weak that it should be raised. When there's nothing left to adjust, it suggests switching
+//! to a more suitable PHY instead.
+//!
+//! Rubble only supports the LE 1M PHY, so PHY suggestions are purely informational: they can be
+//! used to tune the connection from the other side, or to pick different parameters next time.
+//!
+//! Decisions are reported to the `ConnectionEventHook` through
+//! `ConnectionEventSummary::link_quality`.
+//!
+//! [`LinkQualityManager`]: struct.LinkQualityManager.html
+
+use crate::{
+    link::{llcp::Phys, ConnectionEventSummary},
+    phy::TxPower,
+};
+
+/// Parameters of a `LinkQualityManager`.
+#[derive(Debug, Copy, Clone)]
+pub struct LinkQualityConfig {
+    /// Lowest transmission power the manager will lower to.
+    pub min_power: TxPower,
+
+    /// Highest transmission power the manager will raise to.
+    pub max_power: TxPower,
+
+    /// Amount of dB to change the power by in a single step.
+    ///
+    /// The radio clamps the power to the levels it supports, so this should be at least the
+    /// spacing between those levels.
+    pub step_db: u8,
+
+    /// Average RSSI (in dBm) below which the link is considered weak.
+    pub rssi_low: i8,
+
+    /// Average RSSI (in dBm) above which the link is considered to have excess margin.
+    pub rssi_high: i8,
+
+    /// Percentage of useless events (missed or with a CRC error) above which the link is
+    /// considered weak, regardless of the RSSI.
+    pub max_error_percent: u8,
+
+    /// Number of connection events to collect statistics over before making a decision.
+    pub window: u8,
+}
+
+impl Default for LinkQualityConfig {
+    fn default() -> Self {
+        Self {
+            min_power: TxPower::from_dbm(-20),
+            max_power: TxPower::from_dbm(4),
+            step_db: 4,
+            rssi_low: -80,
+            rssi_high: -55,
+            max_error_percent: 10,
+            window: 32,
+        }
+    }
+}
+
+/// A decision made by the `LinkQualityManager`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LinkQualityEvent {
+    /// The connection's transmission power was changed to this level.
+    TxPowerChanged(TxPower),
+
+    /// The transmission power can't be adjusted further, and the link would be better served by
+    /// the given PHY.
+    ///
+    /// `Phys::LE_CODED` is suggested for weak links at maximum power, `Phys::LE_2M` for strong
+    /// links at minimum power. A suggestion is only reported once until the link state changes.
+    PhySuggested(Phys),
+}
+
+/// Per-connection controller that adapts the transmission power to the link quality.
+#[derive(Debug, Clone)]
+pub struct LinkQualityManager {
+    config: LinkQualityConfig,
+    events: u8,
+    errors: u8,
+    rssi_sum: i32,
+    rssi_samples: u8,
+    average_rssi: Option<i8>,
+    suggested: Option<Phys>,
+}
+
+impl LinkQualityManager {
+    /// Creates a manager using `config`.
+    pub fn new(config: LinkQualityConfig) -> Self {
+        Self {
+            config,
+            events: 0,
+            errors: 0,
+            rssi_sum: 0,
+            rssi_samples: 0,
+            average_rssi: None,
+            suggested: None,
+        }
+    }
+
+    /// Returns the configuration of this manager.
+    pub fn config(&self) -> &LinkQualityConfig {
+        &self.config
+    }
+
+    /// Returns the average RSSI measured over the last complete window, if the radio reported any.
+    pub fn average_rssi(&self) -> Option<i8> {
+        self.average_rssi
+    }
+
+    /// Accounts for a finished connection event.
+    ///
+    /// `current` is the transmission power currently used by the connection. Returns the decision
+    /// made at the end of a window, if any. `TxPowerChanged` must be applied to the connection by
+    /// the caller.
+    pub fn event_end(
+        &mut self,
+        summary: &ConnectionEventSummary,
+        current: TxPower,
+    ) -> Option<LinkQualityEvent> {
+        self.events = self.events.saturating_add(1);
+        if summary.packets_received == 0 || summary.crc_errors > 0 {
+            self.errors = self.errors.saturating_add(1);
+        }
+        if let Some(rssi) = summary.rssi {
+            self.rssi_sum += i32::from(rssi);
+            self.rssi_samples = self.rssi_samples.saturating_add(1);
+        }
+
+        if self.events < self.config.window.max(1) {
+            return None;
+        }
+
+        let error_percent = u32::from(self.errors) * 100 / u32::from(self.events);
+        self.average_rssi = if self.rssi_samples == 0 {
+            None
+        } else {
+            Some((self.rssi_sum / i32::from(self.rssi_samples)) as i8)
+        };
+        self.events = 0;
+        self.errors = 0;
+        self.rssi_sum = 0;
+        self.rssi_samples = 0;
+
+        let weak = error_percent > u32::from(self.config.max_error_percent)
+            || self
+                .average_rssi
+                .is_some_and(|rssi| rssi < self.config.rssi_low);
+        // Without RSSI measurements, excess margin can't be detected
+        let strong = error_percent == 0
+            && self
+                .average_rssi
+                .is_some_and(|rssi| rssi > self.config.rssi_high);
+
+        let step = self.config.step_db as i8;
+        if weak {
+            if current < self.config.max_power {
+                let raised = TxPower::from_dbm(current.as_dbm().saturating_add(step));
+                self.power_changed(raised.min(self.config.max_power))
+            } else {
+                self.suggest(Phys::LE_CODED)
+            }
+        } else if strong {
+            if current > self.config.min_power {
+                let lowered = TxPower::from_dbm(current.as_dbm().saturating_sub(step));
+                self.power_changed(lowered.max(self.config.min_power))
+            } else {
+                self.suggest(Phys::LE_2M)
+            }
+        } else {
+            self.suggested = None;
+            None
+        }
+    }
+
+    fn power_changed(&mut self, power: TxPower) -> Option<LinkQualityEvent> {
+        self.suggested = None;
+        Some(LinkQualityEvent::TxPowerChanged(power))
+    }
+
+    fn suggest(&mut self, phy: Phys) -> Option<LinkQualityEvent> {
+        if self.suggested == Some(phy) {
+            None
+        } else {
+            self.suggested = Some(phy);
+            Some(LinkQualityEvent::PhySuggested(phy))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::time::Duration};
+
+    fn summary(rssi: i8, crc_errors: u8) -> ConnectionEventSummary {
+        ConnectionEventSummary {
+            event_counter: 0,
+            packets_received: 1,
+            packets_sent: 1,
+            crc_errors,
+            more_data: false,
+            time_to_next_anchor: Duration::from_micros(0),
+            rssi: Some(rssi),
+            rx_timestamp: None,
+            payload_queued: false,
+            tx_power: TxPower::ZERO_DBM,
+            link_quality: None,
+        }
+    }
+
+    #[test]
+    fn adapts_power() {
+        let mut mgr = LinkQualityManager::new(LinkQualityConfig {
+            window: 4,
+            ..LinkQualityConfig::default()
+        });
+        let mut power = TxPower::ZERO_DBM;
+        let run = |mgr: &mut LinkQualityManager, power: &mut TxPower, rssi, crc_errors| {
+            let mut decision = None;
+            for _ in 0..4 {
+                decision = mgr.event_end(&summary(rssi, crc_errors), *power);
+            }
+            if let Some(LinkQualityEvent::TxPowerChanged(p)) = decision {
+                *power = p;
+            }
+            decision
+        };
+
+        // Strong signal: lower the power until the minimum is reached, then suggest 2M once
+        for &dbm in &[-4, -8, -12, -16, -20] {
+            assert_eq!(
+                run(&mut mgr, &mut power, -40, 0),
+                Some(LinkQualityEvent::TxPowerChanged(TxPower::from_dbm(dbm)))
+            );
+        }
+        assert_eq!(
+            run(&mut mgr, &mut power, -40, 0),
+            Some(LinkQualityEvent::PhySuggested(Phys::LE_2M))
+        );
+        assert_eq!(run(&mut mgr, &mut power, -40, 0), None);
+        assert_eq!(mgr.average_rssi(), Some(-40));
+
+        // Good enough: leave the power alone
+        assert_eq!(run(&mut mgr, &mut power, -70, 0), None);
+
+        // CRC errors make the link weak even with a decent RSSI
+        assert_eq!(
+            run(&mut mgr, &mut power, -70, 1),
+            Some(LinkQualityEvent::TxPowerChanged(TxPower::from_dbm(-16)))
+        );
+    }
+}
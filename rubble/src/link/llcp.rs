@@ -3,11 +3,15 @@
 use {
     crate::{
         bytes::*,
-        link::{channel_map::ChannelMap, comp_id::CompanyId, features::FeatureSet},
+        link::{
+            channel_map::ChannelMap, comp_id::CompanyId, cte::CteInfo, features::FeatureSet,
+            power_control::PowerLimits,
+        },
         time::Duration,
         utils::Hex,
         Error,
     },
+    bitflags::bitflags,
     core::{cmp, convert::TryInto},
 };
 
@@ -66,8 +70,8 @@ impl ConnectionParamRequest {
         let min = min.as_micros() / 1_250;
 
         // Clamp to valid range of 6..=3200
-        let min = cmp::min(cmp::max(min, 6), 3200);
-        let max = cmp::min(cmp::max(max, 6), 3200);
+        let min = min.clamp(6, 3200);
+        let max = max.clamp(6, 3200);
         debug_assert!(min <= max);
         self.interval_min = min as u16;
         self.interval_max = max as u16;
@@ -94,6 +98,12 @@ impl ConnectionParamRequest {
     }
 }
 
+impl Default for ConnectionParamRequest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<'a> FromBytes<'a> for ConnectionParamRequest {
     fn from_bytes(bytes: &mut ByteReader<'a>) -> Result<Self, Error> {
         Ok(Self {
@@ -174,6 +184,401 @@ impl ConnectionUpdateData {
     }
 }
 
+/// Parameters of a Connected Isochronous Stream, sent by the master in an `LL_CIS_REQ`.
+///
+/// "C to P" fields describe the Central (master) to Peripheral (slave) direction, "P to C" fields
+/// the opposite direction.
+#[derive(Debug, Copy, Clone)]
+pub struct CisRequest {
+    cig_id: u8,
+    cis_id: u8,
+    phy_c_to_p: Phys,
+    phy_p_to_c: Phys,
+    /// Bits 0-11: `Max_SDU_C_To_P`, bit 15: `Framed`.
+    max_sdu_c_to_p: u16,
+    max_sdu_p_to_c: u16,
+    sdu_interval_c_to_p: u32,
+    sdu_interval_p_to_c: u32,
+    max_pdu_c_to_p: u16,
+    max_pdu_p_to_c: u16,
+    nse: u8,
+    sub_interval: u32,
+    /// Bits 0-3: `BN_C_To_P`, bits 4-7: `BN_P_To_C`.
+    bn: u8,
+    ft_c_to_p: u8,
+    ft_p_to_c: u8,
+    iso_interval: u16,
+    cis_offset_min: u32,
+    cis_offset_max: u32,
+    conn_event_count: u16,
+}
+
+impl CisRequest {
+    /// Returns the ID of the Connected Isochronous Group the stream belongs to.
+    pub fn cig_id(&self) -> u8 {
+        self.cig_id
+    }
+
+    /// Returns the ID of the stream within its group.
+    pub fn cis_id(&self) -> u8 {
+        self.cis_id
+    }
+
+    /// Returns the PHYs used in master-to-slave and slave-to-master direction.
+    pub fn phys(&self) -> (Phys, Phys) {
+        (self.phy_c_to_p, self.phy_p_to_c)
+    }
+
+    /// Returns whether the stream carries framed PDUs.
+    pub fn framed(&self) -> bool {
+        self.max_sdu_c_to_p & 0x8000 != 0
+    }
+
+    /// Returns the maximum SDU sizes in master-to-slave and slave-to-master direction.
+    pub fn max_sdu(&self) -> (u16, u16) {
+        (self.max_sdu_c_to_p & 0x0FFF, self.max_sdu_p_to_c & 0x0FFF)
+    }
+
+    /// Returns the SDU intervals in master-to-slave and slave-to-master direction.
+    pub fn sdu_interval(&self) -> (Duration, Duration) {
+        (
+            Duration::from_micros(self.sdu_interval_c_to_p & 0x0F_FFFF),
+            Duration::from_micros(self.sdu_interval_p_to_c & 0x0F_FFFF),
+        )
+    }
+
+    /// Returns the maximum PDU payload sizes in master-to-slave and slave-to-master direction.
+    pub fn max_pdu(&self) -> (u16, u16) {
+        (self.max_pdu_c_to_p, self.max_pdu_p_to_c)
+    }
+
+    /// Returns the maximum number of subevents in each isochronous event.
+    pub fn num_subevents(&self) -> u8 {
+        self.nse
+    }
+
+    /// Returns the time between the start of 2 consecutive subevents.
+    pub fn sub_interval(&self) -> Duration {
+        Duration::from_micros(self.sub_interval)
+    }
+
+    /// Returns the burst numbers in master-to-slave and slave-to-master direction.
+    pub fn burst_number(&self) -> (u8, u8) {
+        (self.bn & 0x0F, self.bn >> 4)
+    }
+
+    /// Returns the flush timeouts (in multiples of the ISO interval) in master-to-slave and
+    /// slave-to-master direction.
+    pub fn flush_timeout(&self) -> (u8, u8) {
+        (self.ft_c_to_p, self.ft_p_to_c)
+    }
+
+    /// Returns the time between 2 consecutive CIS anchor points.
+    pub fn iso_interval(&self) -> Duration {
+        Duration::from_micros(u32::from(self.iso_interval) * 1_250)
+    }
+
+    /// Returns the range of offsets from the ACL anchor point the master proposes for the first
+    /// CIS anchor point.
+    pub fn cis_offset_range(&self) -> (Duration, Duration) {
+        (
+            Duration::from_micros(self.cis_offset_min),
+            Duration::from_micros(self.cis_offset_max),
+        )
+    }
+
+    /// Returns the ACL connection event counter the CIS offsets are relative to.
+    pub fn conn_event_count(&self) -> u16 {
+        self.conn_event_count
+    }
+}
+
+impl<'a> FromBytes<'a> for CisRequest {
+    fn from_bytes(bytes: &mut ByteReader<'a>) -> Result<Self, Error> {
+        Ok(Self {
+            cig_id: bytes.read_u8()?,
+            cis_id: bytes.read_u8()?,
+            phy_c_to_p: Phys::from_bits_truncate(bytes.read_u8()?),
+            phy_p_to_c: Phys::from_bits_truncate(bytes.read_u8()?),
+            max_sdu_c_to_p: bytes.read_u16_le()?,
+            max_sdu_p_to_c: bytes.read_u16_le()?,
+            sdu_interval_c_to_p: bytes.read_u24_le()?,
+            sdu_interval_p_to_c: bytes.read_u24_le()?,
+            max_pdu_c_to_p: bytes.read_u16_le()?,
+            max_pdu_p_to_c: bytes.read_u16_le()?,
+            nse: bytes.read_u8()?,
+            sub_interval: bytes.read_u24_le()?,
+            bn: bytes.read_u8()?,
+            ft_c_to_p: bytes.read_u8()?,
+            ft_p_to_c: bytes.read_u8()?,
+            iso_interval: bytes.read_u16_le()?,
+            cis_offset_min: bytes.read_u24_le()?,
+            cis_offset_max: bytes.read_u24_le()?,
+            conn_event_count: bytes.read_u16_le()?,
+        })
+    }
+}
+
+impl ToBytes for CisRequest {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u8(self.cig_id)?;
+        writer.write_u8(self.cis_id)?;
+        writer.write_u8(self.phy_c_to_p.bits())?;
+        writer.write_u8(self.phy_p_to_c.bits())?;
+        writer.write_u16_le(self.max_sdu_c_to_p)?;
+        writer.write_u16_le(self.max_sdu_p_to_c)?;
+        writer.write_u24_le(self.sdu_interval_c_to_p)?;
+        writer.write_u24_le(self.sdu_interval_p_to_c)?;
+        writer.write_u16_le(self.max_pdu_c_to_p)?;
+        writer.write_u16_le(self.max_pdu_p_to_c)?;
+        writer.write_u8(self.nse)?;
+        writer.write_u24_le(self.sub_interval)?;
+        writer.write_u8(self.bn)?;
+        writer.write_u8(self.ft_c_to_p)?;
+        writer.write_u8(self.ft_p_to_c)?;
+        writer.write_u16_le(self.iso_interval)?;
+        writer.write_u24_le(self.cis_offset_min)?;
+        writer.write_u24_le(self.cis_offset_max)?;
+        writer.write_u16_le(self.conn_event_count)?;
+        Ok(())
+    }
+}
+
+/// Final CIS parameters, sent by the master in an `LL_CIS_IND`.
+#[derive(Debug, Copy, Clone)]
+pub struct CisIndication {
+    access_address: u32,
+    cis_offset: u32,
+    cig_sync_delay: u32,
+    cis_sync_delay: u32,
+    conn_event_count: u16,
+}
+
+impl CisIndication {
+    /// Returns the access address used by the CIS.
+    pub fn access_address(&self) -> u32 {
+        self.access_address
+    }
+
+    /// Returns the offset of the first CIS anchor point from the ACL anchor point of the
+    /// connection event `conn_event_count`.
+    pub fn cis_offset(&self) -> Duration {
+        Duration::from_micros(self.cis_offset)
+    }
+
+    /// Returns the maximum time between a CIG anchor point and the CIG synchronization point.
+    pub fn cig_sync_delay(&self) -> Duration {
+        Duration::from_micros(self.cig_sync_delay)
+    }
+
+    /// Returns the maximum time between a CIS anchor point and the CIG synchronization point.
+    pub fn cis_sync_delay(&self) -> Duration {
+        Duration::from_micros(self.cis_sync_delay)
+    }
+
+    /// Returns the ACL connection event counter `cis_offset` is relative to.
+    pub fn conn_event_count(&self) -> u16 {
+        self.conn_event_count
+    }
+}
+
+impl<'a> FromBytes<'a> for CisIndication {
+    fn from_bytes(bytes: &mut ByteReader<'a>) -> Result<Self, Error> {
+        Ok(Self {
+            access_address: bytes.read_u32_le()?,
+            cis_offset: bytes.read_u24_le()?,
+            cig_sync_delay: bytes.read_u24_le()?,
+            cis_sync_delay: bytes.read_u24_le()?,
+            conn_event_count: bytes.read_u16_le()?,
+        })
+    }
+}
+
+impl ToBytes for CisIndication {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u32_le(self.access_address)?;
+        writer.write_u24_le(self.cis_offset)?;
+        writer.write_u24_le(self.cig_sync_delay)?;
+        writer.write_u24_le(self.cis_sync_delay)?;
+        writer.write_u16_le(self.conn_event_count)?;
+        Ok(())
+    }
+}
+
+/// Subrating parameters requested by the slave in an `LL_SUBRATE_REQ`.
+#[derive(Debug, Copy, Clone)]
+pub struct SubrateRequest {
+    subrate_factor_min: u16,
+    subrate_factor_max: u16,
+    max_latency: u16,
+    continuation_number: u16,
+    timeout: u16,
+}
+
+impl SubrateRequest {
+    /// Largest subrate factor allowed by the spec.
+    pub const MAX_SUBRATE_FACTOR: u16 = 500;
+
+    /// Creates a request for a subrate factor between `factor_min` and `factor_max`.
+    ///
+    /// # Parameters
+    ///
+    /// * `factor_min`, `factor_max`: Range of acceptable subrate factors. Only every
+    ///   `factor`-th connection event will be used.
+    /// * `max_latency`: Maximum slave latency, in subrated events.
+    /// * `continuation_number`: Number of connection events to stay active for after a subrated
+    ///   event in which data was exchanged.
+    /// * `timeout`: Supervision timeout to use while subrated. Will be rounded down to units of
+    ///   10 ms.
+    ///
+    /// Returns `Error::InvalidValue` if the factors aren't in range `1..=MAX_SUBRATE_FACTOR` with
+    /// `factor_min <= factor_max`, if `continuation_number` isn't less than `factor_max`, or if
+    /// `timeout` isn't between 100 ms and 32 s.
+    pub fn new(
+        factor_min: u16,
+        factor_max: u16,
+        max_latency: u16,
+        continuation_number: u16,
+        timeout: Duration,
+    ) -> Result<Self, Error> {
+        let timeout = timeout.as_micros() / 10_000;
+        if factor_min < 1
+            || factor_min > factor_max
+            || factor_max > Self::MAX_SUBRATE_FACTOR
+            || continuation_number >= factor_max
+            || max_latency > 499
+            || !(10..=3200).contains(&timeout)
+        {
+            return Err(Error::InvalidValue);
+        }
+
+        Ok(Self {
+            subrate_factor_min: factor_min,
+            subrate_factor_max: factor_max,
+            max_latency,
+            continuation_number,
+            timeout: timeout as u16,
+        })
+    }
+
+    /// Returns the minimum acceptable subrate factor.
+    pub fn subrate_factor_min(&self) -> u16 {
+        self.subrate_factor_min
+    }
+
+    /// Returns the maximum acceptable subrate factor.
+    pub fn subrate_factor_max(&self) -> u16 {
+        self.subrate_factor_max
+    }
+
+    /// Returns the maximum slave latency, in subrated events.
+    pub fn max_latency(&self) -> u16 {
+        self.max_latency
+    }
+
+    /// Returns the requested continuation number.
+    pub fn continuation_number(&self) -> u16 {
+        self.continuation_number
+    }
+
+    /// Returns the requested supervision timeout.
+    pub fn timeout(&self) -> Duration {
+        Duration::from_micros(u32::from(self.timeout) * 10_000)
+    }
+}
+
+impl<'a> FromBytes<'a> for SubrateRequest {
+    fn from_bytes(bytes: &mut ByteReader<'a>) -> Result<Self, Error> {
+        Ok(Self {
+            subrate_factor_min: bytes.read_u16_le()?,
+            subrate_factor_max: bytes.read_u16_le()?,
+            max_latency: bytes.read_u16_le()?,
+            continuation_number: bytes.read_u16_le()?,
+            timeout: bytes.read_u16_le()?,
+        })
+    }
+}
+
+impl ToBytes for SubrateRequest {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u16_le(self.subrate_factor_min)?;
+        writer.write_u16_le(self.subrate_factor_max)?;
+        writer.write_u16_le(self.max_latency)?;
+        writer.write_u16_le(self.continuation_number)?;
+        writer.write_u16_le(self.timeout)?;
+        Ok(())
+    }
+}
+
+/// Subrating parameters chosen by the master, sent in an `LL_SUBRATE_IND`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SubrateIndication {
+    subrate_factor: u16,
+    subrate_base_event: u16,
+    latency: u16,
+    continuation_number: u16,
+    timeout: u16,
+}
+
+impl SubrateIndication {
+    /// Returns the subrate factor: Only every `subrate_factor`-th connection event is used.
+    pub fn subrate_factor(&self) -> u16 {
+        self.subrate_factor
+    }
+
+    /// Returns the connection event counter subrated events are counted from.
+    pub fn subrate_base_event(&self) -> u16 {
+        self.subrate_base_event
+    }
+
+    /// Returns the slave latency, in subrated events.
+    pub fn latency(&self) -> u16 {
+        self.latency
+    }
+
+    /// Returns the number of connection events that stay active after a subrated event in which
+    /// data was exchanged.
+    pub fn continuation_number(&self) -> u16 {
+        self.continuation_number
+    }
+
+    /// Returns the supervision timeout used while subrated.
+    pub fn timeout(&self) -> Duration {
+        Duration::from_micros(u32::from(self.timeout) * 10_000)
+    }
+
+    /// Returns whether the connection event with counter `event_counter` is a subrated event.
+    pub fn is_subrated_event(&self, event_counter: u16) -> bool {
+        let factor = cmp::max(self.subrate_factor, 1);
+        event_counter
+            .wrapping_sub(self.subrate_base_event)
+            .is_multiple_of(factor)
+    }
+}
+
+impl<'a> FromBytes<'a> for SubrateIndication {
+    fn from_bytes(bytes: &mut ByteReader<'a>) -> Result<Self, Error> {
+        Ok(Self {
+            subrate_factor: bytes.read_u16_le()?,
+            subrate_base_event: bytes.read_u16_le()?,
+            latency: bytes.read_u16_le()?,
+            continuation_number: bytes.read_u16_le()?,
+            timeout: bytes.read_u16_le()?,
+        })
+    }
+}
+
+impl ToBytes for SubrateIndication {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u16_le(self.subrate_factor)?;
+        writer.write_u16_le(self.subrate_base_event)?;
+        writer.write_u16_le(self.latency)?;
+        writer.write_u16_le(self.continuation_number)?;
+        writer.write_u16_le(self.timeout)?;
+        Ok(())
+    }
+}
+
 /// A structured representation of an LL Control PDU used by the Link Layer Control Protocol (LLCP).
 #[derive(Debug, Copy, Clone)]
 pub enum ControlPdu<'a> {
@@ -232,6 +637,99 @@ pub enum ControlPdu<'a> {
     ConnectionParamReq(ConnectionParamRequest),
     ConnectionParamRsp(ConnectionParamRequest),
 
+    /// `0x19`/`LL_MIN_USED_CHANNELS_IND` - Slave requires the master to use a minimum number of
+    /// channels.
+    ///
+    /// Sent by the slave. The master has to make sure that the channel map it uses contains at
+    /// least `min_used_channels` channels when any of the `phys` is in use.
+    MinUsedChannelsInd {
+        /// The PHYs the requirement applies to.
+        phys: Phys,
+        /// Minimum number of channels to use, in range 2..=37.
+        min_used_channels: u8,
+    },
+
+    /// `0x1A`/`LL_CTE_REQ` - Requests the peer to send an `LL_CTE_RSP` with a Constant Tone
+    /// Extension attached.
+    CteReq {
+        /// Minimum CTE length and requested CTE type.
+        cte: CteInfo,
+    },
+
+    /// `0x1B`/`LL_CTE_RSP` - Response to `LL_CTE_REQ`, carries the requested CTE.
+    CteRsp,
+
+    /// `0x11`/`LL_REJECT_EXT_IND` - Rejects a procedure initiated by the peer.
+    RejectExtInd {
+        /// Opcode of the rejected LL Control PDU.
+        reject_opcode: ControlOpcode,
+        /// Reason for the rejection.
+        error_code: Hex<u8>,
+    },
+
+    /// `0x1F`/`LL_CIS_REQ` - Master requests creation of a Connected Isochronous Stream.
+    CisReq(CisRequest),
+
+    /// `0x20`/`LL_CIS_RSP` - Slave accepts an `LL_CIS_REQ`, possibly narrowing the offset range.
+    CisRsp {
+        cis_offset_min: u32,
+        cis_offset_max: u32,
+        conn_event_count: u16,
+    },
+
+    /// `0x21`/`LL_CIS_IND` - Master indicates the final parameters of a CIS.
+    CisInd(CisIndication),
+
+    /// `0x22`/`LL_CIS_TERMINATE_IND` - Terminates a CIS (sent by master or slave).
+    CisTerminateInd {
+        cig_id: u8,
+        cis_id: u8,
+        error_code: Hex<u8>,
+    },
+
+    /// `0x23`/`LL_POWER_CONTROL_REQ` - Requests the peer to change its transmission power.
+    ///
+    /// Can be sent by master or slave, and is answered with an `LL_POWER_CONTROL_RSP`.
+    PowerControlReq {
+        /// The PHY the request applies to (a single bit).
+        phy: Phys,
+        /// Requested change in dB, or `power_control::DELTA_UNAVAILABLE` to only ask for the
+        /// peer's power level.
+        delta: i8,
+        /// Current power level of the sender in dBm.
+        tx_power: i8,
+    },
+
+    /// `0x24`/`LL_POWER_CONTROL_RSP` - Reports the power level after an `LL_POWER_CONTROL_REQ`.
+    PowerControlRsp {
+        limits: PowerLimits,
+        /// Change in dB that was actually made.
+        delta: i8,
+        /// New power level of the sender in dBm.
+        tx_power: i8,
+        /// Acceptable power reduction: By how many dB the receiver may lower its power.
+        apr: u8,
+    },
+
+    /// `0x25`/`LL_POWER_CHANGE_IND` - Reports a change of the sender's transmission power.
+    PowerChangeInd {
+        /// The PHYs the change applies to.
+        phys: Phys,
+        limits: PowerLimits,
+        /// Change in dB.
+        delta: i8,
+        /// New power level of the sender in dBm.
+        tx_power: i8,
+    },
+
+    /// `0x26`/`LL_SUBRATE_REQ` - Slave requests connection subrating.
+    SubrateReq(SubrateRequest),
+
+    /// `0x27`/`LL_SUBRATE_IND` - Master indicates the subrating parameters to use.
+    ///
+    /// Sent in response to an `LL_SUBRATE_REQ`, or on the master's own accord.
+    SubrateInd(SubrateIndication),
+
     /// Catch-all variant for unsupported opcodes.
     Unknown {
         /// The opcode we don't support. This can also be the `Unknown` variant.
@@ -255,6 +753,19 @@ impl ControlPdu<'_> {
             ControlPdu::VersionInd { .. } => ControlOpcode::VersionInd,
             ControlPdu::ConnectionParamReq(_) => ControlOpcode::ConnectionParamReq,
             ControlPdu::ConnectionParamRsp(_) => ControlOpcode::ConnectionParamRsp,
+            ControlPdu::MinUsedChannelsInd { .. } => ControlOpcode::MinUsedChannelsInd,
+            ControlPdu::CteReq { .. } => ControlOpcode::CteReq,
+            ControlPdu::CteRsp => ControlOpcode::CteRsp,
+            ControlPdu::RejectExtInd { .. } => ControlOpcode::RejectIndExt,
+            ControlPdu::CisReq(_) => ControlOpcode::CisReq,
+            ControlPdu::CisRsp { .. } => ControlOpcode::CisRsp,
+            ControlPdu::CisInd(_) => ControlOpcode::CisInd,
+            ControlPdu::CisTerminateInd { .. } => ControlOpcode::CisTerminateInd,
+            ControlPdu::PowerControlReq { .. } => ControlOpcode::PowerControlReq,
+            ControlPdu::PowerControlRsp { .. } => ControlOpcode::PowerControlRsp,
+            ControlPdu::PowerChangeInd { .. } => ControlOpcode::PowerChangeInd,
+            ControlPdu::SubrateReq(_) => ControlOpcode::SubrateReq,
+            ControlPdu::SubrateInd(_) => ControlOpcode::SubrateInd,
             ControlPdu::Unknown { opcode, .. } => *opcode,
         }
     }
@@ -263,7 +774,7 @@ impl ControlPdu<'_> {
     pub fn encoded_size(&self) -> u8 {
         use self::ControlOpcode::*;
 
-        1 + match self.opcode() {
+        let size = match self.opcode() {
             ConnectionUpdateReq => 1 + 2 + 2 + 2 + 2 + 2,
             ChannelMapReq => 5 + 2,
             TerminateInd => 1,
@@ -286,18 +797,30 @@ impl ControlPdu<'_> {
             PingReq => 0,
             PingRsp => 0,
             LengthReq | LengthRsp => 2 + 2 + 2 + 2,
-            Unknown(_) => {
-                if let ControlPdu::Unknown {
-                    ctr_data,
-                    opcode: _,
-                } = self
-                {
-                    ctr_data.len().try_into().unwrap()
-                } else {
-                    unreachable!()
+            PhyReq | PhyRsp => 1 + 1,
+            PhyUpdateInd => 1 + 1 + 2,
+            MinUsedChannelsInd => 1 + 1,
+            CteReq => 1,
+            CteRsp => 0,
+            PeriodicSyncInd => 34,
+            ClockAccuracyReq | ClockAccuracyRsp => 1,
+            CisReq => 36,
+            CisRsp => 3 + 3 + 2,
+            CisInd => 4 + 3 + 3 + 3 + 2,
+            CisTerminateInd => 1 + 1 + 1,
+            PowerControlReq => 1 + 1 + 1,
+            PowerControlRsp => 1 + 1 + 1 + 1,
+            PowerChangeInd => 1 + 1 + 1 + 1,
+            SubrateReq | SubrateInd => 2 + 2 + 2 + 2 + 2,
+            Unknown(_) => match self {
+                ControlPdu::Unknown { ctr_data, .. } => {
+                    ctr_data.len().try_into().unwrap_or(u8::MAX)
                 }
-            }
-        }
+                // Only `ControlPdu::Unknown` can have an unknown opcode
+                _ => 0,
+            },
+        };
+        size.saturating_add(1)
     }
 }
 
@@ -336,6 +859,51 @@ impl<'a> FromBytes<'a> for ControlPdu<'a> {
                 comp_id: CompanyId::from_raw(bytes.read_u16_le()?),
                 sub_vers_nr: Hex(bytes.read_u16_le()?),
             },
+            ControlOpcode::MinUsedChannelsInd => ControlPdu::MinUsedChannelsInd {
+                phys: Phys::from_bits_truncate(bytes.read_u8()?),
+                min_used_channels: bytes.read_u8()?,
+            },
+            ControlOpcode::CteReq => ControlPdu::CteReq {
+                cte: CteInfo::from_raw(bytes.read_u8()?),
+            },
+            ControlOpcode::CteRsp => ControlPdu::CteRsp,
+            ControlOpcode::RejectIndExt => ControlPdu::RejectExtInd {
+                reject_opcode: ControlOpcode::from(bytes.read_u8()?),
+                error_code: Hex(bytes.read_u8()?),
+            },
+            ControlOpcode::CisReq => ControlPdu::CisReq(CisRequest::from_bytes(bytes)?),
+            ControlOpcode::CisRsp => ControlPdu::CisRsp {
+                cis_offset_min: bytes.read_u24_le()?,
+                cis_offset_max: bytes.read_u24_le()?,
+                conn_event_count: bytes.read_u16_le()?,
+            },
+            ControlOpcode::CisInd => ControlPdu::CisInd(CisIndication::from_bytes(bytes)?),
+            ControlOpcode::CisTerminateInd => ControlPdu::CisTerminateInd {
+                cig_id: bytes.read_u8()?,
+                cis_id: bytes.read_u8()?,
+                error_code: Hex(bytes.read_u8()?),
+            },
+            ControlOpcode::PowerControlReq => ControlPdu::PowerControlReq {
+                phy: Phys::from_bits_truncate(bytes.read_u8()?),
+                delta: bytes.read_u8()? as i8,
+                tx_power: bytes.read_u8()? as i8,
+            },
+            ControlOpcode::PowerControlRsp => ControlPdu::PowerControlRsp {
+                limits: PowerLimits::from_bits_truncate(bytes.read_u8()?),
+                delta: bytes.read_u8()? as i8,
+                tx_power: bytes.read_u8()? as i8,
+                apr: bytes.read_u8()?,
+            },
+            ControlOpcode::PowerChangeInd => ControlPdu::PowerChangeInd {
+                phys: Phys::from_bits_truncate(bytes.read_u8()?),
+                limits: PowerLimits::from_bits_truncate(bytes.read_u8()?),
+                delta: bytes.read_u8()? as i8,
+                tx_power: bytes.read_u8()? as i8,
+            },
+            ControlOpcode::SubrateReq => ControlPdu::SubrateReq(SubrateRequest::from_bytes(bytes)?),
+            ControlOpcode::SubrateInd => {
+                ControlPdu::SubrateInd(SubrateIndication::from_bytes(bytes)?)
+            }
             _ => ControlPdu::Unknown {
                 opcode,
                 ctr_data: bytes.read_rest(),
@@ -385,6 +953,85 @@ impl<'a> ToBytes for ControlPdu<'a> {
             ControlPdu::ConnectionParamReq(data) | ControlPdu::ConnectionParamRsp(data) => {
                 data.to_bytes(buffer)
             }
+            ControlPdu::MinUsedChannelsInd {
+                phys,
+                min_used_channels,
+            } => {
+                buffer.write_u8(phys.bits())?;
+                buffer.write_u8(*min_used_channels)?;
+                Ok(())
+            }
+            ControlPdu::CteReq { cte } => {
+                buffer.write_u8(cte.to_raw())?;
+                Ok(())
+            }
+            ControlPdu::CteRsp => Ok(()),
+            ControlPdu::RejectExtInd {
+                reject_opcode,
+                error_code,
+            } => {
+                buffer.write_u8(u8::from(*reject_opcode))?;
+                buffer.write_u8(error_code.0)?;
+                Ok(())
+            }
+            ControlPdu::CisReq(req) => req.to_bytes(buffer),
+            ControlPdu::CisRsp {
+                cis_offset_min,
+                cis_offset_max,
+                conn_event_count,
+            } => {
+                buffer.write_u24_le(*cis_offset_min)?;
+                buffer.write_u24_le(*cis_offset_max)?;
+                buffer.write_u16_le(*conn_event_count)?;
+                Ok(())
+            }
+            ControlPdu::CisInd(ind) => ind.to_bytes(buffer),
+            ControlPdu::CisTerminateInd {
+                cig_id,
+                cis_id,
+                error_code,
+            } => {
+                buffer.write_u8(*cig_id)?;
+                buffer.write_u8(*cis_id)?;
+                buffer.write_u8(error_code.0)?;
+                Ok(())
+            }
+            ControlPdu::PowerControlReq {
+                phy,
+                delta,
+                tx_power,
+            } => {
+                buffer.write_u8(phy.bits())?;
+                buffer.write_u8(*delta as u8)?;
+                buffer.write_u8(*tx_power as u8)?;
+                Ok(())
+            }
+            ControlPdu::PowerControlRsp {
+                limits,
+                delta,
+                tx_power,
+                apr,
+            } => {
+                buffer.write_u8(limits.bits())?;
+                buffer.write_u8(*delta as u8)?;
+                buffer.write_u8(*tx_power as u8)?;
+                buffer.write_u8(*apr)?;
+                Ok(())
+            }
+            ControlPdu::PowerChangeInd {
+                phys,
+                limits,
+                delta,
+                tx_power,
+            } => {
+                buffer.write_u8(phys.bits())?;
+                buffer.write_u8(limits.bits())?;
+                buffer.write_u8(*delta as u8)?;
+                buffer.write_u8(*tx_power as u8)?;
+                Ok(())
+            }
+            ControlPdu::SubrateReq(req) => req.to_bytes(buffer),
+            ControlPdu::SubrateInd(ind) => ind.to_bytes(buffer),
             ControlPdu::Unknown { ctr_data, .. } => {
                 buffer.write_slice(ctr_data)?;
                 Ok(())
@@ -419,6 +1066,105 @@ enum_with_unknown! {
         PingRsp = 0x13,
         LengthReq = 0x14,
         LengthRsp = 0x15,
+        PhyReq = 0x16,
+        PhyRsp = 0x17,
+        PhyUpdateInd = 0x18,
+        MinUsedChannelsInd = 0x19,
+        CteReq = 0x1A,
+        CteRsp = 0x1B,
+        PeriodicSyncInd = 0x1C,
+        ClockAccuracyReq = 0x1D,
+        ClockAccuracyRsp = 0x1E,
+        CisReq = 0x1F,
+        CisRsp = 0x20,
+        CisInd = 0x21,
+        CisTerminateInd = 0x22,
+        PowerControlReq = 0x23,
+        PowerControlRsp = 0x24,
+        PowerChangeInd = 0x25,
+        SubrateReq = 0x26,
+        SubrateInd = 0x27,
+    }
+}
+
+/// What a `ControlPduHandler` did with an LL Control PDU.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ControlAction {
+    /// The handler doesn't know the PDU. The Link-Layer will respond with an `LL_UNKNOWN_RSP`.
+    Unhandled,
+
+    /// The PDU was handled and doesn't need a response.
+    NoResponse,
+
+    /// The handler has written a response to the `ByteWriter` it was passed.
+    Respond,
+}
+
+/// Extension point for LL Control PDUs that aren't handled by Rubble itself.
+///
+/// This allows implementing vendor-specific or future LLCP procedures without modifying the
+/// Link-Layer. The handler is called from the real-time Link-Layer code and must return quickly.
+///
+/// Use `()` if you don't need one: It leaves all PDUs unhandled.
+pub trait ControlPduHandler {
+    /// Called when an LL Control PDU is received that the Link-Layer doesn't handle.
+    ///
+    /// Opcodes unknown to Rubble are passed as `ControlPdu::Unknown`. To respond, write the
+    /// complete LL Control PDU payload (the opcode followed by the `CtrData`) to `response` and
+    /// return `ControlAction::Respond`.
+    fn handle_control_pdu(
+        &mut self,
+        pdu: ControlPdu<'_>,
+        response: &mut ByteWriter<'_>,
+    ) -> ControlAction;
+}
+
+impl ControlPduHandler for () {
+    fn handle_control_pdu(
+        &mut self,
+        _pdu: ControlPdu<'_>,
+        _response: &mut ByteWriter<'_>,
+    ) -> ControlAction {
+        ControlAction::Unhandled
+    }
+}
+
+enum_with_unknown! {
+    /// Error codes used as the reason for ending a connection or rejecting a procedure.
+    ///
+    /// These are shared with HCI and defined in Vol 2, Part D of the Bluetooth Core
+    /// Specification. Only the codes that are relevant to the Link-Layer are listed here.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum ErrorCode(u8) {
+        UnknownConnectionIdentifier = 0x02,
+        AuthenticationFailure = 0x05,
+        PinOrKeyMissing = 0x06,
+        ConnectionTimeout = 0x08,
+        RemoteUserTerminatedConnection = 0x13,
+        RemoteDeviceTerminatedLowResources = 0x14,
+        RemoteDeviceTerminatedPowerOff = 0x15,
+        ConnectionTerminatedByLocalHost = 0x16,
+        UnsupportedRemoteFeature = 0x1A,
+        InvalidLlParameters = 0x1E,
+        UnspecifiedError = 0x1F,
+        UnsupportedLlParameterValue = 0x20,
+        LlResponseTimeout = 0x22,
+        LlProcedureCollision = 0x23,
+        InstantPassed = 0x28,
+        UnacceptableConnectionParameters = 0x3B,
+        ConnectionFailedToBeEstablished = 0x3E,
+    }
+}
+
+bitflags! {
+    /// A set of LE PHYs, as used by LL Control PDUs.
+    pub struct Phys: u8 {
+        /// The LE 1M PHY (the only one supported by Rubble).
+        const LE_1M = (1 << 0);
+        /// The LE 2M PHY.
+        const LE_2M = (1 << 1);
+        /// The LE Coded PHY.
+        const LE_CODED = (1 << 2);
     }
 }
 
@@ -473,6 +1219,29 @@ mod tests {
         assert_eq!(max, Duration::from_micros(7_500));
     }
 
+    #[test]
+    fn subrate() {
+        let timeout = Duration::from_secs(4);
+        assert!(SubrateRequest::new(4, 4, 0, 1, timeout).is_ok());
+        assert!(SubrateRequest::new(0, 4, 0, 1, timeout).is_err());
+        assert!(SubrateRequest::new(4, 2, 0, 1, timeout).is_err());
+        assert!(SubrateRequest::new(2, 4, 0, 4, timeout).is_err());
+        assert!(SubrateRequest::new(2, 501, 0, 1, timeout).is_err());
+
+        let bytes = [0x27, 4, 0, 0xFE, 0xFF, 0, 0, 1, 0, 0x90, 0x01];
+        let ind = match ControlPdu::from_bytes(&mut ByteReader::new(&bytes)).unwrap() {
+            ControlPdu::SubrateInd(ind) => ind,
+            pdu => panic!("unexpected PDU {:?}", pdu),
+        };
+        assert_eq!(ind.subrate_factor(), 4);
+        assert_eq!(ind.timeout(), timeout);
+        // Base event 0xFFFE: events wrap around to 2, 6, ...
+        assert!(ind.is_subrated_event(0xFFFE));
+        assert!(ind.is_subrated_event(2));
+        assert!(!ind.is_subrated_event(0));
+        assert!(!ind.is_subrated_event(3));
+    }
+
     #[test]
     #[should_panic(expected = "min <= max")]
     fn update_req_set_conn_interval_minmax() {
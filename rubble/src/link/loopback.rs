@@ -0,0 +1,593 @@
+//! A loopback harness for testing the `LinkLayer` without a radio.
+//!
+//! [`Loopback`] drives a `LinkLayer` the way radio support code would: It follows the returned
+//! `Cmd`s, calls `LinkLayer::update_timer` when the requested time is reached, and passes packets
+//! to `process_adv_packet` or `process_data_packet` if the radio is listening on the right kind of
+//! channel. The other side of the link is scripted by the test. It sends PDUs at given points in
+//! time and inspects the packets the Link-Layer transmits in response.
+//!
+//! Time only advances when the harness is told so, which makes tests of the connection state
+//! machine, sequence number handling and LLCP procedures deterministic. The stack configuration
+//! has to use [`LoopbackTimer`] and [`LoopbackRadio`].
+//!
+//! [`Loopback`]: struct.Loopback.html
+//! [`LoopbackTimer`]: struct.LoopbackTimer.html
+//! [`LoopbackRadio`]: struct.LoopbackRadio.html
+
+use {
+    crate::{
+        config::Config,
+        link::{
+            ad_structure::AdStructure, advertising, data, DeviceAddress, LinkLayer, NextUpdate,
+            RadioCmd, Transmitter, MIN_PAYLOAD_BUF,
+        },
+        phy::{AdvertisingChannel, DataChannel},
+        time::{Duration, Instant, Timer},
+        Error,
+    },
+    byteorder::{ByteOrder, LittleEndian},
+};
+
+/// A `Timer` whose time is controlled by the `Loopback` harness.
+pub struct LoopbackTimer {
+    now: Instant,
+}
+
+impl LoopbackTimer {
+    /// Creates a timer starting at the raw time 0.
+    pub fn new() -> Self {
+        Self {
+            now: Instant::from_raw_micros(0),
+        }
+    }
+}
+
+impl Default for LoopbackTimer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timer for LoopbackTimer {
+    fn now(&self) -> Instant {
+        self.now
+    }
+}
+
+/// The channel a packet was transmitted on.
+#[derive(Debug, Copy, Clone)]
+pub enum PacketChannel {
+    Advertising(AdvertisingChannel),
+    Data {
+        channel: DataChannel,
+        access_address: u32,
+        crc_init: u32,
+    },
+}
+
+/// A packet transmitted by the Link-Layer.
+#[derive(Copy, Clone)]
+pub struct Packet {
+    time: Instant,
+    channel: PacketChannel,
+    header: u16,
+    payload: [u8; MIN_PAYLOAD_BUF],
+}
+
+impl Packet {
+    /// Returns the time at which the packet was transmitted.
+    pub fn time(&self) -> Instant {
+        self.time
+    }
+
+    /// Returns the channel the packet was transmitted on.
+    pub fn channel(&self) -> PacketChannel {
+        self.channel
+    }
+
+    /// Returns the header, assuming this is a data channel PDU.
+    pub fn data_header(&self) -> data::Header {
+        data::Header::parse(&self.header.to_le_bytes())
+    }
+
+    /// Returns the header, assuming this is an advertising channel PDU.
+    pub fn advertising_header(&self) -> advertising::Header {
+        advertising::Header::parse(&self.header.to_le_bytes())
+    }
+
+    /// Returns the payload following the header.
+    pub fn payload(&self) -> &[u8] {
+        // Both header types store the length in the second Byte
+        let len = usize::from((self.header >> 8) as u8).min(MIN_PAYLOAD_BUF);
+        self.payload.get(..len).unwrap_or_default()
+    }
+}
+
+/// A `Transmitter` that records packets instead of sending them.
+pub struct LoopbackRadio {
+    buf: [u8; MIN_PAYLOAD_BUF],
+    now: Instant,
+    last: Option<Packet>,
+}
+
+impl LoopbackRadio {
+    /// Creates a radio that hasn't transmitted anything yet.
+    pub fn new() -> Self {
+        Self {
+            buf: [0; MIN_PAYLOAD_BUF],
+            now: Instant::from_raw_micros(0),
+            last: None,
+        }
+    }
+
+    fn record(&mut self, channel: PacketChannel, header: u16) {
+        self.last = Some(Packet {
+            time: self.now,
+            channel,
+            header,
+            payload: self.buf,
+        });
+    }
+}
+
+impl Default for LoopbackRadio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transmitter for LoopbackRadio {
+    fn tx_payload_buf(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+
+    fn transmit_advertising(&mut self, header: advertising::Header, channel: AdvertisingChannel) {
+        self.record(PacketChannel::Advertising(channel), header.to_u16());
+    }
+
+    fn transmit_data(
+        &mut self,
+        access_address: u32,
+        crc_iv: u32,
+        header: data::Header,
+        channel: DataChannel,
+    ) {
+        let channel = PacketChannel::Data {
+            channel,
+            access_address,
+            crc_init: crc_iv,
+        };
+        self.record(channel, header.to_u16());
+    }
+}
+
+/// A PDU sent by the scripted peer.
+#[derive(Copy, Clone)]
+pub enum PeerPdu<'a> {
+    /// An advertising channel PDU (eg. a `CONNECT_REQ`).
+    Advertising {
+        header: advertising::Header,
+        payload: &'a [u8],
+    },
+
+    /// A data channel PDU. `crc_ok: false` simulates a corrupted packet.
+    Data {
+        header: data::Header,
+        payload: &'a [u8],
+        crc_ok: bool,
+    },
+}
+
+/// A step of a scripted peer: The `pdu` is sent `at` the given time since the harness was created.
+#[derive(Copy, Clone)]
+pub struct Step<'a> {
+    pub at: Duration,
+    pub pdu: PeerPdu<'a>,
+}
+
+/// Connects a `LinkLayer` to a scripted peer.
+///
+/// With `CONNECTIONS > 1`, the script can play several peers. Data channel PDUs are received
+/// whenever the Link-Layer listens on a data channel, so the test has to check that it listens
+/// for the right connection (see `radio_cmd`).
+pub struct Loopback<
+    C: Config<Timer = LoopbackTimer, Transmitter = LoopbackRadio>,
+    const CONNECTIONS: usize = 1,
+> {
+    ll: LinkLayer<C, CONNECTIONS>,
+    radio: LoopbackRadio,
+    listen: RadioCmd,
+    next_update: Option<Instant>,
+}
+
+impl<C: Config<Timer = LoopbackTimer, Transmitter = LoopbackRadio>, const CONNECTIONS: usize>
+    Loopback<C, CONNECTIONS>
+{
+    /// Creates a harness with a Link-Layer using the device address `dev_addr`.
+    pub fn new(dev_addr: DeviceAddress) -> Self {
+        Self {
+            ll: LinkLayer::new(dev_addr, LoopbackTimer::new()),
+            radio: LoopbackRadio::new(),
+            listen: RadioCmd::Off,
+            next_update: None,
+        }
+    }
+
+    /// Returns the Link-Layer under test.
+    pub fn link_layer(&mut self) -> &mut LinkLayer<C, CONNECTIONS> {
+        &mut self.ll
+    }
+
+    /// Returns the current time.
+    pub fn now(&self) -> Instant {
+        self.radio.now
+    }
+
+    /// Returns what the radio is currently doing.
+    pub fn radio_cmd(&self) -> &RadioCmd {
+        &self.listen
+    }
+
+    /// Starts advertising, like `LinkLayer::start_advertise`.
+    ///
+    /// Returns the first advertising PDU, which is sent immediately.
+    pub fn start_advertise(
+        &mut self,
+        interval: Duration,
+        data: &[AdStructure<'_>],
+        tx: C::PacketConsumer,
+        rx: C::PacketProducer,
+    ) -> Result<Option<Packet>, Error> {
+        self.radio.last = None;
+        let next = self
+            .ll
+            .start_advertise(interval, data, &mut self.radio, tx, rx)?;
+        let sent = self.radio.last.take();
+        // Like a BLE radio, listen on the channel of the advertisement that was just sent
+        let listen = match sent.map(|packet| packet.channel) {
+            Some(PacketChannel::Advertising(channel)) => RadioCmd::ListenAdvertising { channel },
+            _ => RadioCmd::Off,
+        };
+        self.apply(listen, next);
+        Ok(sent)
+    }
+
+    /// Advances the time to `time`, running all Link-Layer updates that become due.
+    ///
+    /// Returns the last packet transmitted by these updates, if any.
+    pub fn advance_to(&mut self, time: Instant) -> Option<Packet> {
+        let mut sent = None;
+        while let Some(due) = self.next_update {
+            if due.raw_micros().wrapping_sub(time.raw_micros()) as i32 >= 0 {
+                // `due` is in the future (or right now, in which case the peer's PDU comes first)
+                break;
+            }
+            self.set_time(due);
+            let cmd = self.ll.update_timer(&mut self.radio);
+            self.apply(cmd.radio, cmd.next_update);
+            sent = self.radio.last.take().or(sent);
+        }
+        self.set_time(time);
+        sent
+    }
+
+    /// Lets the peer send `pdu` at `time` (after running all updates due until then).
+    ///
+    /// The PDU is only received if the Link-Layer is listening on a matching channel. Returns the
+    /// packet transmitted in response, if any.
+    pub fn send(&mut self, time: Instant, pdu: PeerPdu<'_>) -> Option<Packet> {
+        self.advance_to(time);
+        self.radio.last = None;
+        let cmd = match (pdu, &self.listen) {
+            (PeerPdu::Advertising { header, payload }, RadioCmd::ListenAdvertising { .. }) => self
+                .ll
+                .process_adv_packet(time, &mut self.radio, header, payload, true),
+            (
+                PeerPdu::Data {
+                    header,
+                    payload,
+                    crc_ok,
+                },
+                RadioCmd::ListenData { .. },
+            ) => self
+                .ll
+                .process_data_packet(time, &mut self.radio, header, payload, crc_ok),
+            _ => {
+                debug!("loopback: Link-Layer isn't listening, PDU lost");
+                return None;
+            }
+        };
+        self.apply(cmd.radio, cmd.next_update);
+        self.radio.last.take()
+    }
+
+    /// Plays a script, calling `check` with the index of every step and the Link-Layer's response.
+    ///
+    /// Step times are relative to the creation of the harness and must be ascending.
+    pub fn play(&mut self, script: &[Step<'_>], mut check: impl FnMut(usize, Option<&Packet>)) {
+        let start = Instant::from_raw_micros(0);
+        for (i, step) in script.iter().enumerate() {
+            let response = self.send(start + step.at, step.pdu);
+            check(i, response.as_ref());
+        }
+    }
+
+    fn set_time(&mut self, time: Instant) {
+        self.ll.timer().now = time;
+        self.radio.now = time;
+    }
+
+    fn apply(&mut self, radio: RadioCmd, next: NextUpdate) {
+        self.listen = radio;
+        match next {
+            NextUpdate::Disable => self.next_update = None,
+            NextUpdate::Keep => {}
+            NextUpdate::At(time) => self.next_update = Some(time),
+        }
+    }
+}
+
+/// Builds the payload of a `CONNECT_REQ` sent by `initiator` to `advertiser`.
+///
+/// The connection uses `access_address` and `crc_init`, all data channels, a hop increment of 7
+/// and a supervision timeout of 1 second. The transmit window starts 1.25 ms after the
+/// `CONNECT_REQ` and is 1.25 ms long. `interval` is given in units of 1.25 ms.
+pub fn connect_request(
+    initiator: &DeviceAddress,
+    advertiser: &DeviceAddress,
+    access_address: u32,
+    crc_init: u32,
+    interval: u16,
+) -> (advertising::Header, [u8; 34]) {
+    let mut header = advertising::Header::new(advertising::PduType::ConnectReq);
+    header.set_payload_length(34);
+    header.set_tx_add(initiator.is_random());
+    header.set_rx_add(advertiser.is_random());
+
+    let mut payload = [0; 34];
+    payload[..6].copy_from_slice(initiator.raw());
+    payload[6..12].copy_from_slice(advertiser.raw());
+    LittleEndian::write_u32(&mut payload[12..16], access_address);
+    LittleEndian::write_u24(&mut payload[16..19], crc_init);
+    payload[19] = 1; // WinSize
+    LittleEndian::write_u16(&mut payload[20..22], 0); // WinOffset
+    LittleEndian::write_u16(&mut payload[22..24], interval);
+    LittleEndian::write_u16(&mut payload[24..26], 0); // Latency
+    LittleEndian::write_u16(&mut payload[26..28], 100); // Timeout
+    payload[28..32].copy_from_slice(&[0xFF; 4]);
+    payload[32] = 0x1F; // ChM
+    payload[33] = 7; // Hop, SCA 0
+    (header, payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            att::NoAttributes,
+            l2cap::BleChannelMap,
+            link::{
+                data::Llid,
+                queue::{PacketQueue, SimpleConsumer, SimpleProducer, SimpleQueue},
+                seq_num::SeqNum,
+                AddressKind,
+            },
+            security::NoSecurity,
+        },
+    };
+
+    enum TestConfig {}
+
+    impl Config for TestConfig {
+        type Timer = LoopbackTimer;
+        type Transmitter = LoopbackRadio;
+        type ChannelMapper = BleChannelMap<NoAttributes, NoSecurity>;
+        type PacketQueue = &'static mut SimpleQueue;
+        type PacketProducer = SimpleProducer<'static>;
+        type PacketConsumer = SimpleConsumer<'static>;
+        type EventHook = ();
+        type ControlHandler = ();
+        type Observer = ();
+        type PowerHooks = ();
+    }
+
+    fn empty_pdu(sn: SeqNum, nesn: SeqNum) -> PeerPdu<'static> {
+        let mut header = data::Header::new(Llid::DataCont);
+        header.set_sn(sn);
+        header.set_nesn(nesn);
+        PeerPdu::Data {
+            header,
+            payload: &[],
+            crc_ok: true,
+        }
+    }
+
+    #[test]
+    fn connection_sequence_numbers() {
+        let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+        let peer = DeviceAddress::new([6, 5, 4, 3, 2, 1], AddressKind::Public);
+        let mut lb = Loopback::<TestConfig>::new(addr);
+
+        let (_, tx_consumer) = Box::leak(Box::new(SimpleQueue::new())).split();
+        let (rx_producer, _) = Box::leak(Box::new(SimpleQueue::new())).split();
+        let adv = lb
+            .start_advertise(Duration::from_millis(100), &[], tx_consumer, rx_producer)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            adv.advertising_header().type_(),
+            advertising::PduType::AdvInd
+        );
+
+        let (header, payload) = connect_request(&peer, &addr, 0x1234_5678, 0xABCDEF, 24);
+        let conn_req = PeerPdu::Advertising {
+            header,
+            payload: &payload,
+        };
+        assert!(lb.send(Instant::from_raw_micros(1_200), conn_req).is_none());
+        assert!(lb.link_layer().is_connected());
+
+        let (zero, one) = (SeqNum::ZERO, SeqNum::ONE);
+        let script = [
+            // First packet is new and acknowledges nothing
+            Step {
+                at: Duration::from_micros(3_000),
+                pdu: empty_pdu(zero, zero),
+            },
+            // Retransmission of the master's packet (our response wasn't received)
+            Step {
+                at: Duration::from_micros(33_000),
+                pdu: empty_pdu(zero, zero),
+            },
+            // Next packet, acknowledging ours
+            Step {
+                at: Duration::from_micros(63_000),
+                pdu: empty_pdu(one, one),
+            },
+        ];
+        let expected = [(zero, one), (zero, one), (one, zero)];
+        lb.play(&script, |i, response| {
+            let header = response.expect("no response").data_header();
+            assert_eq!((header.sn(), header.nesn()), expected[i], "step {}", i);
+        });
+    }
+
+    #[test]
+    fn supervision_timeout() {
+        let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+        let peer = DeviceAddress::new([6, 5, 4, 3, 2, 1], AddressKind::Public);
+        let mut lb = Loopback::<TestConfig>::new(addr);
+
+        let (_, tx_consumer) = Box::leak(Box::new(SimpleQueue::new())).split();
+        let (rx_producer, _) = Box::leak(Box::new(SimpleQueue::new())).split();
+        lb.start_advertise(Duration::from_millis(100), &[], tx_consumer, rx_producer)
+            .unwrap()
+            .unwrap();
+
+        // 30 ms interval, 1 s supervision timeout
+        let (header, payload) = connect_request(&peer, &addr, 0x1234_5678, 0xABCDEF, 24);
+        let conn_req = PeerPdu::Advertising {
+            header,
+            payload: &payload,
+        };
+        lb.send(Instant::from_raw_micros(1_200), conn_req);
+        let start = Instant::from_raw_micros(0);
+        lb.send(
+            start + Duration::from_micros(3_000),
+            empty_pdu(SeqNum::ZERO, SeqNum::ZERO),
+        );
+
+        // Corrupted packets in every event don't keep the connection alive
+        let corrupted = PeerPdu::Data {
+            header: data::Header::new(Llid::DataCont),
+            payload: &[],
+            crc_ok: false,
+        };
+        let mut at = Duration::from_micros(3_000);
+        for _ in 0..40 {
+            at += Duration::from_millis(30);
+            lb.send(start + at, corrupted);
+            if at < Duration::from_millis(1_000) {
+                assert!(lb.link_layer().is_connected(), "lost at {:?}", at);
+            }
+        }
+        assert!(!lb.link_layer().is_connected());
+    }
+
+    #[test]
+    fn two_connections() {
+        let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+        let peers = [
+            DeviceAddress::new([6, 5, 4, 3, 2, 1], AddressKind::Public),
+            DeviceAddress::new([7, 7, 7, 7, 7, 7], AddressKind::Public),
+        ];
+        let access_addresses = [0x1234_5678, 0x8765_4321];
+        let mut lb = Loopback::<TestConfig, 2>::new(addr);
+        let start = Instant::from_raw_micros(0);
+        let at = |micros| start + Duration::from_micros(micros);
+        let (zero, one) = (SeqNum::ZERO, SeqNum::ONE);
+
+        // Both peers connect with a 30 ms interval, the second one after the first connection's
+        // first event
+        for (i, &advertise_at) in [0, 5_000].iter().enumerate() {
+            let (_, tx_consumer) = Box::leak(Box::new(SimpleQueue::new())).split();
+            let (rx_producer, _) = Box::leak(Box::new(SimpleQueue::new())).split();
+            lb.advance_to(at(advertise_at));
+            lb.start_advertise(Duration::from_millis(100), &[], tx_consumer, rx_producer)
+                .unwrap()
+                .expect("no advertisement");
+
+            let (header, payload) =
+                connect_request(&peers[i], &addr, access_addresses[i], 0xABCDEF, 24);
+            let conn_req = PeerPdu::Advertising {
+                header,
+                payload: &payload,
+            };
+            assert!(lb.send(at(advertise_at + 1_200), conn_req).is_none());
+            assert_eq!(lb.link_layer().connections().count(), i + 1);
+            assert!(!lb.link_layer().is_advertising());
+
+            let response = lb.send(at(advertise_at + 3_000), empty_pdu(zero, zero));
+            let header = response.expect("no response").data_header();
+            assert_eq!((header.sn(), header.nesn()), (zero, one));
+        }
+
+        // No slot is left for a third connection
+        let (_, tx_consumer) = Box::leak(Box::new(SimpleQueue::new())).split();
+        let (rx_producer, _) = Box::leak(Box::new(SimpleQueue::new())).split();
+        let result = lb.start_advertise(Duration::from_millis(100), &[], tx_consumer, rx_producer);
+        assert_eq!(result.err(), Some(Error::Eof));
+
+        // The Link-Layer listens for each connection at its events
+        let script = [
+            (0, 33_000, empty_pdu(one, one), (one, zero)),
+            (1, 38_000, empty_pdu(one, one), (one, zero)),
+            (0, 63_000, empty_pdu(zero, zero), (zero, one)),
+            (1, 68_000, empty_pdu(zero, zero), (zero, one)),
+        ];
+        for (step, (conn, time, pdu, expected)) in script.iter().enumerate() {
+            lb.advance_to(at(*time));
+            match lb.radio_cmd() {
+                RadioCmd::ListenData { access_address, .. } => {
+                    assert_eq!(*access_address, access_addresses[*conn], "step {}", step);
+                }
+                _ => panic!("not listening on a data channel in step {}", step),
+            }
+
+            let response = lb.send(at(*time), *pdu).expect("no response");
+            let header = response.data_header();
+            assert_eq!((header.sn(), header.nesn()), *expected, "step {}", step);
+            match response.channel() {
+                PacketChannel::Data { access_address, .. } => {
+                    assert_eq!(access_address, access_addresses[*conn], "step {}", step);
+                }
+                _ => panic!("response not sent on a data channel in step {}", step),
+            }
+        }
+        assert_eq!(lb.link_layer().connections().count(), 2);
+    }
+
+    #[test]
+    fn malformed_connect_request() {
+        let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+        let peer = DeviceAddress::new([6, 5, 4, 3, 2, 1], AddressKind::Public);
+        let mut lb = Loopback::<TestConfig>::new(addr);
+
+        let (_, tx_consumer) = Box::leak(Box::new(SimpleQueue::new())).split();
+        let (rx_producer, _) = Box::leak(Box::new(SimpleQueue::new())).split();
+        lb.start_advertise(Duration::from_millis(100), &[], tx_consumer, rx_producer)
+            .unwrap()
+            .unwrap();
+
+        // A single used channel leaves nothing to remap to
+        let (header, mut payload) = connect_request(&peer, &addr, 0x1234_5678, 0xABCDEF, 24);
+        payload[28..33].copy_from_slice(&[0x01, 0, 0, 0, 0]);
+        let conn_req = PeerPdu::Advertising {
+            header,
+            payload: &payload,
+        };
+        assert!(lb.send(Instant::from_raw_micros(1_200), conn_req).is_none());
+        assert!(!lb.link_layer().is_connected());
+    }
+}
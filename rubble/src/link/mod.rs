@@ -117,22 +117,54 @@
 //! its maximum value is 31, resulting in a 27 octet Payload (the maximum) and a 32-bit `MIC`. 4.2
 //! added the possibility of larger packets.
 
+#![cfg_attr(
+    all(feature = "deny-panics", not(test)),
+    deny(
+        clippy::expect_used,
+        clippy::indexing_slicing,
+        clippy::panic,
+        clippy::unreachable,
+        clippy::unwrap_used
+    )
+)]
+
 pub mod ad_structure;
+pub mod adv_set;
 pub mod advertising;
+#[cfg(feature = "async")]
+pub mod asynch;
+pub mod ccm;
 mod channel_map;
 mod comp_id;
 mod connection;
+pub mod cte;
 pub mod data;
 mod device_address;
 mod features;
 pub mod filter;
+pub mod iso;
+pub mod link_quality;
 pub mod llcp;
+pub mod loopback;
+pub mod periodic;
+pub mod power;
+pub mod power_control;
+pub mod privacy;
 pub mod queue;
 mod responder;
+pub mod scan;
+pub mod scheduler;
 mod seq_num;
+pub mod stats;
+pub mod timeslot;
+pub mod trace;
+pub mod transport;
 
+pub use self::channel_map::ChannelMap;
 pub use self::comp_id::*;
-pub use self::connection::Connection;
+pub use self::connection::{
+    Connection, ConnectionEventHook, ConnectionEventSummary, DEFAULT_EVENT_LENGTH,
+};
 pub use self::device_address::*;
 pub use self::features::*;
 pub use self::responder::*;
@@ -140,20 +172,28 @@ pub use self::responder::*;
 use {
     self::{
         ad_structure::AdStructure,
+        adv_set::{set_spacing, AdvertisingSet, AdvertisingSets},
         advertising::{Pdu, PduBuf},
+        link_quality::{LinkQualityConfig, LinkQualityManager},
+        llcp::ErrorCode,
+        power::{PowerHooks, SleepWindow},
+        scan::{AdvertisementInfo, Observer, ScanParams, ScanRequestReport, ScanSchedule},
+        scheduler::{earliest, latest, reached, Activity, Reservation, Scheduler, MAX_CONNECTIONS},
         seq_num::SeqNum,
+        stats::{Counter, Stats},
+        timeslot::{Timeslot, TIMESLOT_GUARD},
+        trace::TracePoint,
     },
     crate::{
-        bytes::ByteReader,
+        bytes::{ByteReader, ByteWriter},
         config::Config,
-        crc::ble_crc24,
-        phy::{AdvertisingChannel, DataChannel, Radio},
+        phy::{self, AdvertisingChannel, DataChannel, Radio, TxPower},
         time::{Duration, Instant, Timer},
         utils::HexSlice,
         Error,
     },
     byteorder::{ByteOrder, LittleEndian},
-    core::ops::Range,
+    core::{array, cmp},
 };
 
 /// The CRC polynomial to use for CRC24 generation.
@@ -209,43 +249,126 @@ pub const MIN_PACKET_BUF: usize =
     MIN_PDU_BUF +
     3 /* crc */;
 
+/// Time to leave after a connection event before the radio is used for advertising or scanning.
+///
+/// Our last response may still be on air when the event is closed.
+const EVENT_GAP: Duration = Duration::from_micros(500);
+
 /// Link-Layer state machine, according to the Bluetooth spec.
+///
+/// Established connections are kept next to this state, so a device can advertise while it is
+/// connected, as long as it supports more connections.
+#[allow(clippy::large_enum_variant)] // there's no heap to box the advertising sets on
 enum State<C: Config> {
     /// Radio silence: Not listening, not transmitting anything.
     Standby,
 
     /// Device is advertising and wants to establish a connection.
     Advertising {
-        /// Advertising interval.
-        // TODO: check spec for allowed/recommended values and check for them
-        next_adv: Instant,
-        interval: Duration,
+        /// The advertisements to send.
+        // TODO: check spec for allowed/recommended intervals and check for them
+        sets: AdvertisingSets,
 
-        /// Precomputed PDU payload to copy into the transmitter's buffer.
-        pdu: advertising::PduBuf,
+        /// Handle of the set that was transmitted last.
+        ///
+        /// Scan and connect requests are answered on behalf of this set.
+        active: u8,
 
-        /// Next advertising channel to use for a message.
-        // FIXME: spec check; no idea what order or change delay
+        /// The advertising channel the radio is listening on.
         channel: AdvertisingChannel,
 
         data_queues: Option<(C::PacketConsumer, C::PacketProducer)>,
     },
+}
+
+/// An established connection, and when the Link-Layer has to update it next.
+struct ConnectionSlot<C: Config> {
+    conn: Connection<C>,
 
-    /// Connected with another device.
-    Connection(Connection<C>),
+    /// Time at which `Connection::timer_update` is due, from the last `Cmd` of the connection.
+    update_at: Instant,
 }
 
 /// Implementation of the real-time BLE Link-Layer logic.
 ///
 /// Users of this struct must provide an interface to the platform's hardware by implementing
 /// `HardwareInterface`.
-pub struct LinkLayer<C: Config> {
+///
+/// The Link-Layer can maintain up to `CONNECTIONS` connections at once (1 by default, and at most
+/// `scheduler::MAX_CONNECTIONS`). Each connection takes the packet queues passed when advertising
+/// was started, so RAM is only spent on as many connections as the application needs. Connections
+/// are identified by the index of their slot, from 0 to `CONNECTIONS - 1`, and share the radio
+/// through the `Scheduler`: A connection event that has started is never interrupted, so the
+/// events of other connections overlapping it are missed.
+pub struct LinkLayer<C: Config, const CONNECTIONS: usize = 1> {
     dev_addr: DeviceAddress,
     state: State<C>,
     timer: C::Timer,
+
+    /// The established connections, indexed by their slot.
+    connections: [Option<ConnectionSlot<C>>; CONNECTIONS],
+
+    /// Index of the connection the radio was last configured to listen for.
+    listening: Option<u8>,
+
+    /// Transmission power used for advertising channel PDUs (`TxPower::DEFAULT` unless set).
+    adv_tx_power: TxPower,
+
+    /// Transmission power new connections start out with (`TxPower::DEFAULT` unless set).
+    conn_tx_power: TxPower,
+
+    /// Configuration of the link-quality manager installed on new connections.
+    link_quality: Option<LinkQualityConfig>,
+
+    /// Accuracy of the sleep clock (the `Timer`) in ppm.
+    sca_ppm: u16,
+
+    /// Maximum length of connection events.
+    event_length: Duration,
+
+    /// Hook to invoke at the end of each connection event.
+    event_hook: Option<C::EventHook>,
+
+    /// The timeslot currently lent to the application, if any.
+    timeslot: Option<Timeslot>,
+
+    /// Why the last connection was ended.
+    disconnect_reason: Option<ErrorCode>,
+
+    /// Handler for LL Control PDUs that the Link-Layer doesn't support.
+    control_handler: Option<C::ControlHandler>,
+
+    /// Scan window schedule, if scanning while advertising.
+    scan: Option<ScanSchedule>,
+
+    /// Receiver of the advertisements found while scanning.
+    observer: Option<C::Observer>,
+
+    /// Radio timeline shared by all activities.
+    sched: Scheduler,
+
+    /// Hooks notified when the radio is turned off between activities.
+    power_hooks: Option<C::PowerHooks>,
+
+    /// The current sleep window and the radio configuration to restore after it.
+    idle: Option<(SleepWindow, Cmd)>,
+
+    stats: Stats,
 }
 
-impl<C: Config> LinkLayer<C> {
+impl<C: Config, const CONNECTIONS: usize> LinkLayer<C, CONNECTIONS> {
+    /// Fails to compile if `CONNECTIONS` is out of range (once the Link-Layer is used).
+    const VALID: () = {
+        assert!(
+            CONNECTIONS > 0,
+            "LinkLayer must support at least 1 connection"
+        );
+        assert!(
+            CONNECTIONS <= MAX_CONNECTIONS,
+            "LinkLayer supports at most MAX_CONNECTIONS connections"
+        );
+    };
+
     /// Creates a new Link-Layer.
     ///
     /// # Parameters
@@ -255,11 +378,29 @@ impl<C: Config> LinkLayer<C> {
     /// * **`tx`**: Input queue of packets to transmit when connected.
     /// * **`rx`**: Output queue of received packets when connected.
     pub fn new(dev_addr: DeviceAddress, timer: C::Timer) -> Self {
+        let () = Self::VALID;
         trace!("new LinkLayer, dev={:?}", dev_addr);
         Self {
             dev_addr,
             state: State::Standby,
             timer,
+            connections: array::from_fn(|_| None),
+            listening: None,
+            adv_tx_power: TxPower::DEFAULT,
+            conn_tx_power: TxPower::DEFAULT,
+            link_quality: None,
+            sca_ppm: 500,
+            event_length: DEFAULT_EVENT_LENGTH,
+            event_hook: None,
+            timeslot: None,
+            disconnect_reason: None,
+            control_handler: None,
+            scan: None,
+            observer: None,
+            sched: Scheduler::new(),
+            power_hooks: None,
+            idle: None,
+            stats: Stats::default(),
         }
     }
 
@@ -268,7 +409,165 @@ impl<C: Config> LinkLayer<C> {
         &mut self.timer
     }
 
+    /// Installs a hook that will be invoked at the end of every connection event.
+    ///
+    /// This replaces any previously installed hook.
+    pub fn set_event_hook(&mut self, hook: C::EventHook) {
+        self.event_hook = Some(hook);
+    }
+
+    /// Returns a mutable reference to the installed connection event hook, if any.
+    ///
+    /// This can be used to retrieve statistics gathered by the hook.
+    pub fn event_hook(&mut self) -> Option<&mut C::EventHook> {
+        self.event_hook.as_mut()
+    }
+
+    /// Installs `PowerHooks`, making the Link-Layer turn the radio off between activities.
+    ///
+    /// This replaces any previously installed hooks. Also see the [`power`] module.
+    ///
+    /// [`power`]: power/index.html
+    pub fn set_power_hooks(&mut self, hooks: C::PowerHooks) {
+        self.power_hooks = Some(hooks);
+    }
+
+    /// Returns a mutable reference to the installed power hooks, if any.
+    pub fn power_hooks(&mut self) -> Option<&mut C::PowerHooks> {
+        self.power_hooks.as_mut()
+    }
+
+    /// Returns the current sleep window, if the radio has been turned off until the next activity.
+    ///
+    /// The application may put the CPU to sleep while this returns `Some`.
+    pub fn sleep_window(&self) -> Option<SleepWindow> {
+        self.idle.as_ref().map(|(window, _)| *window)
+    }
+
+    /// Installs a handler for LL Control PDUs that aren't supported by Rubble.
+    ///
+    /// Without a handler, those PDUs are answered with `LL_UNKNOWN_RSP`. This replaces any
+    /// previously installed handler.
+    pub fn set_control_handler(&mut self, handler: C::ControlHandler) {
+        self.control_handler = Some(handler);
+    }
+
+    /// Returns a mutable reference to the installed LL Control PDU handler, if any.
+    pub fn control_handler(&mut self) -> Option<&mut C::ControlHandler> {
+        self.control_handler.as_mut()
+    }
+
+    /// Installs the `Observer` that is passed the advertisements found while scanning.
+    ///
+    /// This replaces any previously installed observer.
+    pub fn set_observer(&mut self, observer: C::Observer) {
+        self.observer = Some(observer);
+    }
+
+    /// Returns a mutable reference to the installed observer, if any.
+    pub fn observer(&mut self) -> Option<&mut C::Observer> {
+        self.observer.as_mut()
+    }
+
+    /// Starts scanning for advertisements while advertising.
+    ///
+    /// Scan windows are interleaved with the advertising events of all advertising sets, so the
+    /// device stays connectable. The first window opens at the next timer update. Scanning
+    /// continues when advertising is restarted after a connection has ended. Also see the
+    /// [`scan`] module.
+    ///
+    /// [`scan`]: scan/index.html
+    pub fn start_scanning(&mut self, params: ScanParams) {
+        self.scan = Some(ScanSchedule::new(params, self.timer.now()));
+    }
+
+    /// Stops scanning.
+    pub fn stop_scanning(&mut self) {
+        self.scan = None;
+        self.sched.release(Activity::Scanning);
+    }
+
+    /// Returns whether scanning is enabled.
+    pub fn is_scanning(&self) -> bool {
+        self.scan.is_some()
+    }
+
+    /// Sets the transmission power to use when sending advertising channel PDUs.
+    ///
+    /// The `Transmitter` will clamp `power` to a level it supports. Use
+    /// `Transmitter::supported_tx_power` to find out which levels are available.
+    pub fn set_advertising_tx_power(&mut self, power: TxPower) {
+        self.adv_tx_power = power;
+    }
+
+    /// Returns the transmission power used when sending advertising channel PDUs.
+    pub fn advertising_tx_power(&self) -> TxPower {
+        self.adv_tx_power
+    }
+
+    /// Returns the power `transmitter` actually sends advertising channel PDUs with.
+    ///
+    /// This is the configured power clamped to the levels supported by the radio, which is what
+    /// should be advertised in a `TxPowerLevel` AD structure.
+    pub fn effective_advertising_tx_power(&self, transmitter: &C::Transmitter) -> TxPower {
+        self.adv_tx_power.clamp_to(transmitter.supported_tx_power())
+    }
+
+    /// Sets the transmission power that newly established connections will start out with.
+    ///
+    /// This does not affect an already established connection. Use
+    /// `Connection::set_tx_power` (via `connection_mut`) to change that.
+    pub fn set_default_connection_tx_power(&mut self, power: TxPower) {
+        self.conn_tx_power = power;
+    }
+
+    /// Enables or disables adaptive transmission power for new connections.
+    ///
+    /// With `Some` config, every connection established afterwards gets its own
+    /// `LinkQualityManager`, which adjusts the connection's power starting from the default
+    /// connection power. Its decisions are reported in `ConnectionEventSummary::link_quality`.
+    pub fn set_link_quality(&mut self, config: Option<LinkQualityConfig>) {
+        self.link_quality = config;
+    }
+
+    /// Sets the accuracy of the `Timer` in ppm (parts per million).
+    ///
+    /// This is used to compute how much earlier and longer the radio has to listen for the master's
+    /// packets to compensate for clock drift (*window widening*), so a more accurate clock reduces
+    /// the time the receiver is on. By default, the worst accuracy allowed by the spec (500 ppm) is
+    /// assumed. Values larger than that are clamped to 500.
+    ///
+    /// This affects connections established after this call.
+    pub fn set_sleep_clock_accuracy(&mut self, ppm: u16) {
+        self.sca_ppm = cmp::min(ppm, 500);
+    }
+
+    /// Returns the configured sleep clock accuracy in ppm.
+    pub fn sleep_clock_accuracy(&self) -> u16 {
+        self.sca_ppm
+    }
+
+    /// Sets the maximum length of connection events (`DEFAULT_EVENT_LENGTH` by default).
+    ///
+    /// A longer event allows more packets to be exchanged per connection interval, increasing
+    /// throughput, at the expense of radio time available to timeslots and other activities. The
+    /// length is always capped to leave some time before the next connection event.
+    ///
+    /// This affects connections established after this call. Use `Connection::set_event_length`
+    /// to change it for the current connection.
+    pub fn set_max_event_length(&mut self, length: Duration) {
+        self.event_length = length;
+    }
+
+    /// Returns the configured maximum length of connection events.
+    pub fn max_event_length(&self) -> Duration {
+        self.event_length
+    }
+
     /// Starts advertising this device, optionally sending data along with the advertising PDU.
+    ///
+    /// `tx` and `rx` are the packet queues of the connection that may be established. Returns
+    /// `Error::Eof` if all `CONNECTIONS` connections are in use.
     pub fn start_advertise(
         &mut self,
         interval: Duration,
@@ -277,21 +576,135 @@ impl<C: Config> LinkLayer<C> {
         tx: C::PacketConsumer,
         rx: C::PacketProducer,
     ) -> Result<NextUpdate, Error> {
-        // TODO tear down existing connection?
-
         let pdu = PduBuf::discoverable(self.dev_addr, data)?;
         debug!("start_advertise: adv_data = {:?}", data);
         debug!("start_advertise: PDU = {:?}", pdu);
+        let mut sets = AdvertisingSets::new();
+        sets.add(AdvertisingSet::new(pdu, interval))?;
+        self.start_advertising_sets(sets, transmitter, tx, rx)
+    }
+
+    /// Starts advertising multiple advertising sets at once.
+    ///
+    /// The sets are time-multiplexed, and all of them stop once a connection is established. The
+    /// established connections are kept, and their events take precedence over advertising.
+    ///
+    /// Returns `Error::InvalidValue` if `sets` is empty, and `Error::Eof` if all `CONNECTIONS`
+    /// connections are in use.
+    pub fn start_advertising_sets(
+        &mut self,
+        mut sets: AdvertisingSets,
+        transmitter: &mut C::Transmitter,
+        tx: C::PacketConsumer,
+        rx: C::PacketProducer,
+    ) -> Result<NextUpdate, Error> {
+        if sets.is_empty() {
+            return Err(Error::InvalidValue);
+        }
+        if self.free_slot().is_none() {
+            return Err(Error::Eof);
+        }
+
+        // Advertising replaces whatever the radio was turned off for
+        self.wake_up();
+        let now = self.timer.now();
+        sets.start(now);
+        self.sched
+            .reserve(Reservation::new(Activity::Advertising, now, set_spacing()));
+        if let Some(scan) = &mut self.scan {
+            *scan = ScanSchedule::new(scan.params(), now);
+        }
         self.state = State::Advertising {
-            next_adv: self.timer().now(),
-            interval,
-            pdu,
+            sets,
+            active: 0,
             channel: AdvertisingChannel::first(),
             data_queues: Some((tx, rx)),
         };
         Ok(self.update_timer(transmitter).next_update)
     }
 
+    /// Returns the advertising sets in use, if the Link-Layer is advertising.
+    ///
+    /// Sets can be modified, added or removed while advertising. Newly added sets start
+    /// advertising after the next advertising event.
+    pub fn advertising_sets(&mut self) -> Option<&mut AdvertisingSets> {
+        if let State::Advertising { sets, .. } = &mut self.state {
+            Some(sets)
+        } else {
+            None
+        }
+    }
+
+    /// Requests the radio for `length`, to be used for other protocols.
+    ///
+    /// The timeslot starts immediately. Before using the radio, it must be disabled and taken over
+    /// from the radio driver. Also see the [`timeslot`] module.
+    ///
+    /// Returns `Error::Eof` if the timeslot would not end at least `TIMESLOT_GUARD` before the
+    /// next BLE activity (or, when connected, if the time of the next connection event isn't
+    /// known yet). Returns `Error::InvalidValue` if a timeslot is already active.
+    ///
+    /// [`timeslot`]: timeslot/index.html
+    pub fn request_timeslot(&mut self, length: Duration) -> Result<Timeslot, Error> {
+        if self.timeslot.is_some() {
+            return Err(Error::InvalidValue);
+        }
+
+        let now = self.timer.now();
+        for index in 0..CONNECTIONS as u8 {
+            match self.connection_at(index) {
+                Some(conn) if conn.next_anchor().is_none() => return Err(Error::Eof),
+                Some(_) => self.reserve_connection(index, now),
+                None => {}
+            }
+        }
+
+        let reservation = Reservation::new(Activity::Timeslot, now, length + TIMESLOT_GUARD);
+        if self.sched.conflict(now, &reservation).is_some() {
+            return Err(Error::Eof);
+        }
+        self.sched.reserve(reservation);
+
+        let slot = Timeslot::new(now, length);
+        self.timeslot = Some(slot);
+        Ok(slot)
+    }
+
+    /// Returns the radio timeline, with the reservations of all activities.
+    pub fn scheduler(&self) -> &Scheduler {
+        &self.sched
+    }
+
+    /// Returns the active timeslot, if any.
+    pub fn timeslot(&self) -> Option<&Timeslot> {
+        self.timeslot.as_ref()
+    }
+
+    /// Ends the active timeslot.
+    ///
+    /// The radio driver has to be reconfigured with the returned `Cmd` to resume BLE operation.
+    pub fn end_timeslot(&mut self) -> Cmd {
+        self.timeslot = None;
+        self.sched.release(Activity::Timeslot);
+
+        let listening = self.listening.and_then(|index| self.connection_at(index));
+        let radio = match (&self.state, listening) {
+            // The radio stays off until the sleep window ends
+            _ if self.idle.is_some() => RadioCmd::Off,
+            (_, Some(conn)) => conn.listen_cmd(),
+            (State::Standby, None) => RadioCmd::Off,
+            (State::Advertising { channel, .. }, None) => {
+                RadioCmd::ListenAdvertising { channel: *channel }
+            }
+        };
+
+        Cmd {
+            radio,
+            next_update: NextUpdate::Keep,
+            queued_work: false,
+        }
+    }
+
     /// Process an incoming packet from an advertising channel.
     ///
     /// The access address of the packet must be `ADVERTISING_ADDRESS`.
@@ -312,32 +725,97 @@ impl<C: Config> LinkLayer<C> {
         crc_ok: bool,
     ) -> Cmd {
         let pdu = advertising::Pdu::from_header_and_payload(header, &mut ByteReader::new(payload));
+        trace::mark(TracePoint::PduParsed);
 
         if let Ok(pdu) = pdu {
+            let scanning = self.scan.as_ref().is_some_and(ScanSchedule::is_active);
+            if crc_ok && scanning && pdu.advertising_data().is_some() {
+                if let Some(observer) = &mut self.observer {
+                    let info = AdvertisementInfo {
+                        rx_end,
+                        timestamp: tx.rx_timestamp(),
+                        rssi: tx.last_rssi(),
+                    };
+                    observer.advertisement_received(&pdu, &info);
+                }
+            }
+
             if let State::Advertising {
+                sets,
+                active,
                 channel,
                 data_queues,
-                ..
             } = &mut self.state
             {
-                if crc_ok && pdu.receiver() == Some(&self.dev_addr) {
+                let set = sets
+                    .get(*active)
+                    .filter(|set| crc_ok && pdu.receiver() == Some(&set.address()));
+                if let Some(set) = set {
                     // Got a packet addressed at us, can be a scan or connect request
                     match pdu {
-                        Pdu::ScanRequest { .. } => {
-                            let scan_data = &[]; // TODO make this configurable
-                            let response = PduBuf::scan_response(self.dev_addr, scan_data).unwrap();
+                        Pdu::ScanRequest { scanner_addr, .. } if set.is_scannable() => {
+                            // The RSSI is that of the request, read it before transmitting
+                            let rssi = tx.last_rssi();
+                            let response = set.scan_response();
+                            if ByteWriter::new(tx.tx_payload_buf())
+                                .write_slice(response.payload())
+                                .is_err()
+                            {
+                                warn!("scan response doesn't fit into the TX buffer");
+                                return Self::listen_advertising(*channel);
+                            }
+                            tx.set_tx_power(self.adv_tx_power);
+                            trace::mark(TracePoint::ResponseReady);
                             tx.transmit_advertising(response.header(), *channel);
 
-                            // Log after responding to meet timing
+                            // Log and report after responding to meet timing
                             debug!("-> SCAN RESP: {:?}", response);
+                            if let Some(observer) = &mut self.observer {
+                                observer.scan_request(&ScanRequestReport {
+                                    set: *active,
+                                    scanner: scanner_addr,
+                                    rssi,
+                                });
+                            }
                         }
-                        Pdu::ConnectRequest { lldata, .. } => {
+                        Pdu::ConnectRequest { .. } if !set.is_connectable() => {}
+                        Pdu::ConnectRequest {
+                            lldata,
+                            initiator_addr,
+                            ..
+                        } => {
                             trace!("ADV<- CONN! {:?}", pdu);
 
-                            let (tx, rx) = data_queues.take().unwrap();
-                            let (conn, cmd) = Connection::create(&lldata, rx_end, tx, rx);
-                            self.state = State::Connection(conn);
-                            return cmd;
+                            // The queues and a free slot are always there while advertising
+                            let slot = self.connections.iter().position(Option::is_none);
+                            let (index, (tx, rx)) = match (slot, data_queues.take()) {
+                                (Some(index), Some(queues)) => (index, queues),
+                                _ => return Self::listen_advertising(*channel),
+                            };
+                            let (conn, cmd) = Connection::create(
+                                &lldata,
+                                rx_end,
+                                tx,
+                                rx,
+                                self.conn_tx_power,
+                                self.link_quality.map(LinkQualityManager::new),
+                                initiator_addr,
+                                self.sca_ppm,
+                                self.event_length,
+                            );
+                            if let Some(slot) = self.connections.get_mut(index) {
+                                let update_at = match cmd.next_update {
+                                    NextUpdate::At(t) => t,
+                                    _ => rx_end,
+                                };
+                                *slot = Some(ConnectionSlot { conn, update_at });
+                            }
+                            self.state = State::Standby;
+                            self.sched.release(Activity::Advertising);
+                            self.sched.release(Activity::Scanning);
+                            self.disconnect_reason = None;
+                            self.stats.record(Counter::Connection);
+                            return self.connection_result(index as u8, Ok(cmd));
                         }
                         _ => {}
                     }
@@ -354,20 +832,53 @@ impl<C: Config> LinkLayer<C> {
         );
 
         match self.state {
-            State::Standby => unreachable!("standby, can't receive packets"),
-            State::Connection { .. } => unreachable!("process_adv_packet called while connected"),
-            State::Advertising { channel, .. } => {
-                Cmd {
-                    radio: RadioCmd::ListenAdvertising { channel },
-                    // no change
-                    next_update: NextUpdate::Keep,
-                    queued_work: false,
-                }
+            State::Advertising { channel, .. } => Self::listen_advertising(channel),
+            State::Standby => {
+                warn!("advertising channel packet received while not advertising");
+                Self::unexpected_event()
             }
         }
     }
 
+    /// Returns the `Cmd` that keeps listening for advertising channel packets on `channel`.
+    fn listen_advertising(channel: AdvertisingChannel) -> Cmd {
+        Cmd {
+            radio: RadioCmd::ListenAdvertising { channel },
+            // no change
+            next_update: NextUpdate::Keep,
+            queued_work: false,
+        }
+    }
+
+    /// Returns the `Cmd` that turns the radio and the timer off, when there's nothing to do.
+    fn idle_cmd() -> Cmd {
+        Cmd {
+            radio: RadioCmd::Off,
+            next_update: NextUpdate::Disable,
+            queued_work: false,
+        }
+    }
+
+    /// Returns the `Cmd` for a radio event that doesn't fit the current state.
+    ///
+    /// The radio is turned off, and the timer is left as it is, so that the next `update_timer`
+    /// configures the radio for the current state again.
+    fn unexpected_event() -> Cmd {
+        Cmd {
+            radio: RadioCmd::Off,
+            next_update: NextUpdate::Keep,
+            queued_work: false,
+        }
+    }
+
     /// Process an incoming data channel packet.
+    ///
+    /// This is equivalent to calling [`respond_data_packet`] followed by
+    /// [`finish_data_packet`]. Use those instead if the MCU is too slow to do all the work before
+    /// the response has to be sent.
+    ///
+    /// [`respond_data_packet`]: #method.respond_data_packet
+    /// [`finish_data_packet`]: #method.finish_data_packet
     pub fn process_data_packet(
         &mut self,
         rx_end: Instant,
@@ -376,22 +887,407 @@ impl<C: Config> LinkLayer<C> {
         payload: &[u8],
         crc_ok: bool,
     ) -> Cmd {
-        if let State::Connection(conn) = &mut self.state {
-            match conn.process_data_packet(rx_end, tx, &mut self.timer, header, payload, crc_ok) {
-                Ok(cmd) => cmd,
-                Err(()) => {
-                    debug!("connection ended, standby");
-                    self.state = State::Standby;
-                    Cmd {
+        let index = self.listening;
+        let connections = &mut self.connections;
+        let slot = index.and_then(|index| connections.get_mut(usize::from(index)));
+        if let (Some(index), Some(Some(slot))) = (index, slot) {
+            let result = slot.conn.process_data_packet(
+                rx_end,
+                tx,
+                &mut self.timer,
+                &mut self.event_hook,
+                &mut self.control_handler,
+                &mut self.stats,
+                header,
+                payload,
+                crc_ok,
+            );
+            self.connection_result(index, result)
+        } else {
+            warn!("data channel PDU received while not connected");
+            Self::unexpected_event()
+        }
+    }
+
+    /// Responds to an incoming data channel packet, leaving all work that isn't needed for the
+    /// response to [`finish_data_packet`].
+    ///
+    /// The response has to be on air 150 µs (`T_IFS`) after the packet was received. Radio
+    /// ramp-up and interrupt latency take a good part of that (about 40 µs on the nRF52), so this
+    /// method must complete within roughly 100 µs, ie. 6400 cycles at 64 MHz, or only 1600 cycles
+    /// on a Cortex-M0+ running at 16 MHz. It does nothing but:
+    ///
+    /// * the SN/NESN bookkeeping,
+    /// * copying the received payload into the RX queue (at most `MIN_DATA_PAYLOAD_BUF` Bytes),
+    ///   or answering an LL Control PDU,
+    /// * copying the next, already serialized PDU from the TX queue into the radio buffer (again,
+    ///   at most `MIN_DATA_PAYLOAD_BUF` Bytes), and starting its transmission.
+    ///
+    /// Nothing is logged, unless the connection ends. The `trace` feature can be used to verify
+    /// the budget on the target, between `TracePoint::RadioIrq` and `TracePoint::ResponseReady`.
+    ///
+    /// [`finish_data_packet`] must be called afterwards, before the next packet is received. It
+    /// can run at a lower interrupt priority.
+    ///
+    /// [`finish_data_packet`]: #method.finish_data_packet
+    pub fn respond_data_packet(
+        &mut self,
+        rx_end: Instant,
+        tx: &mut C::Transmitter,
+        header: data::Header,
+        payload: &[u8],
+        crc_ok: bool,
+    ) {
+        let index = self.listening;
+        let connections = &mut self.connections;
+        let slot = index.and_then(|index| connections.get_mut(usize::from(index)));
+        if let (Some(index), Some(Some(slot))) = (index, slot) {
+            if let Err(reason) = slot.conn.respond(
+                rx_end,
+                tx,
+                &mut self.control_handler,
+                &mut self.stats,
+                header,
+                payload,
+                crc_ok,
+            ) {
+                // `finish_data_packet` will return the radio and timer configuration for what's
+                // left to do
+                self.drop_connection(index, reason);
+            }
+        } else {
+            warn!("data channel PDU received while not connected");
+        }
+    }
+
+    /// Finishes the connection event after [`respond_data_packet`].
+    ///
+    /// This computes the next anchor point, checks the procedure and supervision timeouts, applies
+    /// connection updates, selects the next channel and invokes the `ConnectionEventHook`. None of
+    /// this is needed before the next connection event, so it is not bound by `T_IFS`. It may
+    /// take several 10 µs when a connection update is applied.
+    ///
+    /// Returns the `Cmd` that would have been returned by `process_data_packet`.
+    ///
+    /// [`respond_data_packet`]: #method.respond_data_packet
+    pub fn finish_data_packet(&mut self) -> Cmd {
+        let index = self.listening;
+        let connections = &mut self.connections;
+        let slot = index.and_then(|index| connections.get_mut(usize::from(index)));
+        if let (Some(index), Some(Some(slot))) = (index, slot) {
+            let result = slot
+                .conn
+                .finish_event(&mut self.timer, &mut self.event_hook);
+            self.connection_result(index, result)
+        } else {
+            // The connection was ended by `respond_data_packet`
+            self.next_cmd()
+        }
+    }
+
+    /// Turns the radio off until shortly before `next_activity`, if power hooks are installed and
+    /// there's enough time.
+    ///
+    /// `cmd` is held back and returned by `update_timer` once the sleep window ends.
+    fn power_down(&mut self, cmd: Cmd, next_activity: Instant) -> Cmd {
+        let hooks = match &mut self.power_hooks {
+            Some(hooks) => hooks,
+            None => return cmd,
+        };
+        // The timer is reprogrammed for the end of the window, so `cmd` has to set it again
+        match cmd.next_update {
+            NextUpdate::At(_) => {}
+            _ => return cmd,
+        }
+        let now = self.timer.now();
+        let window = match SleepWindow::before(now, next_activity, hooks.wakeup_time()) {
+            Some(window) => window,
+            None => return cmd,
+        };
+
+        hooks.enter_idle(&window);
+        let off = Cmd {
+            radio: RadioCmd::Off,
+            next_update: NextUpdate::At(window.end()),
+            queued_work: cmd.queued_work,
+        };
+        self.idle = Some((
+            window,
+            Cmd {
+                queued_work: false,
+                ..cmd
+            },
+        ));
+        off
+    }
+
+    /// Turns the radio off until the next event of connection `index`, if possible.
+    fn connection_idle(&mut self, index: u8, cmd: Cmd) -> Cmd {
+        match self
+            .connection_at(index)
+            .and_then(Connection::next_rx_window)
+        {
+            Some(next) => self.power_down(cmd, next),
+            None => cmd,
+        }
+    }
+
+    /// Handles the result of an update of connection `index`, returning the `Cmd` to apply.
+    ///
+    /// If the connection is the only activity, its `Cmd` is used as it is. Otherwise, the radio is
+    /// handed to whichever activity is next once the connection event is over.
+    fn connection_result(&mut self, index: u8, result: Result<Cmd, ErrorCode>) -> Cmd {
+        let cmd = match result {
+            Ok(cmd) => cmd,
+            Err(reason) => return self.end_connection(index, reason),
+        };
+        let in_event = match self.connections.get_mut(usize::from(index)) {
+            Some(Some(slot)) => {
+                if let NextUpdate::At(t) = cmd.next_update {
+                    slot.update_at = t;
+                }
+                slot.conn.in_event()
+            }
+            _ => return self.next_cmd(),
+        };
+
+        let now = self.timer.now();
+        self.reserve_connection(index, now);
+        if in_event || self.single_activity() {
+            self.listening = Some(index);
+            return self.connection_idle(index, cmd);
+        }
+
+        let queued_work = cmd.queued_work;
+        let mut cmd = self.arbitrate(now);
+        cmd.queued_work |= queued_work;
+        cmd
+    }
+
+    /// Returns whether a single connection is all the Link-Layer is doing.
+    ///
+    /// The radio then stays configured for that connection, like it would be without support for
+    /// multiple connections.
+    fn single_activity(&self) -> bool {
+        let active = self
+            .connections
+            .iter()
+            .filter(|slot| slot.is_some())
+            .count();
+        active == 1 && matches!(self.state, State::Standby)
+    }
+
+    /// Returns the index of an unused connection slot.
+    fn free_slot(&self) -> Option<u8> {
+        let index = self.connections.iter().position(Option::is_none)?;
+        Some(index as u8)
+    }
+
+    /// Reserves the radio for the next event of connection `index`.
+    fn reserve_connection(&mut self, index: u8, now: Instant) {
+        let activity = Activity::Connection(index);
+        let reservation = match self.connection_at(index) {
+            Some(conn) => match conn.next_reservation(activity) {
+                Some(reservation) => reservation,
+                // The ongoing event keeps its reservation
+                None if conn.in_event() => return,
+                // The anchor point is unknown, so the whole transmit window is reserved
+                None => {
+                    let update_at = self
+                        .connections
+                        .get(usize::from(index))
+                        .and_then(Option::as_ref)
+                        .map_or(now, |slot| slot.update_at);
+                    let end = latest(now, now, update_at);
+                    Reservation::new(activity, now, end.duration_since(now))
+                }
+            },
+            None => return,
+        };
+        if !self.sched.reserve(reservation) {
+            warn!("no room for the reservation of connection {}", index);
+        }
+    }
+
+    /// Updates all connections whose timer has expired at `now`.
+    ///
+    /// With several activities, the timer doesn't necessarily fire for each of them, so this is
+    /// done whenever the radio is rearranged. Connections that are lost are ended.
+    fn update_due_connections(&mut self, now: Instant) {
+        for index in 0..CONNECTIONS as u8 {
+            // Every update moves the next one forward by at least a connection interval
+            loop {
+                let slot = match self.connections.get_mut(usize::from(index)) {
+                    Some(Some(slot)) if reached(now, slot.update_at) => slot,
+                    _ => break,
+                };
+                let result =
+                    slot.conn
+                        .timer_update(&mut self.timer, &mut self.event_hook, &mut self.stats);
+                match result {
+                    Ok(cmd) => {
+                        slot.update_at = match cmd.next_update {
+                            NextUpdate::At(t) => t,
+                            // Not expected, wait for the next connection interval instead
+                            _ => now + slot.conn.connection_interval(),
+                        };
+                        self.reserve_connection(index, now);
+                    }
+                    Err(reason) => self.drop_connection(index, reason),
+                }
+            }
+        }
+    }
+
+    /// Returns the next time at which the timer has to fire: When a connection has to be updated,
+    /// or when the activity owning the radio may change.
+    fn next_wakeup(&self, now: Instant) -> Option<Instant> {
+        self.connections
+            .iter()
+            .flatten()
+            .map(|slot| slot.update_at)
+            .chain(self.sched.next_change(now))
+            .fold(None, |next, t| {
+                Some(next.map_or(t, |next| earliest(now, next, t)))
+            })
+    }
+
+    /// Configures the radio for the activity that owns it at `now`, when there are several.
+    ///
+    /// A connection event that has started keeps the radio. Otherwise, the radio listens on the
+    /// last advertising channel while advertising, and for the connection whose event comes next
+    /// if not. Advertising and scanning themselves are only done in `update_timer`.
+    fn arbitrate(&mut self, now: Instant) -> Cmd {
+        self.update_due_connections(now);
+        let current = self.sched.current(now);
+        let mut next = self.next_wakeup(now);
+
+        let owner = match current {
+            Some(Activity::Connection(index)) => Some(index),
+            _ => None,
+        };
+        let (radio, listening) = match (owner, &self.state) {
+            (Some(index), _) => (self.connection_at(index).map(Connection::listen_cmd), owner),
+            (None, State::Advertising { channel, .. }) => {
+                if current.is_some() {
+                    // An advertising event or scan window is due, let `update_timer` start it once
+                    // our last response is off the air
+                    let soon = now + EVENT_GAP;
+                    next = Some(next.map_or(soon, |next| earliest(now, next, soon)));
+                }
+                (
+                    Some(RadioCmd::ListenAdvertising { channel: *channel }),
+                    None,
+                )
+            }
+            (None, State::Standby) => {
+                let upcoming = self.upcoming_connection(now);
+                (
+                    upcoming
+                        .and_then(|index| self.connection_at(index))
+                        .map(Connection::listen_cmd),
+                    upcoming,
+                )
+            }
+        };
+        self.listening = listening;
+
+        let cmd = Cmd {
+            radio: radio.unwrap_or(RadioCmd::Off),
+            next_update: next.map_or(NextUpdate::Disable, NextUpdate::At),
+            queued_work: false,
+        };
+        match (current, next) {
+            // Nothing needs the radio until the next change
+            (None, Some(next)) => self.power_down(cmd, next),
+            _ => cmd,
+        }
+    }
+
+    /// Returns the index of the connection whose event comes next after `now`.
+    fn upcoming_connection(&self, now: Instant) -> Option<u8> {
+        (0..CONNECTIONS as u8)
+            .filter_map(|index| {
+                let reservation = self.sched.get(Activity::Connection(index))?;
+                Some((index, reservation.start()))
+            })
+            .fold(
+                None,
+                |next: Option<(u8, Instant)>, (index, start)| match next {
+                    Some((_, prev))
+                        if earliest(now, prev, start).raw_micros() == prev.raw_micros() =>
+                    {
+                        next
+                    }
+                    _ => Some((index, start)),
+                },
+            )
+            .map(|(index, _)| index)
+    }
+
+    /// Returns the next time after `now` at which a connection event starts, or a connection has
+    /// to be updated.
+    fn next_connection_start(&self, now: Instant) -> Option<Instant> {
+        (0..CONNECTIONS as u8)
+            .filter_map(|index| self.sched.get(Activity::Connection(index)))
+            .map(Reservation::start)
+            .filter(|start| !reached(now, *start))
+            .chain(self.connections.iter().flatten().map(|slot| slot.update_at))
+            .fold(None, |next, t| {
+                Some(next.map_or(t, |next| earliest(now, next, t)))
+            })
+    }
+
+    /// Returns the `Cmd` for whatever is left to do after a connection was ended.
+    fn next_cmd(&mut self) -> Cmd {
+        if self.connections.iter().all(Option::is_none) {
+            match &self.state {
+                State::Standby => {
+                    return Cmd {
                         next_update: NextUpdate::Disable,
                         radio: RadioCmd::Off,
                         // FIXME(#70) this might need to be changed to `true`
                         queued_work: false,
-                    }
+                    };
                 }
+                State::Advertising { sets, .. } if sets.is_empty() => return Self::idle_cmd(),
+                State::Advertising { .. } => {}
             }
-        } else {
-            unreachable!("received data channel PDU while not in connected state");
+        }
+        let now = self.timer.now();
+        self.arbitrate(now)
+    }
+
+    /// Ends the current sleep window, returning the `Cmd` held back by `power_down`.
+    fn wake_up(&mut self) -> Option<Cmd> {
+        let (_, cmd) = self.idle.take()?;
+        if let Some(hooks) = &mut self.power_hooks {
+            hooks.exit_idle();
+        }
+        Some(cmd)
+    }
+
+    /// Ends connection `index` for `reason`, returning the `Cmd` for what's left to do.
+    ///
+    /// Without other activities, the Link-Layer returns to standby.
+    fn end_connection(&mut self, index: u8, reason: ErrorCode) -> Cmd {
+        self.drop_connection(index, reason);
+        self.next_cmd()
+    }
+
+    /// Removes connection `index`, which was ended for `reason`.
+    fn drop_connection(&mut self, index: u8, reason: ErrorCode) {
+        debug!("connection {} ended ({:?})", index, reason);
+        if let Some(slot) = self.connections.get_mut(usize::from(index)) {
+            *slot = None;
+        }
+        if self.listening == Some(index) {
+            self.listening = None;
+        }
+        self.sched.release(Activity::Connection(index));
+        self.disconnect_reason = Some(reason);
+        if reason == ErrorCode::ConnectionTimeout {
+            self.stats.record(Counter::SupervisionTimeout);
         }
     }
 
@@ -403,75 +1299,204 @@ impl<C: Config> LinkLayer<C> {
     ///
     /// * `tx`: A `Transmitter` for sending packets.
     pub fn update_timer(&mut self, tx: &mut C::Transmitter) -> Cmd {
+        if self.timeslot.take().is_some() {
+            warn!("timeslot still active at next BLE activity, ending it");
+            self.sched.release(Activity::Timeslot);
+        }
+
+        if let Some(cmd) = self.wake_up() {
+            return cmd;
+        }
+
+        if self.single_activity() {
+            let index = self
+                .connections
+                .iter()
+                .position(Option::is_some)
+                .unwrap_or(0);
+            if let Some(Some(slot)) = self.connections.get_mut(index) {
+                let result =
+                    slot.conn
+                        .timer_update(&mut self.timer, &mut self.event_hook, &mut self.stats);
+                return self.connection_result(index as u8, result);
+            }
+        }
+
+        let now = self.timer.now();
+        self.update_due_connections(now);
+        let connection_due = matches!(self.sched.current(now), Some(Activity::Connection(_)));
+        let connected = self.connections.iter().any(Option::is_some);
+        if connection_due || (connected && matches!(self.state, State::Standby)) {
+            return self.arbitrate(now);
+        }
+        let next_connection = self.next_connection_start(now);
+
         match &mut self.state {
             State::Advertising {
-                next_adv,
-                interval,
-                pdu,
+                sets,
+                active,
                 channel,
                 ..
             } => {
-                *channel = channel.cycle();
-                let payload = pdu.payload();
-                let buf = tx.tx_payload_buf();
-                buf[..payload.len()].copy_from_slice(payload);
+                self.listening = None;
+                let handle = match sets.next_due(now) {
+                    Some(handle) => handle,
+                    // All sets were removed
+                    None => {
+                        self.sched.release(Activity::Advertising);
+                        return self.next_cmd();
+                    }
+                };
+                let scan_channel = match &mut self.scan {
+                    Some(scan) => {
+                        let scan_channel = scan.update(now);
+                        self.sched.reserve(scan.reservation());
+                        scan_channel
+                    }
+                    None => None,
+                };
 
-                // FIXME According to the spec, this has to broadcast on all advertising channels
+                let current = self.sched.current(now);
+                if current != Some(Activity::Advertising) {
+                    // Woken up for the start or end of a scan window (or a bit early). After a
+                    // window, keep listening on its channel, there's nothing to respond to anyway.
+                    if let (Some(Activity::Scanning), Some(scan_channel)) = (current, scan_channel)
+                    {
+                        *channel = scan_channel;
+                    }
+                    let next_change = self.sched.next_change(now);
+                    let cmd = Cmd {
+                        radio: RadioCmd::ListenAdvertising { channel: *channel },
+                        next_update: next_change.map_or(NextUpdate::Disable, NextUpdate::At),
+                        queued_work: false,
+                    };
+                    return match (current, next_change) {
+                        // Nothing needs the radio until the next change
+                        (None, Some(next)) => self.power_down(cmd, next),
+                        _ => cmd,
+                    };
+                }
 
-                tx.transmit_advertising(pdu.header(), *channel);
+                let set = match sets.get_mut(handle) {
+                    Some(set) => set,
+                    None => {
+                        self.sched.release(Activity::Advertising);
+                        return self.next_cmd();
+                    }
+                };
+                *active = handle;
+
+                *channel = set.advance();
+                let pdu = set.pdu();
+                if ByteWriter::new(tx.tx_payload_buf())
+                    .write_slice(pdu.payload())
+                    .is_ok()
+                {
+                    // FIXME According to the spec, this has to broadcast on all advertising channels
+
+                    tx.set_tx_power(self.adv_tx_power);
+                    tx.transmit_advertising(pdu.header(), *channel);
+                } else {
+                    warn!("advertising PDU doesn't fit into the TX buffer");
+                }
+
+                // `set` was just transmitted, so its next transmission is due after the spacing
+                let next_adv = sets.next_update(now).unwrap_or_else(|| now + set_spacing());
+                self.sched.reserve(Reservation::new(
+                    Activity::Advertising,
+                    next_adv,
+                    set_spacing(),
+                ));
 
-                *next_adv += *interval;
+                // Listen for scan and connect requests for a while before scanning, unless a
+                // connection event comes first
+                let next_change = self.sched.next_change(now).unwrap_or(next_adv);
+                let next = latest(now, now + set_spacing(), next_change);
+                let next = next_connection.map_or(next, |t| earliest(now, next, t));
 
                 Cmd {
                     radio: RadioCmd::ListenAdvertising { channel: *channel },
-                    next_update: NextUpdate::At(*next_adv),
+                    next_update: NextUpdate::At(next),
                     queued_work: false,
                 }
             }
-            State::Connection(conn) => match conn.timer_update(&mut self.timer) {
-                Ok(cmd) => cmd,
-                Err(()) => {
-                    debug!("connection ended (timer), standby");
-                    self.state = State::Standby;
-                    Cmd {
-                        next_update: NextUpdate::Disable,
-                        radio: RadioCmd::Off,
-                        // FIXME(#70) this might need to be changed to `true`
-                        queued_work: false,
-                    }
-                }
-            },
-            State::Standby => unreachable!("LL in standby received timer event"),
+            State::Standby => {
+                warn!("LL in standby received timer event");
+                Self::idle_cmd()
+            }
         }
     }
 
     /// Returns a reference to the connection state.
     ///
-    /// If the Link Layer is not currently in a connection, returns `None`.
+    /// If the Link Layer is not currently in a connection, returns `None`. With several
+    /// connections, this is the one with the lowest index (see `connection_at`).
     pub fn connection(&self) -> Option<&Connection<C>> {
-        if let State::Connection(conn) = &self.state {
-            Some(conn)
-        } else {
-            None
-        }
+        self.connections().next().map(|(_, conn)| conn)
+    }
+
+    /// Returns a mutable reference to the connection state.
+    ///
+    /// If the Link Layer is not currently in a connection, returns `None`. With several
+    /// connections, this is the one with the lowest index (see `connection_at_mut`).
+    pub fn connection_mut(&mut self) -> Option<&mut Connection<C>> {
+        self.connections
+            .iter_mut()
+            .flatten()
+            .next()
+            .map(|slot| &mut slot.conn)
+    }
+
+    /// Returns a reference to the state of connection `index`, if it is established.
+    pub fn connection_at(&self, index: u8) -> Option<&Connection<C>> {
+        let slot = self.connections.get(usize::from(index))?.as_ref()?;
+        Some(&slot.conn)
+    }
+
+    /// Returns a mutable reference to the state of connection `index`, if it is established.
+    pub fn connection_at_mut(&mut self, index: u8) -> Option<&mut Connection<C>> {
+        let slot = self.connections.get_mut(usize::from(index))?.as_mut()?;
+        Some(&mut slot.conn)
+    }
+
+    /// Returns the established connections, along with their indices.
+    pub fn connections(&self) -> impl Iterator<Item = (u8, &Connection<C>)> + '_ {
+        self.connections
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| Some((index as u8, &slot.as_ref()?.conn)))
     }
 
     /// Returns whether the Link-Layer is currently broadcasting advertisement packets.
     pub fn is_advertising(&self) -> bool {
-        if let State::Advertising { .. } = self.state {
-            true
-        } else {
-            false
-        }
+        matches!(self.state, State::Advertising { .. })
     }
 
-    /// Returns whether the Link-Layer is currently connected.
+    /// Returns whether the Link-Layer is currently connected to at least one device.
     pub fn is_connected(&self) -> bool {
-        if let State::Connection { .. } = self.state {
-            true
-        } else {
-            false
-        }
+        self.connections.iter().any(Option::is_some)
+    }
+
+    /// Returns the reason the last connection was ended for.
+    ///
+    /// This is `None` before the first connection has been ended, and is reset when a connection is
+    /// established. If the master ended the connection, this is the error code from its
+    /// `LL_TERMINATE_IND`.
+    pub fn disconnect_reason(&self) -> Option<ErrorCode> {
+        self.disconnect_reason
+    }
+
+    /// Returns a snapshot of the Link-Layer statistics.
+    ///
+    /// The counters are only maintained when the `stats` feature is enabled, and are all 0
+    /// otherwise.
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Resets all statistics counters to 0.
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats::default();
     }
 }
 
@@ -602,12 +1627,54 @@ pub trait Transmitter {
         header: data::Header,
         channel: DataChannel,
     );
+
+    /// Returns the transmission power levels supported by the radio.
+    ///
+    /// The default implementation reports a single level of 0 dBm.
+    fn supported_tx_power(&self) -> &[TxPower] {
+        &[TxPower::ZERO_DBM]
+    }
+
+    /// Configures the transmission power to use for all following transmissions.
+    ///
+    /// If `power` is not one of the levels returned by `supported_tx_power`, the implementation
+    /// should use the highest supported level below `power` (refer to `TxPower::clamp_to`).
+    ///
+    /// The Link-Layer calls this before transmitting advertising or data channel PDUs, so it should
+    /// be cheap. The default implementation does nothing.
+    fn set_tx_power(&mut self, power: TxPower) {
+        let _ = power;
+    }
+
+    /// Returns the received signal strength of the last received packet in dBm, if the radio
+    /// measures it.
+    ///
+    /// The Link-Layer calls this while processing a received packet, before transmitting a
+    /// response. The default implementation returns `None`.
+    fn last_rssi(&self) -> Option<i8> {
+        None
+    }
+
+    /// Returns the instant at which the access address of the last received packet was received,
+    /// if the radio captures it.
+    ///
+    /// This is usually captured by hardware and is much more precise than the `rx_end` timestamp
+    /// passed to the Link-Layer, which is taken when the radio interrupt gets serviced. It is
+    /// reported to the `Observer` and the `ConnectionEventHook`, where it can be used for time
+    /// synchronization or time-of-arrival measurements.
+    ///
+    /// Like `last_rssi`, this is called before transmitting a response. The default implementation
+    /// returns `None`.
+    fn rx_timestamp(&self) -> Option<Instant> {
+        None
+    }
 }
 
 /// A `Transmitter` that lowers Link-Layer packets to raw byte arrays that can be directly
 /// transmitted over the air, given a suitable radio.
 ///
-/// This implements preamble generation, CRC calculation and whitening in software.
+/// This implements preamble generation, CRC calculation and whitening in software. On the receive
+/// side, `phy::whiten` and `phy::check_crc` do the same for packets received by such a radio.
 pub struct RawTransmitter<R: Radio> {
     tx_buf: [u8; MIN_PACKET_BUF],
     radio: R,
@@ -615,18 +1682,23 @@ pub struct RawTransmitter<R: Radio> {
 
 // First 5 octets are Preamble and Access Address
 const PDU_START: usize = 5;
-const HEADER_RANGE: Range<usize> = PDU_START..PDU_START + 2;
-const PAYLOAD_RANGE: Range<usize> = PDU_START + 2..PDU_START + MIN_PDU_BUF;
 
 impl<R: Radio> RawTransmitter<R> {
     pub fn new(radio: R) -> Self {
         Self {
-            tx_buf: [0; MIN_PACKET_BUF as usize],
+            tx_buf: [0; MIN_PACKET_BUF],
             radio,
         }
     }
 
-    fn transmit(&mut self, access_address: u32, payload_length: u8, crc_iv: u32, freq: u16) {
+    fn transmit(
+        &mut self,
+        access_address: u32,
+        payload_length: u8,
+        crc_iv: u32,
+        whitening_iv: u8,
+        freq: u16,
+    ) {
         let preamble = if access_address & 1 == 1 {
             0b01010101
         } else {
@@ -636,32 +1708,41 @@ impl<R: Radio> RawTransmitter<R> {
 
         LittleEndian::write_u32(&mut self.tx_buf[1..5], access_address);
 
-        let crc = ble_crc24(
-            &self.tx_buf[PDU_START..PDU_START + 2 + payload_length as usize],
-            crc_iv,
-        );
-        LittleEndian::write_u24(&mut self.tx_buf[MIN_PACKET_BUF - 3..], crc);
-
-        // TODO whitening
-        if true {
-            unimplemented!();
-        }
+        let pdu_end = PDU_START + 2 + usize::from(payload_length);
+        let packet = match self.tx_buf.get_mut(..pdu_end + phy::CRC_LEN) {
+            Some(packet) => packet,
+            None => {
+                warn!(
+                    "{}-Byte payload doesn't fit into the TX buffer",
+                    payload_length
+                );
+                return;
+            }
+        };
+        let (pdu, crc_buf) = packet.split_at_mut(pdu_end);
+        let mut crc = [0; phy::CRC_LEN];
+        phy::append_crc(pdu.split_at(PDU_START).1, crc_iv, &mut crc);
+        crc_buf.copy_from_slice(&crc);
 
-        self.radio.transmit(&mut self.tx_buf, freq);
+        phy::whiten(packet.split_at_mut(PDU_START).1, whitening_iv);
+        self.radio.transmit(packet, freq);
+        // The payload might be retransmitted, so restore it
+        phy::whiten(packet.split_at_mut(PDU_START).1, whitening_iv);
     }
 }
 
 impl<R: Radio> Transmitter for RawTransmitter<R> {
     fn tx_payload_buf(&mut self) -> &mut [u8] {
-        &mut self.tx_buf[PAYLOAD_RANGE]
+        &mut self.tx_buf[PDU_START + 2..PDU_START + MIN_PDU_BUF]
     }
 
     fn transmit_advertising(&mut self, header: advertising::Header, channel: AdvertisingChannel) {
-        LittleEndian::write_u16(&mut self.tx_buf[HEADER_RANGE], header.to_u16());
+        LittleEndian::write_u16(&mut self.tx_buf[PDU_START..PDU_START + 2], header.to_u16());
         self.transmit(
             advertising::ACCESS_ADDRESS,
             header.payload_length(),
             advertising::CRC_PRESET,
+            channel.whitening_iv(),
             channel.freq(),
         );
     }
@@ -673,11 +1754,12 @@ impl<R: Radio> Transmitter for RawTransmitter<R> {
         header: data::Header,
         channel: DataChannel,
     ) {
-        LittleEndian::write_u16(&mut self.tx_buf[HEADER_RANGE], header.to_u16());
+        LittleEndian::write_u16(&mut self.tx_buf[PDU_START..PDU_START + 2], header.to_u16());
         self.transmit(
             access_address,
             header.payload_length(),
             crc_iv,
+            channel.whitening_iv(),
             channel.freq(),
         );
     }
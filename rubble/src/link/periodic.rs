@@ -0,0 +1,632 @@
+//! Synchronizing to periodic advertising trains (observer side).
+//!
+//! A periodic advertiser sends `AUX_SYNC_IND` PDUs at a fixed interval on the secondary
+//! (data) channels, hopping with Channel Selection Algorithm #2. To find the train, an observer
+//! receives an extended advertisement: The `ADV_EXT_IND` on the primary channel points (via its
+//! `AuxPtr` field) to an `AUX_ADV_IND`, whose `SyncInfo` field describes the timing, channel map
+//! and access address of the train.
+//!
+//! The Link-Layer's scanner only handles legacy advertising PDUs, so receiving the extended
+//! advertisement is left to the platform code, which has to follow the `AuxPtr` itself. Once it
+//! has the `AUX_ADV_IND`, the sync is driven like this:
+//!
+//! 1. `ExtendedPdu::parse` decodes the `AUX_ADV_IND`, and `PeriodicSync::new` creates a sync
+//!    from it and the instant at which the packet started.
+//! 2. The radio listens as described by `PeriodicSync::radio_cmd`, opening the receive window at
+//!    `PeriodicSync::window_start` for `PeriodicSync::window`.
+//! 3. A received packet is passed to `PeriodicSync::process_packet` instead of the Link-Layer,
+//!    which hands a `PeriodicReport` to the `Observer`. If the window closes without a packet,
+//!    `PeriodicSync::event_missed` is called.
+//!
+//! Both methods move the sync on to its next event. When no packet has been received for the
+//! sync timeout (or the first one doesn't arrive within 6 events), the `Observer` is told that
+//! the sync was lost and `Error::InvalidValue` is returned: The sync must be dropped then.
+//!
+//! Chained `AUX_CHAIN_IND` PDUs aren't followed; reports of PDUs that have further data are
+//! marked `DataStatus::Truncated`. Only the LE 1M PHY is supported for the train itself.
+
+use crate::{
+    bytes::ByteReader,
+    link::{
+        advertising::Header,
+        llcp::{ErrorCode, Phys},
+        scan::Observer,
+        AddressKind, ChannelMap, DeviceAddress, RadioCmd,
+    },
+    phy::DataChannel,
+    time::{Duration, Instant},
+    Error,
+};
+
+/// Number of periodic advertising events that may pass before the first packet has to be
+/// received.
+const ESTABLISH_EVENTS: u8 = 6;
+
+/// Length of the offset adjustment applied when `SyncInfo`'s *Offset Adjust* bit is set.
+const OFFSET_ADJUST: Duration = Duration::from_micros(2_457_600);
+
+/// Mode of an extended advertisement (`AdvMode` field).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AdvMode {
+    NonConnectableNonScannable,
+    Connectable,
+    Scannable,
+    /// Reserved for future use.
+    Reserved,
+}
+
+/// *Advertising Data Info* (`ADI`) field of an extended advertisement.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AdvDataInfo {
+    /// Advertising data ID, which changes whenever the advertising data changes.
+    pub did: u16,
+    /// Advertising set ID.
+    pub sid: u8,
+}
+
+/// `AuxPtr` field pointing to an auxiliary PDU on a secondary channel.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AuxPtr {
+    /// Secondary channel the auxiliary PDU is sent on.
+    pub channel: DataChannel,
+    /// Whether the advertiser's clock is accurate to 50 ppm (instead of 500 ppm).
+    pub accurate_clock: bool,
+    /// Time from the start of the PDU containing this field to the start of the auxiliary one.
+    pub offset: Duration,
+    /// PHY the auxiliary PDU is sent with.
+    pub phy: Phys,
+}
+
+impl AuxPtr {
+    /// Decodes the 3-Byte `AuxPtr` field.
+    ///
+    /// Returns `Error::InvalidValue` if the channel index isn't a valid data channel.
+    fn parse(raw: &[u8; 3]) -> Result<Self, Error> {
+        let offset = u16::from_le_bytes([raw[1], raw[2]]) & 0x1FFF;
+        let unit = if raw[0] & 0x80 == 0 { 30 } else { 300 };
+        let channel = raw[0] & 0x3F;
+        if channel > 36 {
+            return Err(Error::InvalidValue);
+        }
+        Ok(Self {
+            channel: DataChannel::new(channel),
+            accurate_clock: raw[0] & 0x40 != 0,
+            offset: Duration::from_micros(u32::from(offset) * unit),
+            phy: Phys::from_bits_truncate(1 << (raw[2] >> 5)),
+        })
+    }
+}
+
+/// `SyncInfo` field describing a periodic advertising train.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SyncInfo {
+    offset: Duration,
+    offset_unit: Duration,
+    interval: u16,
+    channel_map: ChannelMap,
+    sca: u8,
+    access_address: u32,
+    crc_init: u32,
+    event_counter: u16,
+}
+
+impl SyncInfo {
+    /// Size of the encoded field in Bytes.
+    pub const SIZE: usize = 18;
+
+    /// Decodes the 18-Byte `SyncInfo` field.
+    ///
+    /// Returns `Error::InvalidValue` if the channel map doesn't use at least 2 channels or the
+    /// interval is zero.
+    pub fn parse(raw: &[u8; Self::SIZE]) -> Result<Self, Error> {
+        let offset_field = u16::from_le_bytes([raw[0], raw[1]]);
+        let offset_unit = if offset_field & (1 << 13) == 0 {
+            30
+        } else {
+            300
+        };
+        let mut offset = Duration::from_micros(u32::from(offset_field & 0x1FFF) * offset_unit);
+        if offset_field & (1 << 14) != 0 {
+            offset += OFFSET_ADJUST;
+        }
+
+        let mut chm = [0; 5];
+        chm.copy_from_slice(&raw[4..9]);
+        let channel_map = ChannelMap::from_raw(chm);
+        let interval = u16::from_le_bytes([raw[2], raw[3]]);
+        if channel_map.num_used_channels() < 2 || interval == 0 {
+            return Err(Error::InvalidValue);
+        }
+
+        Ok(Self {
+            offset,
+            offset_unit: Duration::from_micros(offset_unit),
+            interval,
+            channel_map,
+            sca: raw[8] >> 5,
+            access_address: u32::from_le_bytes([raw[9], raw[10], raw[11], raw[12]]),
+            crc_init: u32::from_le_bytes([raw[13], raw[14], raw[15], 0]),
+            event_counter: u16::from_le_bytes([raw[16], raw[17]]),
+        })
+    }
+
+    /// Returns the time from the start of the `AUX_ADV_IND` to the start of the first
+    /// `AUX_SYNC_IND` this field refers to.
+    ///
+    /// The packet may start up to one offset unit (30 or 300 µs) later than this.
+    pub fn offset(&self) -> Duration {
+        self.offset
+    }
+
+    /// Returns the periodic advertising interval.
+    pub fn interval(&self) -> Duration {
+        Duration::from_micros(u32::from(self.interval) * 1_250)
+    }
+
+    /// Returns the secondary channels the train hops on.
+    pub fn channel_map(&self) -> &ChannelMap {
+        &self.channel_map
+    }
+
+    /// Returns the worst-case accuracy of the advertiser's sleep clock in ppm.
+    pub fn sleep_clock_ppm(&self) -> u16 {
+        // `sca` is a 3-bit field, so the fallback is never used
+        [500, 250, 150, 100, 75, 50, 30, 20]
+            .get(usize::from(self.sca))
+            .copied()
+            .unwrap_or(500)
+    }
+
+    /// Returns the access address of the train's packets.
+    pub fn access_address(&self) -> u32 {
+        self.access_address
+    }
+
+    /// Returns the CRC initialization value of the train's packets.
+    pub fn crc_init(&self) -> u32 {
+        self.crc_init
+    }
+
+    /// Returns the event counter of the first `AUX_SYNC_IND` this field refers to.
+    pub fn event_counter(&self) -> u16 {
+        self.event_counter
+    }
+}
+
+/// A PDU using the *Common Extended Advertising Payload Format*.
+///
+/// This is the format of `ADV_EXT_IND`, `AUX_ADV_IND`, `AUX_SYNC_IND` and `AUX_CHAIN_IND`.
+/// Fields the PDU doesn't contain are `None`.
+#[derive(Debug, Copy, Clone)]
+pub struct ExtendedPdu<'a> {
+    pub mode: AdvMode,
+    pub advertiser: Option<DeviceAddress>,
+    pub target: Option<DeviceAddress>,
+    /// Raw `CTEInfo` field, if a Constant Tone Extension follows the PDU.
+    pub cte_info: Option<u8>,
+    pub adi: Option<AdvDataInfo>,
+    pub aux_ptr: Option<AuxPtr>,
+    pub sync_info: Option<SyncInfo>,
+    /// Transmission power of the advertiser in dBm.
+    pub tx_power: Option<i8>,
+    /// *Additional Controller Advertising Data*.
+    pub acad: &'a [u8],
+    /// Advertising data (a sequence of AD structures).
+    pub data: &'a [u8],
+}
+
+impl<'a> ExtendedPdu<'a> {
+    /// Decodes an extended advertising PDU from its header and payload.
+    pub fn parse(header: Header, payload: &'a [u8]) -> Result<Self, Error> {
+        let mut bytes = ByteReader::new(payload);
+        let first = bytes.read_u8()?;
+        let mode = match first >> 6 {
+            0b00 => AdvMode::NonConnectableNonScannable,
+            0b01 => AdvMode::Connectable,
+            0b10 => AdvMode::Scannable,
+            _ => AdvMode::Reserved,
+        };
+        let mut ext = ByteReader::new(bytes.read_slice(usize::from(first & 0x3F))?);
+        let data = bytes.read_rest();
+
+        let mut pdu = Self {
+            mode,
+            advertiser: None,
+            target: None,
+            cte_info: None,
+            adi: None,
+            aux_ptr: None,
+            sync_info: None,
+            tx_power: None,
+            acad: &[],
+            data,
+        };
+        if ext.is_empty() {
+            return Ok(pdu);
+        }
+
+        let address = |raw: &[u8], random: bool| {
+            let mut bytes = [0; 6];
+            bytes.copy_from_slice(raw);
+            let kind = if random {
+                AddressKind::Random
+            } else {
+                AddressKind::Public
+            };
+            DeviceAddress::new(bytes, kind)
+        };
+        let flags = ext.read_u8()?;
+        if flags & (1 << 0) != 0 {
+            pdu.advertiser = Some(address(ext.read_slice(6)?, header.tx_add()));
+        }
+        if flags & (1 << 1) != 0 {
+            pdu.target = Some(address(ext.read_slice(6)?, header.rx_add()));
+        }
+        if flags & (1 << 2) != 0 {
+            pdu.cte_info = Some(ext.read_u8()?);
+        }
+        if flags & (1 << 3) != 0 {
+            let adi = ext.read_u16_le()?;
+            pdu.adi = Some(AdvDataInfo {
+                did: adi & 0x0FFF,
+                sid: (adi >> 12) as u8,
+            });
+        }
+        if flags & (1 << 4) != 0 {
+            pdu.aux_ptr = Some(AuxPtr::parse(&ext.read_array()?)?);
+        }
+        if flags & (1 << 5) != 0 {
+            let mut raw = [0; SyncInfo::SIZE];
+            raw.copy_from_slice(ext.read_slice(SyncInfo::SIZE)?);
+            pdu.sync_info = Some(SyncInfo::parse(&raw)?);
+        }
+        if flags & (1 << 6) != 0 {
+            pdu.tx_power = Some(ext.read_u8()? as i8);
+        }
+        pdu.acad = ext.read_rest();
+        Ok(pdu)
+    }
+}
+
+/// Whether a `PeriodicReport` carries all of the advertising data.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DataStatus {
+    Complete,
+    /// The PDU points to an `AUX_CHAIN_IND` with more data, which isn't received.
+    Truncated,
+}
+
+/// A periodic advertisement received by a `PeriodicSync`.
+#[derive(Debug, Copy, Clone)]
+pub struct PeriodicReport<'a> {
+    /// Address of the periodic advertiser, as given in the `AUX_ADV_IND`.
+    pub advertiser: Option<DeviceAddress>,
+
+    /// Advertising set ID of the train.
+    pub sid: u8,
+
+    /// Counter of the periodic advertising event the packet was received in.
+    pub event_counter: u16,
+
+    /// Transmission power of the advertiser in dBm, if included.
+    pub tx_power: Option<i8>,
+
+    /// Received signal strength of the packet in dBm, if the radio measured it.
+    pub rssi: Option<i8>,
+
+    pub status: DataStatus,
+
+    /// Advertising data (a sequence of AD structures).
+    pub data: &'a [u8],
+}
+
+/// Passed to the `Observer` when a `PeriodicSync` failed or timed out.
+#[derive(Debug, Copy, Clone)]
+pub struct SyncLost {
+    /// Address of the periodic advertiser, as given in the `AUX_ADV_IND`.
+    pub advertiser: Option<DeviceAddress>,
+
+    /// Advertising set ID of the train.
+    pub sid: u8,
+
+    /// `ConnectionFailedToBeEstablished` if the first packet never arrived, `ConnectionTimeout`
+    /// if the sync timeout expired.
+    pub reason: ErrorCode,
+}
+
+/// Synchronization to a periodic advertising train.
+#[derive(Debug)]
+pub struct PeriodicSync {
+    advertiser: Option<DeviceAddress>,
+    sid: u8,
+    interval: Duration,
+    channel_map: ChannelMap,
+    access_address: u32,
+    crc_init: u32,
+    sca_ppm: u16,
+    timeout: Duration,
+
+    /// Counter of the next event.
+    event_counter: u16,
+    /// Expected anchor point of the next event.
+    anchor: Instant,
+    /// Anchor point of the last received packet (or the `AUX_ADV_IND` before that).
+    last_anchor: Instant,
+    /// Extra receive window length to account for the `SyncInfo` offset unit.
+    offset_unit: Duration,
+    /// Events that passed before the first packet was received, `None` once established.
+    establishing: Option<u8>,
+}
+
+impl PeriodicSync {
+    /// Creates a sync to the train described by `aux_adv_ind`, which started at `start`.
+    ///
+    /// * **`local_sca_ppm`**: Accuracy of our sleep clock in ppm.
+    /// * **`timeout`**: Maximum time between 2 received packets before the sync is lost.
+    ///
+    /// Returns `Error::InvalidValue` if the PDU has no `SyncInfo` field.
+    pub fn new(
+        aux_adv_ind: &ExtendedPdu<'_>,
+        start: Instant,
+        local_sca_ppm: u16,
+        timeout: Duration,
+    ) -> Result<Self, Error> {
+        let info = aux_adv_ind.sync_info.ok_or(Error::InvalidValue)?;
+        Ok(Self {
+            advertiser: aux_adv_ind.advertiser,
+            sid: aux_adv_ind.adi.map_or(0, |adi| adi.sid),
+            interval: info.interval(),
+            channel_map: info.channel_map,
+            access_address: info.access_address,
+            crc_init: info.crc_init,
+            sca_ppm: info.sleep_clock_ppm() + local_sca_ppm,
+            timeout,
+            event_counter: info.event_counter,
+            anchor: start + info.offset,
+            last_anchor: start,
+            offset_unit: info.offset_unit,
+            establishing: Some(0),
+        })
+    }
+
+    /// Returns whether the first packet of the train has been received.
+    pub fn is_established(&self) -> bool {
+        self.establishing.is_none()
+    }
+
+    /// Returns the periodic advertising interval.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Returns the counter of the next periodic advertising event.
+    pub fn event_counter(&self) -> u16 {
+        self.event_counter
+    }
+
+    /// Returns the secondary channel of the next event.
+    pub fn channel(&self) -> DataChannel {
+        csa2_channel(
+            self.event_counter,
+            channel_identifier(self.access_address),
+            &self.channel_map,
+        )
+    }
+
+    /// Returns the radio command to listen for the packet of the next event.
+    pub fn radio_cmd(&self) -> RadioCmd {
+        RadioCmd::ListenData {
+            channel: self.channel(),
+            access_address: self.access_address,
+            crc_init: self.crc_init,
+        }
+    }
+
+    /// Returns the instant at which the receive window for the next event opens.
+    pub fn window_start(&self) -> Instant {
+        self.anchor - self.widening()
+    }
+
+    /// Returns the length of the receive window for the next event.
+    ///
+    /// The packet has to start within the window.
+    pub fn window(&self) -> Duration {
+        let window = self.widening() + self.widening();
+        if self.is_established() {
+            window
+        } else {
+            window + self.offset_unit
+        }
+    }
+
+    /// Processes an `AUX_SYNC_IND` received in the current event and moves on to the next one.
+    ///
+    /// `timestamp` is the instant at which the packet started and `rssi` its signal strength.
+    /// Advertising data is passed to `observer`. If the packet can't be decoded, this behaves
+    /// like `event_missed`.
+    pub fn process_packet<O: Observer>(
+        &mut self,
+        header: Header,
+        payload: &[u8],
+        timestamp: Instant,
+        rssi: Option<i8>,
+        observer: &mut O,
+    ) -> Result<(), Error> {
+        let pdu = match ExtendedPdu::parse(header, payload) {
+            Ok(pdu) => pdu,
+            Err(_) => return self.event_missed(observer),
+        };
+
+        observer.periodic_report(&PeriodicReport {
+            advertiser: self.advertiser,
+            sid: self.sid,
+            event_counter: self.event_counter,
+            tx_power: pdu.tx_power,
+            rssi,
+            status: if pdu.aux_ptr.is_some() {
+                DataStatus::Truncated
+            } else {
+                DataStatus::Complete
+            },
+            data: pdu.data,
+        });
+
+        self.establishing = None;
+        self.anchor = timestamp;
+        self.last_anchor = timestamp;
+        self.advance();
+        Ok(())
+    }
+
+    /// Moves on to the next event after no packet was received in the current one.
+    ///
+    /// Returns `Error::InvalidValue` after telling `observer` if the sync was lost.
+    pub fn event_missed<O: Observer>(&mut self, observer: &mut O) -> Result<(), Error> {
+        let reason = match &mut self.establishing {
+            Some(missed) => {
+                *missed += 1;
+                if *missed < ESTABLISH_EVENTS {
+                    None
+                } else {
+                    Some(ErrorCode::ConnectionFailedToBeEstablished)
+                }
+            }
+            None if self.anchor.duration_since(self.last_anchor) >= self.timeout => {
+                Some(ErrorCode::ConnectionTimeout)
+            }
+            None => None,
+        };
+
+        if let Some(reason) = reason {
+            observer.sync_lost(&SyncLost {
+                advertiser: self.advertiser,
+                sid: self.sid,
+                reason,
+            });
+            return Err(Error::InvalidValue);
+        }
+
+        self.advance();
+        Ok(())
+    }
+
+    fn advance(&mut self) {
+        self.event_counter = self.event_counter.wrapping_add(1);
+        self.anchor += self.interval;
+    }
+
+    /// Computes the window widening for the next event, like for connection events.
+    fn widening(&self) -> Duration {
+        let elapsed = self.anchor.duration_since(self.last_anchor);
+        let drift = (u64::from(elapsed.as_micros()) * u64::from(self.sca_ppm)).div_ceil(1_000_000);
+        Duration::from_micros((drift as u32 + 16).min(self.interval.as_micros() / 2))
+    }
+}
+
+/// Derives the Channel Selection Algorithm #2 channel identifier from an access address.
+fn channel_identifier(access_address: u32) -> u16 {
+    (access_address >> 16) as u16 ^ access_address as u16
+}
+
+/// Selects the channel of event `counter` with Channel Selection Algorithm #2.
+fn csa2_channel(counter: u16, channel_id: u16, channel_map: &ChannelMap) -> DataChannel {
+    // The permutation reverses the bits of each Byte, then the Multiply-Add-Modulo step
+    let mut prn = counter ^ channel_id;
+    for _ in 0..3 {
+        let perm = u16::from_le_bytes([
+            (prn as u8).reverse_bits(),
+            ((prn >> 8) as u8).reverse_bits(),
+        ]);
+        prn = perm.wrapping_mul(17).wrapping_add(channel_id);
+    }
+    let prn = prn ^ channel_id;
+
+    let unmapped = DataChannel::new((prn % 37) as u8);
+    if channel_map.is_used(unmapped) {
+        unmapped
+    } else {
+        let index = (u32::from(channel_map.num_used_channels()) * u32::from(prn)) >> 16;
+        channel_map.by_index(index as u8).unwrap_or(unmapped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::link::advertising::PduType};
+
+    #[derive(Default)]
+    struct Reports {
+        data: Option<(u16, [u8; 3])>,
+        lost: Option<ErrorCode>,
+    }
+
+    impl Observer for Reports {
+        fn advertisement(&mut self, _: &crate::link::advertising::Pdu<'_>) {}
+
+        fn periodic_report(&mut self, report: &PeriodicReport<'_>) {
+            let mut data = [0; 3];
+            data.copy_from_slice(report.data);
+            self.data = Some((report.event_counter, data));
+        }
+
+        fn sync_lost(&mut self, lost: &SyncLost) {
+            self.lost = Some(lost.reason);
+        }
+    }
+
+    #[test]
+    fn csa2() {
+        let id = channel_identifier(0x8E89_BED6);
+        assert_eq!(id, 0x305F);
+        let all = ChannelMap::with_all_channels();
+        for (counter, channel) in [25, 20, 6, 21].iter().enumerate() {
+            assert_eq!(csa2_channel(counter as u16, id, &all).index(), *channel);
+        }
+    }
+
+    #[test]
+    fn sync() {
+        let header = Header::new(PduType::from(0b0111));
+        #[rustfmt::skip]
+        let aux_adv_ind = [
+            // Header length 21, non-connectable
+            21, 0b0010_1000,
+            // ADI: SID 3
+            0x01, 0x30,
+            // SyncInfo: offset 100 * 30 µs, interval 80 * 1.25 ms, all channels, 50 ppm
+            100, 0, 80, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0b1011_1111,
+            0xD6, 0xBE, 0x89, 0x8E, 0x11, 0x22, 0x33, 7, 0,
+        ];
+        let pdu = ExtendedPdu::parse(header, &aux_adv_ind).unwrap();
+        assert_eq!(pdu.mode, AdvMode::NonConnectableNonScannable);
+        assert_eq!(pdu.adi.unwrap().sid, 3);
+
+        let start = Instant::from_raw_micros(1_000);
+        let timeout = Duration::from_millis(250);
+        let mut sync = PeriodicSync::new(&pdu, start, 50, timeout).unwrap();
+        assert_eq!(sync.event_counter(), 7);
+        assert_eq!(sync.window_start() - start, Duration::from_micros(2_983));
+        assert!(!sync.is_established());
+
+        // No extended header, 3 Bytes of data
+        let mut reports = Reports::default();
+        let payload = [0, 1, 2, 3];
+        let rx = start + Duration::from_micros(3_010);
+        sync.process_packet(header, &payload, rx, None, &mut reports)
+            .unwrap();
+        assert!(sync.is_established());
+        assert_eq!(reports.data, Some((7, [1, 2, 3])));
+        let rx = rx + Duration::from_millis(100);
+        sync.process_packet(header, &payload, rx, None, &mut reports)
+            .unwrap();
+        assert_eq!(reports.data, Some((8, [1, 2, 3])));
+        assert_eq!(sync.event_counter(), 9);
+
+        // 100 ppm over 200 ms
+        sync.event_missed(&mut reports).unwrap();
+        assert_eq!(sync.window_start() - rx, Duration::from_micros(199_964));
+        sync.event_missed(&mut reports).unwrap();
+        assert_eq!(sync.event_missed(&mut reports), Err(Error::InvalidValue));
+        assert_eq!(reports.lost, Some(ErrorCode::ConnectionTimeout));
+    }
+}
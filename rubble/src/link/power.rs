@@ -0,0 +1,117 @@
+//! Releasing the radio and high-frequency clock between radio activities.
+//!
+//! By default, the Link-Layer keeps the radio listening between advertising and connection
+//! events, which keeps the radio and the high-frequency crystal (HFXO) running all the time. Once
+//! `PowerHooks` are installed via `LinkLayer::set_power_hooks`, the Link-Layer instead turns the
+//! radio off (`RadioCmd::Off`) whenever nothing needs it for at least `MIN_IDLE`, and sets the
+//! timer to wake up `PowerHooks::wakeup_time` before the next activity:
+//!
+//! * `PowerHooks::enter_idle` is called with the `SleepWindow` right before the `RadioCmd::Off`
+//!   is returned. The hooks can stop the HFXO then.
+//! * `PowerHooks::exit_idle` is called by `LinkLayer::update_timer` at the end of the window,
+//!   before the Link-Layer returns the radio configuration for the next activity. It has to
+//!   restart the HFXO and return once it is stable.
+//!
+//! Both hooks run in the Link-Layer's interrupt context and must not sleep themselves. The
+//! application's idle loop can enter System ON sleep (eg. with `WFI`) while
+//! `LinkLayer::sleep_window` returns a window. Timer interrupts still wake the CPU, so sleeping is
+//! safe even if the window ends early.
+//!
+//! Note that the `Timer` used by the Link-Layer has to keep accurate time while the HFXO is
+//! stopped, so it should run from the low-frequency clock.
+
+use crate::time::{Duration, Instant};
+
+/// Minimum time the radio has to be unused for it to be turned off.
+pub const MIN_IDLE: Duration = Duration::from_micros(500);
+
+/// A period during which the Link-Layer doesn't need the radio.
+#[derive(Debug, Copy, Clone)]
+pub struct SleepWindow {
+    start: Instant,
+    end: Instant,
+}
+
+impl SleepWindow {
+    /// Creates the window between `now` and `wakeup` before `next_activity`.
+    ///
+    /// Returns `None` if the window would be shorter than `MIN_IDLE` (or if the activity is in
+    /// the past).
+    pub(super) fn before(now: Instant, next_activity: Instant, wakeup: Duration) -> Option<Self> {
+        let end = next_activity - wakeup;
+        let micros = end.raw_micros().wrapping_sub(now.raw_micros());
+        if micros < MIN_IDLE.as_micros() || micros > Instant::MAX_TIME_BETWEEN.as_micros() {
+            None
+        } else {
+            Some(Self { start: now, end })
+        }
+    }
+
+    /// Returns the instant at which the radio was turned off.
+    pub fn start(&self) -> Instant {
+        self.start
+    }
+
+    /// Returns the instant at which the Link-Layer wakes up again.
+    pub fn end(&self) -> Instant {
+        self.end
+    }
+
+    /// Returns the length of the window.
+    pub fn duration(&self) -> Duration {
+        self.end.duration_since(self.start)
+    }
+
+    /// Converts the length of the window to ticks of a timer running at `hz`, eg. 32768 for an
+    /// RTC.
+    ///
+    /// The result is rounded down, so that a timer set to it never fires after the window ends.
+    pub fn ticks(&self, hz: u32) -> u32 {
+        (u64::from(self.duration().as_micros()) * u64::from(hz) / 1_000_000) as u32
+    }
+}
+
+/// Platform hooks invoked by the Link-Layer when it turns the radio off and on.
+pub trait PowerHooks {
+    /// Returns how long before a radio activity `exit_idle` has to be called, which must cover
+    /// starting the high-frequency clock.
+    fn wakeup_time(&self) -> Duration;
+
+    /// Called when the Link-Layer doesn't need the radio until `window` ends.
+    fn enter_idle(&mut self, window: &SleepWindow);
+
+    /// Called when `window` has ended, before the radio is used again.
+    fn exit_idle(&mut self);
+}
+
+/// Does nothing and needs no wakeup time.
+///
+/// Installing these hooks still makes the Link-Layer turn the radio off between activities.
+impl PowerHooks for () {
+    fn wakeup_time(&self) -> Duration {
+        Duration::from_micros(0)
+    }
+
+    fn enter_idle(&mut self, _: &SleepWindow) {}
+
+    fn exit_idle(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window() {
+        let now = Instant::from_raw_micros(u32::MAX - 100);
+        let activity = now + Duration::from_millis(100);
+        let wakeup = Duration::from_micros(1_000);
+
+        let window = SleepWindow::before(now, activity, wakeup).unwrap();
+        assert_eq!(window.duration(), Duration::from_millis(99));
+        assert_eq!(window.ticks(32_768), 3244);
+
+        assert!(SleepWindow::before(now, now + Duration::from_micros(1_400), wakeup).is_none());
+        assert!(SleepWindow::before(activity, now, wakeup).is_none());
+    }
+}
@@ -0,0 +1,170 @@
+//! LE Power Control (Bluetooth 5.2).
+//!
+//! Power control allows both sides of a connection to ask each other for transmission power
+//! adjustments, and to report their own power level:
+//!
+//! * `LL_POWER_CONTROL_REQ` asks the peer to change its power by some amount of dB (or just to
+//!   report its current level). It is answered with an `LL_POWER_CONTROL_RSP` carrying the new
+//!   level, and whether the minimum or maximum level was reached.
+//! * `LL_POWER_CHANGE_IND` reports a change of the sender's power that was not requested by the
+//!   peer.
+//!
+//! The Link-Layer answers the master's requests by adjusting the connection's `TxPower` within the
+//! levels supported by the `Transmitter`. It reports how much the master may lower its own power
+//! based on the average RSSI measured by the `LinkQualityManager`, if one is installed. Once the
+//! master has used power control, power changes made by the application or by the
+//! `LinkQualityManager` are reported to it with an `LL_POWER_CHANGE_IND`.
+//!
+//! Requests to the master can be sent with `Responder::request_power_change`. The master's
+//! reported power is available via `Connection::peer_tx_power`, and can be combined with the
+//! RSSI of its packets to compute the path loss.
+
+use {crate::phy::TxPower, bitflags::bitflags};
+
+/// Value of the `TxPower` field when the sender can't report its power level.
+pub const TX_POWER_UNAVAILABLE: i8 = 127;
+
+/// Value of the `TxPower` field when the sender isn't transmitting on the PHY in question.
+pub const TX_POWER_UNMANAGED: i8 = 126;
+
+/// Value of the `Delta` field when the change is unknown, or when no change is requested.
+pub const DELTA_UNAVAILABLE: i8 = 127;
+
+/// Value of the `APR` field when the sender can't tell by how much the peer may lower its power.
+pub const APR_UNAVAILABLE: u8 = 0xFF;
+
+bitflags! {
+    /// Power limits reached by the sender of an `LL_POWER_CONTROL_RSP` or `LL_POWER_CHANGE_IND`.
+    pub struct PowerLimits: u8 {
+        /// The power is at the lowest supported level.
+        const MIN = (1 << 0);
+        /// The power is at the highest supported level.
+        const MAX = (1 << 1);
+    }
+}
+
+impl PowerLimits {
+    /// Returns the limits `level` has reached within the `supported` levels.
+    pub(crate) fn of(level: TxPower, supported: &[TxPower]) -> Self {
+        let mut limits = PowerLimits::empty();
+        if supported.iter().all(|lvl| *lvl >= level) {
+            limits |= PowerLimits::MIN;
+        }
+        if supported.iter().all(|lvl| *lvl <= level) {
+            limits |= PowerLimits::MAX;
+        }
+        limits
+    }
+}
+
+/// Transmission power reported by the peer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PeerTxPower {
+    /// The peer's power level, or `None` if it didn't report one.
+    pub level: Option<TxPower>,
+
+    /// Limits the peer's power has reached.
+    pub limits: PowerLimits,
+
+    /// Change of the peer's power in dB with its last report, if known.
+    pub delta: Option<i8>,
+}
+
+impl PeerTxPower {
+    /// Creates a `PeerTxPower` from the raw fields of a power control PDU.
+    pub(crate) fn from_raw(tx_power: i8, limits: PowerLimits, delta: i8) -> Self {
+        Self {
+            level: match tx_power {
+                TX_POWER_UNAVAILABLE | TX_POWER_UNMANAGED => None,
+                dbm => Some(TxPower::from_dbm(dbm)),
+            },
+            limits,
+            delta: if delta == DELTA_UNAVAILABLE {
+                None
+            } else {
+                Some(delta)
+            },
+        }
+    }
+
+    /// Returns the path loss in dB, given the RSSI of a packet received from the peer.
+    ///
+    /// Returns `None` if the peer didn't report its power level.
+    pub fn path_loss(&self, rssi: i8) -> Option<u8> {
+        let loss = i16::from(self.level?.as_dbm()) - i16::from(rssi);
+        Some(saturate_db(loss))
+    }
+}
+
+/// Changes `current` by `delta` dB, keeping the result within the `supported` levels.
+pub(crate) fn adjust(current: TxPower, delta: i8, supported: &[TxPower]) -> TxPower {
+    let target = TxPower::from_dbm(current.as_dbm().saturating_add(delta));
+    if delta > 0 {
+        // Round up, so that a small increase still has an effect
+        supported
+            .iter()
+            .filter(|lvl| **lvl > current && **lvl <= target)
+            .max()
+            .or_else(|| supported.iter().filter(|lvl| **lvl > current).min())
+            .cloned()
+            .unwrap_or(current)
+    } else {
+        target.clamp_to(supported)
+    }
+}
+
+/// Returns the `APR` to report to the peer: by how many dB the average RSSI of its packets exceeds
+/// the level below which the link is considered weak.
+pub(crate) fn acceptable_reduction(average_rssi: Option<i8>, rssi_low: i8) -> u8 {
+    match average_rssi {
+        Some(rssi) => saturate_db(i16::from(rssi) - i16::from(rssi_low)),
+        None => APR_UNAVAILABLE,
+    }
+}
+
+/// Clamps `db` to the range of a `u8`, excluding the "unavailable" value.
+fn saturate_db(db: i16) -> u8 {
+    if db < 0 {
+        0
+    } else if db >= 0xFF {
+        0xFE
+    } else {
+        db as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adjust_within_supported() {
+        let power = TxPower::from_dbm;
+        let supported = [
+            power(-20),
+            power(-16),
+            power(-12),
+            power(-8),
+            power(-4),
+            power(0),
+            power(3),
+            power(4),
+        ];
+
+        assert_eq!(adjust(power(0), 2, &supported), power(3));
+        assert_eq!(adjust(power(0), 10, &supported), power(4));
+        assert_eq!(adjust(power(4), 1, &supported), power(4));
+        assert_eq!(adjust(power(0), -6, &supported), power(-8));
+        assert_eq!(adjust(power(-16), -10, &supported), power(-20));
+
+        assert_eq!(PowerLimits::of(power(4), &supported), PowerLimits::MAX);
+        assert_eq!(PowerLimits::of(power(-20), &supported), PowerLimits::MIN);
+        assert_eq!(PowerLimits::of(power(0), &supported), PowerLimits::empty());
+
+        let peer = PeerTxPower::from_raw(-4, PowerLimits::empty(), DELTA_UNAVAILABLE);
+        assert_eq!(peer.delta, None);
+        assert_eq!(peer.path_loss(-70), Some(66));
+        assert_eq!(acceptable_reduction(Some(-60), -80), 20);
+        assert_eq!(acceptable_reduction(None, -80), APR_UNAVAILABLE);
+    }
+}
@@ -0,0 +1,217 @@
+//! LE Privacy: Resolving private device addresses.
+//!
+//! Devices using LE Privacy periodically change their address to a new *resolvable private
+//! address* (RPA). An RPA consists of a 24-bit random part `prand` and a 24-bit `hash`, which is
+//! computed by encrypting `prand` with the device's **I**dentity **R**esolving **K**ey (IRK):
+//!
+//! ```notrust
+//! hash = ah(IRK, prand) = e(IRK, padding || prand) mod 2^24
+//! ```
+//!
+//! Devices that have bonded with the privacy-enabled device have received its IRK and *identity
+//! address* during key distribution. When they see an RPA, they can check it against all stored
+//! IRKs to find out which device is behind it.
+//!
+//! [`IdentityResolver`] performs this lookup. Since it requires AES-128, the encryption primitive
+//! has to be supplied by the platform via the [`Aes128`] trait (most BLE-capable MCUs have a
+//! hardware AES block that can be used for this).
+//!
+//! [`IdentityResolver`]: struct.IdentityResolver.html
+//! [`Aes128`]: trait.Aes128.html
+
+use {
+    super::{AddressKind, DeviceAddress},
+    crate::utils::HexSlice,
+    core::{iter, slice},
+};
+
+/// Trait for AES-128 block cipher implementations.
+///
+/// This is the security function `e` defined by the Bluetooth specification.
+pub trait Aes128 {
+    /// Encrypts `block` in place using `key`.
+    ///
+    /// Both `key` and `block` are in big-endian byte order (most significant Byte first), as used
+    /// throughout the cryptographic toolbox in the specification.
+    fn encrypt_block(&mut self, key: &[u8; 16], block: &mut [u8; 16]);
+}
+
+/// An Identity Resolving Key (IRK).
+#[derive(Debug, Copy, Clone)]
+pub struct IdentityResolvingKey(HexSlice<[u8; 16]>);
+
+impl IdentityResolvingKey {
+    /// Creates an IRK from its little-endian representation.
+    ///
+    /// This is the Byte order used when the key is distributed via the Security Manager.
+    pub fn from_le_bytes(bytes: [u8; 16]) -> Self {
+        IdentityResolvingKey(HexSlice(bytes))
+    }
+
+    /// Returns the little-endian representation of the key.
+    pub fn to_le_bytes(&self) -> [u8; 16] {
+        self.0 .0
+    }
+
+    /// Computes the random address function `ah(IRK, prand)`.
+    ///
+    /// `prand` and the returned hash are 24-bit values in little-endian Byte order, the same way
+    /// they are stored in the device address.
+    pub fn ah(&self, aes: &mut impl Aes128, prand: [u8; 3]) -> [u8; 3] {
+        let mut key = self.0 .0;
+        key.reverse();
+
+        let mut block = [0; 16];
+        block[13] = prand[2];
+        block[14] = prand[1];
+        block[15] = prand[0];
+        aes.encrypt_block(&key, &mut block);
+
+        [block[15], block[14], block[13]]
+    }
+
+    /// Checks whether `address` is a resolvable private address generated from this key.
+    pub fn resolves(&self, aes: &mut impl Aes128, address: &DeviceAddress) -> bool {
+        if !is_resolvable_private(address) {
+            return false;
+        }
+
+        let raw = address.raw();
+        self.ah(aes, [raw[3], raw[4], raw[5]]) == [raw[0], raw[1], raw[2]]
+    }
+}
+
+/// Returns whether `address` is a resolvable private address.
+///
+/// This only checks the address type bits, not whether the address can be resolved by any IRK.
+pub fn is_resolvable_private(address: &DeviceAddress) -> bool {
+    // The 2 most significant bits of a resolvable private address are `0b01`
+    address.kind() == AddressKind::Random && address.raw()[5] >> 6 == 0b01
+}
+
+/// The identity of a bonded device.
+#[derive(Debug, Copy, Clone)]
+pub struct Identity {
+    irk: IdentityResolvingKey,
+    address: DeviceAddress,
+}
+
+impl Identity {
+    /// Creates an identity from the IRK and identity address distributed by a device.
+    pub fn new(irk: IdentityResolvingKey, address: DeviceAddress) -> Self {
+        Self { irk, address }
+    }
+
+    /// Returns the device's Identity Resolving Key.
+    pub fn irk(&self) -> &IdentityResolvingKey {
+        &self.irk
+    }
+
+    /// Returns the device's identity address.
+    ///
+    /// This is either a public or a static random address that does not change over time.
+    pub fn address(&self) -> DeviceAddress {
+        self.address
+    }
+}
+
+/// Resolves device addresses against a set of known identities.
+///
+/// Like `WhitelistFilter`, the set of identities is given as an iterator, which is cloned and
+/// iterated over for each address that needs to be resolved. This allows the identities to come
+/// from any storage the application uses for bonding information.
+pub struct IdentityResolver<A: Aes128, I: Iterator<Item = Identity> + Clone> {
+    aes: A,
+    identities: I,
+}
+
+impl<A: Aes128, I: Iterator<Item = Identity> + Clone> IdentityResolver<A, I> {
+    /// Creates a resolver using `aes` for IRK checks that looks up addresses in `identities`.
+    pub fn new(aes: A, identities: I) -> Self {
+        Self { aes, identities }
+    }
+
+    /// Resolves `address` to the identity of a known device.
+    ///
+    /// If `address` is a resolvable private address, all IRKs are tried. Otherwise, `address` is
+    /// compared against the identity addresses directly, since a bonded device might also connect
+    /// using its identity address.
+    ///
+    /// Returns `None` if `address` does not belong to any known device.
+    pub fn resolve(&mut self, address: &DeviceAddress) -> Option<Identity> {
+        let aes = &mut self.aes;
+        if is_resolvable_private(address) {
+            self.identities
+                .clone()
+                .find(|id| id.irk.resolves(aes, address))
+        } else {
+            self.identities.clone().find(|id| id.address == *address)
+        }
+    }
+
+    /// Resolves `address` and returns the identity address of the device, if known.
+    pub fn identity_address(&mut self, address: &DeviceAddress) -> Option<DeviceAddress> {
+        self.resolve(address).map(|id| id.address)
+    }
+
+    /// Returns a mutable reference to the AES implementation.
+    pub fn aes(&mut self) -> &mut A {
+        &mut self.aes
+    }
+}
+
+pub type SliceIter<'a> = iter::Cloned<slice::Iter<'a, Identity>>;
+
+impl<'a, A: Aes128> IdentityResolver<A, SliceIter<'a>> {
+    /// Creates a resolver that looks up addresses in a slice of identities.
+    pub fn from_slice(aes: A, identities: &'a [Identity]) -> Self {
+        Self::new(aes, identities.iter().cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Not AES, but good enough to check that the right Bytes end up in the right place.
+    struct XorCipher;
+
+    impl Aes128 for XorCipher {
+        fn encrypt_block(&mut self, key: &[u8; 16], block: &mut [u8; 16]) {
+            for (b, k) in block.iter_mut().zip(key) {
+                *b ^= k;
+            }
+        }
+    }
+
+    #[test]
+    fn resolve() {
+        let mut irk = [0; 16];
+        irk[0] = 0x11;
+        irk[1] = 0x22;
+        irk[2] = 0x33;
+        let irk = IdentityResolvingKey::from_le_bytes(irk);
+        let identity_addr = DeviceAddress::new([6, 5, 4, 3, 2, 1], AddressKind::Public);
+        let ids = [Identity::new(irk, identity_addr)];
+        let mut resolver = IdentityResolver::from_slice(XorCipher, &ids);
+
+        // hash = prand ^ irk[0..3]
+        let prand = [0xAA, 0xBB, 0x7C];
+        let rpa = DeviceAddress::new(
+            [0xAA ^ 0x11, 0xBB ^ 0x22, 0x7C ^ 0x33, 0xAA, 0xBB, 0x7C],
+            AddressKind::Random,
+        );
+        assert_eq!(irk.ah(&mut XorCipher, prand), [0xBB, 0x99, 0x4F]);
+        assert!(is_resolvable_private(&rpa));
+        assert_eq!(resolver.identity_address(&rpa), Some(identity_addr));
+
+        let other = DeviceAddress::new([0, 0, 0, 0xAA, 0xBB, 0x7C], AddressKind::Random);
+        assert_eq!(resolver.identity_address(&other), None);
+
+        // Identity addresses resolve to themselves
+        assert_eq!(
+            resolver.identity_address(&identity_addr),
+            Some(identity_addr)
+        );
+    }
+}
@@ -11,6 +11,25 @@
 //!   splitting a [`PacketQueue`].
 //! * The [`SimpleQueue`], [`SimpleProducer`] and [`SimpleConsumer`] types, a minimal implementation
 //!   of the queue interface defined by [`PacketQueue`], [`Producer`] and [`Consumer`].
+//! * The [`PriorityQueue`], which keeps LL Control PDUs in a separate lane that is always drained
+//!   first.
+//! * The [`RingQueue`], which holds a configurable number of packets.
+//!
+//! # Grants
+//!
+//! Producers hand out [`Grant`]s: A grant is a reservation of queue memory for a single PDU. The
+//! PDU is serialized directly into that memory and then committed to the queue. If the grant is
+//! dropped without being committed, nothing is enqueued. `Producer::produce_with` wraps this in a
+//! closure-based interface, which is what the L2CAP layer uses to encode its responses, so they
+//! are written right into the queue instead of being built in a stack buffer first.
+//!
+//! # Control PDU Priority
+//!
+//! Many LL Control procedures have deadlines: A connection or channel map update has to be
+//! acknowledged before its *instant*, and the master expects responses within 40 seconds. With a
+//! plain FIFO, LL Control PDUs enqueued by the `Responder` are sent after all L2CAP data that was
+//! queued before them, which can take a long time when the master is slow to acknowledge packets.
+//! The `PriorityQueue` avoids this: The Link-Layer picks LL Control PDUs before any data PDU.
 //!
 //! [`PacketQueue`]: trait.PacketQueue.html
 //! [`Producer`]: trait.Producer.html
@@ -18,6 +37,9 @@
 //! [`SimpleQueue`]: struct.SimpleQueue.html
 //! [`SimpleProducer`]: struct.SimpleProducer.html
 //! [`SimpleConsumer`]: struct.SimpleConsumer.html
+//! [`PriorityQueue`]: struct.PriorityQueue.html
+//! [`RingQueue`]: struct.RingQueue.html
+//! [`Grant`]: struct.Grant.html
 
 use {
     crate::{
@@ -29,8 +51,13 @@ use {
         Error,
     },
     byteorder::{ByteOrder, LittleEndian},
+    core::{
+        cell::UnsafeCell,
+        cmp,
+        sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
     heapless::{
-        consts::U1,
+        consts::U2,
         spsc::{self, MultiCore},
     },
 };
@@ -71,10 +98,19 @@ pub trait Producer {
     /// passed.
     fn free_space(&self) -> u8;
 
+    /// Reserves queue memory for a PDU with a payload of up to `payload_bytes`.
+    ///
+    /// *This is the only method that needs to be implemented.*
+    ///
+    /// Returns `Error::Eof` if there's not enough space in the queue, or `Error::InvalidLength` if
+    /// `payload_bytes` exceeds the largest payload the queue can ever hold. The returned `Grant`
+    /// must provide at least `payload_bytes` (and no more than 255) Bytes of payload space. Only
+    /// one grant can exist at a time.
+    fn grant(&mut self, payload_bytes: u8) -> Result<Grant<'_>, Error>;
+
     /// Enqueues a PDU with known size using a closure.
     ///
-    /// *This is an object-safe method complemented by its generic counterpart `produce_with`. Only
-    /// this method need to be implemented.*
+    /// *This is an object-safe method complemented by its generic counterpart `produce_with`.*
     ///
     /// This will check if `payload_bytes` are available in the queue, and bail with `Error::Eof` if
     /// not. If sufficient space is available, a `ByteWriter` with access to that space is
@@ -84,7 +120,14 @@ pub trait Producer {
         &mut self,
         payload_bytes: u8,
         f: &mut dyn FnMut(&mut ByteWriter<'_>) -> Result<Llid, Error>,
-    ) -> Result<(), Error>;
+    ) -> Result<(), Error> {
+        let mut grant = self.grant(payload_bytes)?;
+        let mut writer = ByteWriter::new(grant.payload_mut());
+        let free = writer.space_left();
+        let llid = f(&mut writer)?;
+        let used = free - writer.space_left();
+        grant.commit(llid, used as u8)
+    }
 
     /// Enqueues a PDU with known size using a closure.
     ///
@@ -107,8 +150,9 @@ pub trait Producer {
 
         let mut f = Some(f);
         let mut r = None;
-        self.produce_dyn(payload_bytes, &mut |bytes| {
-            let f = f.take().unwrap();
+        let produced = self.produce_dyn(payload_bytes, &mut |bytes| {
+            // `produce_dyn` calls this at most once
+            let f = f.take().ok_or(Error::InvalidValue)?;
             let result = f(bytes);
             if let Ok(llid) = result {
                 r = Some(Ok(()));
@@ -117,10 +161,70 @@ pub trait Producer {
                 r = Some(result.map(|_| ()));
                 Err(Error::InvalidValue)
             }
-        })
-        .ok();
+        });
+
+        match r {
+            // `f` succeeded, but the queue might still have refused the PDU
+            Some(Ok(())) => produced.map_err(E::from),
+            Some(Err(e)) => Err(e),
+            // The queue bailed out before calling `f`
+            None => Err(produced.err().unwrap_or(Error::Eof).into()),
+        }
+    }
+}
+
+/// Finishes a `Grant` by making the PDU written to it available to the consumer.
+///
+/// This is implemented by (parts of) queue producers.
+pub trait Commit {
+    /// Enqueues `pdu`, the data channel header followed by the payload.
+    ///
+    /// `pdu` is the start of the buffer the `Grant` was created with. Queues that grant their own
+    /// memory can ignore it, others have to copy it into the queue.
+    ///
+    /// Returns `Error::Eof` if the PDU can't be enqueued after all.
+    fn commit(&mut self, pdu: &[u8]) -> Result<(), Error>;
+}
+
+/// Queue memory reserved for a single PDU.
+///
+/// Obtained from `Producer::grant`. The payload is written to `payload_mut`, and then enqueued by
+/// calling `commit`. Dropping the grant without committing it leaves the queue unchanged.
+pub struct Grant<'a> {
+    /// Buffer for the 2-Byte header, followed by the payload.
+    buf: &'a mut [u8],
+    commit: &'a mut dyn Commit,
+}
+
+impl<'a> Grant<'a> {
+    /// Creates a grant for `buf`, which will be committed via `commit`.
+    ///
+    /// `buf` must have room for the 2-Byte data channel header and at least 1 payload Byte.
+    pub fn new(buf: &'a mut [u8], commit: &'a mut dyn Commit) -> Self {
+        assert!(buf.len() > 2);
+        Self { buf, commit }
+    }
 
-        r.unwrap()
+    /// Returns the payload buffer to write the PDU's payload to.
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        let len = self.buf.len().min(2 + 255);
+        self.buf.get_mut(2..len).unwrap_or_default()
+    }
+
+    /// Enqueues the PDU, consisting of the first `used` payload Bytes, with the given LLID.
+    ///
+    /// Returns `Error::InvalidLength` if `used` exceeds the payload buffer, or `Error::Eof` if the
+    /// queue refused the PDU after all (the PDU is discarded in both cases).
+    pub fn commit(self, llid: Llid, used: u8) -> Result<(), Error> {
+        let Grant { buf, commit } = self;
+        let pdu = buf
+            .get_mut(..2 + usize::from(used))
+            .ok_or(Error::InvalidLength)?;
+
+        let mut header = data::Header::new(llid);
+        header.set_payload_length(used);
+        LittleEndian::write_u16(pdu, header.to_u16());
+        commit.commit(pdu)
     }
 }
 
@@ -200,6 +304,19 @@ impl<T> Consume<T> {
             result,
         }
     }
+
+    /// Returns whether the packet should be consumed.
+    ///
+    /// This is useful for code that drives the stack without a `Consumer`, like a host talking to
+    /// an external controller.
+    pub fn should_consume(&self) -> bool {
+        self.consume
+    }
+
+    /// Returns the contained result.
+    pub fn into_result(self) -> Result<T, Error> {
+        self.result
+    }
 }
 
 /// A simple packet queue that can hold a single packet.
@@ -208,96 +325,262 @@ impl<T> Consume<T> {
 /// for other queue implementations.
 ///
 /// This queue also minimizes RAM usage: In addition to the raw buffer space, only minimal space is
-/// needed for housekeeping.
+/// needed for housekeeping. Grants point directly into the packet buffer, so PDUs are never
+/// copied.
 pub struct SimpleQueue {
-    inner: spsc::Queue<[u8; MIN_DATA_PDU_BUF], U1, u8, MultiCore>,
+    buf: UnsafeCell<[u8; MIN_DATA_PDU_BUF]>,
+    /// Whether `buf` contains a packet. Only the producer sets this, and only the consumer clears
+    /// it, so the buffer is always owned by exactly one of them.
+    full: AtomicBool,
 }
 
+// Safety: The `full` flag hands ownership of `buf` back and forth between the producer and the
+// consumer, with the atomic accesses ordering the buffer accesses.
+unsafe impl Sync for SimpleQueue {}
+
 impl SimpleQueue {
     /// Creates a new, empty queue.
     pub const fn new() -> Self {
         Self {
-            inner: spsc::Queue(heapless::i::Queue::u8()),
+            buf: UnsafeCell::new([0; MIN_DATA_PDU_BUF]),
+            full: AtomicBool::new(false),
         }
     }
 }
 
+impl Default for SimpleQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<'a> PacketQueue for &'a mut SimpleQueue {
     type Producer = SimpleProducer<'a>;
 
     type Consumer = SimpleConsumer<'a>;
 
     fn split(self) -> (Self::Producer, Self::Consumer) {
-        let (p, c) = self.inner.split();
-        (SimpleProducer { inner: p }, SimpleConsumer { inner: c })
+        let queue = &*self;
+        (SimpleProducer { queue }, SimpleConsumer { queue })
     }
 }
 
 /// Producer (writer) half returned by `SimpleQueue::split`.
 pub struct SimpleProducer<'a> {
-    inner: spsc::Producer<'a, [u8; MIN_DATA_PDU_BUF], U1, u8, MultiCore>,
+    queue: &'a SimpleQueue,
+}
+
+impl Commit for &'_ SimpleQueue {
+    fn commit(&mut self, _pdu: &[u8]) -> Result<(), Error> {
+        // The PDU was written in place
+        self.full.store(true, Ordering::Release);
+        Ok(())
+    }
 }
 
 impl<'a> Producer for SimpleProducer<'a> {
     fn free_space(&self) -> u8 {
         // We can only have space for either 0 or 1 packets with min. payload size
-        if self.inner.ready() {
-            MIN_DATA_PAYLOAD_BUF as u8
-        } else {
+        if self.queue.full.load(Ordering::Acquire) {
             0
+        } else {
+            MIN_DATA_PAYLOAD_BUF as u8
         }
     }
 
-    fn produce_dyn(
-        &mut self,
-        payload_bytes: u8,
-        f: &mut dyn FnMut(&mut ByteWriter<'_>) -> Result<Llid, Error>,
-    ) -> Result<(), Error> {
-        assert!(usize::from(payload_bytes) <= MIN_DATA_PAYLOAD_BUF);
+    fn grant(&mut self, payload_bytes: u8) -> Result<Grant<'_>, Error> {
+        if usize::from(payload_bytes) > MIN_DATA_PAYLOAD_BUF {
+            return Err(Error::InvalidLength);
+        }
 
-        if !self.inner.ready() {
+        if self.queue.full.load(Ordering::Acquire) {
             return Err(Error::Eof);
         }
 
-        let mut buf = [0; MIN_DATA_PDU_BUF];
-        let mut writer = ByteWriter::new(&mut buf[2..]);
-        let free = writer.space_left();
-        let llid = f(&mut writer)?;
-        let used = free - writer.space_left();
-
-        let mut header = data::Header::new(llid);
-        header.set_payload_length(used as u8);
-        LittleEndian::write_u16(&mut buf, header.to_u16());
-
-        self.inner.enqueue(buf).map_err(|_| ()).unwrap();
-        Ok(())
+        // Safety: The buffer is empty, so the consumer doesn't access it. The `&mut self` borrow
+        // of the grant prevents creating a second one.
+        let buf = unsafe { &mut *self.queue.buf.get() };
+        Ok(Grant::new(buf, &mut self.queue))
     }
 }
 
 /// Consumer (reader) half returned by `SimpleQueue::split`.
 pub struct SimpleConsumer<'a> {
-    inner: spsc::Consumer<'a, [u8; MIN_DATA_PDU_BUF], U1, u8, MultiCore>,
+    queue: &'a SimpleQueue,
 }
 
 impl<'a> Consumer for SimpleConsumer<'a> {
     fn has_data(&self) -> bool {
-        self.inner.ready()
+        self.queue.full.load(Ordering::Acquire)
     }
 
     fn consume_raw_with<R>(
         &mut self,
         f: impl FnOnce(data::Header, &[u8]) -> Consume<R>,
     ) -> Result<R, Error> {
-        if let Some(packet) = self.inner.peek() {
+        if !self.queue.full.load(Ordering::Acquire) {
+            return Err(Error::Eof);
+        }
+
+        // Safety: The buffer is full, so the producer doesn't access it until we clear the flag.
+        let packet = unsafe { &*self.queue.buf.get() };
+        let mut bytes = ByteReader::new(packet);
+        let raw_header: [u8; 2] = bytes.read_array()?;
+        let header = data::Header::parse(&raw_header);
+        let pl_len = usize::from(header.payload_length());
+        let raw_payload = bytes.read_slice(pl_len)?;
+
+        let res = f(header, raw_payload);
+        if res.consume {
+            self.queue.full.store(false, Ordering::Release);
+        }
+        res.result
+    }
+}
+
+/// A packet queue with a separate lane for LL Control PDUs.
+///
+/// Holds up to 2 LL Control PDUs and 2 data PDUs. The consumer always yields LL Control PDUs
+/// first, so they overtake any L2CAP data waiting in the queue.
+///
+/// `Producer::free_space` only reports free space when both lanes have room, so that any PDU can
+/// be enqueued afterwards. LL Control PDUs can still be enqueued when the data lane is full.
+///
+/// The lane of a PDU is only known once it is committed, so grants point to a staging buffer, from
+/// which the PDU is copied into its lane.
+pub struct PriorityQueue {
+    control: spsc::Queue<[u8; MIN_DATA_PDU_BUF], U2, u8, MultiCore>,
+    data: spsc::Queue<[u8; MIN_DATA_PDU_BUF], U2, u8, MultiCore>,
+}
+
+impl PriorityQueue {
+    /// Creates a new, empty queue.
+    pub const fn new() -> Self {
+        Self {
+            control: spsc::Queue(heapless::i::Queue::u8()),
+            data: spsc::Queue(heapless::i::Queue::u8()),
+        }
+    }
+}
+
+impl Default for PriorityQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> PacketQueue for &'a mut PriorityQueue {
+    type Producer = PriorityProducer<'a>;
+
+    type Consumer = PriorityConsumer<'a>;
+
+    fn split(self) -> (Self::Producer, Self::Consumer) {
+        let (control_p, control_c) = self.control.split();
+        let (data_p, data_c) = self.data.split();
+        (
+            PriorityProducer {
+                lanes: Lanes {
+                    control: control_p,
+                    data: data_p,
+                },
+                staging: [0; MIN_DATA_PDU_BUF],
+            },
+            PriorityConsumer {
+                control: control_c,
+                data: data_c,
+            },
+        )
+    }
+}
+
+/// Producer (writer) half returned by `PriorityQueue::split`.
+pub struct PriorityProducer<'a> {
+    lanes: Lanes<'a>,
+    staging: [u8; MIN_DATA_PDU_BUF],
+}
+
+/// Producing ends of both lanes of a `PriorityQueue`.
+struct Lanes<'a> {
+    control: spsc::Producer<'a, [u8; MIN_DATA_PDU_BUF], U2, u8, MultiCore>,
+    data: spsc::Producer<'a, [u8; MIN_DATA_PDU_BUF], U2, u8, MultiCore>,
+}
+
+impl Commit for Lanes<'_> {
+    /// Copies `pdu` into the lane matching its LLID.
+    fn commit(&mut self, pdu: &[u8]) -> Result<(), Error> {
+        let header = data::Header::parse(pdu);
+        let lane = if header.llid() == Llid::Control {
+            &mut self.control
+        } else {
+            &mut self.data
+        };
+
+        let mut buf = [0; MIN_DATA_PDU_BUF];
+        buf.get_mut(..pdu.len())
+            .ok_or(Error::InvalidLength)?
+            .copy_from_slice(pdu);
+        lane.enqueue(buf).map_err(|_| Error::Eof)
+    }
+}
+
+impl<'a> Producer for PriorityProducer<'a> {
+    fn free_space(&self) -> u8 {
+        if self.lanes.control.ready() && self.lanes.data.ready() {
+            MIN_DATA_PAYLOAD_BUF as u8
+        } else {
+            0
+        }
+    }
+
+    /// Reserves space for a PDU.
+    ///
+    /// This succeeds if either lane has room. Committing the grant returns `Error::Eof` if the
+    /// lane of the PDU turns out to be full.
+    fn grant(&mut self, payload_bytes: u8) -> Result<Grant<'_>, Error> {
+        if usize::from(payload_bytes) > MIN_DATA_PAYLOAD_BUF {
+            return Err(Error::InvalidLength);
+        }
+
+        if !self.lanes.control.ready() && !self.lanes.data.ready() {
+            return Err(Error::Eof);
+        }
+
+        Ok(Grant::new(&mut self.staging, &mut self.lanes))
+    }
+}
+
+/// Consumer (reader) half returned by `PriorityQueue::split`.
+pub struct PriorityConsumer<'a> {
+    control: spsc::Consumer<'a, [u8; MIN_DATA_PDU_BUF], U2, u8, MultiCore>,
+    data: spsc::Consumer<'a, [u8; MIN_DATA_PDU_BUF], U2, u8, MultiCore>,
+}
+
+impl<'a> Consumer for PriorityConsumer<'a> {
+    fn has_data(&self) -> bool {
+        self.control.ready() || self.data.ready()
+    }
+
+    fn consume_raw_with<R>(
+        &mut self,
+        f: impl FnOnce(data::Header, &[u8]) -> Consume<R>,
+    ) -> Result<R, Error> {
+        let lane = if self.control.ready() {
+            &mut self.control
+        } else {
+            &mut self.data
+        };
+
+        if let Some(packet) = lane.peek() {
             let mut bytes = ByteReader::new(packet);
-            let raw_header: [u8; 2] = bytes.read_array().unwrap();
+            let raw_header: [u8; 2] = bytes.read_array()?;
             let header = data::Header::parse(&raw_header);
             let pl_len = usize::from(header.payload_length());
             let raw_payload = bytes.read_slice(pl_len)?;
 
             let res = f(header, raw_payload);
             if res.consume {
-                self.inner.dequeue().unwrap(); // can't fail
+                // Removes `packet`, which was just peeked
+                lane.dequeue();
             }
             res.result
         } else {
@@ -306,6 +589,163 @@ impl<'a> Consumer for SimpleConsumer<'a> {
     }
 }
 
+/// A packet queue that can hold `N` packets of up to `PDU` Bytes (including the 2-Byte header).
+///
+/// Like `SimpleQueue`, this only needs atomic loads and stores, so it works on thumbv6 cores, and
+/// grants point directly into the packet slots. Each slot takes `PDU` Bytes, so the depth can be
+/// chosen to trade RAM for throughput: A deeper TX queue lets the application queue up more
+/// notifications between connection events, a deeper RX queue gives it more time to process
+/// incoming data before the Link-Layer has to NACK packets.
+///
+/// `N` must not be 0, and `PDU` must be at least `MIN_DATA_PDU_BUF` (the default), since that's
+/// the packet size every peer may send. Both are checked at compile time. Larger slots only pay off
+/// once the Link-Layer supports the Data Length Extension; until then, each slot should be the
+/// default size.
+pub struct RingQueue<const N: usize, const PDU: usize = MIN_DATA_PDU_BUF> {
+    slots: UnsafeCell<[[u8; PDU]; N]>,
+    /// Number of packets ever dequeued (wrapping). Only written by the consumer.
+    head: AtomicUsize,
+    /// Number of packets ever enqueued (wrapping). Only written by the producer.
+    tail: AtomicUsize,
+}
+
+// Safety: A slot is owned by the producer while it is outside of `head..tail`, and by the consumer
+// while it is inside. The atomic index updates order the slot accesses.
+unsafe impl<const N: usize, const PDU: usize> Sync for RingQueue<N, PDU> {}
+
+impl<const N: usize, const PDU: usize> RingQueue<N, PDU> {
+    /// Fails to compile if `N` or `PDU` is out of range (once the queue is used).
+    const VALID: () = {
+        assert!(N > 0, "RingQueue must have room for at least 1 packet");
+        assert!(
+            PDU >= MIN_DATA_PDU_BUF,
+            "RingQueue slots must fit a PDU with a 27-Byte payload"
+        );
+    };
+
+    /// Creates a new, empty queue.
+    pub const fn new() -> Self {
+        let () = Self::VALID;
+        Self {
+            slots: UnsafeCell::new([[0; PDU]; N]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Largest payload that fits into a slot.
+    fn max_payload(&self) -> usize {
+        // The payload length in the header is 8 bits
+        cmp::min(PDU - 2, usize::from(u8::MAX))
+    }
+
+    /// Returns a pointer to the slot used for the `index`th packet.
+    fn slot(&self, index: usize) -> *mut [u8; PDU] {
+        // Safety: `index % N` is in bounds. No reference to the whole array is created, so the
+        // producer and consumer can access different slots at the same time.
+        unsafe { (self.slots.get() as *mut [u8; PDU]).add(index % N) }
+    }
+
+    fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Acquire);
+        tail.wrapping_sub(self.head.load(Ordering::Acquire))
+    }
+}
+
+impl<const N: usize, const PDU: usize> Default for RingQueue<N, PDU> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, const N: usize, const PDU: usize> PacketQueue for &'a mut RingQueue<N, PDU> {
+    type Producer = RingProducer<'a, N, PDU>;
+
+    type Consumer = RingConsumer<'a, N, PDU>;
+
+    fn split(self) -> (Self::Producer, Self::Consumer) {
+        let queue = &*self;
+        (RingProducer { queue }, RingConsumer { queue })
+    }
+}
+
+/// Producer (writer) half returned by `RingQueue::split`.
+pub struct RingProducer<'a, const N: usize, const PDU: usize = MIN_DATA_PDU_BUF> {
+    queue: &'a RingQueue<N, PDU>,
+}
+
+impl<const N: usize, const PDU: usize> Commit for &'_ RingQueue<N, PDU> {
+    fn commit(&mut self, _pdu: &[u8]) -> Result<(), Error> {
+        // The PDU was written in place
+        let tail = self.tail.load(Ordering::Relaxed);
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+}
+
+impl<'a, const N: usize, const PDU: usize> Producer for RingProducer<'a, N, PDU> {
+    fn free_space(&self) -> u8 {
+        // Report space for a single packet, like the other queues
+        if self.queue.len() < N {
+            self.queue.max_payload() as u8
+        } else {
+            0
+        }
+    }
+
+    fn grant(&mut self, payload_bytes: u8) -> Result<Grant<'_>, Error> {
+        if usize::from(payload_bytes) > self.queue.max_payload() {
+            return Err(Error::InvalidLength);
+        }
+
+        if self.queue.len() >= N {
+            return Err(Error::Eof);
+        }
+
+        // Safety: The slot after the last packet isn't accessed by the consumer. The `&mut self`
+        // borrow of the grant prevents creating a second one.
+        let buf = unsafe { &mut *self.queue.slot(self.queue.tail.load(Ordering::Relaxed)) };
+        Ok(Grant::new(buf, &mut self.queue))
+    }
+}
+
+/// Consumer (reader) half returned by `RingQueue::split`.
+pub struct RingConsumer<'a, const N: usize, const PDU: usize = MIN_DATA_PDU_BUF> {
+    queue: &'a RingQueue<N, PDU>,
+}
+
+impl<'a, const N: usize, const PDU: usize> Consumer for RingConsumer<'a, N, PDU> {
+    fn has_data(&self) -> bool {
+        self.queue.len() > 0
+    }
+
+    fn consume_raw_with<R>(
+        &mut self,
+        f: impl FnOnce(data::Header, &[u8]) -> Consume<R>,
+    ) -> Result<R, Error> {
+        if !self.has_data() {
+            return Err(Error::Eof);
+        }
+
+        // Safety: The oldest packet isn't accessed by the producer until `head` is advanced.
+        let head = self.queue.head.load(Ordering::Relaxed);
+        let packet = unsafe { &*self.queue.slot(head) };
+        let mut bytes = ByteReader::new(packet);
+        let raw_header: [u8; 2] = bytes.read_array()?;
+        let header = data::Header::parse(&raw_header);
+        let pl_len = usize::from(header.payload_length());
+        let raw_payload = bytes.read_slice(pl_len)?;
+
+        let res = f(header, raw_payload);
+        if res.consume {
+            self.queue
+                .head
+                .store(head.wrapping_add(1), Ordering::Release);
+        }
+        res.result
+    }
+}
+
 /// Runs Rubble's packet queue testsuite against the given `PacketQueue`.
 ///
 /// This can be used when implementing your own packet queue. Simply create a `#[test]` function as
@@ -317,6 +757,16 @@ impl<'a> Consumer for SimpleConsumer<'a> {
 ///
 /// Simultaneously, this function ensures that `PacketQueue` implementors can be created and used by
 /// a generic function, something that sometimes doesn't work when invariant lifetimes are involved.
+#[cfg_attr(
+    feature = "deny-panics",
+    allow(
+        clippy::expect_used,
+        clippy::indexing_slicing,
+        clippy::panic,
+        clippy::unreachable,
+        clippy::unwrap_used
+    )
+)]
 pub fn run_tests(queue: impl PacketQueue) {
     fn assert_empty(c: &mut impl Consumer) {
         assert!(!c.has_data(), "empty queue `has_data()` returned true");
@@ -357,14 +807,18 @@ pub fn run_tests(queue: impl PacketQueue) {
         MIN_DATA_PAYLOAD_BUF
     );
 
+    // Queues with larger buffers hand out all of their space, not just the minimum
+    let max_payload = usize::from(free_space);
+    let zeros = &[0; 255][..max_payload];
+
     // Enqueue the largest packet
-    p.produce_with(MIN_DATA_PAYLOAD_BUF as u8, |writer| -> Result<_, Error> {
+    p.produce_with(free_space, |writer| -> Result<_, Error> {
         assert_eq!(
             writer.space_left(),
-            MIN_DATA_PAYLOAD_BUF,
+            max_payload,
             "produce_with didn't pass ByteWriter with correct buffer"
         );
-        writer.write_slice(&[0; MIN_DATA_PAYLOAD_BUF]).unwrap();
+        writer.write_slice(zeros).unwrap();
         Ok(Llid::DataStart)
     })
     .expect("enqueuing packet failed");
@@ -376,12 +830,8 @@ pub fn run_tests(queue: impl PacketQueue) {
 
     // Peek at the packet
     c.consume_raw_with(|header, data| -> Consume<()> {
-        assert_eq!(usize::from(header.payload_length()), MIN_DATA_PAYLOAD_BUF);
-        assert_eq!(
-            data,
-            &[0; MIN_DATA_PAYLOAD_BUF][..],
-            "consume_raw_with didn't yield correct payload"
-        );
+        assert_eq!(usize::from(header.payload_length()), max_payload);
+        assert_eq!(data, zeros, "consume_raw_with didn't yield correct payload");
         Consume::never(Ok(()))
     })
     .expect("consume_raw_with failed when data is available");
@@ -393,7 +843,7 @@ pub fn run_tests(queue: impl PacketQueue) {
 
     // Now consume it
     c.consume_pdu_with(|header, _| -> Consume<()> {
-        assert_eq!(usize::from(header.payload_length()), MIN_DATA_PAYLOAD_BUF);
+        assert_eq!(usize::from(header.payload_length()), max_payload);
         Consume::always(Ok(()))
     })
     .expect("consume_pdu_with failed when data is available");
@@ -405,7 +855,7 @@ pub fn run_tests(queue: impl PacketQueue) {
     p.produce_with(0, |writer| -> Result<_, Error> {
         assert_eq!(
             writer.space_left(),
-            MIN_DATA_PAYLOAD_BUF,
+            max_payload,
             "produce_with didn't pass ByteWriter with correct buffer"
         );
         Ok(Llid::DataStart)
@@ -451,3 +901,71 @@ pub fn run_tests(queue: impl PacketQueue) {
 fn simple_queue() {
     run_tests(&mut SimpleQueue::new());
 }
+
+#[test]
+fn priority_queue() {
+    run_tests(&mut PriorityQueue::new());
+
+    let mut queue = PriorityQueue::new();
+    let (mut p, mut c) = (&mut queue).split();
+    for llid in &[Llid::DataStart, Llid::DataStart, Llid::Control] {
+        p.produce_with(1, |writer| -> Result<_, Error> {
+            writer.write_u8(0)?;
+            Ok(*llid)
+        })
+        .unwrap();
+    }
+    assert_eq!(p.free_space(), 0);
+
+    let first = c.consume_raw_with(|header, _| Consume::always(Ok(header.llid())));
+    assert_eq!(first, Ok(Llid::Control));
+}
+
+#[test]
+fn ring_queue() {
+    run_tests(&mut RingQueue::<1>::new());
+
+    let mut queue = RingQueue::<3>::new();
+    let (mut p, mut c) = (&mut queue).split();
+    for round in 0..2 {
+        for i in 0..3 {
+            p.produce_with(1, |writer| -> Result<_, Error> {
+                writer.write_u8(round * 3 + i)?;
+                Ok(Llid::DataStart)
+            })
+            .unwrap();
+        }
+        assert_eq!(p.free_space(), 0);
+
+        for i in 0..3 {
+            let payload = c.consume_raw_with(|_, pl| Consume::always(Ok(pl[0])));
+            assert_eq!(payload, Ok(round * 3 + i));
+        }
+        assert!(!c.has_data());
+    }
+
+    run_tests(&mut RingQueue::<2, 64>::new());
+    let mut queue = RingQueue::<1, 64>::new();
+    let (p, _) = (&mut queue).split();
+    assert_eq!(p.free_space(), 62);
+}
+
+#[test]
+fn grant() {
+    let mut queue = SimpleQueue::new();
+    let (mut p, mut c) = (&mut queue).split();
+
+    // Dropped grants don't enqueue anything
+    p.grant(1).unwrap().payload_mut()[0] = 0xAB;
+    assert!(!c.has_data());
+
+    // Payloads that can never fit are refused instead of panicking
+    assert_eq!(p.grant(255).err(), Some(Error::InvalidLength));
+
+    let mut grant = p.grant(1).unwrap();
+    grant.payload_mut()[0] = 0xCD;
+    grant.commit(Llid::DataStart, 1).unwrap();
+    assert_eq!(p.free_space(), 0);
+    let payload = c.consume_raw_with(|_, pl| Consume::always(Ok(pl[0])));
+    assert_eq!(payload, Ok(0xCD));
+}
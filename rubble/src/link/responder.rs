@@ -3,11 +3,14 @@ use crate::{
     config::Config,
     l2cap::{L2CAPState, L2CAPStateTx},
     link::{
+        channel_map::ChannelMap,
         data::{Llid, Pdu},
-        llcp::ControlPdu,
+        llcp::{ControlPdu, ErrorCode, Phys, SubrateRequest},
+        power_control,
         queue::{Consume, Consumer, Producer},
     },
-    utils::HexSlice,
+    security::PairingEvent,
+    utils::{Hex, HexSlice},
     Error,
 };
 
@@ -43,42 +46,46 @@ impl<C: Config> Responder<C> {
     /// Returns `true` when this responder has work to do.
     ///
     /// If this returns `true`, `process` may be called to process incoming packets and send
-    /// outgoing ones.
+    /// outgoing ones. This includes an ATT request that is waiting for authorization.
     pub fn has_work(&mut self) -> bool {
-        self.with_rx(|rx, _| rx.has_data())
+        self.l2cap.has_deferred() || self.with_rx(|rx, _| rx.has_data()).unwrap_or(false)
     }
 
     /// Processes a single incoming packet in the packet queue.
     ///
-    /// Returns `Error::Eof` if there are no incoming packets in the RX queue.
+    /// An ATT request waiting for authorization is processed again first, so it's answered before
+    /// later ATT PDUs.
+    ///
+    /// Returns `Error::Eof` if there are no incoming packets in the RX queue and no request is
+    /// waiting for authorization.
     pub fn process_one(&mut self) -> Result<(), Error> {
+        if self.l2cap.has_deferred() {
+            self.l2cap().process_deferred()?;
+            if !self.with_rx(|rx, _| rx.has_data()).unwrap_or(false) {
+                return Ok(());
+            }
+        }
+
         self.with_rx(|rx, this| {
             rx.consume_pdu_with(|_, pdu| match pdu {
                 Pdu::Control { data } => {
                     // Also see:
                     // https://github.com/jonas-schievink/rubble/issues/26
 
+                    // PDUs the real-time code understands (eg. `LL_FEATURE_REQ` and
+                    // `LL_VERSION_IND`) are answered there and never end up here.
                     let pdu = data.read();
                     info!("<- LL Control PDU: {:?}", pdu);
-                    let response = match pdu {
-                        // These PDUs are handled by the real-time code:
-                        ControlPdu::FeatureReq { .. } | ControlPdu::VersionInd { .. } => {
-                            unreachable!("LLCPDU not handled by LL");
-                        }
-                        _ => ControlPdu::UnknownRsp {
-                            unknown_type: pdu.opcode(),
-                        },
+                    let response = ControlPdu::UnknownRsp {
+                        unknown_type: pdu.opcode(),
                     };
                     info!("-> Response: {:?}", response);
 
                     // Consume the LL Control PDU iff we can fit the response in the TX buffer:
-                    Consume::on_success(this.tx.produce_with(
-                        response.encoded_size().into(),
-                        |writer| {
-                            response.to_bytes(writer)?;
-                            Ok(Llid::Control)
-                        },
-                    ))
+                    Consume::on_success(this.tx.produce_with(response.encoded_size(), |writer| {
+                        response.to_bytes(writer)?;
+                        Ok(Llid::Control)
+                    }))
                 }
                 Pdu::DataStart { message } => {
                     info!("L2start: {:?}", HexSlice(message));
@@ -90,6 +97,123 @@ impl<C: Config> Responder<C> {
                 }
             })
         })
+        .unwrap_or(Ok(()))
+    }
+
+    /// Enqueues an LL Control PDU to be sent to the master.
+    ///
+    /// Returns `Error::Eof` if there's not enough space in the TX queue.
+    pub fn send_control(&mut self, pdu: ControlPdu<'_>) -> Result<(), Error> {
+        info!("-> LL Control PDU: {:?}", pdu);
+        self.tx.produce_with(pdu.encoded_size(), |writer| {
+            pdu.to_bytes(writer)?;
+            Ok(Llid::Control)
+        })
+    }
+
+    /// Asks the master to end the connection, giving `reason` as the cause.
+    ///
+    /// This enqueues an `LL_TERMINATE_IND`. The Link-Layer returns to standby once the master has
+    /// acknowledged it (or when the supervision timeout elapses without an acknowledgement), and
+    /// reports `ErrorCode::ConnectionTerminatedByLocalHost` as the `disconnect_reason`. Data
+    /// enqueued after this call will not be sent anymore.
+    ///
+    /// The spec only allows a few error codes to be used for this, namely
+    /// `RemoteUserTerminatedConnection`, `RemoteDeviceTerminatedLowResources`,
+    /// `RemoteDeviceTerminatedPowerOff`, `AuthenticationFailure`, `UnsupportedRemoteFeature` and
+    /// `UnacceptableConnectionParameters`. `Error::InvalidValue` is returned for other codes, and
+    /// `Error::Eof` if there's not enough space in the TX queue.
+    pub fn disconnect(&mut self, reason: ErrorCode) -> Result<(), Error> {
+        match reason {
+            ErrorCode::RemoteUserTerminatedConnection
+            | ErrorCode::RemoteDeviceTerminatedLowResources
+            | ErrorCode::RemoteDeviceTerminatedPowerOff
+            | ErrorCode::AuthenticationFailure
+            | ErrorCode::UnsupportedRemoteFeature
+            | ErrorCode::UnacceptableConnectionParameters => {}
+            _ => return Err(Error::InvalidValue),
+        }
+
+        self.send_control(ControlPdu::TerminateInd {
+            error_code: Hex(reason.into()),
+        })
+    }
+
+    /// Informs the master that at least `min_used_channels` data channels must be used when
+    /// communicating via any of the `phys`.
+    ///
+    /// This can be used by devices that suffer from interference, or have a regulatory requirement
+    /// to use a minimum number of channels. The master is only required to honor this if it
+    /// supports the *Minimum Number of Used Channels Procedure* feature.
+    ///
+    /// Returns `Error::InvalidValue` if `min_used_channels` is not in range 2..=37, and
+    /// `Error::Eof` if there's not enough space in the TX queue.
+    pub fn indicate_min_used_channels(
+        &mut self,
+        phys: Phys,
+        min_used_channels: u8,
+    ) -> Result<(), Error> {
+        if !(2..=37).contains(&min_used_channels) || phys.is_empty() {
+            return Err(Error::InvalidValue);
+        }
+
+        self.send_control(ControlPdu::MinUsedChannelsInd {
+            phys,
+            min_used_channels,
+        })
+    }
+
+    /// Reports the host's channel classification to the master.
+    ///
+    /// `classification` marks the channels the host considers usable. The slave can't tell the
+    /// master which channels to avoid, so this sends an `LL_MIN_USED_CHANNELS_IND` asking the
+    /// master to use at least as many channels as are marked usable (but at least 2). A master
+    /// that classified more channels as bad than the host is then forced to add some back, instead
+    /// of concentrating all traffic on the few channels that are bad for this device.
+    ///
+    /// Returns `Error::InvalidValue` if `phys` is empty, and `Error::Eof` if there's not enough
+    /// space in the TX queue.
+    pub fn report_channel_classification(
+        &mut self,
+        phys: Phys,
+        classification: &ChannelMap,
+    ) -> Result<(), Error> {
+        let min = classification.num_used_channels().max(2);
+        self.indicate_min_used_channels(phys, min)
+    }
+
+    /// Asks the master to change its transmission power by `delta` dB.
+    ///
+    /// This enqueues an `LL_POWER_CONTROL_REQ`. The Link-Layer fills in our current transmission
+    /// power when sending it, and records the power the master responds with (see
+    /// `Connection::peer_tx_power`). Pass `power_control::DELTA_UNAVAILABLE` to only ask for the
+    /// master's power level without requesting a change.
+    ///
+    /// Returns `Error::Eof` if there's not enough space in the TX queue.
+    pub fn request_power_change(&mut self, delta: i8) -> Result<(), Error> {
+        self.send_control(ControlPdu::PowerControlReq {
+            phy: Phys::LE_1M,
+            delta,
+            tx_power: power_control::TX_POWER_UNAVAILABLE,
+        })
+    }
+
+    /// Asks the master to subrate the connection with the parameters in `request`.
+    ///
+    /// This keeps the connection interval (and thus the latency when data is exchanged), but lets
+    /// the radio skip most connection events while the link is idle. The master answers with an
+    /// `LL_SUBRATE_IND`, which is applied by the Link-Layer (see `Connection::subrate`).
+    ///
+    /// Returns `Error::Eof` if there's not enough space in the TX queue.
+    pub fn request_subrate(&mut self, request: SubrateRequest) -> Result<(), Error> {
+        self.send_control(ControlPdu::SubrateReq(request))
+    }
+
+    /// Returns the next event reported by the Security Manager, if any.
+    ///
+    /// This should be called after `process_one`.
+    pub fn pairing_event(&mut self) -> Option<PairingEvent> {
+        self.l2cap.security_manager().take_event()
     }
 
     /// Obtains access to the L2CAP instance.
@@ -101,10 +225,12 @@ impl<C: Config> Responder<C> {
     ///
     /// This can possibly be removed after *RFC 2229 (Closures Capture Disjoint Fields)* is
     /// implemented in stable Rust.
-    fn with_rx<R>(&mut self, f: impl FnOnce(&mut C::PacketConsumer, &mut Self) -> R) -> R {
-        let mut rx = self.rx.take().unwrap();
+    ///
+    /// Returns `None` without calling `f` when called from within `f`, since `rx` is taken then.
+    fn with_rx<R>(&mut self, f: impl FnOnce(&mut C::PacketConsumer, &mut Self) -> R) -> Option<R> {
+        let mut rx = self.rx.take()?;
         let result = f(&mut rx, self);
         self.rx = Some(rx);
-        result
+        Some(result)
     }
 }
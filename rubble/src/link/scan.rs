@@ -0,0 +1,248 @@
+//! Scanning for advertisements while advertising.
+//!
+//! A device that advertises can also act as an observer, listening for the advertisements of
+//! other devices. This is used for presence detection and by mesh-like protocols that relay
+//! broadcasts.
+//!
+//! While advertising, the Link-Layer normally listens on the channel it last advertised on, so it
+//! can answer scan and connect requests. `LinkLayer::start_scanning` additionally opens a *scan
+//! window* of `ScanParams::window` every `ScanParams::interval`, rotating through the advertising
+//! channels. During a window, the radio listens on the scan channel instead (except right after
+//! an advertising event, which always takes precedence), and every received advertisement carrying
+//! advertising data is passed to the `Observer` installed via `LinkLayer::set_observer`. The
+//! device stays connectable the whole time.
+//!
+//! Scanning is passive (no scan requests are sent) and pauses while a connection is established.
+//!
+//! The `Observer` is also told about the scan requests other devices send to the advertising sets,
+//! whether or not a scan is running.
+
+use crate::{
+    link::{
+        advertising::Pdu,
+        periodic::{PeriodicReport, SyncLost},
+        scheduler::{Activity, Reservation},
+        DeviceAddress,
+    },
+    phy::{AdvChannelMap, AdvertisingChannel},
+    time::{Duration, Instant},
+    Error,
+};
+
+/// Timing of scan windows.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ScanParams {
+    interval: Duration,
+    window: Duration,
+    channels: AdvChannelMap,
+}
+
+impl ScanParams {
+    /// Creates scan parameters that listen for `window` every `interval`.
+    ///
+    /// Returns `Error::InvalidValue` if `window` is zero or longer than `interval`, or if
+    /// `interval` is longer than the 10.24 seconds allowed by the spec.
+    pub fn new(interval: Duration, window: Duration) -> Result<Self, Error> {
+        if window.as_micros() == 0 || window > interval || interval > Duration::from_millis(10_240)
+        {
+            return Err(Error::InvalidValue);
+        }
+
+        Ok(Self {
+            interval,
+            window,
+            channels: AdvChannelMap::ALL,
+        })
+    }
+
+    /// Restricts scanning to `channels`, instead of rotating through all 3 advertising channels.
+    pub fn with_channels(mut self, channels: AdvChannelMap) -> Self {
+        self.channels = channels;
+        self
+    }
+
+    /// Returns the channels scan windows rotate through.
+    pub fn channels(&self) -> AdvChannelMap {
+        self.channels
+    }
+
+    /// Returns the time between the starts of 2 consecutive scan windows.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Returns the length of each scan window.
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+}
+
+/// A `SCAN_REQ` received by one of the advertising sets.
+#[derive(Debug, Copy, Clone)]
+pub struct ScanRequestReport {
+    /// Handle of the advertising set that was scanned.
+    pub set: u8,
+
+    /// Address of the scanning device.
+    pub scanner: DeviceAddress,
+
+    /// Received signal strength of the request in dBm, if the radio measured it.
+    pub rssi: Option<i8>,
+}
+
+/// Reception details of an advertisement passed to the `Observer`.
+#[derive(Debug, Copy, Clone)]
+pub struct AdvertisementInfo {
+    /// Instant at which the packet was fully received, as passed to the Link-Layer.
+    pub rx_end: Instant,
+
+    /// Precise instant at which the packet's access address was received, if the radio captures
+    /// it (see `Transmitter::rx_timestamp`).
+    pub timestamp: Option<Instant>,
+
+    /// Received signal strength of the packet in dBm, if the radio measured it.
+    pub rssi: Option<i8>,
+}
+
+/// Receives advertisements found while scanning, and the scan requests sent to us.
+pub trait Observer {
+    /// Called for every advertising channel PDU with advertising data (or scan response data) that
+    /// was received with a correct CRC during a scan window.
+    ///
+    /// This is called from the real-time Link-Layer code, so it should return quickly.
+    fn advertisement(&mut self, pdu: &Pdu<'_>);
+
+    /// Called instead of `advertisement` when the reception details of the PDU are available.
+    ///
+    /// Observers that need the timestamp or RSSI of advertisements can override this. The default
+    /// implementation discards `info` and calls `advertisement`.
+    fn advertisement_received(&mut self, pdu: &Pdu<'_>, info: &AdvertisementInfo) {
+        let _ = info;
+        self.advertisement(pdu);
+    }
+
+    /// Called after a scannable advertising set has answered a `SCAN_REQ` with its scan response.
+    ///
+    /// This tells the application which devices are looking at the advertisements, without them
+    /// having to connect. Like `advertisement`, this is called from real-time code.
+    ///
+    /// The default implementation ignores scan requests.
+    fn scan_request(&mut self, report: &ScanRequestReport) {
+        let _ = report;
+    }
+
+    /// Called by a `PeriodicSync` for every periodic advertisement it receives.
+    ///
+    /// The default implementation ignores periodic advertisements.
+    fn periodic_report(&mut self, report: &PeriodicReport<'_>) {
+        let _ = report;
+    }
+
+    /// Called when a `PeriodicSync` couldn't be established or timed out.
+    ///
+    /// The default implementation does nothing.
+    fn sync_lost(&mut self, lost: &SyncLost) {
+        let _ = lost;
+    }
+}
+
+/// Ignores all advertisements.
+impl Observer for () {
+    fn advertisement(&mut self, _: &Pdu<'_>) {}
+}
+
+/// Scan window scheduling state.
+pub(super) struct ScanSchedule {
+    params: ScanParams,
+    channel: AdvertisingChannel,
+    /// Start of the current or next window.
+    window_start: Instant,
+    active: bool,
+}
+
+impl ScanSchedule {
+    /// Creates a schedule whose first window starts at `start`.
+    pub(super) fn new(params: ScanParams, start: Instant) -> Self {
+        Self {
+            params,
+            channel: params.channels.first(),
+            window_start: start,
+            active: false,
+        }
+    }
+
+    pub(super) fn params(&self) -> ScanParams {
+        self.params
+    }
+
+    /// Returns whether a scan window is open.
+    pub(super) fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Advances the schedule to `now`.
+    ///
+    /// Returns the channel to scan on if a window is open at `now`.
+    pub(super) fn update(&mut self, now: Instant) -> Option<AdvertisingChannel> {
+        while reached(now, self.window_start + self.params.window) {
+            self.window_start += self.params.interval;
+            self.channel = self.channel.cycle_in(self.params.channels);
+        }
+
+        self.active = reached(now, self.window_start);
+        if self.active {
+            Some(self.channel)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the reservation of the current or next window.
+    pub(super) fn reservation(&self) -> Reservation {
+        Reservation::new(Activity::Scanning, self.window_start, self.params.window)
+    }
+}
+
+/// Returns whether `t` is not in the future, relative to `now`.
+fn reached(now: Instant, t: Instant) -> bool {
+    now.raw_micros().wrapping_sub(t.raw_micros()) <= Instant::MAX_TIME_BETWEEN.as_micros()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows() {
+        assert_eq!(
+            ScanParams::new(Duration::from_millis(10), Duration::from_millis(20)),
+            Err(Error::InvalidValue)
+        );
+
+        let params =
+            ScanParams::new(Duration::from_millis(100), Duration::from_millis(30)).unwrap();
+        let t0 = Instant::from_raw_micros(1_000);
+        let at = |ms: u16| t0 + Duration::from_millis(ms);
+        let ms = |t: Instant| (t - t0).whole_millis();
+        let rf = |ch: Option<AdvertisingChannel>| ch.map(|ch| ch.rf_channel());
+        let mut scan = ScanSchedule::new(params, at(10));
+
+        assert_eq!(rf(scan.update(t0)), None);
+        assert_eq!(ms(scan.reservation().start()), 10);
+
+        assert_eq!(rf(scan.update(at(15))), Some(0));
+        assert_eq!(ms(scan.reservation().end()), 40);
+
+        // Woken up late: The next window is on the next channel
+        assert_eq!(rf(scan.update(at(120))), Some(12));
+        assert_eq!(rf(scan.update(at(140))), None);
+        assert_eq!(ms(scan.reservation().start()), 210);
+
+        // Channel 38 is skipped
+        let channels = AdvChannelMap::from_bits(0b101).unwrap();
+        let mut scan = ScanSchedule::new(params.with_channels(channels), t0);
+        assert_eq!(rf(scan.update(t0)), Some(0));
+        assert_eq!(rf(scan.update(at(100))), Some(39));
+        assert_eq!(rf(scan.update(at(200))), Some(0));
+    }
+}
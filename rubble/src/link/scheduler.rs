@@ -0,0 +1,282 @@
+//! Arbitration of the radio between Link-Layer activities.
+//!
+//! Every activity that needs the radio (advertising events, scan windows, connection events and
+//! timeslots lent to the application) reserves its next use of it with the [`Scheduler`] owned by
+//! the `LinkLayer`. Whenever the timer fires, the Link-Layer asks the scheduler which activity owns
+//! the radio now and when that can change next, and configures the radio accordingly.
+//!
+//! Reservations can overlap. Conflicts are resolved by priority: The activity with the highest
+//! [`Activity::priority`] whose reservation has started wins, and ties go to the reservation that
+//! started first. Connection events are never preempted, since missing them too often ends the
+//! connection. Advertising events come next (delaying them only reduces the advertising rate), and
+//! scan windows are simply cut short.
+//!
+//! New reservations that must not be preempted (like timeslots) can check for conflicts with
+//! [`Scheduler::conflict`] before being made.
+//!
+//! Every connection of the `LinkLayer` reserves its events under the index of its connection slot,
+//! so there are at most `MAX_CONNECTIONS` connection reservations next to the other activities.
+//!
+//! [`Scheduler`]: struct.Scheduler.html
+//! [`Activity::priority`]: enum.Activity.html#method.priority
+//! [`Scheduler::conflict`]: struct.Scheduler.html#method.conflict
+
+use {
+    crate::time::{Duration, Instant},
+    core::iter,
+};
+
+/// Maximum number of reservations the scheduler can hold at once.
+///
+/// Every activity holds at most one reservation.
+pub const MAX_RESERVATIONS: usize = 8;
+
+/// Maximum number of connections that can hold reservations, next to advertising, scanning and a
+/// timeslot.
+pub const MAX_CONNECTIONS: usize = MAX_RESERVATIONS - 3;
+
+/// Time reserved for a connection event when its length isn't known.
+///
+/// Enough for exchanging a pair of empty packets (80 µs each, separated by the 150 µs IFS), with
+/// some margin.
+pub const MIN_CONNECTION_EVENT: Duration = Duration::from_micros(500);
+
+/// Something that needs the radio.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Activity {
+    /// Connection events of the connection with the given index.
+    Connection(u8),
+
+    /// Advertising events of all advertising sets.
+    Advertising,
+
+    /// A scan window.
+    Scanning,
+
+    /// A timeslot lent to the application.
+    Timeslot,
+}
+
+impl Activity {
+    /// Returns the priority of the activity. Activities with higher priority win conflicts.
+    pub fn priority(&self) -> u8 {
+        match self {
+            Activity::Connection(_) => 3,
+            Activity::Advertising => 2,
+            Activity::Timeslot => 1,
+            Activity::Scanning => 0,
+        }
+    }
+}
+
+/// A period of time during which an activity wants to use the radio.
+#[derive(Debug, Copy, Clone)]
+pub struct Reservation {
+    activity: Activity,
+    start: Instant,
+    length: Duration,
+}
+
+impl Reservation {
+    /// Creates a reservation of `length`, starting at `start`.
+    pub fn new(activity: Activity, start: Instant, length: Duration) -> Self {
+        Self {
+            activity,
+            start,
+            length,
+        }
+    }
+
+    /// Returns the activity this reservation is for.
+    pub fn activity(&self) -> Activity {
+        self.activity
+    }
+
+    /// Returns the time at which the reservation starts.
+    pub fn start(&self) -> Instant {
+        self.start
+    }
+
+    /// Returns the time at which the reservation is expected to end.
+    pub fn end(&self) -> Instant {
+        self.start + self.length
+    }
+
+    fn overlaps(&self, now: Instant, other: &Reservation) -> bool {
+        key(now, self.start) < key(now, other.end()) && key(now, other.start) < key(now, self.end())
+    }
+}
+
+/// The radio timeline, made up of the reservations of all activities.
+pub struct Scheduler {
+    reservations: [Option<Reservation>; MAX_RESERVATIONS],
+}
+
+impl Scheduler {
+    /// Creates a scheduler without any reservations.
+    pub fn new() -> Self {
+        Self {
+            reservations: [None; MAX_RESERVATIONS],
+        }
+    }
+
+    /// Adds a reservation, replacing the previous reservation of the same activity.
+    ///
+    /// Once started, a reservation stays in effect until it is replaced or released, even after
+    /// its expected end. If all `MAX_RESERVATIONS` slots are taken by other activities, the
+    /// reservation is dropped and `false` is returned.
+    pub fn reserve(&mut self, reservation: Reservation) -> bool {
+        let slot = self
+            .reservations
+            .iter()
+            .position(|r| r.is_some_and(|r| r.activity == reservation.activity))
+            .or_else(|| self.reservations.iter().position(Option::is_none))
+            .and_then(|i| self.reservations.get_mut(i));
+
+        match slot {
+            Some(slot) => {
+                *slot = Some(reservation);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes the reservation of `activity`, if any.
+    pub fn release(&mut self, activity: Activity) {
+        for slot in &mut self.reservations {
+            if slot.is_some_and(|r| r.activity == activity) {
+                *slot = None;
+            }
+        }
+    }
+
+    /// Returns the reservation of `activity`, if any.
+    pub fn get(&self, activity: Activity) -> Option<&Reservation> {
+        self.iter().find(|r| r.activity == activity)
+    }
+
+    /// Returns the activity that owns the radio at `now`.
+    ///
+    /// This is the activity with the highest priority among those whose reservations have started.
+    pub fn current(&self, now: Instant) -> Option<Activity> {
+        let now_key = key(now, now);
+        self.iter()
+            .filter(|r| key(now, r.start) <= now_key)
+            .max_by(|a, b| {
+                a.activity
+                    .priority()
+                    .cmp(&b.activity.priority())
+                    // Prefer the one that started first
+                    .then(key(now, b.start).cmp(&key(now, a.start)))
+            })
+            .map(|r| r.activity)
+    }
+
+    /// Returns the next time after `now` at which a reservation starts or is expected to end.
+    ///
+    /// This is when the Link-Layer has to reevaluate which activity owns the radio.
+    pub fn next_change(&self, now: Instant) -> Option<Instant> {
+        let now_key = key(now, now);
+        self.iter()
+            .flat_map(|r| iter::once(r.start).chain(iter::once(r.end())))
+            .filter(|t| key(now, *t) > now_key)
+            .min_by_key(|t| key(now, *t))
+    }
+
+    /// Returns an activity whose reservation overlaps `reservation` and doesn't have a lower
+    /// priority, if there is one.
+    ///
+    /// If this returns `None`, `reservation` will not be preempted.
+    pub fn conflict(&self, now: Instant, reservation: &Reservation) -> Option<Activity> {
+        self.iter()
+            .filter(|r| r.activity != reservation.activity)
+            .filter(|r| r.activity.priority() >= reservation.activity.priority())
+            .find(|r| r.overlaps(now, reservation))
+            .map(|r| r.activity)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Reservation> + '_ {
+        self.reservations.iter().filter_map(Option::as_ref)
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Maps `t` to a value that can be compared with the values of other `Instant`s near `now`.
+///
+/// Instants before `now` compare as earlier than `now`.
+fn key(now: Instant, t: Instant) -> u32 {
+    let base = now - Instant::MAX_TIME_BETWEEN;
+    t.raw_micros().wrapping_sub(base.raw_micros())
+}
+
+/// Returns the one of `a` and `b` that comes last.
+pub(super) fn latest(now: Instant, a: Instant, b: Instant) -> Instant {
+    if key(now, a) <= key(now, b) {
+        b
+    } else {
+        a
+    }
+}
+
+/// Returns the one of `a` and `b` that comes first.
+pub(super) fn earliest(now: Instant, a: Instant, b: Instant) -> Instant {
+    if key(now, a) <= key(now, b) {
+        a
+    } else {
+        b
+    }
+}
+
+/// Returns whether `t` is at or before `now`.
+pub(super) fn reached(now: Instant, t: Instant) -> bool {
+    key(now, t) <= key(now, now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arbitration() {
+        let t0 = Instant::from_raw_micros(500);
+        let at = |ms: u16| t0 + Duration::from_millis(ms);
+        let ms = |t: Instant| (t - t0).whole_millis();
+
+        let mut sched = Scheduler::new();
+        let scan = Reservation::new(Activity::Scanning, t0, Duration::from_millis(30));
+        assert!(sched.reserve(scan));
+        assert!(sched.reserve(Reservation::new(
+            Activity::Advertising,
+            at(10),
+            Duration::from_millis(2)
+        )));
+
+        assert_eq!(sched.current(t0), Some(Activity::Scanning));
+        assert_eq!(sched.next_change(t0).map(ms), Some(10));
+
+        // The advertising event preempts the scan window, which continues afterwards
+        assert_eq!(sched.current(at(10)), Some(Activity::Advertising));
+        sched.reserve(Reservation::new(
+            Activity::Advertising,
+            at(100),
+            Duration::from_millis(2),
+        ));
+        assert_eq!(sched.current(at(11)), Some(Activity::Scanning));
+        assert_eq!(sched.next_change(at(11)).map(ms), Some(30));
+
+        let slot = Reservation::new(Activity::Timeslot, at(40), Duration::from_millis(70));
+        assert_eq!(sched.conflict(at(40), &slot), Some(Activity::Advertising));
+        let slot = Reservation::new(Activity::Timeslot, at(40), Duration::from_millis(50));
+        assert_eq!(sched.conflict(at(40), &slot), None);
+
+        sched.release(Activity::Scanning);
+        assert_eq!(sched.current(at(11)), None);
+        assert!(sched.get(Activity::Scanning).is_none());
+    }
+}
@@ -0,0 +1,124 @@
+//! Link-Layer statistics.
+//!
+//! When the `stats` feature is enabled, the `LinkLayer` counts a number of events that indicate
+//! a bad radio environment or an overloaded application, like CRC errors and missed connection
+//! events. A snapshot of the counters can be obtained via `LinkLayer::stats` and then logged or
+//! exposed via a GATT characteristic.
+//!
+//! Without the feature, the counters are never incremented and always read as 0, so the
+//! real-time code doesn't pay for them.
+
+use crate::{
+    bytes::{ByteWriter, ToBytes},
+    Error,
+};
+
+/// Snapshot of the Link-Layer counters.
+///
+/// All counters count since the `LinkLayer` was created (or since the last `reset_stats` call),
+/// across all connections, and wrap around on overflow.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Number of connections that were established.
+    pub connections: u32,
+
+    /// Number of received data channel packets with a bad CRC.
+    pub crc_errors: u32,
+
+    /// Number of received packets that failed the MIC check of an encrypted connection.
+    ///
+    /// Rubble doesn't support Link-Layer encryption yet, so this stays 0 until it does.
+    pub mic_failures: u32,
+
+    /// Number of packets we had to retransmit because the master didn't acknowledge them.
+    pub retransmissions: u32,
+
+    /// Number of connection events in which no packet was received from the master.
+    pub missed_events: u32,
+
+    /// Number of received packets that were not acknowledged because the RX queue was full.
+    pub rx_queue_full: u32,
+
+    /// Number of connections lost because the supervision timeout expired.
+    pub supervision_timeouts: u32,
+}
+
+/// The individual counters in `Stats`.
+#[derive(Debug, Copy, Clone)]
+pub(crate) enum Counter {
+    Connection,
+    CrcError,
+    Retransmission,
+    MissedEvent,
+    RxQueueFull,
+    SupervisionTimeout,
+}
+
+impl Stats {
+    /// Encoded size of `Stats`, in Bytes.
+    pub const SIZE: usize = 7 * 4;
+
+    /// Increments `counter` by 1 (if statistics are enabled).
+    #[inline(always)]
+    pub(crate) fn record(&mut self, counter: Counter) {
+        #[cfg(feature = "stats")]
+        {
+            let value = match counter {
+                Counter::Connection => &mut self.connections,
+                Counter::CrcError => &mut self.crc_errors,
+                Counter::Retransmission => &mut self.retransmissions,
+                Counter::MissedEvent => &mut self.missed_events,
+                Counter::RxQueueFull => &mut self.rx_queue_full,
+                Counter::SupervisionTimeout => &mut self.supervision_timeouts,
+            };
+            *value = value.wrapping_add(1);
+        }
+
+        #[cfg(not(feature = "stats"))]
+        let _ = counter;
+    }
+}
+
+/// Encodes the counters as consecutive little-endian `u32`s, in declaration order.
+///
+/// This can be used as the value of a vendor-specific characteristic.
+impl ToBytes for Stats {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        for &value in &[
+            self.connections,
+            self.crc_errors,
+            self.mic_failures,
+            self.retransmissions,
+            self.missed_events,
+            self.rx_queue_full,
+            self.supervision_timeouts,
+        ] {
+            writer.write_u32_le(value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record() {
+        let mut stats = Stats::default();
+        stats.record(Counter::CrcError);
+        stats.record(Counter::CrcError);
+        stats.record(Counter::MissedEvent);
+
+        let expected = if cfg!(feature = "stats") {
+            (2, 1)
+        } else {
+            (0, 0)
+        };
+        assert_eq!((stats.crc_errors, stats.missed_events), expected);
+
+        let mut buf = [0; Stats::SIZE];
+        stats.to_bytes(&mut ByteWriter::new(&mut buf)).unwrap();
+        assert_eq!(buf[4], expected.0 as u8);
+    }
+}
@@ -0,0 +1,53 @@
+//! Lending the radio to other protocols between BLE activities.
+//!
+//! Applications that want to use the radio for something else (eg. IEEE 802.15.4, Nordic's
+//! Enhanced ShockBurst, or a proprietary protocol) can request a *timeslot* from the Link-Layer
+//! via `LinkLayer::request_timeslot`. A timeslot is only granted if it ends at least
+//! `TIMESLOT_GUARD` before the next scheduled advertising event or connection event anchor point,
+//! so BLE timing is never affected.
+//!
+//! While the timeslot is active, the application owns the radio. When it's done (and at the
+//! latest at `Timeslot::end`), it must call `LinkLayer::end_timeslot` and reconfigure the radio
+//! for BLE using the returned `Cmd`. No BLE packets are received during a timeslot, so
+//! advertising scan and connect requests sent by peers in that time are lost.
+//!
+//! The radio driver needs to support handing over the radio as well. `rubble-nrf52` provides
+//! `BleRadio::begin_timeslot` and `BleRadio::end_timeslot` for this.
+
+use crate::time::{Duration, Instant};
+
+/// Time reserved between the end of a timeslot and the next BLE activity.
+///
+/// This covers reconfiguring the radio for BLE and ramping it up.
+pub const TIMESLOT_GUARD: Duration = Duration::from_micros(1000);
+
+/// A period of time during which the radio may be used by the application.
+#[derive(Debug, Copy, Clone)]
+pub struct Timeslot {
+    start: Instant,
+    end: Instant,
+}
+
+impl Timeslot {
+    pub(super) fn new(start: Instant, length: Duration) -> Self {
+        Self {
+            start,
+            end: start + length,
+        }
+    }
+
+    /// Returns the time at which the timeslot was granted.
+    pub fn start(&self) -> Instant {
+        self.start
+    }
+
+    /// Returns the time at which the application must have returned the radio.
+    pub fn end(&self) -> Instant {
+        self.end
+    }
+
+    /// Returns the length of the timeslot.
+    pub fn length(&self) -> Duration {
+        self.end - self.start
+    }
+}
@@ -0,0 +1,78 @@
+//! Timing trace points on the real-time path.
+//!
+//! The Link-Layer has to respond to a packet 150 µs (the Inter Frame Space) after it was received.
+//! Whether that works out depends on the MCU, its clock speed, and the optimization settings the
+//! firmware is built with. To verify it, the `trace` feature makes Rubble call a user-provided hook
+//! at the points listed in [`TracePoint`]. The hook should do as little as possible, typically
+//! reading a cycle counter and writing a marker to an ITM stimulus port or RTT channel:
+//!
+//! ```ignore
+//! use rubble::link::trace::{self, TracePoint};
+//!
+//! fn hook(point: TracePoint) {
+//!     let cycles = cortex_m::peripheral::DWT::cycle_count();
+//!     rtt_target::rprintln!("{} {}", point as u8, cycles);
+//! }
+//!
+//! trace::set_hook(hook);
+//! ```
+//!
+//! [`TracePoint::RadioIrq`] has to be emitted by the radio driver, so drivers should call
+//! [`mark`] when their interrupt handler is entered. The other points are emitted by Rubble.
+//!
+//! Without the feature, `set_hook` does nothing and `mark` compiles to nothing.
+//!
+//! [`TracePoint`]: enum.TracePoint.html
+//! [`TracePoint::RadioIrq`]: enum.TracePoint.html#variant.RadioIrq
+//! [`mark`]: fn.mark.html
+
+#[cfg(feature = "trace")]
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Points on the packet processing path at which the trace hook is called.
+///
+/// In the order they occur during a connection event.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TracePoint {
+    /// The radio interrupt handler was entered.
+    RadioIrq = 0,
+
+    /// The received PDU has been parsed and processed by the Link-Layer.
+    PduParsed = 1,
+
+    /// The response has been written to the radio's TX buffer and is about to be sent.
+    ResponseReady = 2,
+}
+
+/// The installed hook, as a `fn(TracePoint)` cast to `usize` (or 0 if none is installed).
+#[cfg(feature = "trace")]
+static HOOK: AtomicUsize = AtomicUsize::new(0);
+
+/// Installs `hook` to be called at every trace point.
+///
+/// The hook runs in the radio interrupt, so it must be quick.
+pub fn set_hook(hook: fn(TracePoint)) {
+    #[cfg(feature = "trace")]
+    HOOK.store(hook as usize, Ordering::Relaxed);
+
+    #[cfg(not(feature = "trace"))]
+    let _ = hook;
+}
+
+/// Calls the trace hook with `point` (if the `trace` feature is enabled and a hook is installed).
+#[inline(always)]
+pub fn mark(point: TracePoint) {
+    #[cfg(feature = "trace")]
+    {
+        let hook = HOOK.load(Ordering::Relaxed);
+        if hook != 0 {
+            // Safety: `HOOK` only ever holds 0 or a valid `fn(TracePoint)`.
+            let hook: fn(TracePoint) = unsafe { core::mem::transmute(hook) };
+            hook(point);
+        }
+    }
+
+    #[cfg(not(feature = "trace"))]
+    let _ = point;
+}
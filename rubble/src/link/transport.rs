@@ -0,0 +1,382 @@
+//! Carrying the packet queues between `LinkLayer` and `Responder` over a link.
+//!
+//! Normally, both ends of the TX and RX queues live on the same MCU: the real-time `LinkLayer`
+//! talks to the queues from interrupt context, and the `Responder` processes them in the idle
+//! loop. Some applications want to run the two halves on different processors instead, for example
+//! the network and application cores of an nRF5340, or even a radio chip attached to a host MCU
+//! via SPI or UART.
+//!
+//! If both processors share memory, a queue that supports this (like `SimpleQueue`, which is built
+//! on a `MultiCore` SPSC queue) can be placed in shared RAM and no transport is needed. Otherwise,
+//! each side gets its own pair of queues, and this module moves the packets between them:
+//!
+//! * [`send`] drains a `Consumer`, encodes every packet as a frame and hands it to a
+//!   [`Transport`].
+//! * A [`Receiver`] reads bytes from a `Transport`, decodes frames and enqueues the contained
+//!   packets in a `Producer`.
+//!
+//! On the controller side, the `LinkLayer`'s TX queue is fed by a `Receiver` and its RX queue is
+//! drained with `send`, and the host side does the opposite for its `Responder`.
+//!
+//! # Frame format
+//!
+//! | Field   | Size  | Contents                                                       |
+//! |---------|-------|----------------------------------------------------------------|
+//! | Sync    | 1     | `0xA7` ([`SYNC`])                                              |
+//! | Header  | 2     | Data channel PDU header (LLID and payload length), LE          |
+//! | Payload | 0-255 | PDU payload                                                    |
+//! | CRC     | 3     | BLE CRC-24 over header and payload (preset [`CRC_PRESET`]), LE |
+//!
+//! Only the LLID and length of the header are meaningful; the `SN`, `NESN` and `MD` bits are
+//! managed by the link layer when the packet is sent over the air and are always 0 in a frame.
+//!
+//! Frames with a bad CRC are dropped, and the receiver searches the byte stream for the next sync
+//! byte. The transport is expected to be reliable otherwise: no frames are retransmitted, so a link
+//! that drops or corrupts data will lose packets just like an overflowing queue would.
+//!
+//! [`send`]: fn.send.html
+//! [`Transport`]: trait.Transport.html
+//! [`Receiver`]: struct.Receiver.html
+//! [`SYNC`]: constant.SYNC.html
+//! [`CRC_PRESET`]: constant.CRC_PRESET.html
+
+use {
+    crate::{
+        bytes::*,
+        crc::ble_crc24,
+        link::{
+            data::{self, Llid},
+            queue::{Consume, Consumer, Producer},
+        },
+        Error,
+    },
+    byteorder::{ByteOrder, LittleEndian},
+    core::cmp,
+};
+
+/// Byte starting every frame.
+pub const SYNC: u8 = 0xA7;
+
+/// CRC-24 preset used for frames.
+pub const CRC_PRESET: u32 = 0x555555;
+
+/// Size of a frame carrying an empty payload.
+pub const FRAME_OVERHEAD: usize = 1 + 2 + 3;
+
+/// Maximum size of an encoded frame.
+pub const MAX_FRAME: usize = FRAME_OVERHEAD + 255;
+
+/// A bidirectional, byte-oriented link to another processor.
+pub trait Transport {
+    /// Error reported by the link.
+    type Error;
+
+    /// Transmits `frame`.
+    ///
+    /// This is always called with a complete frame, so packet-oriented links (like SPI
+    /// transactions or IPC mailboxes) can send it as a unit.
+    fn send(&mut self, frame: &[u8]) -> Result<(), Self::Error>;
+
+    /// Reads received bytes into `buf`, returning how many Bytes were read.
+    ///
+    /// Must not block: If no data is available, this should return `Ok(0)`.
+    fn receive(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error>;
+}
+
+/// Encodes a packet as a frame.
+///
+/// Returns the number of Bytes written to `buf`.
+pub fn encode_frame(llid: Llid, payload: &[u8], buf: &mut [u8]) -> Result<usize, Error> {
+    if payload.len() > 255 {
+        return Err(Error::InvalidLength);
+    }
+
+    let mut header = data::Header::new(llid);
+    header.set_payload_length(payload.len() as u8);
+
+    let mut writer = ByteWriter::new(buf);
+    let free = writer.space_left();
+    writer.write_u8(SYNC)?;
+    writer.write_u16_le(header.to_u16())?;
+    writer.write_slice(payload)?;
+
+    let mut raw_header = [0; 2];
+    LittleEndian::write_u16(&mut raw_header, header.to_u16());
+    let crc = ble_crc24(&raw_header, CRC_PRESET);
+    let crc = ble_crc24(payload, crc);
+    writer.write_slice(&crc.to_le_bytes()[..3])?;
+
+    Ok(free - writer.space_left())
+}
+
+/// Sends all packets in `consumer` over `transport`.
+///
+/// A packet is only removed from the queue once the transport has accepted it. If the transport
+/// reports an error, the packet stays in the queue and the error is returned.
+pub fn send<C: Consumer, T: Transport>(
+    consumer: &mut C,
+    transport: &mut T,
+) -> Result<(), T::Error> {
+    while consumer.has_data() {
+        let result = consumer.consume_raw_with(|header, payload| {
+            let mut frame = [0; MAX_FRAME];
+            let len = match encode_frame(header.llid(), payload, &mut frame) {
+                Ok(len) => len,
+                Err(e) => return Consume::always(Err(e)),
+            };
+
+            match transport.send(frame.get(..len).unwrap_or_default()) {
+                Ok(()) => Consume::always(Ok(Ok(()))),
+                Err(e) => Consume::never(Ok(Err(e))),
+            }
+        });
+
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => return Err(e),
+            Err(e) => {
+                // Can only be a malformed packet, which is dropped
+                warn!("transport: dropping packet: {:?}", e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    /// Waiting for a sync byte.
+    Sync,
+    /// Receiving header and payload.
+    Data,
+    /// A complete frame is in the buffer, waiting to be enqueued.
+    Complete,
+}
+
+/// Decodes frames received over a `Transport` and enqueues the packets in a `Producer`.
+pub struct Receiver {
+    state: State,
+    /// Header, payload and CRC of the current frame.
+    frame: [u8; MAX_FRAME - 1],
+    /// Number of Bytes of `frame` received so far.
+    pos: usize,
+    /// Bytes read from the transport, but not yet decoded.
+    rx: [u8; 16],
+    rx_pos: usize,
+    rx_len: usize,
+    dropped: u32,
+}
+
+impl Receiver {
+    /// Creates a receiver waiting for the start of a frame.
+    pub fn new() -> Self {
+        Self {
+            state: State::Sync,
+            frame: [0; MAX_FRAME - 1],
+            pos: 0,
+            rx: [0; 16],
+            rx_pos: 0,
+            rx_len: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Returns the number of frames dropped because of a CRC mismatch.
+    pub fn dropped_frames(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Reads all available data from `transport` and enqueues the received packets.
+    ///
+    /// If `producer` runs out of space, the packet is kept and the remaining data is left in the
+    /// transport. The next call will try to enqueue it again, so this should be called again once
+    /// the consumer has made space (eg. after a connection event).
+    pub fn poll<T: Transport, P: Producer>(
+        &mut self,
+        transport: &mut T,
+        producer: &mut P,
+    ) -> Result<(), T::Error> {
+        loop {
+            if self.state == State::Complete && !self.enqueue(producer) {
+                return Ok(());
+            }
+
+            if self.rx_pos == self.rx_len {
+                self.rx_pos = 0;
+                self.rx_len = cmp::min(transport.receive(&mut self.rx)?, self.rx.len());
+                if self.rx_len == 0 {
+                    return Ok(());
+                }
+            }
+
+            while self.state != State::Complete {
+                let byte = match self
+                    .rx
+                    .get(self.rx_pos..self.rx_len)
+                    .and_then(<[u8]>::first)
+                {
+                    Some(&byte) => byte,
+                    None => break,
+                };
+                self.rx_pos += 1;
+                self.push(byte);
+            }
+        }
+    }
+
+    /// Feeds a single received byte into the frame decoder.
+    fn push(&mut self, byte: u8) {
+        match self.state {
+            State::Sync => {
+                if byte == SYNC {
+                    self.state = State::Data;
+                    self.pos = 0;
+                }
+            }
+            State::Data => {
+                match self.frame.get_mut(self.pos) {
+                    Some(slot) => *slot = byte,
+                    None => {
+                        self.state = State::Sync;
+                        return;
+                    }
+                }
+                self.pos += 1;
+                if self.pos >= 2 && self.pos == 2 + self.payload_len() + 3 {
+                    if self.crc_valid() {
+                        self.state = State::Complete;
+                    } else {
+                        debug!("transport: CRC mismatch, dropping frame");
+                        self.dropped = self.dropped.wrapping_add(1);
+                        self.state = State::Sync;
+                    }
+                }
+            }
+            State::Complete => {}
+        }
+    }
+
+    fn header(&self) -> data::Header {
+        data::Header::parse(&self.frame[..2])
+    }
+
+    fn payload_len(&self) -> usize {
+        usize::from(self.header().payload_length())
+    }
+
+    fn crc_valid(&self) -> bool {
+        let end = 2 + self.payload_len();
+        match (self.frame.get(..end), self.frame.get(end..end + 3)) {
+            (Some(data), Some(&[b0, b1, b2])) => {
+                ble_crc24(data, CRC_PRESET) == u32::from_le_bytes([b0, b1, b2, 0])
+            }
+            _ => false,
+        }
+    }
+
+    /// Tries to enqueue the completed frame. Returns `false` if the producer is full.
+    fn enqueue<P: Producer>(&mut self, producer: &mut P) -> bool {
+        let header = self.header();
+        let len = header.payload_length();
+        if producer.free_space() < len {
+            return false;
+        }
+
+        let payload = self.frame.get(2..2 + usize::from(len)).unwrap_or_default();
+        let result = producer.produce_with(len, |writer| -> Result<_, Error> {
+            writer.write_slice(payload)?;
+            Ok(header.llid())
+        });
+        if let Err(e) = result {
+            warn!("transport: dropping packet: {:?}", e);
+        }
+
+        self.state = State::Sync;
+        true
+    }
+}
+
+impl Default for Receiver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::queue::{PacketQueue, SimpleQueue};
+
+    /// Loops sent frames back, optionally corrupting a byte.
+    struct Loopback {
+        buf: [u8; 256],
+        len: usize,
+        read: usize,
+    }
+
+    impl Transport for Loopback {
+        type Error = ();
+
+        fn send(&mut self, frame: &[u8]) -> Result<(), ()> {
+            let end = self.len + frame.len();
+            if end > self.buf.len() {
+                return Err(());
+            }
+            self.buf[self.len..end].copy_from_slice(frame);
+            self.len = end;
+            Ok(())
+        }
+
+        fn receive(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+            let n = (self.len - self.read).min(buf.len());
+            buf[..n].copy_from_slice(&self.buf[self.read..self.read + n]);
+            self.read += n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut link = Loopback {
+            buf: [0; 256],
+            len: 0,
+            read: 0,
+        };
+        let mut tx = SimpleQueue::new();
+        let mut rx = SimpleQueue::new();
+        let (mut tx_p, mut tx_c) = tx.split();
+        let (mut rx_p, mut rx_c) = rx.split();
+        let mut receiver = Receiver::new();
+
+        for i in 0..3u8 {
+            tx_p.produce_with(3, |writer| -> Result<_, Error> {
+                writer.write_slice(&[i, 1, 2])?;
+                Ok(Llid::DataStart)
+            })
+            .unwrap();
+            send(&mut tx_c, &mut link).unwrap();
+        }
+
+        // Corrupt the payload of the second frame
+        link.buf[FRAME_OVERHEAD + 3 + 3] ^= 0xFF;
+
+        // `SimpleQueue` only holds one packet, so the third one stays pending
+        receiver.poll(&mut link, &mut rx_p).unwrap();
+        assert_eq!(receiver.dropped_frames(), 1);
+        let first = rx_c
+            .consume_raw_with(|header, payload| {
+                assert_eq!(header.llid(), Llid::DataStart);
+                Consume::always(Ok(payload[0]))
+            })
+            .unwrap();
+        assert_eq!(first, 0);
+
+        receiver.poll(&mut link, &mut rx_p).unwrap();
+        let third = rx_c
+            .consume_raw_with(|_, payload| Consume::always(Ok(payload[0])))
+            .unwrap();
+        assert_eq!(third, 2);
+        assert!(!rx_c.has_data());
+    }
+}
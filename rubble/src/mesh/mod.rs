@@ -0,0 +1,34 @@
+//! Bluetooth Mesh support.
+//!
+//! Mesh networks are built on top of non-connectable advertisements (the *advertising bearer*)
+//! and, for devices without mesh support, GATT connections (the *GATT bearer*). Rubble currently
+//! implements the foundations needed by an unprovisioned device: the unprovisioned device beacon
+//! and the [`pb_adv`] provisioning bearer. The provisioning protocol itself runs on top of it.
+//! Phones can reach a node via the [`proxy`] service.
+//!
+//! [`pb_adv`]: pb_adv/index.html
+//! [`proxy`]: proxy/index.html
+
+pub mod pb_adv;
+pub mod proxy;
+
+/// AD type of Mesh Beacons.
+pub const MESH_BEACON: u8 = 0x2B;
+
+/// Length of an unprovisioned device beacon without URI hash.
+pub const UNPROVISIONED_BEACON_LEN: usize = 19;
+
+/// Returns the *Unprovisioned Device* beacon announcing that the device can be provisioned.
+///
+/// Broadcast it as `AdStructure::Unknown` with type `MESH_BEACON`. `oob_info` describes the
+/// out-of-band information available to the provisioner (eg. a number printed on the device).
+pub fn unprovisioned_beacon(
+    device_uuid: &[u8; 16],
+    oob_info: u16,
+) -> [u8; UNPROVISIONED_BEACON_LEN] {
+    let mut beacon = [0; UNPROVISIONED_BEACON_LEN];
+    // Beacon type 0x00
+    beacon[1..17].copy_from_slice(device_uuid);
+    beacon[17..].copy_from_slice(&oob_info.to_be_bytes());
+    beacon
+}
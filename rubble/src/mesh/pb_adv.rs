@@ -0,0 +1,530 @@
+//! The PB-ADV provisioning bearer.
+//!
+//! PB-ADV carries provisioning PDUs in non-connectable advertisements with AD type `PB_ADV`. A
+//! provisioner opens a *link* to the device with a given UUID, and both sides then exchange
+//! provisioning PDUs as *transactions*: Each PDU is split into segments that fit into a single
+//! advertisement, and the receiver acknowledges the transaction once all segments have arrived.
+//! Unacknowledged transactions are retransmitted until they are acknowledged or time out.
+//!
+//! [`PbAdvBearer`] implements the device side of this *Generic Provisioning* layer. Its
+//! `process` method is fed with received PB-ADV AD structures, and `next_pdu` returns the next
+//! AD structure data to broadcast.
+//!
+//! [`PbAdvBearer`]: struct.PbAdvBearer.html
+
+use {
+    crate::{
+        bytes::ByteReader,
+        time::{Duration, Instant},
+        Error,
+    },
+    core::cmp,
+};
+
+/// AD type of PB-ADV PDUs.
+pub const PB_ADV: u8 = 0x29;
+
+/// Maximum length of the data of a PB-ADV AD structure.
+pub const MAX_AD_LEN: usize = 29;
+
+/// Maximum length of a provisioning PDU (the *Provisioning Public Key* PDU).
+pub const MAX_PDU_LEN: usize = 65;
+
+/// Data Bytes in the *Transaction Start* segment.
+const START_DATA_LEN: usize = 20;
+/// Data Bytes in each *Transaction Continuation* segment.
+const CONTINUATION_DATA_LEN: usize = 23;
+
+/// Time after which an unacknowledged transaction fails and the link is closed.
+const TRANSACTION_TIMEOUT: Duration = Duration::from_micros(30_000_000);
+/// Time between retransmissions of all segments of an unacknowledged transaction.
+const RETRANSMIT_INTERVAL: Duration = Duration::from_micros(500_000);
+/// Number of times *Link Close* is sent, since it isn't acknowledged.
+const LINK_CLOSE_REPEATS: u8 = 3;
+
+/// Transaction numbers used by the device (the provisioner uses `0x00..=0x7F`).
+const DEVICE_TRANSACTIONS: u8 = 0x80;
+
+const GPCF_START: u8 = 0b00;
+const GPCF_ACK: u8 = 0b01;
+const GPCF_CONTINUATION: u8 = 0b10;
+const GPCF_CONTROL: u8 = 0b11;
+
+const OP_LINK_OPEN: u8 = 0x00;
+const OP_LINK_ACK: u8 = 0x01;
+const OP_LINK_CLOSE: u8 = 0x02;
+
+enum_with_unknown! {
+    /// Why a link was closed.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum CloseReason(u8) {
+        /// Provisioning completed.
+        Success = 0x00,
+        /// A transaction was not acknowledged in time.
+        Timeout = 0x01,
+        /// Provisioning failed.
+        Fail = 0x02,
+    }
+}
+
+/// Events reported by `PbAdvBearer::process`.
+#[derive(Debug)]
+pub enum BearerEvent<'a> {
+    /// A provisioner opened a link to this device.
+    LinkOpened,
+    /// The provisioner closed the link.
+    LinkClosed(CloseReason),
+    /// A provisioning PDU was received.
+    Pdu(&'a [u8]),
+    /// The PDU passed to `PbAdvBearer::send` was acknowledged.
+    Sent,
+}
+
+/// A transaction being received.
+struct Reassembly {
+    number: Option<u8>,
+    /// Total length and FCS, known once the *Transaction Start* segment arrived.
+    start: Option<(usize, u8)>,
+    last_segment: u8,
+    /// Bitmap of received segments.
+    received: u64,
+    buf: [u8; MAX_PDU_LEN],
+}
+
+/// A transaction being sent.
+struct Transmission {
+    number: u8,
+    active: bool,
+    len: usize,
+    buf: [u8; MAX_PDU_LEN],
+    next_segment: u8,
+    started: Instant,
+    /// When the next round of segments may be sent.
+    resend_at: Option<Instant>,
+}
+
+/// Device side of the PB-ADV bearer.
+pub struct PbAdvBearer {
+    device_uuid: [u8; 16],
+    link: Option<u32>,
+    link_ack: bool,
+    /// Link, reason and remaining repetitions of a *Link Close* to send.
+    closing: Option<(u32, CloseReason, u8)>,
+    /// Number of a transaction to acknowledge.
+    ack: Option<u8>,
+    /// Number of the last completely received transaction, to answer retransmissions.
+    last_received: Option<u8>,
+    rx: Reassembly,
+    tx: Transmission,
+}
+
+impl PbAdvBearer {
+    /// Creates a bearer that accepts links to `device_uuid`.
+    pub fn new(device_uuid: [u8; 16]) -> Self {
+        Self {
+            device_uuid,
+            link: None,
+            link_ack: false,
+            closing: None,
+            ack: None,
+            last_received: None,
+            rx: Reassembly {
+                number: None,
+                start: None,
+                last_segment: 0,
+                received: 0,
+                buf: [0; MAX_PDU_LEN],
+            },
+            tx: Transmission {
+                number: DEVICE_TRANSACTIONS,
+                active: false,
+                len: 0,
+                buf: [0; MAX_PDU_LEN],
+                next_segment: 0,
+                started: Instant::from_raw_micros(0),
+                resend_at: None,
+            },
+        }
+    }
+
+    /// Returns whether a link is open.
+    pub fn is_open(&self) -> bool {
+        self.link.is_some()
+    }
+
+    /// Processes the data of a received PB-ADV AD structure.
+    pub fn process(&mut self, data: &[u8]) -> Option<BearerEvent<'_>> {
+        let mut bytes = ByteReader::new(data);
+        let link = u32::from_be_bytes(bytes.read_array().ok()?);
+        let number = bytes.read_u8().ok()?;
+        let header = bytes.read_u8().ok()?;
+        let payload = bytes.read_rest();
+
+        if header & 0b11 == GPCF_CONTROL {
+            return self.process_control(link, header >> 2, payload);
+        }
+        if self.link != Some(link) {
+            return None;
+        }
+
+        match header & 0b11 {
+            GPCF_ACK => {
+                if self.tx.active && number == self.tx.number {
+                    self.tx.active = false;
+                    self.tx.number = next_number(self.tx.number);
+                    return Some(BearerEvent::Sent);
+                }
+                None
+            }
+            GPCF_START | GPCF_CONTINUATION => {
+                if self.last_received == Some(number) {
+                    // The provisioner didn't see our ACK
+                    self.ack = Some(number);
+                    return None;
+                }
+                self.process_segment(number, header, payload)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Starts sending a provisioning PDU.
+    ///
+    /// Returns `Error::InvalidValue` if no link is open or the previous PDU hasn't been
+    /// acknowledged yet, and `Error::InvalidLength` if `pdu` is larger than `MAX_PDU_LEN`.
+    pub fn send(&mut self, now: Instant, pdu: &[u8]) -> Result<(), Error> {
+        if self.link.is_none() || self.tx.active {
+            return Err(Error::InvalidValue);
+        }
+        if pdu.is_empty() || pdu.len() > MAX_PDU_LEN {
+            return Err(Error::InvalidLength);
+        }
+
+        self.tx.buf[..pdu.len()].copy_from_slice(pdu);
+        self.tx.len = pdu.len();
+        self.tx.active = true;
+        self.tx.next_segment = 0;
+        self.tx.started = now;
+        self.tx.resend_at = None;
+        Ok(())
+    }
+
+    /// Closes the link.
+    ///
+    /// The device closes the link with `CloseReason::Fail` when provisioning fails. After
+    /// successful provisioning, it is the provisioner that closes the link.
+    pub fn close(&mut self, reason: CloseReason) {
+        if let Some(link) = self.link.take() {
+            self.closing = Some((link, reason, LINK_CLOSE_REPEATS));
+        }
+        self.tx.active = false;
+    }
+
+    /// Closes the link if the PDU passed to `send` hasn't been acknowledged in time.
+    ///
+    /// Returns whether the link was closed. This should be called regularly while a link is open.
+    pub fn check_timeout(&mut self, now: Instant) -> bool {
+        if self.tx.active && now.duration_since(self.tx.started) >= TRANSACTION_TIMEOUT {
+            self.close(CloseReason::Timeout);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Encodes the next PB-ADV PDU to broadcast into `buf`.
+    ///
+    /// The returned data should be advertised as `AdStructure::Unknown` with type `PB_ADV`. Returns
+    /// `None` if there is nothing to send right now.
+    pub fn next_pdu<'b>(
+        &mut self,
+        now: Instant,
+        buf: &'b mut [u8; MAX_AD_LEN],
+    ) -> Option<&'b [u8]> {
+        if let Some((link, reason, repeats)) = self.closing {
+            self.closing = if repeats > 1 {
+                Some((link, reason, repeats - 1))
+            } else {
+                None
+            };
+            let header = (OP_LINK_CLOSE << 2) | GPCF_CONTROL;
+            return Some(encode(buf, link, 0, &[header, reason.into()]));
+        }
+
+        let link = self.link?;
+        if self.link_ack {
+            self.link_ack = false;
+            return Some(encode(buf, link, 0, &[(OP_LINK_ACK << 2) | GPCF_CONTROL]));
+        }
+        if let Some(number) = self.ack.take() {
+            return Some(encode(buf, link, number, &[GPCF_ACK]));
+        }
+
+        if !self.tx.active || self.tx.resend_at.is_some_and(|at| !reached(now, at)) {
+            return None;
+        }
+        let last = last_segment(self.tx.len);
+        let segment = self.tx.next_segment;
+        let (start, end) = segment_range(segment, self.tx.len);
+        let data = &self.tx.buf[start..end];
+        let mut pdu = [0; MAX_AD_LEN - 5];
+        let len = if segment == 0 {
+            pdu[0] = (last << 2) | GPCF_START;
+            pdu[1..3].copy_from_slice(&(self.tx.len as u16).to_be_bytes());
+            pdu[3] = fcs(&self.tx.buf[..self.tx.len]);
+            pdu[4..4 + data.len()].copy_from_slice(data);
+            4 + data.len()
+        } else {
+            pdu[0] = (segment << 2) | GPCF_CONTINUATION;
+            pdu[1..1 + data.len()].copy_from_slice(data);
+            1 + data.len()
+        };
+
+        if segment == last {
+            self.tx.next_segment = 0;
+            self.tx.resend_at = Some(now + RETRANSMIT_INTERVAL);
+        } else {
+            self.tx.next_segment += 1;
+        }
+        Some(encode(buf, link, self.tx.number, &pdu[..len]))
+    }
+
+    fn process_control(
+        &mut self,
+        link: u32,
+        opcode: u8,
+        payload: &[u8],
+    ) -> Option<BearerEvent<'_>> {
+        match opcode {
+            OP_LINK_OPEN if payload == self.device_uuid => match self.link {
+                // Retransmitted because our ACK got lost
+                Some(open) if open == link => {
+                    self.link_ack = true;
+                    None
+                }
+                Some(_) => None,
+                None => {
+                    self.link = Some(link);
+                    self.link_ack = true;
+                    self.closing = None;
+                    self.ack = None;
+                    self.last_received = None;
+                    self.rx.number = None;
+                    self.tx.active = false;
+                    Some(BearerEvent::LinkOpened)
+                }
+            },
+            OP_LINK_CLOSE if self.link == Some(link) => {
+                self.link = None;
+                self.tx.active = false;
+                let reason = payload.first().map_or(CloseReason::Fail, |r| (*r).into());
+                Some(BearerEvent::LinkClosed(reason))
+            }
+            _ => None,
+        }
+    }
+
+    fn process_segment(
+        &mut self,
+        number: u8,
+        header: u8,
+        payload: &[u8],
+    ) -> Option<BearerEvent<'_>> {
+        let rx = &mut self.rx;
+        if rx.number != Some(number) {
+            rx.number = Some(number);
+            rx.start = None;
+            rx.received = 0;
+        }
+
+        let (segment, data) = if header & 0b11 == GPCF_START {
+            let mut bytes = ByteReader::new(payload);
+            let total = usize::from(u16::from_be_bytes(bytes.read_array().ok()?));
+            let expected_fcs = bytes.read_u8().ok()?;
+            if total == 0 || total > MAX_PDU_LEN || last_segment(total) != header >> 2 {
+                return None;
+            }
+            rx.start = Some((total, expected_fcs));
+            rx.last_segment = header >> 2;
+            (0, bytes.read_rest())
+        } else {
+            (header >> 2, payload)
+        };
+
+        // Place the data, as far as it fits (the total length might not be known yet)
+        let (start, _) = segment_range(segment, MAX_PDU_LEN);
+        let len = cmp::min(data.len(), MAX_PDU_LEN.saturating_sub(start));
+        rx.buf[start..start + len].copy_from_slice(&data[..len]);
+        rx.received |= 1 << segment;
+
+        let (total, expected_fcs) = rx.start?;
+        let all = (1u64 << (rx.last_segment + 1)) - 1;
+        if rx.received & all != all {
+            return None;
+        }
+        rx.number = None;
+        if fcs(&rx.buf[..total]) != expected_fcs {
+            debug!("PB-ADV FCS mismatch");
+            return None;
+        }
+
+        self.ack = Some(number);
+        self.last_received = Some(number);
+        Some(BearerEvent::Pdu(&self.rx.buf[..total]))
+    }
+}
+
+/// Writes a PB-ADV PDU into `buf` and returns the used part.
+fn encode<'b>(buf: &'b mut [u8; MAX_AD_LEN], link: u32, number: u8, pdu: &[u8]) -> &'b [u8] {
+    buf[..4].copy_from_slice(&link.to_be_bytes());
+    buf[4] = number;
+    buf[5..5 + pdu.len()].copy_from_slice(pdu);
+    &buf[..5 + pdu.len()]
+}
+
+/// Returns the index of the last segment of a PDU of `len` Bytes.
+fn last_segment(len: usize) -> u8 {
+    if len <= START_DATA_LEN {
+        0
+    } else {
+        (len - START_DATA_LEN).div_ceil(CONTINUATION_DATA_LEN) as u8
+    }
+}
+
+/// Returns the range of PDU Bytes carried in `segment`, limited to `len`.
+fn segment_range(segment: u8, len: usize) -> (usize, usize) {
+    let start = match segment {
+        0 => 0,
+        n => START_DATA_LEN + usize::from(n - 1) * CONTINUATION_DATA_LEN,
+    };
+    let size = if segment == 0 {
+        START_DATA_LEN
+    } else {
+        CONTINUATION_DATA_LEN
+    };
+    (cmp::min(start, len), cmp::min(start + size, len))
+}
+
+fn next_number(number: u8) -> u8 {
+    match number {
+        0xFF => DEVICE_TRANSACTIONS,
+        n => n + 1,
+    }
+}
+
+/// Computes the Frame Check Sequence of 3GPP TS 27.010 over a provisioning PDU.
+fn fcs(data: &[u8]) -> u8 {
+    let mut fcs = 0xFFu8;
+    for &byte in data {
+        fcs ^= byte;
+        for _ in 0..8 {
+            fcs = if fcs & 1 != 0 {
+                (fcs >> 1) ^ 0xE0
+            } else {
+                fcs >> 1
+            };
+        }
+    }
+    0xFF - fcs
+}
+
+/// Returns whether `t` is not in the future, relative to `now`.
+///
+/// `Instant`s wrap around, so this compares the time passed since `t` instead of the raw values.
+fn reached(now: Instant, t: Instant) -> bool {
+    now.raw_micros().wrapping_sub(t.raw_micros()) <= Instant::MAX_TIME_BETWEEN.as_micros()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: [u8; 16] = [0x70; 16];
+    const LINK: [u8; 4] = [0x12, 0x34, 0x56, 0x78];
+
+    fn pdu(number: u8, gp: &[u8]) -> [u8; MAX_AD_LEN] {
+        let mut buf = [0; MAX_AD_LEN];
+        encode(&mut buf, u32::from_be_bytes(LINK), number, gp);
+        buf
+    }
+
+    /// Opens a link and consumes the Link Ack.
+    fn open_link(now: Instant) -> PbAdvBearer {
+        let mut bearer = PbAdvBearer::new(UUID);
+        let mut open = [0; 17];
+        open[0] = (OP_LINK_OPEN << 2) | GPCF_CONTROL;
+        open[1..].copy_from_slice(&UUID);
+        let open = pdu(0, &open);
+        match bearer.process(&open[..5 + 17]) {
+            Some(BearerEvent::LinkOpened) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        let mut buf = [0; MAX_AD_LEN];
+        assert_eq!(bearer.next_pdu(now, &mut buf).unwrap()[4..], [0, 0x07]);
+        bearer
+    }
+
+    #[test]
+    fn link_and_transactions() {
+        let now = Instant::from_raw_micros(0);
+        let mut bearer = open_link(now);
+        let mut buf = [0; MAX_AD_LEN];
+
+        // Receive a 30-Byte PDU in 2 segments, continuation first
+        let mut message = [0; 30];
+        for (i, b) in message.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let mut cont = [(1 << 2) | GPCF_CONTINUATION; 11];
+        cont[1..].copy_from_slice(&message[20..]);
+        assert!(bearer.process(&pdu(0x05, &cont)[..5 + 11]).is_none());
+        let mut start = [0; 24];
+        start[..4].copy_from_slice(&[(1 << 2) | GPCF_START, 0, 30, fcs(&message)]);
+        start[4..].copy_from_slice(&message[..20]);
+        match bearer.process(&pdu(0x05, &start)) {
+            Some(BearerEvent::Pdu(data)) => assert_eq!(data, &message[..]),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(
+            bearer.next_pdu(now, &mut buf).unwrap()[4..],
+            [0x05, GPCF_ACK]
+        );
+
+        // Send a short PDU, which is retransmitted until acknowledged
+        bearer.send(now, &[0x01, 0x02]).unwrap();
+        let sent = bearer.next_pdu(now, &mut buf).unwrap();
+        assert_eq!(sent[4..], [0x80, GPCF_START, 0, 2, fcs(&[1, 2]), 1, 2]);
+        assert!(bearer.next_pdu(now, &mut buf).is_none());
+        let later = now + RETRANSMIT_INTERVAL;
+        assert!(bearer.next_pdu(later, &mut buf).is_some());
+        match bearer.process(&pdu(0x80, &[GPCF_ACK])[..6]) {
+            Some(BearerEvent::Sent) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(!bearer.check_timeout(now + TRANSACTION_TIMEOUT));
+
+        bearer.close(CloseReason::Fail);
+        for _ in 0..LINK_CLOSE_REPEATS {
+            assert_eq!(bearer.next_pdu(later, &mut buf).unwrap()[5..], [0x0B, 0x02]);
+        }
+        assert!(bearer.next_pdu(later, &mut buf).is_none());
+    }
+
+    #[test]
+    fn retransmit_across_wraparound() {
+        let now = Instant::from_raw_micros(u32::MAX - 100_000);
+        let mut bearer = open_link(now);
+        let mut buf = [0; MAX_AD_LEN];
+
+        bearer.send(now, &[0x01]).unwrap();
+        assert!(bearer.next_pdu(now, &mut buf).is_some());
+        // The retransmission is due after the timer wrapped around
+        assert!(bearer.next_pdu(now, &mut buf).is_none());
+        let wrapped = now + Duration::from_micros(200_000);
+        assert!(wrapped.raw_micros() < now.raw_micros());
+        assert!(bearer.next_pdu(wrapped, &mut buf).is_none());
+        assert!(bearer
+            .next_pdu(now + RETRANSMIT_INTERVAL, &mut buf)
+            .is_some());
+    }
+}
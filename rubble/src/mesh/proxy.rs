@@ -0,0 +1,253 @@
+//! The Mesh Proxy Service and proxy protocol.
+//!
+//! Phones usually can't send and receive mesh advertisements, so they talk to a *proxy node* over
+//! a GATT connection instead. Mesh messages are exchanged as *Proxy PDUs*: The client writes to the
+//! *Mesh Proxy Data In* characteristic, and the node sends notifications of *Mesh Proxy Data Out*.
+//! Messages that don't fit into a single write or notification are segmented.
+//!
+//! [`MeshProxyService`] hosts the service in an `AttributeTable`, reassembles incoming messages
+//! and segments outgoing ones. The messages themselves are handled by the mesh network layer.
+//!
+//! [`MeshProxyService`]: struct.MeshProxyService.html
+
+use {
+    crate::{
+        att::{AttError, Handle},
+        gatt::{
+            characteristic::Properties,
+            table::{AttributeTable, TableEntry},
+        },
+        utils,
+        uuid::Uuid16,
+        Error,
+    },
+    heapless::{ArrayLength, Vec},
+};
+
+/// UUID of the *Mesh Proxy Service*.
+pub const MESH_PROXY_SERVICE: Uuid16 = Uuid16(0x1828);
+
+/// UUID of the *Mesh Proxy Data In* characteristic.
+pub const DATA_IN: Uuid16 = Uuid16(0x2ADD);
+
+/// UUID of the *Mesh Proxy Data Out* characteristic.
+pub const DATA_OUT: Uuid16 = Uuid16(0x2ADE);
+
+/// Maximum length of a Proxy PDU sent as notification with the default `ATT_MTU`.
+pub const FRAGMENT_LEN: usize = 20;
+
+const SAR_COMPLETE: u8 = 0b00;
+const SAR_FIRST: u8 = 0b01;
+const SAR_CONTINUATION: u8 = 0b10;
+const SAR_LAST: u8 = 0b11;
+
+enum_with_unknown! {
+    /// Type of the message carried in a Proxy PDU.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum MessageType(u8) {
+        NetworkPdu = 0x00,
+        MeshBeacon = 0x01,
+        ProxyConfiguration = 0x02,
+        ProvisioningPdu = 0x03,
+    }
+}
+
+/// Returns the service data advertised with the `MESH_PROXY_SERVICE` UUID to identify the node's
+/// network by its Network ID.
+pub fn network_id_data(network_id: &[u8; 8]) -> [u8; 9] {
+    let mut data = [0; 9];
+    // Identification type 0x00
+    data[1..].copy_from_slice(network_id);
+    data
+}
+
+/// The Mesh Proxy Service, hosted in an `AttributeTable`.
+///
+/// `B` is the size of the message buffers.
+pub struct MeshProxyService<B: ArrayLength<u8>> {
+    data_in: Handle,
+    data_out: Handle,
+    rx: Vec<u8, B>,
+    /// Type of the message in `rx`, while it is being reassembled or waiting to be taken.
+    rx_type: Option<MessageType>,
+    rx_complete: bool,
+    tx: Vec<u8, B>,
+    tx_type: MessageType,
+    tx_sent: usize,
+}
+
+impl<B: ArrayLength<u8>> MeshProxyService<B> {
+    /// Adds the service to `table`.
+    pub fn add_to<N, TB>(table: &mut AttributeTable<N, TB>) -> Result<Self, Error>
+    where
+        N: ArrayLength<TableEntry>,
+        TB: ArrayLength<u8>,
+    {
+        table.add_service(MESH_PROXY_SERVICE)?;
+        // Proxy PDUs aren't stored in the table, writes are handled by `write_attr`
+        let data_in = table.add_characteristic(DATA_IN, Properties::WRITE_NO_RSP, &[])?;
+        let data_out = table.add_characteristic(DATA_OUT, Properties::NOTIFY, &[])?;
+        Ok(Self {
+            data_in,
+            data_out,
+            rx: Vec::new(),
+            rx_type: None,
+            rx_complete: false,
+            tx: Vec::new(),
+            tx_type: MessageType::NetworkPdu,
+            tx_sent: 0,
+        })
+    }
+
+    /// Returns the handle of the *Mesh Proxy Data Out* value, on which messages are notified.
+    pub fn data_out(&self) -> Handle {
+        self.data_out
+    }
+
+    /// Handles a write to the attribute at `handle`.
+    ///
+    /// Returns `None` if `handle` doesn't belong to the service, in which case the write should be
+    /// processed as usual. Malformed segments are dropped, since *Write Commands* can't be
+    /// rejected.
+    pub fn write_attr(&mut self, handle: Handle, value: &[u8]) -> Option<Result<(), AttError>> {
+        if handle != self.data_in {
+            return None;
+        }
+
+        if let Some((&header, data)) = value.split_first() {
+            self.reassemble(header >> 6, MessageType::from(header & 0x3F), data);
+        }
+        Some(Ok(()))
+    }
+
+    /// Returns the received message, if there's a complete one that hasn't been taken yet.
+    ///
+    /// The message is discarded when the next Proxy PDU is written.
+    pub fn take_message(&mut self) -> Option<(MessageType, &[u8])> {
+        if !self.rx_complete {
+            return None;
+        }
+        self.rx_complete = false;
+        let ty = self.rx_type.take()?;
+        Some((ty, &self.rx[..]))
+    }
+
+    /// Queues a message to be sent to the client.
+    ///
+    /// Returns `Error::InvalidValue` if the previous message hasn't been sent completely, and
+    /// `Error::Eof` if `message` doesn't fit into the buffer.
+    pub fn send(&mut self, ty: MessageType, message: &[u8]) -> Result<(), Error> {
+        if self.tx_sent < self.tx.len() {
+            return Err(Error::InvalidValue);
+        }
+        utils::truncate(&mut self.tx, 0);
+        self.tx.extend_from_slice(message).map_err(|_| Error::Eof)?;
+        self.tx_type = ty;
+        self.tx_sent = 0;
+        Ok(())
+    }
+
+    /// Encodes the next Proxy PDU to notify on `data_out`, if there is one.
+    pub fn next_fragment<'b>(&mut self, buf: &'b mut [u8; FRAGMENT_LEN]) -> Option<&'b [u8]> {
+        let rest = &self.tx[self.tx_sent..];
+        if rest.is_empty() {
+            return None;
+        }
+
+        let len = rest.len().min(FRAGMENT_LEN - 1);
+        let first = self.tx_sent == 0;
+        let last = len == rest.len();
+        let sar = match (first, last) {
+            (true, true) => SAR_COMPLETE,
+            (true, false) => SAR_FIRST,
+            (false, false) => SAR_CONTINUATION,
+            (false, true) => SAR_LAST,
+        };
+        buf[0] = (sar << 6) | u8::from(self.tx_type);
+        buf[1..=len].copy_from_slice(&rest[..len]);
+        self.tx_sent += len;
+        Some(&buf[..=len])
+    }
+
+    /// Discards all partially received and sent messages, eg. after a disconnection.
+    pub fn reset(&mut self) {
+        utils::truncate(&mut self.rx, 0);
+        self.rx_type = None;
+        self.rx_complete = false;
+        utils::truncate(&mut self.tx, 0);
+        self.tx_sent = 0;
+    }
+
+    fn reassemble(&mut self, sar: u8, ty: MessageType, data: &[u8]) {
+        match sar {
+            SAR_COMPLETE | SAR_FIRST => {
+                // A new message discards any incomplete one
+                utils::truncate(&mut self.rx, 0);
+                self.rx_type = Some(ty);
+                self.rx_complete = false;
+            }
+            // Segments must continue a message of the same type
+            _ if self.rx_complete || self.rx_type != Some(ty) => {
+                debug!("unexpected proxy segment, dropping");
+                return;
+            }
+            _ => {}
+        }
+
+        if self.rx.extend_from_slice(data).is_err() {
+            debug!("proxy message exceeds buffer, dropping");
+            utils::truncate(&mut self.rx, 0);
+            self.rx_type = None;
+            return;
+        }
+        if sar == SAR_COMPLETE || sar == SAR_LAST {
+            self.rx_complete = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, heapless::consts::*};
+
+    #[test]
+    fn segmentation() {
+        let mut table = AttributeTable::<U8, U32>::new();
+        let mut proxy = MeshProxyService::<U64>::add_to(&mut table).unwrap();
+        let data_in = proxy.data_in;
+
+        let mut message = [0; 30];
+        for (i, b) in message.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        proxy
+            .send(MessageType::ProxyConfiguration, &message)
+            .unwrap();
+        let mut buf = [0; FRAGMENT_LEN];
+        let mut segments = [[0; FRAGMENT_LEN]; 2];
+        let mut lens = [0; 2];
+        for (segment, len) in segments.iter_mut().zip(&mut lens) {
+            let fragment = proxy.next_fragment(&mut buf).unwrap();
+            *len = fragment.len();
+            segment[..fragment.len()].copy_from_slice(fragment);
+        }
+        assert!(proxy.next_fragment(&mut buf).is_none());
+        assert_eq!((segments[0][0], lens[0]), (0x42, 20));
+        assert_eq!((segments[1][0], lens[1]), (0xC2, 12));
+
+        // Feed the segments back in
+        proxy
+            .write_attr(data_in, &segments[0][..lens[0]])
+            .unwrap()
+            .unwrap();
+        assert!(proxy.take_message().is_none());
+        proxy
+            .write_attr(data_in, &segments[1][..lens[1]])
+            .unwrap()
+            .unwrap();
+        let (ty, received) = proxy.take_message().unwrap();
+        assert_eq!(ty, MessageType::ProxyConfiguration);
+        assert_eq!(received, &message[..]);
+        assert!(proxy.take_message().is_none());
+    }
+}
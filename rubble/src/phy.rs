@@ -8,6 +8,20 @@
 //! that indices 0..=36 refer to data channels and 37..=39 refer to the advertising channels
 //! (presumably to simplify channel hopping). The Link-Layer is only interested in these channel
 //! indices, so only those are implemented here.
+//!
+//! Radios without BLE support (generic 2.4 GHz transceivers, SDRs) also need the CRC and data
+//! whitening to be done in software. [`append_crc`], [`check_crc`] and [`whiten`] implement both
+//! for raw packets; `link::RawTransmitter` uses them to drive a plain [`Radio`].
+//!
+//! [`append_crc`]: fn.append_crc.html
+//! [`check_crc`]: fn.check_crc.html
+//! [`whiten`]: fn.whiten.html
+//! [`Radio`]: trait.Radio.html
+
+use crate::crc::ble_crc24_on_air;
+
+/// Size of the CRC appended to every PDU.
+pub const CRC_LEN: usize = 3;
 
 /// Returns the center frequency in MHz corresponding to an RF channel.
 fn rf_channel_freq(rf_channel: u8) -> u16 {
@@ -50,6 +64,22 @@ impl AdvertisingChannel {
         }
     }
 
+    /// Returns the next advertising channel in `channels`, wrapping around to the first one.
+    ///
+    /// If `channels` only contains `self`, this returns `self`.
+    pub fn cycle_in(&self, channels: AdvChannelMap) -> Self {
+        let mut next = self.cycle();
+        while !channels.contains(next) {
+            next = next.cycle();
+        }
+        next
+    }
+
+    /// Returns the channel index (37, 38 or 39).
+    pub fn index(&self) -> u8 {
+        self.0
+    }
+
     /// Returns the RF channel corresponding to this advertising channel index.
     ///
     /// RF channels 0, 12 and 39 are used for advertising.
@@ -80,6 +110,61 @@ impl AdvertisingChannel {
     }
 }
 
+/// A non-empty subset of the advertising channels.
+///
+/// Advertising and scanning use all 3 channels by default. Restricting them to fewer channels
+/// makes the device slower to find, but can be required for RF testing or by regulatory setups.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AdvChannelMap(u8);
+
+impl AdvChannelMap {
+    /// All 3 advertising channels.
+    pub const ALL: Self = AdvChannelMap(0b111);
+
+    /// Creates a channel map from its HCI representation, in which bit 0 selects channel 37, bit
+    /// 1 channel 38 and bit 2 channel 39.
+    ///
+    /// Returns `None` if no channel is selected or other bits are set.
+    pub fn from_bits(bits: u8) -> Option<Self> {
+        if bits == 0 || bits & !Self::ALL.0 != 0 {
+            None
+        } else {
+            Some(AdvChannelMap(bits))
+        }
+    }
+
+    /// Creates a map containing only `channel`.
+    pub fn single(channel: AdvertisingChannel) -> Self {
+        AdvChannelMap(1 << (channel.0 - 37))
+    }
+
+    /// Returns the HCI representation of the map.
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+
+    /// Returns whether `channel` is part of the map.
+    pub fn contains(&self, channel: AdvertisingChannel) -> bool {
+        self.0 & (1 << (channel.0 - 37)) != 0
+    }
+
+    /// Returns the lowest-numbered channel in the map.
+    pub fn first(&self) -> AdvertisingChannel {
+        AdvertisingChannel(37 + self.0.trailing_zeros() as u8)
+    }
+
+    /// Returns an iterator over the channels in the map, in ascending order.
+    pub fn iter(self) -> impl Iterator<Item = AdvertisingChannel> {
+        AdvertisingChannel::iter_all().filter(move |&ch| self.contains(ch))
+    }
+}
+
+impl Default for AdvChannelMap {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
 /// One of 37 data channels on which data channel PDUs are sent between connected devices.
 ///
 /// (channel indices 0..=36)
@@ -133,6 +218,46 @@ impl DataChannel {
     }
 }
 
+/// Writes the CRC of `pdu` into `crc`, in the order in which the Bytes are transmitted.
+///
+/// `crc_init` is `CRC_PRESET` for advertising channel PDUs, and the connection's CRC init value
+/// for data channel PDUs. The CRC is sent MSb first, unlike the rest of the packet, so the Bytes
+/// written to `crc` are bit-reversed. This makes the whole packet transmittable LSb first.
+pub fn append_crc(pdu: &[u8], crc_init: u32, crc: &mut [u8; CRC_LEN]) {
+    let value = ble_crc24_on_air(pdu, crc_init);
+    crc[0] = ((value >> 16) as u8).reverse_bits();
+    crc[1] = ((value >> 8) as u8).reverse_bits();
+    crc[2] = (value as u8).reverse_bits();
+}
+
+/// Checks the CRC of a received (and de-whitened) packet.
+///
+/// `packet` is the PDU followed by the 3 CRC Bytes as returned by `append_crc`.
+pub fn check_crc(packet: &[u8], crc_init: u32) -> bool {
+    // Running the CRC over the PDU and its correct CRC leaves the register at 0
+    packet.len() >= CRC_LEN && ble_crc24_on_air(packet, crc_init) == 0
+}
+
+/// Applies data whitening to `data`, or removes it.
+///
+/// `iv` is the LFSR start value returned by `AdvertisingChannel::whitening_iv` or
+/// `DataChannel::whitening_iv`. Whitening covers the PDU and the CRC, and `data` must start at the
+/// PDU header. Since whitening XORs the data with the LFSR output, applying it twice restores the
+/// original data.
+pub fn whiten(data: &mut [u8], iv: u8) {
+    // Position 0 of the LFSR in bit 1, position 6 (the output) in bit 7
+    let mut lfsr = iv.reverse_bits();
+    for byte in data {
+        for bit in 0..8 {
+            if lfsr & 0x80 != 0 {
+                lfsr ^= 0x11;
+                *byte ^= 1 << bit;
+            }
+            lfsr <<= 1;
+        }
+    }
+}
+
 /// Trait for raw 2.4 GHz non-BLE-specific radios.
 ///
 /// You probably won't need to implement this trait, unless you're working with hardware that has
@@ -141,6 +266,93 @@ impl DataChannel {
 pub trait Radio {
     /// Transmit every Byte in `buf` over the air, LSb first, at `freq` MHz.
     ///
-    /// TODO: Document all radio requirements
+    /// `buf` contains the whole packet: The preamble, Access Address, whitened PDU and CRC. The
+    /// radio only has to modulate it with 1 Mbit/s GFSK.
     fn transmit(&mut self, buf: &mut [u8], freq: u16);
 }
+
+/// Radio transmission power, in dBm.
+///
+/// Radios usually only support a small set of discrete power levels. Use [`clamp_to`] together
+/// with `Transmitter::supported_tx_power` to find the closest level a given radio can actually use.
+///
+/// [`clamp_to`]: #method.clamp_to
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TxPower(i8);
+
+impl TxPower {
+    /// 0 dBm (1 mW), a level supported by virtually every BLE radio.
+    pub const ZERO_DBM: Self = TxPower(0);
+
+    /// +4 dBm, the level used until configured otherwise.
+    ///
+    /// This is the power Rubble always transmitted with before it became configurable. Radios that
+    /// can't transmit this strong use their closest lower level instead.
+    pub const DEFAULT: Self = TxPower(4);
+
+    /// Creates a `TxPower` from a level in dBm.
+    pub const fn from_dbm(dbm: i8) -> Self {
+        TxPower(dbm)
+    }
+
+    /// Returns the power level in dBm.
+    pub fn as_dbm(&self) -> i8 {
+        self.0
+    }
+
+    /// Returns the highest level in `supported` that does not exceed `self`.
+    ///
+    /// If `self` is below all supported levels, the lowest supported level is returned instead. If
+    /// `supported` is empty, `self` is returned unchanged.
+    ///
+    /// `supported` does not need to be sorted.
+    pub fn clamp_to(self, supported: &[TxPower]) -> Self {
+        let below = supported.iter().filter(|lvl| **lvl <= self).max();
+        match below {
+            Some(lvl) => *lvl,
+            None => supported.iter().min().cloned().unwrap_or(self),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc_and_whitening() {
+        let mut packet = [0; 2 + 6 + CRC_LEN];
+        packet[..8].copy_from_slice(&[0x42, 0x06, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+        let mut crc = [0; CRC_LEN];
+        append_crc(&packet[..8], 0x555555, &mut crc);
+        packet[8..].copy_from_slice(&crc);
+        assert!(check_crc(&packet, 0x555555));
+        assert!(!check_crc(&packet, 0x123456));
+
+        let original = packet;
+        let channel = AdvertisingChannel::first();
+        whiten(&mut packet, channel.whitening_iv());
+        assert_ne!(packet, original);
+        whiten(&mut packet, channel.whitening_iv());
+        assert_eq!(packet, original);
+
+        packet[3] ^= 0x10;
+        assert!(!check_crc(&packet, 0x555555));
+    }
+
+    #[test]
+    fn clamp_tx_power() {
+        let power = TxPower::from_dbm;
+        let supported = [power(0), power(-20), power(4), power(-8)];
+
+        // Below the supported range
+        assert_eq!(power(-40).clamp_to(&supported), power(-20));
+        // Inside, on and between supported levels
+        assert_eq!(power(-8).clamp_to(&supported), power(-8));
+        assert_eq!(power(3).clamp_to(&supported), power(0));
+        // Above the supported range
+        assert_eq!(power(8).clamp_to(&supported), power(4));
+
+        assert_eq!(power(3).clamp_to(&[]), power(3));
+    }
+}
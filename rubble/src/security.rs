@@ -94,11 +94,48 @@
 //! can still *resolve* this address by using a shared **I**dentity **R**esolving **K**ey (IRK).
 //!
 //! This feature is not related to encryption or authentication of connections.
+//!
+//! ## Out-of-Band Data for LE Secure Connections
+//!
+//! For OOB pairing with *LE Secure Connections*, each device that sends OOB data generates a
+//! 128-bit random nonce `r` and computes a confirmation value `C = f4(PK, PK, r, 0)` from its
+//! public key. Those two values, together with the device address, are transferred over the OOB
+//! channel (eg. an NFC tag or a QR code) to the peer before pairing starts.
+//!
+//! [`OobData`] holds this information and can be encoded in the AD structure format that is used
+//! for NFC handover and most QR code schemes.
+//!
+//! [`OobData`]: struct.OobData.html
+//!
+//! ## Security Requests
+//!
+//! Pairing and encryption are always initiated by the master. The slave can only ask the master to
+//! start one of these procedures by sending a *Security Request*, for example right before it
+//! needs to serve a characteristic that requires an encrypted link. The master may ignore the
+//! request or reject it with a *Pairing Failed* command.
+//!
+//! This can be done with [`SecurityManagerTx::request_security`].
+//!
+//! [`SecurityManagerTx::request_security`]: struct.SecurityManagerTx.html#method.request_security
+//!
+//! ## Pairing Events
+//!
+//! The progress of pairing procedures is reported to the application as [`PairingEvent`]s, which
+//! can be fetched via `Responder::pairing_event` (eg. to show a passkey prompt or to drive a
+//! status LED).
+//!
+//! Rubble does not implement the pairing procedures yet. Pairing requests are rejected with
+//! *Pairing Not Supported*, so only the `Started` and `Failed` events are emitted for now.
+//!
+//! [`PairingEvent`]: enum.PairingEvent.html
 
 use {
     crate::{
+        att::LinkSecurity,
+        bond::Bond,
         bytes::*,
         l2cap::{Protocol, ProtocolObj, Sender},
+        link::{AddressKind, DeviceAddress},
         utils::HexSlice,
         Error,
     },
@@ -134,23 +171,153 @@ impl SecurityLevel for SecureConnections {
 #[derive(Debug)]
 pub struct SecurityManager<S: SecurityLevel> {
     _security: S,
+
+    /// Our OOB data, if it was exported to the peer.
+    local_oob: Option<OobData>,
+
+    /// OOB data received from the peer via the OOB channel.
+    peer_oob: Option<OobData>,
+
+    /// Authentication requirements of the last *Security Request* we sent, if any.
+    requested: Option<AuthReq>,
+
+    /// Pairing events not yet fetched by the application.
+    events: EventQueue,
 }
 
 impl SecurityManager<NoSecurity> {
     pub fn no_security() -> Self {
         Self {
             _security: NoSecurity,
+            local_oob: None,
+            peer_oob: None,
+            requested: None,
+            events: EventQueue::new(),
+        }
+    }
+}
+
+impl<S: SecurityLevel> SecurityManager<S> {
+    /// Sets the local OOB data that was transferred to the peer.
+    ///
+    /// The random value and confirmation value must have been generated from the key pair that
+    /// will be used for the next pairing procedure.
+    pub fn set_local_oob_data(&mut self, data: OobData) {
+        self.local_oob = Some(data);
+    }
+
+    /// Returns the local OOB data, if set.
+    ///
+    /// An application can encode this with `ToBytes` and hand it to its OOB transport.
+    pub fn local_oob_data(&self) -> Option<&OobData> {
+        self.local_oob.as_ref()
+    }
+
+    /// Injects OOB data received from the peer.
+    ///
+    /// This must be done before the peer initiates pairing. When OOB data is present, the OOB
+    /// data flag is set during the pairing feature exchange and the OOB association model can be
+    /// used, which provides MITM protection without requiring user interaction.
+    pub fn set_peer_oob_data(&mut self, data: OobData) {
+        self.peer_oob = Some(data);
+    }
+
+    /// Discards all local and peer OOB data.
+    ///
+    /// OOB data is only valid for a single pairing procedure, so this should be called after
+    /// pairing completes or fails.
+    pub fn clear_oob_data(&mut self) {
+        self.local_oob = None;
+        self.peer_oob = None;
+    }
+
+    /// Returns whether OOB data from the peer is available.
+    pub fn has_peer_oob_data(&self) -> bool {
+        self.peer_oob.is_some()
+    }
+
+    /// Returns the authentication requirements of the last *Security Request* sent to the master.
+    ///
+    /// This is reset once the master initiates pairing.
+    pub fn requested_security(&self) -> Option<AuthReq> {
+        self.requested
+    }
+
+    /// Returns the oldest pairing event that hasn't been fetched yet.
+    ///
+    /// Up to `MAX_PENDING_EVENTS` events are buffered. When more events occur, the oldest ones
+    /// are discarded.
+    pub fn take_event(&mut self) -> Option<PairingEvent> {
+        self.events.pop()
+    }
+
+    /// Gives this Security Manager the ability to send SMP commands.
+    pub fn with_sender<'a>(&'a mut self, sender: Sender<'a>) -> SecurityManagerTx<'a, S> {
+        SecurityManagerTx {
+            manager: self,
+            sender,
         }
     }
 }
 
+/// A `SecurityManager` handle that can initiate SMP procedures.
+///
+/// Obtained via `L2CAPStateTx::security`.
+pub struct SecurityManagerTx<'a, S: SecurityLevel> {
+    manager: &'a mut SecurityManager<S>,
+    sender: Sender<'a>,
+}
+
+impl<'a, S: SecurityLevel> SecurityManagerTx<'a, S> {
+    /// Sends a *Security Request* to the master, asking it to pair or to enable encryption.
+    ///
+    /// `auth_req` states the security properties the application needs. If the master has bonded
+    /// with us before and the stored keys satisfy `auth_req`, it is expected to just enable
+    /// encryption. Otherwise it should start pairing.
+    ///
+    /// The master is free to ignore the request, so applications must not rely on the link being
+    /// encrypted afterwards.
+    pub fn request_security(mut self, auth_req: AuthReq) {
+        self.manager.requested = Some(auth_req);
+
+        // This cannot fail: The `Sender` guarantees that `RSP_PDU_SIZE` Bytes are available, and
+        // a Security Request is only 2 Bytes long.
+        self.sender
+            .send(Command::SecurityRequest { auth_req })
+            .unwrap();
+    }
+}
+
 impl<S: SecurityLevel> ProtocolObj for SecurityManager<S> {
-    fn process_message(&mut self, message: &[u8], _responder: Sender<'_>) -> Result<(), Error> {
+    fn process_message(&mut self, message: &[u8], mut responder: Sender<'_>) -> Result<(), Error> {
         let cmd = Command::from_bytes(&mut ByteReader::new(message))?;
         trace!("SMP cmd {:?}, {:?}", cmd, HexSlice(message));
         match cmd {
-            Command::PairingRequest { .. } => {
-                warn!("pairing request NYI");
+            Command::PairingRequest { oob, auth_req, .. } => {
+                self.requested = None;
+                self.events.push(PairingEvent::Started { auth_req });
+
+                // OOB association is used if either side has received the other's OOB data
+                let use_oob = oob || self.peer_oob.is_some();
+                warn!("pairing request NYI (OOB available: {})", use_oob);
+
+                let reason = PairingFailedReason::PairingNotSupported;
+                responder.send(Command::PairingFailed { reason })?;
+                self.events.push(PairingEvent::Failed {
+                    reason,
+                    local: true,
+                });
+            }
+            Command::PairingFailed { reason } => {
+                info!("master aborted pairing: {:?}", reason);
+                self.events.push(PairingEvent::Failed {
+                    reason,
+                    local: false,
+                });
+            }
+            Command::SecurityRequest { .. } => {
+                // Only the slave may send this, and we're always the slave
+                warn!("ignoring security request from master");
             }
             Command::Unknown {
                 code: CommandCode::Unknown(code),
@@ -173,6 +340,196 @@ impl<S: SecurityLevel> Protocol for SecurityManager<S> {
     const RSP_PDU_SIZE: u8 = S::MTU;
 }
 
+/// Progress of a pairing procedure, reported to the application.
+#[derive(Debug, Copy, Clone)]
+pub enum PairingEvent {
+    /// The master started pairing.
+    Started {
+        /// Authentication requirements of the master.
+        auth_req: AuthReq,
+    },
+
+    /// The passkey displayed on the master has to be entered by the user.
+    PasskeyNeeded,
+
+    /// `passkey` has to be displayed, so the user can enter it on the master (or compare it with
+    /// the value shown there when using *Numeric Comparison*).
+    DisplayPasskey { passkey: u32 },
+
+    /// Pairing finished and the link is now encrypted.
+    Complete {
+        /// Security of the link using the new keys.
+        security: LinkSecurity,
+    },
+
+    /// Pairing failed.
+    Failed {
+        /// The reason sent in the *Pairing Failed* command.
+        reason: PairingFailedReason,
+
+        /// Whether pairing was aborted by us (`true`), or by the master (`false`).
+        local: bool,
+    },
+
+    /// Keys were exchanged with a master that requested bonding.
+    ///
+    /// The bond should be written to a `BondStore`.
+    BondCreated(Bond),
+}
+
+/// Number of pairing events buffered by the `SecurityManager`.
+pub const MAX_PENDING_EVENTS: usize = 4;
+
+/// Ring buffer of pairing events, dropping the oldest event when full.
+#[derive(Debug)]
+struct EventQueue {
+    events: [Option<PairingEvent>; MAX_PENDING_EVENTS],
+    /// Index of the oldest event.
+    head: usize,
+    len: usize,
+}
+
+impl EventQueue {
+    fn new() -> Self {
+        Self {
+            events: [None; MAX_PENDING_EVENTS],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, event: PairingEvent) {
+        if self.len == MAX_PENDING_EVENTS {
+            let dropped = self.pop();
+            warn!("pairing event queue full, dropping {:?}", dropped);
+        }
+        self.events[(self.head + self.len) % MAX_PENDING_EVENTS] = Some(event);
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<PairingEvent> {
+        if self.len == 0 {
+            return None;
+        }
+        let event = self.events[self.head].take();
+        self.head = (self.head + 1) % MAX_PENDING_EVENTS;
+        self.len -= 1;
+        event
+    }
+}
+
+/// Out-of-Band data used for *LE Secure Connections* pairing.
+///
+/// The `ToBytes` and `FromBytes` implementations use a sequence of AD structures containing the
+/// *LE Bluetooth Device Address*, the *LE Secure Connections Confirmation Value* and the *LE Secure
+/// Connections Random Value*, which is the format used in NFC handover records.
+#[derive(Debug, Copy, Clone)]
+pub struct OobData {
+    address: DeviceAddress,
+    random: HexSlice<[u8; 16]>,
+    confirm: HexSlice<[u8; 16]>,
+}
+
+/// AD type of the LE Bluetooth Device Address.
+const AD_LE_ADDRESS: u8 = 0x1B;
+/// AD type of the LE Secure Connections Confirmation Value.
+const AD_SC_CONFIRM: u8 = 0x22;
+/// AD type of the LE Secure Connections Random Value.
+const AD_SC_RANDOM: u8 = 0x23;
+
+impl OobData {
+    /// Creates an OOB data set.
+    ///
+    /// # Parameters
+    ///
+    /// * **`address`**: The device address that will be used when connecting.
+    /// * **`random`**: The random nonce `r`, in little-endian byte order.
+    /// * **`confirm`**: The confirmation value `C = f4(PK, PK, r, 0)` computed using the device's
+    ///   public key, in little-endian byte order.
+    pub fn new(address: DeviceAddress, random: [u8; 16], confirm: [u8; 16]) -> Self {
+        Self {
+            address,
+            random: HexSlice(random),
+            confirm: HexSlice(confirm),
+        }
+    }
+
+    /// Returns the device address the OOB data belongs to.
+    pub fn address(&self) -> DeviceAddress {
+        self.address
+    }
+
+    /// Returns the random nonce `r`.
+    pub fn random(&self) -> &[u8; 16] {
+        &self.random.0
+    }
+
+    /// Returns the confirmation value `C`.
+    pub fn confirm(&self) -> &[u8; 16] {
+        &self.confirm.0
+    }
+}
+
+impl ToBytes for OobData {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u8(1 + 7)?;
+        writer.write_u8(AD_LE_ADDRESS)?;
+        writer.write_slice(self.address.raw())?;
+        writer.write_u8(self.address.is_random() as u8)?;
+
+        writer.write_u8(1 + 16)?;
+        writer.write_u8(AD_SC_CONFIRM)?;
+        writer.write_slice(&self.confirm.0)?;
+
+        writer.write_u8(1 + 16)?;
+        writer.write_u8(AD_SC_RANDOM)?;
+        writer.write_slice(&self.random.0)?;
+        Ok(())
+    }
+}
+
+impl<'a> FromBytes<'a> for OobData {
+    fn from_bytes(bytes: &mut ByteReader<'a>) -> Result<Self, Error> {
+        let (mut address, mut random, mut confirm) = (None, None, None);
+
+        // Other AD structures might be present as well, skip them
+        while !bytes.is_empty() {
+            let len = bytes.read_u8()?;
+            if len == 0 {
+                return Err(Error::InvalidLength);
+            }
+            let ty = bytes.read_u8()?;
+            let data = &mut ByteReader::new(bytes.read_slice(usize::from(len - 1))?);
+
+            match ty {
+                AD_LE_ADDRESS => {
+                    let raw = data.read_array::<[u8; 6]>()?;
+                    let kind = if data.read_u8()? & 1 == 0 {
+                        AddressKind::Public
+                    } else {
+                        AddressKind::Random
+                    };
+                    address = Some(DeviceAddress::new(raw, kind));
+                }
+                AD_SC_CONFIRM => confirm = Some(data.read_array::<[u8; 16]>()?),
+                AD_SC_RANDOM => random = Some(data.read_array::<[u8; 16]>()?),
+                _ => continue,
+            }
+
+            if !data.is_empty() {
+                return Err(Error::IncompleteParse);
+            }
+        }
+
+        match (address, random, confirm) {
+            (Some(address), Some(random), Some(confirm)) => {
+                Ok(OobData::new(address, random, confirm))
+            }
+            _ => Err(Error::InvalidValue),
+        }
+    }
+}
+
 /// An SMP command.
 #[derive(Debug, Copy, Clone)]
 enum Command<'a> {
@@ -196,6 +553,17 @@ enum Command<'a> {
         /// Set of keys the initiator requests the responder to generate and distribute.
         responder_dist: KeyDistribution,
     },
+    /// `0x0B` Security Request
+    ///
+    /// Sent by the slave to ask the master to initiate pairing or encryption.
+    SecurityRequest {
+        /// Authentication requirements of the slave.
+        auth_req: AuthReq,
+    },
+    /// `0x05` Pairing Failed
+    PairingFailed {
+        reason: PairingFailedReason,
+    },
     Unknown {
         code: CommandCode,
         data: &'a [u8],
@@ -214,6 +582,12 @@ impl<'a> FromBytes<'a> for Command<'a> {
                 initiator_dist: KeyDistribution::from_bits_truncate(bytes.read_u8()?),
                 responder_dist: KeyDistribution::from_bits_truncate(bytes.read_u8()?),
             },
+            CommandCode::SecurityRequest => Command::SecurityRequest {
+                auth_req: AuthReq(bytes.read_u8()?),
+            },
+            CommandCode::PairingFailed => Command::PairingFailed {
+                reason: PairingFailedReason::from(bytes.read_u8()?),
+            },
             _ => Command::Unknown {
                 code,
                 data: bytes.read_rest(),
@@ -222,6 +596,42 @@ impl<'a> FromBytes<'a> for Command<'a> {
     }
 }
 
+impl ToBytes for Command<'_> {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        match *self {
+            Command::PairingRequest {
+                io,
+                oob,
+                auth_req,
+                max_keysize,
+                initiator_dist,
+                responder_dist,
+            } => {
+                writer.write_u8(CommandCode::PairingRequest.into())?;
+                writer.write_u8(io.into())?;
+                writer.write_u8(oob as u8)?;
+                writer.write_u8(auth_req.0)?;
+                writer.write_u8(max_keysize)?;
+                writer.write_u8(initiator_dist.bits())?;
+                writer.write_u8(responder_dist.bits())?;
+            }
+            Command::SecurityRequest { auth_req } => {
+                writer.write_u8(CommandCode::SecurityRequest.into())?;
+                writer.write_u8(auth_req.0)?;
+            }
+            Command::PairingFailed { reason } => {
+                writer.write_u8(CommandCode::PairingFailed.into())?;
+                writer.write_u8(reason.into())?;
+            }
+            Command::Unknown { code, data } => {
+                writer.write_u8(code.into())?;
+                writer.write_slice(data)?;
+            }
+        }
+        Ok(())
+    }
+}
+
 enum_with_unknown! {
     #[derive(Debug, Copy, Clone)]
     enum CommandCode(u8) {
@@ -242,6 +652,41 @@ enum_with_unknown! {
     }
 }
 
+enum_with_unknown! {
+    /// Reason codes sent in *Pairing Failed* commands.
+    #[derive(Debug, Copy, Clone)]
+    pub enum PairingFailedReason(u8) {
+        /// The user input of the passkey failed (eg. it was canceled).
+        PasskeyEntryFailed = 0x01,
+        /// The OOB data is not available.
+        OobNotAvailable = 0x02,
+        /// The I/O capabilities of the devices don't allow the required authentication.
+        AuthenticationRequirements = 0x03,
+        /// The confirm value doesn't match the calculated compare value.
+        ConfirmValueFailed = 0x04,
+        /// Pairing is not supported by the device.
+        PairingNotSupported = 0x05,
+        /// The resulting key size is too short.
+        EncryptionKeySize = 0x06,
+        /// The SMP command is not supported.
+        CommandNotSupported = 0x07,
+        /// Pairing failed for an unspecified reason.
+        UnspecifiedReason = 0x08,
+        /// Pairing or authentication was attempted too often in a short time.
+        RepeatedAttempts = 0x09,
+        /// A command had invalid parameters.
+        InvalidParameters = 0x0A,
+        /// The DHKey Check value didn't match.
+        DhKeyCheckFailed = 0x0B,
+        /// The confirm values of Numeric Comparison didn't match.
+        NumericComparisonFailed = 0x0C,
+        /// Pairing over BR/EDR is in progress.
+        BrEdrPairingInProgress = 0x0D,
+        /// Keys generated over BR/EDR can't be used for LE.
+        CrossTransportKeyNotAllowed = 0x0E,
+    }
+}
+
 enum_with_unknown! {
     /// Describes the I/O capabilities of a device that can be used for the pairing process.
     #[derive(Debug, Copy, Clone)]
@@ -268,6 +713,16 @@ enum_with_unknown! {
 pub struct AuthReq(u8);
 
 impl AuthReq {
+    /// Creates authentication requirements with the given bonding type and MITM and *LE Secure
+    /// Connections* flags.
+    pub fn new(bonding: BondingType, mitm: bool, secure_connection: bool) -> Self {
+        let mut auth_req = AuthReq(0);
+        auth_req.set_bonding_type(bonding);
+        auth_req.set_mitm(mitm);
+        auth_req.set_secure_connection(secure_connection);
+        auth_req
+    }
+
     const BITS_BONDING: u8 = 0b0000_0011;
     const BITS_MITM: u8 = 0b0000_0100;
     const BITS_SC: u8 = 0b0000_1000;
@@ -352,3 +807,23 @@ bitflags! {
         const LINK_KEY = (1 << 3);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_queue_drops_oldest() {
+        let mut queue = EventQueue::new();
+        for passkey in 0..5 {
+            queue.push(PairingEvent::DisplayPasskey { passkey });
+        }
+        for expected in 1..5 {
+            match queue.pop() {
+                Some(PairingEvent::DisplayPasskey { passkey }) => assert_eq!(passkey, expected),
+                other => panic!("{:?}", other),
+            }
+        }
+        assert!(queue.pop().is_none());
+    }
+}
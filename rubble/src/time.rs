@@ -3,6 +3,17 @@
 //! These APIs are made for the BLE stack and are not meant to be general-purpose. The APIs here
 //! have microsecond resolution and use 32-bit arithmetic wherever possible.
 
+#![cfg_attr(
+    all(feature = "deny-panics", not(test)),
+    deny(
+        clippy::expect_used,
+        clippy::indexing_slicing,
+        clippy::panic,
+        clippy::unreachable,
+        clippy::unwrap_used
+    )
+)]
+
 use core::{
     fmt,
     ops::{Add, AddAssign, Sub, SubAssign},
@@ -10,8 +21,9 @@ use core::{
 
 /// A duration with microsecond resolution.
 ///
-/// This can represent a maximum duration of about 1 hour. Overflows will result in a panic, but
-/// shouldn't happen since the BLE stack doesn't deal with durations that large.
+/// This can represent a maximum duration of about 1 hour. Arithmetic saturates at zero and at the
+/// maximum instead of overflowing, which shouldn't matter since the BLE stack doesn't deal with
+/// durations that large.
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Duration(u32);
 
@@ -20,7 +32,7 @@ impl Duration {
     pub const T_IFS: Self = Duration(150);
 
     /// Creates a `Duration` from a number of microseconds.
-    pub fn from_micros(micros: u32) -> Self {
+    pub const fn from_micros(micros: u32) -> Self {
         Duration(micros)
     }
 
@@ -31,7 +43,7 @@ impl Duration {
 
     /// Creates a `Duration` representing a number of seconds.
     pub fn from_secs(secs: u16) -> Self {
-        Duration(u32::from(secs) * 1_000_000)
+        Duration(u32::from(secs).saturating_mul(1_000_000))
     }
 
     /// Returns the number of whole seconds that fit in `self`.
@@ -53,13 +65,18 @@ impl Duration {
     pub fn subsec_micros(&self) -> u32 {
         self.0 % 1_000_000
     }
+
+    /// Subtracts `rhs` from `self`, returning a zero duration instead of underflowing.
+    pub fn saturating_sub(self, rhs: Self) -> Self {
+        Duration(self.0.saturating_sub(rhs.0))
+    }
 }
 
 impl Add for Duration {
     type Output = Self;
 
     fn add(self, rhs: Self) -> Self {
-        Duration(self.0.checked_add(rhs.0).expect("duration overflow"))
+        Duration(self.0.saturating_add(rhs.0))
     }
 }
 
@@ -81,7 +98,7 @@ impl Sub for Duration {
     type Output = Self;
 
     fn sub(self, rhs: Self) -> Self {
-        Duration(self.0.checked_sub(rhs.0).expect("duration underflow"))
+        Duration(self.0.saturating_sub(rhs.0))
     }
 }
 
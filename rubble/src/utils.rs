@@ -1,4 +1,4 @@
-use core::fmt;
+use {core::fmt, heapless::ArrayLength};
 
 /// Creates an enum that can be converted from and to a primitive type, with invalid values becoming
 /// a catch-all `Unknown` variant.
@@ -45,6 +45,19 @@ macro_rules! enum_with_unknown {
     }
 }
 
+/// Truncates `s` to at most `max_len` Bytes, without splitting a UTF-8 encoded character.
+pub fn truncate_utf8(s: &str, max_len: usize) -> &str {
+    if s.len() <= max_len {
+        return s;
+    }
+
+    let mut end = max_len;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
 /// `Debug`-formats its contents as a hexadecimal byte slice.
 #[derive(Copy, Clone)]
 pub struct HexSlice<T>(pub T)
@@ -81,3 +94,13 @@ impl<T: fmt::LowerHex> fmt::Debug for Hex<T> {
         write!(f, "{:#x}", self.0)
     }
 }
+
+/// Shortens `vec` to `len` elements, like `Vec::truncate`.
+///
+/// `heapless::Vec::truncate` and `heapless::Vec::clear` index one past the end of the vector when
+/// removing elements, which trips the debug assertions in the standard library.
+pub(crate) fn truncate<T, N: ArrayLength<T>>(vec: &mut heapless::Vec<T, N>, len: usize) {
+    while vec.len() > len {
+        vec.pop();
+    }
+}
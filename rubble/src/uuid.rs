@@ -46,16 +46,16 @@ impl From<Uuid16> for Uuid32 {
     }
 }
 
-impl Into<Uuid> for Uuid16 {
-    fn into(self) -> Uuid {
-        Uuid32::from(self).into()
+impl From<Uuid16> for Uuid {
+    fn from(uuid: Uuid16) -> Self {
+        Uuid32::from(uuid).into()
     }
 }
 
-impl Into<Uuid> for Uuid32 {
-    fn into(self) -> Uuid {
+impl From<Uuid32> for Uuid {
+    fn from(uuid: Uuid32) -> Self {
         let mut buf = BASE_UUID;
-        BigEndian::write_u32(&mut buf, self.0);
+        BigEndian::write_u32(&mut buf, uuid.0);
         Uuid::from_bytes(buf)
     }
 }
@@ -139,3 +139,178 @@ impl IsUuid for Uuid32 {
 impl IsUuid for Uuid {
     const KIND: UuidKind = UuidKind::Uuid128;
 }
+
+/// A UUID of any size, as a compact enum.
+///
+/// This is useful when UUIDs of different sizes need to be stored together, for example in
+/// attribute tables that mix 16-bit SIG-assigned and 128-bit vendor-specific UUIDs.
+///
+/// Comparisons always consider the 128-bit equivalent, so an alias compares equal to its expanded
+/// form: `DynUuid::from(Uuid16(0x180F))` is equal to the 128-bit UUID
+/// `0000180F-0000-1000-8000-00805F9B34FB`. Likewise, `ToBytes` always writes the shortest form,
+/// while `FromBytes` decodes all remaining Bytes as a 2, 4 or 16-Byte UUID.
+#[derive(Copy, Clone)]
+pub enum DynUuid {
+    Uuid16(Uuid16),
+    Uuid32(Uuid32),
+    Uuid128(Uuid),
+}
+
+impl DynUuid {
+    /// Returns the full 128-bit form of this UUID.
+    pub fn to_uuid128(&self) -> Uuid {
+        match *self {
+            DynUuid::Uuid16(uuid) => uuid.into(),
+            DynUuid::Uuid32(uuid) => uuid.into(),
+            DynUuid::Uuid128(uuid) => uuid,
+        }
+    }
+
+    /// Returns the shortest representation of this UUID.
+    ///
+    /// 128-bit UUIDs derived from the Base UUID are turned into 32- or 16-bit aliases, and 32-bit
+    /// aliases that fit into 16 bits are turned into 16-bit aliases.
+    pub fn shortest(&self) -> Self {
+        let full = self.to_uuid128();
+        let bytes = full.as_bytes();
+        if bytes[4..] != BASE_UUID[4..] {
+            return DynUuid::Uuid128(full);
+        }
+
+        let alias = BigEndian::read_u32(bytes);
+        if alias <= u32::from(u16::MAX) {
+            DynUuid::Uuid16(Uuid16(alias as u16))
+        } else {
+            DynUuid::Uuid32(Uuid32(alias))
+        }
+    }
+
+    /// Returns the kind of UUID stored.
+    pub fn kind(&self) -> UuidKind {
+        match self {
+            DynUuid::Uuid16(_) => UuidKind::Uuid16,
+            DynUuid::Uuid32(_) => UuidKind::Uuid32,
+            DynUuid::Uuid128(_) => UuidKind::Uuid128,
+        }
+    }
+
+    /// Returns the number of Bytes `ToBytes` will write.
+    pub fn encoded_len(&self) -> usize {
+        match self.shortest() {
+            DynUuid::Uuid16(_) => 2,
+            DynUuid::Uuid32(_) => 4,
+            DynUuid::Uuid128(_) => 16,
+        }
+    }
+}
+
+impl From<Uuid16> for DynUuid {
+    fn from(uuid: Uuid16) -> Self {
+        DynUuid::Uuid16(uuid)
+    }
+}
+
+impl From<Uuid32> for DynUuid {
+    fn from(uuid: Uuid32) -> Self {
+        DynUuid::Uuid32(uuid)
+    }
+}
+
+impl From<Uuid> for DynUuid {
+    fn from(uuid: Uuid) -> Self {
+        DynUuid::Uuid128(uuid)
+    }
+}
+
+impl From<DynUuid> for Uuid {
+    fn from(uuid: DynUuid) -> Self {
+        uuid.to_uuid128()
+    }
+}
+
+impl PartialEq for DynUuid {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_uuid128() == other.to_uuid128()
+    }
+}
+
+impl Eq for DynUuid {}
+
+impl PartialEq<Uuid16> for DynUuid {
+    fn eq(&self, other: &Uuid16) -> bool {
+        *self == DynUuid::from(*other)
+    }
+}
+
+impl PartialEq<Uuid> for DynUuid {
+    fn eq(&self, other: &Uuid) -> bool {
+        self.to_uuid128() == *other
+    }
+}
+
+impl ToBytes for DynUuid {
+    fn to_bytes(&self, buffer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        match self.shortest() {
+            DynUuid::Uuid16(uuid) => uuid.to_bytes(buffer),
+            DynUuid::Uuid32(uuid) => uuid.to_bytes(buffer),
+            DynUuid::Uuid128(uuid) => uuid.to_bytes(buffer),
+        }
+    }
+}
+
+impl FromBytes<'_> for DynUuid {
+    fn from_bytes(bytes: &mut ByteReader<'_>) -> Result<Self, Error> {
+        Ok(match bytes.bytes_left() {
+            2 => DynUuid::Uuid16(Uuid16::from_bytes(bytes)?),
+            4 => DynUuid::Uuid32(Uuid32::from_bytes(bytes)?),
+            16 => DynUuid::Uuid128(<Uuid as FromBytes>::from_bytes(bytes)?),
+            _ => return Err(Error::InvalidLength),
+        })
+    }
+}
+
+impl fmt::Debug for DynUuid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DynUuid::Uuid16(uuid) => fmt::Debug::fmt(uuid, f),
+            DynUuid::Uuid32(uuid) => fmt::Debug::fmt(uuid, f),
+            DynUuid::Uuid128(uuid) => write!(f, "Uuid128({})", uuid),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dyn_uuid() {
+        let short = DynUuid::from(Uuid16(0x180F));
+        let full = DynUuid::from(Into::<Uuid>::into(Uuid16(0x180F)));
+        assert_eq!(short, full);
+        assert_eq!(full, Uuid16(0x180F));
+        assert_eq!(DynUuid::from(Uuid32(0x180F)).encoded_len(), 2);
+        assert_ne!(short, DynUuid::from(Uuid32(0x0001_180F)));
+
+        let mut buf = [0; 16];
+        let mut writer = ByteWriter::new(&mut buf);
+        full.to_bytes(&mut writer).unwrap();
+        assert_eq!(writer.space_left(), 14);
+        assert_eq!(&buf[..2], &[0x0F, 0x18]);
+
+        let decoded = DynUuid::from_bytes(&mut ByteReader::new(&buf[..2])).unwrap();
+        assert_eq!(decoded, short);
+        assert_eq!(
+            DynUuid::from_bytes(&mut ByteReader::new(&buf[..3])),
+            Err(Error::InvalidLength)
+        );
+
+        let custom = Uuid::from_bytes([0x12; 16]);
+        let mut buf = [0; 16];
+        custom.to_bytes(&mut ByteWriter::new(&mut buf)).unwrap();
+        assert_eq!(
+            DynUuid::from_bytes(&mut ByteReader::new(&buf)),
+            Ok(DynUuid::Uuid128(custom))
+        );
+    }
+}